@@ -130,7 +130,8 @@ impl<T: Simulator> Cluster<T> {
         for _ in 0..self.count {
             let path = TempDir::new("test_cluster").unwrap();
             let kv_db_opt = self.cfg.rocksdb.build_opt();
-            let kv_cfs_opt = self.cfg.rocksdb.build_cf_opts();
+            let cache = self.cfg.build_shared_block_cache();
+            let kv_cfs_opt = self.cfg.rocksdb.build_cf_opts(&cache);
             let engine = Arc::new(
                 rocksdb::new_engine_opt(path.path().to_str().unwrap(), kv_db_opt, kv_cfs_opt)
                     .unwrap(),
@@ -230,6 +230,7 @@ struct Cluster {
     down_peers: HashMap<u64, pdpb::PeerStats>,
     pending_peers: HashMap<u64, metapb::Peer>,
     is_bootstraped: bool,
+    gc_safe_point: u64,
 }
 
 impl Cluster {
@@ -254,6 +255,7 @@ impl Cluster {
             down_peers: HashMap::default(),
             pending_peers: HashMap::default(),
             is_bootstraped: false,
+            gc_safe_point: 0,
         }
     }
 
@@ -660,6 +662,10 @@ impl TestPdClient {
         }
     }
 
+    pub fn set_gc_safe_point(&self, safe_point: u64) {
+        self.cluster.wl().gc_safe_point = safe_point;
+    }
+
     pub fn get_stores(&self) -> Result<Vec<metapb::Store>> {
         Ok(self.cluster.rl().get_stores())
     }
@@ -1099,4 +1105,11 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += regions.len() - 1;
         Box::new(ok(()))
     }
+
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        if let Err(e) = self.check_bootstrap() {
+            return Box::new(err(e));
+        }
+        Box::new(ok(self.cluster.rl().gc_safe_point))
+    }
 }
@@ -139,6 +139,9 @@ impl Simulator for ServerCluster {
             });
         let mut store =
             create_raft_storage(sim_router.clone(), &cfg.storage, storage_read_pool).unwrap();
+        store
+            .mut_gc_worker()
+            .set_local_storage(Arc::clone(&engines.kv));
         store.start(&cfg.storage).unwrap();
         self.storages.insert(node_id, store.get_engine());
 
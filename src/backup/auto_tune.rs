@@ -0,0 +1,367 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Slows the backup down when it hurts the online traffic.
+//!
+//! The foreground load is sampled every interval. The concurrency and the upload speed of the
+//! backup are halved once the load exceeds the thresholds, and raised step by step otherwise,
+//! like the congestion control of TCP.
+
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use prometheus;
+use sys_info;
+
+use util::io_limiter::IOLimiter;
+use util::time::duration_to_sec;
+
+use super::metrics::*;
+use super::Config;
+
+const GRPC_DURATION_METRIC: &str = "tikv_grpc_msg_duration_seconds";
+const THREAD_CPU_METRIC: &str = "tikv_thread_cpu_seconds_total";
+// The upload speed is raised by `1 / SPEED_STEP_DIVISOR` of the max speed every time.
+const SPEED_STEP_DIVISOR: u64 = 10;
+
+/// The foreground load in an interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Load {
+    /// The p99 duration of the gRPC messages.
+    pub foreground_p99: Duration,
+    /// The CPU time used by the instance divided by the interval and the number of CPUs.
+    pub cpu_usage: f64,
+}
+
+pub trait LoadSampler: Send {
+    /// Returns the load since the last sample.
+    fn sample(&mut self) -> Load;
+}
+
+/// Samples the load from the gRPC message duration and the thread CPU time metrics.
+pub struct MetricsSampler {
+    // The cumulative counts of the buckets, and the total count.
+    buckets: Vec<(f64, u64)>,
+    count: u64,
+    cpu_secs: f64,
+    last_sample: Instant,
+    cpu_num: f64,
+}
+
+impl MetricsSampler {
+    pub fn new() -> MetricsSampler {
+        let mut sampler = MetricsSampler {
+            buckets: vec![],
+            count: 0,
+            cpu_secs: 0.0,
+            last_sample: Instant::now(),
+            cpu_num: f64::from(sys_info::cpu_num().unwrap_or(1)),
+        };
+        sampler.sample();
+        sampler
+    }
+}
+
+impl LoadSampler for MetricsSampler {
+    fn sample(&mut self) -> Load {
+        let mut buckets: Vec<(f64, u64)> = vec![];
+        let mut count = 0;
+        let mut cpu_secs = 0.0;
+        for mf in prometheus::gather() {
+            if mf.get_name() == GRPC_DURATION_METRIC {
+                for m in mf.get_metric() {
+                    let h = m.get_histogram();
+                    count += h.get_sample_count();
+                    // All the messages share the same buckets.
+                    for (i, b) in h.get_bucket().iter().enumerate() {
+                        if i == buckets.len() {
+                            buckets.push((b.get_upper_bound(), 0));
+                        }
+                        buckets[i].1 += b.get_cumulative_count();
+                    }
+                }
+            } else if mf.get_name() == THREAD_CPU_METRIC {
+                cpu_secs += mf
+                    .get_metric()
+                    .iter()
+                    .map(|m| m.get_counter().get_value())
+                    .sum::<f64>();
+            }
+        }
+
+        let now = Instant::now();
+        let elapsed = duration_to_sec(now.duration_since(self.last_sample));
+        let mut load = Load::default();
+        if elapsed > 0.0 {
+            load.cpu_usage = (cpu_secs - self.cpu_secs).max(0.0) / elapsed / self.cpu_num;
+        }
+        let total = count.saturating_sub(self.count);
+        if total > 0 && buckets.len() == self.buckets.len() {
+            let target = (total as f64 * 0.99).ceil() as u64;
+            let p99 = buckets
+                .iter()
+                .zip(&self.buckets)
+                .find(|&(b, last)| b.1.saturating_sub(last.1) >= target)
+                .map_or_else(|| buckets.last().unwrap().0, |(b, _)| b.0);
+            load.foreground_p99 = Duration::new(p99 as u64, (p99.fract() * 1e9) as u32);
+        }
+        self.buckets = buckets;
+        self.count = count;
+        self.cpu_secs = cpu_secs;
+        self.last_sample = now;
+        load
+    }
+}
+
+/// Throttle limits the concurrency and the upload speed of a backup.
+pub struct Throttle {
+    concurrency: AtomicUsize,
+    running: Mutex<usize>,
+    cond: Condvar,
+    limiter: Option<IOLimiter>,
+}
+
+impl Throttle {
+    /// `speed_limit` 0 means unlimited.
+    pub fn new(concurrency: usize, speed_limit: u64) -> Throttle {
+        Throttle {
+            concurrency: AtomicUsize::new(concurrency),
+            running: Mutex::new(0),
+            cond: Condvar::new(),
+            limiter: if speed_limit > 0 {
+                Some(IOLimiter::new(speed_limit))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Blocks until a slot is available, and returns a guard which releases it when dropped.
+    pub fn acquire(&self) -> ThrottleGuard {
+        let mut running = self.running.lock().unwrap();
+        while *running >= self.concurrency.load(Ordering::Relaxed) {
+            running = self.cond.wait(running).unwrap();
+        }
+        *running += 1;
+        ThrottleGuard { throttle: self }
+    }
+
+    /// Blocks until `bytes` can be uploaded.
+    pub fn request_upload(&self, bytes: usize) {
+        if let Some(ref limiter) = self.limiter {
+            let max_bytes = cmp::max(limiter.get_max_bytes_per_time(), 1) as usize;
+            let mut remain = bytes;
+            while remain > 0 {
+                let n = cmp::min(remain, max_bytes);
+                limiter.request(n as i64);
+                remain -= n;
+            }
+        }
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.load(Ordering::Relaxed)
+    }
+
+    fn set_concurrency(&self, concurrency: usize) {
+        self.concurrency.store(concurrency, Ordering::Relaxed);
+        BACKUP_CONCURRENCY_GAUGE.set(concurrency as i64);
+        // Wake up the waiters in case it's raised.
+        let _running = self.running.lock().unwrap();
+        self.cond.notify_all();
+    }
+
+    fn set_speed_limit(&self, speed_limit: u64) {
+        if let Some(ref limiter) = self.limiter {
+            limiter.set_bytes_per_second(speed_limit as i64);
+            BACKUP_SPEED_LIMIT_GAUGE.set(speed_limit as i64);
+        }
+    }
+}
+
+pub struct ThrottleGuard<'a> {
+    throttle: &'a Throttle,
+}
+
+impl<'a> Drop for ThrottleGuard<'a> {
+    fn drop(&mut self) {
+        let mut running = self.throttle.running.lock().unwrap();
+        *running -= 1;
+        self.throttle.cond.notify_one();
+    }
+}
+
+/// AutoTuner decides the concurrency and the upload speed of a backup from the load.
+pub struct AutoTuner {
+    cfg: Config,
+    concurrency: usize,
+    speed_limit: u64,
+}
+
+impl AutoTuner {
+    /// Starts at the max concurrency and speed.
+    pub fn new(cfg: &Config) -> AutoTuner {
+        AutoTuner {
+            cfg: cfg.clone(),
+            concurrency: cfg.num_threads,
+            speed_limit: cfg.upload_speed_limit.0,
+        }
+    }
+
+    /// Returns the new concurrency and upload speed limit.
+    pub fn tune(&mut self, load: &Load) -> (usize, u64) {
+        let overloaded = load.foreground_p99 > self.cfg.foreground_latency_threshold.0
+            || load.cpu_usage > self.cfg.cpu_usage_threshold;
+        let (max_speed, min_speed) = (
+            self.cfg.upload_speed_limit.0,
+            self.cfg.min_upload_speed_limit.0,
+        );
+        if overloaded {
+            self.concurrency = cmp::max(self.concurrency / 2, self.cfg.min_num_threads);
+            self.speed_limit = cmp::max(self.speed_limit / 2, min_speed);
+        } else {
+            self.concurrency = cmp::min(self.concurrency + 1, self.cfg.num_threads);
+            let step = cmp::max(max_speed / SPEED_STEP_DIVISOR, 1);
+            self.speed_limit = cmp::min(self.speed_limit + step, max_speed);
+        }
+        // The speed is unlimited if there is no max speed.
+        if max_speed == 0 {
+            self.speed_limit = 0;
+        }
+        (self.concurrency, self.speed_limit)
+    }
+}
+
+/// Tunes a throttle every interval in a background thread until it's dropped.
+pub struct TunerHandle {
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TunerHandle {
+    pub fn start<L: LoadSampler + 'static>(
+        cfg: &Config,
+        mut sampler: L,
+        throttle: Arc<Throttle>,
+    ) -> TunerHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let interval = cfg.auto_tune_interval.0;
+        let mut tuner = AutoTuner::new(cfg);
+        let handle = thread::Builder::new()
+            .name(thd_name!("backup-tuner"))
+            .spawn(move || loop {
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        let load = sampler.sample();
+                        let (concurrency, speed_limit) = tuner.tune(&load);
+                        if concurrency != throttle.concurrency() {
+                            info!(
+                                "backup concurrency is tuned to {} with {:?}",
+                                concurrency, load
+                            );
+                        }
+                        throttle.set_concurrency(concurrency);
+                        throttle.set_speed_limit(speed_limit);
+                    }
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+            })
+            .unwrap();
+        TunerHandle {
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for TunerHandle {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(h) = self.handle.take() {
+            if let Err(e) = h.join() {
+                error!("failed to join backup tuner: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use util::config::{ReadableDuration, ReadableSize};
+
+    #[test]
+    fn test_auto_tuner() {
+        let cfg = Config {
+            num_threads: 8,
+            min_num_threads: 2,
+            upload_speed_limit: ReadableSize(100),
+            min_upload_speed_limit: ReadableSize(20),
+            foreground_latency_threshold: ReadableDuration::millis(100),
+            cpu_usage_threshold: 0.8,
+            ..Config::default()
+        };
+        let mut tuner = AutoTuner::new(&cfg);
+        let idle = Load {
+            foreground_p99: Duration::from_millis(10),
+            cpu_usage: 0.1,
+        };
+        let slow = Load {
+            foreground_p99: Duration::from_millis(200),
+            ..idle
+        };
+        let busy = Load {
+            cpu_usage: 0.9,
+            ..idle
+        };
+        assert_eq!(tuner.tune(&idle), (8, 100));
+        assert_eq!(tuner.tune(&slow), (4, 50));
+        assert_eq!(tuner.tune(&busy), (2, 25));
+        assert_eq!(tuner.tune(&busy), (2, 20));
+        assert_eq!(tuner.tune(&idle), (3, 30));
+        assert_eq!(tuner.tune(&idle), (4, 40));
+
+        // Only the concurrency is tuned without the max speed.
+        let cfg = Config {
+            upload_speed_limit: ReadableSize(0),
+            ..cfg
+        };
+        let mut tuner = AutoTuner::new(&cfg);
+        assert_eq!(tuner.tune(&slow), (4, 0));
+        assert_eq!(tuner.tune(&idle), (5, 0));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Arc::new(Throttle::new(1, 0));
+        let guard = throttle.acquire();
+        let (tx, rx) = mpsc::channel();
+        let t = Arc::clone(&throttle);
+        let h = thread::spawn(move || {
+            let _guard = t.acquire();
+            tx.send(()).unwrap();
+        });
+        // Blocked until the concurrency is raised.
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        throttle.set_concurrency(2);
+        rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        h.join().unwrap();
+        drop(guard);
+        assert_eq!(*throttle.running.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backs up a store by uploading the SST files of its KV engine directly.
+//!
+//! It's much faster than scanning the MVCC versions, but the files have all the versions and
+//! the data out of the requested range, and the replicas of a region on different stores are
+//! backed up repeatedly, so it's only meant for a full backup of a whole store.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crc::crc32;
+use kvproto::raft_serverpb::{PeerState, RaftApplyState, RegionLocalState};
+use protobuf;
+use rocksdb::DB;
+use tempdir::TempDir;
+
+use import::create_storage;
+use raftstore::store::engine::{Iterable, Peekable};
+use raftstore::store::keys;
+use storage::{CF_RAFT, DATA_CFS};
+use util::rocksdb::get_cf_handle;
+use util::time::Instant;
+
+use super::auto_tune::Throttle;
+use super::endpoint::encode_key;
+use super::metrics::*;
+use super::{Config, Error, Result};
+
+/// The max number of times to retry linking the files, when some of them are deleted by
+/// compactions in the middle.
+const MAX_LINK_RETRY: usize = 3;
+
+/// CheckpointRequest asks to back up the SST files of a store overlapping with
+/// [`start_key`, `end_key`).
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointRequest {
+    /// The raw start key, empty means unbounded.
+    pub start_key: Vec<u8>,
+    /// The raw end key, empty means unbounded.
+    pub end_key: Vec<u8>,
+    /// The url of the external storage, like "local:///path/to/dir".
+    pub storage_url: String,
+}
+
+/// An SST file of the KV engine uploaded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckpointFile {
+    pub name: String,
+    pub cf: String,
+    pub level: usize,
+    /// The smallest and largest data keys of the file.
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub crc32: u32,
+    pub size: u64,
+}
+
+/// The apply index of a region when the files are uploaded, the files have all the changes
+/// applied at or before it, and some of the ones after it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegionCheckpoint {
+    pub region_id: u64,
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+    pub conf_ver: u64,
+    pub version: u64,
+    pub apply_index: u64,
+}
+
+/// CheckpointMeta describes the files and the regions of a checkpoint backup.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckpointMeta {
+    pub store_id: u64,
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+    pub files: Vec<CheckpointFile>,
+    pub regions: Vec<RegionCheckpoint>,
+}
+
+/// CheckpointBackup backs up a store by hard linking the SST files of the KV engine to a
+/// checkpoint directory, so that they are not deleted by compactions, and uploading them.
+pub struct CheckpointBackup {
+    store_id: u64,
+    db: Arc<DB>,
+    /// The checkpoints are created under it, which must be in the same file system as the
+    /// KV engine.
+    checkpoint_dir: PathBuf,
+    upload_speed_limit: u64,
+}
+
+impl CheckpointBackup {
+    pub fn new<P: AsRef<Path>>(
+        store_id: u64,
+        db: Arc<DB>,
+        checkpoint_dir: P,
+        cfg: &Config,
+    ) -> CheckpointBackup {
+        CheckpointBackup {
+            store_id,
+            db,
+            checkpoint_dir: checkpoint_dir.as_ref().to_owned(),
+            upload_speed_limit: cfg.upload_speed_limit.0,
+        }
+    }
+
+    pub fn backup(&self, req: &CheckpointRequest) -> Result<CheckpointMeta> {
+        let start = Instant::now_coarse();
+        let storage = create_storage(&req.storage_url)?;
+        let start_key = encode_key(&req.start_key);
+        let end_key = encode_key(&req.end_key);
+
+        // The apply states are loaded before flushing, so that the files flushed have all
+        // the changes applied before them.
+        let regions = self.load_regions(&start_key, &end_key)?;
+        for cf in DATA_CFS {
+            let handle = get_cf_handle(&self.db, cf)?;
+            self.db.flush_cf(handle, true)?;
+        }
+
+        fs::create_dir_all(&self.checkpoint_dir)?;
+        let dir = TempDir::new_in(&self.checkpoint_dir, "checkpoint")?;
+        let lower = keys::data_key(&start_key);
+        let upper = keys::data_end_key(&end_key);
+        let files = self.link_files(dir.path(), &lower, &upper)?;
+
+        let throttle = Throttle::new(1, self.upload_speed_limit);
+        let mut uploaded = Vec::with_capacity(files.len());
+        for (path, mut file) in files {
+            let mut data = Vec::with_capacity(file.size as usize);
+            File::open(&path)?.read_to_end(&mut data)?;
+            throttle.request_upload(data.len());
+            storage.write(&file.name, &mut data.as_slice())?;
+            file.crc32 = crc32::checksum_ieee(&data);
+            BACKUP_BYTES_COUNTER
+                .with_label_values(&[&file.cf])
+                .inc_by(data.len() as i64);
+            uploaded.push(file);
+        }
+        info!(
+            "checkpoint backup uploaded {} files of {} regions, takes {:?}",
+            uploaded.len(),
+            regions.len(),
+            start.elapsed()
+        );
+        Ok(CheckpointMeta {
+            store_id: self.store_id,
+            start_key: req.start_key.clone(),
+            end_key: req.end_key.clone(),
+            files: uploaded,
+            regions,
+        })
+    }
+
+    // Loads the regions overlapping with [`start_key`, `end_key`) and their apply indexes,
+    // the keys are encoded and empty means unbounded.
+    fn load_regions(&self, start_key: &[u8], end_key: &[u8]) -> Result<Vec<RegionCheckpoint>> {
+        let mut regions = Vec::new();
+        let (min_key, max_key) = (keys::REGION_META_MIN_KEY, keys::REGION_META_MAX_KEY);
+        self.db.scan_cf(CF_RAFT, min_key, max_key, false, |key, value| {
+            let (region_id, suffix) = keys::decode_region_meta_key(key)?;
+            if suffix != keys::REGION_STATE_SUFFIX {
+                return Ok(true);
+            }
+            let local_state = protobuf::parse_from_bytes::<RegionLocalState>(value)?;
+            if local_state.get_state() == PeerState::Tombstone {
+                return Ok(true);
+            }
+            let region = local_state.get_region();
+            if (!end_key.is_empty() && region.get_start_key() >= end_key)
+                || (!region.get_end_key().is_empty() && region.get_end_key() <= start_key)
+            {
+                return Ok(true);
+            }
+            let apply_state: RaftApplyState = self
+                .db
+                .get_msg_cf(CF_RAFT, &keys::apply_state_key(region_id))?
+                .unwrap_or_default();
+            regions.push(RegionCheckpoint {
+                region_id,
+                start_key: region.get_start_key().to_vec(),
+                end_key: region.get_end_key().to_vec(),
+                conf_ver: region.get_region_epoch().get_conf_ver(),
+                version: region.get_region_epoch().get_version(),
+                apply_index: apply_state.get_applied_index(),
+            });
+            Ok(true)
+        })?;
+        Ok(regions)
+    }
+
+    // Hard links the live SST files overlapping with [`lower`, `upper`) to `dir`, and returns
+    // the links. A file may be deleted by a compaction before it's linked, then its output
+    // files are not listed, so all of them are listed and linked again.
+    fn link_files(
+        &self,
+        dir: &Path,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Vec<(PathBuf, CheckpointFile)>> {
+        let mut retry = 0;
+        loop {
+            let res = self.try_link_files(dir, lower, upper);
+            let deleted = match res {
+                Err(Error::Io(ref e)) => e.kind() == ErrorKind::NotFound,
+                _ => false,
+            };
+            if !deleted || retry >= MAX_LINK_RETRY {
+                return res;
+            }
+            retry += 1;
+            warn!("sst file deleted during checkpoint, retry {}", retry);
+            for entry in fs::read_dir(dir)? {
+                fs::remove_file(entry?.path())?;
+            }
+        }
+    }
+
+    fn try_link_files(
+        &self,
+        dir: &Path,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Vec<(PathBuf, CheckpointFile)>> {
+        let db_path = Path::new(self.db.path());
+        let mut files = Vec::new();
+        for cf in DATA_CFS {
+            let handle = get_cf_handle(&self.db, cf)?;
+            let cf_meta = self.db.get_column_family_meta_data(handle);
+            for (level, level_meta) in cf_meta.get_levels().iter().enumerate() {
+                for f in level_meta.get_files() {
+                    if upper <= f.get_smallestkey() || lower > f.get_largestkey() {
+                        continue;
+                    }
+                    // The name is like "/000012.sst".
+                    let name = f.get_name();
+                    let file_name = name.trim_left_matches('/');
+                    let link = dir.join(file_name);
+                    fs::hard_link(db_path.join(file_name), &link)?;
+                    files.push((
+                        link,
+                        CheckpointFile {
+                            name: format!("{}/{}", self.store_id, file_name),
+                            cf: cf.to_string(),
+                            level,
+                            smallest_key: f.get_smallestkey().to_vec(),
+                            largest_key: f.get_largestkey().to_vec(),
+                            crc32: 0,
+                            size: f.get_size() as u64,
+                        },
+                    ));
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kvproto::metapb::Region;
+    use rocksdb::Writable;
+
+    use raftstore::store::engine::Mutable;
+    use storage::{Key, ALL_CFS, CF_DEFAULT, CF_WRITE};
+    use util::rocksdb::new_engine;
+
+    fn put_region(db: &DB, id: u64, start: &[u8], end: &[u8], state: PeerState, index: u64) {
+        let handle = get_cf_handle(db, CF_RAFT).unwrap();
+        let mut region = Region::new();
+        region.set_id(id);
+        region.set_start_key(encode_key(start));
+        region.set_end_key(encode_key(end));
+        let mut local_state = RegionLocalState::new();
+        local_state.set_region(region);
+        local_state.set_state(state);
+        db.put_msg_cf(handle, &keys::region_state_key(id), &local_state)
+            .unwrap();
+        let mut apply_state = RaftApplyState::new();
+        apply_state.set_applied_index(index);
+        db.put_msg_cf(handle, &keys::apply_state_key(id), &apply_state)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_backup() {
+        let temp_dir = TempDir::new("test_checkpoint_backup").unwrap();
+        let db_path = temp_dir.path().join("db");
+        let db = new_engine(db_path.to_str().unwrap(), ALL_CFS, None).unwrap();
+        let db = Arc::new(db);
+        for cf in &[CF_DEFAULT, CF_WRITE] {
+            let handle = get_cf_handle(&db, cf).unwrap();
+            for k in &[b"a", b"b", b"c"] {
+                let key = Key::from_raw(*k).append_ts(10);
+                db.put_cf(handle, &keys::data_key(key.as_encoded()), b"v")
+                    .unwrap();
+            }
+        }
+        put_region(&db, 1, b"", b"m", PeerState::Normal, 5);
+        put_region(&db, 2, b"m", b"", PeerState::Normal, 6);
+        put_region(&db, 3, b"x", b"y", PeerState::Tombstone, 7);
+
+        let checkpoint_dir = temp_dir.path().join("checkpoint");
+        let backup = CheckpointBackup::new(1, Arc::clone(&db), &checkpoint_dir, &Config::default());
+        let storage_dir = temp_dir.path().join("storage");
+        let mut req = CheckpointRequest {
+            start_key: vec![],
+            end_key: vec![],
+            storage_url: format!("local://{}", storage_dir.display()),
+        };
+        let meta = backup.backup(&req).unwrap();
+        let regions: Vec<_> = meta
+            .regions
+            .iter()
+            .map(|r| (r.region_id, r.apply_index))
+            .collect();
+        assert_eq!(regions, vec![(1, 5), (2, 6)]);
+        let mut cfs: Vec<_> = meta.files.iter().map(|f| f.cf.as_str()).collect();
+        cfs.sort();
+        assert_eq!(cfs, vec![CF_DEFAULT, CF_WRITE]);
+        for file in &meta.files {
+            let data = fs::read(storage_dir.join(&file.name)).unwrap();
+            assert_eq!(data.len() as u64, file.size);
+            assert_eq!(crc32::checksum_ieee(&data), file.crc32);
+        }
+        // The links are removed after the backup.
+        assert_eq!(fs::read_dir(&checkpoint_dir).unwrap().count(), 0);
+
+        // No file overlaps with the range, and only region 2 does.
+        req.start_key = b"x".to_vec();
+        let meta = backup.backup(&req).unwrap();
+        assert!(meta.files.is_empty());
+        assert_eq!(meta.regions.len(), 1);
+        assert_eq!(meta.regions[0].region_id, 2);
+    }
+}
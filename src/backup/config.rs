@@ -0,0 +1,82 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error::Error;
+use std::result::Result;
+
+use util::config::{ReadableDuration, ReadableSize};
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The max number of regions backed up concurrently.
+    pub num_threads: usize,
+    /// The max bytes of the SST files uploaded every second, 0 means unlimited.
+    pub upload_speed_limit: ReadableSize,
+    /// Adjust the concurrency and the upload speed every `auto_tune_interval` according to the
+    /// foreground load, within [`min_num_threads`, `num_threads`] and
+    /// [`min_upload_speed_limit`, `upload_speed_limit`].
+    pub auto_tune: bool,
+    pub auto_tune_interval: ReadableDuration,
+    pub min_num_threads: usize,
+    pub min_upload_speed_limit: ReadableSize,
+    /// The backup slows down once the p99 duration of the gRPC messages exceeds it.
+    pub foreground_latency_threshold: ReadableDuration,
+    /// The backup slows down once the CPU usage of the instance exceeds it, from 0 to 1.
+    pub cpu_usage_threshold: f64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            num_threads: 4,
+            upload_speed_limit: ReadableSize(0),
+            auto_tune: true,
+            auto_tune_interval: ReadableDuration::secs(5),
+            min_num_threads: 1,
+            min_upload_speed_limit: ReadableSize::mb(8),
+            foreground_latency_threshold: ReadableDuration::millis(100),
+            cpu_usage_threshold: 0.8,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Box<Error>> {
+        if self.num_threads == 0 {
+            return Err("backup.num_threads can not be 0".into());
+        }
+        if !self.auto_tune {
+            return Ok(());
+        }
+        if self.auto_tune_interval.as_millis() == 0 {
+            return Err("backup.auto_tune_interval can not be 0".into());
+        }
+        if self.min_num_threads == 0 || self.min_num_threads > self.num_threads {
+            return Err("backup.min_num_threads should be in [1, backup.num_threads]".into());
+        }
+        if self.upload_speed_limit.0 > 0
+            && (self.min_upload_speed_limit.0 == 0
+                || self.min_upload_speed_limit.0 > self.upload_speed_limit.0)
+        {
+            return Err(
+                "backup.min_upload_speed_limit should be in [1, backup.upload_speed_limit]".into(),
+            );
+        }
+        if self.cpu_usage_threshold <= 0.0 || self.cpu_usage_threshold > 1.0 {
+            return Err("backup.cpu_usage_threshold should be in (0, 1]".into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{cmp, env, fs};
+
+use futures::{future, Future};
+use futures_cpupool::{Builder, CpuPool};
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb::{Peer, Region};
+use serde_json;
+use tempdir::TempDir;
+
+use import::{create_storage, ExternalStorage};
+use pd::PdClient;
+use raftstore::store::SeekRegionResult;
+use storage::engine::RegionInfoProvider;
+use storage::gc_manager::GCSafePointProvider;
+use storage::{Engine, Key};
+use util::time::Instant;
+
+use super::auto_tune::{MetricsSampler, Throttle, TunerHandle};
+use super::metrics::*;
+use super::scanner::BackupScanner;
+use super::writer::{BackupFile, BackupWriter};
+use super::{Config, Error, Result};
+
+/// The max number of regions `seek_region` may skip in a single call.
+const SEEK_REGION_LIMIT: u32 = 128;
+
+/// BackupRequest asks to back up the keys in [`start_key`, `end_key`) as of `backup_ts`.
+#[derive(Clone, Debug, Default)]
+pub struct BackupRequest {
+    /// The raw start key, empty means unbounded.
+    pub start_key: Vec<u8>,
+    /// The raw end key, empty means unbounded.
+    pub end_key: Vec<u8>,
+    /// The `backup_ts` of the previous backup for an incremental backup, which only has the
+    /// versions committed after it. 0 means a full backup.
+    pub start_ts: u64,
+    /// 0 means the backup ts is got from PD when the backup starts.
+    pub backup_ts: u64,
+    /// The url of the external storage, like "local:///path/to/dir".
+    pub storage_url: String,
+}
+
+/// BackupMeta describes the files of a backup, which are needed to restore it.
+///
+/// Every TiKV uploads the meta of the files it backs up as `backupmeta_{store_id}_{backup_ts}`
+/// in JSON.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BackupMeta {
+    pub store_id: u64,
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+    pub start_ts: u64,
+    pub backup_ts: u64,
+    pub files: Vec<BackupFile>,
+}
+
+/// Provides the timestamps allocated by PD.
+pub trait TsoProvider: Send + 'static {
+    fn get_tso(&self) -> Result<u64>;
+}
+
+impl<T: PdClient + 'static> TsoProvider for Arc<T> {
+    fn get_tso(&self) -> Result<u64> {
+        let ts = PdClient::get_tso(self.as_ref()).wait()?;
+        Ok(ts)
+    }
+}
+
+/// BackupEndpoint backs up the regions whose leaders are on this TiKV.
+pub struct BackupEndpoint<E, R, S>
+where
+    E: Engine,
+    R: RegionInfoProvider,
+    S: GCSafePointProvider + TsoProvider,
+{
+    store_id: u64,
+    cfg: Config,
+    engine: E,
+    region_info_provider: R,
+    /// An incremental backup needs all the versions after its `start_ts`, which must not be
+    /// older than the GC safe point. A backup without `backup_ts` gets one from it.
+    pd: S,
+    /// The SST files are built under it before they are uploaded.
+    temp_dir: PathBuf,
+    pool: CpuPool,
+}
+
+impl<E, R, S> BackupEndpoint<E, R, S>
+where
+    E: Engine,
+    R: RegionInfoProvider,
+    S: GCSafePointProvider + TsoProvider,
+{
+    pub fn new(
+        store_id: u64,
+        engine: E,
+        region_info_provider: R,
+        pd: S,
+        cfg: &Config,
+    ) -> BackupEndpoint<E, R, S> {
+        let pool = Builder::new()
+            .name_prefix("backup")
+            .pool_size(cfg.num_threads)
+            .create();
+        BackupEndpoint {
+            store_id,
+            cfg: cfg.clone(),
+            engine,
+            region_info_provider,
+            pd,
+            temp_dir: env::temp_dir(),
+            pool,
+        }
+    }
+
+    /// Builds the SST files under `dir` instead of the temp directory of the system.
+    pub fn with_temp_dir<P: AsRef<Path>>(mut self, dir: P) -> BackupEndpoint<E, R, S> {
+        self.temp_dir = dir.as_ref().to_owned();
+        self
+    }
+
+    /// Backs up the leader regions in the range of `req` concurrently, uploads the files and
+    /// their meta, and returns the meta.
+    ///
+    /// The whole backup fails if any region fails, the uploaded files are left as is.
+    pub fn backup(&self, req: &BackupRequest) -> Result<BackupMeta> {
+        let backup_ts = if req.backup_ts == 0 {
+            self.pd.get_tso()?
+        } else {
+            req.backup_ts
+        };
+        if req.start_ts > 0 {
+            if req.start_ts >= backup_ts {
+                return Err(Error::InvalidTimeRange(req.start_ts, backup_ts));
+            }
+            self.check_safe_point(req.start_ts)?;
+        }
+        let storage = create_storage(&req.storage_url)?;
+        let start_key = encode_key(&req.start_key);
+        let end_key = encode_key(&req.end_key);
+        let regions = self.seek_regions(&start_key, &end_key)?;
+        info!(
+            "backup {} regions in [{:?}, {:?}) at ({}, {}]",
+            regions.len(),
+            req.start_key,
+            req.end_key,
+            req.start_ts,
+            backup_ts
+        );
+
+        let throttle = Arc::new(Throttle::new(
+            self.cfg.num_threads,
+            self.cfg.upload_speed_limit.0,
+        ));
+        // The tuner stops when it's dropped after the backup.
+        let _tuner = if self.cfg.auto_tune {
+            Some(TunerHandle::start(
+                &self.cfg,
+                MetricsSampler::new(),
+                Arc::clone(&throttle),
+            ))
+        } else {
+            None
+        };
+        fs::create_dir_all(&self.temp_dir)?;
+        let mut tasks = Vec::with_capacity(regions.len());
+        for (region, peer) in regions {
+            let engine = self.engine.clone();
+            let storage = Arc::clone(&storage);
+            let throttle = Arc::clone(&throttle);
+            let temp_dir = self.temp_dir.clone();
+            let (start, end) = (start_key.clone(), end_key.clone());
+            let ts = (req.start_ts, backup_ts);
+            tasks.push(self.pool.spawn_fn(move || {
+                let _guard = throttle.acquire();
+                backup_region(
+                    &engine,
+                    &region,
+                    peer,
+                    &start,
+                    &end,
+                    ts,
+                    &temp_dir,
+                    &*storage,
+                    &throttle,
+                )
+            }));
+        }
+        let files = future::join_all(tasks).wait()?;
+        // The regions whose snapshots are taken after the safe point passes `start_ts` may
+        // have lost some versions to GC.
+        if req.start_ts > 0 {
+            self.check_safe_point(req.start_ts)?;
+        }
+
+        let meta = BackupMeta {
+            store_id: self.store_id,
+            start_key: req.start_key.clone(),
+            end_key: req.end_key.clone(),
+            start_ts: req.start_ts,
+            backup_ts,
+            files: files.into_iter().flat_map(|f| f).collect(),
+        };
+        let data = serde_json::to_vec_pretty(&meta)?;
+        let name = format!("backupmeta_{}_{}", self.store_id, backup_ts);
+        storage.write(&name, &mut data.as_slice())?;
+        Ok(meta)
+    }
+
+    fn check_safe_point(&self, start_ts: u64) -> Result<()> {
+        let safe_point = self.pd.get_safe_point()?;
+        if start_ts < safe_point {
+            return Err(Error::SafePointExceeded(start_ts, safe_point));
+        }
+        Ok(())
+    }
+
+    fn seek_regions(&self, start_key: &[u8], end_key: &[u8]) -> Result<Vec<(Region, Peer)>> {
+        let mut regions = Vec::new();
+        let mut key = start_key.to_vec();
+        loop {
+            let res = self.region_info_provider.seek_region(
+                &key,
+                box |peer| peer.is_leader(),
+                SEEK_REGION_LIMIT,
+            )?;
+            match res {
+                SeekRegionResult::Found { local_peer, region } => {
+                    if !end_key.is_empty() && region.get_start_key() >= end_key {
+                        break;
+                    }
+                    key = region.get_end_key().to_vec();
+                    regions.push((region, local_peer));
+                    if key.is_empty() {
+                        break;
+                    }
+                }
+                SeekRegionResult::LimitExceeded { next_key } => key = next_key,
+                SeekRegionResult::Ended => break,
+            }
+            if !end_key.is_empty() && key.as_slice() >= end_key {
+                break;
+            }
+        }
+        Ok(regions)
+    }
+}
+
+pub fn encode_key(key: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        Vec::new()
+    } else {
+        Key::from_raw(key).into_encoded()
+    }
+}
+
+/// Backs up the intersection of `region` and [`start_key`, `end_key`) in the time range
+/// `(start_ts, backup_ts]`, the keys are encoded and empty means unbounded. The SST files are
+/// built in a temp directory under `temp_dir`, which is removed after they are uploaded.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn backup_region<E: Engine>(
+    engine: &E,
+    region: &Region,
+    peer: Peer,
+    start_key: &[u8],
+    end_key: &[u8],
+    (start_ts, backup_ts): (u64, u64),
+    temp_dir: &Path,
+    storage: &ExternalStorage,
+    throttle: &Throttle,
+) -> Result<Vec<BackupFile>> {
+    let start = Instant::now_coarse();
+    let mut ctx = Context::new();
+    ctx.set_region_id(region.get_id());
+    ctx.set_region_epoch(region.get_region_epoch().clone());
+    ctx.set_peer(peer);
+    let snapshot = engine.snapshot(&ctx)?;
+
+    let lower = cmp::max(start_key, region.get_start_key());
+    let upper = if end_key.is_empty() {
+        region.get_end_key()
+    } else if region.get_end_key().is_empty() {
+        end_key
+    } else {
+        cmp::min(end_key, region.get_end_key())
+    };
+    let upper = if upper.is_empty() {
+        None
+    } else {
+        Some(Key::from_encoded_slice(upper))
+    };
+    let lower = Key::from_encoded_slice(lower);
+    let mut scanner = BackupScanner::new(snapshot, lower, upper, start_ts, backup_ts);
+    scanner.check_locks()?;
+    let name = format!(
+        "{}_{}_{}",
+        region.get_id(),
+        region.get_region_epoch().get_version(),
+        backup_ts
+    );
+    let dir = TempDir::new_in(temp_dir, "backup")?;
+    let mut writer = BackupWriter::new(dir.path(), &name)?;
+    scanner.scan(&mut writer)?;
+    let files = writer.save(storage, throttle)?;
+
+    BACKUP_REGION_DURATION.observe(start.elapsed_secs());
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::sync::Mutex;
+
+    use tempdir::TempDir;
+
+    use raftstore::store::SeekRegionFilter;
+    use storage::engine::{self, new_local_engine, TEMP_DIR};
+    use storage::mvcc::tests::*;
+    use storage::{self, ALL_CFS};
+
+    use backup::Error;
+
+    #[derive(Clone, Default)]
+    struct MockPdClient {
+        safe_point: Arc<Mutex<u64>>,
+        ts: Arc<Mutex<u64>>,
+    }
+
+    impl GCSafePointProvider for MockPdClient {
+        fn get_safe_point(&self) -> storage::Result<u64> {
+            Ok(*self.safe_point.lock().unwrap())
+        }
+    }
+
+    impl TsoProvider for MockPdClient {
+        fn get_tso(&self) -> Result<u64> {
+            Ok(*self.ts.lock().unwrap())
+        }
+    }
+
+    /// Provides regions `["", "b")` and `["b", "")` in encoded keys.
+    #[derive(Clone)]
+    struct MockRegionInfoProvider;
+
+    impl RegionInfoProvider for MockRegionInfoProvider {
+        fn seek_region(
+            &self,
+            from: &[u8],
+            _: SeekRegionFilter,
+            _: u32,
+        ) -> engine::Result<SeekRegionResult> {
+            let split = Key::from_raw(b"b").into_encoded();
+            let bounds = [(Vec::new(), split.clone()), (split, Vec::new())];
+            for (id, &(ref start, ref end)) in bounds.iter().enumerate() {
+                if end.is_empty() || from < end.as_slice() {
+                    let mut region = Region::new();
+                    region.set_id(id as u64 + 1);
+                    region.set_start_key(start.clone());
+                    region.set_end_key(end.clone());
+                    return Ok(SeekRegionResult::Found {
+                        local_peer: Peer::new(),
+                        region,
+                    });
+                }
+            }
+            Ok(SeekRegionResult::Ended)
+        }
+    }
+
+    #[test]
+    fn test_backup() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; 1024];
+        // "a" has a long value, "b" is deleted, "c" is committed after the backup.
+        must_prewrite_put(&engine, b"a", &long_value, b"a", 10);
+        must_commit(&engine, b"a", 10, 11);
+        must_prewrite_put(&engine, b"b", b"v", b"b", 10);
+        must_commit(&engine, b"b", 10, 11);
+        must_prewrite_delete(&engine, b"b", b"b", 12);
+        must_commit(&engine, b"b", 12, 13);
+        must_prewrite_put(&engine, b"c", b"v1", b"c", 10);
+        must_commit(&engine, b"c", 10, 11);
+        must_prewrite_put(&engine, b"c", b"v2", b"c", 20);
+        must_commit(&engine, b"c", 20, 21);
+
+        let temp_dir = TempDir::new("test_backup").unwrap();
+        let cfg = Config {
+            auto_tune: false,
+            ..Default::default()
+        };
+        let pd = MockPdClient::default();
+        let endpoint = BackupEndpoint::new(
+            1,
+            engine.clone(),
+            MockRegionInfoProvider,
+            pd.clone(),
+            &cfg,
+        );
+        let mut req = BackupRequest {
+            start_key: vec![],
+            end_key: vec![],
+            start_ts: 0,
+            backup_ts: 15,
+            storage_url: format!("local://{}", temp_dir.path().display()),
+        };
+        let meta = endpoint.backup(&req).unwrap();
+        // Region 1 has "a" in both CFs, region 2 has the older "c" in the write CF only.
+        let mut files: Vec<_> = meta.files.iter().map(|f| f.name.clone()).collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["1_0_15_default.sst", "1_0_15_write.sst", "2_0_15_write.sst"]
+        );
+        for f in &meta.files {
+            assert_eq!(f.total_kvs, 1);
+            let path = temp_dir.path().join(&f.name);
+            assert_eq!(fs::metadata(path).unwrap().len(), f.size);
+        }
+        let f = meta.files.iter().find(|f| f.name == "2_0_15_write.sst").unwrap();
+        let (key, commit_ts) = Key::split_on_ts_for(&f.start_key).unwrap();
+        assert_eq!(key, Key::from_raw(b"c").as_encoded().as_slice());
+        assert_eq!(commit_ts, 11);
+        // The meta is uploaded with the files.
+        let data = fs::read(temp_dir.path().join("backupmeta_1_15")).unwrap();
+        let uploaded: BackupMeta = serde_json::from_slice(&data).unwrap();
+        assert_eq!(uploaded, meta);
+
+        // Only region 2 is backed up.
+        req.start_key = b"c".to_vec();
+        let meta = endpoint.backup(&req).unwrap();
+        assert_eq!(meta.files.len(), 1);
+
+        // A pending lock before the backup ts fails the backup.
+        must_prewrite_put(&engine, b"d", b"v", b"d", 14);
+        match endpoint.backup(&req) {
+            Err(Error::Mvcc(_)) => {}
+            res => panic!("expect key is locked, got {:?}", res),
+        }
+        // But not the ones after it.
+        req.backup_ts = 13;
+        assert_eq!(endpoint.backup(&req).unwrap().files.len(), 1);
+
+        // The backup ts is got from PD if it's not given.
+        *pd.ts.lock().unwrap() = 12;
+        req.backup_ts = 0;
+        let meta = endpoint.backup(&req).unwrap();
+        assert_eq!(meta.backup_ts, 12);
+        assert_eq!(meta.files[0].name, "2_0_12_write.sst");
+        assert!(temp_dir.path().join("backupmeta_1_12").exists());
+    }
+
+    #[test]
+    fn test_incremental_backup() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; 1024];
+        must_prewrite_put(&engine, b"c", b"v1", b"c", 10);
+        must_commit(&engine, b"c", 10, 11);
+        must_prewrite_put(&engine, b"c", &long_value, b"c", 20);
+        must_commit(&engine, b"c", 20, 21);
+        must_prewrite_delete(&engine, b"c", b"c", 22);
+        must_commit(&engine, b"c", 22, 23);
+        must_prewrite_put(&engine, b"d", b"v1", b"d", 24);
+        must_commit(&engine, b"d", 24, 25);
+        must_prewrite_put(&engine, b"d", b"v2", b"d", 30);
+        must_commit(&engine, b"d", 30, 31);
+
+        let temp_dir = TempDir::new("test_incremental_backup").unwrap();
+        let cfg = Config {
+            auto_tune: false,
+            ..Default::default()
+        };
+        let pd = MockPdClient::default();
+        let endpoint = BackupEndpoint::new(
+            1,
+            engine.clone(),
+            MockRegionInfoProvider,
+            pd.clone(),
+            &cfg,
+        );
+        let mut req = BackupRequest {
+            start_key: vec![],
+            end_key: vec![],
+            start_ts: 11,
+            backup_ts: 25,
+            storage_url: format!("local://{}", temp_dir.path().display()),
+        };
+        let meta = endpoint.backup(&req).unwrap();
+        assert_eq!(meta.start_ts, 11);
+        // The put of "c" at 21 with its long value, the delete of "c" at 23 and the put of "d"
+        // at 25.
+        let mut files: Vec<_> = meta
+            .files
+            .iter()
+            .map(|f| (f.name.clone(), f.total_kvs))
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("2_0_25_default.sst".to_owned(), 1),
+                ("2_0_25_write.sst".to_owned(), 3),
+            ]
+        );
+        let f = meta.files.iter().find(|f| f.name == "2_0_25_write.sst").unwrap();
+        let (_, commit_ts) = Key::split_on_ts_for(&f.start_key).unwrap();
+        assert_eq!(commit_ts, 23);
+        let (key, commit_ts) = Key::split_on_ts_for(&f.end_key).unwrap();
+        assert_eq!(key, Key::from_raw(b"d").as_encoded().as_slice());
+        assert_eq!(commit_ts, 25);
+
+        // The versions after `start_ts` may have been GC-ed.
+        *pd.safe_point.lock().unwrap() = 12;
+        match endpoint.backup(&req) {
+            Err(Error::SafePointExceeded(11, 12)) => {}
+            res => panic!("expect safe point exceeded, got {:?}", res),
+        }
+        *pd.safe_point.lock().unwrap() = 11;
+        assert!(endpoint.backup(&req).is_ok());
+
+        req.start_ts = 25;
+        match endpoint.backup(&req) {
+            Err(Error::InvalidTimeRange(25, 25)) => {}
+            res => panic!("expect invalid time range, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error as IoError;
+use std::result;
+
+use import::Error as ImportError;
+use pd::Error as PdError;
+use serde_json::Error as JsonError;
+use raftstore::Error as RaftstoreError;
+use storage::mvcc::Error as MvccError;
+use storage::{EngineError, Error as StorageError};
+use util::codec::Error as CodecError;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: IoError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RocksDB(msg: String) {
+            from()
+            display("RocksDB {}", msg)
+        }
+        Engine(err: EngineError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Storage(err: StorageError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Json(err: JsonError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Import(err: ImportError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Pd(err: PdError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Raftstore(err: RaftstoreError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        InvalidTimeRange(start_ts: u64, backup_ts: u64) {
+            display("start_ts {} should be less than backup_ts {}", start_ts, backup_ts)
+        }
+        SafePointExceeded(start_ts: u64, safe_point: u64) {
+            display("start_ts {} is older than the GC safe point {}", start_ts, safe_point)
+        }
+        DefaultNotFound(key: Vec<u8>, start_ts: u64) {
+            display("default value of {:?} at {} not found", key, start_ts)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
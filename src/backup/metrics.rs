@@ -0,0 +1,40 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use prometheus::*;
+
+lazy_static! {
+    pub static ref BACKUP_REGION_DURATION: Histogram = register_histogram!(
+        "tikv_backup_region_duration_seconds",
+        "Bucketed histogram of backup duration of a region",
+        exponential_buckets(0.001, 2.0, 20).unwrap()
+    ).unwrap();
+    pub static ref BACKUP_KV_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_backup_kv_total",
+        "Total number of kvs backed up",
+        &["cf"]
+    ).unwrap();
+    pub static ref BACKUP_BYTES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_backup_bytes_total",
+        "Total bytes of the SST files backed up",
+        &["cf"]
+    ).unwrap();
+    pub static ref BACKUP_CONCURRENCY_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_backup_concurrency",
+        "The number of regions allowed to be backed up concurrently"
+    ).unwrap();
+    pub static ref BACKUP_SPEED_LIMIT_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_backup_speed_limit_bytes",
+        "The max bytes of the SST files allowed to be uploaded every second"
+    ).unwrap();
+}
@@ -0,0 +1,46 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This mod backs up the data of a key range to SST files on an external storage.
+//!
+//! Every TiKV backs up the regions whose leaders are on it. For each region, the newest
+//! version of every key committed before the backup timestamp is written to an SST file of
+//! the write CF, and the long values are written to an SST file of the default CF, so that
+//! the files can be restored by the SST importer directly. The files of all the regions are
+//! described by a `BackupMeta`, which is uploaded with them.
+//!
+//! An incremental backup has all the versions committed since the previous backup instead,
+//! including deletes, so that it doesn't need to scan and upload the unchanged data again.
+//!
+//! A checkpoint backup uploads the SST files of a store as they are instead, with the apply
+//! indexes of its regions, which is much faster for a full backup of a whole store.
+//!
+//! A backup competes with the foreground requests for the CPU and the disk, so it's throttled
+//! by the concurrency and the upload speed, which are lowered when the foreground slows down.
+
+mod auto_tune;
+mod checkpoint;
+mod config;
+mod endpoint;
+mod errors;
+mod metrics;
+mod scanner;
+mod writer;
+
+pub use self::checkpoint::{
+    CheckpointBackup, CheckpointFile, CheckpointMeta, CheckpointRequest, RegionCheckpoint,
+};
+pub use self::config::Config;
+pub use self::endpoint::{BackupEndpoint, BackupMeta, BackupRequest, TsoProvider};
+pub use self::errors::{Error, Result};
+pub use self::writer::BackupFile;
@@ -0,0 +1,164 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use storage::mvcc::{Error as MvccError, Lock, LockType, Write, WriteType};
+use storage::{CursorBuilder, Key, Snapshot, Statistics, CF_DEFAULT, CF_LOCK, CF_WRITE};
+
+use super::writer::BackupWriter;
+use super::{Error, Result};
+
+/// BackupScanner scans the versions in a range of a snapshot to back up.
+///
+/// A full backup, whose `start_ts` is 0, has the newest committed version of every key whose
+/// commit_ts is not greater than `backup_ts`. An incremental backup has all the versions
+/// committed in (`start_ts`, `backup_ts`], including deletes, so that it can be applied on
+/// top of the previous backup.
+pub struct BackupScanner<S: Snapshot> {
+    snapshot: S,
+    lower: Key,
+    upper: Option<Key>,
+    start_ts: u64,
+    backup_ts: u64,
+    statistics: Statistics,
+}
+
+impl<S: Snapshot> BackupScanner<S> {
+    /// Creates a scanner of [`lower`, `upper`), `None` upper means unbounded.
+    pub fn new(
+        snapshot: S,
+        lower: Key,
+        upper: Option<Key>,
+        start_ts: u64,
+        backup_ts: u64,
+    ) -> BackupScanner<S> {
+        BackupScanner {
+            snapshot,
+            lower,
+            upper,
+            start_ts,
+            backup_ts,
+            statistics: Statistics::default(),
+        }
+    }
+
+    /// Returns an error if there is any lock in the range that may be committed before
+    /// `backup_ts`, since the backup would miss its data.
+    pub fn check_locks(&mut self) -> Result<()> {
+        let mut cursor = CursorBuilder::new(&self.snapshot, CF_LOCK)
+            .fill_cache(false)
+            .range(Some(self.lower.clone()), self.upper.clone())
+            .build()?;
+        let stats = &mut self.statistics.lock;
+        cursor.seek(&self.lower, stats)?;
+        while cursor.valid() {
+            let lock = Lock::parse(cursor.value(stats))?;
+            if lock.ts <= self.backup_ts && lock.lock_type != LockType::Lock {
+                let key = Key::from_encoded_slice(cursor.key(stats)).to_raw()?;
+                return Err(Error::Mvcc(MvccError::KeyIsLocked {
+                    key,
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                }));
+            }
+            cursor.next(stats);
+        }
+        Ok(())
+    }
+
+    /// Writes the versions to `writer`.
+    pub fn scan(&mut self, writer: &mut BackupWriter) -> Result<()> {
+        if self.start_ts > 0 {
+            return self.scan_incremental(writer);
+        }
+        let mut cursor = CursorBuilder::new(&self.snapshot, CF_WRITE)
+            .fill_cache(false)
+            .range(Some(self.lower.clone()), self.upper.clone())
+            .build()?;
+        let stats = &mut self.statistics.write;
+        cursor.seek(&self.lower, stats)?;
+        // The user key whose version to back up has been found.
+        let mut last_key: Option<Vec<u8>> = None;
+        while cursor.valid() {
+            let (user_key, commit_ts) = {
+                let (user_key, commit_ts) = Key::split_on_ts_for(cursor.key(stats))?;
+                (user_key.to_vec(), commit_ts)
+            };
+            if commit_ts > self.backup_ts || last_key.as_ref() == Some(&user_key) {
+                cursor.next(stats);
+                continue;
+            }
+            let write = Write::parse(cursor.value(stats))?;
+            match write.write_type {
+                WriteType::Put => {
+                    put_default(&self.snapshot, writer, &user_key, &write)?;
+                    writer.put_write(cursor.key(stats), cursor.value(stats))?;
+                    last_key = Some(user_key);
+                }
+                WriteType::Delete => last_key = Some(user_key),
+                // Look for an older version.
+                WriteType::Lock | WriteType::Rollback => {}
+            }
+            cursor.next(stats);
+        }
+        Ok(())
+    }
+
+    // The caller must make sure `start_ts` is less than `backup_ts` and not older than the GC
+    // safe point, or some of the versions after it may have been GC-ed.
+    fn scan_incremental(&mut self, writer: &mut BackupWriter) -> Result<()> {
+        let mut cursor = CursorBuilder::new(&self.snapshot, CF_WRITE)
+            .fill_cache(false)
+            .range(Some(self.lower.clone()), self.upper.clone())
+            .build()?;
+        let stats = &mut self.statistics.write;
+        cursor.seek(&self.lower, stats)?;
+        while cursor.valid() {
+            let (user_key, commit_ts) = {
+                let (user_key, commit_ts) = Key::split_on_ts_for(cursor.key(stats))?;
+                (user_key.to_vec(), commit_ts)
+            };
+            if commit_ts > self.start_ts && commit_ts <= self.backup_ts {
+                let write = Write::parse(cursor.value(stats))?;
+                match write.write_type {
+                    WriteType::Put => {
+                        put_default(&self.snapshot, writer, &user_key, &write)?;
+                        writer.put_write(cursor.key(stats), cursor.value(stats))?;
+                    }
+                    WriteType::Delete => writer.put_write(cursor.key(stats), cursor.value(stats))?,
+                    // They don't change the value.
+                    WriteType::Lock | WriteType::Rollback => {}
+                }
+            }
+            cursor.next(stats);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the long value of the put `write` of `user_key` to the default CF.
+fn put_default<S: Snapshot>(
+    snapshot: &S,
+    writer: &mut BackupWriter,
+    user_key: &[u8],
+    write: &Write,
+) -> Result<()> {
+    if write.short_value.is_some() {
+        return Ok(());
+    }
+    let key = Key::from_encoded_slice(user_key).append_ts(write.start_ts);
+    match snapshot.get_cf(CF_DEFAULT, &key)? {
+        Some(value) => writer.put_default(key.as_encoded(), &value),
+        None => Err(Error::DefaultNotFound(user_key.to_vec(), write.start_ts)),
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crc::crc32::{self, Hasher32};
+use rocksdb::{ColumnFamilyOptions, EnvOptions, SstFileWriter};
+
+use import::ExternalStorage;
+use raftstore::store::keys;
+use storage::{CF_DEFAULT, CF_WRITE};
+
+use super::auto_tune::Throttle;
+use super::metrics::*;
+use super::Result;
+
+/// BackupFile describes an SST file uploaded to the external storage.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BackupFile {
+    pub name: String,
+    pub cf: String,
+    /// The smallest key in the file, with timestamp and without the data prefix.
+    pub start_key: Vec<u8>,
+    /// The largest key in the file, with timestamp and without the data prefix.
+    pub end_key: Vec<u8>,
+    pub crc32: u32,
+    pub size: u64,
+    pub total_kvs: u64,
+}
+
+struct CfWriter {
+    cf: &'static str,
+    name: String,
+    path: PathBuf,
+    writer: SstFileWriter,
+    total_kvs: u64,
+}
+
+impl CfWriter {
+    fn new(dir: &Path, name: &str, cf: &'static str) -> Result<CfWriter> {
+        let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        let name = format!("{}_{}.sst", name, cf);
+        let path = dir.join(&name);
+        writer.open(path.to_str().unwrap())?;
+        Ok(CfWriter {
+            cf,
+            name,
+            path,
+            writer,
+            total_kvs: 0,
+        })
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.writer.put(&keys::data_key(key), value)?;
+        self.total_kvs += 1;
+        Ok(())
+    }
+
+    fn save(
+        mut self,
+        storage: &ExternalStorage,
+        throttle: &Throttle,
+    ) -> Result<Option<BackupFile>> {
+        if self.total_kvs == 0 {
+            return Ok(None);
+        }
+        let info = self.writer.finish()?;
+        let mut reader = UploadReader::new(File::open(&self.path)?, throttle);
+        storage.write(&self.name, &mut reader)?;
+
+        BACKUP_KV_COUNTER
+            .with_label_values(&[self.cf])
+            .inc_by(self.total_kvs as i64);
+        BACKUP_BYTES_COUNTER
+            .with_label_values(&[self.cf])
+            .inc_by(reader.size as i64);
+        Ok(Some(BackupFile {
+            name: self.name,
+            cf: self.cf.to_owned(),
+            start_key: keys::origin_key(info.smallest_key()).to_vec(),
+            end_key: keys::origin_key(info.largest_key()).to_vec(),
+            crc32: reader.digest.sum32(),
+            size: reader.size,
+            total_kvs: self.total_kvs,
+        }))
+    }
+}
+
+/// UploadReader reads a file within the speed limit of `throttle`, and computes the crc32 and
+/// the size of the data read.
+struct UploadReader<'a, R> {
+    reader: R,
+    throttle: &'a Throttle,
+    digest: crc32::Digest,
+    size: u64,
+}
+
+impl<'a, R: Read> UploadReader<'a, R> {
+    fn new(reader: R, throttle: &'a Throttle) -> UploadReader<'a, R> {
+        UploadReader {
+            reader,
+            throttle,
+            digest: crc32::Digest::new(crc32::IEEE),
+            size: 0,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for UploadReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.throttle.request_upload(n);
+        self.digest.write(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+/// BackupWriter writes the kvs of a range to the SST files of the default and write CF in a
+/// local directory, and uploads them to the external storage when saved.
+///
+/// Keys must be put in ascending order for each CF.
+pub struct BackupWriter {
+    default: CfWriter,
+    write: CfWriter,
+}
+
+impl BackupWriter {
+    /// Creates a writer whose files are named with prefix `name` under `dir`.
+    pub fn new(dir: &Path, name: &str) -> Result<BackupWriter> {
+        let default = CfWriter::new(dir, name, CF_DEFAULT)?;
+        let write = CfWriter::new(dir, name, CF_WRITE)?;
+        Ok(BackupWriter { default, write })
+    }
+
+    /// Puts an encoded key with start_ts and its value to the default CF.
+    pub fn put_default(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.default.put(key, value)
+    }
+
+    /// Puts an encoded key with commit_ts and its write record to the write CF.
+    pub fn put_write(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write.put(key, value)
+    }
+
+    /// Uploads the non-empty SST files to `storage` within the speed limit of `throttle`, and
+    /// returns them.
+    pub fn save(self, storage: &ExternalStorage, throttle: &Throttle) -> Result<Vec<BackupFile>> {
+        let mut files = Vec::with_capacity(2);
+        for w in vec![self.default, self.write] {
+            if let Some(file) = w.save(storage, throttle)? {
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+}
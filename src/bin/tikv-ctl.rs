@@ -55,6 +55,7 @@ use tikv::pd::{Config as PdConfig, PdClient, RpcClient};
 use tikv::raftstore::store::{keys, Engines};
 use tikv::server::debug::{BottommostLevelCompaction, Debugger, RegionInfo};
 use tikv::storage::{Key, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use tikv::util::io_limiter::IOLimiter;
 use tikv::util::rocksdb as rocksdb_util;
 use tikv::util::security::{SecurityConfig, SecurityManager};
 use tikv::util::{escape, unescape};
@@ -88,22 +89,22 @@ fn new_debug_executor(
                     })
                     .unwrap()
             });
+            let block_cache = cfg.build_shared_block_cache();
             let kv_db_opts = cfg.rocksdb.build_opt();
-            let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+            let kv_cfs_opts = cfg.rocksdb.build_cf_opts(&block_cache);
             let kv_db = rocksdb_util::new_engine_opt(kv_path, kv_db_opts, kv_cfs_opts).unwrap();
 
             let raft_path = raft_db
                 .map(|p| p.to_string())
                 .unwrap_or_else(|| format!("{}/../raft", kv_path));
             let raft_db_opts = cfg.raftdb.build_opt();
-            let raft_db_cf_opts = cfg.raftdb.build_cf_opts();
+            let raft_db_cf_opts = cfg.raftdb.build_cf_opts(&block_cache);
             let raft_db =
                 rocksdb_util::new_engine_opt(&raft_path, raft_db_opts, raft_db_cf_opts).unwrap();
 
-            Box::new(Debugger::new(Engines::new(
-                Arc::new(kv_db),
-                Arc::new(raft_db),
-            ))) as Box<DebugExecutor>
+            let mut debugger = Debugger::new(Engines::new(Arc::new(kv_db), Arc::new(raft_db)));
+            debugger.set_enable_dirty_read(cfg.server.enable_debug_dirty_read);
+            Box::new(debugger) as Box<DebugExecutor>
         }
         (Some(remote), None) => Box::new(new_debug_client(remote, mgr)) as Box<DebugExecutor>,
         _ => unreachable!(),
@@ -233,11 +234,13 @@ trait DebugExecutor {
             limit = 1;
         }
 
-        let scan_future = self.get_mvcc_infos(from.clone(), to, limit).for_each(
-            move |(key, mvcc)| {
+        let mut found = false;
+        let res = self.get_mvcc_infos(from.clone(), to, limit).for_each(
+            |(key, mvcc)| {
                 if point_query && key != from {
-                    println!("no mvcc infos for {}", escape(&from));
+                    return future::ok::<(), String>(());
                 }
+                found = true;
 
                 println!("key: {}", escape(&key));
                 if cfs.contains(&CF_LOCK) && mvcc.has_lock() {
@@ -265,11 +268,14 @@ trait DebugExecutor {
                 println!();
                 future::ok::<(), String>(())
             },
-        );
-        if let Err(e) = scan_future.wait() {
+        ).wait();
+        if let Err(e) = res {
             eprintln!("{}", e);
             process::exit(-1);
         }
+        if point_query && !found {
+            println!("no mvcc infos for {}", escape(&from));
+        }
     }
 
     fn diff_region(
@@ -530,6 +536,17 @@ trait DebugExecutor {
     fn dump_metrics(&self, tags: Vec<&str>);
 
     fn dump_region_properties(&self, region_id: u64);
+
+    fn dump_dirty_read(&self, region_id: u64, key: Vec<u8>);
+
+    fn dump_region_export(
+        &self,
+        region_id: u64,
+        ts: u64,
+        cursor: Vec<u8>,
+        batch_size: usize,
+        bytes_per_sec: u64,
+    );
 }
 
 impl DebugExecutor for DebugClient {
@@ -704,6 +721,14 @@ impl DebugExecutor for DebugClient {
             println!("{}: {}", prop.get_name(), prop.get_value());
         }
     }
+
+    fn dump_dirty_read(&self, _: u64, _: Vec<u8>) {
+        self.check_local_mode();
+    }
+
+    fn dump_region_export(&self, _: u64, _: u64, _: Vec<u8>, _: usize, _: u64) {
+        self.check_local_mode();
+    }
 }
 
 impl DebugExecutor for Debugger {
@@ -873,6 +898,45 @@ impl DebugExecutor for Debugger {
             println!("{}: {}", name, value);
         }
     }
+
+    fn dump_dirty_read(&self, region_id: u64, key: Vec<u8>) {
+        match self.dirty_read(region_id, &key) {
+            Ok(Some(value)) => println!("value: {}", escape(&value)),
+            Ok(None) => println!("no committed value for {}", escape(&key)),
+            Err(e) => perror_and_exit("Debugger::dirty_read", e),
+        }
+    }
+
+    fn dump_region_export(
+        &self,
+        region_id: u64,
+        ts: u64,
+        mut cursor: Vec<u8>,
+        batch_size: usize,
+        bytes_per_sec: u64,
+    ) {
+        let limiter = if bytes_per_sec > 0 {
+            Some(IOLimiter::new(bytes_per_sec))
+        } else {
+            None
+        };
+        loop {
+            let (pairs, next) = self
+                .export_region(region_id, ts, &cursor, batch_size, limiter.as_ref())
+                .unwrap_or_else(|e| perror_and_exit("Debugger::export_region", e));
+            for (key, value) in pairs {
+                println!("{}\t{}", escape(&key), escape(&value));
+            }
+            match next {
+                Some(next) => {
+                    // Printed to stderr, so that the export can be resumed with it.
+                    eprintln!("resume cursor: {}", escape(&next));
+                    cursor = next;
+                }
+                None => return,
+            }
+        }
+    }
 }
 
 fn main() {
@@ -1095,6 +1159,27 @@ fn main() {
                         .help(raw_key_hint)
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("dirty-read")
+                .about(
+                    "UNSAFE: print the latest committed value ignoring locks, \
+                     which requires server.enable-debug-dirty-read in the config",
+                )
+                .arg(
+                    Arg::with_name("region")
+                        .required(true)
+                        .short("r")
+                        .takes_value(true)
+                        .help("set the region id"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .required(true)
+                        .short("k")
+                        .takes_value(true)
+                        .help(raw_key_hint)
+                ),
+        )
         .subcommand(
             SubCommand::with_name("mvcc")
                 .about("print the mvcc value")
@@ -1105,6 +1190,12 @@ fn main() {
                         .takes_value(true)
                         .help(raw_key_hint)
                 )
+                .arg(
+                    Arg::with_name("user-key")
+                        .long("user-key")
+                        .takes_value(false)
+                        .help("the key is a user key, which is encoded with the \"z\" prefix before querying"),
+                )
                 .arg(
                     Arg::with_name("show-cf")
                         .long("show-cf")
@@ -1113,7 +1204,7 @@ fn main() {
                         .use_delimiter(true)
                         .require_delimiter(true)
                         .value_delimiter(",")
-                        .default_value(CF_DEFAULT)
+                        .default_value("default,lock,write")
                         .help("column family names, combined from default/lock/write"),
                 )
                 .arg(
@@ -1450,6 +1541,48 @@ fn main() {
                         .help("how to compact the bottommost level"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("export-region")
+                .about(
+                    "export the committed data of a region at a timestamp, \
+                     the output is resumable with the printed cursor",
+                )
+                .arg(
+                    Arg::with_name("region")
+                        .short("r")
+                        .required(true)
+                        .takes_value(true)
+                        .help("the target region id"),
+                )
+                .arg(
+                    Arg::with_name("ts")
+                        .long("ts")
+                        .required(true)
+                        .takes_value(true)
+                        .help("the timestamp to read data at"),
+                )
+                .arg(
+                    Arg::with_name("cursor")
+                        .long("cursor")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("the cursor to resume from, empty means the start of the region"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .takes_value(true)
+                        .default_value("1024")
+                        .help("the number of key-value pairs read in each batch"),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("the max bytes read per second, 0 means unlimited"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("region-properties")
                 .about("show region properties")
@@ -1632,8 +1765,15 @@ fn main() {
         let start_ts = matches.value_of("start_ts").map(|s| s.parse().unwrap());
         let commit_ts = matches.value_of("commit_ts").map(|s| s.parse().unwrap());
         debug_executor.dump_mvccs_infos(from, to, limit, cfs, start_ts, commit_ts);
+    } else if let Some(matches) = matches.subcommand_matches("dirty-read") {
+        let region_id = value_t_or_exit!(matches.value_of("region"), u64);
+        let key = unescape(matches.value_of("key").unwrap());
+        debug_executor.dump_dirty_read(region_id, key);
     } else if let Some(matches) = matches.subcommand_matches("mvcc") {
-        let from = unescape(matches.value_of("key").unwrap());
+        let mut from = unescape(matches.value_of("key").unwrap());
+        if matches.is_present("user-key") {
+            from = keys::data_key(Key::from_raw(&from).encoded());
+        }
         let cfs = Vec::from_iter(matches.values_of("show-cf").unwrap());
         let start_ts = matches.value_of("start_ts").map(|s| s.parse().unwrap());
         let commit_ts = matches.value_of("commit_ts").map(|s| s.parse().unwrap());
@@ -1721,6 +1861,13 @@ fn main() {
     } else if let Some(matches) = matches.subcommand_matches("metrics") {
         let tags = Vec::from_iter(matches.values_of("tag").unwrap());
         debug_executor.dump_metrics(tags)
+    } else if let Some(matches) = matches.subcommand_matches("export-region") {
+        let region_id = value_t_or_exit!(matches.value_of("region"), u64);
+        let ts = value_t_or_exit!(matches.value_of("ts"), u64);
+        let cursor = unescape(matches.value_of("cursor").unwrap());
+        let batch_size = value_t_or_exit!(matches.value_of("batch-size"), usize);
+        let rate = value_t_or_exit!(matches.value_of("rate"), u64);
+        debug_executor.dump_region_export(region_id, ts, cursor, batch_size, rate);
     } else if let Some(matches) = matches.subcommand_matches("region-properties") {
         let region_id = value_t_or_exit!(matches.value_of("region"), u64);
         debug_executor.dump_region_properties(region_id)
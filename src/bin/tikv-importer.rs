@@ -120,6 +120,9 @@ fn setup_config(matches: &ArgMatches) -> TiKvConfig {
     if let Err(e) = config.import.validate() {
         fatal!("invalid configuration: {:?}", e);
     }
+    if let Err(e) = config.security.validate() {
+        fatal!("invalid security configuration: {:?}", e);
+    }
     info!(
         "using config: {}",
         serde_json::to_string_pretty(&config).unwrap()
@@ -129,7 +132,8 @@ fn setup_config(matches: &ArgMatches) -> TiKvConfig {
 }
 
 fn run_import_server(config: &TiKvConfig) {
-    let mut server = ImportKVServer::new(config);
+    let mut server = ImportKVServer::new(config)
+        .unwrap_or_else(|e| fatal!("failed to create import server: {:?}", e));
     server.start();
     info!("import server started");
     signal_handler::handle_signal(None);
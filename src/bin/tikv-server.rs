@@ -56,22 +56,34 @@ use std::usize;
 use clap::{App, Arg};
 use fs2::FileExt;
 
-use tikv::config::{check_and_persist_critical_config, TiKvConfig};
+use tikv::config::{check_and_persist_critical_config, ConfigController, TiKvConfig};
 use tikv::coprocessor;
 use tikv::import::{ImportSSTService, SSTImporter};
-use tikv::pd::{PdClient, RpcClient};
+use tikv::log_backup::{LogBackupObserver, Runner as LogBackupRunner};
+use tikv::pd::{ClusterWatcher, PdClient, RpcClient};
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::raftstore::store::{self, new_compaction_listener, Engines, SnapManagerBuilder};
+use tikv::server::quota_limiter::{self, QuotaLimiter};
 use tikv::server::readpool::ReadPool;
 use tikv::server::resolve;
 use tikv::server::transport::ServerRaftStoreRouter;
-use tikv::server::{create_raft_storage, Node, Server, DEFAULT_CLUSTER_ID};
+use tikv::server::{create_raft_storage, Node, Server, StatusServer, DEFAULT_CLUSTER_ID};
+use tikv::storage::gc_manager::{GCManager, GCManagerConfig};
 use tikv::storage::{self, DEFAULT_ROCKSDB_SUB_DIR};
+use tikv::util::encryption::{
+    self, DataKeyManager, RotatorHandle, DATA_KEY_ROTATION_CHECK_INTERVAL_SECS,
+};
+use tikv::util::io_limiter::{self, IORateLimiter};
+use tikv::util::memory;
 use tikv::util::rocksdb::metrics_flusher::{MetricsFlusher, DEFAULT_FLUSHER_INTERVAL};
+use tikv::util::rocksdb::rate_limiter_tuner::{
+    DbSampler, TunerHandle, DEFAULT_TUNE_INTERVAL_SECS,
+};
 use tikv::util::security::SecurityManager;
 use tikv::util::time::Monitor;
+use tikv::util::trace;
 use tikv::util::transport::SendCh;
-use tikv::util::worker::{Builder, FutureWorker};
+use tikv::util::worker::{Builder, FutureWorker, Worker};
 use tikv::util::{self as tikv_util, panic_hook, rocksdb as rocksdb_util};
 
 const RESERVED_OPEN_FDS: u64 = 1000;
@@ -104,6 +116,7 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     let snap_path = store_path.join(Path::new("snap"));
     let raft_db_path = Path::new(&cfg.raft_store.raftdb_path);
     let import_path = store_path.join("import");
+    let encryption_path = store_path.join("encryption");
 
     let f = File::create(lock_path.as_path())
         .unwrap_or_else(|e| fatal!("failed to create lock at {}: {:?}", lock_path.display(), e));
@@ -114,6 +127,8 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
         );
     }
 
+    trace::init(&cfg.tracing).unwrap_or_else(|e| fatal!("failed to start tracing: {}", e));
+
     // Initialize raftstore channels.
     let mut event_loop = store::create_event_loop(&cfg.raft_store)
         .unwrap_or_else(|e| fatal!("failed to create event loop: {:?}", e));
@@ -129,7 +144,9 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     // Create router.
     let raft_router =
         ServerRaftStoreRouter::new(store_sendch.clone(), significant_msg_sender, local_ch);
-    let compaction_listener = new_compaction_listener(store_sendch.clone());
+    // Other components can watch the events of the kv engine by registering listeners to it.
+    let kv_event_listeners = rocksdb_util::EventListenerRegistry::new();
+    kv_event_listeners.register(new_compaction_listener(store_sendch.clone()));
 
     // Create pd client and pd worker
     let pd_client = Arc::new(pd_client);
@@ -138,10 +155,30 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
         .unwrap_or_else(|e| fatal!("failed to start address resolver: {:?}", e));
     let pd_sender = pd_worker.scheduler();
 
+    // Create the data key manager if encryption is enabled. It only encrypts the snapshot and
+    // import files, the files of the engines are not encrypted.
+    encryption::check_encryption_mode(&cfg.security.encryption, &encryption_path)
+        .unwrap_or_else(|e| fatal!("{}", e));
+    let key_manager = DataKeyManager::from_config(&cfg.security.encryption, &encryption_path)
+        .unwrap_or_else(|e| fatal!("failed to create data key manager: {:?}", e))
+        .map(Arc::new);
+    let rotation_period = cfg.security.encryption.data_key_rotation_period.0;
+    let key_rotator = key_manager.as_ref().and_then(|m| {
+        if rotation_period.as_secs() == 0 {
+            return None;
+        }
+        Some(RotatorHandle::start(
+            Arc::clone(m),
+            rotation_period,
+            Duration::from_secs(DATA_KEY_ROTATION_CHECK_INTERVAL_SECS),
+        ))
+    });
+
     // Create kv engine, storage.
+    let block_cache = cfg.build_shared_block_cache();
     let mut kv_db_opts = cfg.rocksdb.build_opt();
-    kv_db_opts.add_event_listener(compaction_listener);
-    let kv_cfs_opts = cfg.rocksdb.build_cf_opts();
+    kv_db_opts.add_event_listener(kv_event_listeners.clone());
+    let kv_cfs_opts = cfg.rocksdb.build_cf_opts(&block_cache);
     let kv_engine = Arc::new(
         rocksdb_util::new_engine_opt(db_path.to_str().unwrap(), kv_db_opts, kv_cfs_opts)
             .unwrap_or_else(|s| fatal!("failed to create kv engine: {:?}", s)),
@@ -159,10 +196,13 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     storage
         .mut_gc_worker()
         .set_raft_store_router(raft_router.clone());
+    storage
+        .mut_gc_worker()
+        .set_use_delete_range(cfg.raft_store.use_delete_range);
 
     // Create raft engine.
     let raft_db_opts = cfg.raftdb.build_opt();
-    let raft_db_cf_opts = cfg.raftdb.build_cf_opts();
+    let raft_db_cf_opts = cfg.raftdb.build_cf_opts(&block_cache);
     let raft_engine = Arc::new(
         rocksdb_util::new_engine_opt(
             raft_db_path.to_str().unwrap(),
@@ -176,12 +216,25 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     let snap_mgr = SnapManagerBuilder::default()
         .max_write_bytes_per_sec(cfg.server.snap_max_write_bytes_per_sec.0)
         .max_total_size(cfg.server.snap_max_total_size.0)
+        .encryption_key_manager(key_manager.clone())
         .build(
             snap_path.as_path().to_str().unwrap().to_owned(),
             Some(store_sendch),
         );
 
-    let importer = Arc::new(SSTImporter::new(import_path).unwrap());
+    let mut importer = SSTImporter::new(import_path)
+        .unwrap()
+        .with_speed_limit(
+            cfg.import.upload_speed_limit.0,
+            cfg.import.ingest_speed_limit.0,
+        )
+        .with_max_download_size(cfg.import.max_download_size.0);
+    if let Some(ref key_manager) = key_manager {
+        importer = importer
+            .with_key_manager(Arc::clone(key_manager), &cfg.import.memory_dir)
+            .unwrap_or_else(|e| fatal!("failed to create the sst importer: {:?}", e));
+    }
+    let importer = Arc::new(importer);
     let import_service = ImportSSTService::new(
         cfg.import.clone(),
         raft_router.clone(),
@@ -210,10 +263,28 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     let trans = server.transport();
 
     // Create node.
-    let mut node = Node::new(&mut event_loop, &server_cfg, &cfg.raft_store, pd_client);
+    let mut node = Node::new(
+        &mut event_loop,
+        &server_cfg,
+        &cfg.raft_store,
+        Arc::clone(&pd_client),
+    );
 
     // Create CoprocessorHost.
-    let coprocessor_host = CoprocessorHost::new(cfg.coprocessor.clone(), node.get_sendch());
+    let mut coprocessor_host = CoprocessorHost::new(cfg.coprocessor.clone(), node.get_sendch());
+    let mut log_backup_worker = Worker::new("log-backup");
+    if cfg.log_backup.enable {
+        let observer = LogBackupObserver::new(log_backup_worker.scheduler());
+        coprocessor_host
+            .registry
+            .register_admin_observer(100, Box::new(observer.clone()));
+        coprocessor_host
+            .registry
+            .register_query_observer(100, Box::new(observer.clone()));
+        coprocessor_host
+            .registry
+            .register_role_observer(100, Box::new(observer));
+    }
 
     node.start(
         event_loop,
@@ -228,26 +299,89 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
     ).unwrap_or_else(|e| fatal!("failed to start node: {:?}", e));
     initial_metric(&cfg.metric, Some(node.id()));
 
+    // Start log backup, the changes observed before are queued in the worker.
+    if cfg.log_backup.enable {
+        let runner = LogBackupRunner::new(node.id(), Arc::clone(&kv_engine), &cfg.log_backup)
+            .unwrap_or_else(|e| fatal!("failed to create log backup: {:?}", e));
+        let timer = runner.new_timer();
+        log_backup_worker
+            .start_with_timer(runner, timer)
+            .unwrap_or_else(|e| fatal!("failed to start log backup: {:?}", e));
+    }
+
     // Start storage.
     info!("start storage");
     if let Err(e) = storage.start(&cfg.storage) {
         fatal!("failed to start storage, error: {:?}", e);
     }
 
+    // Watch the safe point, the cluster config and the cluster version from PD.
+    let cluster_watcher = ClusterWatcher::with_feature_gate(pd_client.feature_gate());
+    let cluster_watcher_handle = cluster_watcher
+        .start(
+            Arc::clone(&pd_client),
+            cfg.storage.gc_poll_safe_point_interval.0,
+        )
+        .unwrap_or_else(|e| fatal!("failed to start cluster watcher: {:?}", e));
+
+    // Start the GC manager, which drives GC by the safe point from PD.
+    let gc_manager_cfg = GCManagerConfig {
+        poll_safe_point_interval: cfg.storage.gc_poll_safe_point_interval.0,
+    };
+    let gc_manager = GCManager::new(
+        gc_manager_cfg,
+        cluster_watcher.clone(),
+        storage.get_engine(),
+        storage.mut_gc_worker().clone(),
+    ).start()
+        .unwrap_or_else(|e| fatal!("failed to start gc manager: {:?}", e));
+
+    memory::set_high_water_mark(cfg.memory_usage_high_water.0 as usize);
+    if cfg.storage.max_io_bytes_per_sec.0 > 0 {
+        let limiter = IORateLimiter::new(cfg.storage.max_io_bytes_per_sec.0);
+        io_limiter::set_io_rate_limiter(Some(Arc::new(limiter)));
+    }
+    if !cfg.server.source_quotas.is_empty() {
+        let limiter = QuotaLimiter::new(&cfg.server.source_quotas);
+        quota_limiter::set_quota_limiter(Some(Arc::new(limiter)));
+    }
     let mut metrics_flusher = MetricsFlusher::new(
         engines.clone(),
         Duration::from_millis(DEFAULT_FLUSHER_INTERVAL),
     );
+    metrics_flusher.set_shared_block_cache(cfg.storage.block_cache.shared);
+    let rate_limiter_tuner = cfg.rocksdb.build_rate_limiter_tuner().map(|tuner| {
+        TunerHandle::start(
+            Arc::clone(&kv_engine),
+            DbSampler::new(Arc::clone(&kv_engine)),
+            tuner,
+            Duration::from_secs(DEFAULT_TUNE_INTERVAL_SECS),
+        )
+    });
 
     // Start metrics flusher
     if let Err(e) = metrics_flusher.start() {
         error!("failed to start metrics flusher, error: {:?}", e);
     }
 
+    // Start the status server before the server, so that operators can check the progress.
+    let mut status_server = StatusServer::new(cfg);
+    let config_controller = ConfigController::new(cfg.clone(), engines.clone());
+    status_server.set_config_controller(Arc::new(config_controller));
+    if let Some(key_manager) = key_manager {
+        status_server.set_data_key_manager(key_manager);
+    }
+    if !cfg.server.status_addr.is_empty() {
+        if let Err(e) = status_server.start(&cfg.server.status_addr) {
+            error!("failed to start status server, error: {:?}", e);
+        }
+    }
+
     // Run server.
     server
         .start(server_cfg, security_mgr)
         .unwrap_or_else(|e| fatal!("failed to start server: {:?}", e));
+    status_server.set_ready();
     signal_handler::handle_signal(Some(engines));
 
     // Stop.
@@ -255,10 +389,23 @@ fn run_raft_server(pd_client: RpcClient, cfg: &TiKvConfig, security_mgr: Arc<Sec
         .stop()
         .unwrap_or_else(|e| fatal!("failed to stop server: {:?}", e));
 
+    gc_manager
+        .stop()
+        .unwrap_or_else(|e| fatal!("failed to stop gc manager: {:?}", e));
+    cluster_watcher_handle.stop();
+
     metrics_flusher.stop();
+    drop(rate_limiter_tuner);
+    drop(key_rotator);
+
+    status_server.stop();
 
     node.stop()
         .unwrap_or_else(|e| fatal!("failed to stop node: {:?}", e));
+    // Flush the changes left after the node is stopped.
+    if let Some(Err(e)) = log_backup_worker.stop().map(|j| j.join()) {
+        info!("ignore failure when stopping log backup: {:?}", e);
+    }
     if let Some(Err(e)) = worker.stop().map(|j| j.join()) {
         info!("ignore failure when stopping resolver: {:?}", e);
     }
@@ -292,6 +439,13 @@ fn main() {
                 .value_name("IP:PORT")
                 .help("Sets advertise listening address for client communication"),
         )
+        .arg(
+            Arg::with_name("status-addr")
+                .long("status-addr")
+                .takes_value(true)
+                .value_name("IP:PORT")
+                .help("Sets HTTP listening address for the status server"),
+        )
         .arg(
             Arg::with_name("log-level")
                 .short("L")
@@ -13,12 +13,13 @@
 
 use std::env;
 use std::io::BufWriter;
+use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 
 use chrono;
 use clap::ArgMatches;
-use slog::{Drain, Logger};
+use slog::{Drain, Fuse, Logger, Never};
 use slog_async::{Async, OverflowStrategy};
 use slog_scope::GlobalLoggerGuard;
 use slog_term::{PlainDecorator, TermDecorator};
@@ -53,6 +54,7 @@ pub fn init_log(config: &TiKvConfig) -> GlobalLoggerGuard {
     let log_rotation_timespan = chrono::Duration::from_std(
         config.log_rotation_timespan.clone().into(),
     ).expect("config.log_rotation_timespan is an invalid duration.");
+    logger::set_slow_log_threshold(config.slow_log_threshold.0);
     let guard = if config.log_file.is_empty() {
         let decorator = TermDecorator::new().build();
         let drain = logger::TikvFormat::new(decorator).fuse();
@@ -62,35 +64,52 @@ pub fn init_log(config: &TiKvConfig) -> GlobalLoggerGuard {
             .thread_name(thd_name!("term-slogger"))
             .build()
             .fuse();
-        let logger = Logger::root_typed(drain, slog_o!());
-        logger::init_log(logger, config.log_level).unwrap_or_else(|e| {
-            fatal!("failed to initialize log: {:?}", e);
-        })
+        init_log_with_slow_log(drain, config, log_rotation_timespan)
     } else {
-        let logger = BufWriter::new(
-            RotatingFileLogger::new(&config.log_file, log_rotation_timespan).unwrap_or_else(|e| {
-                fatal!(
-                    "failed to initialize log with file {:?}: {:?}",
-                    config.log_file,
-                    e
-                );
-            }),
-        );
-        let decorator = PlainDecorator::new(logger);
-        let drain = logger::TikvFormat::new(decorator).fuse();
-        let drain = Async::new(drain)
-            .thread_name(thd_name!("file-slogger"))
-            .build()
-            .fuse();
-        let logger = Logger::root_typed(drain, slog_o!());
-        logger::init_log(logger, config.log_level).unwrap_or_else(|e| {
-            fatal!("failed to initialize log: {:?}", e);
-        })
+        let drain = file_drain(&config.log_file, log_rotation_timespan, "file-slogger");
+        init_log_with_slow_log(drain, config, log_rotation_timespan)
     };
     LOG_INITIALIZED.store(true, Ordering::SeqCst);
     guard
 }
 
+fn file_drain(path: &str, rotation_timespan: chrono::Duration, thread_name: &str) -> Fuse<Async> {
+    let logger = BufWriter::new(
+        RotatingFileLogger::new(path, rotation_timespan).unwrap_or_else(|e| {
+            fatal!("failed to initialize log with file {:?}: {:?}", path, e);
+        }),
+    );
+    let decorator = PlainDecorator::new(logger);
+    let drain = logger::TikvFormat::new(decorator).fuse();
+    Async::new(drain)
+        .thread_name(thd_name!(thread_name))
+        .build()
+        .fuse()
+}
+
+// Writes the slow logs to `config.slow_log_file` if it's set.
+fn init_log_with_slow_log<D>(
+    drain: D,
+    config: &TiKvConfig,
+    rotation_timespan: chrono::Duration,
+) -> GlobalLoggerGuard
+where
+    D: Drain<Ok = (), Err = Never> + Send + Sync + RefUnwindSafe + UnwindSafe + 'static,
+{
+    let res = if config.slow_log_file.is_empty() {
+        let logger = Logger::root_typed(drain, slog_o!());
+        logger::init_log(logger, config.log_level)
+    } else {
+        let slow_drain = file_drain(&config.slow_log_file, rotation_timespan, "slow-slogger");
+        let drain = logger::LogDispatcher::new(drain, slow_drain);
+        let logger = Logger::root_typed(drain, slog_o!());
+        logger::init_log(logger, config.log_level)
+    };
+    res.unwrap_or_else(|e| {
+        fatal!("failed to initialize log: {:?}", e);
+    })
+}
+
 pub fn initial_metric(cfg: &MetricConfig, node_id: Option<u64>) {
     if cfg.interval.as_secs() == 0 || cfg.address.is_empty() {
         return;
@@ -126,6 +145,10 @@ pub fn overwrite_config_with_cmd_args(config: &mut TiKvConfig, matches: &ArgMatc
         config.server.advertise_addr = advertise_addr.to_owned();
     }
 
+    if let Some(status_addr) = matches.value_of("status-addr") {
+        config.server.status_addr = status_addr.to_owned();
+    }
+
     if let Some(data_dir) = matches.value_of("data-dir") {
         config.storage.data_dir = data_dir.to_owned();
     }
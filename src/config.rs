@@ -20,35 +20,43 @@ use std::i32;
 use std::io::Error as IoError;
 use std::io::{Read, Write};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
 use std::usize;
 
 use rocksdb::{
-    BlockBasedOptions, ColumnFamilyOptions, CompactionPriority, DBCompactionStyle,
-    DBCompressionType, DBOptions, DBRecoveryMode,
+    BlockBasedOptions, Cache, ColumnFamilyOptions, CompactionPriority, DBCompactionStyle,
+    DBCompressionType, DBOptions, DBRecoveryMode, LRUCacheOptions, DB,
 };
 use slog;
 use sys_info;
 
+use backup::Config as BackupConfig;
 use import::Config as ImportConfig;
+use log_backup::Config as LogBackupConfig;
 use pd::Config as PdConfig;
 use raftstore::coprocessor::Config as CopConfig;
 use raftstore::store::keys::region_raft_prefix_len;
 use raftstore::store::Config as RaftstoreConfig;
+use raftstore::store::Engines;
 use server::readpool;
 use server::Config as ServerConfig;
 use storage::{
-    Config as StorageConfig, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE, DEFAULT_ROCKSDB_SUB_DIR,
+    CfName, Config as StorageConfig, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE,
+    DEFAULT_ROCKSDB_SUB_DIR,
 };
 use util::config::{
     self, compression_type_level_serde, ReadableDuration, ReadableSize, GB, KB, MB,
 };
 use util::properties::{MvccPropertiesCollectorFactory, RangePropertiesCollectorFactory};
+use util::rocksdb::rate_limiter_tuner::RateLimiterTuner;
 use util::rocksdb::{
-    db_exist, CFOptions, EventListener, FixedPrefixSliceTransform, FixedSuffixSliceTransform,
-    NoopSliceTransform,
+    db_exist, get_cf_handle, CFOptions, EventListener, FixedPrefixSliceTransform,
+    FixedSuffixSliceTransform, NoopSliceTransform,
 };
 use util::security::SecurityConfig;
 use util::time::duration_to_sec;
+use util::trace::Config as TracingConfig;
 
 const LOCKCF_MIN_MEM: usize = 256 * MB as usize;
 const LOCKCF_MAX_MEM: usize = GB as usize;
@@ -83,6 +91,7 @@ macro_rules! cf_config {
             pub block_size: ReadableSize,
             pub block_cache_size: ReadableSize,
             pub disable_block_cache: bool,
+            pub block_cache_high_pri_ratio: f64,
             pub cache_index_and_filter_blocks: bool,
             pub pin_l0_filter_and_index_blocks: bool,
             pub use_bloom_filter: bool,
@@ -115,12 +124,36 @@ macro_rules! cf_config {
     };
 }
 
+macro_rules! validate_cf_config {
+    ($opt:expr, $display_name:expr) => {{
+        if $opt.block_cache_high_pri_ratio < 0.0 || $opt.block_cache_high_pri_ratio > 1.0 {
+            return Err(format!(
+                "{}.block-cache-high-pri-ratio should be between 0 and 1",
+                $display_name
+            ).into());
+        }
+    }};
+}
+
 macro_rules! build_cf_opt {
-    ($opt:ident) => {{
+    ($opt:ident, $cache:ident) => {{
         let mut block_base_opts = BlockBasedOptions::new();
         block_base_opts.set_block_size($opt.block_size.0 as usize);
         block_base_opts.set_no_block_cache($opt.disable_block_cache);
-        block_base_opts.set_lru_cache($opt.block_cache_size.0 as usize, -1, 0, 0.0);
+        match *$cache {
+            Some(ref cache) => block_base_opts.set_block_cache(cache),
+            None => block_base_opts.set_lru_cache(
+                $opt.block_cache_size.0 as usize,
+                -1,
+                0,
+                $opt.block_cache_high_pri_ratio,
+            ),
+        }
+        // Index and filter blocks of the column families which reserve a part of the
+        // high-priority pool are less likely to be evicted by the data blocks.
+        block_base_opts.set_cache_index_and_filter_blocks_with_high_priority(
+            $opt.block_cache_high_pri_ratio > 0.0,
+        );
         block_base_opts.set_cache_index_and_filter_blocks($opt.cache_index_and_filter_blocks);
         block_base_opts
             .set_pin_l0_filter_and_index_blocks_in_cache($opt.pin_l0_filter_and_index_blocks);
@@ -167,6 +200,7 @@ impl Default for DefaultCfConfig {
             block_size: ReadableSize::kb(64),
             block_cache_size: ReadableSize::mb(memory_mb_for_cf(false, CF_DEFAULT) as u64),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.0,
             cache_index_and_filter_blocks: true,
             pin_l0_filter_and_index_blocks: true,
             use_bloom_filter: true,
@@ -205,8 +239,8 @@ impl Default for DefaultCfConfig {
 }
 
 impl DefaultCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &Option<Cache>) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache);
         let f = Box::new(RangePropertiesCollectorFactory::default());
         cf_opts.add_table_properties_collector_factory("tikv.range-properties-collector", f);
         cf_opts
@@ -221,6 +255,7 @@ impl Default for WriteCfConfig {
             block_size: ReadableSize::kb(64),
             block_cache_size: ReadableSize::mb(memory_mb_for_cf(false, CF_WRITE) as u64),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.0,
             cache_index_and_filter_blocks: true,
             pin_l0_filter_and_index_blocks: true,
             use_bloom_filter: true,
@@ -259,8 +294,8 @@ impl Default for WriteCfConfig {
 }
 
 impl WriteCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &Option<Cache>) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache);
         // Prefix extractor(trim the timestamp at tail) for write cf.
         let e = Box::new(FixedSuffixSliceTransform::new(8));
         cf_opts
@@ -285,6 +320,7 @@ impl Default for LockCfConfig {
             block_size: ReadableSize::kb(16),
             block_cache_size: ReadableSize::mb(memory_mb_for_cf(false, CF_LOCK) as u64),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.0,
             cache_index_and_filter_blocks: true,
             pin_l0_filter_and_index_blocks: true,
             use_bloom_filter: true,
@@ -315,8 +351,8 @@ impl Default for LockCfConfig {
 }
 
 impl LockCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &Option<Cache>) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache);
         let f = Box::new(NoopSliceTransform);
         cf_opts
             .set_prefix_extractor("NoopSliceTransform", f)
@@ -334,6 +370,7 @@ impl Default for RaftCfConfig {
             block_size: ReadableSize::kb(16),
             block_cache_size: ReadableSize::mb(128),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.0,
             cache_index_and_filter_blocks: true,
             pin_l0_filter_and_index_blocks: true,
             use_bloom_filter: true,
@@ -364,8 +401,8 @@ impl Default for RaftCfConfig {
 }
 
 impl RaftCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &Option<Cache>) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache);
         let f = Box::new(NoopSliceTransform);
         cf_opts
             .set_prefix_extractor("NoopSliceTransform", f)
@@ -397,12 +434,15 @@ pub struct DbConfig {
     pub info_log_keep_log_file_num: u64,
     pub info_log_dir: String,
     pub rate_bytes_per_sec: ReadableSize,
+    pub rate_limiter_auto_tuned: bool,
+    pub rate_limiter_min_bytes_per_sec: ReadableSize,
     pub bytes_per_sync: ReadableSize,
     pub wal_bytes_per_sync: ReadableSize,
     pub max_sub_compactions: u32,
     pub writable_file_max_buffer_size: ReadableSize,
     pub use_direct_io_for_flush_and_compaction: bool,
     pub enable_pipelined_write: bool,
+    pub enable_multi_batch_write: bool,
     pub defaultcf: DefaultCfConfig,
     pub writecf: WriteCfConfig,
     pub lockcf: LockCfConfig,
@@ -429,12 +469,15 @@ impl Default for DbConfig {
             info_log_keep_log_file_num: 10,
             info_log_dir: "".to_owned(),
             rate_bytes_per_sec: ReadableSize::kb(0),
+            rate_limiter_auto_tuned: false,
+            rate_limiter_min_bytes_per_sec: ReadableSize::mb(10),
             bytes_per_sync: ReadableSize::mb(1),
             wal_bytes_per_sync: ReadableSize::kb(512),
             max_sub_compactions: 1,
             writable_file_max_buffer_size: ReadableSize::mb(1),
             use_direct_io_for_flush_and_compaction: false,
             enable_pipelined_write: true,
+            enable_multi_batch_write: false,
             defaultcf: DefaultCfConfig::default(),
             writecf: WriteCfConfig::default(),
             lockcf: LockCfConfig::default(),
@@ -483,22 +526,83 @@ impl DbConfig {
             self.use_direct_io_for_flush_and_compaction,
         );
         opts.enable_pipelined_write(self.enable_pipelined_write);
+        opts.enable_multi_batch_write(self.enable_multi_batch_write);
         opts.add_event_listener(EventListener::new("kv"));
         opts
     }
 
-    pub fn build_cf_opts(&self) -> Vec<CFOptions> {
+    pub fn build_cf_opts(&self, cache: &Option<Cache>) -> Vec<CFOptions> {
         vec![
-            CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt()),
-            CFOptions::new(CF_LOCK, self.lockcf.build_opt()),
-            CFOptions::new(CF_WRITE, self.writecf.build_opt()),
-            CFOptions::new(CF_RAFT, self.raftcf.build_opt()),
+            CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt(cache)),
+            CFOptions::new(CF_LOCK, self.lockcf.build_opt(cache)),
+            CFOptions::new(CF_WRITE, self.writecf.build_opt(cache)),
+            CFOptions::new(CF_RAFT, self.raftcf.build_opt(cache)),
         ]
     }
 
+    /// Returns the tuner of the rate limiter if it's auto tuned. The limit is raised once the
+    /// pending compaction bytes reach a quarter of the soft limit, so that compactions catch
+    /// up before the writes are slowed down.
+    pub fn build_rate_limiter_tuner(&self) -> Option<RateLimiterTuner> {
+        if !self.rate_limiter_auto_tuned {
+            return None;
+        }
+        let soft_limit = [
+            self.defaultcf.soft_pending_compaction_bytes_limit,
+            self.writecf.soft_pending_compaction_bytes_limit,
+            self.lockcf.soft_pending_compaction_bytes_limit,
+            self.raftcf.soft_pending_compaction_bytes_limit,
+        ].iter()
+            .map(|l| l.0)
+            .min()
+            .unwrap();
+        let high_water = soft_limit / 4;
+        Some(RateLimiterTuner::new(
+            self.rate_limiter_min_bytes_per_sec.0,
+            self.rate_bytes_per_sec.0,
+            high_water,
+            high_water / 8,
+        ))
+    }
+
     fn validate(&mut self) -> Result<(), Box<Error>> {
+        if self.enable_multi_batch_write && self.enable_pipelined_write {
+            return Err("rocksdb.enable-multi-batch-write can't work with \
+                        rocksdb.enable-pipelined-write"
+                .into());
+        }
+        if self.rate_limiter_auto_tuned {
+            if self.rate_bytes_per_sec.0 == 0 {
+                return Err(
+                    "rocksdb.rate-bytes-per-sec should be > 0 when the rate limiter is auto tuned"
+                        .into(),
+                );
+            }
+            if self.rate_limiter_min_bytes_per_sec.0 == 0
+                || self.rate_limiter_min_bytes_per_sec.0 > self.rate_bytes_per_sec.0
+            {
+                return Err("rocksdb.rate-limiter-min-bytes-per-sec should be in \
+                            (0, rocksdb.rate-bytes-per-sec]"
+                    .into());
+            }
+        }
+        validate_cf_config!(self.defaultcf, "rocksdb.defaultcf");
+        validate_cf_config!(self.writecf, "rocksdb.writecf");
+        validate_cf_config!(self.lockcf, "rocksdb.lockcf");
+        validate_cf_config!(self.raftcf, "rocksdb.raftcf");
         Ok(())
     }
+
+    fn block_cache_budgets(&self) -> Vec<(usize, f64)> {
+        [
+            (self.defaultcf.block_cache_size, self.defaultcf.block_cache_high_pri_ratio),
+            (self.writecf.block_cache_size, self.writecf.block_cache_high_pri_ratio),
+            (self.lockcf.block_cache_size, self.lockcf.block_cache_high_pri_ratio),
+            (self.raftcf.block_cache_size, self.raftcf.block_cache_high_pri_ratio),
+        ].iter()
+            .map(|&(size, ratio)| (size.0 as usize, ratio))
+            .collect()
+    }
 }
 
 cf_config!(RaftDefaultCfConfig);
@@ -509,6 +613,7 @@ impl Default for RaftDefaultCfConfig {
             block_size: ReadableSize::kb(64),
             block_cache_size: ReadableSize::mb(memory_mb_for_cf(true, CF_DEFAULT) as u64),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.0,
             cache_index_and_filter_blocks: true,
             pin_l0_filter_and_index_blocks: true,
             use_bloom_filter: false,
@@ -547,8 +652,8 @@ impl Default for RaftDefaultCfConfig {
 }
 
 impl RaftDefaultCfConfig {
-    pub fn build_opt(&self) -> ColumnFamilyOptions {
-        let mut cf_opts = build_cf_opt!(self);
+    pub fn build_opt(&self, cache: &Option<Cache>) -> ColumnFamilyOptions {
+        let mut cf_opts = build_cf_opt!(self, cache);
         let f = Box::new(FixedPrefixSliceTransform::new(region_raft_prefix_len()));
         cf_opts
             .set_memtable_insert_hint_prefix_extractor("RaftPrefixSliceTransform", f)
@@ -663,8 +768,20 @@ impl RaftDbConfig {
         opts
     }
 
-    pub fn build_cf_opts(&self) -> Vec<CFOptions> {
-        vec![CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt())]
+    pub fn build_cf_opts(&self, cache: &Option<Cache>) -> Vec<CFOptions> {
+        vec![CFOptions::new(CF_DEFAULT, self.defaultcf.build_opt(cache))]
+    }
+
+    fn validate(&mut self) -> Result<(), Box<Error>> {
+        validate_cf_config!(self.defaultcf, "raftdb.defaultcf");
+        Ok(())
+    }
+
+    fn block_cache_budgets(&self) -> Vec<(usize, f64)> {
+        vec![(
+            self.defaultcf.block_cache_size.0 as usize,
+            self.defaultcf.block_cache_high_pri_ratio,
+        )]
     }
 }
 
@@ -726,6 +843,8 @@ macro_rules! readpool_config {
             pub max_tasks_per_worker_normal: usize,
             pub max_tasks_per_worker_low: usize,
             pub stack_size: ReadableSize,
+            pub min_concurrency: usize,
+            pub max_queue_latency: ReadableDuration,
         }
 
         impl $struct_name {
@@ -738,6 +857,8 @@ macro_rules! readpool_config {
                     max_tasks_per_worker_normal: self.max_tasks_per_worker_normal,
                     max_tasks_per_worker_low: self.max_tasks_per_worker_low,
                     stack_size: self.stack_size,
+                    min_concurrency: self.min_concurrency,
+                    max_queue_latency: self.max_queue_latency,
                 }
             }
 
@@ -782,6 +903,12 @@ macro_rules! readpool_config {
                         $display_name
                     ).into());
                 }
+                if self.min_concurrency > 0 && self.max_queue_latency.as_millis() == 0 {
+                    return Err(format!(
+                        "readpool.{}.max-queue-latency should be > 0 when min-concurrency is set",
+                        $display_name
+                    ).into());
+                }
 
                 Ok(())
             }
@@ -835,6 +962,13 @@ macro_rules! readpool_config {
                 assert!(invalid_cfg.validate().is_err());
                 invalid_cfg.max_tasks_per_worker_low = 100;
                 assert!(cfg.validate().is_ok());
+
+                let mut invalid_cfg = cfg.clone();
+                invalid_cfg.min_concurrency = 1;
+                invalid_cfg.max_queue_latency = ReadableDuration::millis(0);
+                assert!(invalid_cfg.validate().is_err());
+                invalid_cfg.min_concurrency = 0;
+                assert!(invalid_cfg.validate().is_ok());
             }
         }
     };
@@ -854,6 +988,10 @@ impl Default for StorageReadPoolConfig {
             max_tasks_per_worker_normal: readpool::config::DEFAULT_MAX_TASKS_PER_WORKER,
             max_tasks_per_worker_low: readpool::config::DEFAULT_MAX_TASKS_PER_WORKER,
             stack_size: ReadableSize::mb(readpool::config::DEFAULT_STACK_SIZE_MB),
+            min_concurrency: 0,
+            max_queue_latency: ReadableDuration::millis(
+                readpool::config::DEFAULT_MAX_QUEUE_LATENCY_MS,
+            ),
         }
     }
 }
@@ -882,6 +1020,10 @@ impl Default for CoprocessorReadPoolConfig {
             max_tasks_per_worker_normal: readpool::config::DEFAULT_MAX_TASKS_PER_WORKER,
             max_tasks_per_worker_low: readpool::config::DEFAULT_MAX_TASKS_PER_WORKER,
             stack_size: ReadableSize::mb(readpool::config::DEFAULT_STACK_SIZE_MB),
+            min_concurrency: 0,
+            max_queue_latency: ReadableDuration::millis(
+                readpool::config::DEFAULT_MAX_QUEUE_LATENCY_MS,
+            ),
         }
     }
 }
@@ -910,6 +1052,9 @@ pub struct TiKvConfig {
     pub log_level: slog::Level,
     pub log_file: String,
     pub log_rotation_timespan: ReadableDuration,
+    pub slow_log_file: String,
+    pub slow_log_threshold: ReadableDuration,
+    pub memory_usage_high_water: ReadableSize,
     pub readpool: ReadPoolConfig,
     pub server: ServerConfig,
     pub storage: StorageConfig,
@@ -922,6 +1067,9 @@ pub struct TiKvConfig {
     pub raftdb: RaftDbConfig,
     pub security: SecurityConfig,
     pub import: ImportConfig,
+    pub backup: BackupConfig,
+    pub log_backup: LogBackupConfig,
+    pub tracing: TracingConfig,
 }
 
 impl Default for TiKvConfig {
@@ -930,6 +1078,9 @@ impl Default for TiKvConfig {
             log_level: slog::Level::Info,
             log_file: "".to_owned(),
             log_rotation_timespan: ReadableDuration::hours(24),
+            slow_log_file: "".to_owned(),
+            slow_log_threshold: ReadableDuration::secs(1),
+            memory_usage_high_water: ReadableSize(0),
             readpool: ReadPoolConfig::default(),
             server: ServerConfig::default(),
             metric: MetricConfig::default(),
@@ -941,6 +1092,9 @@ impl Default for TiKvConfig {
             storage: StorageConfig::default(),
             security: SecurityConfig::default(),
             import: ImportConfig::default(),
+            backup: BackupConfig::default(),
+            log_backup: LogBackupConfig::default(),
+            tracing: TracingConfig::default(),
         }
     }
 }
@@ -951,6 +1105,7 @@ impl TiKvConfig {
         self.storage.validate()?;
 
         self.raft_store.region_split_check_diff = self.coprocessor.region_split_size / 16;
+        self.raft_store.apply_multi_batch_write = self.rocksdb.enable_multi_batch_write;
         self.raft_store.raftdb_path = if self.raft_store.raftdb_path.is_empty() {
             config::canonicalize_sub_path(&self.storage.data_dir, "raft")?
         } else {
@@ -980,15 +1135,68 @@ impl TiKvConfig {
         }
 
         self.rocksdb.validate()?;
+        self.raftdb.validate()?;
+        if self.storage.block_cache.shared {
+            let (capacity, high_pri) = self.shared_block_cache_budget();
+            if high_pri > capacity {
+                return Err(format!(
+                    "the high-priority pools of the column families need {} bytes, \
+                     more than storage.block-cache.capacity {}",
+                    high_pri, capacity
+                ).into());
+            }
+        }
         self.server.validate()?;
         self.raft_store.validate()?;
         self.pd.validate()?;
         self.coprocessor.validate()?;
         self.security.validate()?;
+        if self.server.status_verify_client_cert && self.security.ca_path.is_empty() {
+            return Err("server.status-verify-client-cert needs the CA in security".into());
+        }
         self.import.validate()?;
+        self.backup.validate()?;
+        self.log_backup.validate()?;
+        self.tracing.validate()?;
         Ok(())
     }
 
+    /// Returns the capacity of the shared block cache and the bytes reserved for the
+    /// high-priority pool by all the column families of the kv and raft engines.
+    fn shared_block_cache_budget(&self) -> (usize, usize) {
+        let budgets: Vec<_> = self
+            .rocksdb
+            .block_cache_budgets()
+            .into_iter()
+            .chain(self.raftdb.block_cache_budgets())
+            .collect();
+        let capacity = match self.storage.block_cache.capacity {
+            Some(capacity) => capacity.0 as usize,
+            None => budgets.iter().map(|&(size, _)| size).sum(),
+        };
+        let high_pri = budgets
+            .iter()
+            .map(|&(size, ratio)| (size as f64 * ratio) as usize)
+            .sum();
+        (capacity, high_pri)
+    }
+
+    /// Builds the block cache shared by the kv and raft engines, returns `None` if every
+    /// column family should use its own cache.
+    pub fn build_shared_block_cache(&self) -> Option<Cache> {
+        let cfg = &self.storage.block_cache;
+        if !cfg.shared {
+            return None;
+        }
+        let (capacity, high_pri) = self.shared_block_cache_budget();
+        let mut cache_opts = LRUCacheOptions::new();
+        cache_opts.set_capacity(capacity);
+        cache_opts.set_num_shard_bits(cfg.num_shard_bits);
+        cache_opts.set_strict_capacity_limit(cfg.strict_capacity_limit);
+        cache_opts.set_high_pri_pool_ratio(high_pri as f64 / capacity as f64);
+        Some(Cache::new_lru_cache(cache_opts))
+    }
+
     pub fn compatible_adjust(&mut self) {
         let default_raft_store = RaftstoreConfig::default();
         let default_coprocessor = CopConfig::default();
@@ -1134,6 +1342,203 @@ impl TiKvConfig {
     }
 }
 
+fn parse_size(name: &str, value: &str) -> Result<ReadableSize, Box<Error>> {
+    ReadableSize::from_str(value).map_err(|e| format!("invalid {}: {}", name, e).into())
+}
+
+fn parse_num<T: FromStr>(name: &str, value: &str) -> Result<T, Box<Error>> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {}: {:?}", name, value).into())
+}
+
+// Updates an option of a column family config, and returns the name and value of the option
+// in RocksDB.
+macro_rules! update_cf_config {
+    ($cfg:expr, $name:expr, $value:expr) => {{
+        match $name {
+            "write-buffer-size" => {
+                $cfg.write_buffer_size = parse_size($name, $value)?;
+                ("write_buffer_size", $cfg.write_buffer_size.0.to_string())
+            }
+            "max-write-buffer-number" => {
+                $cfg.max_write_buffer_number = parse_num($name, $value)?;
+                (
+                    "max_write_buffer_number",
+                    $cfg.max_write_buffer_number.to_string(),
+                )
+            }
+            "level0-file-num-compaction-trigger" => {
+                $cfg.level0_file_num_compaction_trigger = parse_num($name, $value)?;
+                (
+                    "level0_file_num_compaction_trigger",
+                    $cfg.level0_file_num_compaction_trigger.to_string(),
+                )
+            }
+            "level0-slowdown-writes-trigger" => {
+                $cfg.level0_slowdown_writes_trigger = parse_num($name, $value)?;
+                (
+                    "level0_slowdown_writes_trigger",
+                    $cfg.level0_slowdown_writes_trigger.to_string(),
+                )
+            }
+            "level0-stop-writes-trigger" => {
+                $cfg.level0_stop_writes_trigger = parse_num($name, $value)?;
+                (
+                    "level0_stop_writes_trigger",
+                    $cfg.level0_stop_writes_trigger.to_string(),
+                )
+            }
+            _ => return Err(format!("{} can't be changed online", $name).into()),
+        }
+    }};
+}
+
+/// A validated change of the options of an engine.
+enum EngineChange<'a> {
+    KvDbOption(&'static str, String),
+    KvRateBytesPerSec(i64),
+    CfOption(&'a DB, CfName, &'static str, String),
+}
+
+/// `ConfigController` changes the configurations of the kv and raft engines at runtime, by
+/// the dynamic option setters of RocksDB, and keeps the effective configuration up to date.
+///
+/// The names are the same as in the configuration file, e.g. `rocksdb.max-background-jobs`
+/// and `rocksdb.defaultcf.write-buffer-size`.
+pub struct ConfigController {
+    current: Mutex<TiKvConfig>,
+    engines: Engines,
+}
+
+impl ConfigController {
+    pub fn new(cfg: TiKvConfig, engines: Engines) -> ConfigController {
+        ConfigController {
+            current: Mutex::new(cfg),
+            engines,
+        }
+    }
+
+    pub fn get_current(&self) -> TiKvConfig {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn update(&self, name: &str, value: &str) -> Result<(), Box<Error>> {
+        self.update_all(&[(name, value)])
+    }
+
+    /// Changes the configurations of `changes` together. All of them are validated before
+    /// any is applied, so that an invalid one leaves the engines and the configuration
+    /// untouched.
+    pub fn update_all(&self, changes: &[(&str, &str)]) -> Result<(), Box<Error>> {
+        let mut current = self.current.lock().unwrap();
+        // Works on a copy, so that a failed change leaves the configuration untouched.
+        let mut cfg = current.clone();
+        let mut validated = Vec::with_capacity(changes.len());
+        for &(name, value) in changes {
+            match self.validate(&mut cfg, name, value) {
+                Ok(change) => validated.push(change),
+                Err(e) => return Err(format!("failed to change {}: {}", name, e).into()),
+            }
+        }
+        for (i, change) in validated.into_iter().enumerate() {
+            let (name, value) = changes[i];
+            if let Err(e) = self.apply(change) {
+                // RocksDB rejects the value, keeps the configuration consistent with the ones
+                // applied before it.
+                for &(name, value) in &changes[..i] {
+                    self.validate(&mut *current, name, value).unwrap();
+                }
+                return Err(format!("failed to change {}: {}", name, e).into());
+            }
+            info!("configuration {} is changed to {}", name, value);
+        }
+        *current = cfg;
+        Ok(())
+    }
+
+    // Updates the configuration `name` of `cfg`, and returns the change of the engines.
+    fn validate(
+        &self,
+        cfg: &mut TiKvConfig,
+        name: &str,
+        value: &str,
+    ) -> Result<EngineChange, Box<Error>> {
+        let parts: Vec<&str> = name.split('.').collect();
+        let change = match parts.as_slice() {
+            ["rocksdb", "max-background-jobs"] => {
+                cfg.rocksdb.max_background_jobs = parse_num(name, value)?;
+                let jobs = cfg.rocksdb.max_background_jobs.to_string();
+                EngineChange::KvDbOption("max_background_jobs", jobs)
+            }
+            ["rocksdb", "rate-bytes-per-sec"] => {
+                if cfg.rocksdb.rate_bytes_per_sec.0 == 0 {
+                    return Err("the rate limiter is disabled, it can't be changed online".into());
+                }
+                if cfg.rocksdb.rate_limiter_auto_tuned {
+                    return Err("the rate limiter is auto tuned".into());
+                }
+                let rate = parse_size(name, value)?;
+                if rate.0 == 0 {
+                    return Err("the rate limiter can't be disabled online".into());
+                }
+                cfg.rocksdb.rate_bytes_per_sec = rate;
+                EngineChange::KvRateBytesPerSec(rate.0 as i64)
+            }
+            [db, cf, opt] => {
+                let (engine, cf_name, (opt_name, opt_value)) = match (*db, *cf) {
+                    ("rocksdb", "defaultcf") => (
+                        &*self.engines.kv,
+                        CF_DEFAULT,
+                        update_cf_config!(cfg.rocksdb.defaultcf, *opt, value),
+                    ),
+                    ("rocksdb", "writecf") => (
+                        &*self.engines.kv,
+                        CF_WRITE,
+                        update_cf_config!(cfg.rocksdb.writecf, *opt, value),
+                    ),
+                    ("rocksdb", "lockcf") => (
+                        &*self.engines.kv,
+                        CF_LOCK,
+                        update_cf_config!(cfg.rocksdb.lockcf, *opt, value),
+                    ),
+                    ("rocksdb", "raftcf") => (
+                        &*self.engines.kv,
+                        CF_RAFT,
+                        update_cf_config!(cfg.rocksdb.raftcf, *opt, value),
+                    ),
+                    ("raftdb", "defaultcf") => (
+                        &*self.engines.raft,
+                        CF_DEFAULT,
+                        update_cf_config!(cfg.raftdb.defaultcf, *opt, value),
+                    ),
+                    _ => return Err(format!("unknown configuration {}", name).into()),
+                };
+                EngineChange::CfOption(engine, cf_name, opt_name, opt_value)
+            }
+            _ => return Err(format!("{} can't be changed online", name).into()),
+        };
+        Ok(change)
+    }
+
+    fn apply(&self, change: EngineChange) -> Result<(), Box<Error>> {
+        match change {
+            EngineChange::KvDbOption(opt, value) => {
+                self.engines.kv.set_db_options(&[(opt, value.as_str())])?;
+            }
+            EngineChange::KvRateBytesPerSec(rate) => {
+                let mut opts = self.engines.kv.get_db_options();
+                opts.set_rate_bytes_per_sec(rate)?;
+            }
+            EngineChange::CfOption(engine, cf, opt, value) => {
+                let handle = get_cf_handle(engine, cf)?;
+                engine.set_options_cf(handle, &[(opt, value.as_str())])?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub fn check_and_persist_critical_config(config: &TiKvConfig) -> Result<(), String> {
     // Check current critical configurations with last time, if there are some
     // changes, user must guarantee relevant works have been done.
@@ -1169,11 +1574,13 @@ pub fn check_and_persist_critical_config(config: &TiKvConfig) -> Result<(), Stri
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
     use tempdir::TempDir;
 
     use super::*;
     use slog::Level;
     use toml;
+    use util::rocksdb::new_engine_opt;
 
     #[test]
     fn test_check_critical_cfg_with() {
@@ -1252,6 +1659,98 @@ mod test {
         tikv_cfg.validate().unwrap();
     }
 
+    #[test]
+    fn test_status_verify_client_cert_check() {
+        let mut tikv_cfg = TiKvConfig::default();
+        tikv_cfg.pd.endpoints = vec!["".to_owned()];
+        tikv_cfg.server.status_verify_client_cert = true;
+        assert!(tikv_cfg.validate().is_err());
+    }
+
+    #[test]
+    fn test_shared_block_cache_budget() {
+        let mut tikv_cfg = TiKvConfig::default();
+        tikv_cfg.pd.endpoints = vec!["".to_owned()];
+        tikv_cfg.storage.block_cache.shared = true;
+        tikv_cfg.rocksdb.defaultcf.block_cache_size = ReadableSize::mb(512);
+        tikv_cfg.rocksdb.writecf.block_cache_size = ReadableSize::mb(256);
+        tikv_cfg.rocksdb.lockcf.block_cache_size = ReadableSize::mb(128);
+        tikv_cfg.rocksdb.raftcf.block_cache_size = ReadableSize::mb(64);
+        tikv_cfg.raftdb.defaultcf.block_cache_size = ReadableSize::mb(64);
+        tikv_cfg.rocksdb.lockcf.block_cache_high_pri_ratio = 0.5;
+        let (capacity, high_pri) = tikv_cfg.shared_block_cache_budget();
+        assert_eq!(capacity, ReadableSize::gb(1).0 as usize);
+        assert_eq!(high_pri, ReadableSize::mb(64).0 as usize);
+        tikv_cfg.validate().unwrap();
+
+        tikv_cfg.storage.block_cache.capacity = Some(ReadableSize::mb(32));
+        assert!(tikv_cfg.validate().is_err());
+
+        tikv_cfg.storage.block_cache.capacity = None;
+        tikv_cfg.rocksdb.lockcf.block_cache_high_pri_ratio = 1.5;
+        assert!(tikv_cfg.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_controller() {
+        let dir = TempDir::new("test_config_controller").unwrap();
+        let mut cfg = TiKvConfig::default();
+        cfg.rocksdb.rate_bytes_per_sec = ReadableSize::mb(64);
+        let kv_path = dir.path().join("kv");
+        let kv_engine = new_engine_opt(
+            kv_path.to_str().unwrap(),
+            cfg.rocksdb.build_opt(),
+            cfg.rocksdb.build_cf_opts(&None),
+        ).unwrap();
+        let raft_path = dir.path().join("raft");
+        let raft_engine = new_engine_opt(
+            raft_path.to_str().unwrap(),
+            cfg.raftdb.build_opt(),
+            cfg.raftdb.build_cf_opts(&None),
+        ).unwrap();
+        let engines = Engines::new(Arc::new(kv_engine), Arc::new(raft_engine));
+        let controller = ConfigController::new(cfg, engines.clone());
+
+        controller.update("rocksdb.max-background-jobs", "8").unwrap();
+        assert_eq!(engines.kv.get_db_options().get_max_background_jobs(), 8);
+        assert_eq!(controller.get_current().rocksdb.max_background_jobs, 8);
+
+        controller.update("rocksdb.rate-bytes-per-sec", "128MB").unwrap();
+        controller.update("rocksdb.writecf.write-buffer-size", "256MB").unwrap();
+        controller.update("raftdb.defaultcf.level0-slowdown-writes-trigger", "32").unwrap();
+        let current = controller.get_current();
+        assert_eq!(current.rocksdb.rate_bytes_per_sec, ReadableSize::mb(128));
+        assert_eq!(current.rocksdb.writecf.write_buffer_size, ReadableSize::mb(256));
+        assert_eq!(current.raftdb.defaultcf.level0_slowdown_writes_trigger, 32);
+
+        // Failed changes leave the configuration untouched.
+        assert!(controller.update("rocksdb.max-background-jobs", "x").is_err());
+        assert!(controller.update("rocksdb.rate-bytes-per-sec", "0").is_err());
+        assert!(controller.update("rocksdb.defaultcf.block-size", "4KB").is_err());
+        assert!(controller.update("raftdb.lockcf.write-buffer-size", "4MB").is_err());
+        assert!(controller.update("log-level", "debug").is_err());
+        assert_eq!(controller.get_current(), current);
+
+        // None of the changes is applied if any of them is invalid.
+        let changes = [
+            ("rocksdb.max-background-jobs", "4"),
+            ("rocksdb.writecf.write-buffer-size", "x"),
+        ];
+        assert!(controller.update_all(&changes).is_err());
+        assert_eq!(engines.kv.get_db_options().get_max_background_jobs(), 8);
+        assert_eq!(controller.get_current(), current);
+
+        let changes = [
+            ("rocksdb.max-background-jobs", "4"),
+            ("rocksdb.writecf.write-buffer-size", "128MB"),
+        ];
+        controller.update_all(&changes).unwrap();
+        assert_eq!(engines.kv.get_db_options().get_max_background_jobs(), 4);
+        let current = controller.get_current();
+        assert_eq!(current.rocksdb.max_background_jobs, 4);
+        assert_eq!(current.rocksdb.writecf.write_buffer_size, ReadableSize::mb(128));
+    }
+
     #[test]
     fn test_parse_log_level() {
         #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,234 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use crc::crc64::{self, Hasher64};
+use kvproto::coprocessor::{self as coppb, KeyRange};
+use kvproto::kvrpcpb;
+
+use storage::Snapshot;
+use util::lru::LruCache;
+
+use coprocessor::dag::executor::ExecutorMetrics;
+use coprocessor::metrics::*;
+use coprocessor::*;
+
+/// Identifies the result of a unary request. Requests with the same type, body and ranges
+/// on the same region epoch produce the same result as long as the data is unchanged. The
+/// body is given without the start ts, since the data version already tells whether the
+/// data is changed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    region_id: u64,
+    conf_ver: u64,
+    version: u64,
+    digest: u64,
+}
+
+impl CacheKey {
+    pub fn new(ctx: &kvrpcpb::Context, tp: i64, data: &[u8], ranges: &[KeyRange]) -> CacheKey {
+        let mut digest = crc64::Digest::new(crc64::ECMA);
+        let mut buf = Vec::with_capacity(8);
+        buf.write_i64::<BigEndian>(tp).unwrap();
+        digest.write(&buf);
+        digest.write(data);
+        // Lengths are included so that different splits of the same bytes differ.
+        for range in ranges {
+            for key in &[range.get_start(), range.get_end()] {
+                buf.clear();
+                buf.write_u64::<BigEndian>(key.len() as u64).unwrap();
+                digest.write(&buf);
+                digest.write(key);
+            }
+        }
+        let epoch = ctx.get_region_epoch();
+        CacheKey {
+            region_id: ctx.get_region_id(),
+            conf_ver: epoch.get_conf_ver(),
+            version: epoch.get_version(),
+            digest: digest.sum64(),
+        }
+    }
+}
+
+struct CachedResult {
+    data_version: u64,
+    data: Vec<u8>,
+}
+
+/// `ResultCache` is an LRU cache of small coprocessor results, so that identical queries on
+/// regions that haven't been written recently don't need to be computed again.
+///
+/// Every result is tagged with the data version of the snapshot it's computed on, which is
+/// the applied index of the region, and is only valid for snapshots of the same version.
+pub struct ResultCache {
+    results: Mutex<LruCache<CacheKey, CachedResult>>,
+    max_result_size: usize,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize, max_result_size: usize) -> ResultCache {
+        ResultCache {
+            results: Mutex::new(LruCache::with_capacity(capacity)),
+            max_result_size,
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey, data_version: u64) -> Option<Vec<u8>> {
+        let mut results = self.results.lock().unwrap();
+        match results.get(key) {
+            Some(res) if res.data_version == data_version => Some(res.data.clone()),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the cached result is still valid for the data version.
+    pub fn is_valid(&self, key: &CacheKey, data_version: u64) -> bool {
+        let mut results = self.results.lock().unwrap();
+        match results.get(key) {
+            Some(res) => res.data_version == data_version,
+            None => false,
+        }
+    }
+
+    /// Caches the result, returns false if it's too large to be cached.
+    pub fn put(&self, key: CacheKey, data_version: u64, data: Vec<u8>) -> bool {
+        if data.len() > self.max_result_size {
+            return false;
+        }
+        let mut results = self.results.lock().unwrap();
+        results.insert(key, CachedResult { data_version, data });
+        true
+    }
+}
+
+/// Wraps the builder so that the handler uses the cached result of the snapshot version if
+/// there is one, and otherwise caches the result it produces.
+pub fn cached_handler_builder<S: Snapshot + 'static>(
+    builder: RequestHandlerBuilder<S>,
+    cache: Arc<ResultCache>,
+    key: CacheKey,
+) -> RequestHandlerBuilder<S> {
+    box move |snap: S, req_ctx: &_| {
+        let data_version = match snap.get_data_version() {
+            Some(v) => v,
+            None => return builder.call_box((snap, req_ctx)),
+        };
+        if let Some(data) = cache.get(&key, data_version) {
+            COPR_RESULT_CACHE_COUNTER.with_label_values(&["hit"]).inc();
+            return Ok(CachedRequestHandler { data: Some(data) }.into_boxed());
+        }
+        COPR_RESULT_CACHE_COUNTER.with_label_values(&["miss"]).inc();
+        let inner = builder.call_box((snap, req_ctx))?;
+        Ok(CachingRequestHandler {
+            inner,
+            cache,
+            key: Some(key),
+            data_version,
+        }.into_boxed())
+    }
+}
+
+struct CachedRequestHandler {
+    data: Option<Vec<u8>>,
+}
+
+impl RequestHandler for CachedRequestHandler {
+    fn handle_request(&mut self) -> Result<coppb::Response> {
+        let mut resp = coppb::Response::new();
+        resp.set_data(self.data.take().unwrap());
+        Ok(resp)
+    }
+}
+
+struct CachingRequestHandler {
+    inner: Box<RequestHandler + Send>,
+    cache: Arc<ResultCache>,
+    key: Option<CacheKey>,
+    data_version: u64,
+}
+
+impl CachingRequestHandler {
+    fn on_response(&mut self, resp: &coppb::Response) {
+        // Only complete results are cached.
+        if resp.has_region_error() || resp.has_locked() || !resp.get_other_error().is_empty() {
+            return;
+        }
+        // Another request of the same version may have cached it meanwhile.
+        if let Some(key) = self.key.take() {
+            if !self.cache.is_valid(&key, self.data_version) {
+                self.cache.put(key, self.data_version, resp.get_data().to_vec());
+            }
+        }
+    }
+}
+
+impl RequestHandler for CachingRequestHandler {
+    fn handle_request(&mut self) -> Result<coppb::Response> {
+        let resp = self.inner.handle_request()?;
+        self.on_response(&resp);
+        Ok(resp)
+    }
+
+    fn handle_request_for(&mut self, time_slice: Duration) -> Result<Option<coppb::Response>> {
+        let resp = self.inner.handle_request_for(time_slice)?;
+        if let Some(ref resp) = resp {
+            self.on_response(resp);
+        }
+        Ok(resp)
+    }
+
+    fn continue_after(&self) -> Duration {
+        self.inner.continue_after()
+    }
+
+    fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
+        self.inner.collect_metrics_into(metrics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_cache() {
+        let mut ctx = kvrpcpb::Context::new();
+        ctx.set_region_id(1);
+        let mut range = KeyRange::new();
+        range.set_start(b"a".to_vec());
+        range.set_end(b"z".to_vec());
+        let key = CacheKey::new(&ctx, REQ_TYPE_DAG, b"dag", &[range.clone()]);
+        assert_ne!(key, CacheKey::new(&ctx, REQ_TYPE_ANALYZE, b"dag", &[range.clone()]));
+        assert_ne!(key, CacheKey::new(&ctx, REQ_TYPE_DAG, b"dag", &[]));
+        ctx.mut_region_epoch().set_version(2);
+        assert_ne!(key, CacheKey::new(&ctx, REQ_TYPE_DAG, b"dag", &[range]));
+
+        let cache = ResultCache::new(2, 4);
+        assert!(!cache.is_valid(&key, 10));
+        assert!(cache.get(&key, 10).is_none());
+        assert!(cache.put(key.clone(), 10, b"res".to_vec()));
+        assert!(cache.is_valid(&key, 10));
+        assert_eq!(cache.get(&key, 10).unwrap(), b"res".to_vec());
+        // The data has been changed.
+        assert!(!cache.is_valid(&key, 11));
+        assert!(cache.get(&key, 11).is_none());
+
+        // Large results are not cached.
+        assert!(!cache.put(key.clone(), 11, b"large".to_vec()));
+        assert!(cache.is_valid(&key, 10));
+    }
+}
@@ -11,6 +11,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use std::vec::IntoIter;
 
 use crc::crc64::{self, Digest, Hasher64};
@@ -18,11 +22,100 @@ use kvproto::coprocessor::{KeyRange, Response};
 use protobuf::Message;
 use tipb::checksum::{ChecksumAlgorithm, ChecksumRequest, ChecksumResponse, ChecksumScanOn};
 
+use server::quota_limiter::TokenBucket;
 use storage::{Snapshot, SnapshotStore};
+use util::time::Instant;
 
 use coprocessor::dag::executor::{ExecutorMetrics, ScanOn, Scanner};
 use coprocessor::*;
 
+// The scanned bytes are charged to the limiters in batches of about this size.
+const LIMIT_BATCH_BYTES: usize = 64 * 1024;
+
+/// `ChecksumLimiter` limits the checksum requests of a store, which scan whole tables and
+/// would saturate the disk IO otherwise.
+#[derive(Clone)]
+pub struct ChecksumLimiter {
+    store_limiter: Option<Arc<TokenBucket>>,
+    request_bytes_per_sec: u64,
+    max_concurrency: usize,
+    running: Arc<AtomicUsize>,
+}
+
+impl ChecksumLimiter {
+    /// A limit or the concurrency of 0 means unlimited.
+    pub fn new(
+        store_bytes_per_sec: u64,
+        request_bytes_per_sec: u64,
+        max_concurrency: usize,
+    ) -> ChecksumLimiter {
+        let store_limiter = if store_bytes_per_sec > 0 {
+            Some(Arc::new(TokenBucket::new(store_bytes_per_sec as f64)))
+        } else {
+            None
+        };
+        ChecksumLimiter {
+            store_limiter,
+            request_bytes_per_sec,
+            max_concurrency,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Gets the limit of a new request, returns `Error::Full` if there are too many running
+    /// requests.
+    pub fn acquire(&self) -> Result<ChecksumLimit> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst);
+        if self.max_concurrency > 0 && running >= self.max_concurrency {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return Err(Error::Full);
+        }
+        let mut limiters = Vec::with_capacity(2);
+        if self.request_bytes_per_sec > 0 {
+            limiters.push(Arc::new(TokenBucket::new(self.request_bytes_per_sec as f64)));
+        }
+        if let Some(ref limiter) = self.store_limiter {
+            limiters.push(Arc::clone(limiter));
+        }
+        Ok(ChecksumLimit {
+            limiters,
+            pending_bytes: 0,
+            running: Arc::clone(&self.running),
+        })
+    }
+}
+
+/// The limit of a running checksum request. It never blocks: a request over the limit is
+/// paused by the read pool until its debt is paid.
+pub struct ChecksumLimit {
+    limiters: Vec<Arc<TokenBucket>>,
+    pending_bytes: usize,
+    running: Arc<AtomicUsize>,
+}
+
+impl ChecksumLimit {
+    /// Charges the scanned bytes, and returns how long the request should pause.
+    fn consume(&mut self, bytes: usize) -> Duration {
+        self.pending_bytes += bytes;
+        if self.pending_bytes < LIMIT_BATCH_BYTES {
+            return Duration::from_secs(0);
+        }
+        let mut delay = Duration::from_secs(0);
+        for limiter in &self.limiters {
+            limiter.consume(self.pending_bytes as f64);
+            delay = cmp::max(delay, limiter.delay());
+        }
+        self.pending_bytes = 0;
+        delay
+    }
+}
+
+impl Drop for ChecksumLimit {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 // `ChecksumContext` is used to handle `ChecksumRequest`
 pub struct ChecksumContext<S: Snapshot> {
     req: ChecksumRequest,
@@ -30,6 +123,12 @@ pub struct ChecksumContext<S: Snapshot> {
     ranges: IntoIter<KeyRange>,
     scanner: Option<Scanner<S>>,
     metrics: ExecutorMetrics,
+    limit: Option<ChecksumLimit>,
+    checksum: u64,
+    total_kvs: u64,
+    total_bytes: usize,
+    // How long to pause before continuing, when the request is over the limit.
+    delay: Duration,
 }
 
 impl<S: Snapshot> ChecksumContext<S> {
@@ -51,9 +150,19 @@ impl<S: Snapshot> ChecksumContext<S> {
             ranges: ranges.into_iter(),
             scanner: None,
             metrics: ExecutorMetrics::default(),
+            limit: None,
+            checksum: 0,
+            total_kvs: 0,
+            total_bytes: 0,
+            delay: Duration::from_secs(0),
         })
     }
 
+    pub fn with_limit(mut self, limit: ChecksumLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
     fn next_row(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
         loop {
             if let Some(scanner) = self.scanner.as_mut() {
@@ -89,30 +198,39 @@ impl<S: Snapshot> ChecksumContext<S> {
 }
 
 impl<S: Snapshot> RequestHandler for ChecksumContext<S> {
-    fn handle_request(&mut self) -> Result<Response> {
+    fn handle_request_for(&mut self, time_slice: Duration) -> Result<Option<Response>> {
         let algorithm = self.req.get_algorithm();
         if algorithm != ChecksumAlgorithm::Crc64_Xor {
             return Err(box_err!("unknown checksum algorithm {:?}", algorithm));
         }
 
-        let mut checksum = 0;
-        let mut total_kvs = 0;
-        let mut total_bytes = 0;
+        let yield_at = Instant::now_coarse() + time_slice;
+        self.delay = Duration::from_secs(0);
         while let Some((k, v)) = self.next_row()? {
-            checksum = checksum_crc64_xor(checksum, &k, &v);
-            total_kvs += 1;
-            total_bytes += k.len() + v.len();
+            self.checksum = checksum_crc64_xor(self.checksum, &k, &v);
+            self.total_kvs += 1;
+            self.total_bytes += k.len() + v.len();
+            if let Some(limit) = self.limit.as_mut() {
+                self.delay = limit.consume(k.len() + v.len());
+            }
+            if self.delay > Duration::from_secs(0) || Instant::now_coarse() >= yield_at {
+                return Ok(None);
+            }
         }
 
         let mut resp = ChecksumResponse::new();
-        resp.set_checksum(checksum);
-        resp.set_total_kvs(total_kvs);
-        resp.set_total_bytes(total_bytes as u64);
+        resp.set_checksum(self.checksum);
+        resp.set_total_kvs(self.total_kvs);
+        resp.set_total_bytes(self.total_bytes as u64);
         let data = box_try!(resp.write_to_bytes());
 
         let mut resp = Response::new();
         resp.set_data(data);
-        Ok(resp)
+        Ok(Some(resp))
+    }
+
+    fn continue_after(&self) -> Duration {
+        self.delay
     }
 
     fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
@@ -126,3 +244,33 @@ fn checksum_crc64_xor(checksum: u64, k: &[u8], v: &[u8]) -> u64 {
     digest.write(v);
     checksum ^ digest.sum64()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_limiter() {
+        let limiter = ChecksumLimiter::new(0, 1024 * 1024, 2);
+        let mut l1 = limiter.acquire().unwrap();
+        let _l2 = limiter.clone().acquire().unwrap();
+        match limiter.acquire() {
+            Err(Error::Full) => {}
+            _ => panic!("the concurrency should be limited"),
+        }
+        // The bytes are charged in batches.
+        assert_eq!(l1.consume(LIMIT_BATCH_BYTES - 1), Duration::from_secs(0));
+        assert_eq!(l1.pending_bytes, LIMIT_BATCH_BYTES - 1);
+        assert_eq!(l1.consume(1), Duration::from_secs(0));
+        assert_eq!(l1.pending_bytes, 0);
+        // Over the limit, the request should pause instead of blocking.
+        assert!(l1.consume(1024 * 1024) > Duration::from_secs(0));
+        drop(l1);
+        limiter.acquire().unwrap();
+
+        // Unlimited.
+        let limiter = ChecksumLimiter::new(0, 0, 0);
+        let limits: Vec<_> = (0..10).map(|_| limiter.acquire().unwrap()).collect();
+        assert!(limits[0].limiters.is_empty());
+    }
+}
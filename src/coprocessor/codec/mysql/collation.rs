@@ -0,0 +1,190 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use tipb::expression::FieldType;
+
+use super::general_ci::GENERAL_CI_PAGES;
+use coprocessor::codec::Datum;
+
+// Collate ids of the supported collations.
+pub const COLLATION_UTF8_GENERAL_CI_ID: i32 = 33;
+pub const COLLATION_UTF8MB4_GENERAL_CI_ID: i32 = 45;
+pub const COLLATION_UTF8MB4_BIN_ID: i32 = 46;
+pub const COLLATION_UTF8_BIN_ID: i32 = 83;
+
+// The weight of the characters out of the basic multilingual plane in general_ci collations.
+const GENERAL_CI_REPLACEMENT_WEIGHT: u16 = 0xFFFD;
+
+/// `Collation` decides how strings are compared.
+///
+/// TiDB sends negative collate ids when the new collation framework is enabled, in which case
+/// the strings are compared with the rules of the collation. Non-negative collate ids are
+/// compared as binary as before, so that old TiDBs get the same results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collation {
+    /// Compares bytes as they are.
+    Binary,
+    /// Compares bytes with trailing spaces ignored, like `utf8mb4_bin`.
+    PaddingBinary,
+    /// Compares characters case-insensitively with trailing spaces ignored, like
+    /// `utf8mb4_general_ci`.
+    GeneralCi,
+}
+
+impl Collation {
+    pub fn from_collate(collate: i32) -> Collation {
+        if collate >= 0 {
+            return Collation::Binary;
+        }
+        match -collate {
+            COLLATION_UTF8_BIN_ID | COLLATION_UTF8MB4_BIN_ID => Collation::PaddingBinary,
+            COLLATION_UTF8_GENERAL_CI_ID | COLLATION_UTF8MB4_GENERAL_CI_ID => Collation::GeneralCi,
+            _ => Collation::Binary,
+        }
+    }
+
+    #[inline]
+    pub fn from_field_type(tp: &FieldType) -> Collation {
+        Collation::from_collate(tp.get_collate())
+    }
+
+    #[inline]
+    pub fn is_binary(self) -> bool {
+        self == Collation::Binary
+    }
+
+    /// Returns the sort key of `s`. Two strings are equal in the collation if and only if
+    /// their sort keys are equal, and the sort keys are ordered the same way as the strings.
+    pub fn sort_key(self, s: &[u8]) -> Cow<[u8]> {
+        match self {
+            Collation::Binary => Cow::Borrowed(s),
+            Collation::PaddingBinary => Cow::Borrowed(trim_right_spaces(s)),
+            Collation::GeneralCi => {
+                let s = String::from_utf8_lossy(trim_right_spaces(s));
+                let mut key = Vec::with_capacity(s.len() * 2);
+                for c in s.chars() {
+                    let weight = general_ci_weight(c);
+                    key.push((weight >> 8) as u8);
+                    key.push(weight as u8);
+                }
+                Cow::Owned(key)
+            }
+        }
+    }
+
+    pub fn compare(self, lhs: &[u8], rhs: &[u8]) -> Ordering {
+        match self {
+            Collation::Binary => lhs.cmp(rhs),
+            _ => self.sort_key(lhs).cmp(&self.sort_key(rhs)),
+        }
+    }
+
+    /// Replaces the string in `datum` with its sort key, so that datums can be compared or
+    /// encoded as binary.
+    pub fn sort_key_datum(self, datum: Datum) -> Datum {
+        match datum {
+            Datum::Bytes(ref s) if !self.is_binary() => {
+                Datum::Bytes(self.sort_key(s).into_owned())
+            }
+            datum => datum,
+        }
+    }
+}
+
+fn trim_right_spaces(s: &[u8]) -> &[u8] {
+    let len = s.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &s[..len]
+}
+
+fn general_ci_weight(c: char) -> u16 {
+    let c = c as u32;
+    if c > 0xFFFF {
+        return GENERAL_CI_REPLACEMENT_WEIGHT;
+    }
+    match GENERAL_CI_PAGES[(c >> 8) as usize] {
+        Some(page) => page[(c & 0xFF) as usize],
+        None => c as u16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation() {
+        assert_eq!(Collation::from_collate(63), Collation::Binary);
+        assert_eq!(Collation::from_collate(45), Collation::Binary);
+        assert_eq!(Collation::from_collate(-63), Collation::Binary);
+        assert_eq!(Collation::from_collate(-46), Collation::PaddingBinary);
+        assert_eq!(Collation::from_collate(-33), Collation::GeneralCi);
+
+        let tests: Vec<(&[u8], &[u8], Ordering, Ordering, Ordering)> = vec![
+            (b"a", b"a", Ordering::Equal, Ordering::Equal, Ordering::Equal),
+            (b"a ", b"a", Ordering::Greater, Ordering::Equal, Ordering::Equal),
+            (b"a", b"A", Ordering::Greater, Ordering::Greater, Ordering::Equal),
+            (b"aB", b"Ab ", Ordering::Greater, Ordering::Greater, Ordering::Equal),
+            (b"a", b"b", Ordering::Less, Ordering::Less, Ordering::Less),
+            (b"B", b"a", Ordering::Less, Ordering::Less, Ordering::Greater),
+            (b"", b" ", Ordering::Less, Ordering::Equal, Ordering::Equal),
+            (
+                "\u{00e9}".as_bytes(),
+                "\u{00c9}".as_bytes(),
+                Ordering::Greater,
+                Ordering::Greater,
+                Ordering::Equal,
+            ),
+            (
+                "\u{00e9}".as_bytes(),
+                b"E",
+                Ordering::Greater,
+                Ordering::Greater,
+                Ordering::Equal,
+            ),
+            (
+                "\u{00df}".as_bytes(),
+                b"s",
+                Ordering::Greater,
+                Ordering::Greater,
+                Ordering::Equal,
+            ),
+            (
+                "\u{0439}".as_bytes(),
+                "\u{0438}".as_bytes(),
+                Ordering::Greater,
+                Ordering::Greater,
+                Ordering::Greater,
+            ),
+            (
+                "\u{1f600}".as_bytes(),
+                "\u{1f601}".as_bytes(),
+                Ordering::Less,
+                Ordering::Less,
+                Ordering::Equal,
+            ),
+        ];
+        for (lhs, rhs, binary, padding, ci) in tests {
+            assert_eq!(Collation::Binary.compare(lhs, rhs), binary);
+            assert_eq!(Collation::PaddingBinary.compare(lhs, rhs), padding);
+            assert_eq!(Collation::GeneralCi.compare(lhs, rhs), ci);
+        }
+
+        let datum = Collation::GeneralCi.sort_key_datum(Datum::Bytes(b"ab ".to_vec()));
+        assert_eq!(datum, Datum::Bytes(vec![0, b'A', 0, b'B']));
+        let datum = Collation::GeneralCi.sort_key_datum(Datum::I64(1));
+        assert_eq!(datum, Datum::I64(1));
+    }
+}
@@ -60,12 +60,15 @@ fn parse_frac(s: &[u8], fsp: u8) -> Result<u32> {
 }
 
 pub mod charset;
+pub mod collation;
 pub mod decimal;
 pub mod duration;
+mod general_ci;
 pub mod json;
 pub mod time;
 pub mod types;
 
+pub use self::collation::Collation;
 pub use self::decimal::{dec_encoded_len, Decimal, DecimalEncoder, Res, RoundMode};
 pub use self::duration::{Duration, DurationEncoder};
 pub use self::json::{parse_json_path_expr, Json, JsonEncoder, ModifyType, PathExpression};
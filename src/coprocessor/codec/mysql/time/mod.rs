@@ -33,6 +33,7 @@ use coprocessor::codec::{Error, Result, TEN_POW};
 use self::extension::*;
 use self::weekmode::WeekMode;
 
+pub use self::extension::WeekdayExtension;
 pub use self::tz::Tz;
 
 const ZERO_DATETIME_STR: &str = "0000-00-00 00:00:00";
@@ -46,7 +47,7 @@ const ZERO_TIMESTAMP: i64 = -62169984000;
 pub const MAX_TIMESTAMP: i64 = 253402300799;
 pub const MAX_TIME_NANOSECONDS: u32 = 999999000;
 
-const MONTH_NAMES: &[&str] = &[
+pub const MONTH_NAMES: &[&str] = &[
     "January",
     "February",
     "March",
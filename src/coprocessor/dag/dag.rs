@@ -11,23 +11,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::mem;
 use std::sync::Arc;
 
 use kvproto::coprocessor::{KeyRange, Response};
 use protobuf::{Message, RepeatedField};
+use tipb::executor::ExecType;
 use tipb::select::{Chunk, DAGRequest, EncodeType, SelectResponse, StreamResponse};
 
-use coprocessor::dag::expr::EvalConfig;
+use coprocessor::codec::chunk::{Chunk as ArrowChunk, ChunkEncoder};
+use coprocessor::dag::expr::{EvalConfig, EvalContext};
 use coprocessor::*;
 use storage::{Snapshot, SnapshotStore};
+use util::time::{Duration, Instant};
 
-use super::executor::{build_exec, Executor, ExecutorMetrics};
+use super::executor::{build_exec, Executor, ExecutorMetrics, Row};
+use super::ranges;
+
+// Check whether the time slice is used up every this many rows, so that the clock
+// isn't read for every row.
+const CHECK_TIME_SLICE_ROWS: usize = 64;
 
 pub struct DAGContext {
     deadline: Deadline,
     exec: Box<Executor + Send>,
     output_offsets: Vec<u32>,
     batch_row_limit: usize,
+    encode_type: EncodeType,
+    rows_encoder: RowsEncoder,
+    // The chunks of a unary request produced so far, kept across time slices.
+    chunks: Vec<Chunk>,
+    record_cnt: usize,
 }
 
 impl DAGContext {
@@ -64,62 +78,87 @@ impl DAGContext {
             !req_ctx.context.get_not_fill_cache(),
         );
 
+        let execs = req.take_executors().into_vec();
+        let encode_type = req.get_encode_type();
+        if encode_type == EncodeType::TypeArrow {
+            let tp = execs.last().map(|e| e.get_tp());
+            if tp == Some(ExecType::TypeAggregation) || tp == Some(ExecType::TypeStreamAgg) {
+                return Err(box_err!(
+                    "encode type {:?} is not supported by aggregation",
+                    encode_type
+                ));
+            }
+        }
+        let eval_cfg = Arc::new(eval_cfg);
+        let rows_encoder = match encode_type {
+            EncodeType::TypeDefault => RowsEncoder::Default,
+            EncodeType::TypeArrow => RowsEncoder::Arrow {
+                ctx: EvalContext::new(Arc::clone(&eval_cfg)),
+                chunk: None,
+            },
+        };
+        // Range counts are reported per request range, so the ranges can't be changed.
+        let ranges = if req.get_collect_range_counts() {
+            ranges
+        } else {
+            ranges::tighten_ranges(&execs, ranges)
+        };
         let dag_executor = build_exec(
-            req.take_executors().into_vec(),
+            execs,
             store,
             ranges,
-            Arc::new(eval_cfg),
+            eval_cfg,
             req.get_collect_range_counts(),
+            req_ctx.deadline,
+            Arc::clone(&req_ctx.memory_tracker),
         )?;
         Ok(Self {
             deadline: req_ctx.deadline,
             exec: dag_executor,
             output_offsets: req.take_output_offsets(),
             batch_row_limit,
+            encode_type,
+            rows_encoder,
+            chunks: Vec::new(),
+            record_cnt: 0,
         })
     }
 
-    fn make_stream_response(&mut self, chunk: Chunk, range: Option<KeyRange>) -> Result<Response> {
-        let mut s_resp = StreamResponse::new();
-        s_resp.set_encode_type(EncodeType::TypeDefault);
-        s_resp.set_data(box_try!(chunk.write_to_bytes()));
-        if let Some(eval_warnings) = self.exec.take_eval_warnings() {
-            s_resp.set_warnings(RepeatedField::from_vec(eval_warnings.warnings));
-            s_resp.set_warning_count(eval_warnings.warning_cnt as i64);
-        }
-        self.exec.collect_output_counts(s_resp.mut_output_counts());
-
-        let mut resp = Response::new();
-        resp.set_data(box_try!(s_resp.write_to_bytes()));
-        if let Some(range) = range {
-            resp.set_range(range);
-        }
-        Ok(resp)
-    }
-}
-
-impl RequestHandler for DAGContext {
-    fn handle_request(&mut self) -> Result<Response> {
-        let mut record_cnt = 0;
-        let mut chunks = Vec::new();
+    /// Handles the unary request until it finishes or `yield_at` is reached. Returns `None`
+    /// in the latter case.
+    fn handle_request_until(&mut self, yield_at: Option<Instant>) -> Result<Option<Response>> {
+        let mut rows = 0;
         loop {
             match self.exec.next() {
                 Ok(Some(row)) => {
                     self.deadline.check_if_exceeded()?;
-                    if chunks.is_empty() || record_cnt >= self.batch_row_limit {
+                    if self.chunks.is_empty() || self.record_cnt >= self.batch_row_limit {
+                        if let Some(chunk) = self.chunks.last_mut() {
+                            self.rows_encoder.finish(chunk)?;
+                        }
                         let chunk = Chunk::new();
-                        chunks.push(chunk);
-                        record_cnt = 0;
+                        self.chunks.push(chunk);
+                        self.record_cnt = 0;
+                    }
+                    let chunk = self.chunks.last_mut().unwrap();
+                    self.record_cnt += 1;
+                    self.rows_encoder
+                        .append(&row, &self.output_offsets, chunk)?;
+
+                    rows += 1;
+                    if let Some(yield_at) = yield_at {
+                        if rows % CHECK_TIME_SLICE_ROWS == 0 && Instant::now_coarse() >= yield_at {
+                            return Ok(None);
+                        }
                     }
-                    let chunk = chunks.last_mut().unwrap();
-                    record_cnt += 1;
-                    // for default encode type
-                    let value = row.get_binary(&self.output_offsets)?;
-                    chunk.mut_rows_data().extend_from_slice(&value);
                 }
                 Ok(None) => {
+                    if let Some(chunk) = self.chunks.last_mut() {
+                        self.rows_encoder.finish(chunk)?;
+                    }
                     let mut resp = Response::new();
                     let mut sel_resp = SelectResponse::new();
+                    let chunks = mem::replace(&mut self.chunks, Vec::new());
                     sel_resp.set_chunks(RepeatedField::from_vec(chunks));
                     if let Some(eval_warnings) = self.exec.take_eval_warnings() {
                         sel_resp.set_warnings(RepeatedField::from_vec(eval_warnings.warnings));
@@ -129,7 +168,7 @@ impl RequestHandler for DAGContext {
                         .collect_output_counts(sel_resp.mut_output_counts());
                     let data = box_try!(sel_resp.write_to_bytes());
                     resp.set_data(data);
-                    return Ok(resp);
+                    return Ok(Some(resp));
                 }
                 Err(Error::Eval(err)) => {
                     let mut resp = Response::new();
@@ -137,13 +176,41 @@ impl RequestHandler for DAGContext {
                     sel_resp.set_error(err);
                     let data = box_try!(sel_resp.write_to_bytes());
                     resp.set_data(data);
-                    return Ok(resp);
+                    return Ok(Some(resp));
                 }
                 Err(e) => return Err(e),
             }
         }
     }
 
+    fn make_stream_response(&mut self, chunk: Chunk, range: Option<KeyRange>) -> Result<Response> {
+        let mut s_resp = StreamResponse::new();
+        s_resp.set_encode_type(self.encode_type);
+        s_resp.set_data(box_try!(chunk.write_to_bytes()));
+        if let Some(eval_warnings) = self.exec.take_eval_warnings() {
+            s_resp.set_warnings(RepeatedField::from_vec(eval_warnings.warnings));
+            s_resp.set_warning_count(eval_warnings.warning_cnt as i64);
+        }
+        self.exec.collect_output_counts(s_resp.mut_output_counts());
+
+        let mut resp = Response::new();
+        resp.set_data(box_try!(s_resp.write_to_bytes()));
+        if let Some(range) = range {
+            resp.set_range(range);
+        }
+        Ok(resp)
+    }
+}
+
+impl RequestHandler for DAGContext {
+    fn handle_request(&mut self) -> Result<Response> {
+        self.handle_request_until(None).map(|resp| resp.unwrap())
+    }
+
+    fn handle_request_for(&mut self, time_slice: Duration) -> Result<Option<Response>> {
+        self.handle_request_until(Some(Instant::now_coarse() + time_slice))
+    }
+
     fn handle_streaming_request(&mut self) -> Result<(Option<Response>, bool)> {
         let (mut record_cnt, mut finished) = (0, false);
         let mut chunk = Chunk::new();
@@ -153,8 +220,8 @@ impl RequestHandler for DAGContext {
                 Ok(Some(row)) => {
                     self.deadline.check_if_exceeded()?;
                     record_cnt += 1;
-                    let value = row.get_binary(&self.output_offsets)?;
-                    chunk.mut_rows_data().extend_from_slice(&value);
+                    self.rows_encoder
+                        .append(&row, &self.output_offsets, &mut chunk)?;
                 }
                 Ok(None) => {
                     finished = true;
@@ -172,6 +239,7 @@ impl RequestHandler for DAGContext {
             }
         }
         if record_cnt > 0 {
+            self.rows_encoder.finish(&mut chunk)?;
             let range = self.exec.stop_scan();
             return self
                 .make_stream_response(chunk, range)
@@ -184,3 +252,129 @@ impl RequestHandler for DAGContext {
         self.exec.collect_metrics_into(metrics);
     }
 }
+
+/// `RowsEncoder` encodes the rows into the `rows_data` of a chunk in the requested
+/// encode type.
+enum RowsEncoder {
+    /// Rows are encoded as datums one by one.
+    Default,
+    /// Rows are encoded column by column, see `coprocessor::codec::chunk`. Rows are
+    /// buffered until `finish` is called, as the columns are laid out one after another.
+    Arrow {
+        ctx: EvalContext,
+        chunk: Option<ArrowChunk>,
+    },
+}
+
+impl RowsEncoder {
+    fn append(&mut self, row: &Row, output_offsets: &[u32], chunk: &mut Chunk) -> Result<()> {
+        match *self {
+            RowsEncoder::Default => {
+                let value = row.get_binary(output_offsets)?;
+                chunk.mut_rows_data().extend_from_slice(&value);
+            }
+            RowsEncoder::Arrow {
+                ref mut ctx,
+                chunk: ref mut arrow_chunk,
+            } => {
+                if arrow_chunk.is_none() {
+                    let tps = row.get_field_types(output_offsets)?;
+                    *arrow_chunk = Some(ArrowChunk::new(&tps, 0));
+                }
+                row.append_to_chunk(ctx, output_offsets, arrow_chunk.as_mut().unwrap())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows to the chunk.
+    fn finish(&mut self, chunk: &mut Chunk) -> Result<()> {
+        if let RowsEncoder::Arrow {
+            chunk: Some(ref mut arrow_chunk),
+            ..
+        } = *self
+        {
+            if arrow_chunk.num_rows() > 0 {
+                box_try!(chunk.mut_rows_data().encode_chunk(arrow_chunk));
+                arrow_chunk.reset();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tipb::schema::ColumnInfo;
+
+    use coprocessor::codec::datum::Datum;
+    use coprocessor::codec::mysql::types;
+    use coprocessor::codec::table;
+    use util::collections::HashSet;
+
+    use super::*;
+
+    fn new_col_info(cid: i64, tp: u8) -> ColumnInfo {
+        let mut col_info = ColumnInfo::new();
+        col_info.set_tp(i32::from(tp));
+        col_info.set_column_id(cid);
+        col_info
+    }
+
+    #[test]
+    fn test_arrow_rows_encoder() {
+        let cols = Arc::new(vec![
+            new_col_info(1, types::LONG_LONG),
+            new_col_info(2, types::VARCHAR),
+        ]);
+        let col_ids: HashSet<i64> = vec![1, 2].into_iter().collect();
+        let new_row = |id: i64, name: &[u8]| {
+            let value =
+                table::encode_row(vec![Datum::I64(id), Datum::Bytes(name.to_vec())], &[1, 2])
+                    .unwrap();
+            Row::origin(id, table::cut_row(value, &col_ids).unwrap(), Arc::clone(&cols))
+        };
+        // Only the second column is output.
+        let output_offsets = vec![1];
+
+        let mut encoder = RowsEncoder::Arrow {
+            ctx: EvalContext::default(),
+            chunk: None,
+        };
+        let mut chunk = Chunk::new();
+        for (id, name) in vec![(1, b"a"), (2, b"b")] {
+            encoder
+                .append(&new_row(id, name), &output_offsets, &mut chunk)
+                .unwrap();
+        }
+        // Rows are buffered until finished.
+        assert!(chunk.get_rows_data().is_empty());
+        encoder.finish(&mut chunk).unwrap();
+
+        let tps = new_row(0, b"").get_field_types(&output_offsets).unwrap();
+        let decoded = ArrowChunk::decode(&mut chunk.get_rows_data(), &tps).unwrap();
+        assert_eq!(decoded.num_rows(), 2);
+        assert_eq!(decoded.num_cols(), 1);
+        for (i, name) in vec![b"a", b"b"].into_iter().enumerate() {
+            let row = decoded.get_row(i).unwrap();
+            assert_eq!(
+                row.get_datum(0, &tps[0]).unwrap(),
+                Datum::Bytes(name.to_vec())
+            );
+        }
+
+        // The next chunk starts from empty.
+        let mut chunk = Chunk::new();
+        encoder
+            .append(&new_row(3, b"c"), &output_offsets, &mut chunk)
+            .unwrap();
+        encoder.finish(&mut chunk).unwrap();
+        let decoded = ArrowChunk::decode(&mut chunk.get_rows_data(), &tps).unwrap();
+        assert_eq!(decoded.num_rows(), 1);
+
+        let agg_row = Row::agg(vec![Datum::I64(1)], vec![]);
+        assert!(encoder.append(&agg_row, &[], &mut chunk).is_err());
+    }
+}
@@ -21,6 +21,7 @@ use tipb::expression::{Expr, ExprType};
 use util::collections::{OrderMap, OrderMapEntry};
 
 use coprocessor::codec::datum::{self, Datum};
+use coprocessor::codec::mysql::Collation;
 use coprocessor::dag::expr::{EvalConfig, EvalContext, EvalWarnings, Expression};
 use coprocessor::*;
 
@@ -28,6 +29,9 @@ use super::aggregate::{self, AggrFunc};
 use super::ExecutorMetrics;
 use super::{Executor, ExprColumnRefVisitor, Row};
 
+// The estimated memory used by the state of an aggregate function of a group.
+const AGGR_FUNC_STATE_SIZE: usize = 64;
+
 struct AggFuncExpr {
     args: Vec<Expression>,
     tp: ExprType,
@@ -76,6 +80,7 @@ impl AggrFunc {
 
 struct AggExecutor {
     group_by: Vec<Expression>,
+    group_by_collations: Vec<Collation>,
     aggr_func: Vec<AggFuncExpr>,
     executed: bool,
     ctx: EvalContext,
@@ -96,8 +101,14 @@ impl AggExecutor {
         visitor.batch_visit(&group_by)?;
         visitor.batch_visit(&aggr_func)?;
         let mut ctx = EvalContext::new(eval_config);
+        let group_by = Expression::batch_build(&mut ctx, group_by)?;
+        let group_by_collations = group_by
+            .iter()
+            .map(|expr| Collation::from_field_type(expr.get_tp()))
+            .collect();
         Ok(AggExecutor {
-            group_by: Expression::batch_build(&mut ctx, group_by)?,
+            group_by,
+            group_by_collations,
             aggr_func: AggFuncExpr::batch_build(&mut ctx, aggr_func)?,
             executed: false,
             ctx,
@@ -129,6 +140,10 @@ impl AggExecutor {
         Ok(vals)
     }
 
+    fn is_binary_group_by(&self) -> bool {
+        self.group_by_collations.iter().all(|c| c.is_binary())
+    }
+
     fn collect_output_counts(&mut self, counts: &mut Vec<i64>) {
         self.src.collect_output_counts(counts);
     }
@@ -160,7 +175,11 @@ impl AggExecutor {
 pub struct HashAggExecutor {
     inner: AggExecutor,
     group_key_aggrs: OrderMap<Vec<u8>, Vec<Box<AggrFunc>>>,
+    // The encoded group by columns of the groups in `group_key_aggrs`, only used when the
+    // group keys are made of sort keys of non-binary collations.
+    group_by_values: Vec<Vec<u8>>,
     cursor: usize,
+    memory_tracker: Arc<MemoryTracker>,
 }
 
 impl HashAggExecutor {
@@ -168,6 +187,7 @@ impl HashAggExecutor {
         mut meta: Aggregation,
         eval_config: Arc<EvalConfig>,
         src: Box<Executor + Send>,
+        memory_tracker: Arc<MemoryTracker>,
     ) -> Result<HashAggExecutor> {
         let group_bys = meta.take_group_by().into_vec();
         let aggs = meta.take_agg_func().into_vec();
@@ -175,25 +195,45 @@ impl HashAggExecutor {
         Ok(HashAggExecutor {
             inner,
             group_key_aggrs: OrderMap::new(),
+            group_by_values: Vec::new(),
             cursor: 0,
+            memory_tracker,
         })
     }
 
-    fn get_group_key(&mut self, row: &[Datum]) -> Result<Vec<u8>> {
+    /// Returns the group key and the encoded group by columns if they are different, which
+    /// happens when some group by columns are not binary collated.
+    fn get_group_key(&mut self, row: &[Datum]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
         let group_by_cols = self.inner.get_group_by_cols(row)?;
         if group_by_cols.is_empty() {
             let single_group = Datum::Bytes(SINGLE_GROUP.to_vec());
-            return Ok(box_try!(datum::encode_value(&[single_group])));
+            return Ok((box_try!(datum::encode_value(&[single_group])), None));
         }
         let res = box_try!(datum::encode_value(&group_by_cols));
-        Ok(res)
+        if self.inner.is_binary_group_by() {
+            return Ok((res, None));
+        }
+        let sort_keys: Vec<_> = group_by_cols
+            .into_iter()
+            .zip(&self.inner.group_by_collations)
+            .map(|(col, collation)| collation.sort_key_datum(col))
+            .collect();
+        let key = box_try!(datum::encode_value(&sort_keys));
+        Ok((key, Some(res)))
     }
 
     fn aggregate(&mut self) -> Result<()> {
         while let Some(cols) = self.inner.next()? {
-            let group_key = self.get_group_key(&cols)?;
+            let (group_key, group_by_value) = self.get_group_key(&cols)?;
+            let group_size = group_key.len()
+                + group_by_value.as_ref().map_or(0, |v| v.len())
+                + self.inner.aggr_func.len() * AGGR_FUNC_STATE_SIZE;
             match self.group_key_aggrs.entry(group_key) {
                 OrderMapEntry::Vacant(e) => {
+                    self.memory_tracker.consume(group_size)?;
+                    if let Some(value) = group_by_value {
+                        self.group_by_values.push(value);
+                    }
                     let mut aggrs = Vec::with_capacity(self.inner.aggr_func.len());
                     for expr in &mut self.inner.aggr_func {
                         let mut aggr = aggregate::build_aggr_func(expr.tp)?;
@@ -232,10 +272,11 @@ impl Executor for HashAggExecutor {
                 }
 
                 if !self.inner.group_by.is_empty() {
-                    Ok(Some(Row::agg(
-                        aggr_cols,
-                        mem::replace(&mut group_key, Vec::new()),
-                    )))
+                    let group_by_value = match self.group_by_values.get_mut(self.cursor - 1) {
+                        Some(value) => mem::replace(value, Vec::new()),
+                        None => mem::replace(&mut group_key, Vec::new()),
+                    };
+                    Ok(Some(Row::agg(aggr_cols, group_by_value)))
                 } else {
                     Ok(Some(Row::agg(aggr_cols, Vec::default())))
                 }
@@ -361,8 +402,13 @@ impl StreamAggExecutor {
             return Ok(false);
         }
         let mut meet_new_group = false;
-        for (prev, cur) in self.cur_group_row.iter().zip(cur_group_by_cols.iter()) {
-            if prev.cmp(&mut self.inner.ctx, cur)? != Ordering::Equal {
+        let cols = self.cur_group_row.iter().zip(cur_group_by_cols.iter());
+        for ((prev, cur), collation) in cols.zip(&self.inner.group_by_collations) {
+            let ord = match (prev, cur) {
+                (&Datum::Bytes(ref l), &Datum::Bytes(ref r)) => collation.compare(l, r),
+                _ => prev.cmp(&mut self.inner.ctx, cur)?,
+            };
+            if ord != Ordering::Equal {
                 meet_new_group = true;
                 break;
             }
@@ -749,10 +795,12 @@ mod test {
         let aggr_funcs = build_aggr_func(&aggr_funcs);
         aggregation.set_agg_func(RepeatedField::from_vec(aggr_funcs));
         // init the hash aggregation executor
+        let memory_tracker = Arc::new(MemoryTracker::default());
         let mut aggr_ect = HashAggExecutor::new(
             aggregation,
             Arc::new(EvalConfig::default()),
             Box::new(ts_ect),
+            Arc::clone(&memory_tracker),
         ).unwrap();
         let expect_row_cnt = 4;
         let mut row_data = Vec::with_capacity(expect_row_cnt);
@@ -760,6 +808,7 @@ mod test {
             row_data.push(row.get_binary().unwrap());
         }
         assert_eq!(row_data.len(), expect_row_cnt);
+        assert!(memory_tracker.consumed() > 0);
         let expect_row_data = vec![
             (
                 3 as u64,
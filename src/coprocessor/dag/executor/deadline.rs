@@ -0,0 +1,132 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use kvproto::coprocessor::KeyRange;
+
+use coprocessor::dag::expr::EvalWarnings;
+use coprocessor::metrics::*;
+use coprocessor::*;
+
+use super::{Executor, ExecutorMetrics, Row};
+
+// Check the deadline every this many rows, so that the clock is not read for every row.
+const CHECK_DEADLINE_ROWS: usize = 64;
+
+/// `DeadlineExecutor` stops the request once its deadline is exceeded.
+///
+/// It's put right above the scan executor, so that executors consuming all of their source
+/// before producing any row, like aggregations and TopN, are cancelled in the middle too.
+pub struct DeadlineExecutor {
+    deadline: Deadline,
+    rows: usize,
+    src: Box<Executor + Send>,
+}
+
+impl DeadlineExecutor {
+    pub fn new(deadline: Deadline, src: Box<Executor + Send>) -> DeadlineExecutor {
+        DeadlineExecutor {
+            deadline,
+            rows: 0,
+            src,
+        }
+    }
+
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline.check_if_exceeded() {
+            Err(Error::Outdated(elapsed, tag)) => {
+                COPR_CANCELLED_TASK_COUNTER.with_label_values(&[tag]).inc();
+                Err(Error::Outdated(elapsed, tag))
+            }
+            res => res,
+        }
+    }
+}
+
+impl Executor for DeadlineExecutor {
+    fn next(&mut self) -> Result<Option<Row>> {
+        self.rows += 1;
+        if self.rows % CHECK_DEADLINE_ROWS == 0 {
+            self.check_deadline()?;
+        }
+        self.src.next()
+    }
+
+    fn collect_output_counts(&mut self, counts: &mut Vec<i64>) {
+        self.src.collect_output_counts(counts);
+    }
+
+    fn collect_metrics_into(&mut self, metrics: &mut ExecutorMetrics) {
+        self.src.collect_metrics_into(metrics);
+    }
+
+    fn get_len_of_columns(&self) -> usize {
+        self.src.get_len_of_columns()
+    }
+
+    fn take_eval_warnings(&mut self) -> Option<EvalWarnings> {
+        self.src.take_eval_warnings()
+    }
+
+    fn start_scan(&mut self) {
+        self.src.start_scan();
+    }
+
+    fn stop_scan(&mut self) -> Option<KeyRange> {
+        self.src.stop_scan()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    struct InfiniteExecutor;
+
+    impl Executor for InfiniteExecutor {
+        fn next(&mut self) -> Result<Option<Row>> {
+            Ok(Some(Row::agg(vec![], vec![])))
+        }
+
+        fn collect_output_counts(&mut self, _: &mut Vec<i64>) {}
+
+        fn collect_metrics_into(&mut self, _: &mut ExecutorMetrics) {}
+
+        fn get_len_of_columns(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    fn test_deadline_executor() {
+        let deadline = Deadline::from_now("select", Duration::from_millis(50));
+        let mut exec = DeadlineExecutor::new(deadline, box InfiniteExecutor);
+        for _ in 0..CHECK_DEADLINE_ROWS * 2 {
+            exec.next().unwrap().unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        let mut cancelled = false;
+        for _ in 0..CHECK_DEADLINE_ROWS {
+            match exec.next() {
+                Ok(_) => continue,
+                Err(Error::Outdated(_, tag)) => assert_eq!(tag, "select"),
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+            cancelled = true;
+            break;
+        }
+        assert!(cancelled);
+    }
+}
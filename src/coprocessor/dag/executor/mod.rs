@@ -15,13 +15,14 @@ use std::sync::Arc;
 
 use kvproto::coprocessor::KeyRange;
 use tipb::executor::{self, ExecType};
-use tipb::expression::{Expr, ExprType};
+use tipb::expression::{Expr, ExprType, FieldType};
 use tipb::schema::ColumnInfo;
 
 use storage::{Snapshot, SnapshotStore};
 use util::codec::number;
 use util::collections::HashSet;
 
+use coprocessor::codec::chunk::Chunk;
 use coprocessor::codec::datum::{self, Datum, DatumEncoder};
 use coprocessor::codec::mysql;
 use coprocessor::codec::table::{self, RowColsDict};
@@ -31,6 +32,7 @@ use coprocessor::*;
 
 mod aggregate;
 mod aggregation;
+mod deadline;
 mod index_scan;
 mod limit;
 mod scanner;
@@ -42,6 +44,7 @@ mod topn_heap;
 mod metrics;
 
 pub use self::aggregation::{HashAggExecutor, StreamAggExecutor};
+pub use self::deadline::DeadlineExecutor;
 pub use self::index_scan::IndexScanExecutor;
 pub use self::limit::LimitExecutor;
 pub use self::metrics::*;
@@ -149,6 +152,27 @@ impl Row {
             Row::Agg(row) => row.get_binary(), // ignore output offsets for aggregation.
         }
     }
+
+    /// Appends the columns in `output_offsets` to the chunk. Rows generated by
+    /// aggregation don't carry the types of the columns, so they can't be appended.
+    pub fn append_to_chunk(
+        &self,
+        ctx: &mut EvalContext,
+        output_offsets: &[u32],
+        chunk: &mut Chunk,
+    ) -> Result<()> {
+        match self {
+            Row::Origin(row) => row.append_to_chunk(ctx, output_offsets, chunk),
+            Row::Agg(_) => Err(box_err!("can't append the aggregated row to chunk")),
+        }
+    }
+
+    pub fn get_field_types(&self, output_offsets: &[u32]) -> Result<Vec<FieldType>> {
+        match self {
+            Row::Origin(row) => Ok(row.get_field_types(output_offsets)),
+            Row::Agg(_) => Err(box_err!("the aggregated row has no field types")),
+        }
+    }
 }
 
 impl OriginCols {
@@ -206,6 +230,36 @@ impl OriginCols {
         Ok(values)
     }
 
+    pub fn append_to_chunk(
+        &self,
+        ctx: &mut EvalContext,
+        output_offsets: &[u32],
+        chunk: &mut Chunk,
+    ) -> Result<()> {
+        let offsets: Vec<usize> = output_offsets.iter().map(|o| *o as usize).collect();
+        let datums = self.inflate_cols_with_offsets(ctx, &offsets)?;
+        for (col_idx, offset) in offsets.into_iter().enumerate() {
+            box_try!(chunk.append_datum(col_idx, &datums[offset]));
+        }
+        Ok(())
+    }
+
+    pub fn get_field_types(&self, output_offsets: &[u32]) -> Vec<FieldType> {
+        output_offsets
+            .iter()
+            .map(|offset| {
+                let col = &self.cols[*offset as usize];
+                let mut tp = FieldType::new();
+                tp.set_tp(col.get_tp());
+                tp.set_flag(col.get_flag() as u32);
+                tp.set_flen(col.get_column_len());
+                tp.set_decimal(col.get_decimal());
+                tp.set_collate(col.get_collation());
+                tp
+            })
+            .collect()
+    }
+
     // inflate with the real value(Datum) for each columns in offsets
     // inflate with Datum::Null for those cols not in offsets.
     // It's used in expression since column is marked with offset
@@ -274,12 +328,15 @@ pub fn build_exec<S: Snapshot + 'static>(
     ranges: Vec<KeyRange>,
     ctx: Arc<EvalConfig>,
     collect: bool,
+    deadline: Deadline,
+    memory_tracker: Arc<MemoryTracker>,
 ) -> Result<Box<Executor + Send>> {
     let mut execs = execs.into_iter();
     let first = execs
         .next()
         .ok_or_else(|| Error::Other(box_err!("has no executor")))?;
-    let mut src = build_first_executor(first, store, ranges, collect)?;
+    let first = build_first_executor(first, store, ranges, collect)?;
+    let mut src: Box<Executor + Send> = box DeadlineExecutor::new(deadline, first);
     for mut exec in execs {
         let curr: Box<Executor + Send> = match exec.get_tp() {
             ExecType::TypeTableScan | ExecType::TypeIndexScan => {
@@ -294,15 +351,19 @@ pub fn build_exec<S: Snapshot + 'static>(
                 exec.take_aggregation(),
                 Arc::clone(&ctx),
                 src,
+                Arc::clone(&memory_tracker),
             )?),
             ExecType::TypeStreamAgg => Box::new(StreamAggExecutor::new(
                 Arc::clone(&ctx),
                 src,
                 exec.take_aggregation(),
             )?),
-            ExecType::TypeTopN => {
-                Box::new(TopNExecutor::new(exec.take_topN(), Arc::clone(&ctx), src)?)
-            }
+            ExecType::TypeTopN => Box::new(TopNExecutor::new(
+                exec.take_topN(),
+                Arc::clone(&ctx),
+                src,
+                Arc::clone(&memory_tracker),
+            )?),
             ExecType::TypeLimit => Box::new(LimitExecutor::new(exec.take_limit(), src)),
         };
         src = curr;
@@ -19,9 +19,10 @@ use std::vec::IntoIter;
 use tipb::executor::TopN;
 use tipb::expression::ByItem;
 
-use coprocessor::codec::datum::Datum;
+use coprocessor::codec::datum::{self, Datum};
+use coprocessor::codec::mysql::Collation;
 use coprocessor::dag::expr::{EvalConfig, EvalContext, EvalWarnings, Expression};
-use coprocessor::Result;
+use coprocessor::{MemoryTracker, Result};
 
 use super::topn_heap::TopNHeap;
 use super::{Executor, ExecutorMetrics, ExprColumnRefVisitor, Row};
@@ -29,6 +30,7 @@ use super::{Executor, ExecutorMetrics, ExprColumnRefVisitor, Row};
 struct OrderBy {
     items: Arc<Vec<ByItem>>,
     exprs: Vec<Expression>,
+    collations: Vec<Collation>,
 }
 
 impl OrderBy {
@@ -37,16 +39,23 @@ impl OrderBy {
         for v in &mut order_by {
             exprs.push(Expression::build(ctx, v.take_expr())?);
         }
+        let collations = exprs
+            .iter()
+            .map(|expr| Collation::from_field_type(expr.get_tp()))
+            .collect();
         Ok(OrderBy {
             items: Arc::new(order_by),
             exprs,
+            collations,
         })
     }
 
+    /// Evaluates the values to sort by, strings are replaced by the sort keys of their
+    /// collations.
     fn eval(&self, ctx: &mut EvalContext, row: &[Datum]) -> Result<Vec<Datum>> {
         let mut res = Vec::with_capacity(self.exprs.len());
-        for expr in &self.exprs {
-            res.push(expr.eval(ctx, row)?);
+        for (expr, collation) in self.exprs.iter().zip(&self.collations) {
+            res.push(collation.sort_key_datum(expr.eval(ctx, row)?));
         }
         Ok(res)
     }
@@ -61,6 +70,7 @@ pub struct TopNExecutor {
     src: Box<Executor + Send>,
     limit: usize,
     first_collect: bool,
+    memory_tracker: Arc<MemoryTracker>,
 }
 
 impl TopNExecutor {
@@ -68,6 +78,7 @@ impl TopNExecutor {
         mut meta: TopN,
         eval_cfg: Arc<EvalConfig>,
         src: Box<Executor + Send>,
+        memory_tracker: Arc<MemoryTracker>,
     ) -> Result<TopNExecutor> {
         let order_by = meta.take_order_by().into_vec();
 
@@ -86,6 +97,7 @@ impl TopNExecutor {
             src,
             limit: meta.get_limit() as usize,
             first_collect: true,
+            memory_tracker,
         })
     }
 
@@ -106,6 +118,11 @@ impl TopNExecutor {
             let cols =
                 row.inflate_cols_with_offsets(&mut ctx.borrow_mut(), &self.related_cols_offset)?;
             let ob_values = self.order_by.eval(&mut ctx.borrow_mut(), &cols)?;
+            // Rows replacing the ones in a full heap are taken as the same size.
+            if heap.rows.len() < self.limit {
+                let size = row.data.value.len() + datum::approximate_size(&ob_values, false);
+                self.memory_tracker.consume(size)?;
+            }
             heap.try_add_row(row, ob_values, Arc::clone(&self.order_by.items))?;
         }
         let sort_rows = heap.into_sorted_vec()?;
@@ -439,13 +456,19 @@ pub mod test {
         let limit = 4;
         topn.set_limit(limit);
         // init topn executor
-        let mut topn_ect =
-            TopNExecutor::new(topn, Arc::new(EvalConfig::default()), Box::new(ts_ect)).unwrap();
+        let memory_tracker = Arc::new(MemoryTracker::default());
+        let mut topn_ect = TopNExecutor::new(
+            topn,
+            Arc::new(EvalConfig::default()),
+            Box::new(ts_ect),
+            Arc::clone(&memory_tracker),
+        ).unwrap();
         let mut topn_rows = Vec::with_capacity(limit as usize);
         while let Some(row) = topn_ect.next().unwrap() {
             topn_rows.push(row.take_origin());
         }
         assert_eq!(topn_rows.len(), limit as usize);
+        assert!(memory_tracker.consumed() > 0);
         let expect_row_handles = vec![1, 3, 2, 6];
         for (row, handle) in topn_rows.iter().zip(expect_row_handles) {
             assert_eq!(row.handle, handle);
@@ -496,6 +519,7 @@ pub mod test {
             topn,
             Arc::new(EvalConfig::default()),
             Box::new(TableScanExecutor::new(table_scan, key_ranges, snap, false).unwrap()),
+            Arc::new(MemoryTracker::default()),
         ).unwrap();
         assert!(topn_ect.next().unwrap().is_none());
     }
@@ -18,7 +18,7 @@ use std::{f64, i64};
 use super::{Error, EvalContext, Result, ScalarFunc};
 use chrono::TimeZone;
 
-use coprocessor::codec::mysql::{Decimal, Duration, Json, Time};
+use coprocessor::codec::mysql::{Collation, Decimal, Duration, Json, Time};
 use coprocessor::codec::{datum, mysql, Datum};
 use coprocessor::dag::expr::Expression;
 
@@ -77,8 +77,9 @@ impl ScalarFunc {
         row: &[Datum],
         op: CmpOp,
     ) -> Result<Option<i64>> {
+        let collation = Collation::from_field_type(self.children[0].get_tp());
         let e = |i: usize| self.children[i].eval_string(ctx, row);
-        do_compare(e, op, |l, r| Ok(l.cmp(&r)))
+        do_compare(e, op, |l, r| Ok(collation.compare(&l, &r)))
     }
 
     pub fn compare_time(
@@ -302,7 +303,12 @@ impl ScalarFunc {
     }
 
     pub fn in_string(&self, ctx: &mut EvalContext, row: &[Datum]) -> Result<Option<i64>> {
-        do_in(self, |v| v.eval_string(ctx, row), |l, r| Ok(l.cmp(r)))
+        let collation = Collation::from_field_type(self.children[0].get_tp());
+        do_in(
+            self,
+            |v| v.eval_string(ctx, row),
+            |l, r| Ok(collation.compare(l, r)),
+        )
     }
 
     pub fn in_json(&self, ctx: &mut EvalContext, row: &[Datum]) -> Result<Option<i64>> {
@@ -972,4 +978,37 @@ mod test {
             assert_eq!(err.code(), ERR_TRUNCATE_WRONG_VALUE);
         }
     }
+
+    #[test]
+    fn test_compare_string_with_collation() {
+        let cases: Vec<(ScalarFuncSig, &[u8], &[u8], i32, i64)> = vec![
+            (ScalarFuncSig::EQString, b"abc", b"ABC", 33, 0),
+            (ScalarFuncSig::EQString, b"abc", b"ABC", -63, 0),
+            (ScalarFuncSig::EQString, b"abc", b"ABC ", -33, 1),
+            (ScalarFuncSig::EQString, b"abc", b"abc ", -46, 1),
+            (ScalarFuncSig::EQString, b"abc", b"ABC", -46, 0),
+            (ScalarFuncSig::LTString, b"a", b"B", -45, 1),
+            (ScalarFuncSig::LTString, b"a", b"B", 45, 0),
+            (ScalarFuncSig::InString, b"abc", b"ABC", -45, 1),
+            (ScalarFuncSig::InString, b"abc", b"ABC", 45, 0),
+        ];
+        let mut ctx = EvalContext::default();
+        for (sig, lhs, rhs, collate, exp) in cases {
+            let children: Vec<Expr> = [lhs, rhs]
+                .iter()
+                .map(|s| {
+                    let mut expr = datum_expr(Datum::Bytes(s.to_vec()));
+                    expr.mut_field_type().set_collate(collate);
+                    expr
+                })
+                .collect();
+            let mut expr = Expr::new();
+            expr.set_tp(ExprType::ScalarFunc);
+            expr.set_sig(sig);
+            expr.set_children(RepeatedField::from_vec(children));
+            let e = Expression::build(&mut ctx, expr).unwrap();
+            let res = e.eval(&mut ctx, &[]).unwrap();
+            assert_eq!(res, Datum::I64(exp), "{:?} {:?}", sig, collate);
+        }
+    }
 }
@@ -15,6 +15,7 @@ use super::{EvalContext, Result, ScalarFunc};
 use chrono::offset::TimeZone;
 use chrono::Datelike;
 use coprocessor::codec::error::Error;
+use coprocessor::codec::mysql::time::{WeekdayExtension, MONTH_NAMES};
 use coprocessor::codec::mysql::{self, Time};
 use coprocessor::codec::Datum;
 use std::borrow::Cow;
@@ -120,11 +121,163 @@ impl ScalarFunc {
         };
         Error::handle_invalid_time_error(ctx, e).map(|_| Ok(None))?
     }
+
+    /// Evaluates the time argument, a zero time is handled as an incorrect datetime value.
+    #[inline]
+    fn eval_non_zero_time<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<Cow<'a, Time>>> {
+        let t = try_opt!(self.children[0].eval_time(ctx, row));
+        if t.is_zero() {
+            return Error::handle_invalid_time_error(
+                ctx,
+                Error::incorrect_datetime_value(&format!("{}", t)),
+            ).map(|_| None);
+        }
+        Ok(Some(t))
+    }
+
+    #[inline]
+    pub fn day_of_month<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.children[0].eval_time(ctx, row));
+        if t.is_zero() {
+            return Ok(Some(0));
+        }
+        Ok(Some(i64::from(t.get_time().day())))
+    }
+
+    #[inline]
+    pub fn day_of_week<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.eval_non_zero_time(ctx, row));
+        let day = t.get_time().weekday().number_from_sunday();
+        Ok(Some(i64::from(day)))
+    }
+
+    #[inline]
+    pub fn day_of_year<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.eval_non_zero_time(ctx, row));
+        Ok(Some(i64::from(t.get_time().ordinal())))
+    }
+
+    #[inline]
+    pub fn week_day<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.eval_non_zero_time(ctx, row));
+        let day = t.get_time().weekday().num_days_from_monday();
+        Ok(Some(i64::from(day)))
+    }
+
+    #[inline]
+    pub fn quarter<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.children[0].eval_time(ctx, row));
+        if t.is_zero() {
+            return Ok(Some(0));
+        }
+        Ok(Some(i64::from((t.get_time().month() + 2) / 3)))
+    }
+
+    #[inline]
+    pub fn to_days<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let t = try_opt!(self.eval_non_zero_time(ctx, row));
+        // MySQL counts the days from year 0, which is a leap year of 366 days,
+        // while `num_days_from_ce` returns 1 for 0001-01-01.
+        Ok(Some(i64::from(t.get_time().num_days_from_ce()) + 365))
+    }
+
+    #[inline]
+    pub fn day_name<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<Cow<'a, [u8]>>> {
+        let t = try_opt!(self.eval_non_zero_time(ctx, row));
+        let name = t.get_time().weekday().name();
+        Ok(Some(Cow::Borrowed(name.as_bytes())))
+    }
+
+    #[inline]
+    pub fn month_name<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<Cow<'a, [u8]>>> {
+        let t = try_opt!(self.children[0].eval_time(ctx, row));
+        if t.is_zero() {
+            return Ok(None);
+        }
+        let name = MONTH_NAMES[t.get_time().month() as usize - 1];
+        Ok(Some(Cow::Borrowed(name.as_bytes())))
+    }
+
+    #[inline]
+    pub fn hour<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let dur = try_opt!(self.children[0].eval_duration(ctx, row));
+        Ok(Some(dur.hours() as i64))
+    }
+
+    #[inline]
+    pub fn minute<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let dur = try_opt!(self.children[0].eval_duration(ctx, row));
+        Ok(Some(dur.minutes() as i64))
+    }
+
+    #[inline]
+    pub fn second<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let dur = try_opt!(self.children[0].eval_duration(ctx, row));
+        Ok(Some(dur.secs() as i64))
+    }
+
+    #[inline]
+    pub fn micro_second<'a, 'b: 'a>(
+        &'b self,
+        ctx: &mut EvalContext,
+        row: &'a [Datum],
+    ) -> Result<Option<i64>> {
+        let dur = try_opt!(self.children[0].eval_duration(ctx, row));
+        Ok(Some(i64::from(dur.micro_secs())))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use coprocessor::codec::mysql::Time;
+    use coprocessor::codec::mysql::{Duration, Time};
     use coprocessor::codec::Datum;
     use coprocessor::dag::expr::test::{datum_expr, scalar_func_expr};
     use coprocessor::dag::expr::*;
@@ -368,4 +521,109 @@ mod test {
         let op = Expression::build(&mut ctx, f).unwrap();
         op.eval(&mut ctx, &[]).unwrap_err();
     }
+
+    #[test]
+    fn test_day_of_functions() {
+        let tests = vec![
+            (ScalarFuncSig::DayOfMonth, "2018-02-28 10:10:10", 28i64),
+            (ScalarFuncSig::DayOfMonth, "0000-00-00 00:00:00", 0),
+            (ScalarFuncSig::DayOfWeek, "2018-08-19 10:10:10", 1),
+            (ScalarFuncSig::DayOfWeek, "2018-08-25 10:10:10", 7),
+            (ScalarFuncSig::DayOfYear, "2018-02-01", 32),
+            (ScalarFuncSig::DayOfYear, "2016-12-31", 366),
+            (ScalarFuncSig::WeekDay, "2018-08-20 10:10:10", 0),
+            (ScalarFuncSig::WeekDay, "2018-08-19 10:10:10", 6),
+            (ScalarFuncSig::Quarter, "2018-03-31", 1),
+            (ScalarFuncSig::Quarter, "2018-04-01", 2),
+            (ScalarFuncSig::Quarter, "2018-12-01", 4),
+            (ScalarFuncSig::Quarter, "0000-00-00 00:00:00", 0),
+            (ScalarFuncSig::ToDays, "0001-01-01", 366),
+            (ScalarFuncSig::ToDays, "2007-10-07", 733321),
+        ];
+        let mut ctx = EvalContext::default();
+        for (sig, arg, exp) in tests {
+            let arg = datum_expr(Datum::Time(Time::parse_utc_datetime(arg, 6).unwrap()));
+            let f = scalar_func_expr(sig, &[arg]);
+            let op = Expression::build(&mut ctx, f).unwrap();
+            let got = op.eval(&mut ctx, &[]).unwrap();
+            assert_eq!(got, Datum::I64(exp), "{:?}", sig);
+        }
+
+        for &sig in &[
+            ScalarFuncSig::DayOfWeek,
+            ScalarFuncSig::DayOfYear,
+            ScalarFuncSig::WeekDay,
+            ScalarFuncSig::ToDays,
+        ] {
+            // test NULL case
+            let input = datum_expr(Datum::Null);
+            let f = scalar_func_expr(sig, &[input]);
+            let op = Expression::build(&mut ctx, f).unwrap();
+            let got = op.eval(&mut ctx, &[]).unwrap();
+            assert_eq!(got, Datum::Null);
+
+            // test zero case
+            let arg = datum_expr(Datum::Time(
+                Time::parse_utc_datetime("0000-00-00 00:00:00", 6).unwrap(),
+            ));
+            let f = scalar_func_expr(sig, &[arg]);
+            let op = Expression::build(&mut ctx, f).unwrap();
+            op.eval(&mut ctx, &[]).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_day_name_and_month_name() {
+        let tests = vec![
+            (ScalarFuncSig::DayName, "2018-08-19 10:10:10", Some("Sunday")),
+            (ScalarFuncSig::DayName, "2018-08-22", Some("Wednesday")),
+            (ScalarFuncSig::MonthName, "2018-01-31", Some("January")),
+            (ScalarFuncSig::MonthName, "2018-12-01 10:10:10", Some("December")),
+            (ScalarFuncSig::MonthName, "0000-00-00 00:00:00", None),
+        ];
+        let mut ctx = EvalContext::default();
+        for (sig, arg, exp) in tests {
+            let arg = datum_expr(Datum::Time(Time::parse_utc_datetime(arg, 6).unwrap()));
+            let exp = match exp {
+                Some(s) => Datum::Bytes(s.as_bytes().to_vec()),
+                None => Datum::Null,
+            };
+            let f = scalar_func_expr(sig, &[arg]);
+            let op = Expression::build(&mut ctx, f).unwrap();
+            let got = op.eval(&mut ctx, &[]).unwrap();
+            assert_eq!(got, exp);
+        }
+
+        // test zero case
+        let arg = datum_expr(Datum::Time(
+            Time::parse_utc_datetime("0000-00-00 00:00:00", 6).unwrap(),
+        ));
+        let f = scalar_func_expr(ScalarFuncSig::DayName, &[arg]);
+        let op = Expression::build(&mut ctx, f).unwrap();
+        op.eval(&mut ctx, &[]).unwrap_err();
+    }
+
+    #[test]
+    fn test_duration_parts() {
+        let tests = vec![
+            ("11:12:13.123456", 11i64, 12i64, 13i64, 123456i64),
+            ("-838:59:59", 838, 59, 59, 0),
+            ("00:00:00", 0, 0, 0, 0),
+        ];
+        let mut ctx = EvalContext::default();
+        for (arg, hour, minute, second, micro_second) in tests {
+            let arg = Datum::Dur(Duration::parse(arg.as_bytes(), 6).unwrap());
+            for &(sig, exp) in &[
+                (ScalarFuncSig::Hour, hour),
+                (ScalarFuncSig::Minute, minute),
+                (ScalarFuncSig::Second, second),
+                (ScalarFuncSig::MicroSecond, micro_second),
+            ] {
+                let f = scalar_func_expr(sig, &[datum_expr(arg.clone())]);
+                let op = Expression::build(&mut ctx, f).unwrap();
+                let got = op.eval(&mut ctx, &[]).unwrap();
+                assert_eq!(got, Datum::I64(exp), "{:?}", sig);
+            }
+        }
+    }
 }
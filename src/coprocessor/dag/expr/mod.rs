@@ -96,7 +96,7 @@ impl Expression {
     }
 
     #[inline]
-    fn get_tp(&self) -> &FieldType {
+    pub fn get_tp(&self) -> &FieldType {
         match *self {
             Expression::Constant(ref c) => &c.tp,
             Expression::ColumnRef(ref c) => &c.tp,
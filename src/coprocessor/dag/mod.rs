@@ -14,6 +14,7 @@
 pub mod dag;
 pub mod executor;
 pub mod expr;
+pub mod ranges;
 
 pub use self::dag::DAGContext;
 pub use self::executor::{ScanOn, Scanner};
@@ -0,0 +1,456 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+
+use kvproto::coprocessor::KeyRange;
+use tipb::executor::{self, ExecType};
+use tipb::expression::{Expr, ExprType, ScalarFuncSig};
+use tipb::schema::ColumnInfo;
+
+use coprocessor::codec::datum::{self, Datum};
+use coprocessor::codec::{mysql, table};
+use coprocessor::util;
+use util::codec::number;
+
+/// The max number of point ranges an IN-list is allowed to be expanded into.
+/// Larger lists are left to the `Selection` executor.
+pub const MAX_IN_LIST_RANGES: usize = 1024;
+
+/// The default escape character used by `LIKE`.
+const DEFAULT_ESCAPE: u8 = b'\\';
+
+/// `tighten_ranges` tries to narrow down `ranges` by using the conditions of the
+/// `Selection` executor directly following the scan executor. It handles:
+///
+/// * `pk IN (c1, c2, ...)` for table scans on integer handles;
+/// * `col IN (c1, c2, ...)` for index scans on the first index column;
+/// * `col LIKE 'prefix%'` for index scans on the first index column.
+///
+/// The returned ranges are always a subset of the given ones. If no condition can
+/// be used, the given ranges are returned unchanged.
+pub fn tighten_ranges(execs: &[executor::Executor], ranges: Vec<KeyRange>) -> Vec<KeyRange> {
+    if execs.len() < 2 || execs[1].get_tp() != ExecType::TypeSelection {
+        return ranges;
+    }
+    let conditions = execs[1].get_selection().get_conditions();
+    let candidates = match execs[0].get_tp() {
+        ExecType::TypeTableScan => {
+            let table_id = match ranges.first().map(|r| table::decode_table_id(r.get_start())) {
+                Some(Ok(id)) => id,
+                _ => return ranges,
+            };
+            let columns = execs[0].get_tbl_scan().get_columns();
+            conditions
+                .iter()
+                .filter_map(|cond| build_handle_ranges(table_id, columns, cond))
+                .min_by_key(|r| r.len())
+        }
+        ExecType::TypeIndexScan => {
+            let meta = execs[0].get_idx_scan();
+            conditions
+                .iter()
+                .filter_map(|cond| {
+                    build_index_ranges(
+                        meta.get_table_id(),
+                        meta.get_index_id(),
+                        meta.get_columns(),
+                        cond,
+                    )
+                })
+                .min_by_key(|r| r.len())
+        }
+        _ => None,
+    };
+    match candidates {
+        Some(candidates) => intersect_ranges(&ranges, candidates),
+        None => ranges,
+    }
+}
+
+/// Builds point ranges for `pk IN (...)`, returns `None` if `cond` can't be used.
+fn build_handle_ranges(
+    table_id: i64,
+    columns: &[ColumnInfo],
+    cond: &Expr,
+) -> Option<Vec<KeyRange>> {
+    if cond.get_tp() != ExprType::ScalarFunc
+        || cond.get_sig() != ScalarFuncSig::InInt
+        || cond.get_children().is_empty()
+    {
+        return None;
+    }
+    let col = column_of(columns, &cond.get_children()[0])?;
+    if !col.get_pk_handle() {
+        return None;
+    }
+    let unsigned = mysql::has_unsigned_flag(col.get_flag() as u64);
+    let consts = &cond.get_children()[1..];
+    if consts.len() > MAX_IN_LIST_RANGES {
+        return None;
+    }
+    let mut ranges = Vec::with_capacity(consts.len());
+    for c in consts {
+        let handle = match int_const(c, unsigned)? {
+            Some(Datum::I64(i)) => i,
+            Some(Datum::U64(u)) => u as i64,
+            // NULL and out of range values never match.
+            _ => continue,
+        };
+        ranges.push(point_range(table::encode_row_key(table_id, handle)));
+    }
+    Some(ranges)
+}
+
+/// Builds ranges for `IN (...)` or `LIKE 'prefix%'` on the first index column,
+/// returns `None` if `cond` can't be used.
+fn build_index_ranges(
+    table_id: i64,
+    index_id: i64,
+    columns: &[ColumnInfo],
+    cond: &Expr,
+) -> Option<Vec<KeyRange>> {
+    let children = cond.get_children();
+    if cond.get_tp() != ExprType::ScalarFunc || children.is_empty() {
+        return None;
+    }
+    let first = columns.first()?;
+    if first.get_pk_handle()
+        || column_of(columns, &children[0])?.get_column_id() != first.get_column_id()
+    {
+        return None;
+    }
+    let encode = |d: Datum| -> Option<Vec<u8>> {
+        let encoded = datum::encode_key(&[d]).ok()?;
+        Some(table::encode_index_seek_key(table_id, index_id, &encoded))
+    };
+    match cond.get_sig() {
+        ScalarFuncSig::InInt | ScalarFuncSig::InString => {
+            let consts = &children[1..];
+            if consts.len() > MAX_IN_LIST_RANGES {
+                return None;
+            }
+            let unsigned = mysql::has_unsigned_flag(first.get_flag() as u64);
+            let mut ranges = Vec::with_capacity(consts.len());
+            for c in consts {
+                let d = if cond.get_sig() == ScalarFuncSig::InInt {
+                    int_const(c, unsigned)?
+                } else {
+                    bytes_const(c)?.map(Datum::Bytes)
+                };
+                let d = match d {
+                    Some(d) => d,
+                    None => continue,
+                };
+                ranges.push(point_range(encode(d)?));
+            }
+            Some(ranges)
+        }
+        ScalarFuncSig::LikeSig if children.len() == 3 => {
+            let pattern = bytes_const(&children[1])??;
+            let escape = match int_const(&children[2], false)? {
+                Some(Datum::I64(e)) if e >= 0 && e <= i64::from(u8::max_value()) => e as u8,
+                _ => DEFAULT_ESCAPE,
+            };
+            let (prefix, exact) = like_prefix(&pattern, escape);
+            if exact {
+                return Some(vec![point_range(encode(Datum::Bytes(prefix))?)]);
+            }
+            if prefix.is_empty() {
+                return None;
+            }
+            let mut next = prefix.clone();
+            util::convert_to_prefix_next(&mut next);
+            let mut range = KeyRange::new();
+            range.set_start(encode(Datum::Bytes(prefix))?);
+            range.set_end(encode(Datum::Bytes(next))?);
+            Some(vec![range])
+        }
+        _ => None,
+    }
+}
+
+/// Returns the literal prefix of a `LIKE` pattern and whether the pattern
+/// contains no wildcard at all.
+fn like_prefix(pattern: &[u8], escape: u8) -> (Vec<u8>, bool) {
+    let mut prefix = Vec::with_capacity(pattern.len());
+    let mut iter = pattern.iter();
+    while let Some(&c) = iter.next() {
+        match c {
+            b'%' | b'_' => return (prefix, false),
+            c if c == escape => match iter.next() {
+                Some(&next) => prefix.push(next),
+                None => prefix.push(c),
+            },
+            c => prefix.push(c),
+        }
+    }
+    (prefix, true)
+}
+
+fn column_of<'a>(columns: &'a [ColumnInfo], expr: &Expr) -> Option<&'a ColumnInfo> {
+    if expr.get_tp() != ExprType::ColumnRef {
+        return None;
+    }
+    let offset = number::decode_i64(&mut expr.get_val()).ok()?;
+    if offset < 0 {
+        return None;
+    }
+    columns.get(offset as usize)
+}
+
+/// Decodes an integer constant. The outer `None` means `expr` is not a usable
+/// constant, while the inner `None` means the constant can never match a column
+/// with the given signedness.
+fn int_const(expr: &Expr, unsigned: bool) -> Option<Option<Datum>> {
+    match expr.get_tp() {
+        ExprType::Null => Some(None),
+        ExprType::Int64 => {
+            let i = number::decode_i64(&mut expr.get_val()).ok()?;
+            if !unsigned {
+                Some(Some(Datum::I64(i)))
+            } else if i >= 0 {
+                Some(Some(Datum::U64(i as u64)))
+            } else {
+                Some(None)
+            }
+        }
+        ExprType::Uint64 => {
+            let u = number::decode_u64(&mut expr.get_val()).ok()?;
+            if unsigned {
+                Some(Some(Datum::U64(u)))
+            } else if u <= i64::max_value() as u64 {
+                Some(Some(Datum::I64(u as i64)))
+            } else {
+                Some(None)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn bytes_const(expr: &Expr) -> Option<Option<Vec<u8>>> {
+    match expr.get_tp() {
+        ExprType::Null => Some(None),
+        ExprType::String | ExprType::Bytes => Some(Some(expr.get_val().to_vec())),
+        _ => None,
+    }
+}
+
+fn point_range(start: Vec<u8>) -> KeyRange {
+    let mut end = start.clone();
+    util::convert_to_prefix_next(&mut end);
+    let mut range = KeyRange::new();
+    range.set_start(start);
+    range.set_end(end);
+    range
+}
+
+/// Intersects `candidates` with `ranges`. The result is sorted and contains no
+/// overlapping ranges.
+fn intersect_ranges(ranges: &[KeyRange], candidates: Vec<KeyRange>) -> Vec<KeyRange> {
+    let mut parts = Vec::with_capacity(candidates.len());
+    for c in &candidates {
+        for r in ranges {
+            let start = cmp::max(c.get_start(), r.get_start());
+            let end = cmp::min(c.get_end(), r.get_end());
+            if start < end {
+                parts.push((start.to_vec(), end.to_vec()));
+            }
+        }
+    }
+    parts.sort();
+
+    let mut res: Vec<KeyRange> = Vec::with_capacity(parts.len());
+    for (start, end) in parts {
+        if let Some(last) = res.last_mut() {
+            if last.get_end() >= start.as_slice() {
+                if last.get_end() < end.as_slice() {
+                    last.set_end(end);
+                }
+                continue;
+            }
+        }
+        let mut range = KeyRange::new();
+        range.set_start(start);
+        range.set_end(end);
+        res.push(range);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use coprocessor::dag::expr::test::{col_expr, datum_expr, scalar_func_expr};
+    use tipb::executor::{IndexScan, Selection, TableScan};
+
+    fn new_range(start: Vec<u8>, end: Vec<u8>) -> KeyRange {
+        let mut range = KeyRange::new();
+        range.set_start(start);
+        range.set_end(end);
+        range
+    }
+
+    fn new_col(id: i64, pk_handle: bool) -> ColumnInfo {
+        let mut col = ColumnInfo::new();
+        col.set_column_id(id);
+        col.set_pk_handle(pk_handle);
+        col
+    }
+
+    fn new_selection(cond: Expr) -> executor::Executor {
+        let mut sel = Selection::new();
+        sel.mut_conditions().push(cond);
+        let mut exec = executor::Executor::new();
+        exec.set_tp(ExecType::TypeSelection);
+        exec.set_selection(sel);
+        exec
+    }
+
+    #[test]
+    fn test_like_prefix() {
+        let cases: Vec<(&[u8], &[u8], bool)> = vec![
+            (b"abc%", b"abc", false),
+            (b"ab_c", b"ab", false),
+            (b"abc", b"abc", true),
+            (b"%abc", b"", false),
+            (b"a\\%b%", b"a%b", false),
+            (b"a\\", b"a\\", true),
+        ];
+        for (pattern, prefix, exact) in cases {
+            assert_eq!(like_prefix(pattern, DEFAULT_ESCAPE), (prefix.to_vec(), exact));
+        }
+    }
+
+    #[test]
+    fn test_tighten_table_ranges() {
+        let table_id = 10;
+        let mut scan = TableScan::new();
+        scan.mut_columns().push(new_col(1, true));
+        scan.mut_columns().push(new_col(2, false));
+        let mut exec = executor::Executor::new();
+        exec.set_tp(ExecType::TypeTableScan);
+        exec.set_tbl_scan(scan);
+
+        let cond = scalar_func_expr(
+            ScalarFuncSig::InInt,
+            &[
+                col_expr(0),
+                datum_expr(Datum::I64(3)),
+                datum_expr(Datum::I64(100)),
+                datum_expr(Datum::Null),
+                datum_expr(Datum::I64(1)),
+            ],
+        );
+        let execs = vec![exec, new_selection(cond)];
+        let ranges = vec![new_range(
+            table::encode_row_key(table_id, 0),
+            table::encode_row_key(table_id, 10),
+        )];
+        let got = tighten_ranges(&execs, ranges);
+        let expect: Vec<_> = [1, 3]
+            .iter()
+            .map(|h| point_range(table::encode_row_key(table_id, *h)))
+            .collect();
+        assert_eq!(got, expect);
+
+        // Conditions on non-handle columns can't be used.
+        let cond = scalar_func_expr(
+            ScalarFuncSig::InInt,
+            &[col_expr(1), datum_expr(Datum::I64(3))],
+        );
+        let execs = vec![execs[0].clone(), new_selection(cond)];
+        let ranges = vec![new_range(
+            table::encode_row_key(table_id, 0),
+            table::encode_row_key(table_id, 10),
+        )];
+        assert_eq!(tighten_ranges(&execs, ranges.clone()), ranges);
+    }
+
+    #[test]
+    fn test_tighten_index_ranges() {
+        let (table_id, index_id) = (10, 2);
+        let mut scan = IndexScan::new();
+        scan.set_table_id(table_id);
+        scan.set_index_id(index_id);
+        scan.mut_columns().push(new_col(2, false));
+        let mut exec = executor::Executor::new();
+        exec.set_tp(ExecType::TypeIndexScan);
+        exec.set_idx_scan(scan);
+
+        let seek_key = |d: Datum| {
+            table::encode_index_seek_key(table_id, index_id, &datum::encode_key(&[d]).unwrap())
+        };
+        let full = vec![new_range(
+            table::encode_index_seek_key(table_id, index_id, &[]),
+            table::encode_index_seek_key(table_id, index_id + 1, &[]),
+        )];
+
+        let cond = scalar_func_expr(
+            ScalarFuncSig::LikeSig,
+            &[
+                col_expr(0),
+                datum_expr(Datum::Bytes(b"ab%".to_vec())),
+                datum_expr(Datum::I64(i64::from(DEFAULT_ESCAPE))),
+            ],
+        );
+        let execs = vec![exec.clone(), new_selection(cond)];
+        let got = tighten_ranges(&execs, full.clone());
+        assert_eq!(
+            got,
+            vec![new_range(
+                seek_key(Datum::Bytes(b"ab".to_vec())),
+                seek_key(Datum::Bytes(b"ac".to_vec())),
+            )]
+        );
+
+        let cond = scalar_func_expr(
+            ScalarFuncSig::InString,
+            &[
+                col_expr(0),
+                datum_expr(Datum::Bytes(b"b".to_vec())),
+                datum_expr(Datum::Bytes(b"a".to_vec())),
+            ],
+        );
+        let execs = vec![exec, new_selection(cond)];
+        let got = tighten_ranges(&execs, full);
+        assert_eq!(
+            got,
+            vec![
+                point_range(seek_key(Datum::Bytes(b"a".to_vec()))),
+                point_range(seek_key(Datum::Bytes(b"b".to_vec()))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_intersect_ranges() {
+        let ranges = vec![
+            new_range(b"a".to_vec(), b"c".to_vec()),
+            new_range(b"e".to_vec(), b"g".to_vec()),
+        ];
+        let candidates = vec![
+            new_range(b"f".to_vec(), b"h".to_vec()),
+            new_range(b"b".to_vec(), b"f".to_vec()),
+            new_range(b"x".to_vec(), b"z".to_vec()),
+        ];
+        assert_eq!(
+            intersect_ranges(&ranges, candidates),
+            vec![
+                new_range(b"b".to_vec(), b"c".to_vec()),
+                new_range(b"e".to_vec(), b"g".to_vec()),
+            ]
+        );
+    }
+}
@@ -11,7 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant as StdInstant};
 
 use futures::sync::mpsc;
 use futures::{future, stream, Future, Stream};
@@ -23,13 +24,23 @@ use tipb::checksum::{ChecksumRequest, ChecksumScanOn};
 use tipb::executor::ExecType;
 use tipb::select::DAGRequest;
 
+use server::quota_limiter;
 use server::readpool::{self, ReadPool};
 use server::Config;
 use storage::{self, Engine};
+use util::futurepool::Turn;
+use util::memory;
+use util::time::thread_cpu_time;
+use util::timer::GLOBAL_TIMER_HANDLE;
 use util::Either;
 
+use coprocessor::cache::{self, CacheKey, ResultCache};
+use coprocessor::checksum::ChecksumLimiter;
 use coprocessor::dag::executor::ExecutorMetrics;
+use coprocessor::memory::{MemoryQuota, MemoryTracker};
 use coprocessor::metrics::*;
+use coprocessor::statistics::analyze::AnalyzeLimits;
+use coprocessor::stream_ranges::{RecordSentRanges, SessionKey, StreamRanges, StreamSession};
 use coprocessor::tracker::Tracker;
 use coprocessor::util as cop_util;
 use coprocessor::*;
@@ -37,6 +48,10 @@ use coprocessor::*;
 const OUTDATED_ERROR_MSG: &str = "request outdated.";
 const BUSY_ERROR_MSG: &str = "server is busy (coprocessor full).";
 
+// A unary request yields the read pool thread after running for this long, so that a huge
+// request won't block small ones behind it.
+const UNARY_REQUEST_TIME_SLICE_MILLIS: u64 = 10;
+
 pub struct Endpoint<E: Engine> {
     engine: E,
     read_pool: ReadPool<ReadPoolContext>,
@@ -45,6 +60,12 @@ pub struct Endpoint<E: Engine> {
     stream_batch_row_limit: usize,
     stream_channel_size: usize,
     max_handle_duration: Duration,
+    analyze_limits: AnalyzeLimits,
+    checksum_limiter: ChecksumLimiter,
+    stream_ranges: Option<Arc<StreamRanges>>,
+    result_cache: Option<Arc<ResultCache>>,
+    request_memory_quota: usize,
+    memory_quota: Option<Arc<MemoryQuota>>,
 }
 
 impl<E: Engine> Clone for Endpoint<E> {
@@ -52,6 +73,10 @@ impl<E: Engine> Clone for Endpoint<E> {
         Self {
             engine: self.engine.clone(),
             read_pool: self.read_pool.clone(),
+            stream_ranges: self.stream_ranges.clone(),
+            result_cache: self.result_cache.clone(),
+            checksum_limiter: self.checksum_limiter.clone(),
+            memory_quota: self.memory_quota.clone(),
             ..*self
         }
     }
@@ -61,6 +86,27 @@ impl<E: Engine> ::util::AssertSend for Endpoint<E> {}
 
 impl<E: Engine> Endpoint<E> {
     pub fn new(cfg: &Config, engine: E, read_pool: ReadPool<ReadPoolContext>) -> Self {
+        let stream_ranges = if cfg.end_point_stream_dedup_capacity > 0 {
+            Some(Arc::new(StreamRanges::new(
+                cfg.end_point_stream_dedup_capacity,
+                cfg.end_point_request_max_handle_duration.0,
+            )))
+        } else {
+            None
+        };
+        let result_cache = if cfg.end_point_result_cache_capacity > 0 {
+            Some(Arc::new(ResultCache::new(
+                cfg.end_point_result_cache_capacity,
+                cfg.end_point_result_cache_max_size.0 as usize,
+            )))
+        } else {
+            None
+        };
+        let memory_quota = if cfg.end_point_memory_quota.0 > 0 {
+            Some(Arc::new(MemoryQuota::new(cfg.end_point_memory_quota.0 as usize)))
+        } else {
+            None
+        };
         Self {
             engine,
             read_pool,
@@ -69,6 +115,20 @@ impl<E: Engine> Endpoint<E> {
             stream_batch_row_limit: cfg.end_point_stream_batch_row_limit,
             stream_channel_size: cfg.end_point_stream_channel_size,
             max_handle_duration: cfg.end_point_request_max_handle_duration.0,
+            analyze_limits: AnalyzeLimits {
+                max_sample_size: cfg.end_point_analyze_max_sample_size,
+                max_cmsketch_depth: cfg.end_point_analyze_max_cmsketch_depth,
+                max_cmsketch_width: cfg.end_point_analyze_max_cmsketch_width,
+            },
+            checksum_limiter: ChecksumLimiter::new(
+                cfg.end_point_checksum_max_bytes_per_sec.0,
+                cfg.end_point_checksum_request_max_bytes_per_sec.0,
+                cfg.end_point_checksum_max_concurrency,
+            ),
+            stream_ranges,
+            result_cache,
+            request_memory_quota: cfg.end_point_request_memory_quota.0 as usize,
+            memory_quota,
         }
     }
 
@@ -80,22 +140,33 @@ impl<E: Engine> Endpoint<E> {
         peer: Option<String>,
         is_streaming: bool,
     ) -> Result<(RequestHandlerBuilder<E::Snap>, ReqContext)> {
-        let (context, data, ranges) = (
+        let (context, data, mut ranges) = (
             req.take_context(),
             req.take_data(),
             req.take_ranges().to_vec(),
         );
 
+        // The body is cached with its start ts cleared, so that the same request of different
+        // transactions hits the result of the same data version.
+        let use_cache = self.result_cache.is_some() && !is_streaming;
+        let mut cache_key = None;
+
         let mut is = CodedInputStream::from_bytes(&data);
         is.set_recursion_limit(self.recursion_limit);
 
         let req_ctx: ReqContext;
-        let builder: RequestHandlerBuilder<E::Snap>;
+        let mut builder: RequestHandlerBuilder<E::Snap>;
 
         match req.get_tp() {
             REQ_TYPE_DAG => {
                 let mut dag = DAGRequest::new();
                 box_try!(dag.merge_from(&mut is));
+                if use_cache {
+                    let mut body = dag.clone();
+                    body.set_start_ts(0);
+                    let body = box_try!(body.write_to_bytes());
+                    cache_key = Some(CacheKey::new(&context, REQ_TYPE_DAG, &body, &ranges));
+                }
                 let mut table_scan = false;
                 let mut is_desc_scan = false;
                 if let Some(scan) = dag.get_executors().iter().next() {
@@ -106,6 +177,15 @@ impl<E: Engine> Endpoint<E> {
                         is_desc_scan = scan.get_idx_scan().get_desc();
                     }
                 }
+                // A retried streaming request skips the ranges sent by the previous attempts.
+                let session = match self.stream_ranges {
+                    Some(ref stream_ranges) if is_streaming => {
+                        let key = SessionKey::new(&context, dag.get_start_ts(), &data);
+                        ranges = stream_ranges.trim(&key, ranges);
+                        Some(StreamSession::new(Arc::clone(stream_ranges), key))
+                    }
+                    _ => None,
+                };
                 req_ctx = ReqContext::new(
                     make_tag(table_scan),
                     context,
@@ -114,7 +194,11 @@ impl<E: Engine> Endpoint<E> {
                     peer,
                     Some(is_desc_scan),
                     Some(dag.get_start_ts()),
-                );
+                ).with_stream_session(session)
+                    .with_memory_tracker(MemoryTracker::new(
+                        self.request_memory_quota,
+                        self.memory_quota.clone(),
+                    ));
                 let batch_row_limit = self.get_batch_row_limit(is_streaming);
                 builder = box move |snap, req_ctx: &_| {
                     // See rust-lang#41078 to know why we have `: &_` here.
@@ -125,6 +209,13 @@ impl<E: Engine> Endpoint<E> {
             REQ_TYPE_ANALYZE => {
                 let mut analyze = AnalyzeReq::new();
                 box_try!(analyze.merge_from(&mut is));
+                self.analyze_limits.apply(&mut analyze);
+                if use_cache {
+                    let mut body = analyze.clone();
+                    body.set_start_ts(0);
+                    let body = box_try!(body.write_to_bytes());
+                    cache_key = Some(CacheKey::new(&context, REQ_TYPE_ANALYZE, &body, &ranges));
+                }
                 let table_scan = analyze.get_tp() == AnalyzeType::TypeColumn;
                 req_ctx = ReqContext::new(
                     make_tag(table_scan),
@@ -134,7 +225,7 @@ impl<E: Engine> Endpoint<E> {
                     peer,
                     None,
                     Some(analyze.get_start_ts()),
-                );
+                ).with_source(quota_limiter::SOURCE_ANALYZE);
                 builder = box move |snap, req_ctx: &_| {
                     statistics::analyze::AnalyzeContext::new(analyze, ranges, snap, req_ctx)
                         .map(|h| h.into_boxed())
@@ -143,6 +234,12 @@ impl<E: Engine> Endpoint<E> {
             REQ_TYPE_CHECKSUM => {
                 let mut checksum = ChecksumRequest::new();
                 box_try!(checksum.merge_from(&mut is));
+                if use_cache {
+                    let mut body = checksum.clone();
+                    body.set_start_ts(0);
+                    let body = box_try!(body.write_to_bytes());
+                    cache_key = Some(CacheKey::new(&context, REQ_TYPE_CHECKSUM, &body, &ranges));
+                }
                 let table_scan = checksum.get_scan_on() == ChecksumScanOn::Table;
                 req_ctx = ReqContext::new(
                     make_tag(table_scan),
@@ -152,14 +249,20 @@ impl<E: Engine> Endpoint<E> {
                     peer,
                     None,
                     Some(checksum.get_start_ts()),
-                );
+                ).with_source(quota_limiter::SOURCE_CHECKSUM);
+                let limiter = self.checksum_limiter.clone();
                 builder = box move |snap, req_ctx: &_| {
+                    let limit = limiter.acquire()?;
                     checksum::ChecksumContext::new(checksum, ranges, snap, req_ctx)
-                        .map(|h| h.into_boxed())
+                        .map(|h| h.with_limit(limit).into_boxed())
                 };
             }
             tp => return Err(box_err!("unsupported tp {}", tp)),
         };
+        if let Some(key) = cache_key {
+            let result_cache = Arc::clone(self.result_cache.as_ref().unwrap());
+            builder = cache::cached_handler_builder(builder, result_cache, key);
+        }
         Ok((builder, req_ctx))
     }
 
@@ -223,12 +326,17 @@ impl<E: Engine> Endpoint<E> {
     // TODO: Convert to use async / await.
     fn handle_unary_request_impl(
         engine: E,
+        read_pool: ReadPool<ReadPoolContext>,
+        priority: readpool::Priority,
         tracker: Box<Tracker>,
         handler_builder: RequestHandlerBuilder<E::Snap>,
     ) -> impl Future<Item = coppb::Response, Error = Error> {
         // When this function is being executed, it may be queued for a long time, so that
         // deadline may exceed.
-        future::result(tracker.req_ctx.deadline.check_if_exceeded())
+        let res = check_memory_usage().and_then(|_| tracker.req_ctx.deadline.check_if_exceeded());
+        let source = tracker.req_ctx.source;
+        future::result(res)
+            .and_then(move |_| wait_for_quota(source))
             .and_then(move |_| {
                 Self::async_snapshot(engine, &tracker.req_ctx.context)
                     .map(|snapshot| (tracker, snapshot))
@@ -242,13 +350,37 @@ impl<E: Engine> Endpoint<E> {
                 future::result(handler_builder.call_box((snapshot, &tracker.req_ctx)))
                     .map(|handler| (tracker, handler))
             })
-            .and_then(|(mut tracker, mut handler)| {
+            .and_then(move |(mut tracker, handler)| {
                 tracker.on_begin_all_items();
                 tracker.on_begin_item();
 
+                // Every time slice runs as a new turn of the read pool, so that the requests
+                // queued meanwhile don't wait for the whole request.
+                let time_slice = Duration::from_millis(UNARY_REQUEST_TIME_SLICE_MILLIS);
+                let mut state = Some((tracker, handler));
+                let mut cpu_time = Duration::from_secs(0);
+                read_pool
+                    .run_in_turns(priority, move || -> Result<_> {
+                        let cpu_begin = thread_cpu_time();
+                        let result = state.as_mut().unwrap().1.handle_request_for(time_slice);
+                        cpu_time += thread_cpu_time() - cpu_begin;
+                        let result = match result {
+                            Ok(None) => {
+                                let delay = state.as_ref().unwrap().1.continue_after();
+                                return Ok(Turn::Continue(delay));
+                            }
+                            Ok(Some(resp)) => Ok(resp),
+                            Err(e) => Err(e),
+                        };
+                        let (tracker, handler) = state.take().unwrap();
+                        Ok(Turn::Finished((tracker, handler, result, cpu_time)))
+                    })
+                    .map_err(|cancel| Error::Other(box_err!(cancel)))
+                    .and_then(|r| r)
+            })
+            .and_then(|(mut tracker, mut handler, result, cpu_time)| {
                 // There might be errors when handling requests. In this case, we still need its
                 // execution metrics.
-                let result = handler.handle_request();
                 let exec_metrics = {
                     let mut metrics = ExecutorMetrics::default();
                     handler.collect_metrics_into(&mut metrics);
@@ -257,12 +389,14 @@ impl<E: Engine> Endpoint<E> {
 
                 tracker.on_finish_item(Some(exec_metrics));
                 let exec_details = tracker.get_item_exec_details();
+                let source = tracker.req_ctx.source;
 
                 tracker.on_finish_all_items();
 
                 future::result(result)
                     .or_else(|e| Ok::<_, Error>(make_error_response(e)))
-                    .map(|mut resp| {
+                    .map(move |mut resp| {
+                        consume_quota(source, cpu_time, &resp);
                         resp.set_exec_details(exec_details);
                         resp
                     })
@@ -278,13 +412,14 @@ impl<E: Engine> Endpoint<E> {
         handler_builder: RequestHandlerBuilder<E::Snap>,
     ) -> impl Future<Item = coppb::Response, Error = ()> {
         let engine = self.engine.clone();
+        let read_pool = self.read_pool.clone();
         let priority = readpool::Priority::from(req_ctx.context.get_priority());
         let mut tracker = box Tracker::new(req_ctx);
 
         let result = self.read_pool.future_execute(priority, move |ctxd| {
             tracker.attach_ctxd(ctxd);
 
-            Self::handle_unary_request_impl(engine, tracker, handler_builder)
+            Self::handle_unary_request_impl(engine, read_pool, priority, tracker, handler_builder)
         });
 
         future::result(result)
@@ -317,13 +452,14 @@ impl<E: Engine> Endpoint<E> {
     ) -> impl Stream<Item = coppb::Response, Error = Error> {
         // When this function is being executed, it may be queued for a long time, so that
         // deadline may exceed.
-
-        let tracker_and_handler_future = future::result(
-            tracker.req_ctx.deadline.check_if_exceeded(),
-        ).and_then(move |_| {
-            Self::async_snapshot(engine, &tracker.req_ctx.context)
-                .map(|snapshot| (tracker, snapshot))
-        })
+        let res = check_memory_usage().and_then(|_| tracker.req_ctx.deadline.check_if_exceeded());
+        let source = tracker.req_ctx.source;
+        let tracker_and_handler_future = future::result(res)
+            .and_then(move |_| wait_for_quota(source))
+            .and_then(move |_| {
+                Self::async_snapshot(engine, &tracker.req_ctx.context)
+                    .map(|snapshot| (tracker, snapshot))
+            })
             .and_then(move |(tracker, snapshot)| {
                 // When snapshot is retrieved, deadline may exceed.
                 future::result(tracker.req_ctx.deadline.check_if_exceeded())
@@ -356,7 +492,9 @@ impl<E: Engine> Endpoint<E> {
                             // There are future items
                             tracker.on_begin_item();
 
+                            let cpu_begin = thread_cpu_time();
                             let result = handler.handle_streaming_request();
+                            let cpu_time = thread_cpu_time() - cpu_begin;
                             let exec_metrics = {
                                 let mut metrics = ExecutorMetrics::default();
                                 handler.collect_metrics_into(&mut metrics);
@@ -375,6 +513,7 @@ impl<E: Engine> Endpoint<E> {
                                 }
                                 Ok((Some(resp), finished)) => (resp, finished),
                             };
+                            consume_quota(tracker.req_ctx.source, cpu_time, &resp);
                             resp.set_exec_details(exec_details);
 
                             let yielded = Either::Left(resp);
@@ -451,8 +590,9 @@ impl<E: Engine> Endpoint<E> {
         req: coppb::Request,
         peer: Option<String>,
     ) -> impl Stream<Item = coppb::Response, Error = ()> {
-        let (handler_builder, req_ctx) = self.parse_request(req, peer, true);
-        self.handle_stream_request(req_ctx, handler_builder)
+        let (handler_builder, mut req_ctx) = self.parse_request(req, peer, true);
+        let session = req_ctx.stream_session.take();
+        RecordSentRanges::new(self.handle_stream_request(req_ctx, handler_builder), session)
     }
 }
 
@@ -464,6 +604,37 @@ fn make_tag(is_table_scan: bool) -> &'static str {
     }
 }
 
+/// Rejects the requests as if the end-point is full when the memory usage of the instance
+/// reaches its high water mark, so that the running requests can release their memory.
+fn check_memory_usage() -> Result<()> {
+    if memory::exceeds_high_water_mark() {
+        return Err(Error::Full);
+    }
+    Ok(())
+}
+
+/// Delays the request until the quota of its source is available again.
+fn wait_for_quota(source: &'static str) -> impl Future<Item = (), Error = Error> {
+    let delay = quota_limiter::get_quota_limiter()
+        .map_or(Duration::from_secs(0), |limiter| limiter.delay(source));
+    if delay == Duration::from_secs(0) {
+        return future::Either::A(future::ok(()));
+    }
+    let when = StdInstant::now() + delay;
+    future::Either::B(
+        GLOBAL_TIMER_HANDLE
+            .delay(when)
+            .map_err(|e| box_err!("failed to wait for the quota of {}: {:?}", source, e)),
+    )
+}
+
+/// Charges the CPU time and the response size of the last item to the quota of its source.
+fn consume_quota(source: &'static str, cpu_time: Duration, resp: &coppb::Response) {
+    if let Some(limiter) = quota_limiter::get_quota_limiter() {
+        limiter.consume(source, cpu_time, resp.get_data().len());
+    }
+}
+
 fn make_error_response(e: Error) -> coppb::Response {
     let mut resp = coppb::Response::new();
     let tag;
@@ -492,6 +663,10 @@ fn make_error_response(e: Error) -> coppb::Response {
             errorpb.set_server_is_busy(server_is_busy_err);
             resp.set_region_error(errorpb);
         }
+        Error::MemoryQuotaExceeded(_) => {
+            tag = "memory_quota";
+            resp.set_other_error(format!("{}", e));
+        }
         Error::Other(_) | Error::Eval(_) => {
             tag = "other";
             resp.set_other_error(format!("{}", e));
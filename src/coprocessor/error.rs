@@ -38,6 +38,10 @@ quick_error! {
         Full {
             description("Coprocessor end-point thread pool is full")
         }
+        MemoryQuotaExceeded(quota: usize) {
+            description("memory quota exceeded")
+            display("memory quota {} bytes exceeded", quota)
+        }
         Eval(err: tipb::select::Error) {
             from()
             description("eval failed")
@@ -105,6 +105,7 @@ pub struct BasicLocalMetrics {
     pub outdate_time: LocalHistogramVec,
     pub handle_time: LocalHistogramVec,
     pub wait_time: LocalHistogramVec,
+    pub snapshot_wait_time: LocalHistogramVec,
     pub error_cnt: LocalIntCounterVec,
     pub scan_keys: LocalHistogramVec,
     pub rocksdb_perf_stats: LocalIntCounterVec,
@@ -117,6 +118,7 @@ impl Default for BasicLocalMetrics {
             outdate_time: OUTDATED_REQ_WAIT_TIME.local(),
             handle_time: COPR_REQ_HANDLE_TIME.local(),
             wait_time: COPR_REQ_WAIT_TIME.local(),
+            snapshot_wait_time: COPR_SNAPSHOT_WAIT_TIME.local(),
             error_cnt: COPR_REQ_ERROR.local(),
             scan_keys: COPR_SCAN_KEYS.local(),
             rocksdb_perf_stats: COPR_ROCKSDB_PERF_COUNTER.local(),
@@ -130,6 +132,7 @@ impl BasicLocalMetrics {
         self.outdate_time.flush();
         self.handle_time.flush();
         self.wait_time.flush();
+        self.snapshot_wait_time.flush();
         self.scan_keys.flush();
         self.error_cnt.flush();
         self.rocksdb_perf_stats.flush();
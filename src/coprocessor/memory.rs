@@ -0,0 +1,157 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use coprocessor::*;
+use util::memory::{self, MemoryConsumer};
+
+/// `MemoryQuota` is the memory quota shared by all coprocessor requests of the store.
+#[derive(Debug)]
+pub struct MemoryQuota {
+    capacity: usize,
+    in_use: AtomicUsize,
+}
+
+impl MemoryQuota {
+    pub fn new(capacity: usize) -> MemoryQuota {
+        MemoryQuota {
+            capacity,
+            in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    fn alloc(&self, bytes: usize) -> bool {
+        let mut in_use = self.in_use.load(Ordering::Relaxed);
+        loop {
+            if in_use + bytes > self.capacity {
+                return false;
+            }
+            let prev = self
+                .in_use
+                .compare_and_swap(in_use, in_use + bytes, Ordering::Relaxed);
+            if prev == in_use {
+                return true;
+            }
+            in_use = prev;
+        }
+    }
+
+    fn free(&self, bytes: usize) {
+        self.in_use.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// `MemoryTracker` tracks the memory used by the buffers of the executors of a request
+/// against the quota of the request and the quota of the store. The memory is returned to the
+/// store when the tracker is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    // 0 means unlimited.
+    limit: usize,
+    consumed: AtomicUsize,
+    quota: Option<Arc<MemoryQuota>>,
+}
+
+impl MemoryTracker {
+    pub fn new(limit: usize, quota: Option<Arc<MemoryQuota>>) -> MemoryTracker {
+        MemoryTracker {
+            limit,
+            consumed: AtomicUsize::new(0),
+            quota,
+        }
+    }
+
+    /// Records `bytes` more memory is used, returns `Error::MemoryQuotaExceeded` if the request
+    /// or the store runs out of its quota.
+    pub fn consume(&self, bytes: usize) -> Result<()> {
+        let consumed = self.consumed.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if self.limit > 0 && consumed > self.limit {
+            self.consumed.fetch_sub(bytes, Ordering::Relaxed);
+            return Err(Error::MemoryQuotaExceeded(self.limit));
+        }
+        if let Some(ref quota) = self.quota {
+            if !quota.alloc(bytes) {
+                self.consumed.fetch_sub(bytes, Ordering::Relaxed);
+                return Err(Error::MemoryQuotaExceeded(quota.capacity));
+            }
+        }
+        memory::add(MemoryConsumer::Coprocessor, bytes);
+        Ok(())
+    }
+
+    pub fn release(&self, bytes: usize) {
+        self.consumed.fetch_sub(bytes, Ordering::Relaxed);
+        memory::sub(MemoryConsumer::Coprocessor, bytes);
+        if let Some(ref quota) = self.quota {
+            quota.free(bytes);
+        }
+    }
+
+    pub fn consumed(&self) -> usize {
+        self.consumed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MemoryTracker {
+    fn drop(&mut self) {
+        let consumed = self.consumed.load(Ordering::Relaxed);
+        memory::sub(MemoryConsumer::Coprocessor, consumed);
+        if let Some(ref quota) = self.quota {
+            quota.free(consumed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_tracker() {
+        let quota = Arc::new(MemoryQuota::new(100));
+        let tracker1 = MemoryTracker::new(60, Some(Arc::clone(&quota)));
+        let tracker2 = MemoryTracker::new(0, Some(Arc::clone(&quota)));
+
+        tracker1.consume(50).unwrap();
+        match tracker1.consume(20) {
+            Err(Error::MemoryQuotaExceeded(60)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(tracker1.consumed(), 50);
+        tracker2.consume(40).unwrap();
+        // The quota of the store is used up.
+        match tracker2.consume(20) {
+            Err(Error::MemoryQuotaExceeded(100)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(quota.in_use(), 90);
+
+        tracker1.release(30);
+        tracker2.consume(20).unwrap();
+        assert_eq!(quota.in_use(), 80);
+        drop(tracker1);
+        assert_eq!(quota.in_use(), 60);
+        drop(tracker2);
+        assert_eq!(quota.in_use(), 0);
+
+        // A tracker without any quota never fails.
+        let tracker = MemoryTracker::default();
+        tracker.consume(usize::max_value() / 2).unwrap();
+    }
+}
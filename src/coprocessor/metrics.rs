@@ -38,6 +38,12 @@ lazy_static! {
         &["req"],
         exponential_buckets(0.0005, 2.0, 20).unwrap()
     ).unwrap();
+    pub static ref COPR_SNAPSHOT_WAIT_TIME: HistogramVec = register_histogram_vec!(
+        "tikv_coprocessor_snapshot_wait_seconds",
+        "Bucketed histogram of coprocessor request snapshot wait duration",
+        &["req"],
+        exponential_buckets(0.0005, 2.0, 20).unwrap()
+    ).unwrap();
     pub static ref COPR_REQ_ERROR: IntCounterVec = register_int_counter_vec!(
         "tikv_coprocessor_request_error",
         "Total number of push down request error.",
@@ -69,4 +75,14 @@ lazy_static! {
         "Total number of rocksdb query of get or scan count",
         &["type"]
     ).unwrap();
+    pub static ref COPR_RESULT_CACHE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_coprocessor_result_cache",
+        "Total number of coprocessor result cache lookups",
+        &["type"]
+    ).unwrap();
+    pub static ref COPR_CANCELLED_TASK_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_coprocessor_cancelled_task",
+        "Total number of coprocessor tasks cancelled in execution for exceeding the deadline",
+        &["req"]
+    ).unwrap();
 }
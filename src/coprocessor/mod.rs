@@ -11,26 +11,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod cache;
 mod checksum;
 pub mod codec;
 pub mod dag;
 mod endpoint;
 mod error;
+mod memory;
 pub mod local_metrics;
 mod metrics;
 mod readpool_context;
 mod statistics;
+mod stream_ranges;
 mod tracker;
 mod util;
 
 pub use self::endpoint::Endpoint;
 pub use self::error::{Error, Result};
+pub use self::memory::MemoryTracker;
 pub use self::readpool_context::Context as ReadPoolContext;
 
 use std::boxed::FnBox;
+use std::sync::Arc;
 
 use kvproto::{coprocessor as coppb, kvrpcpb};
 
+use self::stream_ranges::StreamSession;
+use server::quota_limiter;
 use util::time::{Duration, Instant};
 
 pub const REQ_TYPE_DAG: i64 = 103;
@@ -46,6 +53,19 @@ trait RequestHandler: Send {
         panic!("unary request is not supported for this handler");
     }
 
+    /// Handles the unary request for at most about `time_slice`. Returns `None` if the request
+    /// isn't finished yet, then it should be called again to continue. It gives the read pool
+    /// a chance to run other requests in the middle of a long one.
+    fn handle_request_for(&mut self, _time_slice: Duration) -> Result<Option<coppb::Response>> {
+        self.handle_request().map(Some)
+    }
+
+    /// Returns how long to wait before continuing the request after `handle_request_for`
+    /// returns `None`, e.g. when the request is throttled.
+    fn continue_after(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+
     fn handle_streaming_request(&mut self) -> HandlerStreamStepResult {
         panic!("streaming request is not supported for this handler");
     }
@@ -125,6 +145,15 @@ pub struct ReqContext {
 
     /// The transaction start_ts of the request
     pub txn_start_ts: Option<u64>,
+
+    /// Tracks the memory used by the executors of the request
+    pub memory_tracker: Arc<MemoryTracker>,
+
+    /// The source of the request, whose quota is charged for the request
+    pub source: &'static str,
+
+    /// The streaming session of the request, whose sent ranges are recorded
+    pub stream_session: Option<StreamSession>,
 }
 
 impl ReqContext {
@@ -138,6 +167,7 @@ impl ReqContext {
         txn_start_ts: Option<u64>,
     ) -> Self {
         let deadline = Deadline::from_now(tag, max_handle_duration);
+        let source = quota_limiter::source_of_priority(context.get_priority());
         Self {
             tag,
             context,
@@ -147,9 +177,27 @@ impl ReqContext {
             txn_start_ts,
             first_range: ranges.first().cloned(),
             ranges_len: ranges.len(),
+            memory_tracker: Arc::new(MemoryTracker::default()),
+            source,
+            stream_session: None,
         }
     }
 
+    pub fn with_memory_tracker(mut self, tracker: MemoryTracker) -> Self {
+        self.memory_tracker = Arc::new(tracker);
+        self
+    }
+
+    pub fn with_source(mut self, source: &'static str) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_stream_session(mut self, session: Option<StreamSession>) -> Self {
+        self.stream_session = session;
+        self
+    }
+
     #[cfg(test)]
     pub fn default_for_test() -> Self {
         Self::new(
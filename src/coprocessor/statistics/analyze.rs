@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::mem;
+use std::{cmp, mem};
 
 use kvproto::coprocessor::{KeyRange, Response};
 use protobuf::{Message, RepeatedField};
@@ -29,6 +29,36 @@ use super::cmsketch::CMSketch;
 use super::fmsketch::FMSketch;
 use super::histogram::Histogram;
 
+/// `AnalyzeLimits` caps the samples and count-min sketches an `AnalyzeReq` asks for, which
+/// are kept in memory for every column until the scan finishes.
+#[derive(Clone, Copy, Debug)]
+pub struct AnalyzeLimits {
+    pub max_sample_size: usize,
+    pub max_cmsketch_depth: usize,
+    pub max_cmsketch_width: usize,
+}
+
+impl AnalyzeLimits {
+    pub fn apply(&self, req: &mut AnalyzeReq) {
+        let depth = self.max_cmsketch_depth as i32;
+        let width = self.max_cmsketch_width as i32;
+        if req.has_idx_req() {
+            let idx_req = req.mut_idx_req();
+            let (d, w) = (idx_req.get_cmsketch_depth(), idx_req.get_cmsketch_width());
+            idx_req.set_cmsketch_depth(cmp::min(d, depth));
+            idx_req.set_cmsketch_width(cmp::min(w, width));
+        }
+        if req.has_col_req() {
+            let col_req = req.mut_col_req();
+            let (d, w) = (col_req.get_cmsketch_depth(), col_req.get_cmsketch_width());
+            col_req.set_cmsketch_depth(cmp::min(d, depth));
+            col_req.set_cmsketch_width(cmp::min(w, width));
+            let sample_size = col_req.get_sample_size();
+            col_req.set_sample_size(cmp::min(sample_size, self.max_sample_size as i64));
+        }
+    }
+}
+
 // `AnalyzeContext` is used to handle `AnalyzeReq`
 pub struct AnalyzeContext<S: Snapshot> {
     req: AnalyzeReq,
@@ -324,4 +354,30 @@ mod test {
         assert_eq!(sample.cm_sketch.unwrap().count(), 3);
         assert_eq!(sample.total_size, 6)
     }
+
+    #[test]
+    fn test_analyze_limits() {
+        let limits = AnalyzeLimits {
+            max_sample_size: 100,
+            max_cmsketch_depth: 5,
+            max_cmsketch_width: 1024,
+        };
+
+        let mut req = AnalyzeReq::new();
+        req.mut_col_req().set_sample_size(1000);
+        req.mut_col_req().set_cmsketch_depth(3);
+        req.mut_col_req().set_cmsketch_width(4096);
+        limits.apply(&mut req);
+        assert_eq!(req.get_col_req().get_sample_size(), 100);
+        assert_eq!(req.get_col_req().get_cmsketch_depth(), 3);
+        assert_eq!(req.get_col_req().get_cmsketch_width(), 1024);
+        assert!(!req.has_idx_req());
+
+        let mut req = AnalyzeReq::new();
+        req.mut_idx_req().set_cmsketch_depth(10);
+        req.mut_idx_req().set_cmsketch_width(0);
+        limits.apply(&mut req);
+        assert_eq!(req.get_idx_req().get_cmsketch_depth(), 5);
+        assert_eq!(req.get_idx_req().get_cmsketch_width(), 0);
+    }
 }
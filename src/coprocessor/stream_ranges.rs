@@ -0,0 +1,328 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crc::crc64::{self, Hasher64};
+use futures::{Async, Poll, Stream};
+use kvproto::coprocessor::{self as coppb, KeyRange};
+use kvproto::kvrpcpb;
+
+use util::lru::LruCache;
+use util::time::Instant;
+
+/// Identifies the streaming requests of a session. The retries of a request share the same
+/// region epoch, start ts and request body, only the ranges differ.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SessionKey {
+    region_id: u64,
+    conf_ver: u64,
+    version: u64,
+    start_ts: u64,
+    digest: u64,
+}
+
+impl SessionKey {
+    pub fn new(ctx: &kvrpcpb::Context, start_ts: u64, data: &[u8]) -> SessionKey {
+        let mut digest = crc64::Digest::new(crc64::ECMA);
+        digest.write(data);
+        let epoch = ctx.get_region_epoch();
+        SessionKey {
+            region_id: ctx.get_region_id(),
+            conf_ver: epoch.get_conf_ver(),
+            version: epoch.get_version(),
+            start_ts,
+            digest: digest.sum64(),
+        }
+    }
+}
+
+/// `StreamRanges` remembers the ranges already sent to the client by streaming sessions.
+///
+/// When a stream breaks in the middle, the SQL layer retries the request with ranges which
+/// may overlap with the responses it has received, which would count the overlapped rows
+/// twice in aggregates. The retried ranges are trimmed by the processed ones here. Only
+/// the sessions broken in the middle are remembered, and a session is forgotten if it makes
+/// no progress in `ttl`.
+pub struct StreamRanges {
+    // session -> (last update time, processed ranges)
+    sessions: Mutex<LruCache<SessionKey, (Instant, Vec<(Vec<u8>, Vec<u8>)>)>>,
+    ttl: Duration,
+}
+
+impl StreamRanges {
+    pub fn new(capacity: usize, ttl: Duration) -> StreamRanges {
+        StreamRanges {
+            sessions: Mutex::new(LruCache::with_capacity(capacity)),
+            ttl,
+        }
+    }
+
+    /// Removes the processed parts of the session from `ranges`.
+    pub fn trim(&self, key: &SessionKey, ranges: Vec<KeyRange>) -> Vec<KeyRange> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired = match sessions.get(key) {
+            Some(&(update_time, _)) => update_time.elapsed() >= self.ttl,
+            None => return ranges,
+        };
+        if expired {
+            sessions.remove(key);
+            return ranges;
+        }
+        let processed = &sessions.get(key).unwrap().1;
+        let mut res = Vec::with_capacity(ranges.len());
+        for mut range in ranges {
+            for &(ref start, ref end) in processed {
+                if end.as_slice() <= range.get_start() {
+                    continue;
+                }
+                if start.as_slice() >= range.get_end() {
+                    break;
+                }
+                if start.as_slice() > range.get_start() {
+                    let mut left = KeyRange::new();
+                    left.set_start(range.take_start());
+                    left.set_end(start.clone());
+                    res.push(left);
+                }
+                range.set_start(end.clone());
+                if range.get_start() >= range.get_end() {
+                    break;
+                }
+            }
+            if range.get_start() < range.get_end() {
+                res.push(range);
+            }
+        }
+        res
+    }
+
+    /// Marks `range` of the session as processed.
+    pub fn record(&self, key: &SessionKey, range: &KeyRange) {
+        if range.get_start() >= range.get_end() {
+            return;
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut processed = match sessions.remove(key) {
+            Some((update_time, processed)) => if update_time.elapsed() < self.ttl {
+                processed
+            } else {
+                vec![]
+            },
+            None => vec![],
+        };
+        processed.push((range.get_start().to_vec(), range.get_end().to_vec()));
+        processed.sort();
+        // Merges the overlapped or adjacent ranges.
+        let mut merged: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(processed.len());
+        for (start, end) in processed {
+            if let Some(last) = merged.last_mut() {
+                if start <= last.1 {
+                    last.1 = cmp::max(end, last.1.clone());
+                    continue;
+                }
+            }
+            merged.push((start, end));
+        }
+        sessions.insert(key.clone(), (Instant::now_coarse(), merged));
+    }
+
+    /// Forgets the session. A finished session must be forgotten, otherwise the same
+    /// query executed again in the transaction would be trimmed.
+    pub fn forget(&self, key: &SessionKey) {
+        self.sessions.lock().unwrap().remove(key);
+    }
+}
+
+/// The streaming session a request belongs to.
+#[derive(Clone)]
+pub struct StreamSession {
+    ranges: Arc<StreamRanges>,
+    key: SessionKey,
+}
+
+impl StreamSession {
+    pub fn new(ranges: Arc<StreamRanges>, key: SessionKey) -> StreamSession {
+        StreamSession { ranges, key }
+    }
+}
+
+impl Debug for StreamSession {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("StreamSession")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+/// A stream of the responses of a streaming request, which records the range of a response
+/// only after it has been sent, that is, when the consumer asks for the next response. The
+/// response in flight when the stream is dropped is not recorded.
+pub struct RecordSentRanges<S> {
+    inner: S,
+    session: Option<StreamSession>,
+    // The range of the last response returned to the consumer.
+    sending: Option<KeyRange>,
+    failed: bool,
+}
+
+impl<S> RecordSentRanges<S> {
+    pub fn new(inner: S, session: Option<StreamSession>) -> RecordSentRanges<S> {
+        RecordSentRanges {
+            inner,
+            session,
+            sending: None,
+            failed: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = coppb::Response>> Stream for RecordSentRanges<S> {
+    type Item = coppb::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<coppb::Response>, S::Error> {
+        let session = match self.session {
+            Some(ref session) => session,
+            None => return self.inner.poll(),
+        };
+        if let Some(range) = self.sending.take() {
+            session.ranges.record(&session.key, &range);
+        }
+        match try_ready!(self.inner.poll()) {
+            Some(resp) => {
+                if resp.has_range() {
+                    self.sending = Some(resp.get_range().clone());
+                }
+                self.failed = resp.has_region_error()
+                    || resp.has_locked()
+                    || !resp.get_other_error().is_empty();
+                Ok(Async::Ready(Some(resp)))
+            }
+            None => {
+                // A finished session must be forgotten, otherwise the same query executed
+                // again in the transaction would be trimmed. A failed one is retried.
+                if !self.failed {
+                    session.ranges.forget(&session.key);
+                }
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::stream;
+
+    fn range(start: &[u8], end: &[u8]) -> KeyRange {
+        let mut range = KeyRange::new();
+        range.set_start(start.to_vec());
+        range.set_end(end.to_vec());
+        range
+    }
+
+    fn trim(ranges: &StreamRanges, key: &SessionKey, input: &[(&[u8], &[u8])]) -> Vec<KeyRange> {
+        let input = input.iter().map(|&(s, e)| range(s, e)).collect();
+        ranges.trim(key, input)
+    }
+
+    #[test]
+    fn test_stream_ranges() {
+        let ranges = StreamRanges::new(10, Duration::from_secs(60));
+        let mut ctx = kvrpcpb::Context::new();
+        ctx.set_region_id(1);
+        let key = SessionKey::new(&ctx, 10, b"dag");
+
+        // Nothing is processed yet.
+        let res = trim(&ranges, &key, &[(b"a", b"z")]);
+        assert_eq!(res, vec![range(b"a", b"z")]);
+
+        ranges.record(&key, &range(b"a", b"c"));
+        ranges.record(&key, &range(b"c", b"e"));
+        ranges.record(&key, &range(b"g", b"h"));
+        let res = trim(&ranges, &key, &[(b"a", b"z")]);
+        assert_eq!(res, vec![range(b"e", b"g"), range(b"h", b"z")]);
+        let res = trim(&ranges, &key, &[(b"b", b"d"), (b"f", b"k")]);
+        assert_eq!(res, vec![range(b"f", b"g"), range(b"h", b"k")]);
+        let res = trim(&ranges, &key, &[(b"a", b"b")]);
+        assert!(res.is_empty());
+
+        // Other sessions are not affected.
+        let other = SessionKey::new(&ctx, 11, b"dag");
+        let res = trim(&ranges, &other, &[(b"a", b"b")]);
+        assert_eq!(res, vec![range(b"a", b"b")]);
+        ctx.mut_region_epoch().set_version(2);
+        let other = SessionKey::new(&ctx, 10, b"dag");
+        let res = trim(&ranges, &other, &[(b"a", b"b")]);
+        assert_eq!(res, vec![range(b"a", b"b")]);
+
+        ranges.forget(&key);
+        let res = trim(&ranges, &key, &[(b"a", b"z")]);
+        assert_eq!(res, vec![range(b"a", b"z")]);
+    }
+
+    #[test]
+    fn test_stream_ranges_expire() {
+        let ranges = StreamRanges::new(10, Duration::from_secs(0));
+        let key = SessionKey::new(&kvrpcpb::Context::new(), 10, b"dag");
+        ranges.record(&key, &range(b"a", b"c"));
+        let res = trim(&ranges, &key, &[(b"a", b"z")]);
+        assert_eq!(res, vec![range(b"a", b"z")]);
+    }
+
+    fn response(start: &[u8], end: &[u8]) -> coppb::Response {
+        let mut resp = coppb::Response::new();
+        resp.set_range(range(start, end));
+        resp
+    }
+
+    #[test]
+    fn test_record_sent_ranges() {
+        let ranges = Arc::new(StreamRanges::new(10, Duration::from_secs(60)));
+        let key = SessionKey::new(&kvrpcpb::Context::new(), 10, b"dag");
+        let session = StreamSession::new(Arc::clone(&ranges), key.clone());
+        let resps = vec![response(b"a", b"c"), response(b"c", b"e")];
+        let mut s = RecordSentRanges::new(stream::iter_ok::<_, ()>(resps), Some(session));
+
+        // The range of a response is recorded once the next one is asked.
+        assert!(s.poll().unwrap().is_ready());
+        assert_eq!(trim(&ranges, &key, &[(b"a", b"z")]), vec![range(b"a", b"z")]);
+        assert!(s.poll().unwrap().is_ready());
+        assert_eq!(trim(&ranges, &key, &[(b"a", b"z")]), vec![range(b"c", b"z")]);
+        // The stream is broken before the second response is sent.
+        drop(s);
+        assert_eq!(trim(&ranges, &key, &[(b"a", b"z")]), vec![range(b"c", b"z")]);
+
+        // A finished session is forgotten.
+        let session = StreamSession::new(Arc::clone(&ranges), key.clone());
+        let resps = vec![response(b"c", b"z")];
+        let mut s = RecordSentRanges::new(stream::iter_ok::<_, ()>(resps), Some(session));
+        while let Async::Ready(Some(_)) = s.poll().unwrap() {}
+        assert_eq!(trim(&ranges, &key, &[(b"a", b"z")]), vec![range(b"a", b"z")]);
+
+        // A failed session is kept for the retry.
+        ranges.record(&key, &range(b"a", b"c"));
+        let session = StreamSession::new(Arc::clone(&ranges), key.clone());
+        let mut resp = coppb::Response::new();
+        resp.set_other_error("error".to_owned());
+        let mut s = RecordSentRanges::new(stream::iter_ok::<_, ()>(vec![resp]), Some(session));
+        while let Async::Ready(Some(_)) = s.poll().unwrap() {}
+        assert_eq!(trim(&ranges, &key, &[(b"a", b"z")]), vec![range(b"c", b"z")]);
+    }
+}
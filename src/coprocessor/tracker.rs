@@ -15,14 +15,12 @@ use kvproto::kvrpcpb;
 
 use storage::engine::{PerfStatisticsDelta, PerfStatisticsInstant};
 use util::futurepool;
+use util::logger::{self, SLOW_LOG_TARGET};
 use util::time::{self, Duration, Instant};
 
 use coprocessor::dag::executor::ExecutorMetrics;
 use coprocessor::*;
 
-// If handle time is larger than the lower bound, the query is considered as slow query.
-const SLOW_QUERY_LOWER_BOUND: f64 = 1.0; // 1 second.
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum TrackerState {
     /// The tracker is just created and not initialized. Initialize means `ctxd` is attached.
@@ -56,7 +54,9 @@ pub struct Tracker {
 
     // Intermediate results
     current_stage: TrackerState,
-    wait_time: Duration,
+    wait_time: Duration, // Sum of `schedule_wait_time` and `snapshot_wait_time`
+    schedule_wait_time: Duration, // Time waiting in the read pool
+    snapshot_wait_time: Duration, // Time retrieving the snapshot and building the handler
     req_time: Duration,
     item_process_time: Duration,
     total_process_time: Duration,
@@ -82,6 +82,8 @@ impl Tracker {
 
             current_stage: TrackerState::NotInitialized,
             wait_time: Duration::default(),
+            schedule_wait_time: Duration::default(),
+            snapshot_wait_time: Duration::default(),
             req_time: Duration::default(),
             item_process_time: Duration::default(),
             total_process_time: Duration::default(),
@@ -97,6 +99,7 @@ impl Tracker {
     /// Attach future pool's context delegators.
     pub fn attach_ctxd(&mut self, ctxd: futurepool::ContextDelegators<ReadPoolContext>) {
         assert!(self.current_stage == TrackerState::NotInitialized);
+        self.schedule_wait_time = Instant::now_coarse() - self.request_begin_at;
         self.ctxd = Some(ctxd);
         self.current_stage = TrackerState::Initialized;
     }
@@ -104,6 +107,7 @@ impl Tracker {
     pub fn on_begin_all_items(&mut self) {
         assert!(self.current_stage == TrackerState::Initialized);
         self.wait_time = Instant::now_coarse() - self.request_begin_at;
+        self.snapshot_wait_time = self.wait_time - self.schedule_wait_time;
         self.current_stage = TrackerState::AllItemsBegan;
     }
 
@@ -136,7 +140,7 @@ impl Tracker {
     /// TiDB asks for ExecDetail to be printed in its log.
     pub fn get_item_exec_details(&self) -> kvrpcpb::ExecDetails {
         assert!(self.current_stage == TrackerState::ItemFinished);
-        let is_slow_query = time::duration_to_sec(self.item_process_time) > SLOW_QUERY_LOWER_BOUND;
+        let is_slow_query = logger::is_slow(self.item_process_time);
         let mut exec_details = kvrpcpb::ExecDetails::new();
         if self.req_ctx.context.get_handle_time() || is_slow_query {
             let mut handle = kvrpcpb::HandleTime::new();
@@ -167,19 +171,22 @@ impl Tracker {
         }
 
         // Print slow log if *process* time is long.
-        if time::duration_to_sec(self.total_process_time) > SLOW_QUERY_LOWER_BOUND {
+        if logger::is_slow(self.total_process_time) {
             let some_table_id = self.req_ctx.first_range.as_ref().map(|range| {
                 super::codec::table::decode_table_id(range.get_start()).unwrap_or_default()
             });
 
             info!(
-                "[region {}] [slow-query] execute takes {:?}, wait takes {:?}, \
-                 peer: {:?}, start_ts: {:?}, table_id: {:?}, \
+                target: SLOW_LOG_TARGET,
+                "[region {}] [slow-query] execute takes {:?}, wait takes {:?} \
+                 (schedule: {:?}, snapshot: {:?}), peer: {:?}, start_ts: {:?}, table_id: {:?}, \
                  tag: {} (desc: {:?}) \
                  [keys: {}, hit: {}, ranges: {} ({:?}), perf: {:?}]",
                 self.req_ctx.context.get_region_id(),
                 self.total_process_time,
                 self.wait_time,
+                self.schedule_wait_time,
+                self.snapshot_wait_time,
                 self.req_ctx.peer,
                 self.req_ctx.txn_start_ts,
                 some_table_id,
@@ -206,6 +213,11 @@ impl Tracker {
             .wait_time
             .with_label_values(&[self.req_ctx.tag])
             .observe(time::duration_to_sec(self.wait_time));
+        thread_ctx
+            .basic_local_metrics
+            .snapshot_wait_time
+            .with_label_values(&[self.req_ctx.tag])
+            .observe(time::duration_to_sec(self.snapshot_wait_time));
         thread_ctx
             .basic_local_metrics
             .handle_time
@@ -221,31 +233,13 @@ impl Tracker {
             self.req_ctx.tag,
             total_exec_metrics,
         );
-        thread_ctx
-            .basic_local_metrics
-            .rocksdb_perf_stats
-            .with_label_values(&[self.req_ctx.tag, "internal_key_skipped_count"])
-            .inc_by(self.total_perf_statistics.internal_key_skipped_count as i64);
-        thread_ctx
-            .basic_local_metrics
-            .rocksdb_perf_stats
-            .with_label_values(&[self.req_ctx.tag, "internal_delete_skipped_count"])
-            .inc_by(self.total_perf_statistics.internal_delete_skipped_count as i64);
-        thread_ctx
-            .basic_local_metrics
-            .rocksdb_perf_stats
-            .with_label_values(&[self.req_ctx.tag, "block_cache_hit_count"])
-            .inc_by(self.total_perf_statistics.block_cache_hit_count as i64);
-        thread_ctx
-            .basic_local_metrics
-            .rocksdb_perf_stats
-            .with_label_values(&[self.req_ctx.tag, "block_read_count"])
-            .inc_by(self.total_perf_statistics.block_read_count as i64);
-        thread_ctx
-            .basic_local_metrics
-            .rocksdb_perf_stats
-            .with_label_values(&[self.req_ctx.tag, "block_read_byte"])
-            .inc_by(self.total_perf_statistics.block_read_byte as i64);
+        for &(metric, value) in &self.total_perf_statistics.labeled_values() {
+            thread_ctx
+                .basic_local_metrics
+                .rocksdb_perf_stats
+                .with_label_values(&[self.req_ctx.tag, metric])
+                .inc_by(value as i64);
+        }
         self.current_stage = TrackerState::Tracked;
     }
 }
@@ -0,0 +1,189 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use raftstore::Result;
+use storage::CfName;
+use util::collections::HashMap;
+
+use super::{
+    BufferedWriteBatch, EngineIterator, IterOption, Iterable, KvEngine, Peekable, Snapshot, Write,
+};
+
+type CfMap = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn get_cf<'a>(cfs: &'a HashMap<String, CfMap>, cf: &str) -> Result<&'a CfMap> {
+    cfs.get(cf).ok_or_else(|| box_err!("cf {} not found", cf))
+}
+
+fn new_iterator(
+    cfs: &HashMap<String, CfMap>,
+    cf: &str,
+    iter_opt: &IterOption,
+) -> Result<BTreeIterator> {
+    let kvs = get_cf(cfs, cf)?
+        .iter()
+        .filter(|&(k, _)| {
+            iter_opt.lower_bound().map_or(true, |l| k.as_slice() >= l)
+                && iter_opt.upper_bound().map_or(true, |u| k.as_slice() < u)
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    Ok(BTreeIterator { kvs, pos: 0 })
+}
+
+/// BTreeEngine is an in-memory `KvEngine`, which is meant for tests.
+#[derive(Clone)]
+pub struct BTreeEngine {
+    cf_names: Arc<Vec<String>>,
+    cfs: Arc<RwLock<HashMap<String, CfMap>>>,
+}
+
+impl BTreeEngine {
+    pub fn new(cf_names: &[CfName]) -> BTreeEngine {
+        let cf_names: Vec<_> = cf_names.iter().map(|cf| cf.to_string()).collect();
+        let cfs = cf_names.iter().map(|cf| (cf.clone(), CfMap::new())).collect();
+        BTreeEngine {
+            cf_names: Arc::new(cf_names),
+            cfs: Arc::new(RwLock::new(cfs)),
+        }
+    }
+}
+
+impl Peekable for BTreeEngine {
+    type DBVector = Vec<u8>;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let cfs = self.cfs.read().unwrap();
+        Ok(get_cf(&cfs, cf)?.get(key).cloned())
+    }
+}
+
+impl Iterable for BTreeEngine {
+    type Iterator = BTreeIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<BTreeIterator> {
+        let cfs = self.cfs.read().unwrap();
+        new_iterator(&cfs, cf, &iter_opt)
+    }
+}
+
+impl KvEngine for BTreeEngine {
+    type Snapshot = BTreeSnapshot;
+    type WriteBatch = BufferedWriteBatch;
+
+    fn snapshot(&self) -> BTreeSnapshot {
+        let cfs = self.cfs.read().unwrap();
+        BTreeSnapshot {
+            cfs: Arc::new(cfs.clone()),
+        }
+    }
+
+    fn write_batch(&self) -> BufferedWriteBatch {
+        BufferedWriteBatch::default()
+    }
+
+    fn write(&self, wb: BufferedWriteBatch, _: bool) -> Result<()> {
+        let mut cfs = self.cfs.write().unwrap();
+        // Check all the column families first, so that the batch is applied atomically.
+        for &(ref cf, _) in &wb.writes {
+            get_cf(&cfs, cf)?;
+        }
+        for (cf, write) in wb.writes {
+            let cf = cfs.get_mut(&cf).unwrap();
+            match write {
+                Write::Put(key, value) => {
+                    cf.insert(key, value);
+                }
+                Write::Delete(key) => {
+                    cf.remove(&key);
+                }
+                // `BTreeMap::range` panics if the range is reversed.
+                Write::DeleteRange(start, end) => if start < end {
+                    let keys: Vec<_> = cf.range(start..end).map(|(k, _)| k.clone()).collect();
+                    for key in keys {
+                        cf.remove(&key);
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn cf_names(&self) -> Vec<&str> {
+        self.cf_names.iter().map(|cf| cf.as_str()).collect()
+    }
+}
+
+/// BTreeSnapshot is a copy of the data of a `BTreeEngine`.
+#[derive(Clone)]
+pub struct BTreeSnapshot {
+    cfs: Arc<HashMap<String, CfMap>>,
+}
+
+impl Peekable for BTreeSnapshot {
+    type DBVector = Vec<u8>;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(get_cf(&self.cfs, cf)?.get(key).cloned())
+    }
+}
+
+impl Iterable for BTreeSnapshot {
+    type Iterator = BTreeIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<BTreeIterator> {
+        new_iterator(&self.cfs, cf, &iter_opt)
+    }
+}
+
+impl Snapshot for BTreeSnapshot {}
+
+/// BTreeIterator iterates over a copy of the keys within the bounds.
+pub struct BTreeIterator {
+    kvs: Vec<(Vec<u8>, Vec<u8>)>,
+    pos: usize,
+}
+
+impl EngineIterator for BTreeIterator {
+    fn seek(&mut self, key: &[u8]) -> bool {
+        self.pos = match self.kvs.binary_search_by(|&(ref k, _)| k.as_slice().cmp(key)) {
+            Ok(pos) | Err(pos) => pos,
+        };
+        self.valid()
+    }
+
+    fn seek_to_first(&mut self) -> bool {
+        self.pos = 0;
+        self.valid()
+    }
+
+    fn next(&mut self) -> bool {
+        self.pos += 1;
+        self.valid()
+    }
+
+    fn valid(&self) -> bool {
+        self.pos < self.kvs.len()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.kvs[self.pos].0
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.kvs[self.pos].1
+    }
+}
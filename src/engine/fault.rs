@@ -0,0 +1,197 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An engine wrapper which injects latency and errors into the operations, so the behavior of
+//! raftstore on a degraded disk can be tested without external tooling. It's only built for
+//! tests or with the `engine-fault-injection` feature.
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use rand::{self, Rng};
+
+use raftstore::Result;
+use util::collections::HashMap;
+
+use super::{IterOption, Iterable, KvEngine, Peekable, Snapshot};
+
+/// The kinds of operations faults are injected into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EngineOp {
+    Get,
+    /// Only the creation of iterators is affected, iterating isn't.
+    Iter,
+    Write,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fault {
+    /// The latency added to every operation.
+    pub latency: Duration,
+    /// The probability of an operation to fail, in `[0.0, 1.0]`.
+    pub error_rate: f64,
+}
+
+/// `FaultEngine` wraps an engine or a snapshot and injects the configured faults before
+/// calling it. The faults can be changed at any time, and are shared by the clones and the
+/// snapshots.
+#[derive(Clone)]
+pub struct FaultEngine<E> {
+    engine: E,
+    faults: Arc<RwLock<HashMap<EngineOp, Fault>>>,
+}
+
+impl<E> FaultEngine<E> {
+    pub fn new(engine: E) -> FaultEngine<E> {
+        FaultEngine {
+            engine,
+            faults: Arc::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &E {
+        &self.engine
+    }
+
+    pub fn set_fault(&self, op: EngineOp, fault: Fault) {
+        self.faults.write().unwrap().insert(op, fault);
+    }
+
+    pub fn clear_faults(&self) {
+        self.faults.write().unwrap().clear();
+    }
+
+    fn inject(&self, op: EngineOp) -> Result<()> {
+        let fault = match self.faults.read().unwrap().get(&op) {
+            Some(fault) => fault.clone(),
+            None => return Ok(()),
+        };
+        if fault.latency > Duration::from_secs(0) {
+            thread::sleep(fault.latency);
+        }
+        if fault.error_rate > 0.0 && rand::thread_rng().gen::<f64>() < fault.error_rate {
+            return Err(box_err!("injected {:?} error", op));
+        }
+        Ok(())
+    }
+}
+
+impl<E: Peekable> Peekable for FaultEngine<E> {
+    type DBVector = E::DBVector;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<E::DBVector>> {
+        self.inject(EngineOp::Get)?;
+        self.engine.get_value_cf(cf, key)
+    }
+}
+
+impl<E: Iterable> Iterable for FaultEngine<E> {
+    type Iterator = E::Iterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<E::Iterator> {
+        self.inject(EngineOp::Iter)?;
+        self.engine.iterator_cf(cf, iter_opt)
+    }
+}
+
+impl<S: Snapshot> Snapshot for FaultEngine<S> {}
+
+impl<E: KvEngine> KvEngine for FaultEngine<E> {
+    type Snapshot = FaultEngine<E::Snapshot>;
+    type WriteBatch = E::WriteBatch;
+
+    fn snapshot(&self) -> FaultEngine<E::Snapshot> {
+        FaultEngine {
+            engine: self.engine.snapshot(),
+            faults: Arc::clone(&self.faults),
+        }
+    }
+
+    fn write_batch(&self) -> E::WriteBatch {
+        self.engine.write_batch()
+    }
+
+    fn write(&self, wb: E::WriteBatch, sync: bool) -> Result<()> {
+        self.inject(EngineOp::Write)?;
+        self.engine.write(wb, sync)
+    }
+
+    fn cf_names(&self) -> Vec<&str> {
+        self.engine.cf_names()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::super::{BTreeEngine, EngineIterator, WriteBatch};
+    use super::*;
+    use storage::{ALL_CFS, CF_DEFAULT};
+
+    fn put(engine: &FaultEngine<BTreeEngine>, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut wb = engine.write_batch();
+        wb.put_cf(CF_DEFAULT, key, value).unwrap();
+        engine.write(wb, false)
+    }
+
+    #[test]
+    fn test_fault_engine() {
+        let engine = FaultEngine::new(BTreeEngine::new(ALL_CFS));
+        put(&engine, b"k1", b"v1").unwrap();
+        assert_eq!(&*engine.get_value_cf(CF_DEFAULT, b"k1").unwrap().unwrap(), b"v1");
+
+        engine.set_fault(
+            EngineOp::Get,
+            Fault {
+                latency: Duration::from_millis(50),
+                error_rate: 0.0,
+            },
+        );
+        let now = Instant::now();
+        assert_eq!(&*engine.get_value_cf(CF_DEFAULT, b"k1").unwrap().unwrap(), b"v1");
+        assert!(now.elapsed() >= Duration::from_millis(50));
+
+        engine.set_fault(
+            EngineOp::Write,
+            Fault {
+                latency: Duration::from_millis(0),
+                error_rate: 1.0,
+            },
+        );
+        assert!(put(&engine, b"k2", b"v2").is_err());
+        let inner = engine.get_ref();
+        assert!(inner.get_value_cf(CF_DEFAULT, b"k2").unwrap().is_none());
+        // Reads are not affected by the faults of writes.
+        let mut iter = engine.iterator_cf(CF_DEFAULT, IterOption::default()).unwrap();
+        assert!(iter.seek(b"k1"));
+
+        // The snapshots share the faults.
+        let snap = engine.snapshot();
+        engine.set_fault(
+            EngineOp::Iter,
+            Fault {
+                latency: Duration::from_millis(0),
+                error_rate: 1.0,
+            },
+        );
+        assert!(snap.iterator_cf(CF_DEFAULT, IterOption::default()).is_err());
+
+        // So do the clones.
+        engine.clone().clear_faults();
+        assert!(snap.iterator_cf(CF_DEFAULT, IterOption::default()).is_ok());
+        put(&engine, b"k2", b"v2").unwrap();
+        assert_eq!(&*inner.get_value_cf(CF_DEFAULT, b"k2").unwrap().unwrap(), b"v2");
+    }
+}
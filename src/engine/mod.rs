@@ -0,0 +1,272 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The traits of the KV engine under raftstore and storage.
+//!
+//! The engine owns the types of its snapshots, iterators and write batches, so that the code
+//! taking a `KvEngine` doesn't depend on rust-rocksdb and can run on `BTreeEngine` in tests.
+//! The column families are identified by their names.
+
+mod btree;
+#[cfg(any(test, feature = "engine-fault-injection"))]
+mod fault;
+mod multi_rocks;
+mod rocks;
+
+pub use self::btree::{BTreeEngine, BTreeIterator, BTreeSnapshot};
+#[cfg(any(test, feature = "engine-fault-injection"))]
+pub use self::fault::{EngineOp, Fault, FaultEngine};
+pub use self::multi_rocks::{GroupExport, GroupFile, MultiIterator, MultiRocksEngine, MultiSnapshot};
+pub use self::rocks::{RocksEngine, RocksIterator, RocksSnapshot, RocksWriteBatch};
+pub use raftstore::store::engine::IterOption;
+
+use std::ops::Deref;
+
+use protobuf;
+
+use raftstore::Result;
+
+pub trait Peekable {
+    type DBVector: Deref<Target = [u8]>;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Self::DBVector>>;
+
+    fn get_msg_cf<M: protobuf::Message>(&self, cf: &str, key: &[u8]) -> Result<Option<M>> {
+        let value = match self.get_value_cf(cf, key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut m = M::new();
+        m.merge_from_bytes(&value)?;
+        Ok(Some(m))
+    }
+}
+
+/// An iterator over the keys of a column family in ascending order.
+pub trait EngineIterator: Send {
+    /// Seeks to the first key >= `key`, returns whether it's valid.
+    fn seek(&mut self, key: &[u8]) -> bool;
+    fn seek_to_first(&mut self) -> bool;
+    fn next(&mut self) -> bool;
+    fn valid(&self) -> bool;
+    fn key(&self) -> &[u8];
+    fn value(&self) -> &[u8];
+}
+
+pub trait Iterable {
+    type Iterator: EngineIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<Self::Iterator>;
+
+    // scan_cf scans the column family in range [start_key, end_key), calls function f for
+    // each iteration, if f returns false, terminates this scan.
+    fn scan_cf<F>(
+        &self,
+        cf: &str,
+        start_key: &[u8],
+        end_key: &[u8],
+        fill_cache: bool,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        let iter_opt =
+            IterOption::new(Some(start_key.to_vec()), Some(end_key.to_vec()), fill_cache);
+        let mut it = self.iterator_cf(cf, iter_opt)?;
+        it.seek(start_key);
+        while it.valid() {
+            if !f(it.key(), it.value())? || !it.next() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Seek the first key >= given key, if no found, return None.
+    fn seek_cf(&self, cf: &str, key: &[u8]) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut it = self.iterator_cf(cf, IterOption::default())?;
+        if it.seek(key) {
+            return Ok(Some((it.key().to_vec(), it.value().to_vec())));
+        }
+        Ok(None)
+    }
+}
+
+/// WriteBatch collects the writes to be applied to the engine atomically.
+pub trait WriteBatch: Send {
+    fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()>;
+    fn delete_cf(&mut self, cf: &str, key: &[u8]) -> Result<()>;
+    /// Deletes the keys in [`start_key`, `end_key`).
+    fn delete_range_cf(&mut self, cf: &str, start_key: &[u8], end_key: &[u8]) -> Result<()>;
+    fn count(&self) -> usize;
+    fn data_size(&self) -> usize;
+    fn clear(&mut self);
+
+    fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+}
+
+enum Write {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    DeleteRange(Vec<u8>, Vec<u8>),
+}
+
+/// BufferedWriteBatch keeps the writes in memory, for the engines which apply the writes by
+/// themselves.
+#[derive(Default)]
+pub struct BufferedWriteBatch {
+    writes: Vec<(String, Write)>,
+    data_size: usize,
+}
+
+impl WriteBatch for BufferedWriteBatch {
+    fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.data_size += key.len() + value.len();
+        let write = Write::Put(key.to_vec(), value.to_vec());
+        self.writes.push((cf.to_owned(), write));
+        Ok(())
+    }
+
+    fn delete_cf(&mut self, cf: &str, key: &[u8]) -> Result<()> {
+        self.data_size += key.len();
+        self.writes.push((cf.to_owned(), Write::Delete(key.to_vec())));
+        Ok(())
+    }
+
+    fn delete_range_cf(&mut self, cf: &str, start_key: &[u8], end_key: &[u8]) -> Result<()> {
+        self.data_size += start_key.len() + end_key.len();
+        let write = Write::DeleteRange(start_key.to_vec(), end_key.to_vec());
+        self.writes.push((cf.to_owned(), write));
+        Ok(())
+    }
+
+    fn count(&self) -> usize {
+        self.writes.len()
+    }
+
+    fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    fn clear(&mut self) {
+        self.writes.clear();
+        self.data_size = 0;
+    }
+}
+
+/// A consistent view of the engine at the time it's taken. The clones share the same view.
+pub trait Snapshot: Peekable + Iterable + Clone + Send + Sync + 'static {}
+
+pub trait KvEngine: Peekable + Iterable + Clone + Send + Sync + 'static {
+    type Snapshot: Snapshot;
+    type WriteBatch: WriteBatch;
+
+    fn snapshot(&self) -> Self::Snapshot;
+    fn write_batch(&self) -> Self::WriteBatch;
+    fn write(&self, wb: Self::WriteBatch, sync: bool) -> Result<()>;
+    fn cf_names(&self) -> Vec<&str>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    use config::DbConfig;
+    use storage::{ALL_CFS, CF_DEFAULT, CF_WRITE};
+    use util::rocksdb::new_engine;
+
+    fn must_write<E: KvEngine>(engine: &E, cf: &str, kvs: &[(&str, Option<&str>)]) {
+        let mut wb = engine.write_batch();
+        for &(k, v) in kvs {
+            match v {
+                Some(v) => wb.put_cf(cf, k.as_bytes(), v.as_bytes()).unwrap(),
+                None => wb.delete_cf(cf, k.as_bytes()).unwrap(),
+            }
+        }
+        assert_eq!(wb.count(), kvs.len());
+        engine.write(wb, false).unwrap();
+    }
+
+    fn scan<I: Iterable>(iterable: &I, cf: &str, start: &[u8], end: &[u8]) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        iterable
+            .scan_cf(cf, start, end, true, |k, _| {
+                keys.push(k.to_vec());
+                Ok(true)
+            })
+            .unwrap();
+        keys
+    }
+
+    fn check_engine<E: KvEngine>(engine: E) {
+        assert_eq!(engine.cf_names().len(), ALL_CFS.len());
+
+        must_write(&engine, CF_DEFAULT, &[("a", Some("1")), ("b", Some("2"))]);
+        must_write(&engine, CF_WRITE, &[("c", Some("3"))]);
+        let snap = engine.snapshot();
+        must_write(&engine, CF_DEFAULT, &[("a", None), ("d", Some("4"))]);
+
+        assert!(engine.get_value_cf(CF_DEFAULT, b"a").unwrap().is_none());
+        assert_eq!(&*snap.get_value_cf(CF_DEFAULT, b"a").unwrap().unwrap(), b"1");
+        assert!(engine.get_value_cf(CF_DEFAULT, b"c").unwrap().is_none());
+        assert!(engine.get_value_cf("unknown", b"a").is_err());
+
+        assert_eq!(scan(&engine, CF_DEFAULT, b"", b"z"), vec![b"b".to_vec(), b"d".to_vec()]);
+        assert_eq!(scan(&snap, CF_DEFAULT, b"", b"z"), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(scan(&engine, CF_DEFAULT, b"c", b"z"), vec![b"d".to_vec()]);
+        assert_eq!(
+            engine.seek_cf(CF_DEFAULT, b"c").unwrap(),
+            Some((b"d".to_vec(), b"4".to_vec()))
+        );
+        assert_eq!(engine.seek_cf(CF_DEFAULT, b"e").unwrap(), None);
+
+        let mut wb = engine.write_batch();
+        wb.delete_range_cf(CF_DEFAULT, b"a", b"c").unwrap();
+        engine.write(wb, false).unwrap();
+        assert_eq!(scan(&engine, CF_DEFAULT, b"", b"z"), vec![b"d".to_vec()]);
+
+        let mut it = engine
+            .iterator_cf(CF_WRITE, IterOption::default())
+            .unwrap();
+        assert!(it.seek_to_first());
+        assert_eq!((it.key(), it.value()), (&b"c"[..], &b"3"[..]));
+        assert!(!it.next());
+    }
+
+    #[test]
+    fn test_rocks_engine() {
+        let temp_dir = TempDir::new("test_rocks_engine").unwrap();
+        let db = new_engine(temp_dir.path().to_str().unwrap(), ALL_CFS, None).unwrap();
+        check_engine(RocksEngine::from_db(Arc::new(db)));
+    }
+
+    #[test]
+    fn test_btree_engine() {
+        check_engine(BTreeEngine::new(ALL_CFS));
+    }
+
+    #[test]
+    fn test_multi_rocks_engine() {
+        let temp_dir = TempDir::new("test_multi_rocks_engine").unwrap();
+        let engine = MultiRocksEngine::open(temp_dir.path(), &DbConfig::default(), None).unwrap();
+        engine.split_group(b"b").unwrap();
+        engine.split_group(b"c").unwrap();
+        check_engine(engine);
+    }
+}
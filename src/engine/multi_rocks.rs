@@ -0,0 +1,759 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fs::{self, File};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use rocksdb::{Cache, DBVector, EnvOptions, IngestExternalFileOptions, SstFileWriter};
+use serde_json;
+
+use config::DbConfig;
+use raftstore::Result;
+use storage::ALL_CFS;
+use util::escape;
+use util::file::{calc_crc32, delete_dir_if_exist, get_file_size};
+use util::rocksdb::{get_cf_handle, new_engine_opt};
+
+use super::{
+    BufferedWriteBatch, EngineIterator, IterOption, Iterable, KvEngine, Peekable, RocksEngine,
+    RocksIterator, RocksSnapshot, Snapshot, Write, WriteBatch,
+};
+
+const GROUPS_FILE: &str = "GROUPS";
+const MOVE_BATCH_SIZE: usize = 4 * 1024 * 1024;
+
+// The options the instances are opened with.
+struct Options {
+    cfg: DbConfig,
+    cache: Option<Cache>,
+}
+
+// The block cache of RocksDB is thread safe.
+unsafe impl Send for Options {}
+unsafe impl Sync for Options {}
+
+impl Options {
+    fn open(&self, path: &Path) -> Result<RocksEngine> {
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => return Err(box_err!("invalid path {}", path.display())),
+        };
+        let cfs_opts = self.cfg.build_cf_opts(&self.cache);
+        let db = new_engine_opt(path, self.cfg.build_opt(), cfs_opts)?;
+        Ok(RocksEngine::from_db(Arc::new(db)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GroupMeta {
+    id: u64,
+    start_key: Vec<u8>,
+    end_key: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct Group<T> {
+    id: u64,
+    start_key: Vec<u8>,
+    // An empty end key means unbounded.
+    end_key: Vec<u8>,
+    data: T,
+}
+
+// Returns the index of the group containing `key`. The groups are sorted by their start keys,
+// and the first one starts with the empty key.
+fn find_group<T>(groups: &[Group<T>], key: &[u8]) -> usize {
+    match groups.binary_search_by(|g| g.start_key.as_slice().cmp(key)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    }
+}
+
+fn get_value_cf<T>(groups: &[Group<T>], cf: &str, key: &[u8]) -> Result<Option<DBVector>>
+where
+    T: Peekable<DBVector = DBVector>,
+{
+    groups[find_group(groups, key)].data.get_value_cf(cf, key)
+}
+
+fn new_iterator<T>(groups: &[Group<T>], cf: &str, iter_opt: &IterOption) -> Result<MultiIterator>
+where
+    T: Iterable<Iterator = RocksIterator>,
+{
+    let mut iters = Vec::new();
+    for g in groups {
+        if iter_opt.upper_bound().map_or(false, |u| u <= g.start_key.as_slice()) {
+            break;
+        }
+        let end_key = g.end_key.as_slice();
+        if !end_key.is_empty() && iter_opt.lower_bound().map_or(false, |l| l >= end_key) {
+            continue;
+        }
+        // The iterator is bounded by the group too, so that it never reads the keys left in
+        // the group by an interrupted split.
+        let mut opt = iter_opt.clone();
+        let lower = cmp::max(iter_opt.lower_bound().unwrap_or(b""), g.start_key.as_slice());
+        opt.set_lower_bound(lower.to_vec());
+        if !end_key.is_empty() && iter_opt.upper_bound().map_or(true, |u| u > end_key) {
+            opt.set_upper_bound(end_key.to_vec());
+        }
+        iters.push((lower.to_vec(), g.data.iterator_cf(cf, opt)?));
+    }
+    Ok(MultiIterator { iters, pos: 0 })
+}
+
+// Calls `f` for the keys of the cf in [`start_key`, `end_key`), an empty `end_key` means
+// unbounded.
+fn scan_range<I, F>(
+    engine: &I,
+    cf: &str,
+    start_key: &[u8],
+    end_key: &[u8],
+    mut f: F,
+) -> Result<()>
+where
+    I: Iterable,
+    F: FnMut(&[u8], &[u8]) -> Result<()>,
+{
+    let mut opt = IterOption::new(Some(start_key.to_vec()), None, false);
+    if !end_key.is_empty() {
+        opt.set_upper_bound(end_key.to_vec());
+    }
+    let mut it = engine.iterator_cf(cf, opt)?;
+    it.seek(start_key);
+    while it.valid() {
+        f(it.key(), it.value())?;
+        it.next();
+    }
+    Ok(())
+}
+
+// Copies the keys in the range of `to` from `from` to the instance of `to`.
+fn copy_keys(from: &RocksEngine, to: &Group<RocksEngine>) -> Result<()> {
+    for cf in ALL_CFS {
+        let mut wb = to.data.write_batch();
+        scan_range(from, cf, &to.start_key, &to.end_key, |key, value| {
+            wb.put_cf(cf, key, value)?;
+            if wb.data_size() >= MOVE_BATCH_SIZE {
+                let full = mem::replace(&mut wb, to.data.write_batch());
+                to.data.write(full, false)?;
+            }
+            Ok(())
+        })?;
+        to.data.write(wb, true)?;
+    }
+    Ok(())
+}
+
+fn delete_keys(engine: &RocksEngine, start_key: &[u8], end_key: &[u8]) -> Result<()> {
+    for cf in ALL_CFS {
+        let mut wb = engine.write_batch();
+        scan_range(engine, cf, start_key, end_key, |key, _| {
+            wb.delete_cf(cf, key)?;
+            if wb.data_size() >= MOVE_BATCH_SIZE {
+                let full = mem::replace(&mut wb, engine.write_batch());
+                engine.write(full, false)?;
+            }
+            Ok(())
+        })?;
+        engine.write(wb, true)?;
+    }
+    Ok(())
+}
+
+fn load_groups(path: &Path) -> Result<Vec<GroupMeta>> {
+    let path = path.join(GROUPS_FILE);
+    if !path.exists() {
+        return Ok(vec![GroupMeta {
+            id: 0,
+            start_key: vec![],
+            end_key: vec![],
+        }]);
+    }
+    let f = File::open(&path)?;
+    let metas = box_try!(serde_json::from_reader(f));
+    Ok(metas)
+}
+
+// Saves the layout of the groups atomically.
+fn save_groups(path: &Path, groups: &[Group<RocksEngine>]) -> Result<()> {
+    let metas: Vec<_> = groups
+        .iter()
+        .map(|g| GroupMeta {
+            id: g.id,
+            start_key: g.start_key.clone(),
+            end_key: g.end_key.clone(),
+        })
+        .collect();
+    let tmp_path = path.join(format!("{}.tmp", GROUPS_FILE));
+    {
+        let mut f = File::create(&tmp_path)?;
+        box_try!(serde_json::to_writer(&mut f, &metas));
+        f.sync_all()?;
+    }
+    fs::rename(&tmp_path, path.join(GROUPS_FILE))?;
+    Ok(())
+}
+
+/// An SST file of a column family exported from a group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupFile {
+    pub cf: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+/// The data of a group exported by `MultiRocksEngine::export_group`, which can be ingested
+/// by the group of the same range on another engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupExport {
+    pub start_key: Vec<u8>,
+    pub end_key: Vec<u8>,
+    /// The column families without any key have no file.
+    pub files: Vec<GroupFile>,
+}
+
+struct Groups {
+    next_id: u64,
+    groups: Vec<Group<RocksEngine>>,
+}
+
+/// MultiRocksEngine is an experimental `KvEngine`, which keeps the keys of each group of
+/// regions in a RocksDB instance of its own. So the compactions of a group don't interfere
+/// with the others, and the data of a group can be dropped or moved as files.
+///
+/// Every group covers a range of keys, and the groups cover all the keys without overlapping.
+/// The instance of a group lives in `<path>/<group id>`. All the instances are opened with the
+/// same options and share the block cache if one is given. A write batch spanning several
+/// groups isn't applied atomically, and a snapshot is only consistent within each group.
+#[derive(Clone)]
+pub struct MultiRocksEngine {
+    path: Arc<PathBuf>,
+    opts: Arc<Options>,
+    groups: Arc<RwLock<Groups>>,
+}
+
+impl MultiRocksEngine {
+    /// Opens the engine in `path`. A new engine has one group for all the keys.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        cfg: &DbConfig,
+        cache: Option<Cache>,
+    ) -> Result<MultiRocksEngine> {
+        let path = path.as_ref().to_owned();
+        fs::create_dir_all(&path)?;
+        let mut cfg = cfg.clone();
+        // Every instance keeps its WAL and info log in its own directory.
+        cfg.wal_dir.clear();
+        cfg.info_log_dir.clear();
+        let opts = Options { cfg, cache };
+        let mut groups = Vec::new();
+        for meta in load_groups(&path)? {
+            groups.push(Group {
+                id: meta.id,
+                start_key: meta.start_key,
+                end_key: meta.end_key,
+                data: opts.open(&group_path(&path, meta.id))?,
+            });
+        }
+        save_groups(&path, &groups)?;
+        let next_id = groups.iter().map(|g| g.id).max().unwrap() + 1;
+        Ok(MultiRocksEngine {
+            path: Arc::new(path),
+            opts: Arc::new(opts),
+            groups: Arc::new(RwLock::new(Groups { next_id, groups })),
+        })
+    }
+
+    pub fn group_path(&self, id: u64) -> PathBuf {
+        group_path(&self.path, id)
+    }
+
+    /// Returns the id of the group containing `key`.
+    pub fn group_id(&self, key: &[u8]) -> u64 {
+        let groups = self.groups.read().unwrap();
+        groups.groups[find_group(&groups.groups, key)].id
+    }
+
+    /// Splits the group containing `split_key` at it. The keys >= `split_key` of the group
+    /// are moved to the instance of a new group, whose id is returned.
+    pub fn split_group(&self, split_key: &[u8]) -> Result<u64> {
+        let mut groups = self.groups.write().unwrap();
+        let idx = find_group(&groups.groups, split_key);
+        if groups.groups[idx].start_key == split_key {
+            return Err(box_err!(
+                "group {} already starts with {}",
+                groups.groups[idx].id,
+                escape(split_key)
+            ));
+        }
+        // The directory may be left by an interrupted split.
+        let id = groups.next_id;
+        delete_dir_if_exist(self.group_path(id))?;
+        let mut new_groups = groups.groups.clone();
+        let group = Group {
+            id,
+            start_key: split_key.to_vec(),
+            end_key: new_groups[idx].end_key.clone(),
+            data: self.opts.open(&self.group_path(id))?,
+        };
+        copy_keys(&new_groups[idx].data, &group)?;
+        new_groups[idx].end_key = split_key.to_vec();
+        new_groups.insert(idx + 1, group);
+        save_groups(&self.path, &new_groups)?;
+        groups.groups = new_groups;
+        groups.next_id += 1;
+
+        // The moved keys are out of the range of the group now, they are never read even if
+        // they are not deleted.
+        let g = &groups.groups[idx + 1];
+        if let Err(e) = delete_keys(&groups.groups[idx].data, &g.start_key, &g.end_key) {
+            warn!("failed to delete the keys moved to group {}: {:?}", id, e);
+        }
+        info!("group {} is split at {}", groups.groups[idx].id, escape(split_key));
+        Ok(id)
+    }
+
+    /// Drops the instance of the group and removes its files, so its keys are gone. Its range
+    /// is covered by the previous group, or the next one if it's the first group.
+    pub fn destroy_group(&self, id: u64) -> Result<()> {
+        let mut groups = self.groups.write().unwrap();
+        let idx = match groups.groups.iter().position(|g| g.id == id) {
+            Some(idx) => idx,
+            None => return Err(box_err!("group {} not found", id)),
+        };
+        if groups.groups.len() == 1 {
+            return Err(box_err!("group {} is the only group", id));
+        }
+        let mut new_groups = groups.groups.clone();
+        let group = new_groups.remove(idx);
+        let neighbor = if idx == 0 { 0 } else { idx - 1 };
+        // The neighbor may have the keys left by an interrupted split in the range.
+        delete_keys(&new_groups[neighbor].data, &group.start_key, &group.end_key)?;
+        if idx == 0 {
+            new_groups[0].start_key = group.start_key;
+        } else {
+            new_groups[neighbor].end_key = group.end_key;
+        }
+        save_groups(&self.path, &new_groups)?;
+        groups.groups = new_groups;
+        delete_dir_if_exist(self.group_path(id))?;
+        info!("group {} is destroyed", id);
+        Ok(())
+    }
+}
+
+impl MultiRocksEngine {
+    /// Exports the keys of the group to SST files in `dir`, one for each column family,
+    /// from a snapshot of its instance.
+    ///
+    /// The instance only has the keys of the group, so exporting it is a sequential read of
+    /// its own files, and the files can be copied to another store and ingested wholesale
+    /// there, e.g. to move the replicas of cold regions as files instead of as keys. The
+    /// files of the instance itself can't be ingested, as RocksDB only ingests the files
+    /// written by an `SstFileWriter`.
+    pub fn export_group(&self, id: u64, dir: &Path) -> Result<GroupExport> {
+        let (start_key, end_key, db, snap) = {
+            let groups = self.groups.read().unwrap();
+            let g = match groups.groups.iter().find(|g| g.id == id) {
+                Some(g) => g,
+                None => return Err(box_err!("group {} not found", id)),
+            };
+            let db = Arc::clone(g.data.as_inner());
+            (g.start_key.clone(), g.end_key.clone(), db, g.data.snapshot())
+        };
+        fs::create_dir_all(dir)?;
+        let mut files = Vec::new();
+        for cf in ALL_CFS {
+            let handle = get_cf_handle(&db, cf)?;
+            let path = dir.join(format!("{}_{}.sst", id, cf));
+            let opts = db.get_options_cf(handle).clone();
+            let mut writer = SstFileWriter::new(EnvOptions::new(), opts);
+            box_try!(writer.open(path.to_str().unwrap()));
+            let mut count = 0;
+            // The keys out of the range may be left by an interrupted split.
+            scan_range(&snap, cf, &start_key, &end_key, |key, value| {
+                count += 1;
+                box_try!(writer.put(key, value));
+                Ok(())
+            })?;
+            if count == 0 {
+                // RocksDB can't finish an empty file.
+                continue;
+            }
+            box_try!(writer.finish());
+            files.push(GroupFile {
+                cf: cf.to_string(),
+                size: get_file_size(&path)?,
+                checksum: calc_crc32(&path)?,
+                path,
+            });
+        }
+        info!("group {} is exported to {} files", id, files.len());
+        Ok(GroupExport {
+            start_key,
+            end_key,
+            files,
+        })
+    }
+
+    /// Replaces the keys of the group with the files exported from a group of the same range.
+    /// The files are moved into the instance. Like applying a snapshot, the keys of the group
+    /// must not be read or written in the meantime.
+    pub fn ingest_group(&self, id: u64, export: &GroupExport) -> Result<()> {
+        let groups = self.groups.read().unwrap();
+        let g = match groups.groups.iter().find(|g| g.id == id) {
+            Some(g) => g,
+            None => return Err(box_err!("group {} not found", id)),
+        };
+        if g.start_key != export.start_key || g.end_key != export.end_key {
+            return Err(box_err!(
+                "group {} is [{}, {}), but the files are of [{}, {})",
+                id,
+                escape(&g.start_key),
+                escape(&g.end_key),
+                escape(&export.start_key),
+                escape(&export.end_key)
+            ));
+        }
+        for f in &export.files {
+            let (size, checksum) = (get_file_size(&f.path)?, calc_crc32(&f.path)?);
+            if size != f.size || checksum != f.checksum {
+                return Err(box_err!(
+                    "{} is corrupted, expect size {} checksum {}, got size {} checksum {}",
+                    f.path.display(),
+                    f.size,
+                    f.checksum,
+                    size,
+                    checksum
+                ));
+            }
+        }
+        delete_keys(&g.data, &g.start_key, &g.end_key)?;
+        let db = g.data.as_inner();
+        let mut opts = IngestExternalFileOptions::new();
+        opts.move_files(true);
+        for f in &export.files {
+            let handle = get_cf_handle(db, &f.cf)?;
+            box_try!(db.ingest_external_file_cf(handle, &opts, &[f.path.to_str().unwrap()]));
+        }
+        info!("group {} ingests {} files", id, export.files.len());
+        Ok(())
+    }
+}
+
+fn group_path(path: &Path, id: u64) -> PathBuf {
+    path.join(id.to_string())
+}
+
+impl Peekable for MultiRocksEngine {
+    type DBVector = DBVector;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<DBVector>> {
+        let groups = self.groups.read().unwrap();
+        get_value_cf(&groups.groups, cf, key)
+    }
+}
+
+impl Iterable for MultiRocksEngine {
+    type Iterator = MultiIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<MultiIterator> {
+        let groups = self.groups.read().unwrap();
+        new_iterator(&groups.groups, cf, &iter_opt)
+    }
+}
+
+impl KvEngine for MultiRocksEngine {
+    type Snapshot = MultiSnapshot;
+    type WriteBatch = BufferedWriteBatch;
+
+    fn snapshot(&self) -> MultiSnapshot {
+        let groups = self.groups.read().unwrap();
+        let snaps = groups
+            .groups
+            .iter()
+            .map(|g| Group {
+                id: g.id,
+                start_key: g.start_key.clone(),
+                end_key: g.end_key.clone(),
+                data: g.data.snapshot(),
+            })
+            .collect();
+        MultiSnapshot {
+            groups: Arc::new(snaps),
+        }
+    }
+
+    fn write_batch(&self) -> BufferedWriteBatch {
+        BufferedWriteBatch::default()
+    }
+
+    fn write(&self, wb: BufferedWriteBatch, sync: bool) -> Result<()> {
+        let groups = self.groups.read().unwrap();
+        let groups = &groups.groups;
+        let mut wbs: Vec<_> = groups.iter().map(|g| g.data.write_batch()).collect();
+        for (cf, write) in wb.writes {
+            match write {
+                Write::Put(key, value) => {
+                    wbs[find_group(groups, &key)].put_cf(&cf, &key, &value)?;
+                }
+                Write::Delete(key) => wbs[find_group(groups, &key)].delete_cf(&cf, &key)?,
+                Write::DeleteRange(start, end) => {
+                    for (g, wb) in groups.iter().zip(&mut wbs) {
+                        let start = cmp::max(start.as_slice(), g.start_key.as_slice());
+                        let end = if g.end_key.is_empty() {
+                            end.as_slice()
+                        } else {
+                            cmp::min(end.as_slice(), g.end_key.as_slice())
+                        };
+                        if start < end {
+                            wb.delete_range_cf(&cf, start, end)?;
+                        }
+                    }
+                }
+            }
+        }
+        for (g, wb) in groups.iter().zip(wbs) {
+            if !wb.is_empty() {
+                g.data.write(wb, sync)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cf_names(&self) -> Vec<&str> {
+        ALL_CFS.to_vec()
+    }
+}
+
+/// MultiSnapshot has a snapshot of every group.
+#[derive(Clone)]
+pub struct MultiSnapshot {
+    groups: Arc<Vec<Group<RocksSnapshot>>>,
+}
+
+impl Peekable for MultiSnapshot {
+    type DBVector = DBVector;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<DBVector>> {
+        get_value_cf(&self.groups, cf, key)
+    }
+}
+
+impl Iterable for MultiSnapshot {
+    type Iterator = MultiIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<MultiIterator> {
+        new_iterator(&self.groups, cf, &iter_opt)
+    }
+}
+
+impl Snapshot for MultiSnapshot {}
+
+/// MultiIterator iterates over the groups within the bounds one by one.
+pub struct MultiIterator {
+    // The iterators of the groups with their lower bounds.
+    iters: Vec<(Vec<u8>, RocksIterator)>,
+    pos: usize,
+}
+
+impl MultiIterator {
+    // Moves to the next group until a valid key is found.
+    fn skip_invalid(&mut self) -> bool {
+        while self.pos < self.iters.len() && !self.iters[self.pos].1.valid() {
+            self.pos += 1;
+            if self.pos < self.iters.len() {
+                let (ref lower, ref mut iter) = self.iters[self.pos];
+                iter.seek(lower);
+            }
+        }
+        self.valid()
+    }
+}
+
+impl EngineIterator for MultiIterator {
+    fn seek(&mut self, key: &[u8]) -> bool {
+        self.pos = match self.iters
+            .binary_search_by(|&(ref lower, _)| lower.as_slice().cmp(key))
+        {
+            Ok(pos) => pos,
+            Err(pos) => pos.saturating_sub(1),
+        };
+        if self.pos < self.iters.len() {
+            let (ref lower, ref mut iter) = self.iters[self.pos];
+            iter.seek(cmp::max(lower.as_slice(), key));
+        }
+        self.skip_invalid()
+    }
+
+    fn seek_to_first(&mut self) -> bool {
+        self.pos = 0;
+        if let Some(&mut (ref lower, ref mut iter)) = self.iters.first_mut() {
+            iter.seek(lower);
+        }
+        self.skip_invalid()
+    }
+
+    fn next(&mut self) -> bool {
+        self.iters[self.pos].1.next();
+        self.skip_invalid()
+    }
+
+    fn valid(&self) -> bool {
+        self.pos < self.iters.len() && self.iters[self.pos].1.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iters[self.pos].1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iters[self.pos].1.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rocksdb::LRUCacheOptions;
+    use tempdir::TempDir;
+
+    use storage::{CF_DEFAULT, CF_WRITE};
+
+    fn must_put(engine: &MultiRocksEngine, keys: &[&[u8]]) {
+        let mut wb = engine.write_batch();
+        for k in keys {
+            wb.put_cf(CF_DEFAULT, k, k).unwrap();
+            wb.put_cf(CF_WRITE, k, k).unwrap();
+        }
+        engine.write(wb, true).unwrap();
+    }
+
+    fn scan<I: Iterable>(iterable: &I, start: &[u8], end: &[u8]) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        iterable
+            .scan_cf(CF_DEFAULT, start, end, false, |k, _| {
+                keys.push(k.to_vec());
+                Ok(true)
+            })
+            .unwrap();
+        keys
+    }
+
+    fn group_keys(engine: &MultiRocksEngine, id: u64) -> Vec<Vec<u8>> {
+        let groups = engine.groups.read().unwrap();
+        let g = groups.groups.iter().find(|g| g.id == id).unwrap();
+        let mut keys = Vec::new();
+        let res = scan_range(&g.data, CF_WRITE, b"", b"", |k, _| {
+            keys.push(k.to_vec());
+            Ok(())
+        });
+        res.unwrap();
+        keys
+    }
+
+    fn keys(keys: &[&[u8]]) -> Vec<Vec<u8>> {
+        keys.iter().map(|k| k.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_multi_rocks_engine_groups() {
+        let temp_dir = TempDir::new("test_multi_rocks_engine_groups").unwrap();
+        let mut cache_opts = LRUCacheOptions::new();
+        cache_opts.set_capacity(8 * 1024 * 1024);
+        let cache = Some(Cache::new_lru_cache(cache_opts));
+        let cfg = DbConfig::default();
+        let engine = MultiRocksEngine::open(temp_dir.path(), &cfg, cache).unwrap();
+        must_put(&engine, &[b"a", b"b", b"c", b"d"]);
+
+        // The keys of the new group are moved to its instance.
+        let id = engine.split_group(b"c").unwrap();
+        assert!(engine.split_group(b"c").is_err());
+        assert_eq!(engine.group_id(b"b"), 0);
+        assert_eq!(engine.group_id(b"c"), id);
+        assert_eq!(group_keys(&engine, 0), keys(&[b"a", b"b"]));
+        assert_eq!(group_keys(&engine, id), keys(&[b"c", b"d"]));
+
+        // Reads and writes go across the groups.
+        let snap = engine.snapshot();
+        must_put(&engine, &[b"bb", b"e"]);
+        let all = keys(&[b"a", b"b", b"bb", b"c", b"d", b"e"]);
+        assert_eq!(scan(&engine, b"", b"z"), all);
+        assert_eq!(scan(&snap, b"", b"z"), keys(&[b"a", b"b", b"c", b"d"]));
+        assert_eq!(scan(&engine, b"bb", b"d"), keys(&[b"bb", b"c"]));
+        assert_eq!(&*engine.get_value_cf(CF_DEFAULT, b"e").unwrap().unwrap(), b"e");
+        assert!(snap.get_value_cf(CF_DEFAULT, b"e").unwrap().is_none());
+        let mut it = engine.iterator_cf(CF_DEFAULT, IterOption::default()).unwrap();
+        assert!(it.seek(b"bc"));
+        assert_eq!(it.key(), b"c");
+        assert!(!it.seek(b"f"));
+
+        let mut wb = engine.write_batch();
+        wb.delete_range_cf(CF_DEFAULT, b"b", b"d").unwrap();
+        engine.write(wb, false).unwrap();
+        assert_eq!(scan(&engine, b"", b"z"), keys(&[b"a", b"d", b"e"]));
+
+        // The layout is restored on reopen.
+        drop((engine, snap));
+        let engine = MultiRocksEngine::open(temp_dir.path(), &cfg, None).unwrap();
+        assert_eq!(engine.group_id(b"c"), id);
+        assert_eq!(scan(&engine, b"", b"z"), keys(&[b"a", b"d", b"e"]));
+
+        // The keys of a destroyed group are gone with its files.
+        engine.destroy_group(id).unwrap();
+        assert!(!engine.group_path(id).exists());
+        assert_eq!(engine.group_id(b"c"), 0);
+        assert_eq!(scan(&engine, b"", b"z"), keys(&[b"a"]));
+        must_put(&engine, &[b"c"]);
+        assert_eq!(scan(&engine, b"", b"z"), keys(&[b"a", b"c"]));
+        assert!(engine.destroy_group(id).is_err());
+        assert!(engine.destroy_group(0).is_err());
+    }
+
+    #[test]
+    fn test_multi_rocks_engine_move_group() {
+        let temp_dir = TempDir::new("test_multi_rocks_engine_move_group").unwrap();
+        let cfg = DbConfig::default();
+        let source = MultiRocksEngine::open(temp_dir.path().join("source"), &cfg, None).unwrap();
+        must_put(&source, &[b"a", b"b", b"c", b"d"]);
+        let id = source.split_group(b"b").unwrap();
+        source.split_group(b"d").unwrap();
+
+        let export_dir = temp_dir.path().join("export");
+        let export = source.export_group(id, &export_dir).unwrap();
+        assert!(source.export_group(100, &export_dir).is_err());
+        assert_eq!(export.start_key, b"b".to_vec());
+        assert_eq!(export.end_key, b"d".to_vec());
+        let mut cfs: Vec<_> = export.files.iter().map(|f| f.cf.as_str()).collect();
+        cfs.sort();
+        assert_eq!(cfs, vec![CF_DEFAULT, CF_WRITE]);
+
+        let target = MultiRocksEngine::open(temp_dir.path().join("target"), &cfg, None).unwrap();
+        must_put(&target, &[b"a", b"bb", b"e"]);
+        let target_id = target.split_group(b"b").unwrap();
+        // The range doesn't match.
+        assert!(target.ingest_group(target_id, &export).is_err());
+        target.split_group(b"d").unwrap();
+        target.ingest_group(target_id, &export).unwrap();
+        assert_eq!(group_keys(&target, target_id), keys(&[b"b", b"c"]));
+        assert_eq!(scan(&target, b"", b"z"), keys(&[b"a", b"b", b"c", b"e"]));
+        // The files are moved.
+        assert!(export.files.iter().all(|f| !f.path.exists()));
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use rocksdb::{
+    DBIterator, DBVector, SeekKey, Writable, WriteBatch as RawWriteBatch, WriteOptions, DB,
+};
+
+use raftstore::store::engine::{Peekable as RaftPeekable, SyncSnapshot};
+use raftstore::Result;
+use util::rocksdb::get_cf_handle;
+
+use super::{EngineIterator, IterOption, Iterable, KvEngine, Peekable, Snapshot, WriteBatch};
+
+/// The RocksDB implementation of `KvEngine`.
+#[derive(Clone, Debug)]
+pub struct RocksEngine(Arc<DB>);
+
+impl RocksEngine {
+    pub fn from_db(db: Arc<DB>) -> RocksEngine {
+        RocksEngine(db)
+    }
+
+    pub fn as_inner(&self) -> &Arc<DB> {
+        &self.0
+    }
+}
+
+impl Peekable for RocksEngine {
+    type DBVector = DBVector;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<DBVector>> {
+        RaftPeekable::get_value_cf(&*self.0, cf, key)
+    }
+}
+
+impl Iterable for RocksEngine {
+    type Iterator = RocksIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<RocksIterator> {
+        let handle = get_cf_handle(&self.0, cf)?;
+        let opts = iter_opt.build_read_opts();
+        let iter = DBIterator::new_cf(Arc::clone(&self.0), handle, opts);
+        Ok(RocksIterator(iter))
+    }
+}
+
+impl KvEngine for RocksEngine {
+    type Snapshot = RocksSnapshot;
+    type WriteBatch = RocksWriteBatch;
+
+    fn snapshot(&self) -> RocksSnapshot {
+        RocksSnapshot(SyncSnapshot::new(Arc::clone(&self.0)))
+    }
+
+    fn write_batch(&self) -> RocksWriteBatch {
+        RocksWriteBatch {
+            db: Arc::clone(&self.0),
+            wb: RawWriteBatch::new(),
+        }
+    }
+
+    fn write(&self, wb: RocksWriteBatch, sync: bool) -> Result<()> {
+        let mut opts = WriteOptions::new();
+        opts.set_sync(sync);
+        self.0.write_opt(wb.wb, &opts)?;
+        Ok(())
+    }
+
+    fn cf_names(&self) -> Vec<&str> {
+        self.0.cf_names()
+    }
+}
+
+/// A RocksDB snapshot, which is shared by its clones.
+#[derive(Clone, Debug)]
+pub struct RocksSnapshot(SyncSnapshot);
+
+impl RocksSnapshot {
+    pub fn into_sync(self) -> SyncSnapshot {
+        self.0
+    }
+}
+
+impl Peekable for RocksSnapshot {
+    type DBVector = DBVector;
+
+    fn get_value_cf(&self, cf: &str, key: &[u8]) -> Result<Option<DBVector>> {
+        RaftPeekable::get_value_cf(&*self.0, cf, key)
+    }
+}
+
+impl Iterable for RocksSnapshot {
+    type Iterator = RocksIterator;
+
+    fn iterator_cf(&self, cf: &str, iter_opt: IterOption) -> Result<RocksIterator> {
+        let iter = self.0.db_iterator_cf(cf, iter_opt)?;
+        Ok(RocksIterator(iter))
+    }
+}
+
+impl Snapshot for RocksSnapshot {}
+
+pub struct RocksIterator(DBIterator<Arc<DB>>);
+
+impl EngineIterator for RocksIterator {
+    fn seek(&mut self, key: &[u8]) -> bool {
+        self.0.seek(SeekKey::Key(key))
+    }
+
+    fn seek_to_first(&mut self) -> bool {
+        self.0.seek(SeekKey::Start)
+    }
+
+    fn next(&mut self) -> bool {
+        self.0.next()
+    }
+
+    fn valid(&self) -> bool {
+        self.0.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.0.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.0.value()
+    }
+}
+
+pub struct RocksWriteBatch {
+    db: Arc<DB>,
+    wb: RawWriteBatch,
+}
+
+impl WriteBatch for RocksWriteBatch {
+    fn put_cf(&mut self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let handle = get_cf_handle(&self.db, cf)?;
+        self.wb.put_cf(handle, key, value)?;
+        Ok(())
+    }
+
+    fn delete_cf(&mut self, cf: &str, key: &[u8]) -> Result<()> {
+        let handle = get_cf_handle(&self.db, cf)?;
+        self.wb.delete_cf(handle, key)?;
+        Ok(())
+    }
+
+    fn delete_range_cf(&mut self, cf: &str, start_key: &[u8], end_key: &[u8]) -> Result<()> {
+        let handle = get_cf_handle(&self.db, cf)?;
+        self.wb.delete_range_cf(handle, start_key, end_key)?;
+        Ok(())
+    }
+
+    fn count(&self) -> usize {
+        self.wb.count()
+    }
+
+    fn data_size(&self) -> usize {
+        self.wb.data_size()
+    }
+
+    fn clear(&mut self) {
+        self.wb.clear();
+    }
+}
@@ -58,15 +58,19 @@ pub struct Client {
     pd: Arc<RpcClient>,
     env: Arc<Environment>,
     channels: Mutex<HashMap<u64, Channel>>,
+    security_mgr: Arc<SecurityManager>,
 }
 
 impl Client {
-    pub fn new(pd_addr: &str, cq_count: usize) -> Result<Client> {
+    pub fn new(
+        pd_addr: &str,
+        cq_count: usize,
+        security_mgr: Arc<SecurityManager>,
+    ) -> Result<Client> {
         let cfg = PdConfig {
             endpoints: vec![pd_addr.to_owned()],
         };
-        let sec_mgr = SecurityManager::default();
-        let rpc_client = RpcClient::new(&cfg, Arc::new(sec_mgr))?;
+        let rpc_client = RpcClient::new(&cfg, Arc::clone(&security_mgr))?;
         let env = EnvBuilder::new()
             .name_prefix("import-client")
             .cq_count(cq_count)
@@ -75,6 +79,7 @@ impl Client {
             pd: Arc::new(rpc_client),
             env: Arc::new(env),
             channels: Mutex::new(HashMap::default()),
+            security_mgr,
         })
     }
 
@@ -92,7 +97,7 @@ impl Client {
             HashMapEntry::Vacant(e) => {
                 let store = self.pd.get_store(store_id)?;
                 let builder = ChannelBuilder::new(Arc::clone(&self.env));
-                let channel = builder.connect(store.get_address());
+                let channel = self.security_mgr.connect(builder, store.get_address());
                 Ok(e.insert(channel).clone())
             }
         }
@@ -166,6 +171,7 @@ impl Clone for Client {
             pd: Arc::clone(&self.pd),
             env: Arc::clone(&self.env),
             channels: Mutex::new(HashMap::default()),
+            security_mgr: Arc::clone(&self.security_mgr),
         }
     }
 }
@@ -29,6 +29,32 @@ pub struct Config {
     pub region_split_size: ReadableSize,
     pub stream_channel_window: usize,
     pub max_open_engines: usize,
+    /// Flush an engine being written every so many bytes, so that the writing can be resumed
+    /// from there after a crash. 0 means never flush until the engine is closed.
+    pub engine_flush_chunk_size: ReadableSize,
+    /// The number of RocksDB instances an engine consists of. Every batch is written to one of
+    /// them in turn, so that batches can be written concurrently. It only applies to the
+    /// engines created afterwards.
+    pub num_engine_shards: usize,
+    /// Switch back to the normal mode if the import mode is not refreshed by the importing
+    /// requests for so long.
+    pub import_mode_timeout: ReadableDuration,
+    /// The max bytes of the SST files received or downloaded every second, 0 means unlimited.
+    pub upload_speed_limit: ReadableSize,
+    /// The max bytes of the SST files ingested every second, 0 means unlimited.
+    pub ingest_speed_limit: ReadableSize,
+    /// Verify the checksums of all the blocks of an SST file before ingesting it, besides its
+    /// crc32 and length.
+    pub verify_sst_checksum: bool,
+    /// Roll to a new SST file once the current one generated from an engine reaches this
+    /// size. 0 means all the data of a range and a column family is in one file.
+    pub sst_file_size: ReadableSize,
+    /// The directory on a memory-backed file system, e.g. tmpfs, where the encrypted SST files
+    /// are decrypted to be read, so that their plaintext is never written to disk. It's only
+    /// used with encryption enabled, and is cleared on start, so it can't be shared.
+    pub memory_dir: String,
+    /// Reject the SST files larger than this to download from external storage.
+    pub max_download_size: ReadableSize,
 }
 
 impl Default for Config {
@@ -42,6 +68,15 @@ impl Default for Config {
             region_split_size: ReadableSize::mb(SPLIT_SIZE_MB),
             stream_channel_window: 128,
             max_open_engines: 8,
+            engine_flush_chunk_size: ReadableSize::gb(1),
+            num_engine_shards: 4,
+            import_mode_timeout: ReadableDuration::minutes(10),
+            upload_speed_limit: ReadableSize(0),
+            ingest_speed_limit: ReadableSize(0),
+            verify_sst_checksum: false,
+            sst_file_size: ReadableSize::mb(64),
+            memory_dir: "/dev/shm/tikv-import".to_owned(),
+            max_download_size: ReadableSize::gb(1),
         }
     }
 }
@@ -66,6 +101,15 @@ impl Config {
         if self.max_open_engines == 0 {
             return Err("import.max_open_engines can not be 0".into());
         }
+        if self.num_engine_shards == 0 {
+            return Err("import.num_engine_shards can not be 0".into());
+        }
+        if self.import_mode_timeout.as_secs() == 0 {
+            return Err("import.import_mode_timeout can not be less than 1s".into());
+        }
+        if self.max_download_size.0 == 0 {
+            return Err("import.max_download_size can not be 0".into());
+        }
         Ok(())
     }
 }
@@ -0,0 +1,187 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detects the keys written more than once with different commit timestamps.
+//!
+//! Every key of the imported data is written with the same commit timestamp,
+//! so a key with several committed versions is usually a duplicate primary or
+//! unique key in the source data, which the importer should fail fast on.
+
+use std::u64;
+
+use engine::{EngineIterator, IterOption, Iterable};
+use raftstore::store::keys;
+use storage::mvcc::{Write, WriteType};
+use storage::types::Key;
+use storage::CF_WRITE;
+
+use super::Result;
+
+/// A key that has more than one committed version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateKey {
+    /// The encoded key without timestamp.
+    pub key: Vec<u8>,
+    /// The commit timestamps of the conflicting versions.
+    pub commit_ts: Vec<u64>,
+}
+
+// Lock and rollback records are not versions of the value.
+fn is_committed_version(write: &[u8]) -> Result<bool> {
+    Ok(match Write::parse_type(write)? {
+        WriteType::Put | WriteType::Delete => true,
+        WriteType::Lock | WriteType::Rollback => false,
+    })
+}
+
+fn new_write_iter<E: Iterable>(engine: &E, start: &[u8], end: &[u8]) -> Result<E::Iterator> {
+    let lower = keys::data_key(start);
+    let upper = if end.is_empty() {
+        keys::DATA_MAX_KEY.to_vec()
+    } else {
+        keys::data_key(end)
+    };
+    let iter_opt = IterOption::new(Some(lower.clone()), Some(upper), false);
+    let mut iter = engine.iterator_cf(CF_WRITE, iter_opt)?;
+    iter.seek(&lower);
+    Ok(iter)
+}
+
+/// Scans the keys in [`start`, `end`) of `engine`, and returns at most `limit` keys
+/// which have more than one committed version. An empty `end` means unbounded.
+pub fn scan_duplicate_keys<E: Iterable>(
+    engine: &E,
+    start: &[u8],
+    end: &[u8],
+    limit: usize,
+) -> Result<Vec<DuplicateKey>> {
+    let mut iter = new_write_iter(engine, start, end)?;
+    let mut dups = Vec::new();
+    let mut current: Option<DuplicateKey> = None;
+    while iter.valid() && dups.len() < limit {
+        let (key, commit_ts) = {
+            let (key, commit_ts) = Key::split_on_ts_for(keys::origin_key(iter.key()))?;
+            (key.to_vec(), commit_ts)
+        };
+        if is_committed_version(iter.value())? {
+            match current {
+                Some(ref mut dup) if dup.key == key => dup.commit_ts.push(commit_ts),
+                _ => {
+                    if let Some(dup) = current.take() {
+                        if dup.commit_ts.len() > 1 {
+                            dups.push(dup);
+                        }
+                    }
+                    current = Some(DuplicateKey {
+                        key,
+                        commit_ts: vec![commit_ts],
+                    });
+                }
+            }
+        }
+        iter.next();
+    }
+    if let Some(dup) = current {
+        if dup.commit_ts.len() > 1 && dups.len() < limit {
+            dups.push(dup);
+        }
+    }
+    Ok(dups)
+}
+
+/// Gets the commit timestamps of the committed versions of `key` in `engine`,
+/// newest first.
+pub fn get_commit_ts<E: Iterable>(engine: &E, key: &[u8]) -> Result<Vec<u64>> {
+    let start = Key::from_encoded_slice(key).append_ts(u64::MAX);
+    let mut iter = new_write_iter(engine, start.as_encoded(), &[])?;
+    let mut commit_ts = Vec::new();
+    while iter.valid() {
+        let ts = {
+            let origin_key = keys::origin_key(iter.key());
+            if !Key::is_user_key_eq(origin_key, key) {
+                break;
+            }
+            Key::decode_ts_from(origin_key)?
+        };
+        if is_committed_version(iter.value())? {
+            commit_ts.push(ts);
+        }
+        iter.next();
+    }
+    Ok(commit_ts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    use engine::{BTreeEngine, KvEngine, RocksEngine, WriteBatch};
+    use storage::ALL_CFS;
+    use util::rocksdb::new_engine;
+
+    fn put_write<E: KvEngine>(engine: &E, key: &[u8], commit_ts: u64, tp: WriteType) {
+        let k = Key::from_encoded_slice(key).append_ts(commit_ts);
+        let v = Write::new(tp, commit_ts - 1, None).to_bytes();
+        let mut wb = engine.write_batch();
+        wb.put_cf(CF_WRITE, &keys::data_key(k.as_encoded()), &v)
+            .unwrap();
+        engine.write(wb, false).unwrap();
+    }
+
+    fn check_duplicate_keys<E: KvEngine>(engine: &E) {
+        put_write(engine, b"a", 10, WriteType::Put);
+        put_write(engine, b"b", 10, WriteType::Put);
+        put_write(engine, b"b", 20, WriteType::Put);
+        put_write(engine, b"c", 10, WriteType::Put);
+        put_write(engine, b"c", 20, WriteType::Rollback);
+        put_write(engine, b"d", 10, WriteType::Put);
+        put_write(engine, b"d", 20, WriteType::Delete);
+        put_write(engine, b"d", 30, WriteType::Put);
+
+        let dups = scan_duplicate_keys(engine, b"", b"", 10).unwrap();
+        assert_eq!(
+            dups,
+            vec![
+                DuplicateKey {
+                    key: b"b".to_vec(),
+                    commit_ts: vec![20, 10],
+                },
+                DuplicateKey {
+                    key: b"d".to_vec(),
+                    commit_ts: vec![30, 20, 10],
+                },
+            ]
+        );
+        let dups = scan_duplicate_keys(engine, b"", b"", 1).unwrap();
+        assert_eq!(dups.len(), 1);
+        assert_eq!(dups[0].key, b"b");
+        let dups = scan_duplicate_keys(engine, b"c", b"d", 10).unwrap();
+        assert!(dups.is_empty());
+
+        assert_eq!(get_commit_ts(engine, b"a").unwrap(), vec![10]);
+        assert_eq!(get_commit_ts(engine, b"c").unwrap(), vec![10]);
+        assert_eq!(get_commit_ts(engine, b"d").unwrap(), vec![30, 20, 10]);
+        assert!(get_commit_ts(engine, b"e").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let temp_dir = TempDir::new("test_duplicate_keys").unwrap();
+        let db = new_engine(temp_dir.path().to_str().unwrap(), ALL_CFS, None).unwrap();
+        check_duplicate_keys(&RocksEngine::from_db(Arc::new(db)));
+        check_duplicate_keys(&BTreeEngine::new(ALL_CFS));
+    }
+}
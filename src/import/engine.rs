@@ -13,11 +13,13 @@
 
 use std::cmp;
 use std::fmt;
+use std::fs::{self, File};
 use std::i32;
-use std::io::Read;
+use std::io::{Read, Write as IoWrite};
 use std::ops::Deref;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use uuid::Uuid;
 
@@ -25,7 +27,8 @@ use kvproto::import_kvpb::*;
 use kvproto::import_sstpb::*;
 use rocksdb::{
     BlockBasedOptions, ColumnFamilyOptions, DBIterator, DBOptions, Env, EnvOptions,
-    ExternalSstFileInfo, ReadOptions, SstFileWriter, Writable, WriteBatch as RawBatch, DB,
+    ExternalSstFileInfo, ReadOptions, SeekKey, SstFileWriter, Writable, WriteBatch as RawBatch,
+    DB,
 };
 
 use config::DbConfig;
@@ -33,31 +36,106 @@ use raftstore::store::keys;
 use storage::mvcc::{Write, WriteType};
 use storage::types::Key;
 use storage::{is_short_value, CF_DEFAULT, CF_WRITE};
+use util::collections::HashMap;
 use util::config::MB;
 use util::rocksdb::properties::{SizeProperties, SizePropertiesCollectorFactory};
-use util::rocksdb::{new_engine_opt, CFOptions};
+use util::rocksdb::{db_exist, new_engine_opt, CFOptions};
 
 use super::common::*;
-use super::Result;
+use super::{Error, Result};
+
+/// The file in the engine directory that records the high-water marks.
+const HIGH_WATER_MARK_FILE: &str = "IMPORT_HIGH_WATER_MARK";
+
+/// The write progress of a stream. A write stream is identified by the commit ts of its
+/// batches, which is the only thing in a batch that the client chooses per stream.
+#[derive(Clone, Copy, Default)]
+struct StreamProgress {
+    /// Number of batches written, including the ones that are not flushed yet.
+    written_batches: u64,
+    /// Number of batches that are guaranteed to be persisted.
+    flushed_batches: u64,
+}
+
+#[derive(Default)]
+struct WriteProgress {
+    streams: HashMap<u64, StreamProgress>,
+    unflushed_bytes: usize,
+}
 
 /// Engine wraps rocksdb::DB with customized options to support efficient bulk
 /// write.
+///
+/// Batches are written without WAL, so the engine flushes memtables every `flush_chunk_size`
+/// bytes and then records the number of batches written so far by every stream as its
+/// high-water mark. After a crash, the engine can be reopened and the client only needs to
+/// replay the batches of each stream after its high-water mark. Concurrent streams must use
+/// different commit ts to be resumed separately.
+///
+/// The vector memtable doesn't support concurrent write, so an engine consists of several
+/// RocksDB instances, called shards, and every batch is written to one of them in turn. The
+/// shards are merged by `EngineIterator` when generating SST files.
 pub struct Engine {
-    db: Arc<DB>,
+    /// The first shard is in the engine directory, and the others are in its sub-directories.
+    shards: Vec<Arc<DB>>,
     uuid: Uuid,
     opts: DbConfig,
+    next_shard: AtomicUsize,
+    /// 0 means never flush automatically.
+    flush_chunk_size: usize,
+    progress: Mutex<WriteProgress>,
 }
 
 impl Engine {
     pub fn new<P: AsRef<Path>>(path: P, uuid: Uuid, opts: DbConfig) -> Result<Engine> {
-        let db = {
-            let (db_opts, cf_opts) = tune_dboptions_for_bulk_load(&opts);
-            new_engine_opt(path.as_ref().to_str().unwrap(), db_opts, vec![cf_opts])?
+        Engine::with_shards(path, uuid, opts, 1)
+    }
+
+    /// Creates an engine with `num_shards` shards. An existing engine is reopened with the
+    /// shards it was created with.
+    pub fn with_shards<P: AsRef<Path>>(
+        path: P,
+        uuid: Uuid,
+        opts: DbConfig,
+        num_shards: usize,
+    ) -> Result<Engine> {
+        assert!(num_shards > 0);
+        let path = path.as_ref();
+        let num_shards = if db_exist(path.to_str().unwrap()) {
+            (1..)
+                .take_while(|&i| db_exist(shard_path(path, i).to_str().unwrap()))
+                .count() + 1
+        } else {
+            num_shards
         };
+        let mut shards = Vec::with_capacity(num_shards);
+        for i in 0..num_shards {
+            let (db_opts, cf_opts) = tune_dboptions_for_bulk_load(&opts);
+            let db_path = shard_path(path, i);
+            let db = new_engine_opt(db_path.to_str().unwrap(), db_opts, vec![cf_opts])?;
+            shards.push(Arc::new(db));
+        }
+        let high_water_marks = load_high_water_marks(&high_water_mark_path(path))?;
+        let streams = high_water_marks
+            .into_iter()
+            .map(|(commit_ts, batches)| {
+                let progress = StreamProgress {
+                    written_batches: batches,
+                    flushed_batches: batches,
+                };
+                (commit_ts, progress)
+            })
+            .collect();
         Ok(Engine {
-            db: Arc::new(db),
+            shards,
             uuid,
             opts,
+            next_shard: AtomicUsize::new(0),
+            flush_chunk_size: 0,
+            progress: Mutex::new(WriteProgress {
+                streams,
+                unflushed_bytes: 0,
+            }),
         })
     }
 
@@ -65,6 +143,66 @@ impl Engine {
         self.uuid
     }
 
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Flushes the memtables of all the shards.
+    pub fn flush(&self, sync: bool) -> Result<()> {
+        for db in &self.shards {
+            db.flush(sync)?;
+        }
+        Ok(())
+    }
+
+    /// Flush memtables every `size` bytes written, so that the high-water mark is advanced.
+    pub fn set_flush_chunk_size(&mut self, size: usize) {
+        self.flush_chunk_size = size;
+    }
+
+    /// Returns the number of batches of the stream writing with `commit_ts` that are persisted
+    /// and needn't be written again after reopening the engine.
+    pub fn high_water_mark(&self, commit_ts: u64) -> u64 {
+        let progress = self.progress.lock().unwrap();
+        progress
+            .streams
+            .get(&commit_ts)
+            .map_or(0, |stream| stream.flushed_batches)
+    }
+
+    /// Returns the high-water marks of all the streams, keyed by their commit ts.
+    pub fn high_water_marks(&self) -> HashMap<u64, u64> {
+        let progress = self.progress.lock().unwrap();
+        progress
+            .streams
+            .iter()
+            .map(|(&commit_ts, stream)| (commit_ts, stream.flushed_batches))
+            .collect()
+    }
+
+    /// Flush all written batches and advance the high-water marks.
+    pub fn flush_and_mark(&self) -> Result<()> {
+        let mut progress = self.progress.lock().unwrap();
+        self.flush_progress(&mut progress)
+    }
+
+    fn flush_progress(&self, progress: &mut WriteProgress) -> Result<()> {
+        // Batches counted in `written_batches` are already in the memtables, so they will be
+        // persisted by the flush.
+        self.flush(true)?;
+        let marks = progress
+            .streams
+            .iter()
+            .map(|(&commit_ts, stream)| (commit_ts, stream.written_batches))
+            .collect();
+        save_high_water_marks(&high_water_mark_path(self.path()), &marks)?;
+        for stream in progress.streams.values_mut() {
+            stream.flushed_batches = stream.written_batches;
+        }
+        progress.unflushed_bytes = 0;
+        Ok(())
+    }
+
     pub fn write(&self, mut batch: WriteBatch) -> Result<usize> {
         // Just a guess.
         let wb_cap = cmp::min(batch.get_mutations().len() * 128, MB as usize);
@@ -79,40 +217,167 @@ impl Engine {
             }
         }
 
+        // Concurrent batches are written to different shards.
+        let shard = self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len();
         let size = wb.data_size();
-        self.write_without_wal(wb)?;
+        self.shards[shard].write_without_wal(wb)?;
+
+        let mut progress = self.progress.lock().unwrap();
+        progress
+            .streams
+            .entry(commit_ts)
+            .or_insert_with(StreamProgress::default)
+            .written_batches += 1;
+        progress.unflushed_bytes += size;
+        if self.flush_chunk_size > 0 && progress.unflushed_bytes >= self.flush_chunk_size {
+            self.flush_progress(&mut progress)?;
+        }
 
         Ok(size)
     }
 
-    pub fn new_iter(&self, verify_checksum: bool) -> DBIterator<Arc<DB>> {
-        let mut ropts = ReadOptions::new();
-        ropts.fill_cache(false);
-        ropts.set_verify_checksums(verify_checksum);
-        DBIterator::new(Arc::clone(&self.db), ropts)
+    fn new_shard_iters(&self, verify_checksum: bool) -> Vec<DBIterator<Arc<DB>>> {
+        self.shards
+            .iter()
+            .map(|db| {
+                let mut ropts = ReadOptions::new();
+                ropts.fill_cache(false);
+                ropts.set_verify_checksums(verify_checksum);
+                DBIterator::new(Arc::clone(db), ropts)
+            })
+            .collect()
+    }
+
+    pub fn new_iter(&self, verify_checksum: bool) -> EngineIterator {
+        EngineIterator::new(self.new_shard_iters(verify_checksum))
     }
 
-    pub fn new_sst_writer(&self) -> Result<SSTWriter> {
-        SSTWriter::new(&self.opts)
+    /// Returns the smallest and largest keys in the engine, or `None` if it's empty.
+    pub fn written_range(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+        for mut iter in self.new_shard_iters(false) {
+            if !iter.seek(SeekKey::Start) {
+                continue;
+            }
+            let start = iter.key().to_owned();
+            iter.seek(SeekKey::End);
+            let end = iter.key().to_owned();
+            range = Some(match range {
+                Some((s, e)) => (cmp::min(s, start), cmp::max(e, end)),
+                None => (start, end),
+            });
+        }
+        range
+    }
+
+    /// Creates a writer that rolls to a new SST file every `sst_file_size` bytes, 0 means
+    /// never.
+    pub fn new_sst_writer(&self, sst_file_size: usize) -> Result<SSTWriter> {
+        SSTWriter::new(&self.opts, sst_file_size)
     }
 
     pub fn get_size_properties(&self) -> Result<SizeProperties> {
         let mut res = SizeProperties::default();
-        let collection = self.get_properties_of_all_tables()?;
-        for (_, v) in &*collection {
-            let props = SizeProperties::decode(v.user_collected_properties())?;
-            res.total_size += props.total_size;
-            res.index_handles.extend(props.index_handles.clone());
+        for db in &self.shards {
+            let collection = db.get_properties_of_all_tables()?;
+            for (_, v) in &*collection {
+                let props = SizeProperties::decode(v.user_collected_properties())?;
+                res.total_size += props.total_size;
+                res.index_handles.extend(props.index_handles.clone());
+            }
         }
         Ok(res)
     }
 }
 
+fn shard_path(engine_path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        engine_path.to_owned()
+    } else {
+        engine_path.join(format!("shard-{}", index))
+    }
+}
+
+/// EngineIterator merges the iterators of all the shards of an engine in key order.
+///
+/// A batch replayed after a crash may be written to another shard, so a key can be in several
+/// shards, but it's only returned once.
+pub struct EngineIterator {
+    iters: Vec<DBIterator<Arc<DB>>>,
+    // The index of the iterator with the smallest key.
+    current: Option<usize>,
+}
+
+impl EngineIterator {
+    pub fn new(iters: Vec<DBIterator<Arc<DB>>>) -> EngineIterator {
+        EngineIterator {
+            iters,
+            current: None,
+        }
+    }
+
+    fn update_current(&mut self) -> bool {
+        let mut current = None;
+        for (i, iter) in self.iters.iter().enumerate() {
+            if !iter.valid() {
+                continue;
+            }
+            current = match current {
+                Some(j) if self.iters[j].key() <= iter.key() => Some(j),
+                _ => Some(i),
+            };
+        }
+        self.current = current;
+        current.is_some()
+    }
+
+    /// Seeks to the first key not less than `key`.
+    pub fn seek(&mut self, key: &[u8]) -> bool {
+        for iter in &mut self.iters {
+            iter.seek(SeekKey::Key(key));
+        }
+        self.update_current()
+    }
+
+    pub fn seek_to_first(&mut self) -> bool {
+        for iter in &mut self.iters {
+            iter.seek(SeekKey::Start);
+        }
+        self.update_current()
+    }
+
+    pub fn next(&mut self) -> bool {
+        let key = match self.current {
+            Some(i) => self.iters[i].key().to_owned(),
+            None => return false,
+        };
+        // Skips the same key in the other shards too.
+        for iter in &mut self.iters {
+            if iter.valid() && iter.key() == key.as_slice() {
+                iter.next();
+            }
+        }
+        self.update_current()
+    }
+
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn key(&self) -> &[u8] {
+        self.iters[self.current.unwrap()].key()
+    }
+
+    pub fn value(&self) -> &[u8] {
+        self.iters[self.current.unwrap()].value()
+    }
+}
+
 impl Deref for Engine {
     type Target = DB;
 
     fn deref(&self) -> &Self::Target {
-        &self.db
+        &self.shards[0]
     }
 }
 
@@ -125,6 +390,49 @@ impl fmt::Debug for Engine {
     }
 }
 
+fn high_water_mark_path<P: AsRef<Path>>(engine_path: P) -> PathBuf {
+    engine_path.as_ref().join(HIGH_WATER_MARK_FILE)
+}
+
+/// The high-water marks are saved as lines of `{commit_ts} {batches}`.
+fn load_high_water_marks(path: &Path) -> Result<HashMap<u64, u64>> {
+    let mut marks = HashMap::default();
+    if !path.exists() {
+        return Ok(marks);
+    }
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    for line in s.lines() {
+        let mut fields = line.split_whitespace().map(|f| f.parse::<u64>());
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(commit_ts)), Some(Ok(batches)), None) => {
+                marks.insert(commit_ts, batches);
+            }
+            _ => {
+                return Err(Error::FileCorrupted(
+                    path.to_owned(),
+                    format!("invalid high-water mark {:?}", line),
+                ))
+            }
+        }
+    }
+    Ok(marks)
+}
+
+fn save_high_water_marks(path: &Path, marks: &HashMap<u64, u64>) -> Result<()> {
+    // Write to a temp file and rename it, so that the marks are never half written.
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp_path)?;
+        for (commit_ts, batches) in marks {
+            f.write_all(format!("{} {}\n", commit_ts, batches).as_bytes())?;
+        }
+        f.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
 pub struct SSTInfo {
     pub data: Vec<u8>,
     pub range: Range,
@@ -152,35 +460,97 @@ impl SSTInfo {
     }
 }
 
-pub struct SSTWriter {
+/// Writes the SST files of a column family, and rolls to a new file once the current one
+/// reaches `sst_file_size`, so that a large range doesn't end up in a single huge file.
+struct CfWriter {
     env: Arc<Env>,
-    default: SstFileWriter,
-    default_entries: u64,
-    write: SstFileWriter,
-    write_entries: u64,
+    cfg: DbConfig,
+    cf_name: &'static str,
+    /// 0 means unlimited.
+    sst_file_size: usize,
+    writer: Option<SstFileWriter>,
+    entries: u64,
+    size: usize,
+    seq: u64,
+    infos: Vec<SSTInfo>,
 }
 
-impl SSTWriter {
-    pub fn new(cfg: &DbConfig) -> Result<SSTWriter> {
-        let env = Arc::new(Env::new_mem());
+impl CfWriter {
+    fn new(env: Arc<Env>, cfg: &DbConfig, cf_name: &'static str, sst_file_size: usize) -> CfWriter {
+        CfWriter {
+            env,
+            cfg: cfg.clone(),
+            cf_name,
+            sst_file_size,
+            writer: None,
+            entries: 0,
+            size: 0,
+            seq: 0,
+            infos: Vec::new(),
+        }
+    }
 
-        let mut default_opts = cfg.defaultcf.build_opt();
-        default_opts.set_env(Arc::clone(&env));
-        let mut default = SstFileWriter::new(EnvOptions::new(), default_opts);
-        default.open(CF_DEFAULT)?;
+    fn open(&mut self) -> Result<SstFileWriter> {
+        let mut opts = match self.cf_name {
+            CF_DEFAULT => self.cfg.defaultcf.build_opt(&None),
+            CF_WRITE => self.cfg.writecf.build_opt(&None),
+            _ => unreachable!(),
+        };
+        opts.set_env(Arc::clone(&self.env));
+        let mut writer = SstFileWriter::new(EnvOptions::new(), opts);
+        // Every file needs a distinct name in the memory env.
+        writer.open(&format!("{}.{}", self.cf_name, self.seq))?;
+        self.seq += 1;
+        Ok(writer)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.writer.is_none() {
+            self.writer = Some(self.open()?);
+        }
+        self.writer.as_mut().unwrap().put(key, value)?;
+        self.entries += 1;
+        self.size += key.len() + value.len();
+        if self.sst_file_size > 0 && self.size >= self.sst_file_size {
+            self.finish_file()?;
+        }
+        Ok(())
+    }
 
-        let mut write_opts = cfg.writecf.build_opt();
-        write_opts.set_env(Arc::clone(&env));
-        let mut write = SstFileWriter::new(EnvOptions::new(), write_opts);
-        write.open(CF_WRITE)?;
+    fn finish_file(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            if self.entries > 0 {
+                let info = writer.finish()?;
+                let info = SSTInfo::new(Arc::clone(&self.env), info, self.cf_name)?;
+                self.infos.push(info);
+            }
+        }
+        self.entries = 0;
+        self.size = 0;
+        Ok(())
+    }
 
-        Ok(SSTWriter {
-            env,
-            default,
-            default_entries: 0,
-            write,
-            write_entries: 0,
-        })
+    fn finish(&mut self) -> Result<Vec<SSTInfo>> {
+        self.finish_file()?;
+        Ok(self.infos.drain(..).collect())
+    }
+}
+
+/// SSTWriter converts the kvs of an engine to the SST files of the default and write CF.
+///
+/// The SST files of a CF cover contiguous and non-overlapping sub-ranges of the written keys,
+/// each of them is at most about `sst_file_size` bytes if it's not 0.
+pub struct SSTWriter {
+    default: CfWriter,
+    write: CfWriter,
+}
+
+impl SSTWriter {
+    pub fn new(cfg: &DbConfig, sst_file_size: usize) -> Result<SSTWriter> {
+        let env = Arc::new(Env::new_mem());
+        let default = CfWriter::new(Arc::clone(&env), cfg, CF_DEFAULT, sst_file_size);
+        let write = CfWriter::new(env, cfg, CF_WRITE, sst_file_size);
+        Ok(SSTWriter { default, write })
     }
 
     pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -189,27 +559,19 @@ impl SSTWriter {
         if is_short_value(value) {
             let w = Write::new(WriteType::Put, commit_ts, Some(value.to_vec()));
             self.write.put(&k, &w.to_bytes())?;
-            self.write_entries += 1;
         } else {
             let w = Write::new(WriteType::Put, commit_ts, None);
             self.write.put(&k, &w.to_bytes())?;
-            self.write_entries += 1;
             self.default.put(&k, value)?;
-            self.default_entries += 1;
         }
         Ok(())
     }
 
+    /// Finishes all the SST files, the ones of the default CF come first, and the files of
+    /// the same CF are sorted by range.
     pub fn finish(&mut self) -> Result<Vec<SSTInfo>> {
-        let mut infos = Vec::new();
-        if self.default_entries > 0 {
-            let info = self.default.finish()?;
-            infos.push(SSTInfo::new(Arc::clone(&self.env), info, CF_DEFAULT)?);
-        }
-        if self.write_entries > 0 {
-            let info = self.write.finish()?;
-            infos.push(SSTInfo::new(Arc::clone(&self.env), info, CF_WRITE)?);
-        }
+        let mut infos = self.default.finish()?;
+        infos.extend(self.write.finish()?);
         Ok(infos)
     }
 }
@@ -334,6 +696,112 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sharded_engine() {
+        let dir = TempDir::new("test_import_engine").unwrap();
+        let uuid = Uuid::new_v4();
+        let n = 100;
+        {
+            let engine = Engine::with_shards(dir.path(), uuid, DbConfig::default(), 4).unwrap();
+            for commit_ts in 1..5 {
+                engine.write(new_write_batch(n, commit_ts)).unwrap();
+            }
+            // A replayed batch goes to another shard.
+            engine.write(new_write_batch(n, 4)).unwrap();
+            engine.flush(true).unwrap();
+            // Every batch is written to one shard.
+            for (i, mut iter) in engine.new_shard_iters(false).into_iter().enumerate() {
+                let key = new_encoded_key(0, i as u64 + 1);
+                assert!(iter.seek(SeekKey::Key(&key)));
+                assert_eq!(iter.key(), key.as_slice());
+            }
+        }
+
+        // The shards are kept after reopening.
+        let engine = Engine::new(dir.path(), uuid, DbConfig::default()).unwrap();
+        assert_eq!(engine.num_shards(), 4);
+        let mut iter = engine.new_iter(true);
+        assert!(iter.seek_to_first());
+        for i in 0..n {
+            for commit_ts in (1..5).rev() {
+                assert_eq!(iter.key(), new_encoded_key(i, commit_ts).as_slice());
+                assert_eq!(iter.value(), &[i]);
+                iter.next();
+            }
+        }
+        assert!(!iter.valid());
+        assert!(iter.seek(&new_encoded_key(n / 2, 2)));
+        assert_eq!(iter.key(), new_encoded_key(n / 2, 2).as_slice());
+
+        let start = new_encoded_key(0, 4);
+        let end = new_encoded_key(n - 1, 1);
+        assert_eq!(engine.written_range(), Some((start, end)));
+    }
+
+    #[test]
+    fn test_high_water_mark() {
+        let dir = TempDir::new("test_import_engine").unwrap();
+        let uuid = Uuid::new_v4();
+        {
+            let mut engine = Engine::new(dir.path(), uuid, DbConfig::default()).unwrap();
+            let size = engine.write(new_write_batch(10, 1)).unwrap();
+            assert_eq!(engine.high_water_mark(1), 0);
+            engine.set_flush_chunk_size(size * 3);
+            // Two streams write concurrently.
+            engine.write(new_write_batch(10, 2)).unwrap();
+            engine.write(new_write_batch(10, 1)).unwrap();
+            assert_eq!(engine.high_water_mark(1), 0);
+            assert_eq!(engine.high_water_mark(2), 0);
+            // Flushed at the chunk boundary.
+            engine.write(new_write_batch(10, 2)).unwrap();
+            assert_eq!(engine.high_water_mark(1), 2);
+            assert_eq!(engine.high_water_mark(2), 2);
+            engine.write(new_write_batch(10, 1)).unwrap();
+            assert_eq!(engine.high_water_mark(1), 2);
+        }
+
+        // The high-water marks are kept after reopening.
+        let engine = Engine::new(dir.path(), uuid, DbConfig::default()).unwrap();
+        let marks = engine.high_water_marks();
+        assert_eq!(marks.len(), 2);
+        assert_eq!(marks[&1], 2);
+        assert_eq!(marks[&2], 2);
+        assert_eq!(engine.high_water_mark(3), 0);
+        for i in 0..10 {
+            let key = new_encoded_key(i, 2);
+            assert_eq!(engine.get(&key).unwrap().unwrap(), &[i]);
+        }
+        engine.write(new_write_batch(10, 1)).unwrap();
+        engine.write(new_write_batch(10, 3)).unwrap();
+        engine.flush_and_mark().unwrap();
+        assert_eq!(engine.high_water_mark(1), 3);
+        assert_eq!(engine.high_water_mark(2), 2);
+        assert_eq!(engine.high_water_mark(3), 1);
+    }
+
+    #[test]
+    fn test_engine_reopen() {
+        let dir = TempDir::new("test_import_engine").unwrap();
+        let uuid = Uuid::new_v4();
+        let n = 10;
+        let commit_ts = 10;
+        {
+            let opts = DbConfig::default();
+            let engine = Engine::with_shards(dir.path(), uuid, opts, 2).unwrap();
+            engine.write(new_write_batch(n, commit_ts)).unwrap();
+            engine.flush(true).unwrap();
+        }
+
+        // The shards are kept when it's reopened.
+        let opts = DbConfig::default();
+        let engine = Engine::with_shards(dir.path(), uuid, opts, 1).unwrap();
+        assert_eq!(engine.num_shards(), 2);
+        for i in 0..n {
+            let key = new_encoded_key(i, commit_ts);
+            assert_eq!(engine.get(&key).unwrap().unwrap(), &[i]);
+        }
+    }
+
     #[test]
     fn test_sst_writer() {
         test_sst_writer_with(1, &[CF_WRITE]);
@@ -345,13 +813,13 @@ mod tests {
 
         let cfg = DbConfig::default();
         let db_opts = cfg.build_opt();
-        let cfs_opts = cfg.build_cf_opts();
+        let cfs_opts = cfg.build_cf_opts(&None);
         let db = new_engine_opt(temp_dir.path().to_str().unwrap(), db_opts, cfs_opts).unwrap();
         let db = Arc::new(db);
 
         let n = 10;
         let commit_ts = 10;
-        let mut w = SSTWriter::new(&cfg).unwrap();
+        let mut w = SSTWriter::new(&cfg, 0).unwrap();
 
         // Write some keys.
         let value = vec![1u8; value_size];
@@ -403,6 +871,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sst_writer_split() {
+        let cfg = DbConfig::default();
+        let n = 10;
+        let commit_ts = 10;
+        let value = vec![1u8; 1024];
+        // Every default CF file contains 3 entries, but all the write CF entries are small
+        // enough to be in one file.
+        let mut w = SSTWriter::new(&cfg, 3 * 1024).unwrap();
+        for i in 0..n {
+            let key = new_encoded_key(i, commit_ts);
+            w.put(&key, &value).unwrap();
+        }
+
+        let infos = w.finish().unwrap();
+        let (default, write): (Vec<_>, Vec<_>) =
+            infos.iter().partition(|info| info.cf_name == CF_DEFAULT);
+        assert_eq!(write.len(), 1);
+        assert_eq!(default.len(), 4);
+        let expected = [(0, 2), (3, 5), (6, 8), (9, 9)];
+        for (info, &(start, end)) in default.iter().zip(expected.iter()) {
+            let start = new_encoded_key(start, commit_ts);
+            let end = new_encoded_key(end, commit_ts);
+            assert_eq!(info.range.get_start(), start.as_slice());
+            assert_eq!(info.range.get_end(), end.as_slice());
+        }
+    }
+
     const SIZE_INDEX_DISTANCE: usize = 4 * 1024 * 1024;
 
     #[test]
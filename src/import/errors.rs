@@ -24,7 +24,9 @@ use uuid::{ParseError, Uuid};
 
 use pd::{Error as PdError, RegionInfo};
 use raftstore::errors::Error as RaftStoreError;
+use storage::mvcc::Error as MvccError;
 use util::codec::Error as CodecError;
+use util::encryption::Error as EncryptionError;
 
 quick_error! {
     #[derive(Debug)]
@@ -62,6 +64,16 @@ quick_error! {
             cause(err)
             description(err.description())
         }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Encryption(err: EncryptionError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
         ParseIntError(err: ParseIntError) {
             from()
             cause(err)
@@ -76,9 +88,15 @@ quick_error! {
         FileCorrupted(path: PathBuf, reason: String) {
             display("File {:?} corrupted: {}", path, reason)
         }
+        FileTooLarge(name: String, size: u64, limit: u64) {
+            display("File {} of {} bytes is larger than {} bytes", name, size, limit)
+        }
         InvalidSSTPath(path: PathBuf) {
             display("Invalid SST path {:?}", path)
         }
+        NotMemoryBacked(path: PathBuf) {
+            display("Directory {:?} is not on a memory-backed file system", path)
+        }
         EngineInUse(uuid: Uuid) {
             display("Engine {} is in use", uuid)
         }
@@ -89,6 +107,12 @@ quick_error! {
             display("Invalid proto message {}", reason)
         }
         InvalidChunk {}
+        InvalidStorageUrl(url: String, reason: String) {
+            display("Invalid external storage url {}: {}", url, reason)
+        }
+        KeyNotRewritable(key: Vec<u8>, prefix: Vec<u8>) {
+            display("Key {:?} doesn't have the prefix {:?} to rewrite", key, prefix)
+        }
         PdRPC(err: PdError) {
             from()
             cause(err)
@@ -0,0 +1,421 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use url::Url;
+
+use util::aws::{self, Credentials};
+
+use super::{Error, Result};
+
+const S3_SERVICE: &str = "s3";
+const S3_REQUEST_TIMEOUT_SECS: u64 = 60;
+const MAX_RESPONSE_HEADER_SIZE: usize = 64 * 1024;
+// Only the beginning of the body of an error response is kept in the error.
+const MAX_ERROR_BODY_SIZE: u64 = 4 * 1024;
+
+/// ExternalStorage is a storage outside of TiKV, which the SST files to be
+/// restored are downloaded from, and the backup files are uploaded to.
+pub trait ExternalStorage: Send + Sync {
+    /// Opens the file `name` for reading.
+    fn read(&self, name: &str) -> io::Result<Box<Read + Send>>;
+
+    /// Writes all the data of `reader` to the file `name`.
+    fn write(&self, name: &str, reader: &mut Read) -> io::Result<()>;
+}
+
+/// LocalStorage reads files from a local directory, which is usually a mounted
+/// network file system shared by all TiKV instances.
+pub struct LocalStorage {
+    base: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(base: P) -> LocalStorage {
+        LocalStorage {
+            base: base.as_ref().to_owned(),
+        }
+    }
+}
+
+impl LocalStorage {
+    fn path_of(&self, name: &str) -> io::Result<PathBuf> {
+        let path = self.base.join(name);
+        // Do not allow the name to escape from the base directory.
+        if !path.starts_with(&self.base) || Path::new(name).is_absolute() || name.contains("..") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file name {}", name),
+            ));
+        }
+        Ok(path)
+    }
+}
+
+impl ExternalStorage for LocalStorage {
+    fn read(&self, name: &str) -> io::Result<Box<Read + Send>> {
+        Ok(box File::open(self.path_of(name)?)?)
+    }
+
+    fn write(&self, name: &str, reader: &mut Read) -> io::Result<()> {
+        let path = self.path_of(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temp file and rename it, so that the file is never half written.
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut f = File::create(&tmp_path)?;
+            io::copy(reader, &mut f)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)
+    }
+}
+
+/// S3Storage reads and writes the objects under a prefix of a bucket in S3, or a service
+/// compatible with it. Like the AWS KMS client, it signs the requests by the AWS Signature
+/// Version 4 and sends them over plain HTTP to an endpoint on the loopback interface, which is
+/// usually a local proxy forwarding them to S3 over TLS. The credentials are read from the
+/// environment variables, so that they never show up in the urls.
+pub struct S3Storage {
+    bucket: String,
+    prefix: String,
+    region: String,
+    endpoint: Url,
+    host: String,
+    credentials: Credentials,
+    timeout: Duration,
+}
+
+impl S3Storage {
+    /// Creates the storage of `url`, like
+    /// "s3://bucket/prefix?endpoint=http://127.0.0.1:9000&region=us-east-1".
+    pub fn new(url: &Url) -> result::Result<S3Storage, String> {
+        S3Storage::with_credentials(url, Credentials::new("", "")?)
+    }
+
+    fn with_credentials(url: &Url, credentials: Credentials) -> result::Result<S3Storage, String> {
+        let bucket = match url.host_str() {
+            Some(bucket) if !bucket.is_empty() => bucket.to_owned(),
+            _ => return Err("no bucket".to_owned()),
+        };
+        let (mut endpoint, mut region) = (None, None);
+        for (name, value) in url.query_pairs() {
+            match &*name {
+                "endpoint" => endpoint = Some(aws::parse_endpoint(&value)?),
+                "region" => region = Some(value.into_owned()),
+                name => return Err(format!("unknown parameter {}", name)),
+            }
+        }
+        let (endpoint, region) = match (endpoint, region) {
+            (Some(endpoint), Some(region)) => (endpoint, region),
+            _ => return Err("both endpoint and region should be set".to_owned()),
+        };
+        let host = match aws::host_header(&endpoint) {
+            Some(host) => host,
+            None => return Err(format!("no host in endpoint {}", endpoint)),
+        };
+        Ok(S3Storage {
+            bucket,
+            prefix: url.path().trim_matches('/').to_owned(),
+            region,
+            endpoint,
+            host,
+            credentials,
+            timeout: Duration::from_secs(S3_REQUEST_TIMEOUT_SECS),
+        })
+    }
+
+    // The URI encoded path of the object `name` in a path-style request.
+    fn object_path(&self, name: &str) -> io::Result<String> {
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|s| s == "..") {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid file name {}", name),
+            ));
+        }
+        let mut path = format!("/{}", uri_encode(&self.bucket));
+        let segments = self.prefix.split('/').chain(name.split('/'));
+        for segment in segments.filter(|s| !s.is_empty()) {
+            path.push('/');
+            path.push_str(&uri_encode(segment));
+        }
+        Ok(path)
+    }
+
+    // Sends a request of the object `name`, and returns the status code and the headers of
+    // the response, with the connection to read its body from.
+    fn request(
+        &self,
+        method: &str,
+        name: &str,
+        payload: &[u8],
+    ) -> io::Result<(u16, Vec<(String, String)>, BufReader<TcpStream>)> {
+        let path = self.object_path(name)?;
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut headers = vec![
+            ("host".to_owned(), self.host.clone()),
+            ("x-amz-content-sha256".to_owned(), aws::sha256_hex(payload)),
+            ("x-amz-date".to_owned(), amz_date.clone()),
+        ];
+        if let Some(ref token) = self.credentials.session_token {
+            headers.push(("x-amz-security-token".to_owned(), token.clone()));
+        }
+        let authorization = self.credentials.sign(
+            &self.region,
+            S3_SERVICE,
+            &amz_date,
+            method,
+            &path,
+            &headers,
+            payload,
+        );
+        headers.push(("authorization".to_owned(), authorization));
+
+        let host = self.endpoint.host_str().unwrap();
+        let port = self.endpoint.port_or_known_default().unwrap_or(80);
+        let mut stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut request = format!("{} {} HTTP/1.1\r\n", method, path);
+        for &(ref name, ref value) in &headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!(
+            "content-length: {}\r\nconnection: close\r\n\r\n",
+            payload.len()
+        ));
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(payload)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            let n = reader.read_until(b'\n', &mut header)?;
+            if n == 0 || header.len() > MAX_RESPONSE_HEADER_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "invalid response header"));
+            }
+        }
+        let (status, headers) = aws::parse_response_header(&header[..header.len() - 4])?;
+        Ok((status, headers, reader))
+    }
+}
+
+// Encodes a segment of the URI path as the AWS Signature Version 4 requires.
+fn uri_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn response_error<R: Read>(status: u16, body: R) -> io::Error {
+    let mut data = Vec::new();
+    let _ = body.take(MAX_ERROR_BODY_SIZE).read_to_end(&mut data);
+    let kind = if status == 404 {
+        ErrorKind::NotFound
+    } else {
+        ErrorKind::Other
+    };
+    let msg = format!("status {}: {}", status, String::from_utf8_lossy(&data));
+    io::Error::new(kind, msg)
+}
+
+impl ExternalStorage for S3Storage {
+    fn read(&self, name: &str) -> io::Result<Box<Read + Send>> {
+        let (status, headers, reader) = self.request("GET", name, b"")?;
+        if status != 200 {
+            return Err(response_error(status, reader));
+        }
+        let length = headers
+            .iter()
+            .find(|h| h.0 == "content-length")
+            .and_then(|h| h.1.parse().ok());
+        match length {
+            Some(length) => Ok(box reader.take(length)),
+            None => Err(io::Error::new(ErrorKind::InvalidData, "no content length")),
+        }
+    }
+
+    /// The file is read into memory first, because the payload is signed.
+    fn write(&self, name: &str, reader: &mut Read) -> io::Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let (status, _, reader) = self.request("PUT", name, &data)?;
+        if status != 200 {
+            return Err(response_error(status, reader));
+        }
+        Ok(())
+    }
+}
+
+/// Creates the external storage of `url`, like "local:///path/to/dir", or
+/// "s3://bucket/prefix?endpoint=http://127.0.0.1:9000&region=us-east-1".
+///
+/// "gcs" is not supported yet.
+pub fn create_storage(url: &str) -> Result<Arc<ExternalStorage>> {
+    let u = match Url::parse(url) {
+        Ok(u) => u,
+        Err(e) => return Err(Error::InvalidStorageUrl(url.to_owned(), format!("{}", e))),
+    };
+    match u.scheme() {
+        "local" | "file" => Ok(Arc::new(LocalStorage::new(u.path()))),
+        "s3" => match S3Storage::new(&u) {
+            Ok(s) => Ok(Arc::new(s)),
+            Err(e) => Err(Error::InvalidStorageUrl(url.to_owned(), e)),
+        },
+        scheme => Err(Error::InvalidStorageUrl(
+            url.to_owned(),
+            format!("unsupported scheme {}", scheme),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_local_storage() {
+        let temp_dir = TempDir::new("test_local_storage").unwrap();
+        File::create(temp_dir.path().join("a.sst"))
+            .unwrap()
+            .write_all(b"abc")
+            .unwrap();
+
+        let url = format!("local://{}", temp_dir.path().display());
+        let storage = create_storage(&url).unwrap();
+        let mut data = Vec::new();
+        storage
+            .read("a.sst")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"abc");
+
+        assert!(storage.read("b.sst").is_err());
+        storage.write("dir/b.sst", &mut &b"def"[..]).unwrap();
+        data.clear();
+        storage
+            .read("dir/b.sst")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"def");
+        assert!(storage.write("../b.sst", &mut &b"def"[..]).is_err());
+        assert!(storage.read("../a.sst").is_err());
+        assert!(storage.read("/etc/passwd").is_err());
+
+        assert!(create_storage("s3://bucket/prefix").is_err());
+        assert!(create_storage("gcs://bucket/prefix").is_err());
+        assert!(create_storage("not a url").is_err());
+    }
+
+    // Serves `n` requests of the objects in `objects`, and checks they are signed.
+    fn serve_s3(listener: TcpListener, objects: Arc<Mutex<HashMap<String, Vec<u8>>>>, n: usize) {
+        for stream in listener.incoming().take(n) {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut header = String::new();
+            while !header.ends_with("\r\n\r\n") {
+                reader.read_line(&mut header).unwrap();
+            }
+            let (method, path) = {
+                let mut parts = header.split(' ');
+                (parts.next().unwrap().to_owned(), parts.next().unwrap().to_owned())
+            };
+            assert!(header.contains("/us-east-1/s3/aws4_request"), "{}", header);
+            let length: usize = header
+                .lines()
+                .find(|l| l.starts_with("content-length: "))
+                .map(|l| l["content-length: ".len()..].parse().unwrap())
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut objects = objects.lock().unwrap();
+            let resp = if method == "PUT" {
+                objects.insert(path, body);
+                b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_vec()
+            } else if let Some(data) = objects.get(&path) {
+                let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", data.len());
+                let mut resp = header.into_bytes();
+                resp.extend_from_slice(data);
+                resp
+            } else {
+                b"HTTP/1.1 404 Not Found\r\ncontent-length: 9\r\n\r\nNoSuchKey".to_vec()
+            };
+            reader.get_mut().write_all(&resp).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_s3_storage() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "s3://bucket/backup/1?endpoint=http://{}&region=us-east-1",
+            listener.local_addr().unwrap()
+        );
+        let objects = Arc::new(Mutex::new(HashMap::new()));
+        let objects1 = Arc::clone(&objects);
+        let h = thread::spawn(move || serve_s3(listener, objects1, 3));
+
+        let credentials = Credentials {
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_key: "secret".to_owned(),
+            session_token: None,
+        };
+        let storage = S3Storage::with_credentials(&Url::parse(&url).unwrap(), credentials).unwrap();
+        storage.write("dir/a b.sst", &mut &b"abc"[..]).unwrap();
+        assert_eq!(objects.lock().unwrap()["/bucket/backup/1/dir/a%20b.sst"], b"abc");
+        let mut data = Vec::new();
+        storage
+            .read("dir/a b.sst")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"abc");
+        match storage.read("b.sst") {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("b.sst should not exist"),
+        }
+        assert!(storage.read("../a.sst").is_err());
+        assert!(storage.write("/a.sst", &mut &b"abc"[..]).is_err());
+        h.join().unwrap();
+
+        // The endpoint must be on the loopback interface.
+        let url = "s3://bucket/prefix?endpoint=http://10.0.0.1&region=us-east-1";
+        assert!(create_storage(url).is_err());
+        assert!(create_storage("s3://bucket/prefix?region=us-east-1").is_err());
+    }
+}
@@ -11,24 +11,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::{Duration, Instant};
+
 use kvproto::import_sstpb::*;
 use rocksdb::DB;
 
 use super::Result;
 
+/// ImportModeSwitcher switches the RocksDB options between the normal mode and
+/// the import mode, which is optimized for ingesting lots of SST files.
+///
+/// The import mode should be refreshed by the importing requests, otherwise it
+/// times out and should be switched back to the normal mode, in case the
+/// importing tool exits without switching back.
 pub struct ImportModeSwitcher {
     mode: SwitchMode,
     backup_options: Vec<(String, ImportModeOptions)>,
+    timeout: Duration,
+    deadline: Instant,
 }
 
 impl ImportModeSwitcher {
-    pub fn new() -> ImportModeSwitcher {
+    pub fn new(timeout: Duration) -> ImportModeSwitcher {
         ImportModeSwitcher {
             mode: SwitchMode::Normal,
             backup_options: Vec::new(),
+            timeout,
+            deadline: Instant::now(),
+        }
+    }
+
+    /// Extends the import mode, if it is on, by another timeout.
+    pub fn refresh(&mut self) {
+        if self.mode == SwitchMode::Import {
+            self.deadline = Instant::now() + self.timeout;
         }
     }
 
+    /// Checks whether the import mode has not been refreshed for the timeout.
+    pub fn is_timeout(&self) -> bool {
+        self.mode == SwitchMode::Import && Instant::now() >= self.deadline
+    }
+
     pub fn enter_normal_mode(&mut self, db: &DB) -> Result<()> {
         if self.mode == SwitchMode::Normal {
             return Ok(());
@@ -44,6 +68,7 @@ impl ImportModeSwitcher {
 
     pub fn enter_import_mode(&mut self, db: &DB) -> Result<()> {
         if self.mode == SwitchMode::Import {
+            self.refresh();
             return Ok(());
         }
 
@@ -56,6 +81,7 @@ impl ImportModeSwitcher {
         }
 
         self.mode = SwitchMode::Import;
+        self.refresh();
         Ok(())
     }
 }
@@ -67,6 +93,7 @@ struct ImportModeOptions {
     level0_slowdown_writes_trigger: u32,
     soft_pending_compaction_bytes_limit: u64,
     hard_pending_compaction_bytes_limit: u64,
+    max_write_buffer_number: u32,
 }
 
 impl ImportModeOptions {
@@ -78,6 +105,9 @@ impl ImportModeOptions {
             level0_slowdown_writes_trigger: 1 << 30,
             soft_pending_compaction_bytes_limit: 0,
             hard_pending_compaction_bytes_limit: 0,
+            // Allows more memtables to absorb the writes while flushes fall
+            // behind, since the ingested files don't go through memtables.
+            max_write_buffer_number: 8,
         }
     }
 
@@ -92,6 +122,7 @@ impl ImportModeOptions {
             level0_slowdown_writes_trigger: cf_opts.get_level_zero_slowdown_writes_trigger(),
             soft_pending_compaction_bytes_limit: cf_opts.get_soft_pending_compaction_bytes_limit(),
             hard_pending_compaction_bytes_limit: cf_opts.get_hard_pending_compaction_bytes_limit(),
+            max_write_buffer_number: cf_opts.get_max_write_buffer_number(),
         }
     }
 
@@ -121,6 +152,10 @@ impl ImportModeOptions {
                 "hard_pending_compaction_bytes_limit".to_owned(),
                 self.hard_pending_compaction_bytes_limit.to_string(),
             ),
+            (
+                "max_write_buffer_number".to_owned(),
+                self.max_write_buffer_number.to_string(),
+            ),
         ];
 
         let tmp_opts: Vec<_> = opts.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
@@ -133,6 +168,7 @@ impl ImportModeOptions {
 mod tests {
     use super::*;
 
+    use std::thread;
     use tempdir::TempDir;
     use util::rocksdb::new_engine;
 
@@ -153,6 +189,10 @@ mod tests {
                 cf_opts.get_level_zero_slowdown_writes_trigger(),
                 opts.level0_slowdown_writes_trigger
             );
+            assert_eq!(
+                cf_opts.get_max_write_buffer_number(),
+                opts.max_write_buffer_number
+            );
             // TODO: https://github.com/facebook/rocksdb/pull/3823
             // These options are set correctly, but we can't get them
             // because of the issue above.
@@ -175,7 +215,7 @@ mod tests {
         let import_options = ImportModeOptions::new();
         let normal_options = ImportModeOptions::new_options_cf(&db, "default");
 
-        let mut switcher = ImportModeSwitcher::new();
+        let mut switcher = ImportModeSwitcher::new(Duration::from_secs(60));
         check_import_options(&db, &normal_options);
         switcher.enter_import_mode(&db).unwrap();
         check_import_options(&db, &import_options);
@@ -186,4 +226,23 @@ mod tests {
         switcher.enter_normal_mode(&db).unwrap();
         check_import_options(&db, &normal_options);
     }
+
+    #[test]
+    fn test_import_mode_timeout() {
+        let temp_dir = TempDir::new("test_import_mode_timeout").unwrap();
+        let db = new_engine(temp_dir.path().to_str().unwrap(), &["a", "b"], None).unwrap();
+
+        let mut switcher = ImportModeSwitcher::new(Duration::from_millis(100));
+        assert!(!switcher.is_timeout());
+        switcher.enter_import_mode(&db).unwrap();
+        assert!(!switcher.is_timeout());
+        thread::sleep(Duration::from_millis(60));
+        switcher.refresh();
+        thread::sleep(Duration::from_millis(60));
+        assert!(!switcher.is_timeout());
+        thread::sleep(Duration::from_millis(60));
+        assert!(switcher.is_timeout());
+        switcher.enter_normal_mode(&db).unwrap();
+        assert!(!switcher.is_timeout());
+    }
 }
@@ -12,15 +12,18 @@
 // limitations under the License.
 
 use std::fmt;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use kvproto::import_kvpb::*;
+use serde_json;
 use uuid::Uuid;
 
 use config::DbConfig;
 use util::collections::HashMap;
+use util::security::SecurityManager;
 
 use super::client::*;
 use super::engine::*;
@@ -37,11 +40,24 @@ pub struct KVImporter {
     cfg: Config,
     dir: EngineDir,
     inner: Mutex<Inner>,
+    security_mgr: Arc<SecurityManager>,
 }
 
 impl KVImporter {
-    pub fn new(cfg: Config, opts: DbConfig) -> Result<KVImporter> {
-        let dir = EngineDir::new(&cfg.import_dir, opts)?;
+    pub fn new(
+        cfg: Config,
+        opts: DbConfig,
+        security_mgr: Arc<SecurityManager>,
+    ) -> Result<KVImporter> {
+        let dir = EngineDir::new(
+            &cfg.import_dir,
+            opts,
+            cfg.engine_flush_chunk_size.0 as usize,
+            cfg.num_engine_shards,
+        )?;
+        for meta in dir.recover()? {
+            info!("recover {:?}", meta);
+        }
         Ok(KVImporter {
             cfg,
             dir,
@@ -49,6 +65,7 @@ impl KVImporter {
                 engines: HashMap::default(),
                 import_jobs: HashMap::default(),
             }),
+            security_mgr,
         })
     }
 
@@ -68,7 +85,11 @@ impl KVImporter {
 
         match self.dir.open(uuid) {
             Ok(engine) => {
-                info!("open {:?}", engine);
+                info!(
+                    "open {:?}, high-water marks {:?}",
+                    engine,
+                    engine.high_water_marks()
+                );
                 inner.engines.insert(uuid, Arc::new(engine));
                 Ok(())
             }
@@ -88,6 +109,19 @@ impl KVImporter {
         }
     }
 
+    /// Returns the number of batches written to the engine with `commit_ts` that survive
+    /// crashes. After the engine is reopened, the client only needs to write the batches of
+    /// that stream after it again.
+    pub fn engine_high_water_mark(&self, uuid: Uuid, commit_ts: u64) -> Result<u64> {
+        self.bind_engine(uuid).map(|engine| engine.high_water_mark(commit_ts))
+    }
+
+    /// Returns the metadata of all the engines in the import directory, including the ones left
+    /// by the last process, so that the client can decide to resume or clean them up.
+    pub fn list_engines(&self) -> Result<Vec<EngineMeta>> {
+        self.dir.list()
+    }
+
     /// Close the engine.
     /// Engine can not be closed when it is writing.
     pub fn close_engine(&self, uuid: Uuid) -> Result<()> {
@@ -121,19 +155,33 @@ impl KVImporter {
     /// Import the engine to TiKV stores.
     /// Engine can not be imported before it is closed.
     pub fn import_engine(&self, uuid: Uuid, pd_addr: &str) -> Result<()> {
-        let client = Client::new(pd_addr, self.cfg.num_import_jobs)?;
+        let client = Client::new(
+            pd_addr,
+            self.cfg.num_import_jobs,
+            Arc::clone(&self.security_mgr),
+        )?;
         let job = {
             let mut inner = self.inner.lock().unwrap();
             if inner.engines.contains_key(&uuid) || inner.import_jobs.contains_key(&uuid) {
                 return Err(Error::EngineInUse(uuid));
             }
             let engine = self.dir.import(uuid)?;
+            self.dir.set_state(uuid, EngineState::Importing)?;
             let job = Arc::new(ImportJob::new(self.cfg.clone(), client, engine));
             inner.import_jobs.insert(uuid, Arc::clone(&job));
             job
         };
 
-        let res = job.run();
+        let mut res = job.run();
+        let state = match res {
+            Ok(_) => EngineState::Imported,
+            Err(_) => EngineState::Closed,
+        };
+        if let Err(e) = self.dir.set_state(uuid, state) {
+            if res.is_ok() {
+                res = Err(e);
+            }
+        }
         self.inner.lock().unwrap().import_jobs.remove(&uuid);
 
         match res {
@@ -151,8 +199,8 @@ impl KVImporter {
     /// Clean up the engine.
     /// Engine can not be cleaned up when it is writing or importing.
     pub fn cleanup_engine(&self, uuid: Uuid) -> Result<()> {
-        // Drop the engine outside of the lock.
-        let _ = {
+        // Close the engine outside of the lock.
+        let engine = {
             let mut inner = self.inner.lock().unwrap();
             if inner.import_jobs.contains_key(&uuid) {
                 return Err(Error::EngineInUse(uuid));
@@ -169,6 +217,10 @@ impl KVImporter {
                 None
             }
         };
+        if let Some(mut engine) = engine {
+            // Errors are ignored here since the directory is removed again below.
+            let _ = engine.cleanup();
+        }
 
         match self.dir.cleanup(uuid) {
             Ok(_) => {
@@ -186,9 +238,12 @@ impl KVImporter {
 /// EngineDir is responsible for managing engine directories.
 ///
 /// The temporary RocksDB engine is placed in `$root/.temp/$uuid`. After writing
-/// is completed, the files are stored in `$root/$uuid`.
+/// is completed, the files are stored in `$root/$uuid`. Temporary engines are kept
+/// after restarting, so that an interrupted writing can be resumed.
 pub struct EngineDir {
     opts: DbConfig,
+    flush_chunk_size: usize,
+    num_shards: usize,
     root_dir: PathBuf,
     temp_dir: PathBuf,
 }
@@ -196,15 +251,19 @@ pub struct EngineDir {
 impl EngineDir {
     const TEMP_DIR: &'static str = ".temp";
 
-    fn new<P: AsRef<Path>>(root: P, opts: DbConfig) -> Result<EngineDir> {
+    fn new<P: AsRef<Path>>(
+        root: P,
+        opts: DbConfig,
+        flush_chunk_size: usize,
+        num_shards: usize,
+    ) -> Result<EngineDir> {
         let root_dir = root.as_ref().to_owned();
         let temp_dir = root_dir.join(Self::TEMP_DIR);
-        if temp_dir.exists() {
-            fs::remove_dir_all(&temp_dir)?;
-        }
         fs::create_dir_all(&temp_dir)?;
         Ok(EngineDir {
             opts,
+            flush_chunk_size,
+            num_shards,
             root_dir,
             temp_dir,
         })
@@ -225,12 +284,70 @@ impl EngineDir {
         if path.save.exists() {
             return Err(Error::FileExists(path.save));
         }
-        EngineFile::new(uuid, path, self.opts.clone())
+        EngineFile::new(
+            uuid,
+            path,
+            self.opts.clone(),
+            self.flush_chunk_size,
+            self.num_shards,
+        )
     }
 
     fn import(&self, uuid: Uuid) -> Result<Engine> {
         let path = self.join(uuid);
-        Engine::new(&path.save, uuid, self.opts.clone())
+        Engine::with_shards(&path.save, uuid, self.opts.clone(), 1)
+    }
+
+    /// Updates the state of a closed engine.
+    fn set_state(&self, uuid: Uuid, state: EngineState) -> Result<()> {
+        let path = self.join(uuid);
+        let mut meta = load_engine_meta(&path.save, uuid, EngineState::Closed)?;
+        meta.state = state;
+        save_engine_meta(&path.save, &meta)
+    }
+
+    /// Returns the metadata of all the engines, sorted by UUID.
+    fn list(&self) -> Result<Vec<EngineMeta>> {
+        let mut metas = Vec::new();
+        // Engines written by old versions have no metadata, so the state is inferred from
+        // the directory they are in.
+        for &(dir, state) in &[
+            (&self.temp_dir, EngineState::Writing),
+            (&self.root_dir, EngineState::Closed),
+        ] {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                let uuid = match path.file_name().and_then(|s| s.to_str()) {
+                    Some(name) => match Uuid::parse_str(name) {
+                        Ok(uuid) => uuid,
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                };
+                metas.push(load_engine_meta(&path, uuid, state)?);
+            }
+        }
+        metas.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        Ok(metas)
+    }
+
+    /// Returns the metadata of all the engines left by the last process. No import job can
+    /// be running after restarting, so the interrupted ones are rolled back to `Closed` and
+    /// can be imported again.
+    fn recover(&self) -> Result<Vec<EngineMeta>> {
+        let mut metas = self.list()?;
+        for meta in &mut metas {
+            if meta.state == EngineState::Importing {
+                let uuid = Uuid::parse_str(&meta.uuid).unwrap();
+                warn!("import {} is interrupted", uuid);
+                meta.state = EngineState::Closed;
+                save_engine_meta(&self.join(uuid).save, meta)?;
+            }
+        }
+        Ok(metas)
     }
 
     fn cleanup(&self, uuid: Uuid) -> Result<EnginePath> {
@@ -263,7 +380,8 @@ impl fmt::Debug for EnginePath {
 }
 
 /// EngineFile creates an engine in the temp directory for writing, and when
-/// writing is completed, it moves files to the save directory.
+/// writing is completed, it moves files to the save directory. If the engine
+/// already exists in the temp directory, it's reopened to continue writing.
 pub struct EngineFile {
     uuid: Uuid,
     path: EnginePath,
@@ -271,8 +389,16 @@ pub struct EngineFile {
 }
 
 impl EngineFile {
-    fn new(uuid: Uuid, path: EnginePath, opts: DbConfig) -> Result<EngineFile> {
-        let engine = Engine::new(&path.temp, uuid, opts)?;
+    fn new(
+        uuid: Uuid,
+        path: EnginePath,
+        opts: DbConfig,
+        flush_chunk_size: usize,
+        num_shards: usize,
+    ) -> Result<EngineFile> {
+        let mut engine = Engine::with_shards(&path.temp, uuid, opts, num_shards)?;
+        engine.set_flush_chunk_size(flush_chunk_size);
+        save_engine_meta(&path.temp, &EngineMeta::new(uuid, EngineState::Writing))?;
         Ok(EngineFile {
             uuid,
             path,
@@ -284,12 +410,26 @@ impl EngineFile {
         self.engine.as_ref().unwrap().write(batch)
     }
 
+    pub fn high_water_mark(&self, commit_ts: u64) -> u64 {
+        self.engine.as_ref().unwrap().high_water_mark(commit_ts)
+    }
+
+    pub fn high_water_marks(&self) -> HashMap<u64, u64> {
+        self.engine.as_ref().unwrap().high_water_marks()
+    }
+
     /// Finish writing and move files from temp directory to save directory.
     fn close(&mut self) -> Result<()> {
-        self.engine.take().unwrap().flush(true)?;
+        let mut meta = EngineMeta::new(self.uuid, EngineState::Closed);
+        {
+            let engine = self.engine.take().unwrap();
+            engine.flush(true)?;
+            meta.written_range = engine.written_range();
+        }
         if self.path.save.exists() {
             return Err(Error::FileExists(self.path.save.clone()));
         }
+        save_engine_meta(&self.path.temp, &meta)?;
         fs::rename(&self.path.temp, &self.path.save)?;
         Ok(())
     }
@@ -306,8 +446,12 @@ impl EngineFile {
 
 impl Drop for EngineFile {
     fn drop(&mut self) {
-        if let Err(e) = self.cleanup() {
-            warn!("cleanup {:?}: {:?}", self, e);
+        // Keep the temp files so that writing can be resumed later, unless the engine is
+        // cleaned up explicitly.
+        if let Some(engine) = self.engine.take() {
+            if let Err(e) = engine.flush_and_mark() {
+                warn!("flush {:?}: {:?}", self, e);
+            }
         }
     }
 }
@@ -321,19 +465,83 @@ impl fmt::Debug for EngineFile {
     }
 }
 
+/// The file in the engine directory that records the engine metadata.
+const ENGINE_META_FILE: &str = "IMPORT_ENGINE_META";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EngineState {
+    /// The engine is in the temp directory and can be written.
+    Writing,
+    /// The engine is closed and can be imported.
+    Closed,
+    /// The engine is being imported.
+    Importing,
+    /// The engine has been imported and can be cleaned up.
+    Imported,
+}
+
+/// EngineMeta is saved in the engine directory, so that the engines can be resumed or cleaned
+/// up after restarting.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EngineMeta {
+    pub uuid: String,
+    pub state: EngineState,
+    /// The smallest and largest keys written, recorded when the engine is closed.
+    pub written_range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl EngineMeta {
+    fn new(uuid: Uuid, state: EngineState) -> EngineMeta {
+        EngineMeta {
+            uuid: format!("{}", uuid),
+            state,
+            written_range: None,
+        }
+    }
+}
+
+fn load_engine_meta(engine_path: &Path, uuid: Uuid, state: EngineState) -> Result<EngineMeta> {
+    let path = engine_path.join(ENGINE_META_FILE);
+    if !path.exists() {
+        return Ok(EngineMeta::new(uuid, state));
+    }
+    let mut s = String::new();
+    File::open(&path)?.read_to_string(&mut s)?;
+    serde_json::from_str(&s)
+        .map_err(|e| Error::FileCorrupted(path, format!("invalid engine meta: {:?}", e)))
+}
+
+fn save_engine_meta(engine_path: &Path, meta: &EngineMeta) -> Result<()> {
+    // Write to a temp file and rename it, so that the meta is never half written.
+    let path = engine_path.join(ENGINE_META_FILE);
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(&serde_json::to_vec(meta).unwrap())?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use tempdir::TempDir;
 
+    use storage::types::Key;
+
     #[test]
     fn test_kv_importer() {
         let temp_dir = TempDir::new("test_kv_importer").unwrap();
 
         let mut cfg = Config::default();
         cfg.import_dir = temp_dir.path().to_str().unwrap().to_owned();
-        let importer = KVImporter::new(cfg, DbConfig::default()).unwrap();
+        let security_mgr = Arc::new(SecurityManager::default());
+        let importer = KVImporter::new(cfg, DbConfig::default(), security_mgr).unwrap();
 
         let uuid = Uuid::new_v4();
         // Can not bind to an unopened engine.
@@ -347,6 +555,63 @@ mod tests {
         importer.close_engine(uuid).unwrap();
     }
 
+    #[test]
+    fn test_recover_engines() {
+        let temp_dir = TempDir::new("test_recover_engines").unwrap();
+
+        let mut cfg = Config::default();
+        cfg.import_dir = temp_dir.path().to_str().unwrap().to_owned();
+        let new_importer = || {
+            let security_mgr = Arc::new(SecurityManager::default());
+            KVImporter::new(cfg.clone(), DbConfig::default(), security_mgr).unwrap()
+        };
+
+        let (uuid1, uuid2) = (Uuid::new_v4(), Uuid::new_v4());
+        {
+            let importer = new_importer();
+            importer.open_engine(uuid1).unwrap();
+            importer.open_engine(uuid2).unwrap();
+            let mut wb = WriteBatch::new();
+            for k in &[b"a", b"b"] {
+                let mut m = Mutation::new();
+                m.set_op(Mutation_OP::Put);
+                m.set_key(k.to_vec());
+                wb.mut_mutations().push(m);
+            }
+            wb.set_commit_ts(10);
+            importer.bind_engine(uuid2).unwrap().write(wb).unwrap();
+            importer.close_engine(uuid2).unwrap();
+            // Pretend that the importer exits while importing.
+            importer.dir.set_state(uuid2, EngineState::Importing).unwrap();
+        }
+
+        let importer = new_importer();
+        let metas = importer.list_engines().unwrap();
+        assert_eq!(metas.len(), 2);
+        let get_meta = |uuid: Uuid| {
+            let uuid = format!("{}", uuid);
+            metas.iter().find(|m| m.uuid == uuid).unwrap().clone()
+        };
+        let meta1 = get_meta(uuid1);
+        assert_eq!(meta1.state, EngineState::Writing);
+        assert_eq!(meta1.written_range, None);
+        // The interrupted import is rolled back.
+        let meta2 = get_meta(uuid2);
+        assert_eq!(meta2.state, EngineState::Closed);
+        let start = Key::from_raw(b"a").append_ts(10).into_encoded();
+        let end = Key::from_raw(b"b").append_ts(10).into_encoded();
+        assert_eq!(meta2.written_range, Some((start, end)));
+
+        // Resume writing the unfinished engine and clean up the other one.
+        importer.open_engine(uuid1).unwrap();
+        importer.close_engine(uuid1).unwrap();
+        importer.cleanup_engine(uuid2).unwrap();
+        let metas = importer.list_engines().unwrap();
+        assert_eq!(metas.len(), 1);
+        assert_eq!(metas[0].state, EngineState::Closed);
+        assert_eq!(metas[0].written_range, None);
+    }
+
     #[test]
     fn test_engine_file() {
         let temp_dir = TempDir::new("test_engine_file").unwrap();
@@ -360,9 +625,9 @@ mod tests {
 
         // Test close.
         {
-            let mut f = EngineFile::new(uuid, path.clone(), opts.clone()).unwrap();
+            let mut f = EngineFile::new(uuid, path.clone(), opts.clone(), 0, 1).unwrap();
             // Cannot create the same file again.
-            assert!(EngineFile::new(uuid, path.clone(), opts.clone()).is_err());
+            assert!(EngineFile::new(uuid, path.clone(), opts.clone(), 0, 1).is_err());
             assert!(path.temp.exists());
             assert!(!path.save.exists());
             f.close().unwrap();
@@ -371,12 +636,23 @@ mod tests {
             fs::remove_dir_all(&path.save).unwrap();
         }
 
+        // Test reopen.
+        {
+            let f = EngineFile::new(uuid, path.clone(), opts.clone(), 0, 1).unwrap();
+            f.write(WriteBatch::new()).unwrap();
+            assert_eq!(f.high_water_mark(0), 0);
+            drop(f);
+            assert!(path.temp.exists());
+            let f = EngineFile::new(uuid, path.clone(), opts.clone(), 0, 1).unwrap();
+            assert_eq!(f.high_water_mark(0), 1);
+        }
+
         // Test cleanup.
         {
-            let f = EngineFile::new(uuid, path.clone(), opts.clone()).unwrap();
+            let mut f = EngineFile::new(uuid, path.clone(), opts.clone(), 0, 1).unwrap();
             assert!(path.temp.exists());
             assert!(!path.save.exists());
-            drop(f);
+            f.cleanup().unwrap();
             assert!(!path.temp.exists());
             assert!(!path.save.exists());
         }
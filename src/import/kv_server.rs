@@ -19,8 +19,9 @@ use grpc::{ChannelBuilder, EnvBuilder, Server as GrpcServer, ServerBuilder};
 use kvproto::import_kvpb_grpc::create_import_kv;
 
 use config::TiKvConfig;
+use util::security::SecurityManager;
 
-use super::{ImportKVService, KVImporter};
+use super::{ImportKVService, KVImporter, Result};
 
 const MAX_GRPC_MSG_LEN: i32 = 32 * 1024 * 1024;
 
@@ -31,12 +32,24 @@ pub struct ImportKVServer {
 }
 
 impl ImportKVServer {
-    pub fn new(tikv: &TiKvConfig) -> ImportKVServer {
+    pub fn new(tikv: &TiKvConfig) -> Result<ImportKVServer> {
         let cfg = &tikv.server;
         let addr = SocketAddr::from_str(&cfg.addr).unwrap();
 
-        let importer = KVImporter::new(tikv.import.clone(), tikv.rocksdb.clone()).unwrap();
-        let import_service = ImportKVService::new(tikv.import.clone(), Arc::new(importer));
+        let security_mgr = Arc::new(SecurityManager::new(&tikv.security).unwrap());
+        let importer = KVImporter::new(
+            tikv.import.clone(),
+            tikv.rocksdb.clone(),
+            Arc::clone(&security_mgr),
+        )?;
+        if tikv.security.encryption.enabled() {
+            warn!("the engines of the importer are not encrypted, even if encryption is enabled");
+        }
+        let import_service = ImportKVService::new(
+            tikv.import.clone(),
+            Arc::new(importer),
+            Arc::clone(&security_mgr),
+        );
 
         let env = Arc::new(
             EnvBuilder::new()
@@ -52,14 +65,15 @@ impl ImportKVServer {
             .max_receive_message_len(MAX_GRPC_MSG_LEN)
             .build_args();
 
-        let grpc_server = ServerBuilder::new(Arc::clone(&env))
-            .bind(format!("{}", addr.ip()), addr.port())
+        let sb = ServerBuilder::new(Arc::clone(&env));
+        let grpc_server = security_mgr
+            .bind(sb, &format!("{}", addr.ip()), addr.port())
             .channel_args(channel_args)
             .register_service(create_import_kv(import_service))
             .build()
             .unwrap();
 
-        ImportKVServer { grpc_server }
+        Ok(ImportKVServer { grpc_server })
     }
 
     pub fn start(&mut self) {
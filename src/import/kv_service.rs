@@ -23,6 +23,7 @@ use uuid::Uuid;
 
 use raftstore::store::keys;
 use storage::types::Key;
+use util::security::SecurityManager;
 use util::time::Instant;
 
 use super::client::*;
@@ -35,10 +36,15 @@ pub struct ImportKVService {
     cfg: Config,
     threads: CpuPool,
     importer: Arc<KVImporter>,
+    security_mgr: Arc<SecurityManager>,
 }
 
 impl ImportKVService {
-    pub fn new(cfg: Config, importer: Arc<KVImporter>) -> ImportKVService {
+    pub fn new(
+        cfg: Config,
+        importer: Arc<KVImporter>,
+        security_mgr: Arc<SecurityManager>,
+    ) -> ImportKVService {
         let threads = Builder::new()
             .name_prefix("kv-importer")
             .pool_size(cfg.num_threads)
@@ -47,6 +53,7 @@ impl ImportKVService {
             cfg,
             threads,
             importer,
+            security_mgr,
         }
     }
 }
@@ -61,10 +68,11 @@ impl ImportKv for ImportKVService {
         let label = "switch_mode";
         let timer = Instant::now_coarse();
 
+        let security_mgr = Arc::clone(&self.security_mgr);
         ctx.spawn(
             self.threads
                 .spawn_fn(move || {
-                    let client = Client::new(req.get_pd_addr(), 1)?;
+                    let client = Client::new(req.get_pd_addr(), 1, security_mgr)?;
                     match client.switch_cluster(req.get_request()) {
                         Ok(_) => {
                             info!("switch cluster {:?}", req.get_request());
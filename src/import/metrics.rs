@@ -40,4 +40,15 @@ lazy_static! {
         "Bucketed histogram of import upload chunk duration",
         exponential_buckets(0.001, 2.0, 20).unwrap()
     ).unwrap();
+    pub static ref IMPORT_RATE_LIMITER_WAIT_DURATION: HistogramVec = register_histogram_vec!(
+        "tikv_import_rate_limiter_wait_duration",
+        "Bucketed histogram of the duration waiting for the import rate limiters",
+        &["type"],
+        exponential_buckets(0.001, 2.0, 20).unwrap()
+    ).unwrap();
+    pub static ref IMPORT_RATE_LIMITER_BYTES: IntCounterVec = register_int_counter_vec!(
+        "tikv_import_rate_limiter_bytes",
+        "Total bytes passed through the import rate limiters",
+        &["type"]
+    ).unwrap();
 }
@@ -31,8 +31,10 @@
 mod client;
 mod common;
 mod config;
+mod duplicate;
 mod engine;
 mod errors;
+mod external_storage;
 mod import;
 mod metrics;
 mod prepare;
@@ -49,9 +51,11 @@ mod sst_service;
 pub mod test_helpers;
 
 pub use self::config::Config;
+pub use self::duplicate::{scan_duplicate_keys, DuplicateKey};
 pub use self::errors::{Error, Result};
-pub use self::kv_importer::KVImporter;
+pub use self::external_storage::{create_storage, ExternalStorage, LocalStorage};
+pub use self::kv_importer::{EngineMeta, EngineState, KVImporter};
 pub use self::kv_server::ImportKVServer;
 pub use self::kv_service::ImportKVService;
-pub use self::sst_importer::SSTImporter;
+pub use self::sst_importer::{RewriteRule, SSTImporter};
 pub use self::sst_service::ImportSSTService;
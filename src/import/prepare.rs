@@ -65,7 +65,12 @@ impl<Client: ImportClient> PrepareJob<Client> {
             }
         };
 
-        let num_prepares = self.prepare(&props);
+        let ranges = get_approximate_ranges(
+            &props,
+            self.cfg.num_import_jobs,
+            self.cfg.region_split_size.0 as usize,
+        );
+        let num_prepares = self.prepare(&props) + self.prepare_job_ranges(&ranges);
 
         // PD needs some time to scatter regions. But we don't know how much
         // time it should take, so we just calculate an approximate duration.
@@ -84,11 +89,7 @@ impl<Client: ImportClient> PrepareJob<Client> {
             start.elapsed(),
         );
 
-        Ok(get_approximate_ranges(
-            &props,
-            self.cfg.num_import_jobs,
-            self.cfg.region_split_size.0 as usize,
-        ))
+        Ok(ranges)
     }
 
     fn prepare(&self, props: &SizeProperties) -> usize {
@@ -115,6 +116,21 @@ impl<Client: ImportClient> PrepareJob<Client> {
         num_prepares
     }
 
+    /// Splits and scatters the regions at the boundaries of the import job ranges, so
+    /// that the jobs never ingest to the same region at the same time.
+    fn prepare_job_ranges(&self, ranges: &[RangeInfo]) -> usize {
+        let mut num_prepares = 0;
+        for range in ranges {
+            // A range with the same start and end splits the region containing the key
+            // at it, unless the region starts with it already.
+            let range = RangeInfo::new(range.get_start(), range.get_start(), 0);
+            if let Ok(true) = self.run_prepare_job(range) {
+                num_prepares += 1;
+            }
+        }
+        num_prepares
+    }
+
     fn run_prepare_job(&self, range: RangeInfo) -> Result<bool> {
         let id = self.counter.fetch_add(1, Ordering::SeqCst);
         let tag = format!("[PrepareRangeJob {}:{}]", self.engine.uuid(), id);
@@ -325,10 +341,13 @@ mod tests {
             let mut client = MockClient::new();
             client.add_region_range(b"", b"");
             // Expected region ranges returned by the prepare job.
+            // The regions are split at the boundaries of the ranges too.
             let region_ranges = vec![
                 (vec![], vec![3], true),
-                (vec![3], vec![6], true),
-                (vec![6], vec![9], true),
+                (vec![3], vec![4], true),
+                (vec![4], vec![6], true),
+                (vec![6], vec![8], true),
+                (vec![8], vec![9], true),
                 (vec![9], vec![12], true),
                 (vec![12], vec![15], true),
                 (vec![15], vec![], false),
@@ -361,10 +380,13 @@ mod tests {
             // Expected region ranges returned by the prepare job.
             let region_ranges = vec![
                 (vec![], vec![3], true),
-                (vec![3], vec![5], false),
+                (vec![3], vec![4], true),
+                (vec![4], vec![5], false),
                 (vec![5], vec![7], false),
-                (vec![7], vec![10], true),
-                (vec![10], vec![13], true),
+                (vec![7], vec![8], true),
+                (vec![8], vec![10], true),
+                (vec![10], vec![12], true),
+                (vec![12], vec![13], true),
                 (vec![13], vec![15], false),
                 (vec![15], vec![], false),
             ];
@@ -11,32 +11,140 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crc::crc32::{self, Hasher32};
 use kvproto::import_sstpb::*;
-use rocksdb::{IngestExternalFileOptions, DB};
+use rocksdb::{
+    ColumnFamilyOptions, DBIterator, EnvOptions, IngestExternalFileOptions, ReadOptions, SeekKey,
+    SstFileWriter, DB,
+};
 use uuid::Uuid;
 
-use util::rocksdb::{get_cf_handle, prepare_sst_for_ingestion, validate_sst_for_ingestion};
+use engine::RocksEngine;
+use raftstore::store::keys;
+use storage::types::Key;
+use storage::{CF_DEFAULT, CF_WRITE};
+use util::encryption::{
+    AesCtrCrypter, DataKeyManager, DecrypterReader, EncrypterWriter, FileEncryptionInfo,
+};
+use util::file::{calc_crc32, calc_crc32_from_reader, get_file_size, is_memory_backed};
+use util::io_limiter::{self, IOLimiter, IOType};
+use util::rocksdb::{
+    get_cf_handle, new_engine, prepare_sst_for_ingestion, validate_sst_for_ingestion,
+};
+use util::time::Instant;
 
-use super::{Error, Result};
+use super::duplicate::{get_commit_ts, DuplicateKey};
+use super::metrics::*;
+use super::{Error, ExternalStorage, Result};
+
+const DOWNLOAD_BUFFER_SIZE: usize = 1024 * 1024;
+// The decrypted copy of an encrypted file in a temporary RocksDB in the memory dir.
+const PLAIN_SST_FILE: &str = "plain.sst";
+// The plaintext of a rewritten file in a temporary RocksDB in the memory dir.
+const REWRITTEN_SST_FILE: &str = "rewritten.sst";
+
+/// RewriteRule replaces the key prefix of the SST files restored from another
+/// cluster, e.g. a table prefix, with the prefix used in this cluster. The
+/// prefixes are raw keys, while the keys in the files are encoded with
+/// timestamps, so every key is decoded to be rewritten and encoded again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RewriteRule {
+    pub old_prefix: Vec<u8>,
+    pub new_prefix: Vec<u8>,
+}
+
+impl RewriteRule {
+    pub fn new(old_prefix: Vec<u8>, new_prefix: Vec<u8>) -> RewriteRule {
+        RewriteRule {
+            old_prefix,
+            new_prefix,
+        }
+    }
+
+    fn is_noop(&self) -> bool {
+        self.old_prefix == self.new_prefix
+    }
+
+    fn rewrite(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let (user_key, ts) = Key::split_on_ts_for(key)?;
+        let raw_key = Key::from_encoded_slice(user_key).into_raw()?;
+        if !raw_key.starts_with(&self.old_prefix) {
+            return Err(Error::KeyNotRewritable(raw_key, self.old_prefix.clone()));
+        }
+        let len = raw_key.len() - self.old_prefix.len() + self.new_prefix.len();
+        let mut new_key = Vec::with_capacity(len);
+        new_key.extend_from_slice(&self.new_prefix);
+        new_key.extend_from_slice(&raw_key[self.old_prefix.len()..]);
+        Ok(Key::from_raw(&new_key).append_ts(ts).into_encoded())
+    }
+}
 
 /// SSTImporter manages SST files that are waiting for ingesting.
 pub struct SSTImporter {
     dir: ImportDir,
+    ingest_limiter: Option<Arc<IOLimiter>>,
 }
 
 impl SSTImporter {
     pub fn new<P: AsRef<Path>>(root: P) -> Result<SSTImporter> {
         Ok(SSTImporter {
             dir: ImportDir::new(root)?,
+            ingest_limiter: None,
         })
     }
 
+    /// Limits the bytes of the uploaded, or downloaded, and the ingested files
+    /// every second, 0 means unlimited.
+    pub fn with_speed_limit(
+        mut self,
+        upload_bytes_per_sec: u64,
+        ingest_bytes_per_sec: u64,
+    ) -> SSTImporter {
+        let new_limiter = |bytes_per_sec| {
+            if bytes_per_sec > 0 {
+                Some(Arc::new(IOLimiter::new(bytes_per_sec)))
+            } else {
+                None
+            }
+        };
+        self.dir.limiter = new_limiter(upload_bytes_per_sec);
+        self.ingest_limiter = new_limiter(ingest_bytes_per_sec);
+        self
+    }
+
+    /// Rejects the files larger than `bytes` to download.
+    pub fn with_max_download_size(mut self, bytes: u64) -> SSTImporter {
+        self.dir.max_download_size = bytes;
+        self
+    }
+
+    /// Encrypts the uploaded and downloaded files by the data keys of `key_manager` while they
+    /// wait to be ingested. They are decrypted into `memory_dir` to be read, which must be on a
+    /// memory-backed file system, and is cleared.
+    pub fn with_key_manager<P: AsRef<Path>>(
+        mut self,
+        key_manager: Arc<DataKeyManager>,
+        memory_dir: P,
+    ) -> Result<SSTImporter> {
+        let memory_dir = memory_dir.as_ref().to_owned();
+        fs::create_dir_all(&memory_dir)?;
+        if !is_memory_backed(&memory_dir)? {
+            return Err(Error::NotMemoryBacked(memory_dir));
+        }
+        fs::remove_dir_all(&memory_dir)?;
+        fs::create_dir_all(&memory_dir)?;
+        self.dir.key_manager = Some(key_manager);
+        self.dir.memory_dir = Some(memory_dir);
+        Ok(self)
+    }
+
     pub fn create(&self, meta: &SSTMeta) -> Result<ImportFile> {
         match self.dir.create(meta) {
             Ok(f) => {
@@ -63,6 +171,24 @@ impl SSTImporter {
         }
     }
 
+    /// Waits until the file `meta` can be ingested under the speed limit.
+    /// RocksDB ingests a file at once, so the ingestions are paced instead.
+    pub fn wait_for_ingest(&self, meta: &SSTMeta) {
+        if let Some(ref limiter) = self.ingest_limiter {
+            request_limiter(limiter, meta.get_length() as usize, "ingest");
+        }
+    }
+
+    /// Verifies the crc32 and the length of the uploaded file `meta`, and the
+    /// checksums of all its blocks if `verify_blocks` is true, so a corrupted
+    /// file is rejected before it is proposed to be ingested.
+    pub fn verify(&self, meta: &SSTMeta, verify_blocks: bool) -> Result<()> {
+        self.dir.verify(meta, verify_blocks).map_err(|e| {
+            error!("verify {:?}: {:?}", meta, e);
+            e
+        })
+    }
+
     pub fn ingest(&self, meta: &SSTMeta, db: &DB) -> Result<()> {
         match self.dir.ingest(meta, db) {
             Ok(_) => {
@@ -76,6 +202,43 @@ impl SSTImporter {
         }
     }
 
+    /// Downloads the file `name` described by `meta` from `storage` and
+    /// rewrites its keys with `rewrite_rule`, so that the file can be ingested
+    /// later. Returns the meta of the rewritten file to ingest with.
+    pub fn download(
+        &self,
+        meta: &SSTMeta,
+        storage: &ExternalStorage,
+        name: &str,
+        rewrite_rule: &RewriteRule,
+    ) -> Result<SSTMeta> {
+        match self.dir.download(meta, storage, name, rewrite_rule) {
+            Ok(new_meta) => {
+                info!("download {} to {:?} with {:?}", name, new_meta, rewrite_rule);
+                Ok(new_meta)
+            }
+            Err(e) => {
+                error!("download {} to {:?}: {:?}", name, meta, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Finds at most `limit` keys of the uploaded write cf file `meta`, which
+    /// have been committed in `db` with different timestamps. The file must be
+    /// checked before it is ingested.
+    pub fn detect_duplicates(
+        &self,
+        meta: &SSTMeta,
+        db: &Arc<DB>,
+        limit: usize,
+    ) -> Result<Vec<DuplicateKey>> {
+        self.dir.detect_duplicates(meta, db, limit).map_err(|e| {
+            error!("detect duplicate keys of {:?}: {:?}", meta, e);
+            e
+        })
+    }
+
     pub fn list_ssts(&self) -> Result<Vec<SSTMeta>> {
         self.dir.list_ssts()
     }
@@ -88,11 +251,20 @@ impl SSTImporter {
 /// is completed, the file is moved to `$root/$file_name`. The file generated
 /// from the ingestion process will be placed in `$root/.clone/$file_name`.
 ///
-/// TODO: Add size and rate limit.
+/// With a key manager, the files in `$root/.temp` and `$root` are encrypted. They are decrypted
+/// into the temporary RocksDBs in `$memory_dir` to be read, which is on a memory-backed file
+/// system, so that their plaintext is never written to disk.
+///
+/// The files are written under the speed limit of `limiter`, and the files larger than
+/// `max_download_size` are not downloaded.
 pub struct ImportDir {
     root_dir: PathBuf,
     temp_dir: PathBuf,
     clone_dir: PathBuf,
+    key_manager: Option<Arc<DataKeyManager>>,
+    memory_dir: Option<PathBuf>,
+    limiter: Option<Arc<IOLimiter>>,
+    max_download_size: u64,
 }
 
 impl ImportDir {
@@ -115,9 +287,17 @@ impl ImportDir {
             root_dir,
             temp_dir,
             clone_dir,
+            key_manager: None,
+            memory_dir: None,
+            limiter: None,
+            max_download_size: u64::max_value(),
         })
     }
 
+    fn key_manager(&self) -> Option<&DataKeyManager> {
+        self.key_manager.as_ref().map(|m| m.as_ref())
+    }
+
     fn join(&self, meta: &SSTMeta) -> Result<ImportPath> {
         let file_name = sst_meta_to_path(meta)?;
         let save_path = self.root_dir.join(&file_name);
@@ -135,27 +315,62 @@ impl ImportDir {
         if path.save.exists() {
             return Err(Error::FileExists(path.save));
         }
-        ImportFile::create(meta.clone(), path)
+        let mut f = ImportFile::create(meta.clone(), path, self.key_manager.clone())?;
+        f.limiter = self.limiter.clone();
+        Ok(f)
     }
 
     fn delete(&self, meta: &SSTMeta) -> Result<ImportPath> {
         let path = self.join(meta)?;
-        if path.save.exists() {
-            fs::remove_file(&path.save)?;
+        delete_file(self.key_manager(), &path.save)?;
+        delete_file(self.key_manager(), &path.temp)?;
+        delete_file(self.key_manager(), &path.clone)?;
+        Ok(path)
+    }
+
+    fn verify(&self, meta: &SSTMeta, verify_blocks: bool) -> Result<()> {
+        let path = self.join(meta)?;
+        if !path.save.exists() {
+            return Err(Error::FileNotExists(path.save));
         }
-        if path.temp.exists() {
-            fs::remove_file(&path.temp)?;
+        let length = get_file_size(&path.save)?;
+        if length != meta.get_length() {
+            let reason = format!("length {}, expect {}", length, meta.get_length());
+            return Err(Error::FileCorrupted(path.save, reason));
         }
-        if path.clone.exists() {
-            fs::remove_file(&path.clone)?;
+        let info = file_encryption_info(self.key_manager(), &path.save)?;
+        let mut reader = DecrypterReader::new(File::open(&path.save)?, info.new_crypter());
+        let crc32 = calc_crc32_from_reader(&mut reader)?;
+        if crc32 != meta.get_crc32() {
+            let reason = format!("crc32 {}, expect {}", crc32, meta.get_crc32());
+            return Err(Error::FileCorrupted(path.save, reason));
         }
-        Ok(path)
+        if !verify_blocks {
+            return Ok(());
+        }
+
+        let db_path = self.temp_db_path(meta)?;
+        let res = verify_sst_blocks(&db_path, &path.save, self.key_manager());
+        if db_path.exists() {
+            fs::remove_dir_all(&db_path)?;
+        }
+        res
     }
 
     fn ingest(&self, meta: &SSTMeta, db: &DB) -> Result<()> {
         let path = self.join(meta)?;
         let cf = meta.get_cf_name();
-        prepare_sst_for_ingestion(&path.save, &path.clone)?;
+        let info = file_encryption_info(self.key_manager(), &path.save)?;
+        if info.is_encrypted() {
+            // RocksDB only ingests plaintext files, the decrypted file is moved into the engine
+            // right away, whose files are not encrypted anyway.
+            let mut reader = DecrypterReader::new(File::open(&path.save)?, info.new_crypter());
+            let mut f = File::create(&path.clone)?;
+            io::copy(&mut reader, &mut f)?;
+            f.sync_all()?;
+        } else {
+            prepare_sst_for_ingestion(&path.save, &path.clone)?;
+        }
         validate_sst_for_ingestion(db, cf, &path.clone, meta.get_length(), meta.get_crc32())?;
 
         let handle = get_cf_handle(db, cf)?;
@@ -165,6 +380,95 @@ impl ImportDir {
         Ok(())
     }
 
+    fn download(
+        &self,
+        meta: &SSTMeta,
+        storage: &ExternalStorage,
+        name: &str,
+        rewrite_rule: &RewriteRule,
+    ) -> Result<SSTMeta> {
+        if meta.get_length() > self.max_download_size {
+            let (length, limit) = (meta.get_length(), self.max_download_size);
+            return Err(Error::FileTooLarge(name.to_owned(), length, limit));
+        }
+        let path = self.join(meta)?;
+        {
+            // A larger file than expected is rejected by the length check once a byte more is
+            // read, instead of being read through.
+            let mut reader = storage.read(name)?.take(meta.get_length() + 1);
+            let mut file = self.create(meta)?;
+            let mut buf = vec![0; DOWNLOAD_BUFFER_SIZE];
+            loop {
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                file.append(&buf[..n])?;
+            }
+            file.finish()?;
+        }
+        if rewrite_rule.is_noop() {
+            return Ok(meta.clone());
+        }
+
+        let res = self.rewrite(meta, &path, rewrite_rule);
+        if res.is_err() {
+            if let Err(e) = self.delete(meta) {
+                warn!("cleanup {:?}: {:?}", path, e);
+            }
+        }
+        res
+    }
+
+    // The path of the temporary RocksDB to read the keys of the file. It's in the memory dir
+    // if there is one, because the encrypted files are decrypted into it.
+    fn temp_db_path(&self, meta: &SSTMeta) -> Result<PathBuf> {
+        let uuid = Uuid::from_bytes(meta.get_uuid())?;
+        let dir = self.memory_dir.as_ref().unwrap_or(&self.temp_dir);
+        Ok(dir.join(format!("{}.db", uuid)))
+    }
+
+    fn rewrite(
+        &self,
+        meta: &SSTMeta,
+        path: &ImportPath,
+        rewrite_rule: &RewriteRule,
+    ) -> Result<SSTMeta> {
+        let db_path = self.temp_db_path(meta)?;
+        let res = rewrite_sst(&db_path, path, rewrite_rule, self.key_manager());
+        if db_path.exists() {
+            fs::remove_dir_all(&db_path)?;
+        }
+        let (crc32, length, (start, end)) = res?;
+
+        let mut new_meta = meta.clone();
+        new_meta.set_crc32(crc32);
+        new_meta.set_length(length);
+        new_meta.mut_range().set_start(start);
+        new_meta.mut_range().set_end(end);
+        Ok(new_meta)
+    }
+
+    fn detect_duplicates(
+        &self,
+        meta: &SSTMeta,
+        db: &Arc<DB>,
+        limit: usize,
+    ) -> Result<Vec<DuplicateKey>> {
+        // Only the keys in the write cf carry commit timestamps.
+        if meta.get_cf_name() != CF_WRITE {
+            let reason = format!("detect duplicate keys in cf {}", meta.get_cf_name());
+            return Err(Error::InvalidProtoMessage(reason));
+        }
+        let path = self.join(meta)?;
+        let db_path = self.temp_db_path(meta)?;
+        let res = detect_sst_duplicates(&db_path, &path.save, db, limit, self.key_manager());
+        if db_path.exists() {
+            fs::remove_dir_all(&db_path)?;
+        }
+        res
+    }
+
     fn list_ssts(&self) -> Result<Vec<SSTMeta>> {
         let mut ssts = Vec::new();
         for e in fs::read_dir(&self.root_dir)? {
@@ -208,24 +512,50 @@ pub struct ImportFile {
     path: ImportPath,
     file: Option<File>,
     digest: crc32::Digest,
+    limiter: Option<Arc<IOLimiter>>,
+    key_manager: Option<Arc<DataKeyManager>>,
+    // Encrypts the data written to the file if there is a key manager.
+    crypter: Option<AesCtrCrypter>,
 }
 
 impl ImportFile {
-    fn create(meta: SSTMeta, path: ImportPath) -> Result<ImportFile> {
+    fn create(
+        meta: SSTMeta,
+        path: ImportPath,
+        key_manager: Option<Arc<DataKeyManager>>,
+    ) -> Result<ImportFile> {
         let file = OpenOptions::new()
             .write(true)
             .create_new(true)
             .open(&path.temp)?;
-        Ok(ImportFile {
+        let mut f = ImportFile {
             meta,
             path,
             file: Some(file),
             digest: crc32::Digest::new(crc32::IEEE),
-        })
+            limiter: None,
+            key_manager,
+            crypter: None,
+        };
+        if let Some(ref m) = f.key_manager {
+            f.crypter = m.new_file(f.path.temp.to_str().unwrap())?.new_crypter();
+        }
+        Ok(f)
     }
 
     pub fn append(&mut self, data: &[u8]) -> Result<()> {
-        self.file.as_mut().unwrap().write_all(data)?;
+        io_limiter::request_io(IOType::Import, data.len());
+        if let Some(ref limiter) = self.limiter {
+            request_limiter(limiter, data.len(), "upload");
+        }
+        match self.crypter {
+            Some(ref mut c) => {
+                let mut buf = data.to_vec();
+                c.crypt(&mut buf);
+                self.file.as_mut().unwrap().write_all(&buf)?;
+            }
+            None => self.file.as_mut().unwrap().write_all(data)?,
+        }
         self.digest.write(data);
         Ok(())
     }
@@ -236,16 +566,22 @@ impl ImportFile {
         if self.path.save.exists() {
             return Err(Error::FileExists(self.path.save.clone()));
         }
+        // The encryption info is linked before the rename and deleted after it, so that the
+        // file is never unknown to the key manager.
+        let (temp, save) = (self.path.temp.to_str().unwrap(), self.path.save.to_str().unwrap());
+        if let Some(ref m) = self.key_manager {
+            m.link_file(temp, save)?;
+        }
         fs::rename(&self.path.temp, &self.path.save)?;
+        if let Some(ref m) = self.key_manager {
+            m.delete_file(temp)?;
+        }
         Ok(())
     }
 
     fn cleanup(&mut self) -> Result<()> {
         self.file.take();
-        if self.path.temp.exists() {
-            fs::remove_file(&self.path.temp)?;
-        }
-        Ok(())
+        delete_file(self.key_manager.as_ref().map(|m| m.as_ref()), &self.path.temp)
     }
 
     fn validate(&self) -> Result<()> {
@@ -284,6 +620,210 @@ impl fmt::Debug for ImportFile {
     }
 }
 
+// Requests `bytes` from `limiter` in pieces no larger than its burst.
+fn request_limiter(limiter: &IOLimiter, bytes: usize, tp: &str) {
+    let timer = Instant::now_coarse();
+    let max_bytes = cmp::max(limiter.get_max_bytes_per_time(), 1) as usize;
+    let mut remain = bytes;
+    while remain > 0 {
+        let n = cmp::min(remain, max_bytes);
+        limiter.request(n as i64);
+        remain -= n;
+    }
+    IMPORT_RATE_LIMITER_BYTES
+        .with_label_values(&[tp])
+        .inc_by(bytes as i64);
+    IMPORT_RATE_LIMITER_WAIT_DURATION
+        .with_label_values(&[tp])
+        .observe(timer.elapsed_secs());
+}
+
+fn file_encryption_info(
+    key_manager: Option<&DataKeyManager>,
+    path: &Path,
+) -> Result<FileEncryptionInfo> {
+    match key_manager {
+        Some(m) => Ok(m.get_file(path.to_str().unwrap())?),
+        None => Ok(FileEncryptionInfo::plaintext()),
+    }
+}
+
+// Removes the file and its encryption info if it exists.
+fn delete_file(key_manager: Option<&DataKeyManager>, path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    if let Some(m) = key_manager {
+        m.delete_file(path.to_str().unwrap())?;
+    }
+    Ok(())
+}
+
+// The keys of an SST file can only be read after the file is ingested, so the
+// file is ingested into a temporary RocksDB at `db_path` to be read.
+//
+// An encrypted file is decrypted into the temporary RocksDB, which is in the memory dir of
+// `ImportDir`, so the plaintext is never written to disk.
+fn load_sst(db_path: &Path, sst_path: &Path, key_manager: Option<&DataKeyManager>) -> Result<DB> {
+    let db = new_engine(db_path.to_str().unwrap(), &[CF_DEFAULT], None)?;
+    let info = file_encryption_info(key_manager, sst_path)?;
+    {
+        let handle = get_cf_handle(&db, CF_DEFAULT)?;
+        let mut opts = IngestExternalFileOptions::new();
+        if info.is_encrypted() {
+            let plain_path = db_path.join(PLAIN_SST_FILE);
+            {
+                let mut reader = DecrypterReader::new(File::open(sst_path)?, info.new_crypter());
+                io::copy(&mut reader, &mut File::create(&plain_path)?)?;
+            }
+            opts.move_files(true);
+            db.ingest_external_file_cf(handle, &opts, &[plain_path.to_str().unwrap()])?;
+        } else {
+            db.ingest_external_file_cf(handle, &opts, &[sst_path.to_str().unwrap()])?;
+        }
+    }
+    Ok(db)
+}
+
+// Reads all the entries of the file with checksums verified. A corrupted block
+// stops the iteration early, so the entries read are fewer than the entries
+// recorded in the table properties.
+fn verify_sst_blocks(
+    db_path: &Path,
+    sst_path: &Path,
+    key_manager: Option<&DataKeyManager>,
+) -> Result<()> {
+    // RocksDB rejects a file with a corrupted footer or properties block.
+    let db = match load_sst(db_path, sst_path, key_manager) {
+        Ok(db) => Arc::new(db),
+        Err(e) => return Err(Error::FileCorrupted(sst_path.to_owned(), format!("{:?}", e))),
+    };
+    let mut expected = 0;
+    {
+        let handle = get_cf_handle(&db, CF_DEFAULT)?;
+        let collection = db.get_properties_of_all_tables_cf(handle)?;
+        for (_, v) in &*collection {
+            expected += v.num_entries();
+        }
+    }
+
+    let mut ropts = ReadOptions::new();
+    ropts.fill_cache(false);
+    ropts.set_verify_checksums(true);
+    let mut iter = DBIterator::new(Arc::clone(&db), ropts);
+    let mut entries = 0;
+    iter.seek(SeekKey::Start);
+    while iter.valid() {
+        entries += 1;
+        iter.next();
+    }
+    if entries != expected {
+        let reason = format!("read {} entries, expect {}", entries, expected);
+        return Err(Error::FileCorrupted(sst_path.to_owned(), reason));
+    }
+    Ok(())
+}
+
+// Rewrites the keys of `path.save` into a new file, which replaces `path.save`, and returns
+// the crc32, the length and the first and the last keys of the new file.
+//
+// The new file is built in `path.temp`. With a key manager, it's built in the temporary RocksDB
+// in the memory dir instead, and then encrypted into `path.temp`, so that its plaintext is
+// never written to disk.
+fn rewrite_sst(
+    db_path: &Path,
+    path: &ImportPath,
+    rewrite_rule: &RewriteRule,
+    key_manager: Option<&DataKeyManager>,
+) -> Result<(u32, u64, (Vec<u8>, Vec<u8>))> {
+    let db = load_sst(db_path, &path.save, key_manager)?;
+
+    let plain_path = match key_manager {
+        Some(_) => db_path.join(REWRITTEN_SST_FILE),
+        None => path.temp.clone(),
+    };
+    let mut writer = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+    writer.open(plain_path.to_str().unwrap())?;
+    let mut range = None;
+    let mut iter = db.iter();
+    iter.seek(SeekKey::Start);
+    while iter.valid() {
+        if !keys::validate_data_key(iter.key()) {
+            let reason = format!("invalid data key {:?}", iter.key());
+            return Err(Error::FileCorrupted(path.save.clone(), reason));
+        }
+        let key = rewrite_rule.rewrite(keys::origin_key(iter.key()))?;
+        writer.put(&keys::data_key(&key), iter.value())?;
+        range = match range {
+            None => Some((key.clone(), key)),
+            Some((start, _)) => Some((start, key)),
+        };
+        iter.next();
+    }
+    let range = match range {
+        Some(range) => range,
+        None => return Err(Error::FileCorrupted(path.save.clone(), "no keys".to_owned())),
+    };
+    writer.finish()?;
+    let crc32 = calc_crc32(&plain_path)?;
+    let length = get_file_size(&plain_path)?;
+
+    let (temp, save) = (path.temp.to_str().unwrap(), path.save.to_str().unwrap());
+    match key_manager {
+        Some(m) => {
+            let crypter = m.new_file(temp)?.new_crypter();
+            let mut writer = EncrypterWriter::new(File::create(&path.temp)?, crypter);
+            io::copy(&mut File::open(&plain_path)?, &mut writer)?;
+            writer.into_inner().sync_all()?;
+            // Linked before the rename like `ImportFile::finish`.
+            m.link_file(temp, save)?;
+        }
+        None => File::open(&path.temp)?.sync_all()?,
+    }
+    fs::rename(&path.temp, &path.save)?;
+    if let Some(m) = key_manager {
+        m.delete_file(temp)?;
+    }
+    Ok((crc32, length, range))
+}
+
+fn detect_sst_duplicates(
+    db_path: &Path,
+    sst_path: &Path,
+    db: &Arc<DB>,
+    limit: usize,
+    key_manager: Option<&DataKeyManager>,
+) -> Result<Vec<DuplicateKey>> {
+    let sst_db = load_sst(db_path, sst_path, key_manager)?;
+    let engine = RocksEngine::from_db(Arc::clone(db));
+    let mut dups = Vec::new();
+    let mut iter = sst_db.iter();
+    iter.seek(SeekKey::Start);
+    while iter.valid() && dups.len() < limit {
+        if !keys::validate_data_key(iter.key()) {
+            let reason = format!("invalid data key {:?}", iter.key());
+            return Err(Error::FileCorrupted(sst_path.to_owned(), reason));
+        }
+        let (key, commit_ts) = {
+            let (key, commit_ts) = Key::split_on_ts_for(keys::origin_key(iter.key()))?;
+            (key.to_vec(), commit_ts)
+        };
+        let mut conflicts: Vec<_> = get_commit_ts(&engine, &key)?
+            .into_iter()
+            .filter(|ts| *ts != commit_ts)
+            .collect();
+        if !conflicts.is_empty() {
+            conflicts.insert(0, commit_ts);
+            dups.push(DuplicateKey {
+                key,
+                commit_ts: conflicts,
+            });
+        }
+        iter.next();
+    }
+    Ok(dups)
+}
+
 const SST_SUFFIX: &str = ".sst";
 
 fn sst_meta_to_path(meta: &SSTMeta) -> Result<PathBuf> {
@@ -330,8 +870,12 @@ fn path_to_sst_meta<P: AsRef<Path>>(path: P) -> Result<SSTMeta> {
 mod tests {
     use super::*;
     use import::test_helpers::*;
+    use import::LocalStorage;
+    use rocksdb::Writable;
+    use storage::mvcc::{Write, WriteType};
 
     use tempdir::TempDir;
+    use util::encryption::{EncryptionMethod, PlaintextMasterKey};
     use util::rocksdb::new_engine;
 
     #[test]
@@ -400,6 +944,252 @@ mod tests {
         assert!(dir.list_ssts().unwrap().is_empty());
     }
 
+    #[test]
+    fn test_import_dir_with_key_manager() {
+        let temp_dir = TempDir::new("test_import_dir_with_key_manager").unwrap();
+        let storage_dir = TempDir::new("test_import_dir_with_key_manager_storage").unwrap();
+        let key_manager = DataKeyManager::new(
+            Box::new(PlaintextMasterKey),
+            EncryptionMethod::Aes256Ctr,
+            temp_dir.path().join("encryption"),
+        ).unwrap();
+        let key_manager = Arc::new(key_manager);
+        let mut dir = ImportDir::new(temp_dir.path().join("import")).unwrap();
+        dir.key_manager = Some(Arc::clone(&key_manager));
+        // It's not necessarily memory-backed in the test.
+        let memory_dir = temp_dir.path().join("memory");
+        fs::create_dir_all(&memory_dir).unwrap();
+        dir.memory_dir = Some(memory_dir.clone());
+
+        let db_path = temp_dir.path().join("db");
+        let db = new_engine(db_path.to_str().unwrap(), &[CF_DEFAULT], None).unwrap();
+
+        // The uploaded file is encrypted at rest.
+        let (meta, data) = gen_sst_file(temp_dir.path().join("0.sst"), (0, 10));
+        let path = dir.join(&meta).unwrap();
+        let mut f = dir.create(&meta).unwrap();
+        f.append(&data).unwrap();
+        f.finish().unwrap();
+        let key_path = path.save.to_str().unwrap();
+        assert!(key_manager.get_file(key_path).unwrap().is_encrypted());
+        assert_ne!(fs::read(&path.save).unwrap(), data);
+        // The file is decrypted into the memory dir to be read.
+        let db_path = dir.temp_db_path(&meta).unwrap();
+        assert!(db_path.starts_with(&memory_dir));
+        dir.verify(&meta, true).unwrap();
+        assert!(!db_path.exists());
+        dir.ingest(&meta, &db).unwrap();
+        check_db_range(&db, (0, 10));
+
+        // The rewritten file is encrypted too.
+        let storage = LocalStorage::new(storage_dir.path());
+        let raw_keys: &[&[u8]] = &[b"a", b"b"];
+        let meta = gen_mvcc_sst_file(storage_dir.path().join("1.sst"), raw_keys, 5);
+        let rule = RewriteRule::new(vec![], b"t".to_vec());
+        let new_meta = dir.download(&meta, &storage, "1.sst", &rule).unwrap();
+        let path = dir.join(&new_meta).unwrap();
+        let key_path = path.save.to_str().unwrap();
+        assert!(key_manager.get_file(key_path).unwrap().is_encrypted());
+        assert!(!path.temp.exists());
+        assert_eq!(fs::read_dir(&memory_dir).unwrap().count(), 0);
+        dir.verify(&new_meta, true).unwrap();
+        dir.ingest(&new_meta, &db).unwrap();
+        assert_eq!(&*db.get(&mvcc_key(b"ta", 5)).unwrap().unwrap(), b"a");
+        assert_eq!(&*db.get(&mvcc_key(b"tb", 5)).unwrap().unwrap(), b"b");
+
+        dir.delete(&new_meta).unwrap();
+        assert!(!key_manager.get_file(key_path).unwrap().is_encrypted());
+    }
+
+    #[test]
+    fn test_import_dir_download() {
+        let temp_dir = TempDir::new("test_import_dir_download").unwrap();
+        let storage_dir = TempDir::new("test_import_dir_download_storage").unwrap();
+        let dir = ImportDir::new(temp_dir.path()).unwrap();
+        let storage = LocalStorage::new(storage_dir.path());
+
+        let db_path = temp_dir.path().join("db");
+        let db = new_engine(db_path.to_str().unwrap(), &["default"], None).unwrap();
+
+        // Download without rewriting.
+        let (meta, _) = gen_sst_file(storage_dir.path().join("0.sst"), (0, 10));
+        let new_meta = dir
+            .download(&meta, &storage, "0.sst", &RewriteRule::default())
+            .unwrap();
+        assert_eq!(new_meta, meta);
+        dir.ingest(&new_meta, &db).unwrap();
+        check_db_range(&db, (0, 10));
+
+        // Download and rewrite the keys with a new prefix, whose length differs from the old
+        // one, so the encoded keys are regrouped.
+        let raw_keys: &[&[u8]] = &[b"t1_r", b"t1_r01234", b"t1_r0123456789abcdef"];
+        let meta = gen_mvcc_sst_file(storage_dir.path().join("1.sst"), raw_keys, 5);
+        let rule = RewriteRule::new(b"t1_".to_vec(), b"t22_".to_vec());
+        let new_meta = dir.download(&meta, &storage, "1.sst", &rule).unwrap();
+        let new_key = |k: &[u8]| {
+            let mut new_key = b"t22_".to_vec();
+            new_key.extend_from_slice(&k[3..]);
+            Key::from_raw(&new_key).append_ts(5).into_encoded()
+        };
+        assert_eq!(new_meta.get_range().get_start(), &*new_key(raw_keys[0]));
+        assert_eq!(new_meta.get_range().get_end(), &*new_key(raw_keys[2]));
+        // The rewritten file is moved out of the temp directory.
+        assert!(!dir.join(&new_meta).unwrap().temp.exists());
+        dir.verify(&new_meta, true).unwrap();
+        dir.ingest(&new_meta, &db).unwrap();
+        for k in raw_keys {
+            let value = db.get(&keys::data_key(&new_key(k))).unwrap().unwrap();
+            assert_eq!(&*value, *k);
+        }
+
+        // Keys without the old prefix can't be rewritten.
+        let raw_keys: &[&[u8]] = &[b"t1_a", b"u1_a"];
+        let meta = gen_mvcc_sst_file(storage_dir.path().join("2.sst"), raw_keys, 5);
+        match dir.download(&meta, &storage, "2.sst", &rule) {
+            Err(Error::KeyNotRewritable(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(!dir.join(&meta).unwrap().save.exists());
+        // Neither can the keys not encoded.
+        let (meta, _) = gen_sst_file(storage_dir.path().join("3.sst"), (20, 30));
+        assert!(dir.download(&meta, &storage, "3.sst", &rule).is_err());
+        assert!(!dir.join(&meta).unwrap().save.exists());
+
+        // File not found in the storage.
+        assert!(dir.download(&meta, &storage, "4.sst", &rule).is_err());
+    }
+
+    #[test]
+    fn test_import_dir_download_size() {
+        let temp_dir = TempDir::new("test_import_dir_download_size").unwrap();
+        let storage_dir = TempDir::new("test_import_dir_download_size_storage").unwrap();
+        let mut dir = ImportDir::new(temp_dir.path()).unwrap();
+        let storage = LocalStorage::new(storage_dir.path());
+        let (mut meta, data) = gen_sst_file(storage_dir.path().join("0.sst"), (0, 10));
+        let rule = RewriteRule::default();
+
+        dir.max_download_size = meta.get_length() - 1;
+        match dir.download(&meta, &storage, "0.sst", &rule) {
+            Err(Error::FileTooLarge(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // The file is larger than the meta says.
+        dir.max_download_size = meta.get_length();
+        meta.set_length(meta.get_length() - 1);
+        meta.set_crc32(calc_data_crc32(&data[..data.len() - 1]));
+        match dir.download(&meta, &storage, "0.sst", &rule) {
+            Err(Error::FileCorrupted(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(!dir.join(&meta).unwrap().save.exists());
+    }
+
+    #[test]
+    fn test_rewrite_rule() {
+        let key = |k: &[u8], ts| Key::from_raw(k).append_ts(ts).into_encoded();
+        let rule = RewriteRule::new(b"t\x80\x00\x01_r".to_vec(), b"t\x80\x00\x00\x02_r".to_vec());
+        assert_eq!(
+            rule.rewrite(&key(b"t\x80\x00\x01_r\x01\x02", 10)).unwrap(),
+            key(b"t\x80\x00\x00\x02_r\x01\x02", 10)
+        );
+        match rule.rewrite(&key(b"t\x80\x00\x02_r\x01", 10)) {
+            Err(Error::KeyNotRewritable(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // The keys must be encoded with timestamps.
+        assert!(rule.rewrite(b"t\x80\x00\x01_r\x01").is_err());
+    }
+
+    #[test]
+    fn test_import_dir_detect_duplicates() {
+        let temp_dir = TempDir::new("test_import_dir_detect_duplicates").unwrap();
+        let dir = ImportDir::new(temp_dir.path()).unwrap();
+
+        let db_path = temp_dir.path().join("db");
+        let db = new_engine(db_path.to_str().unwrap(), &[CF_DEFAULT, CF_WRITE], None).unwrap();
+        let db = Arc::new(db);
+        let write_key =
+            |k: &[u8], ts: u64| keys::data_key(Key::from_raw(k).append_ts(ts).as_encoded());
+        let write = Write::new(WriteType::Put, 1, None).to_bytes();
+        let handle = get_cf_handle(&db, CF_WRITE).unwrap();
+        db.put_cf(handle, &write_key(b"a", 5), &write).unwrap();
+        db.put_cf(handle, &write_key(b"b", 10), &write).unwrap();
+        db.put_cf(handle, &write_key(b"c", 5), &write).unwrap();
+        db.put_cf(handle, &write_key(b"c", 10), &write).unwrap();
+
+        let sst_path = temp_dir.path().join("write.sst");
+        let mut w = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+        w.open(sst_path.to_str().unwrap()).unwrap();
+        let sst_keys: &[&[u8]] = &[b"a", b"b", b"c", b"d"];
+        for k in sst_keys {
+            w.put(&write_key(k, 10), &write).unwrap();
+        }
+        w.finish().unwrap();
+        let (mut meta, data) = read_sst_file(&sst_path, (0, 0));
+        meta.set_cf_name(CF_WRITE.to_owned());
+        let mut f = dir.create(&meta).unwrap();
+        f.append(&data).unwrap();
+        f.finish().unwrap();
+
+        let dups = dir.detect_duplicates(&meta, &db, 10).unwrap();
+        let expected = vec![
+            DuplicateKey {
+                key: Key::from_raw(b"a").into_encoded(),
+                commit_ts: vec![10, 5],
+            },
+            DuplicateKey {
+                key: Key::from_raw(b"c").into_encoded(),
+                commit_ts: vec![10, 5],
+            },
+        ];
+        assert_eq!(dups, expected);
+        assert_eq!(dir.detect_duplicates(&meta, &db, 1).unwrap().len(), 1);
+        assert!(!dir.temp_db_path(&meta).unwrap().exists());
+
+        meta.set_cf_name(CF_DEFAULT.to_owned());
+        assert!(dir.detect_duplicates(&meta, &db, 10).is_err());
+    }
+
+    #[test]
+    fn test_import_dir_verify() {
+        let temp_dir = TempDir::new("test_import_dir_verify").unwrap();
+        let dir = ImportDir::new(temp_dir.path()).unwrap();
+
+        let (mut meta, mut data) = gen_sst_file(temp_dir.path().join("test.sst"), (0, 10));
+        assert!(dir.verify(&meta, false).is_err());
+        {
+            let mut f = dir.create(&meta).unwrap();
+            f.append(&data).unwrap();
+            f.finish().unwrap();
+        }
+        dir.verify(&meta, false).unwrap();
+        dir.verify(&meta, true).unwrap();
+        assert!(!dir.temp_db_path(&meta).unwrap().exists());
+
+        // Corrupt the first data block, and update the crc32 to pass the first check.
+        data[10] ^= 0xff;
+        let path = dir.join(&meta).unwrap();
+        File::create(&path.save).unwrap().write_all(&data).unwrap();
+        meta.set_crc32(calc_data_crc32(&data));
+        dir.verify(&meta, false).unwrap();
+        match dir.verify(&meta, true) {
+            Err(Error::FileCorrupted(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        meta.set_crc32(meta.get_crc32().wrapping_add(1));
+        match dir.verify(&meta, false) {
+            Err(Error::FileCorrupted(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        meta.set_length(meta.get_length() + 1);
+        match dir.verify(&meta, false) {
+            Err(Error::FileCorrupted(..)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
     #[test]
     fn test_import_file() {
         let temp_dir = TempDir::new("test_import_file").unwrap();
@@ -416,9 +1206,9 @@ mod tests {
         let mut meta = SSTMeta::new();
 
         {
-            let mut f = ImportFile::create(meta.clone(), path.clone()).unwrap();
+            let mut f = ImportFile::create(meta.clone(), path.clone(), None).unwrap();
             // Cannot create the same file again.
-            assert!(ImportFile::create(meta.clone(), path.clone()).is_err());
+            assert!(ImportFile::create(meta.clone(), path.clone(), None).is_err());
             f.append(data).unwrap();
             // Invalid crc32 and length.
             assert!(f.finish().is_err());
@@ -429,7 +1219,7 @@ mod tests {
         meta.set_crc32(crc32);
 
         {
-            let mut f = ImportFile::create(meta.clone(), path.clone()).unwrap();
+            let mut f = ImportFile::create(meta.clone(), path.clone(), None).unwrap();
             f.append(data).unwrap();
             // Invalid length.
             assert!(f.finish().is_err());
@@ -438,7 +1228,7 @@ mod tests {
         meta.set_length(data.len() as u64);
 
         {
-            let mut f = ImportFile::create(meta.clone(), path.clone()).unwrap();
+            let mut f = ImportFile::create(meta.clone(), path.clone(), None).unwrap();
             f.append(data).unwrap();
             f.finish().unwrap();
             assert!(!path.temp.exists());
@@ -11,27 +11,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant as StdInstant};
 
+use futures::future::Loop;
 use futures::sync::mpsc;
 use futures::{future, Future, Stream};
-use futures_cpupool::{Builder, CpuPool};
+use futures_cpupool::{Builder, CpuFuture, CpuPool};
 use grpc::{ClientStreamingSink, RequestStream, RpcContext, UnarySink};
 use kvproto::import_sstpb::*;
 use kvproto::import_sstpb_grpc::*;
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb::Region;
 use kvproto::raft_cmdpb::*;
+use kvproto::raft_serverpb::{PeerState, RegionLocalState};
 use rocksdb::DB;
 
-use raftstore::store::Callback;
+use raftstore::errors::{Error as RaftStoreError, Result as RaftStoreResult};
+use raftstore::store::engine::Peekable;
+use raftstore::store::{keys, util, Callback};
 use server::transport::RaftStoreRouter;
+use storage::{CF_DEFAULT, CF_RAFT};
 use util::future::paired_future_callback;
 use util::rocksdb::compact_files_in_range;
 use util::time::Instant;
+use util::timer::GLOBAL_TIMER_HANDLE;
 
 use super::import_mode::*;
 use super::metrics::*;
 use super::service::*;
-use super::{Config, Error, SSTImporter};
+use super::{create_storage, Config, Error, RewriteRule, SSTImporter};
+
+const IMPORT_MODE_CHECK_INTERVAL_SECS: u64 = 10;
 
 /// ImportSSTService provides tikv-server with the ability to ingest SST files.
 ///
@@ -58,15 +69,235 @@ impl<Router: RaftStoreRouter> ImportSSTService<Router> {
             .name_prefix("sst-importer")
             .pool_size(cfg.num_threads)
             .create();
+        let switcher = Arc::new(Mutex::new(ImportModeSwitcher::new(
+            cfg.import_mode_timeout.0,
+        )));
+        threads
+            .spawn(check_import_mode_timeout(
+                Arc::downgrade(&switcher),
+                Arc::downgrade(&engine),
+            ))
+            .forget();
         ImportSSTService {
             cfg,
             router,
             engine,
             threads,
             importer,
-            switcher: Arc::new(Mutex::new(ImportModeSwitcher::new())),
+            switcher,
         }
     }
+
+    /// Ingests the files of the same region in one raft command, so that they are ingested
+    /// together or not at all, like the default and write CF files of a range.
+    pub fn ingest_files(
+        &self,
+        context: Context,
+        ssts: Vec<SSTMeta>,
+    ) -> CpuFuture<IngestResponse, Error> {
+        self.refresh_import_mode();
+        let router = self.router.clone();
+        let engine = Arc::clone(&self.engine);
+        let import = Arc::clone(&self.importer);
+        let verify_checksum = self.cfg.verify_sst_checksum;
+
+        self.threads.spawn_fn(move || {
+            let res = get_region(&engine, &context).and_then(|region| {
+                for sst in &ssts {
+                    util::check_sst_for_ingestion(sst, &region)?;
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                return future::Either::A(future::ok(new_error_response(e)));
+            }
+            ingest(&router, &import, verify_checksum, context, ssts)
+        })
+    }
+
+    /// Downloads the `files` from the external storage of `url`, rewrites their keys with
+    /// `rewrite_rule`, and then ingests them like `ingest_files`. The `name` of every file is
+    /// its name in the storage, and the region id and the epoch of its `meta` are ignored.
+    ///
+    /// The region epoch is checked before the files are downloaded, so that no file is fetched
+    /// for a stale request, and the rewritten keys must be in the region.
+    pub fn download_and_ingest(
+        &self,
+        context: Context,
+        url: String,
+        files: Vec<(String, SSTMeta)>,
+        rewrite_rule: RewriteRule,
+    ) -> CpuFuture<IngestResponse, Error> {
+        self.refresh_import_mode();
+        let router = self.router.clone();
+        let engine = Arc::clone(&self.engine);
+        let import = Arc::clone(&self.importer);
+        let verify_checksum = self.cfg.verify_sst_checksum;
+
+        self.threads.spawn_fn(move || {
+            let region = match get_region(&engine, &context) {
+                Ok(region) => region,
+                Err(e) => return future::Either::A(future::ok(new_error_response(e))),
+            };
+            let storage = match create_storage(&url) {
+                Ok(storage) => storage,
+                Err(e) => return future::Either::A(future::err(e)),
+            };
+            let mut ssts = Vec::with_capacity(files.len());
+            for (name, mut meta) in files {
+                meta.set_region_id(region.get_id());
+                meta.set_region_epoch(region.get_region_epoch().clone());
+                let res = import
+                    .download(&meta, &*storage, &name, &rewrite_rule)
+                    .and_then(|sst| match util::check_sst_for_ingestion(&sst, &region) {
+                        Ok(_) => Ok(sst),
+                        Err(e) => {
+                            if let Err(e) = import.delete(&sst) {
+                                warn!("cleanup {:?}: {:?}", sst, e);
+                            }
+                            Err(Error::from(e))
+                        }
+                    });
+                match res {
+                    Ok(sst) => ssts.push(sst),
+                    Err(e) => {
+                        for sst in &ssts {
+                            if let Err(e) = import.delete(sst) {
+                                warn!("cleanup {:?}: {:?}", sst, e);
+                            }
+                        }
+                        return future::Either::A(future::err(e));
+                    }
+                }
+            }
+            ingest(&router, &import, verify_checksum, context, ssts)
+        })
+    }
+
+    fn refresh_import_mode(&self) {
+        self.switcher.lock().unwrap().refresh();
+    }
+}
+
+/// Verifies the `ssts` and sends a command to ingest them to raftstore.
+fn ingest<Router: RaftStoreRouter>(
+    router: &Router,
+    import: &SSTImporter,
+    verify_checksum: bool,
+    context: Context,
+    ssts: Vec<SSTMeta>,
+) -> future::Either<
+    future::FutureResult<IngestResponse, Error>,
+    impl Future<Item = IngestResponse, Error = Error>,
+> {
+    // A corrupted file must be rejected before it is proposed,
+    // since the ingestion can't fail in the apply thread.
+    for sst in &ssts {
+        if let Err(e) = import.verify(sst, verify_checksum) {
+            return future::Either::A(future::err(e));
+        }
+    }
+    // The ingestion runs in the apply thread, so it is paced here
+    // before the command is sent.
+    for sst in &ssts {
+        import.wait_for_ingest(sst);
+    }
+
+    let cmd = new_ingest_cmd(context, ssts);
+    let (cb, future) = paired_future_callback();
+    let res = future::result(router.send_command(cmd, Callback::Write(cb)))
+        .map_err(Error::from)
+        .and_then(|_| future.map_err(Error::from))
+        .map(|mut res| {
+            let mut resp = IngestResponse::new();
+            let mut header = res.response.take_header();
+            if header.has_error() {
+                resp.set_error(header.take_error());
+            }
+            resp
+        });
+    future::Either::B(res)
+}
+
+fn new_error_response(e: RaftStoreError) -> IngestResponse {
+    let mut resp = IngestResponse::new();
+    resp.set_error(e.into());
+    resp
+}
+
+/// Gets the region of `context` from the engine, whose epoch must be the epoch of `context`,
+/// so that the stale requests are rejected before the files are read.
+fn get_region(engine: &DB, context: &Context) -> RaftStoreResult<Region> {
+    let region_id = context.get_region_id();
+    let state: Option<RegionLocalState> =
+        engine.get_msg_cf(CF_RAFT, &keys::region_state_key(region_id))?;
+    let region = match state {
+        Some(ref s) if s.get_state() != PeerState::Tombstone => s.get_region().clone(),
+        _ => return Err(RaftStoreError::RegionNotFound(region_id)),
+    };
+    let stale = {
+        let (epoch, region_epoch) = (context.get_region_epoch(), region.get_region_epoch());
+        epoch.get_conf_ver() != region_epoch.get_conf_ver()
+            || epoch.get_version() != region_epoch.get_version()
+    };
+    if stale {
+        let error = format!(
+            "{:?} != {:?}",
+            context.get_region_epoch(),
+            region.get_region_epoch()
+        );
+        return Err(RaftStoreError::StaleEpoch(error, vec![region]));
+    }
+    Ok(region)
+}
+
+/// Makes a command that ingests all the `ssts`. The files are ingested in order by the apply
+/// thread, so the default CF files are put first, and the write records never point to values
+/// that are not ingested yet.
+fn new_ingest_cmd(mut context: Context, mut ssts: Vec<SSTMeta>) -> RaftCmdRequest {
+    ssts.sort_by_key(|sst| sst.get_cf_name() != CF_DEFAULT);
+    let mut header = RaftRequestHeader::new();
+    header.set_peer(context.take_peer());
+    header.set_region_id(context.get_region_id());
+    header.set_region_epoch(context.take_region_epoch());
+    let mut cmd = RaftCmdRequest::new();
+    cmd.set_header(header);
+    for sst in ssts {
+        let mut ingest = Request::new();
+        ingest.set_cmd_type(CmdType::IngestSST);
+        ingest.mut_ingest_sst().set_sst(sst);
+        cmd.mut_requests().push(ingest);
+    }
+    cmd
+}
+
+/// Switches back to the normal mode when the import mode times out. It stops
+/// after the service is dropped.
+fn check_import_mode_timeout(
+    switcher: Weak<Mutex<ImportModeSwitcher>>,
+    engine: Weak<DB>,
+) -> impl Future<Item = (), Error = ()> + Send {
+    let interval = Duration::from_secs(IMPORT_MODE_CHECK_INTERVAL_SECS);
+    future::loop_fn((), move |_| {
+        let (switcher, engine) = (switcher.clone(), engine.clone());
+        GLOBAL_TIMER_HANDLE
+            .delay(StdInstant::now() + interval)
+            .map_err(|e| error!("check import mode timeout: {:?}", e))
+            .map(move |_| {
+                let (switcher, engine) = match (switcher.upgrade(), engine.upgrade()) {
+                    (Some(switcher), Some(engine)) => (switcher, engine),
+                    _ => return Loop::Break(()),
+                };
+                let mut switcher = switcher.lock().unwrap();
+                if switcher.is_timeout() {
+                    match switcher.enter_normal_mode(&engine) {
+                        Ok(_) => info!("switch mode {:?} after timeout", SwitchMode::Normal),
+                        Err(e) => error!("switch mode {:?}: {:?}", SwitchMode::Normal, e),
+                    }
+                }
+                Loop::Continue(())
+            })
+    })
 }
 
 impl<Router: RaftStoreRouter> ImportSst for ImportSSTService<Router> {
@@ -107,6 +338,7 @@ impl<Router: RaftStoreRouter> ImportSst for ImportSSTService<Router> {
     ) {
         let label = "upload";
         let timer = Instant::now_coarse();
+        self.refresh_import_mode();
         let import = Arc::clone(&self.importer);
         let bounded_stream = mpsc::spawn(stream, &self.threads, self.cfg.stream_channel_window);
 
@@ -157,39 +389,10 @@ impl<Router: RaftStoreRouter> ImportSst for ImportSSTService<Router> {
     fn ingest(&self, ctx: RpcContext, mut req: IngestRequest, sink: UnarySink<IngestResponse>) {
         let label = "ingest";
         let timer = Instant::now_coarse();
-
-        // Make ingest command.
-        let mut ingest = Request::new();
-        ingest.set_cmd_type(CmdType::IngestSST);
-        ingest.mut_ingest_sst().set_sst(req.take_sst());
-        let mut context = req.take_context();
-        let mut header = RaftRequestHeader::new();
-        header.set_peer(context.take_peer());
-        header.set_region_id(context.get_region_id());
-        header.set_region_epoch(context.take_region_epoch());
-        let mut cmd = RaftCmdRequest::new();
-        cmd.set_header(header);
-        cmd.mut_requests().push(ingest);
-
-        let (cb, future) = paired_future_callback();
-        if let Err(e) = self.router.send_command(cmd, Callback::Write(cb)) {
-            return send_rpc_error(ctx, sink, e);
-        }
+        let ssts = vec![req.take_sst()];
 
         ctx.spawn(
-            future
-                .map_err(Error::from)
-                .then(|res| match res {
-                    Ok(mut res) => {
-                        let mut resp = IngestResponse::new();
-                        let mut header = res.response.take_header();
-                        if header.has_error() {
-                            resp.set_error(header.take_error());
-                        }
-                        future::ok(resp)
-                    }
-                    Err(e) => future::err(e),
-                })
+            self.ingest_files(req.take_context(), ssts)
                 .then(move |res| send_rpc_response!(res, sink, label, timer)),
         )
     }
@@ -236,3 +439,117 @@ impl<Router: RaftStoreRouter> ImportSst for ImportSSTService<Router> {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use kvproto::raft_cmdpb::RaftCmdResponse;
+    use tempdir::TempDir;
+
+    use super::*;
+    use import::test_helpers::*;
+    use raftstore::store::{Msg as StoreMsg, Mutable, SignificantMsg};
+    use storage::{Key, ALL_CFS};
+    use util::rocksdb::{get_cf_handle, new_engine};
+
+    #[derive(Clone)]
+    struct MockRouter {
+        cmds: Arc<Mutex<Vec<RaftCmdRequest>>>,
+    }
+
+    impl RaftStoreRouter for MockRouter {
+        fn send(&self, msg: StoreMsg) -> RaftStoreResult<()> {
+            if let StoreMsg::RaftCmd {
+                request, callback, ..
+            } = msg
+            {
+                self.cmds.lock().unwrap().push(request);
+                callback.invoke_with_response(RaftCmdResponse::new());
+            }
+            Ok(())
+        }
+
+        fn try_send(&self, msg: StoreMsg) -> RaftStoreResult<()> {
+            self.send(msg)
+        }
+
+        fn significant_send(&self, _: SignificantMsg) -> RaftStoreResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_download_and_ingest() {
+        let temp_dir = TempDir::new("test_download_and_ingest").unwrap();
+        let storage_dir = TempDir::new("test_download_and_ingest_storage").unwrap();
+        let db_path = temp_dir.path().join("db");
+        let engine = Arc::new(new_engine(db_path.to_str().unwrap(), ALL_CFS, None).unwrap());
+
+        let mut region = Region::new();
+        region.set_id(1);
+        region.set_start_key(Key::from_raw(b"t2").into_encoded());
+        region.set_end_key(Key::from_raw(b"t3").into_encoded());
+        region.mut_region_epoch().set_conf_ver(1);
+        region.mut_region_epoch().set_version(2);
+        let mut state = RegionLocalState::new();
+        state.set_region(region.clone());
+        {
+            let handle = get_cf_handle(&engine, CF_RAFT).unwrap();
+            engine
+                .put_msg_cf(handle, &keys::region_state_key(1), &state)
+                .unwrap();
+        }
+
+        let cmds = Arc::new(Mutex::new(Vec::new()));
+        let router = MockRouter {
+            cmds: Arc::clone(&cmds),
+        };
+        let importer = Arc::new(SSTImporter::new(temp_dir.path().join("import")).unwrap());
+        let service = ImportSSTService::new(Config::default(), router, engine, importer);
+
+        // The keys of table 1 are restored into table 2.
+        let raw_keys: &[&[u8]] = &[b"t1_a", b"t1_b"];
+        let meta = gen_mvcc_sst_file(storage_dir.path().join("1.sst"), raw_keys, 5);
+        let url = format!("local://{}", storage_dir.path().display());
+        let rule = RewriteRule::new(b"t1".to_vec(), b"t2".to_vec());
+        let download = |ctx: &Context, name: &str, rule: &RewriteRule| {
+            let files = vec![(name.to_owned(), meta.clone())];
+            service
+                .download_and_ingest(ctx.clone(), url.clone(), files, rule.clone())
+                .wait()
+        };
+
+        // The stale requests are rejected before the files are downloaded.
+        let mut ctx = Context::new();
+        ctx.set_region_id(1);
+        ctx.mut_region_epoch().set_conf_ver(1);
+        ctx.mut_region_epoch().set_version(1);
+        let resp = download(&ctx, "missing.sst", &rule).unwrap();
+        assert!(resp.get_error().has_stale_epoch());
+        ctx.set_region_id(2);
+        let resp = download(&ctx, "missing.sst", &rule).unwrap();
+        assert!(resp.get_error().has_region_not_found());
+        assert!(cmds.lock().unwrap().is_empty());
+
+        // The keys rewritten out of the region are rejected, and the file is deleted.
+        ctx.set_region_id(1);
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        let out_of_region = RewriteRule::new(b"t1".to_vec(), b"t3".to_vec());
+        assert!(download(&ctx, "1.sst", &out_of_region).is_err());
+        assert!(cmds.lock().unwrap().is_empty());
+
+        let resp = download(&ctx, "1.sst", &rule).unwrap();
+        assert!(!resp.has_error());
+        let cmds = cmds.lock().unwrap();
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].get_header().get_region_id(), 1);
+        let sst = cmds[0].get_requests()[0].get_ingest_sst().get_sst();
+        assert_eq!(sst.get_region_id(), 1);
+        assert_eq!(sst.get_region_epoch(), region.get_region_epoch());
+        let start = Key::from_raw(b"t2_a").append_ts(5).into_encoded();
+        let end = Key::from_raw(b"t2_b").append_ts(5).into_encoded();
+        assert_eq!(sst.get_range().get_start(), &*start);
+        assert_eq!(sst.get_range().get_end(), &*end);
+    }
+}
@@ -19,7 +19,6 @@ use uuid::Uuid;
 
 use kvproto::import_sstpb::*;
 use kvproto::metapb::*;
-use rocksdb::{DBIterator, SeekKey, DB};
 
 use super::client::*;
 use super::common::*;
@@ -58,6 +57,7 @@ pub struct SSTFileStream<Client> {
     iter: RangeIterator,
     engine: Arc<Engine>,
     stream_range: Range,
+    sst_file_size: usize,
 }
 
 impl<Client: ImportClient> SSTFileStream<Client> {
@@ -77,6 +77,7 @@ impl<Client: ImportClient> SSTFileStream<Client> {
             iter,
             engine,
             stream_range,
+            sst_file_size: cfg.sst_file_size.0 as usize,
         }
     }
 
@@ -85,7 +86,7 @@ impl<Client: ImportClient> SSTFileStream<Client> {
             return Ok(None);
         }
 
-        let mut w = self.engine.new_sst_writer()?;
+        let mut w = self.engine.new_sst_writer(self.sst_file_size)?;
         let start = self.iter.key().to_owned();
         self.ctx.reset(&start);
 
@@ -138,14 +139,14 @@ impl<Client: ImportClient> SSTFileStream<Client> {
 }
 
 pub struct RangeIterator {
-    iter: DBIterator<Arc<DB>>,
+    iter: EngineIterator,
     ranges: Vec<Range>,
     ranges_index: usize,
 }
 
 impl RangeIterator {
     pub fn new(
-        iter: DBIterator<Arc<DB>>,
+        iter: EngineIterator,
         range: Range,
         mut finished_ranges: Vec<Range>,
     ) -> RangeIterator {
@@ -199,7 +200,7 @@ impl RangeIterator {
 
     fn seek_next(&mut self) -> bool {
         while let Some(range) = self.ranges.get(self.ranges_index) {
-            if !self.iter.seek(SeekKey::Key(range.get_start())) {
+            if !self.iter.seek(range.get_start()) {
                 break;
             }
             assert!(self.iter.key() >= range.get_start());
@@ -261,7 +262,7 @@ mod tests {
 
     fn new_range_iter(db: Arc<DB>, range: Range, skip_ranges: Vec<Range>) -> RangeIterator {
         let ropts = ReadOptions::new();
-        let iter = DBIterator::new(Arc::clone(&db), ropts);
+        let iter = EngineIterator::new(vec![DBIterator::new(Arc::clone(&db), ropts)]);
         RangeIterator::new(iter, range, skip_ranges)
     }
 
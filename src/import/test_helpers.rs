@@ -27,6 +27,7 @@ use uuid::Uuid;
 
 use pd::RegionInfo;
 use raftstore::store::keys;
+use storage::types::Key;
 
 use super::client::*;
 use super::common::*;
@@ -76,6 +77,22 @@ pub fn read_sst_file<P: AsRef<Path>>(path: P, range: (u8, u8)) -> (SSTMeta, Vec<
     (meta, data)
 }
 
+/// The key of `raw_key` at `ts` in RocksDB.
+pub fn mvcc_key(raw_key: &[u8], ts: u64) -> Vec<u8> {
+    keys::data_key(Key::from_raw(raw_key).append_ts(ts).as_encoded())
+}
+
+/// Generates an SST file of the `raw_keys` at `ts`, whose values are the raw keys.
+pub fn gen_mvcc_sst_file<P: AsRef<Path>>(path: P, raw_keys: &[&[u8]], ts: u64) -> SSTMeta {
+    let mut w = SstFileWriter::new(EnvOptions::new(), ColumnFamilyOptions::new());
+    w.open(path.as_ref().to_str().unwrap()).unwrap();
+    for k in raw_keys {
+        w.put(&mvcc_key(k, ts), k).unwrap();
+    }
+    w.finish().unwrap();
+    read_sst_file(path, (0, 0)).0
+}
+
 #[derive(Clone)]
 pub struct MockClient {
     counter: Arc<AtomicUsize>,
@@ -73,6 +73,7 @@ extern crate raft;
 extern crate rand;
 extern crate regex;
 extern crate rocksdb;
+extern crate rustc_serialize;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -115,9 +116,12 @@ extern crate derive_more;
 
 #[macro_use]
 pub mod util;
+pub mod backup;
 pub mod config;
 pub mod coprocessor;
+pub mod engine;
 pub mod import;
+pub mod log_backup;
 pub mod pd;
 pub mod raftstore;
 pub mod server;
@@ -0,0 +1,77 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error::Error;
+use std::result::Result;
+
+use util::config::{ReadableDuration, ReadableSize};
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Archives the changes applied to the regions led by this TiKV.
+    pub enable: bool,
+    /// The external storage the change-log files are flushed to, like "local:///path/to/dir".
+    pub storage_url: String,
+    pub flush_interval: ReadableDuration,
+    /// Flushes the buffered changes early once they exceed this size.
+    pub max_buffer_size: ReadableSize,
+    /// Warns about the regions whose watermarks fall behind the current time by more than
+    /// this. 0 disables the warnings.
+    pub watermark_lag_alert_threshold: ReadableDuration,
+    /// Attaches the previous values of the keys to the commits archived.
+    pub enable_old_value: bool,
+    /// The number of the latest committed values kept to save the reads of the old values.
+    pub old_value_cache_capacity: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enable: false,
+            storage_url: "".to_owned(),
+            flush_interval: ReadableDuration::minutes(3),
+            max_buffer_size: ReadableSize::mb(128),
+            watermark_lag_alert_threshold: ReadableDuration::minutes(10),
+            enable_old_value: false,
+            old_value_cache_capacity: 65536,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Box<Error>> {
+        if !self.enable {
+            return Ok(());
+        }
+        if self.storage_url.is_empty() {
+            return Err("log_backup.storage_url can not be empty".into());
+        }
+        if self.flush_interval.as_millis() == 0 {
+            return Err("log_backup.flush_interval can not be 0".into());
+        }
+        if self.max_buffer_size.0 == 0 {
+            return Err("log_backup.max_buffer_size can not be 0".into());
+        }
+        // The watermarks only advance on flushes.
+        let threshold = self.watermark_lag_alert_threshold;
+        if threshold.as_millis() != 0 && threshold.as_millis() < self.flush_interval.as_millis() {
+            return Err(
+                "log_backup.watermark_lag_alert_threshold can not be less than flush_interval"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
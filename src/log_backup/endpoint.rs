@@ -0,0 +1,747 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crc::crc32;
+use kvproto::metapb::Region;
+use rocksdb::DB;
+use serde_json;
+
+use import::{create_storage, ExternalStorage};
+use pd::tso::PHYSICAL_SHIFT_BITS;
+use raftstore::store::engine::{Iterable, Peekable};
+use raftstore::store::keys;
+use storage::mvcc::{Lock, Write, WriteType};
+use storage::{Key, CF_DEFAULT, CF_LOCK, CF_WRITE};
+use util::collections::HashMap;
+use util::lru::LruCache;
+use util::time::{duration_to_ms, duration_to_sec, Instant};
+use util::timer::Timer;
+use util::worker::{Runnable, RunnableWithTimer};
+
+use super::log_file::{encode_changes, Change, LogFile};
+use super::metrics::*;
+use super::{Config, Result};
+
+pub enum Task {
+    /// The changes applied to a region by a command.
+    Changes {
+        region_id: u64,
+        changes: Vec<Change>,
+    },
+    /// The region is led by this TiKV now.
+    Register { region: Region },
+    /// The region is no longer led by this TiKV.
+    Deregister { region_id: u64 },
+    /// Flushes the buffered changes now.
+    Flush,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Changes {
+                region_id,
+                ref changes,
+            } => write!(f, "{} changes of region {}", changes.len(), region_id),
+            Task::Register { ref region } => write!(f, "register region {}", region.get_id()),
+            Task::Deregister { region_id } => write!(f, "deregister region {}", region_id),
+            Task::Flush => write!(f, "flush"),
+        }
+    }
+}
+
+/// LogBackupMeta describes the change-log files uploaded by a flush.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogBackupMeta {
+    pub store_id: u64,
+    pub flush_seq: u64,
+    /// The min watermark of all the regions observed by this store.
+    pub watermark: u64,
+    pub files: Vec<LogFile>,
+}
+
+#[derive(Default)]
+struct RegionBuffer {
+    changes: Vec<Change>,
+    // The start timestamps of the locks not committed or rolled back yet.
+    locks: HashMap<Vec<u8>, u64>,
+    watermark: u64,
+    // Whether the region is led by this TiKV, and whether it has been since the last flush.
+    // Only the changes of such regions are flushed.
+    leader: bool,
+    was_leader: bool,
+    // Whether `locks` has all the locks of the region, which is false until the locks
+    // written before this TiKV became the leader are loaded.
+    locks_loaded: bool,
+    // The number of changes captured before the last flush. The changes of a follower are
+    // kept for a flush interval, in case it becomes the leader before they are archived.
+    stale_changes: usize,
+}
+
+impl RegionBuffer {
+    // Returns the commit timestamp if it's a commit.
+    fn push(&mut self, change: Change) -> Result<Option<u64>> {
+        let mut commit_ts = None;
+        if change.cf == CF_LOCK {
+            match change.value {
+                Some(ref value) => {
+                    let lock = Lock::parse(value)?;
+                    self.locks.insert(change.key.clone(), lock.ts);
+                }
+                None => {
+                    self.locks.remove(&change.key);
+                }
+            }
+        } else if change.cf == CF_WRITE && change.value.is_some() {
+            commit_ts = Some(Key::decode_ts_from(&change.key)?);
+        }
+        self.changes.push(change);
+        Ok(commit_ts)
+    }
+
+    // The transactions committed at or before `max_commit_ts` have all been observed,
+    // except the ones still locked.
+    fn advance_watermark(&mut self, max_commit_ts: u64) -> u64 {
+        if !self.locks_loaded {
+            return self.watermark;
+        }
+        let resolved = match self.locks.values().min() {
+            Some(&ts) => cmp::min(ts.saturating_sub(1), max_commit_ts),
+            None => max_commit_ts,
+        };
+        self.watermark = cmp::max(self.watermark, resolved);
+        self.watermark
+    }
+}
+
+// How far the watermark falls behind `now`, the milliseconds since the epoch. None if no
+// commit has been observed yet.
+fn watermark_lag(watermark: u64, now: u64) -> Option<Duration> {
+    if watermark == 0 {
+        return None;
+    }
+    let physical = watermark >> PHYSICAL_SHIFT_BITS;
+    Some(Duration::from_millis(now.saturating_sub(physical)))
+}
+
+fn now_ms() -> u64 {
+    duration_to_ms(SystemTime::now().duration_since(UNIX_EPOCH).unwrap())
+}
+
+/// Runner buffers the changes sent by `LogBackupObserver`, and flushes them to the external
+/// storage every `flush_interval`, or once they exceed `max_buffer_size`.
+pub struct Runner {
+    store_id: u64,
+    engine: Arc<DB>,
+    storage: Arc<ExternalStorage>,
+    flush_interval: Duration,
+    max_buffer_size: usize,
+    lag_alert_threshold: Duration,
+    enable_old_value: bool,
+    // The latest committed value of each key and its commit timestamp, `None` for a delete.
+    old_values: LruCache<Vec<u8>, (u64, Option<Vec<u8>>)>,
+    buffers: HashMap<u64, RegionBuffer>,
+    buffered_size: usize,
+    // The max commit timestamp observed of all the regions.
+    max_commit_ts: u64,
+    watermark: u64,
+    flush_seq: u64,
+}
+
+impl Runner {
+    pub fn new(store_id: u64, engine: Arc<DB>, cfg: &Config) -> Result<Runner> {
+        let storage = create_storage(&cfg.storage_url)?;
+        Ok(Runner {
+            store_id,
+            engine,
+            storage,
+            flush_interval: cfg.flush_interval.0,
+            max_buffer_size: cfg.max_buffer_size.0 as usize,
+            lag_alert_threshold: cfg.watermark_lag_alert_threshold.0,
+            enable_old_value: cfg.enable_old_value,
+            old_values: LruCache::with_capacity(cfg.old_value_cache_capacity),
+            buffers: HashMap::default(),
+            buffered_size: 0,
+            max_commit_ts: 0,
+            watermark: 0,
+            flush_seq: 0,
+        })
+    }
+
+    pub fn new_timer(&self) -> Timer<()> {
+        let mut timer = Timer::new(1);
+        timer.add_task(self.flush_interval, ());
+        timer
+    }
+
+    fn on_changes(&mut self, region_id: u64, mut changes: Vec<Change>) {
+        if self.enable_old_value {
+            for change in &mut changes {
+                if let Err(e) = self.attach_old_value(change) {
+                    error!("[region {}] failed to get old value: {:?}", region_id, e);
+                }
+            }
+        }
+        let buffer = self.buffers.entry(region_id).or_insert_with(Default::default);
+        for change in changes {
+            let size = change.size();
+            LOG_BACKUP_CHANGE_COUNTER
+                .with_label_values(&[&change.cf])
+                .inc();
+            match buffer.push(change) {
+                Ok(Some(commit_ts)) => self.max_commit_ts = cmp::max(self.max_commit_ts, commit_ts),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("[region {}] failed to observe change: {:?}", region_id, e);
+                    continue;
+                }
+            }
+            self.buffered_size += size;
+        }
+        LOG_BACKUP_BUFFERED_BYTES_GAUGE.set(self.buffered_size as i64);
+    }
+
+    // Attaches the value committed before to a commit. The commits of a key are observed in
+    // order, so a value cached with an earlier commit timestamp is the previous one.
+    fn attach_old_value(&mut self, change: &mut Change) -> Result<()> {
+        if change.cf != CF_WRITE {
+            return Ok(());
+        }
+        let write = match change.value {
+            Some(ref value) => Write::parse(value)?,
+            None => return Ok(()),
+        };
+        if write.write_type == WriteType::Rollback || write.write_type == WriteType::Lock {
+            return Ok(());
+        }
+        let commit_ts = Key::decode_ts_from(&change.key)?;
+        let key = Key::truncate_ts_for(&change.key)?.to_vec();
+        let cached = match self.old_values.get(&key) {
+            Some(&(ts, ref value)) if ts < commit_ts => Some(value.clone()),
+            _ => None,
+        };
+        change.old_value = match cached {
+            Some(value) => {
+                LOG_BACKUP_OLD_VALUE_CACHE_COUNTER
+                    .with_label_values(&["hit"])
+                    .inc();
+                value
+            }
+            None => {
+                LOG_BACKUP_OLD_VALUE_CACHE_COUNTER
+                    .with_label_values(&["miss"])
+                    .inc();
+                self.load_old_value(&key, commit_ts)?
+            }
+        };
+        // A long value is only in CF_DEFAULT, so it's read from the engine next time.
+        if write.write_type == WriteType::Put && write.short_value.is_none() {
+            self.old_values.remove(&key);
+        } else {
+            self.old_values.insert(key, (commit_ts, write.short_value));
+        }
+        Ok(())
+    }
+
+    // Reads the latest value of the key committed before `commit_ts` from the engine.
+    fn load_old_value(&self, key: &[u8], commit_ts: u64) -> Result<Option<Vec<u8>>> {
+        let key = Key::from_encoded_slice(key);
+        let start_key = keys::data_key(key.clone().append_ts(commit_ts - 1).as_encoded());
+        let end_key = keys::data_key(key.clone().append_ts(0).as_encoded());
+        let mut latest = None;
+        self.engine
+            .scan_cf(CF_WRITE, &start_key, &end_key, false, |_, value| {
+                // The rollbacks and the locks don't change the value.
+                match Write::parse_type(value) {
+                    Ok(WriteType::Rollback) | Ok(WriteType::Lock) => Ok(true),
+                    _ => {
+                        latest = Some(value.to_vec());
+                        Ok(false)
+                    }
+                }
+            })?;
+        let write = match latest {
+            Some(value) => Write::parse(&value)?,
+            None => return Ok(None),
+        };
+        if write.write_type == WriteType::Delete || write.short_value.is_some() {
+            return Ok(write.short_value);
+        }
+        let default_key = keys::data_key(key.append_ts(write.start_ts).as_encoded());
+        let value = self.engine.get_value_cf(CF_DEFAULT, &default_key)?;
+        Ok(value.map(|v| v.to_vec()))
+    }
+
+    fn on_register(&mut self, region: &Region) {
+        let region_id = region.get_id();
+        let locks = match self.load_locks(region) {
+            Ok(locks) => Some(locks),
+            Err(e) => {
+                // The watermark is held back, as some locks may be missed.
+                error!("[region {}] failed to load locks for log backup: {:?}", region_id, e);
+                None
+            }
+        };
+        let buffer = self.buffers.entry(region_id).or_insert_with(Default::default);
+        buffer.leader = true;
+        buffer.was_leader = true;
+        buffer.locks_loaded = locks.is_some();
+        buffer.locks = locks.unwrap_or_default();
+    }
+
+    // Loads the locks of the region from the engine, including the ones written before this
+    // TiKV became the leader. The changes applied after the locks are loaded only arrive
+    // later, so they update the loaded locks in order.
+    fn load_locks(&self, region: &Region) -> Result<HashMap<Vec<u8>, u64>> {
+        let start_key = keys::enc_start_key(region);
+        let end_key = keys::enc_end_key(region);
+        let mut raw_locks = vec![];
+        self.engine
+            .scan_cf(CF_LOCK, &start_key, &end_key, false, |key, value| {
+                raw_locks.push((keys::origin_key(key).to_vec(), value.to_vec()));
+                Ok(true)
+            })?;
+        let mut locks = HashMap::default();
+        for (key, value) in raw_locks {
+            locks.insert(key, Lock::parse(&value)?.ts);
+        }
+        Ok(locks)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let start = Instant::now_coarse();
+        let seq = self.flush_seq + 1;
+        let mut files = Vec::with_capacity(self.buffers.len());
+        let mut watermark = self.max_commit_ts;
+        let now = now_ms();
+        let mut lagging_regions = 0;
+        for (region_id, buffer) in &mut self.buffers {
+            if !buffer.was_leader {
+                continue;
+            }
+            // The regions led by others are covered by their watermarks.
+            let region_watermark = if buffer.leader {
+                let w = buffer.advance_watermark(self.max_commit_ts);
+                watermark = cmp::min(watermark, w);
+                if let Some(lag) = watermark_lag(w, now) {
+                    LOG_BACKUP_REGION_WATERMARK_LAG.observe(duration_to_sec(lag));
+                    if self.lag_alert_threshold > Duration::from_secs(0)
+                        && lag > self.lag_alert_threshold
+                    {
+                        warn!(
+                            "[region {}] log backup watermark {} lags behind for {:?}",
+                            region_id, w, lag
+                        );
+                        lagging_regions += 1;
+                    }
+                }
+                w
+            } else {
+                buffer.watermark
+            };
+            if buffer.changes.is_empty() {
+                continue;
+            }
+            let name = format!("{}/{}/{}_{}.log", self.store_id, region_id, seq, region_watermark);
+            let data = encode_changes(&buffer.changes)?;
+            self.storage.write(&name, &mut data.as_slice())?;
+            files.push(LogFile {
+                name,
+                region_id: *region_id,
+                watermark: region_watermark,
+                crc32: crc32::checksum_ieee(&data),
+                size: data.len() as u64,
+                total_changes: buffer.changes.len() as u64,
+            });
+        }
+        let meta = LogBackupMeta {
+            store_id: self.store_id,
+            flush_seq: seq,
+            watermark: cmp::max(self.watermark, watermark),
+            files,
+        };
+        // The files are only referenced by the meta, so a failed flush is retried with all of
+        // them next time.
+        let data = serde_json::to_vec(&meta)?;
+        let name = format!("{}/meta/{}.json", self.store_id, seq);
+        self.storage.write(&name, &mut data.as_slice())?;
+
+        self.flush_seq = seq;
+        self.watermark = meta.watermark;
+        if let Some(lag) = watermark_lag(meta.watermark, now) {
+            LOG_BACKUP_WATERMARK_LAG_GAUGE.set(duration_to_sec(lag));
+        }
+        LOG_BACKUP_LAGGING_REGION_GAUGE.set(lagging_regions);
+        let mut buffered_size = 0;
+        for buffer in self.buffers.values_mut() {
+            if buffer.was_leader {
+                buffer.changes.clear();
+            } else {
+                let stale = buffer.stale_changes;
+                buffer.changes.drain(..stale);
+            }
+            buffer.stale_changes = buffer.changes.len();
+            buffer.was_leader = buffer.leader;
+            buffered_size += buffer.changes.iter().map(|c| c.size()).sum::<usize>();
+        }
+        self.buffers
+            .retain(|_, buffer| buffer.leader || !buffer.changes.is_empty());
+        self.buffered_size = buffered_size;
+        LOG_BACKUP_BUFFERED_BYTES_GAUGE.set(buffered_size as i64);
+        LOG_BACKUP_FLUSH_DURATION.observe(duration_to_sec(start.elapsed()));
+        info!(
+            "log backup flushed {} files, seq {}, watermark {}, takes {:?}",
+            meta.files.len(),
+            seq,
+            meta.watermark,
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    fn try_flush(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("log backup failed to flush: {:?}", e);
+        }
+    }
+}
+
+impl Runnable<Task> for Runner {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Changes { region_id, changes } => {
+                self.on_changes(region_id, changes);
+                if self.buffered_size >= self.max_buffer_size {
+                    self.try_flush();
+                }
+            }
+            Task::Register { region } => self.on_register(&region),
+            Task::Deregister { region_id } => {
+                // Its locks will be resolved by the new leader, so they no longer hold back
+                // the watermark here. The changes applied before the next flush are still
+                // flushed.
+                if let Some(buffer) = self.buffers.get_mut(&region_id) {
+                    buffer.leader = false;
+                    buffer.locks.clear();
+                }
+            }
+            Task::Flush => self.try_flush(),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.try_flush();
+    }
+}
+
+impl RunnableWithTimer<Task, ()> for Runner {
+    fn on_timeout(&mut self, timer: &mut Timer<()>, _: ()) {
+        self.try_flush();
+        timer.add_task(self.flush_interval, ());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use tempdir::TempDir;
+
+    use rocksdb::Writable;
+
+    use storage::mvcc::LockType;
+    use storage::ALL_CFS;
+    use util::rocksdb::{get_cf_handle, new_engine};
+
+    use log_backup::decode_changes;
+
+    fn lock(key: &[u8], ts: u64) -> Change {
+        let lock = Lock::new(LockType::Put, key.to_vec(), ts, 0, None);
+        Change {
+            cf: CF_LOCK.to_owned(),
+            key: Key::from_raw(key).into_encoded(),
+            value: Some(lock.to_bytes()),
+            old_value: None,
+        }
+    }
+
+    fn commit(key: &[u8], start_ts: u64, commit_ts: u64) -> Vec<Change> {
+        let write = Write::new(WriteType::Put, start_ts, Some(b"v".to_vec()));
+        vec![
+            Change {
+                cf: CF_WRITE.to_owned(),
+                key: Key::from_raw(key).append_ts(commit_ts).into_encoded(),
+                value: Some(write.to_bytes()),
+                old_value: None,
+            },
+            Change {
+                cf: CF_LOCK.to_owned(),
+                key: Key::from_raw(key).into_encoded(),
+                value: None,
+                old_value: None,
+            },
+        ]
+    }
+
+    fn write(key: &[u8], commit_ts: u64, write: Write) -> Change {
+        Change {
+            cf: CF_WRITE.to_owned(),
+            key: Key::from_raw(key).append_ts(commit_ts).into_encoded(),
+            value: Some(write.to_bytes()),
+            old_value: None,
+        }
+    }
+
+    fn put_engine(runner: &Runner, cf: &str, key: Key, value: &[u8]) {
+        let handle = get_cf_handle(&runner.engine, cf).unwrap();
+        runner
+            .engine
+            .put_cf(handle, &keys::data_key(key.as_encoded()), value)
+            .unwrap();
+    }
+
+    fn new_runner(dir: &TempDir) -> Runner {
+        let cfg = Config {
+            enable: true,
+            storage_url: format!("local://{}", dir.path().join("storage").display()),
+            ..Default::default()
+        };
+        let path = dir.path().join("db");
+        let engine = Arc::new(new_engine(path.to_str().unwrap(), ALL_CFS, None).unwrap());
+        Runner::new(1, engine, &cfg).unwrap()
+    }
+
+    fn register(runner: &mut Runner, region_id: u64, start: &[u8], end: &[u8]) {
+        let mut region = Region::new();
+        region.set_id(region_id);
+        region.set_start_key(Key::from_raw(start).into_encoded());
+        if !end.is_empty() {
+            region.set_end_key(Key::from_raw(end).into_encoded());
+        }
+        runner.run(Task::Register { region });
+    }
+
+    fn must_flush(runner: &mut Runner, dir: &TempDir) -> LogBackupMeta {
+        runner.run(Task::Flush);
+        let name = format!("1/meta/{}.json", runner.flush_seq);
+        let data = fs::read(dir.path().join("storage").join(name)).unwrap();
+        serde_json::from_slice(&data).unwrap()
+    }
+
+    #[test]
+    fn test_flush_change_logs() {
+        let temp_dir = TempDir::new("test_flush_change_logs").unwrap();
+        let mut runner = new_runner(&temp_dir);
+        for region_id in 1..4 {
+            register(&mut runner, region_id, b"", b"");
+        }
+
+        let put_a = Change {
+            cf: CF_DEFAULT.to_owned(),
+            key: Key::from_raw(b"a").append_ts(10).into_encoded(),
+            value: Some(b"long value".to_vec()),
+            old_value: None,
+        };
+        let changes = vec![lock(b"a", 10), put_a];
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: changes.clone(),
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 0);
+        assert_eq!(meta.files.len(), 1);
+        let file = &meta.files[0];
+        assert_eq!((file.region_id, file.total_changes), (1, 2));
+        let data = fs::read(temp_dir.path().join("storage").join(&file.name)).unwrap();
+        assert_eq!(data.len() as u64, file.size);
+        assert_eq!(decode_changes(&data).unwrap(), changes);
+
+        // The lock of b holds back the watermark of region 2, but not below the commits
+        // observed before it's written.
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: commit(b"a", 10, 11),
+        });
+        runner.run(Task::Changes {
+            region_id: 2,
+            changes: vec![lock(b"b", 15)],
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 11);
+        assert_eq!(meta.files.len(), 2);
+
+        runner.run(Task::Changes {
+            region_id: 2,
+            changes: commit(b"b", 15, 20),
+        });
+        runner.run(Task::Changes {
+            region_id: 3,
+            changes: vec![lock(b"c", 18)],
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 17);
+
+        // Nothing to flush, but the watermark advances once region 3 is deregistered.
+        runner.run(Task::Deregister { region_id: 3 });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 20);
+        assert!(meta.files.is_empty());
+    }
+
+    #[test]
+    fn test_flush_changes_of_followers() {
+        let temp_dir = TempDir::new("test_flush_changes_of_followers").unwrap();
+        let mut runner = new_runner(&temp_dir);
+
+        // The changes of a follower are not flushed, but kept for a flush interval.
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: vec![lock(b"a", 10)],
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert!(meta.files.is_empty());
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: commit(b"a", 10, 11),
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert!(meta.files.is_empty());
+        assert_eq!(runner.buffers[&1].changes.len(), 2);
+
+        // They are flushed once it becomes the leader.
+        register(&mut runner, 1, b"", b"");
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.files.len(), 1);
+        assert_eq!(meta.files[0].total_changes, 2);
+        assert_eq!(meta.watermark, 11);
+
+        // The changes applied after it steps down are flushed once more.
+        runner.run(Task::Deregister { region_id: 1 });
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: vec![lock(b"b", 12)],
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.files.len(), 1);
+        assert_eq!(meta.files[0].total_changes, 1);
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: commit(b"b", 12, 13),
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert!(meta.files.is_empty());
+    }
+
+    #[test]
+    fn test_watermark_lag() {
+        use pd::tso::compose_ts;
+
+        assert_eq!(watermark_lag(0, 1000), None);
+        let lag = watermark_lag(compose_ts(400, 3), 1000);
+        assert_eq!(lag, Some(Duration::from_millis(600)));
+        // The clock of PD may be ahead of this TiKV.
+        let lag = watermark_lag(compose_ts(1200, 0), 1000);
+        assert_eq!(lag, Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn test_load_locks_on_register() {
+        let temp_dir = TempDir::new("test_load_locks_on_register").unwrap();
+        let mut runner = new_runner(&temp_dir);
+
+        // The lock of b is written before this store becomes the leader of region 2.
+        let lock_b = lock(b"b", 15);
+        let handle = get_cf_handle(&runner.engine, CF_LOCK).unwrap();
+        runner
+            .engine
+            .put_cf(handle, &keys::data_key(&lock_b.key), lock_b.value.as_ref().unwrap())
+            .unwrap();
+        register(&mut runner, 1, b"", b"b");
+        register(&mut runner, 2, b"b", b"");
+        assert!(runner.buffers[&1].locks.is_empty());
+        assert_eq!(runner.buffers[&2].locks.len(), 1);
+
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes: commit(b"a", 18, 20),
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 14);
+
+        runner.run(Task::Changes {
+            region_id: 2,
+            changes: commit(b"b", 15, 16),
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        assert_eq!(meta.watermark, 20);
+    }
+
+    #[test]
+    fn test_attach_old_values() {
+        let temp_dir = TempDir::new("test_attach_old_values").unwrap();
+        let mut runner = new_runner(&temp_dir);
+        runner.enable_old_value = true;
+        register(&mut runner, 1, b"", b"");
+
+        // The versions committed before are only in the engine.
+        let put =
+            |start_ts, value: &[u8]| Write::new(WriteType::Put, start_ts, Some(value.to_vec()));
+        let a = Key::from_raw(b"a");
+        let b = Key::from_raw(b"b");
+        put_engine(&runner, CF_WRITE, a.clone().append_ts(10), &put(9, b"v1").to_bytes());
+        let rollback = Write::new(WriteType::Rollback, 15, None);
+        put_engine(&runner, CF_WRITE, a.clone().append_ts(15), &rollback.to_bytes());
+        let long_put = Write::new(WriteType::Put, 19, None);
+        put_engine(&runner, CF_WRITE, b.clone().append_ts(20), &long_put.to_bytes());
+        put_engine(&runner, CF_DEFAULT, b.clone().append_ts(19), b"long value");
+
+        let changes = vec![
+            write(b"a", 30, put(25, b"v2")),
+            // The value of the commit before is cached.
+            write(b"a", 40, Write::new(WriteType::Delete, 35, None)),
+            write(b"a", 45, Write::new(WriteType::Rollback, 45, None)),
+            write(b"a", 50, put(48, b"v3")),
+            write(b"b", 50, put(45, b"v4")),
+            write(b"c", 60, put(55, b"v5")),
+        ];
+        runner.run(Task::Changes {
+            region_id: 1,
+            changes,
+        });
+        let meta = must_flush(&mut runner, &temp_dir);
+        let data = fs::read(temp_dir.path().join("storage").join(&meta.files[0].name)).unwrap();
+        let old_values: Vec<_> = decode_changes(&data)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.old_value)
+            .collect();
+        assert_eq!(
+            old_values,
+            vec![
+                Some(b"v1".to_vec()),
+                Some(b"v2".to_vec()),
+                None,
+                None,
+                Some(b"long value".to_vec()),
+                None,
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Error as IoError;
+use std::result;
+
+use serde_json::Error as JsonError;
+
+use import::Error as ImportError;
+use raftstore::Error as RaftStoreError;
+use storage::mvcc::Error as MvccError;
+use util::codec::Error as CodecError;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: IoError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Json(err: JsonError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Mvcc(err: MvccError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Codec(err: CodecError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Import(err: ImportError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        RaftStore(err: RaftStoreError) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        InvalidChangeLog(msg: String) {
+            display("invalid change log: {}", msg)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,106 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use util::codec::bytes::{self, BytesEncoder};
+use util::codec::number;
+
+use super::{Error, Result};
+
+const FLAG_PUT: u8 = b'P';
+const FLAG_DELETE: u8 = b'D';
+const FLAG_OLD_VALUE: u8 = b'O';
+
+/// A write applied to a region, `value` is `None` for a delete.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub cf: String,
+    /// The key without the data prefix.
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    /// The value of the key committed before, attached to the commits in `CF_WRITE` when
+    /// `log-backup.enable-old-value` is on. `None` if the key had no value.
+    pub old_value: Option<Vec<u8>>,
+}
+
+impl Change {
+    pub fn size(&self) -> usize {
+        self.cf.len()
+            + self.key.len()
+            + self.value.as_ref().map_or(0, |v| v.len())
+            + self.old_value.as_ref().map_or(0, |v| v.len())
+    }
+}
+
+/// LogFile is a change-log file of a region, which has the changes flushed at a time,
+/// in the order they were applied.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LogFile {
+    pub name: String,
+    pub region_id: u64,
+    /// The changes committed at or before it are in this file or the earlier ones.
+    pub watermark: u64,
+    pub crc32: u32,
+    pub size: u64,
+    pub total_changes: u64,
+}
+
+/// Encodes the changes into the content of a change-log file.
+pub fn encode_changes(changes: &[Change]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(changes.iter().map(|c| c.size() + 16).sum());
+    for change in changes {
+        data.encode_compact_bytes(change.cf.as_bytes())?;
+        data.encode_compact_bytes(&change.key)?;
+        if let Some(ref old_value) = change.old_value {
+            data.push(FLAG_OLD_VALUE);
+            data.encode_compact_bytes(old_value)?;
+        }
+        match change.value {
+            Some(ref value) => {
+                data.push(FLAG_PUT);
+                data.encode_compact_bytes(value)?;
+            }
+            None => data.push(FLAG_DELETE),
+        }
+    }
+    Ok(data)
+}
+
+/// Decodes the changes from the content of a change-log file.
+pub fn decode_changes(mut data: &[u8]) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    while !data.is_empty() {
+        let cf = bytes::decode_compact_bytes(&mut data)?;
+        let cf = String::from_utf8(cf)
+            .map_err(|e| Error::InvalidChangeLog(format!("invalid cf: {}", e)))?;
+        let key = bytes::decode_compact_bytes(&mut data)?;
+        let mut flag = number::read_u8(&mut data)?;
+        let mut old_value = None;
+        if flag == FLAG_OLD_VALUE {
+            old_value = Some(bytes::decode_compact_bytes(&mut data)?);
+            flag = number::read_u8(&mut data)?;
+        }
+        let value = match flag {
+            FLAG_PUT => Some(bytes::decode_compact_bytes(&mut data)?),
+            FLAG_DELETE => None,
+            flag => return Err(Error::InvalidChangeLog(format!("invalid flag {}", flag))),
+        };
+        changes.push(Change {
+            cf,
+            key,
+            value,
+            old_value,
+        });
+    }
+    Ok(changes)
+}
@@ -0,0 +1,49 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use prometheus::*;
+
+lazy_static! {
+    pub static ref LOG_BACKUP_FLUSH_DURATION: Histogram = register_histogram!(
+        "tikv_log_backup_flush_duration_seconds",
+        "Bucketed histogram of the duration of flushing the change logs",
+        exponential_buckets(0.001, 2.0, 20).unwrap()
+    ).unwrap();
+    pub static ref LOG_BACKUP_CHANGE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_log_backup_change_total",
+        "Total number of changes archived",
+        &["cf"]
+    ).unwrap();
+    pub static ref LOG_BACKUP_BUFFERED_BYTES_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_log_backup_buffered_bytes",
+        "The bytes of the changes buffered and not flushed yet"
+    ).unwrap();
+    pub static ref LOG_BACKUP_REGION_WATERMARK_LAG: Histogram = register_histogram!(
+        "tikv_log_backup_region_watermark_lag_seconds",
+        "Bucketed histogram of how far the watermarks of the regions fall behind",
+        exponential_buckets(0.1, 2.0, 20).unwrap()
+    ).unwrap();
+    pub static ref LOG_BACKUP_WATERMARK_LAG_GAUGE: Gauge = register_gauge!(
+        "tikv_log_backup_watermark_lag_seconds",
+        "How far the watermark of the store falls behind"
+    ).unwrap();
+    pub static ref LOG_BACKUP_LAGGING_REGION_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_log_backup_lagging_regions",
+        "The number of regions whose watermarks lag behind beyond the alert threshold"
+    ).unwrap();
+    pub static ref LOG_BACKUP_OLD_VALUE_CACHE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_log_backup_old_value_cache_total",
+        "Total number of old values looked up in the cache",
+        &["type"]
+    ).unwrap();
+}
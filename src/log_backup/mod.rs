@@ -0,0 +1,49 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This mod archives the changes applied to the regions continuously, for point-in-time
+//! recovery.
+//!
+//! The observer captures the writes applied to the regions on every peer, and the endpoint
+//! buffers them per region and flushes the ones of the regions led by this TiKV as change-log
+//! files to an external storage every interval. The changes of a follower are kept for an
+//! interval, so the writes applied right before it becomes the leader are archived too, and
+//! an old leader still flushes the writes it applies after stepping down. Every flush also
+//! uploads a meta file which has the watermark of the changes: the writes committed at or
+//! before it have been flushed. Restoring a full backup taken at `backup_ts` and then
+//! replaying the change-log files in (`backup_ts`, `ts`] recovers the data at any `ts` no
+//! later than the watermark.
+//!
+//! The watermark of a region is derived from the locks and the commits observed, and the
+//! locks in the region are loaded when this TiKV becomes its leader, so it's only advanced
+//! by the writes to the region. The SST files ingested by the importer and
+//! the ranges deleted by `DeleteRange` are not archived.
+//!
+//! With `enable-old-value`, every commit archived carries the value committed before it, for
+//! the consumers that need the before-images. It's looked up in a cache of the latest values
+//! committed, or read from the engine on a miss, on the endpoint's thread instead of the
+//! apply path. The writes not archived don't update the cache, so it may return a stale value
+//! for a key overwritten by them.
+
+mod config;
+mod endpoint;
+mod errors;
+mod log_file;
+mod metrics;
+mod observer;
+
+pub use self::config::Config;
+pub use self::endpoint::{LogBackupMeta, Runner, Task};
+pub use self::errors::{Error, Result};
+pub use self::log_file::{decode_changes, encode_changes, Change, LogFile};
+pub use self::observer::LogBackupObserver;
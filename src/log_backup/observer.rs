@@ -0,0 +1,248 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use kvproto::raft_cmdpb::{AdminRequest, AdminResponse, CmdType, Request, Response};
+use protobuf::RepeatedField;
+use raft::StateRole;
+
+use raftstore::coprocessor::{
+    AdminObserver, Coprocessor, ObserverContext, QueryObserver, RoleObserver,
+};
+use storage::CF_DEFAULT;
+use util::collections::{HashMap, HashSet};
+use util::worker::Scheduler;
+
+use super::endpoint::Task;
+use super::log_file::Change;
+
+#[derive(Default)]
+struct State {
+    leaders: HashSet<u64>,
+    // The changes of every command being applied, in the order they are applied, and `None`
+    // for an admin command. A command may fail after `pre_apply_query`, so its changes are
+    // not sent until it's known to succeed in `post_apply_query`. A failed admin command
+    // has no admin response, so it reaches `post_apply_query` too.
+    pending: HashMap<u64, VecDeque<Option<Vec<Change>>>>,
+}
+
+impl State {
+    fn push(&mut self, region_id: u64, changes: Option<Vec<Change>>) {
+        self.pending
+            .entry(region_id)
+            .or_insert_with(VecDeque::new)
+            .push_back(changes);
+    }
+
+    fn pop(&mut self, region_id: u64) -> Option<Option<Vec<Change>>> {
+        let (changes, drained) = match self.pending.get_mut(&region_id) {
+            Some(queue) => (queue.pop_front(), queue.is_empty()),
+            None => (None, false),
+        };
+        if drained {
+            self.pending.remove(&region_id);
+        }
+        changes
+    }
+}
+
+/// LogBackupObserver captures the writes applied to the regions on every peer, and sends
+/// them to the log backup endpoint, which decides what to archive by the roles of the peers.
+/// So the writes applied right before this TiKV becomes the leader, or by an old leader
+/// after it steps down, are not lost.
+#[derive(Clone)]
+pub struct LogBackupObserver {
+    scheduler: Scheduler<Task>,
+    state: Arc<Mutex<State>>,
+}
+
+impl LogBackupObserver {
+    pub fn new(scheduler: Scheduler<Task>) -> LogBackupObserver {
+        LogBackupObserver {
+            scheduler,
+            state: Arc::default(),
+        }
+    }
+}
+
+impl Coprocessor for LogBackupObserver {}
+
+fn to_change(req: &Request) -> Option<Change> {
+    let (cf, key, value) = match req.get_cmd_type() {
+        CmdType::Put => {
+            let put = req.get_put();
+            (put.get_cf(), put.get_key(), Some(put.get_value().to_vec()))
+        }
+        CmdType::Delete => {
+            let delete = req.get_delete();
+            (delete.get_cf(), delete.get_key(), None)
+        }
+        _ => return None,
+    };
+    let cf = if cf.is_empty() { CF_DEFAULT } else { cf };
+    Some(Change {
+        cf: cf.to_owned(),
+        key: key.to_vec(),
+        value,
+        old_value: None,
+    })
+}
+
+impl AdminObserver for LogBackupObserver {
+    fn pre_apply_admin(&self, ctx: &mut ObserverContext, _: &AdminRequest) {
+        let region_id = ctx.region().get_id();
+        self.state.lock().unwrap().push(region_id, None);
+    }
+
+    fn post_apply_admin(&self, ctx: &mut ObserverContext, _: &mut AdminResponse) {
+        let region_id = ctx.region().get_id();
+        self.state.lock().unwrap().pop(region_id);
+    }
+}
+
+impl QueryObserver for LogBackupObserver {
+    fn pre_apply_query(&self, ctx: &mut ObserverContext, reqs: &[Request]) {
+        let region_id = ctx.region().get_id();
+        let changes = reqs.iter().filter_map(to_change).collect();
+        self.state.lock().unwrap().push(region_id, Some(changes));
+    }
+
+    fn post_apply_query(&self, ctx: &mut ObserverContext, resps: &mut RepeatedField<Response>) {
+        let region_id = ctx.region().get_id();
+        let changes = match self.state.lock().unwrap().pop(region_id) {
+            Some(Some(changes)) => changes,
+            // A failed admin command, or nothing is pending.
+            Some(None) | None => return,
+        };
+        // A failed command has no responses, and nothing of it is written.
+        if changes.is_empty() || resps.is_empty() {
+            return;
+        }
+        if let Err(e) = self.scheduler.schedule(Task::Changes { region_id, changes }) {
+            error!("[region {}] failed to send changes to log backup: {:?}", region_id, e);
+        }
+    }
+}
+
+impl RoleObserver for LogBackupObserver {
+    fn on_role_change(&self, ctx: &mut ObserverContext, role: StateRole) {
+        let region = ctx.region();
+        let region_id = region.get_id();
+        let task = {
+            let mut state = self.state.lock().unwrap();
+            if role == StateRole::Leader {
+                if !state.leaders.insert(region_id) {
+                    return;
+                }
+                Task::Register {
+                    region: region.clone(),
+                }
+            } else {
+                if !state.leaders.remove(&region_id) {
+                    return;
+                }
+                Task::Deregister { region_id }
+            }
+        };
+        if let Err(e) = self.scheduler.schedule(task) {
+            error!("[region {}] failed to send role change to log backup: {:?}", region_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{self, Sender};
+
+    use kvproto::metapb::Region;
+
+    use util::worker::{Runnable, Worker};
+
+    struct Forwarder(Sender<Task>);
+
+    impl Runnable<Task> for Forwarder {
+        fn run(&mut self, task: Task) {
+            self.0.send(task).unwrap();
+        }
+    }
+
+    fn new_put(key: &[u8]) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Put);
+        req.mut_put().set_key(key.to_vec());
+        req.mut_put().set_value(b"v".to_vec());
+        req
+    }
+
+    #[test]
+    fn test_observe_changes() {
+        let mut worker = Worker::new("test-log-backup");
+        let (tx, rx) = mpsc::channel();
+        worker.start(Forwarder(tx)).unwrap();
+        let observer = LogBackupObserver::new(worker.scheduler());
+        let mut region = Region::new();
+        region.set_id(1);
+        let mut ctx = ObserverContext::new(&region);
+        let mut resps = RepeatedField::from_vec(vec![Response::new()]);
+        let mut no_resps = RepeatedField::default();
+
+        // Not a leader yet, but the changes are still captured.
+        observer.pre_apply_query(&mut ctx, &[new_put(b"a")]);
+        observer.post_apply_query(&mut ctx, &mut resps);
+        observer.on_role_change(&mut ctx, StateRole::Leader);
+        observer.pre_apply_query(&mut ctx, &[new_put(b"b")]);
+        // The admin command fails, and is skipped without taking the changes of others.
+        observer.pre_apply_admin(&mut ctx, &AdminRequest::new());
+        observer.pre_apply_query(&mut ctx, &[new_put(b"c")]);
+        observer.pre_apply_admin(&mut ctx, &AdminRequest::new());
+        // The command of b fails.
+        observer.post_apply_query(&mut ctx, &mut no_resps);
+        observer.post_apply_query(&mut ctx, &mut no_resps);
+        observer.post_apply_query(&mut ctx, &mut resps);
+        observer.post_apply_admin(&mut ctx, &mut AdminResponse::new());
+        observer.on_role_change(&mut ctx, StateRole::Follower);
+        observer.on_role_change(&mut ctx, StateRole::Follower);
+        worker.stop().unwrap().join().unwrap();
+
+        let tasks: Vec<_> = rx.try_iter().collect();
+        assert_eq!(tasks.len(), 4);
+        let keys: Vec<&[u8]> = vec![b"a", b"c"];
+        for (task, key) in vec![&tasks[0], &tasks[2]].into_iter().zip(keys) {
+            match *task {
+                Task::Changes {
+                    region_id,
+                    ref changes,
+                } => {
+                    assert_eq!(region_id, 1);
+                    assert_eq!(changes.len(), 1);
+                    assert_eq!(changes[0].cf, CF_DEFAULT);
+                    assert_eq!(changes[0].key, key);
+                }
+                ref t => panic!("unexpected task {}", t),
+            }
+        }
+        match tasks[1] {
+            Task::Register { ref region } => assert_eq!(region.get_id(), 1),
+            ref t => panic!("unexpected task {}", t),
+        }
+        match tasks[3] {
+            Task::Deregister { region_id } => assert_eq!(region_id, 1),
+            ref t => panic!("unexpected task {}", t),
+        }
+        assert!(observer.state.lock().unwrap().pending.is_empty());
+    }
+}
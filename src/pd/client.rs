@@ -12,19 +12,22 @@
 // limitations under the License.
 
 use std::fmt;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 use futures::sync::mpsc;
 use futures::{future, Future, Sink, Stream};
-use grpc::{CallOption, EnvBuilder, WriteFlags};
+use grpc::{CallOption, ClientDuplexReceiver, ClientDuplexSender, EnvBuilder, WriteFlags};
 use kvproto::metapb;
 use kvproto::pdpb::{self, Member};
 use protobuf::RepeatedField;
 
 use super::metrics::*;
-use super::util::{check_resp_header, sync_request, validate_endpoints, Inner, LeaderClient};
-use super::{Error, PdClient, RegionInfo, RegionStat, Result, REQUEST_TIMEOUT};
+use super::tso::TsoBatcher;
+use super::util::{
+    check_resp_header, sync_request, validate_endpoints, BatchHintStream, Inner, LeaderClient,
+};
+use super::{Error, FeatureGate, PdClient, RegionInfo, RegionStat, Result, REQUEST_TIMEOUT};
 use pd::{Config, PdFuture};
 use util::security::SecurityManager;
 use util::time::{duration_to_sec, time_now_sec};
@@ -33,9 +36,16 @@ use util::{Either, HandyRwLock};
 const CQ_COUNT: usize = 1;
 const CLIENT_PREFIX: &str = "pd";
 
+type TsoStream = (
+    ClientDuplexSender<pdpb::TsoRequest>,
+    ClientDuplexReceiver<pdpb::TsoResponse>,
+);
+
 pub struct RpcClient {
     cluster_id: u64,
     leader_client: LeaderClient,
+    tso: TsoBatcher,
+    feature_gate: FeatureGate,
 }
 
 impl RpcClient {
@@ -47,10 +57,15 @@ impl RpcClient {
                 .build(),
         );
         let (client, members) = validate_endpoints(Arc::clone(&env), cfg, &security_mgr)?;
+        let cluster_id = members.get_header().get_cluster_id();
+        let leader_client = LeaderClient::new(env, security_mgr, client, members);
+        let tso = Self::new_tso_batcher(cluster_id, leader_client.clone());
 
         Ok(RpcClient {
-            cluster_id: members.get_header().get_cluster_id(),
-            leader_client: LeaderClient::new(env, security_mgr, client, members),
+            cluster_id,
+            leader_client,
+            tso,
+            feature_gate: FeatureGate::new(),
         })
     }
 
@@ -60,6 +75,60 @@ impl RpcClient {
         header
     }
 
+    fn new_tso_batcher(cluster_id: u64, leader_client: LeaderClient) -> TsoBatcher {
+        // The batcher has at most one request in flight, so the requests never interleave on
+        // the stream. It's dropped on any error, and the retry opens a new one to the leader.
+        let stream: Arc<Mutex<Option<TsoStream>>> = Arc::default();
+        TsoBatcher::new(Box::new(move |count| {
+            let timer = Instant::now();
+
+            let mut req = pdpb::TsoRequest::new();
+            req.mut_header().set_cluster_id(cluster_id);
+            req.set_count(count);
+
+            let stream = Arc::clone(&stream);
+            let executor = move |client: &RwLock<Inner>, req: pdpb::TsoRequest| {
+                let (tx, rx) = match stream.lock().unwrap().take() {
+                    Some(s) => s,
+                    None => client.rl().client.tso().unwrap(),
+                };
+                let stream = Arc::clone(&stream);
+                Box::new(
+                    tx.send((req, WriteFlags::default()))
+                        .and_then(|tx| {
+                            rx.into_future()
+                                .map(|(resp, rx)| (tx, rx, resp))
+                                .map_err(|(e, _)| e)
+                        })
+                        .map_err(Error::Grpc)
+                        .and_then(move |(tx, rx, resp)| {
+                            PD_REQUEST_HISTOGRAM_VEC
+                                .with_label_values(&["tso"])
+                                .observe(duration_to_sec(timer.elapsed()));
+                            let mut resp = match resp {
+                                Some(resp) => resp,
+                                None => return Err(box_err!("TSO stream is closed")),
+                            };
+                            check_resp_header(resp.get_header())?;
+                            if resp.get_count() != count {
+                                return Err(box_err!(
+                                    "got {} timestamps, want {}",
+                                    resp.get_count(),
+                                    count
+                                ));
+                            }
+                            *stream.lock().unwrap() = Some((tx, rx));
+                            Ok(resp.take_timestamp())
+                        }),
+                ) as PdFuture<_>
+            };
+
+            leader_client
+                .request(req, executor, LEADER_CHANGE_RETRY)
+                .execute()
+        }))
+    }
+
     pub fn get_leader(&self) -> Member {
         self.leader_client.get_leader()
     }
@@ -285,7 +354,7 @@ impl PdClient for RpcClient {
         req.set_bytes_written(region_stat.written_bytes);
         req.set_keys_written(region_stat.written_keys);
         req.set_bytes_read(region_stat.read_bytes);
-        req.set_keys_read(region_stat.read_bytes);
+        req.set_keys_read(region_stat.read_keys);
         req.set_approximate_size(region_stat.approximate_size);
         req.set_approximate_keys(region_stat.approximate_keys);
         let mut interval = pdpb::TimeInterval::new();
@@ -311,10 +380,9 @@ impl PdClient for RpcClient {
             Box::new(
                 sender
                     .sink_map_err(Error::Grpc)
-                    .send_all(rx.then(|r| match r {
-                        Ok(r) => Ok((r, WriteFlags::default())),
-                        Err(()) => Err(Error::Other(box_err!("failed to recv heartbeat"))),
-                    }))
+                    .send_all(BatchHintStream::new(rx.map_err(|()| {
+                        Error::Other(box_err!("failed to recv heartbeat"))
+                    })))
                     .then(|result| match result {
                         Ok((mut sender, _)) => {
                             info!("cancel region heartbeat sender");
@@ -473,6 +541,41 @@ impl PdClient for RpcClient {
         check_resp_header(resp.get_header())
     }
 
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        let timer = Instant::now();
+
+        let mut req = pdpb::GetGCSafePointRequest::new();
+        req.set_header(self.header());
+
+        let executor = move |client: &RwLock<Inner>, req: pdpb::GetGCSafePointRequest| {
+            let handler = client
+                .rl()
+                .client
+                .get_gc_safe_point_async_opt(&req, Self::call_option())
+                .unwrap();
+            Box::new(handler.map_err(Error::Grpc).and_then(move |resp| {
+                PD_REQUEST_HISTOGRAM_VEC
+                    .with_label_values(&["get_gc_safe_point"])
+                    .observe(duration_to_sec(timer.elapsed()));
+                check_resp_header(resp.get_header())?;
+                Ok(resp.get_safe_point())
+            })) as PdFuture<_>
+        };
+
+        self.leader_client
+            .request(req, executor, LEADER_CHANGE_RETRY)
+            .execute()
+    }
+
+    fn get_tso(&self) -> PdFuture<u64> {
+        self.tso.get_ts()
+    }
+
+    // The cluster version is set by the `ClusterWatcher` which watches the stores.
+    fn feature_gate(&self) -> FeatureGate {
+        self.feature_gate.clone()
+    }
+
     fn handle_reconnect<F: Fn() + Sync + Send + 'static>(&self, f: F) {
         self.leader_client.on_reconnect(Box::new(f))
     }
@@ -0,0 +1,319 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures::Future;
+use kvproto::metapb;
+
+use super::feature_gate::min_store_version;
+use super::{FeatureGate, PdClient, Result};
+
+/// Provides the cluster-level states, which are usually maintained by PD.
+pub trait ClusterProvider: Send + 'static {
+    fn get_gc_safe_point(&self) -> Result<u64>;
+    fn get_cluster_config(&self) -> Result<metapb::Cluster>;
+    fn get_all_stores(&self) -> Result<Vec<metapb::Store>>;
+}
+
+impl<T: PdClient + 'static> ClusterProvider for Arc<T> {
+    fn get_gc_safe_point(&self) -> Result<u64> {
+        PdClient::get_gc_safe_point(self.as_ref()).wait()
+    }
+
+    fn get_cluster_config(&self) -> Result<metapb::Cluster> {
+        PdClient::get_cluster_config(self.as_ref())
+    }
+
+    fn get_all_stores(&self) -> Result<Vec<metapb::Store>> {
+        PdClient::get_all_stores(self.as_ref())
+    }
+}
+
+/// A change of the cluster-level states.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClusterEvent {
+    SafePointAdvanced(u64),
+    ConfigChanged(metapb::Cluster),
+    /// The minimal version of the stores is advanced.
+    VersionAdvanced(String),
+}
+
+#[derive(Default)]
+struct ClusterState {
+    safe_point: u64,
+    config: Option<metapb::Cluster>,
+    feature_gate: FeatureGate,
+    subscribers: Vec<Sender<ClusterEvent>>,
+}
+
+impl ClusterState {
+    fn update_safe_point(&mut self, safe_point: u64) {
+        if safe_point < self.safe_point {
+            warn!(
+                "cluster watcher: safe point {} is less than the last one {}, ignore it",
+                safe_point, self.safe_point
+            );
+            return;
+        }
+        if safe_point == self.safe_point {
+            return;
+        }
+        self.safe_point = safe_point;
+        self.notify(ClusterEvent::SafePointAdvanced(safe_point));
+    }
+
+    fn update_config(&mut self, config: metapb::Cluster) {
+        if self.config.as_ref() == Some(&config) {
+            return;
+        }
+        info!("cluster watcher: cluster config is changed to {:?}", config);
+        self.config = Some(config.clone());
+        self.notify(ClusterEvent::ConfigChanged(config));
+    }
+
+    fn update_version(&mut self, stores: &[metapb::Store]) {
+        let version = match min_store_version(stores) {
+            Some(v) => v,
+            None => return,
+        };
+        match self.feature_gate.set_version(&version) {
+            Ok(true) => self.notify(ClusterEvent::VersionAdvanced(version)),
+            Ok(false) => {}
+            Err(e) => warn!("cluster watcher: failed to set cluster version: {:?}", e),
+        }
+    }
+
+    fn notify(&mut self, event: ClusterEvent) {
+        // Subscribers whose receivers are dropped are removed.
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    fn subscribe(&mut self) -> Receiver<ClusterEvent> {
+        let (tx, rx) = mpsc::channel();
+        // A new subscriber learns the known states first.
+        if self.safe_point > 0 {
+            tx.send(ClusterEvent::SafePointAdvanced(self.safe_point))
+                .unwrap();
+        }
+        if let Some(ref config) = self.config {
+            tx.send(ClusterEvent::ConfigChanged(config.clone())).unwrap();
+        }
+        self.subscribers.push(tx);
+        rx
+    }
+}
+
+/// `ClusterWatcher` polls the GC safe point, the cluster config and the versions of the stores
+/// from PD, and notifies the subscribers once they change. The components embedded in TiKV
+/// get them from here instead of asking PD by themselves.
+#[derive(Clone, Default)]
+pub struct ClusterWatcher {
+    state: Arc<Mutex<ClusterState>>,
+}
+
+impl ClusterWatcher {
+    pub fn new() -> ClusterWatcher {
+        ClusterWatcher::default()
+    }
+
+    /// Creates a watcher which advances `feature_gate` by the minimal version of the stores.
+    pub fn with_feature_gate(feature_gate: FeatureGate) -> ClusterWatcher {
+        let watcher = ClusterWatcher::new();
+        watcher.state.lock().unwrap().feature_gate = feature_gate;
+        watcher
+    }
+
+    /// Returns a receiver of the cluster events. The safe point and the cluster config that
+    /// are already known are sent to it first.
+    pub fn subscribe(&self) -> Receiver<ClusterEvent> {
+        self.state.lock().unwrap().subscribe()
+    }
+
+    /// Returns the safe point learned from the last poll, or 0 if it's never learned.
+    pub fn get_safe_point(&self) -> u64 {
+        self.state.lock().unwrap().safe_point
+    }
+
+    /// Returns the cluster config learned from the last poll.
+    pub fn get_config(&self) -> Option<metapb::Cluster> {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    pub fn feature_gate(&self) -> FeatureGate {
+        self.state.lock().unwrap().feature_gate.clone()
+    }
+
+    /// Polls the states by `provider` every `interval` in a new thread. The returned handle
+    /// is used to stop it.
+    pub fn start<P: ClusterProvider>(
+        &self,
+        provider: P,
+        interval: Duration,
+    ) -> Result<ClusterWatcherHandle> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let state = Arc::clone(&self.state);
+        let join_handle = thread::Builder::new()
+            .name(thd_name!("cluster-watcher"))
+            .spawn(move || loop {
+                match provider.get_gc_safe_point() {
+                    Ok(sp) => state.lock().unwrap().update_safe_point(sp),
+                    Err(e) => warn!("cluster watcher failed to get safe point: {:?}", e),
+                }
+                match provider.get_cluster_config() {
+                    Ok(cfg) => state.lock().unwrap().update_config(cfg),
+                    Err(e) => warn!("cluster watcher failed to get cluster config: {:?}", e),
+                }
+                match provider.get_all_stores() {
+                    Ok(stores) => state.lock().unwrap().update_version(&stores),
+                    Err(e) => warn!("cluster watcher failed to get stores: {:?}", e),
+                }
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+            })?;
+        Ok(ClusterWatcherHandle {
+            join_handle,
+            stop_tx,
+        })
+    }
+}
+
+/// Used to stop a started `ClusterWatcher`.
+pub struct ClusterWatcherHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl ClusterWatcherHandle {
+    pub fn stop(self) {
+        let _ = self.stop_tx.send(());
+        if let Err(e) = self.join_handle.join() {
+            error!("failed to join cluster watcher thread: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pd::feature_gate::BATCH_SPLIT;
+
+    #[derive(Clone, Default)]
+    struct MockClusterProvider {
+        safe_point: Arc<Mutex<u64>>,
+        max_peer_count: Arc<Mutex<u32>>,
+        versions: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl ClusterProvider for MockClusterProvider {
+        fn get_gc_safe_point(&self) -> Result<u64> {
+            Ok(*self.safe_point.lock().unwrap())
+        }
+
+        fn get_cluster_config(&self) -> Result<metapb::Cluster> {
+            Ok(new_cluster(*self.max_peer_count.lock().unwrap()))
+        }
+
+        fn get_all_stores(&self) -> Result<Vec<metapb::Store>> {
+            let versions = self.versions.lock().unwrap();
+            Ok(versions
+                .iter()
+                .map(|v| {
+                    let mut store = metapb::Store::new();
+                    store.set_version(v.to_string());
+                    store
+                })
+                .collect())
+        }
+    }
+
+    fn new_cluster(max_peer_count: u32) -> metapb::Cluster {
+        let mut cluster = metapb::Cluster::new();
+        cluster.set_id(1);
+        cluster.set_max_peer_count(max_peer_count);
+        cluster
+    }
+
+    #[test]
+    fn test_cluster_watcher() {
+        let provider = MockClusterProvider::default();
+        *provider.safe_point.lock().unwrap() = 10;
+        *provider.max_peer_count.lock().unwrap() = 3;
+        let watcher = ClusterWatcher::new();
+        let rx1 = watcher.subscribe();
+        let handle = watcher
+            .start(provider.clone(), Duration::from_millis(10))
+            .unwrap();
+        let timeout = Duration::from_secs(3);
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::SafePointAdvanced(10)
+        );
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::ConfigChanged(new_cluster(3))
+        );
+
+        // A late subscriber gets the known states.
+        let rx2 = watcher.subscribe();
+        assert_eq!(
+            rx2.recv_timeout(timeout).unwrap(),
+            ClusterEvent::SafePointAdvanced(10)
+        );
+        assert_eq!(
+            rx2.recv_timeout(timeout).unwrap(),
+            ClusterEvent::ConfigChanged(new_cluster(3))
+        );
+        drop(rx2);
+
+        // The safe point never goes back.
+        *provider.safe_point.lock().unwrap() = 5;
+        *provider.max_peer_count.lock().unwrap() = 5;
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::ConfigChanged(new_cluster(5))
+        );
+        assert_eq!(watcher.get_safe_point(), 10);
+
+        *provider.safe_point.lock().unwrap() = 20;
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::SafePointAdvanced(20)
+        );
+        assert_eq!(watcher.get_safe_point(), 20);
+        assert_eq!(watcher.get_config(), Some(new_cluster(5)));
+
+        // The cluster version is the minimal version of the stores.
+        *provider.versions.lock().unwrap() = vec!["2.1.0", "2.0.5"];
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::VersionAdvanced("2.0.5".to_owned())
+        );
+        assert!(!watcher.feature_gate().can_enable(BATCH_SPLIT));
+        *provider.versions.lock().unwrap() = vec!["2.1.0", "2.1.0"];
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            ClusterEvent::VersionAdvanced("2.1.0".to_owned())
+        );
+        assert!(watcher.feature_gate().can_enable(BATCH_SPLIT));
+
+        handle.stop();
+        // The dropped subscriber has been removed.
+        assert_eq!(watcher.state.lock().unwrap().subscribers.len(), 1);
+    }
+}
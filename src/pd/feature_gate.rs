@@ -0,0 +1,167 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Gates the features which old TiKVs can't handle by the cluster version.
+//!
+//! During a rolling upgrade, a store running the new version must not propose the admin
+//! commands or send the messages that the stores running the old version don't know. The
+//! cluster version is the minimal version of all the stores reported by PD, and a feature is
+//! enabled only after the cluster version reaches the version which introduced it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use kvproto::metapb;
+
+use super::Result;
+
+/// A feature, which is enabled since the given version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Feature {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Feature {
+    fn version(&self) -> u64 {
+        encode_version(self.major, self.minor, self.patch)
+    }
+}
+
+/// Splits a region into multiple regions by a single admin command.
+pub const BATCH_SPLIT: Feature = Feature {
+    major: 2,
+    minor: 1,
+    patch: 0,
+};
+
+fn encode_version(major: u64, minor: u64, patch: u64) -> u64 {
+    (major << 40) | (minor << 20) | patch
+}
+
+/// Parses a version like `2.1.0` or `2.1.0-rc.3`. The pre-release part is ignored.
+pub fn parse_version(version: &str) -> Result<u64> {
+    let numbers = version.split(|c| c == '-' || c == '+').next().unwrap();
+    let parts: Vec<_> = numbers.trim_left_matches('v').split('.').collect();
+    if parts.len() != 3 {
+        return Err(box_err!("invalid version {:?}", version));
+    }
+    let mut nums = [0; 3];
+    for (n, p) in nums.iter_mut().zip(parts) {
+        *n = p
+            .parse()
+            .map_err(|e| box_err!("invalid version {:?}: {:?}", version, e))?;
+    }
+    Ok(encode_version(nums[0], nums[1], nums[2]))
+}
+
+/// Returns the minimal version of the stores which are not tombstone. The stores which don't
+/// report their versions are regarded as running the oldest version.
+pub fn min_store_version(stores: &[metapb::Store]) -> Option<String> {
+    stores
+        .iter()
+        .filter(|s| s.get_state() != metapb::StoreState::Tombstone)
+        .map(|s| s.get_version())
+        .min_by_key(|v| parse_version(v).unwrap_or(0))
+        .map(|v| v.to_owned())
+}
+
+/// `FeatureGate` tells whether a feature can be enabled by the cluster version.
+///
+/// The cluster version never goes back, so a feature is never disabled once enabled.
+#[derive(Clone, Default)]
+pub struct FeatureGate {
+    version: Arc<AtomicU64>,
+}
+
+impl FeatureGate {
+    /// Creates a gate which enables no features until the cluster version is set.
+    pub fn new() -> FeatureGate {
+        FeatureGate::default()
+    }
+
+    /// Sets the cluster version. Returns whether the cluster version is advanced.
+    pub fn set_version(&self, version: &str) -> Result<bool> {
+        let new = parse_version(version)?;
+        let mut current = self.version.load(Ordering::SeqCst);
+        while current < new {
+            let prev = self
+                .version
+                .compare_and_swap(current, new, Ordering::SeqCst);
+            if prev == current {
+                info!("cluster version is advanced to {}", version);
+                return Ok(true);
+            }
+            current = prev;
+        }
+        Ok(false)
+    }
+
+    pub fn can_enable(&self, feature: Feature) -> bool {
+        self.version.load(Ordering::SeqCst) >= feature.version()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_store(version: &str, state: metapb::StoreState) -> metapb::Store {
+        let mut store = metapb::Store::new();
+        store.set_version(version.to_owned());
+        store.set_state(state);
+        store
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("2.1.0").unwrap(), encode_version(2, 1, 0));
+        assert_eq!(parse_version("v2.1.3").unwrap(), encode_version(2, 1, 3));
+        assert_eq!(
+            parse_version("2.1.0-rc.3").unwrap(),
+            encode_version(2, 1, 0)
+        );
+        assert!(parse_version("2.0.10").unwrap() < parse_version("2.1.0").unwrap());
+        for v in &["", "2.1", "2.1.x", "2.1.0.1"] {
+            parse_version(v).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_min_store_version() {
+        assert_eq!(min_store_version(&[]), None);
+        let stores = vec![
+            new_store("2.1.0", metapb::StoreState::Up),
+            new_store("2.0.5", metapb::StoreState::Offline),
+            new_store("1.0.8", metapb::StoreState::Tombstone),
+        ];
+        assert_eq!(min_store_version(&stores), Some("2.0.5".to_owned()));
+    }
+
+    #[test]
+    fn test_feature_gate() {
+        let gate = FeatureGate::new();
+        assert!(!gate.can_enable(BATCH_SPLIT));
+        assert!(gate.set_version("2.0.5").unwrap());
+        assert!(!gate.can_enable(BATCH_SPLIT));
+        gate.set_version("invalid").unwrap_err();
+
+        let cloned = gate.clone();
+        assert!(cloned.set_version("2.1.0-rc.1").unwrap());
+        assert!(gate.can_enable(BATCH_SPLIT));
+        // The cluster version never goes back.
+        assert!(!gate.set_version("2.0.5").unwrap());
+        assert!(gate.can_enable(BATCH_SPLIT));
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use util::collections::HashMap;
+
+// The flow of a region is averaged over its last this many heartbeats.
+const FLOW_WINDOW_SIZE: usize = 5;
+// The flow of a region is dropped if it's not reported in the time of this many windows, like
+// when its leader is moved away.
+const STALE_WINDOWS: u64 = 2;
+
+#[derive(Clone, Copy, Default)]
+struct FlowSample {
+    bytes: u64,
+    keys: u64,
+    secs: u64,
+}
+
+struct FlowWindow {
+    samples: VecDeque<FlowSample>,
+    total: FlowSample,
+    last_push: Instant,
+}
+
+impl FlowWindow {
+    fn new(now: Instant) -> FlowWindow {
+        FlowWindow {
+            samples: VecDeque::with_capacity(FLOW_WINDOW_SIZE),
+            total: FlowSample::default(),
+            last_push: now,
+        }
+    }
+
+    fn push(&mut self, sample: FlowSample, now: Instant) {
+        if self.samples.len() == FLOW_WINDOW_SIZE {
+            let expired = self.samples.pop_front().unwrap();
+            self.total.bytes -= expired.bytes;
+            self.total.keys -= expired.keys;
+            self.total.secs -= expired.secs;
+        }
+        self.total.bytes += sample.bytes;
+        self.total.keys += sample.keys;
+        self.total.secs += sample.secs;
+        self.samples.push_back(sample);
+        self.last_push = now;
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        if now <= self.last_push || self.samples.is_empty() {
+            return false;
+        }
+        let interval = self.total.secs / self.samples.len() as u64;
+        let elapsed = now.duration_since(self.last_push).as_secs();
+        elapsed > interval * FLOW_WINDOW_SIZE as u64 * STALE_WINDOWS
+    }
+
+    fn stat(&self, region_id: u64) -> HotRegionStat {
+        let secs = self.total.secs.max(1) as f64;
+        HotRegionStat {
+            region_id,
+            bytes_rate: self.total.bytes as f64 / secs,
+            keys_rate: self.total.keys as f64 / secs,
+        }
+    }
+}
+
+/// The average flow of a region in its rolling window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotRegionStat {
+    pub region_id: u64,
+    pub bytes_rate: f64,
+    pub keys_rate: f64,
+}
+
+/// `HotRegionRecorder` keeps the read and write flows of the regions reported in the last
+/// heartbeats, and picks the hottest regions of the store by bytes per second.
+pub struct HotRegionRecorder {
+    top_k: usize,
+    read: HashMap<u64, FlowWindow>,
+    write: HashMap<u64, FlowWindow>,
+}
+
+impl HotRegionRecorder {
+    pub fn new(top_k: usize) -> HotRegionRecorder {
+        HotRegionRecorder {
+            top_k,
+            read: HashMap::default(),
+            write: HashMap::default(),
+        }
+    }
+
+    /// Records the flow of a region between two heartbeats which are `secs` apart.
+    pub fn record(
+        &mut self,
+        region_id: u64,
+        secs: u64,
+        read_bytes: u64,
+        read_keys: u64,
+        written_bytes: u64,
+        written_keys: u64,
+    ) {
+        if secs == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let read = FlowSample {
+            bytes: read_bytes,
+            keys: read_keys,
+            secs,
+        };
+        self.read
+            .entry(region_id)
+            .or_insert_with(|| FlowWindow::new(now))
+            .push(read, now);
+        let write = FlowSample {
+            bytes: written_bytes,
+            keys: written_keys,
+            secs,
+        };
+        self.write
+            .entry(region_id)
+            .or_insert_with(|| FlowWindow::new(now))
+            .push(write, now);
+    }
+
+    /// Drops the flows of the regions which are not reported in the time of `STALE_WINDOWS`
+    /// windows by `now`.
+    pub fn evict_stale(&mut self, now: Instant) {
+        self.read.retain(|_, window| !window.is_stale(now));
+        self.write.retain(|_, window| !window.is_stale(now));
+    }
+
+    pub fn remove(&mut self, region_id: u64) {
+        self.read.remove(&region_id);
+        self.write.remove(&region_id);
+    }
+
+    pub fn top_read(&self) -> Vec<HotRegionStat> {
+        top_k(&self.read, self.top_k)
+    }
+
+    pub fn top_write(&self) -> Vec<HotRegionStat> {
+        top_k(&self.write, self.top_k)
+    }
+}
+
+fn top_k(flows: &HashMap<u64, FlowWindow>, k: usize) -> Vec<HotRegionStat> {
+    let mut stats: Vec<_> = flows
+        .iter()
+        .map(|(&region_id, window)| window.stat(region_id))
+        .filter(|stat| stat.bytes_rate > 0.0)
+        .collect();
+    stats.sort_by(|a, b| {
+        b.bytes_rate
+            .partial_cmp(&a.bytes_rate)
+            .unwrap_or(Ordering::Equal)
+    });
+    stats.truncate(k);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_hot_region_recorder() {
+        let mut recorder = HotRegionRecorder::new(2);
+        // Regions reported for the first time have no interval.
+        recorder.record(1, 0, 100, 1, 100, 1);
+        assert!(recorder.top_read().is_empty());
+
+        recorder.record(1, 10, 100, 10, 1000, 100);
+        recorder.record(2, 10, 200, 20, 0, 0);
+        recorder.record(3, 10, 300, 30, 500, 50);
+        let read: Vec<_> = recorder.top_read().iter().map(|s| s.region_id).collect();
+        assert_eq!(read, vec![3, 2]);
+        let write = recorder.top_write();
+        assert_eq!(write.len(), 2);
+        assert_eq!(write[0].region_id, 1);
+        assert_eq!(write[0].bytes_rate, 100.0);
+        assert_eq!(write[0].keys_rate, 10.0);
+        assert_eq!(write[1].region_id, 3);
+
+        // Old samples leave the window.
+        for _ in 0..FLOW_WINDOW_SIZE {
+            recorder.record(1, 10, 0, 0, 0, 0);
+        }
+        let write: Vec<_> = recorder.top_write().iter().map(|s| s.region_id).collect();
+        assert_eq!(write, vec![3]);
+
+        recorder.remove(3);
+        let read: Vec<_> = recorder.top_read().iter().map(|s| s.region_id).collect();
+        assert_eq!(read, vec![2]);
+
+        // Region 2 is reported every 10s, and is stale after 2 windows without reports.
+        let now = Instant::now();
+        recorder.evict_stale(now + Duration::from_secs(100));
+        assert_eq!(recorder.top_read().len(), 1);
+        recorder.evict_stale(now + Duration::from_secs(101));
+        assert!(recorder.top_read().is_empty());
+        assert!(recorder.read.is_empty() && recorder.write.is_empty());
+    }
+}
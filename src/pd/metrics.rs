@@ -24,6 +24,16 @@ lazy_static! {
         "Total number of PD heartbeat messages.",
         &["type"]
     ).unwrap();
+    pub static ref PD_RECONNECT_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_pd_reconnect_total",
+        "Total number of PD client reconnections.",
+        &["type"]
+    ).unwrap();
+    pub static ref PD_TSO_BATCH_SIZE_HISTOGRAM: Histogram = register_histogram!(
+        "tikv_pd_tso_batch_size",
+        "Bucketed histogram of the number of timestamps asked for by a TSO request",
+        exponential_buckets(1.0, 2.0, 14).unwrap()
+    ).unwrap();
     pub static ref PD_VALIDATE_PEER_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
         "tikv_pd_validate_peer_total",
         "Total number of pd worker validate peer task.",
@@ -51,4 +61,18 @@ lazy_static! {
         "Histogram of keys written for regions",
         exponential_buckets(1.0, 2.0, 20).unwrap()
     ).unwrap();
+    pub static ref HOT_REGION_FLOW_GAUGE_VEC: GaugeVec = register_gauge_vec!(
+        "tikv_pd_hot_region_flow_bytes",
+        "Bytes per second of the hottest regions of the store.",
+        &["type", "region"]
+    ).unwrap();
+    pub static ref STORE_CPU_USAGE_GAUGE: Gauge = register_gauge!(
+        "tikv_store_cpu_usage",
+        "CPU cores used by the store between the last two heartbeats."
+    ).unwrap();
+    pub static ref STORE_IO_RATE_GAUGE_VEC: GaugeVec = register_gauge_vec!(
+        "tikv_store_io_rate_bytes",
+        "Bytes per second read from and written to the disk by the store.",
+        &["type"]
+    ).unwrap();
 }
@@ -12,16 +12,26 @@
 // limitations under the License.
 
 mod client;
+mod cluster_watcher;
+mod hot_region;
 mod metrics;
 mod util;
 
 mod config;
 pub mod errors;
+pub mod feature_gate;
 pub mod pd;
+mod store_watcher;
+pub mod tso;
 pub use self::client::RpcClient;
+pub use self::cluster_watcher::{
+    ClusterEvent, ClusterProvider, ClusterWatcher, ClusterWatcherHandle,
+};
 pub use self::config::Config;
 pub use self::errors::{Error, Result};
+pub use self::feature_gate::FeatureGate;
 pub use self::pd::{Runner as PdRunner, Task as PdTask};
+pub use self::store_watcher::{StoreEvent, StoreProvider, StoreWatcher, StoreWatcherHandle};
 pub use self::util::validate_endpoints;
 pub use self::util::RECONNECT_INTERVAL_SEC;
 
@@ -172,6 +182,25 @@ pub trait PdClient: Send + Sync {
         unimplemented!();
     }
 
+    // Get the GC safe point of the cluster. Versions older than the safe point
+    // can be removed by GC.
+    fn get_gc_safe_point(&self) -> PdFuture<u64> {
+        unimplemented!();
+    }
+
+    // Get a timestamp from PD. Concurrent calls are served by a single request to PD.
+    fn get_tso(&self) -> PdFuture<u64> {
+        unimplemented!();
+    }
+
+    // Get the gate of the features by the cluster version. By default, all the stores are
+    // regarded as running the same version as this one.
+    fn feature_gate(&self) -> FeatureGate {
+        let gate = FeatureGate::new();
+        gate.set_version(env!("CARGO_PKG_VERSION")).unwrap();
+        gate
+    }
+
     // Register a handler to the client, it will be invoked after reconnecting to PD.
     //
     // Please note that this method should only be called once.
@@ -13,6 +13,7 @@
 
 use std::fmt::{self, Display, Formatter};
 use std::sync::Arc;
+use std::time::Instant;
 
 use futures::Future;
 use tokio_core::reactor::Handle;
@@ -26,30 +27,37 @@ use protobuf::RepeatedField;
 use raft::eraftpb::ConfChangeType;
 use rocksdb::DB;
 
+use super::hot_region::{HotRegionRecorder, HotRegionStat};
 use super::metrics::*;
-use pd::{Error, PdClient, RegionStat};
+use pd::feature_gate::BATCH_SPLIT;
+use pd::{Error, FeatureGate, PdClient, RegionInfo, RegionStat};
 use prometheus::local::LocalHistogram;
 use raftstore::store::cmd_resp::new_error;
 use raftstore::store::util::KeysInfoFormatter;
 use raftstore::store::util::{
     get_region_approximate_keys, get_region_approximate_size, is_epoch_stale,
 };
-use raftstore::store::Callback;
+use raftstore::store::{Callback, WriteResponse};
 use raftstore::store::Msg;
 use raftstore::store::StoreInfo;
 use storage::FlowStatistics;
 use util::collections::HashMap;
-use util::escape;
+use util::metrics::{process_stat, ProcessStat};
 use util::rocksdb::*;
-use util::time::time_now_sec;
+use util::time::{duration_to_sec, time_now_sec};
 use util::transport::SendCh;
 use util::worker::{FutureRunnable as Runnable, FutureScheduler as Scheduler, Stopped};
 
+// The number of the hottest regions reported in metrics.
+const HOT_REGION_TOP_K: usize = 10;
+
 // Use an asynchronous thread to tell pd something.
 pub enum Task {
+    // Splits the region at the keys one by one, for the clusters which don't support
+    // batch split.
     AskSplit {
         region: metapb::Region,
-        split_key: Vec<u8>,
+        split_keys: Vec<Vec<u8>>,
         peer: metapb::Peer,
         // If true, right region derive origin region_id.
         right_derive: bool,
@@ -80,6 +88,10 @@ pub enum Task {
     ReportBatchSplit {
         regions: Vec<metapb::Region>,
     },
+    ScatterRegion {
+        region: metapb::Region,
+        leader: metapb::Peer,
+    },
     ValidatePeer {
         region: metapb::Region,
         peer: metapb::Peer,
@@ -99,6 +111,7 @@ pub struct StoreStat {
     pub engine_last_total_bytes_read: u64,
     pub engine_last_total_keys_read: u64,
     pub last_report_ts: u64,
+    pub last_process_stat: Option<(Instant, ProcessStat)>,
 
     pub region_bytes_read: LocalHistogram,
     pub region_keys_read: LocalHistogram,
@@ -115,6 +128,7 @@ impl Default for StoreStat {
             region_keys_written: REGION_WRITTEN_KEYS_HISTOGRAM.local(),
 
             last_report_ts: 0,
+            last_process_stat: None,
             engine_total_bytes_read: 0,
             engine_total_keys_read: 0,
             engine_last_total_bytes_read: 0,
@@ -139,13 +153,13 @@ impl Display for Task {
         match *self {
             Task::AskSplit {
                 ref region,
-                ref split_key,
+                ref split_keys,
                 ..
             } => write!(
                 f,
-                "ask split region {} with key {}",
+                "ask split region {} one by one with {}",
                 region.get_id(),
-                escape(split_key)
+                KeysInfoFormatter(&split_keys)
             ),
             Task::AskBatchSplit {
                 ref region,
@@ -171,6 +185,9 @@ impl Display for Task {
                 write!(f, "store heartbeat stats: {:?}", stats)
             }
             Task::ReportBatchSplit { ref regions } => write!(f, "report split {:?}", regions),
+            Task::ScatterRegion { ref region, .. } => {
+                write!(f, "scatter region {}", region.get_id())
+            }
             Task::ValidatePeer {
                 ref region,
                 ref peer,
@@ -195,7 +212,9 @@ pub struct Runner<T: PdClient> {
     db: Arc<DB>,
     region_peers: HashMap<u64, PeerStat>,
     store_stat: StoreStat,
+    hot_regions: HotRegionRecorder,
     is_hb_receiver_scheduled: bool,
+    feature_gate: FeatureGate,
 
     // use for Runner inner handle function to send Task to itself
     // actually it is the sender connected to Runner's Worker which
@@ -211,14 +230,17 @@ impl<T: PdClient> Runner<T> {
         db: Arc<DB>,
         scheduler: Scheduler<Task>,
     ) -> Runner<T> {
+        let feature_gate = pd_client.feature_gate();
         Runner {
             store_id,
             pd_client,
             ch,
             db,
             is_hb_receiver_scheduled: false,
+            feature_gate,
             region_peers: HashMap::default(),
             store_stat: StoreStat::default(),
+            hot_regions: HotRegionRecorder::new(HOT_REGION_TOP_K),
             scheduler,
         }
     }
@@ -227,15 +249,23 @@ impl<T: PdClient> Runner<T> {
         &self,
         handle: &Handle,
         mut region: metapb::Region,
-        split_key: Vec<u8>,
+        mut split_keys: Vec<Vec<u8>>,
         peer: metapb::Peer,
         right_derive: bool,
         callback: Callback,
     ) {
         let ch = self.ch.clone();
+        let scheduler = self.scheduler.clone();
         let f = self.pd_client.ask_split(region.clone()).then(move |resp| {
             match resp {
                 Ok(mut resp) => {
+                    // Splits off the part farthest from the derived region first, so that the
+                    // rest keys are still in the derived region.
+                    let split_key = if right_derive {
+                        split_keys.remove(0)
+                    } else {
+                        split_keys.pop().unwrap()
+                    };
                     info!(
                         "[region {}] try to split with new region id {} for region {:?}",
                         region.get_id(),
@@ -251,6 +281,18 @@ impl<T: PdClient> Runner<T> {
                     );
                     let region_id = region.get_id();
                     let epoch = region.take_region_epoch();
+                    let callback = if split_keys.is_empty() {
+                        callback
+                    } else {
+                        split_rest_callback(
+                            scheduler,
+                            region_id,
+                            split_keys,
+                            peer.clone(),
+                            right_derive,
+                            callback,
+                        )
+                    };
                     send_admin_request(&ch, region_id, epoch, peer, req, callback)
                 }
                 Err(e) => {
@@ -266,11 +308,20 @@ impl<T: PdClient> Runner<T> {
         &self,
         handle: &Handle,
         mut region: metapb::Region,
-        mut split_keys: Vec<Vec<u8>>,
+        split_keys: Vec<Vec<u8>>,
         peer: metapb::Peer,
         right_derive: bool,
         callback: Callback,
     ) {
+        if !self.feature_gate.can_enable(BATCH_SPLIT) {
+            // Some stores in the cluster can't apply the batch split admin command yet.
+            info!(
+                "[region {}] batch split is not enabled yet, use ask_split instead",
+                region.get_id()
+            );
+            return self.handle_ask_split(handle, region, split_keys, peer, right_derive, callback);
+        }
+
         let ch = self.ch.clone();
         let scheduler = self.scheduler.clone();
         let f = self
@@ -299,32 +350,18 @@ impl<T: PdClient> Runner<T> {
                     // In this situation, pd version check would refuse ask_batch_split.
                     // But if update time is long, it may cause large regions, so call ask_split instead.
                     Err(Error::Incompatible) => {
-                        let (region_id, peer_id) = (region.id, peer.id);
                         info!(
                             "[region {}] ask_batch_split is incompatible, use ask_split instead",
-                            region_id
+                            region.get_id()
                         );
-                        let task = Task::AskSplit {
+                        schedule_ask_split(
+                            &scheduler,
                             region,
-                            split_key: split_keys.pop().unwrap(),
+                            split_keys,
                             peer,
                             right_derive,
                             callback,
-                        };
-                        if let Err(Stopped(t)) = scheduler.schedule(task) {
-                            error!(
-                                "[region {}] {} failed to notify pd to split: Stopped",
-                                region_id, peer_id
-                            );
-                            match t {
-                                Task::AskSplit { callback, .. } => {
-                                    callback.invoke_with_response(new_error(box_err!(
-                                        "failed to split: Stopped"
-                                    )));
-                                }
-                                _ => unreachable!(),
-                            }
-                        }
+                        );
                     }
                     Err(e) => {
                         debug!(
@@ -433,6 +470,8 @@ impl<T: PdClient> Runner<T> {
         self.store_stat.region_keys_written.flush();
         self.store_stat.region_bytes_read.flush();
         self.store_stat.region_keys_read.flush();
+        self.report_hot_regions();
+        self.report_process_rates();
 
         STORE_SIZE_GAUGE_VEC
             .with_label_values(&["capacity"])
@@ -447,6 +486,49 @@ impl<T: PdClient> Runner<T> {
         handle.spawn(f);
     }
 
+    // The StoreStats of this kvproto has no fields for the CPU usage or the IO rates, so they
+    // are only exported as metrics.
+    fn report_process_rates(&mut self) {
+        let stat = match process_stat() {
+            Ok(stat) => stat,
+            Err(e) => {
+                debug!("failed to collect process stat: {:?}", e);
+                return;
+            }
+        };
+        let now = Instant::now();
+        if let Some((last_time, last)) = self.store_stat.last_process_stat {
+            let secs = duration_to_sec(now.duration_since(last_time));
+            if secs > 0.0 {
+                STORE_CPU_USAGE_GAUGE.set((stat.cpu_time - last.cpu_time) / secs);
+                let read = stat.read_bytes.saturating_sub(last.read_bytes);
+                STORE_IO_RATE_GAUGE_VEC
+                    .with_label_values(&["read"])
+                    .set(read as f64 / secs);
+                let write = stat.write_bytes.saturating_sub(last.write_bytes);
+                STORE_IO_RATE_GAUGE_VEC
+                    .with_label_values(&["write"])
+                    .set(write as f64 / secs);
+            }
+        }
+        self.store_stat.last_process_stat = Some((now, stat));
+    }
+
+    fn report_hot_regions(&mut self) {
+        self.hot_regions.evict_stale(Instant::now());
+        HOT_REGION_FLOW_GAUGE_VEC.reset();
+        let set_flow = |tp: &str, stats: Vec<HotRegionStat>| {
+            for stat in stats {
+                let region_id = stat.region_id.to_string();
+                HOT_REGION_FLOW_GAUGE_VEC
+                    .with_label_values(&[tp, region_id.as_str()])
+                    .set(stat.bytes_rate);
+            }
+        };
+        set_flow("read", self.hot_regions.top_read());
+        set_flow("write", self.hot_regions.top_write());
+    }
+
     fn handle_report_batch_split(&self, handle: &Handle, regions: Vec<metapb::Region>) {
         let f = self.pd_client.report_batch_split(regions).map_err(|e| {
             debug!("report split failed {:?}", e);
@@ -454,6 +536,17 @@ impl<T: PdClient> Runner<T> {
         handle.spawn(f);
     }
 
+    fn handle_scatter_region(&self, region: metapb::Region, leader: metapb::Peer) {
+        let region_id = region.get_id();
+        match self
+            .pd_client
+            .scatter_region(RegionInfo::new(region, Some(leader)))
+        {
+            Ok(()) => info!("[region {}] scatter region", region_id),
+            Err(e) => warn!("[region {}] failed to scatter region: {:?}", region_id, e),
+        }
+    }
+
     fn handle_validate_peer(
         &self,
         handle: &Handle,
@@ -630,6 +723,7 @@ impl<T: PdClient> Runner<T> {
             None => return,
             Some(_) => info!("[region {}] remove peer statistic record in pd", region_id),
         }
+        self.hot_regions.remove(region_id);
     }
 }
 
@@ -644,11 +738,11 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
         match task {
             Task::AskSplit {
                 region,
-                split_key,
+                split_keys,
                 peer,
                 right_derive,
                 callback,
-            } => self.handle_ask_split(handle, region, split_key, peer, right_derive, callback),
+            } => self.handle_ask_split(handle, region, split_keys, peer, right_derive, callback),
             Task::AskBatchSplit {
                 region,
                 split_keys,
@@ -700,6 +794,16 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
                     peer_stat.last_read_bytes = peer_stat.read_bytes;
                     peer_stat.last_read_keys = peer_stat.read_keys;
                     peer_stat.last_report_ts = time_now_sec();
+                    if last_report_ts > 0 {
+                        self.hot_regions.record(
+                            region.get_id(),
+                            peer_stat.last_report_ts.saturating_sub(last_report_ts),
+                            read_bytes_delta,
+                            read_keys_delta,
+                            written_bytes_delta,
+                            written_keys_delta,
+                        );
+                    }
                     (
                         read_bytes_delta,
                         read_keys_delta,
@@ -729,6 +833,7 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
                 self.handle_store_heartbeat(handle, stats, store_info)
             }
             Task::ReportBatchSplit { regions } => self.handle_report_batch_split(handle, regions),
+            Task::ScatterRegion { region, leader } => self.handle_scatter_region(region, leader),
             Task::ValidatePeer {
                 region,
                 peer,
@@ -824,6 +929,70 @@ fn send_admin_request(
     }
 }
 
+fn schedule_ask_split(
+    scheduler: &Scheduler<Task>,
+    region: metapb::Region,
+    split_keys: Vec<Vec<u8>>,
+    peer: metapb::Peer,
+    right_derive: bool,
+    callback: Callback,
+) {
+    let (region_id, peer_id) = (region.get_id(), peer.get_id());
+    let task = Task::AskSplit {
+        region,
+        split_keys,
+        peer,
+        right_derive,
+        callback,
+    };
+    if let Err(Stopped(t)) = scheduler.schedule(task) {
+        error!(
+            "[region {}] {} failed to notify pd to split: Stopped",
+            region_id, peer_id
+        );
+        match t {
+            Task::AskSplit { callback, .. } => {
+                callback.invoke_with_response(new_error(box_err!("failed to split: Stopped")));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Returns the callback of a split, which asks for the split at the rest keys once the split
+// is applied. The response of the last split is passed to `callback`.
+fn split_rest_callback(
+    scheduler: Scheduler<Task>,
+    region_id: u64,
+    split_keys: Vec<Vec<u8>>,
+    peer: metapb::Peer,
+    right_derive: bool,
+    callback: Callback,
+) -> Callback {
+    Callback::Write(box move |resp: WriteResponse| {
+        let mut resp = resp.response;
+        if resp.get_header().has_error() {
+            callback.invoke_with_response(resp);
+            return;
+        }
+        let derived = resp
+            .mut_admin_response()
+            .mut_splits()
+            .take_regions()
+            .into_iter()
+            .find(|r| r.get_id() == region_id);
+        match derived {
+            Some(region) => {
+                schedule_ask_split(&scheduler, region, split_keys, peer, right_derive, callback)
+            }
+            None => callback.invoke_with_response(new_error(box_err!(
+                "[region {}] derived region is missing in split response",
+                region_id
+            ))),
+        }
+    })
+}
+
 // send merge fail to gc merge source.
 fn send_merge_fail(ch: SendCh<Msg>, source: u64) {
     if let Err(e) = ch.send(Msg::MergeFail { region_id: source }) {
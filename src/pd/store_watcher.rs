@@ -0,0 +1,240 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use kvproto::metapb;
+
+use util::collections::HashMap;
+
+use super::{PdClient, Result};
+
+/// Provides the stores of the cluster, which are usually maintained by PD.
+pub trait StoreProvider: Send + 'static {
+    fn get_all_stores(&self) -> Result<Vec<metapb::Store>>;
+}
+
+impl<T: PdClient + 'static> StoreProvider for Arc<T> {
+    fn get_all_stores(&self) -> Result<Vec<metapb::Store>> {
+        PdClient::get_all_stores(self.as_ref())
+    }
+}
+
+/// A change of the stores in the cluster.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreEvent {
+    Added(metapb::Store),
+    Removed(metapb::Store),
+    /// The address, labels or state of the store is changed.
+    Changed {
+        old: metapb::Store,
+        new: metapb::Store,
+    },
+}
+
+#[derive(Default)]
+struct Topology {
+    stores: HashMap<u64, metapb::Store>,
+    subscribers: Vec<Sender<StoreEvent>>,
+}
+
+impl Topology {
+    fn update(&mut self, stores: Vec<metapb::Store>) {
+        let stores: HashMap<u64, metapb::Store> =
+            stores.into_iter().map(|s| (s.get_id(), s)).collect();
+        let mut events = vec![];
+        for (id, old) in &self.stores {
+            match stores.get(id) {
+                Some(new) if new != old => events.push(StoreEvent::Changed {
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                Some(_) => {}
+                None => events.push(StoreEvent::Removed(old.clone())),
+            }
+        }
+        for (id, new) in &stores {
+            if !self.stores.contains_key(id) {
+                events.push(StoreEvent::Added(new.clone()));
+            }
+        }
+        self.stores = stores;
+
+        if events.is_empty() {
+            return;
+        }
+        // Subscribers whose receivers are dropped are removed.
+        self.subscribers
+            .retain(|tx| events.iter().all(|e| tx.send(e.clone()).is_ok()));
+    }
+
+    fn subscribe(&mut self) -> Receiver<StoreEvent> {
+        let (tx, rx) = mpsc::channel();
+        // A new subscriber learns the known stores first.
+        for store in self.stores.values() {
+            tx.send(StoreEvent::Added(store.clone())).unwrap();
+        }
+        self.subscribers.push(tx);
+        rx
+    }
+}
+
+/// `StoreWatcher` polls the stores of the cluster from PD, and notifies the subscribers of
+/// the stores that are added, removed or changed. It lets the components embedded in TiKV
+/// follow the cluster topology without polling PD themselves.
+#[derive(Clone, Default)]
+pub struct StoreWatcher {
+    topology: Arc<Mutex<Topology>>,
+}
+
+impl StoreWatcher {
+    pub fn new() -> StoreWatcher {
+        StoreWatcher::default()
+    }
+
+    /// Returns a receiver of the store events. The stores that are already known are sent
+    /// to it as `StoreEvent::Added` first.
+    pub fn subscribe(&self) -> Receiver<StoreEvent> {
+        self.topology.lock().unwrap().subscribe()
+    }
+
+    /// Returns the stores learned from the last poll.
+    pub fn get_stores(&self) -> Vec<metapb::Store> {
+        self.topology.lock().unwrap().stores.values().cloned().collect()
+    }
+
+    /// Polls the stores by `provider` every `interval` in a new thread. The returned handle
+    /// is used to stop it.
+    pub fn start<P: StoreProvider>(
+        &self,
+        provider: P,
+        interval: Duration,
+    ) -> Result<StoreWatcherHandle> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let topology = Arc::clone(&self.topology);
+        let join_handle = thread::Builder::new()
+            .name(thd_name!("store-watcher"))
+            .spawn(move || loop {
+                match provider.get_all_stores() {
+                    Ok(stores) => topology.lock().unwrap().update(stores),
+                    Err(e) => warn!("store watcher failed to get stores: {:?}", e),
+                }
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+            })?;
+        Ok(StoreWatcherHandle {
+            join_handle,
+            stop_tx,
+        })
+    }
+}
+
+/// Used to stop a started `StoreWatcher`.
+pub struct StoreWatcherHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl StoreWatcherHandle {
+    pub fn stop(self) {
+        let _ = self.stop_tx.send(());
+        if let Err(e) = self.join_handle.join() {
+            error!("failed to join store watcher thread: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockStoreProvider(Arc<Mutex<Vec<metapb::Store>>>);
+
+    impl StoreProvider for MockStoreProvider {
+        fn get_all_stores(&self) -> Result<Vec<metapb::Store>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    fn new_store(id: u64, zone: &str) -> metapb::Store {
+        let mut store = metapb::Store::new();
+        store.set_id(id);
+        let mut label = metapb::StoreLabel::new();
+        label.set_key("zone".to_owned());
+        label.set_value(zone.to_owned());
+        store.mut_labels().push(label);
+        store
+    }
+
+    #[test]
+    fn test_store_watcher() {
+        let stores = Arc::new(Mutex::new(vec![new_store(1, "z1")]));
+        let watcher = StoreWatcher::new();
+        let rx1 = watcher.subscribe();
+        let handle = watcher
+            .start(
+                MockStoreProvider(Arc::clone(&stores)),
+                Duration::from_millis(10),
+            )
+            .unwrap();
+        let timeout = Duration::from_secs(3);
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            StoreEvent::Added(new_store(1, "z1"))
+        );
+
+        // A late subscriber gets the known stores.
+        let rx2 = watcher.subscribe();
+        assert_eq!(
+            rx2.recv_timeout(timeout).unwrap(),
+            StoreEvent::Added(new_store(1, "z1"))
+        );
+        drop(rx2);
+
+        *stores.lock().unwrap() = vec![new_store(1, "z2"), new_store(2, "z1")];
+        let mut events = vec![
+            rx1.recv_timeout(timeout).unwrap(),
+            rx1.recv_timeout(timeout).unwrap(),
+        ];
+        events.sort_by_key(|e| match *e {
+            StoreEvent::Added(ref s) | StoreEvent::Removed(ref s) => s.get_id(),
+            StoreEvent::Changed { ref new, .. } => new.get_id(),
+        });
+        assert_eq!(
+            events,
+            vec![
+                StoreEvent::Changed {
+                    old: new_store(1, "z1"),
+                    new: new_store(1, "z2"),
+                },
+                StoreEvent::Added(new_store(2, "z1")),
+            ]
+        );
+
+        *stores.lock().unwrap() = vec![new_store(2, "z1")];
+        assert_eq!(
+            rx1.recv_timeout(timeout).unwrap(),
+            StoreEvent::Removed(new_store(1, "z2"))
+        );
+        assert_eq!(watcher.get_stores(), vec![new_store(2, "z1")]);
+
+        handle.stop();
+        // The dropped subscriber has been removed.
+        assert_eq!(watcher.topology.lock().unwrap().subscribers.len(), 1);
+    }
+}
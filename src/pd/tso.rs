@@ -0,0 +1,210 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coalesces the concurrent requests for timestamps into single TSO requests to PD.
+//!
+//! While a TSO request is in flight, the following requests wait in a queue. Once it
+//! returns, all of them are served by the next request, which asks PD for as many
+//! timestamps as the waiters. PD returns the largest one of the range, from which the
+//! others are derived.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::sync::oneshot;
+use futures::Future;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use kvproto::pdpb::Timestamp;
+
+use super::metrics::PD_TSO_BATCH_SIZE_HISTOGRAM;
+use super::{PdFuture, Result};
+
+pub const PHYSICAL_SHIFT_BITS: u64 = 18;
+
+// The maximal number of timestamps asked for by a single TSO request.
+const MAX_BATCH_SIZE: usize = 10240;
+
+/// Composes the physical and the logical part into a timestamp.
+pub fn compose_ts(physical: i64, logical: i64) -> u64 {
+    ((physical << PHYSICAL_SHIFT_BITS) + logical) as u64
+}
+
+/// Asks PD for the given number of timestamps, and returns the largest one.
+pub type TsoFetcher = Box<Fn(u32) -> PdFuture<Timestamp> + Send + Sync>;
+
+type Waiter = oneshot::Sender<Result<u64>>;
+
+struct Pending {
+    waiters: Vec<Waiter>,
+    in_flight: bool,
+}
+
+struct Inner {
+    fetcher: TsoFetcher,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Clone)]
+pub struct TsoBatcher {
+    inner: Arc<Inner>,
+    // Drives the TSO requests, and hands out the timestamps they return.
+    pool: CpuPool,
+}
+
+impl TsoBatcher {
+    pub fn new(fetcher: TsoFetcher) -> TsoBatcher {
+        TsoBatcher {
+            inner: Arc::new(Inner {
+                fetcher,
+                pending: Mutex::new(Pending {
+                    waiters: vec![],
+                    in_flight: false,
+                }),
+            }),
+            pool: CpuPoolBuilder::new()
+                .name_prefix(thd_name!("pd-tso"))
+                .pool_size(1)
+                .create(),
+        }
+    }
+
+    /// Gets a timestamp, which is larger than all the timestamps got before it is called.
+    pub fn get_ts(&self) -> PdFuture<u64> {
+        let (tx, rx) = oneshot::channel();
+        let dispatch = {
+            let mut pending = self.inner.pending.lock().unwrap();
+            pending.waiters.push(tx);
+            !mem::replace(&mut pending.in_flight, true)
+        };
+        if dispatch {
+            self.dispatch();
+        }
+        Box::new(
+            rx.map_err(|_| box_err!("the TSO request is canceled"))
+                .and_then(|res| res),
+        )
+    }
+
+    fn dispatch(&self) {
+        let waiters = {
+            let mut pending = self.inner.pending.lock().unwrap();
+            if pending.waiters.is_empty() {
+                pending.in_flight = false;
+                return;
+            }
+            let len = pending.waiters.len();
+            if len > MAX_BATCH_SIZE {
+                pending.waiters.drain(..MAX_BATCH_SIZE).collect()
+            } else {
+                mem::replace(&mut pending.waiters, vec![])
+            }
+        };
+        let count = waiters.len();
+        PD_TSO_BATCH_SIZE_HISTOGRAM.observe(count as f64);
+
+        let batcher = self.clone();
+        let f = (self.inner.fetcher)(count as u32).then(move |res| {
+            match res {
+                Ok(ts) => {
+                    // The returned timestamp is the largest one of the range.
+                    let first = ts.get_logical() - count as i64 + 1;
+                    for (i, w) in waiters.into_iter().enumerate() {
+                        let _ = w.send(Ok(compose_ts(ts.get_physical(), first + i as i64)));
+                    }
+                }
+                Err(e) => {
+                    error!("failed to get {} timestamps: {:?}", count, e);
+                    for w in waiters {
+                        let _ = w.send(Err(box_err!("failed to get timestamp: {:?}", e)));
+                    }
+                }
+            }
+            // Serves the requests which come during this one.
+            batcher.dispatch();
+            Ok(())
+        });
+        self.pool.spawn(f).forget();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    type Requests = Arc<Mutex<Vec<(u32, oneshot::Sender<Timestamp>)>>>;
+
+    fn new_batcher() -> (TsoBatcher, Requests) {
+        let requests: Requests = Arc::default();
+        let reqs = Arc::clone(&requests);
+        let batcher = TsoBatcher::new(Box::new(move |count| {
+            let (tx, rx) = oneshot::channel();
+            reqs.lock().unwrap().push((count, tx));
+            Box::new(rx.map_err(|_| box_err!("canceled"))) as PdFuture<_>
+        }));
+        (batcher, requests)
+    }
+
+    fn wait_request(requests: &Requests) -> (u32, oneshot::Sender<Timestamp>) {
+        for _ in 0..100 {
+            if let Some(r) = requests.lock().unwrap().pop() {
+                return r;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no TSO request is sent");
+    }
+
+    fn new_ts(physical: i64, logical: i64) -> Timestamp {
+        let mut ts = Timestamp::new();
+        ts.set_physical(physical);
+        ts.set_logical(logical);
+        ts
+    }
+
+    #[test]
+    fn test_tso_batcher() {
+        let (batcher, requests) = new_batcher();
+
+        let first = batcher.get_ts();
+        let (count, tx) = wait_request(&requests);
+        assert_eq!(count, 1);
+
+        // The requests during the first one are batched.
+        let others: Vec<_> = (0..3).map(|_| batcher.get_ts()).collect();
+        assert!(requests.lock().unwrap().is_empty());
+
+        tx.send(new_ts(10, 5)).unwrap();
+        assert_eq!(first.wait().unwrap(), compose_ts(10, 5));
+
+        let (count, tx) = wait_request(&requests);
+        assert_eq!(count, 3);
+        tx.send(new_ts(10, 8)).unwrap();
+        let ts: Vec<_> = others.into_iter().map(|f| f.wait().unwrap()).collect();
+        assert_eq!(
+            ts,
+            vec![compose_ts(10, 6), compose_ts(10, 7), compose_ts(10, 8)]
+        );
+
+        // Failures are returned to all the waiters.
+        let f = batcher.get_ts();
+        let (count, tx) = wait_request(&requests);
+        assert_eq!(count, 1);
+        drop(tx);
+        f.wait().unwrap_err();
+
+        assert_eq!(compose_ts(1, 1), (1 << PHYSICAL_SHIFT_BITS) + 1);
+    }
+}
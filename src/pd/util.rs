@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::result;
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -22,9 +23,10 @@ use futures::future::{loop_fn, ok, Loop};
 use futures::sync::mpsc::UnboundedSender;
 use futures::task::Task;
 use futures::{task, Async, Future, Poll, Stream};
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
 use grpc::{
     CallOption, ChannelBuilder, ClientDuplexReceiver, ClientDuplexSender, Environment,
-    Result as GrpcResult,
+    Error as GrpcError, Result as GrpcResult, WriteFlags,
 };
 use kvproto::pdpb::{
     ErrorType, GetMembersRequest, GetMembersResponse, Member, RegionHeartbeatRequest,
@@ -33,6 +35,7 @@ use kvproto::pdpb::{
 use kvproto::pdpb_grpc::PdClient;
 use tokio_timer::timer::Handle;
 
+use super::metrics::{PD_HEARTBEAT_COUNTER_VEC, PD_RECONNECT_COUNTER_VEC};
 use super::{Config, Error, PdFuture, Result, REQUEST_TIMEOUT};
 use util::security::SecurityManager;
 use util::timer::GLOBAL_TIMER_HANDLE;
@@ -91,10 +94,63 @@ impl Stream for HeartbeatReceiver {
     }
 }
 
+/// Pairs the items of a stream with write flags. An item is sent with the buffer hint if the
+/// next one is ready already, so that the ready items are flushed to the stream together.
+pub struct BatchHintStream<S: Stream> {
+    inner: S,
+    next: Option<S::Item>,
+    finished: bool,
+}
+
+impl<S: Stream> BatchHintStream<S> {
+    pub fn new(inner: S) -> BatchHintStream<S> {
+        BatchHintStream {
+            inner,
+            next: None,
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream> Stream for BatchHintStream<S> {
+    type Item = (S::Item, WriteFlags);
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, S::Error> {
+        let item = match self.next.take() {
+            Some(item) => item,
+            None if self.finished => return Ok(Async::Ready(None)),
+            None => match try_ready!(self.inner.poll()) {
+                Some(item) => item,
+                None => return Ok(Async::Ready(None)),
+            },
+        };
+        if !self.finished {
+            match self.inner.poll()? {
+                Async::Ready(Some(next)) => {
+                    self.next = Some(next);
+                    PD_HEARTBEAT_COUNTER_VEC.with_label_values(&["batch"]).inc();
+                    return Ok(Async::Ready(Some((
+                        item,
+                        WriteFlags::default().buffer_hint(true),
+                    ))));
+                }
+                Async::Ready(None) => self.finished = true,
+                Async::NotReady => {}
+            }
+        }
+        Ok(Async::Ready(Some((item, WriteFlags::default()))))
+    }
+}
+
 /// A leader client doing requests asynchronous.
+#[derive(Clone)]
 pub struct LeaderClient {
     timer: Handle,
     inner: Arc<RwLock<Inner>>,
+    // Reconnecting blocks on the members of PD, so it runs here instead of the
+    // threads polling the requests.
+    reconnect_pool: CpuPool,
 }
 
 impl LeaderClient {
@@ -107,6 +163,10 @@ impl LeaderClient {
         let (tx, rx) = client.region_heartbeat().unwrap();
         LeaderClient {
             timer: GLOBAL_TIMER_HANDLE.clone(),
+            reconnect_pool: CpuPoolBuilder::new()
+                .name_prefix(thd_name!("pd-reconnect"))
+                .pool_size(1)
+                .create(),
             inner: Arc::new(RwLock::new(Inner {
                 env,
                 hb_sender: Either::Left(Some(tx)),
@@ -150,10 +210,7 @@ impl LeaderClient {
         Request {
             reconnect_count: retry,
             request_sent: 0,
-            client: LeaderClient {
-                timer: self.timer.clone(),
-                inner: Arc::clone(&self.inner),
-            },
+            client: self.clone(),
             req,
             resp: None,
             func,
@@ -164,20 +221,32 @@ impl LeaderClient {
         self.inner.rl().members.get_leader().clone()
     }
 
+    // Re-establish connection with PD leader in asynchronous fashion. The members of
+    // PD are fetched again, so a new leader is found after the leader changes.
+    pub fn reconnect_async(&self) -> PdFuture<()> {
+        let client = self.clone();
+        Box::new(self.reconnect_pool.spawn_fn(move || client.reconnect()))
+    }
+
     // Re-establish connection with PD leader in synchronized fashion.
     pub fn reconnect(&self) -> Result<()> {
         let ((client, members), start) = {
             let inner = self.inner.rl();
             if inner.last_update.elapsed() < Duration::from_secs(RECONNECT_INTERVAL_SEC) {
                 // Avoid unnecessary updating.
+                PD_RECONNECT_COUNTER_VEC.with_label_values(&["skip"]).inc();
                 return Ok(());
             }
 
             let start = Instant::now();
-            (
-                try_connect_leader(Arc::clone(&inner.env), &inner.security_mgr, &inner.members)?,
-                start,
-            )
+            match try_connect_leader(Arc::clone(&inner.env), &inner.security_mgr, &inner.members)
+            {
+                Ok(r) => (r, start),
+                Err(e) => {
+                    PD_RECONNECT_COUNTER_VEC.with_label_values(&["failure"]).inc();
+                    return Err(e);
+                }
+            }
         };
 
         {
@@ -202,12 +271,37 @@ impl LeaderClient {
                 on_reconnect();
             }
         }
+        PD_RECONNECT_COUNTER_VEC.with_label_values(&["success"]).inc();
         warn!("updating PD client done, spent {:?}", start.elapsed());
         Ok(())
     }
 }
 
 pub const RECONNECT_INTERVAL_SEC: u64 = 1; // 1s
+// The interval between retries of a request grows from the base to the max.
+const RETRY_BACKOFF_BASE_MILLIS: u64 = 100;
+const RETRY_BACKOFF_MAX_MILLIS: u64 = 1000;
+
+// PD followers reply requests with this error.
+const NOT_LEADER_MESSAGE: &str = "not leader";
+
+/// Returns whether the request is sent to a PD member which isn't the leader.
+pub fn is_not_leader(err: &Error) -> bool {
+    match *err {
+        Error::Grpc(GrpcError::RpcFailure(ref status)) => status
+            .details
+            .as_ref()
+            .map_or(false, |d| d.contains(NOT_LEADER_MESSAGE)),
+        Error::Other(ref e) => e.to_string().contains(NOT_LEADER_MESSAGE),
+        _ => false,
+    }
+}
+
+fn retry_backoff(retried: usize) -> Duration {
+    let shift = cmp::min(retried, 16) as u32;
+    let millis = RETRY_BACKOFF_BASE_MILLIS.saturating_mul(1u64 << shift);
+    Duration::from_millis(cmp::min(millis, RETRY_BACKOFF_MAX_MILLIS))
+}
 
 /// The context of sending requets.
 pub struct Request<Req, Resp, F> {
@@ -233,26 +327,38 @@ where
         debug!("reconnect remains: {}", self.reconnect_count);
 
         if self.request_sent < MAX_REQUEST_COUNT {
-            return Box::new(ok(self));
+            if self.request_sent == 0 {
+                return Box::new(ok(self));
+            }
+            // Back off before retrying the failed request.
+            let backoff = retry_backoff(self.request_sent - 1);
+            return Box::new(
+                self.client
+                    .timer
+                    .delay(Instant::now() + backoff)
+                    .then(|_| Ok(self)),
+            );
         }
 
         // Updating client.
         self.reconnect_count -= 1;
 
-        // FIXME: should not block the core.
-        warn!("updating PD client, block the tokio core");
-        match self.client.reconnect() {
+        warn!("updating PD client");
+        Box::new(self.client.reconnect_async().then(move |res| match res {
             Ok(_) => {
                 self.request_sent = 0;
-                Box::new(ok(self))
+                Box::new(ok(self)) as Box<Future<Item = Self, Error = Self> + Send>
             }
-            Err(_) => Box::new(
-                self.client
-                    .timer
-                    .delay(Instant::now() + Duration::from_secs(RECONNECT_INTERVAL_SEC))
-                    .then(|_| Err(self)),
-            ),
-        }
+            Err(e) => {
+                error!("failed to reconnect PD: {:?}", e);
+                Box::new(
+                    self.client
+                        .timer
+                        .delay(Instant::now() + Duration::from_secs(RECONNECT_INTERVAL_SEC))
+                        .then(|_| Err(self)),
+                ) as Box<Future<Item = Self, Error = Self> + Send>
+            }
+        }))
     }
 
     fn send_and_receive(mut self) -> Box<Future<Item = Self, Error = Self> + Send> {
@@ -268,6 +374,10 @@ where
                     Ok(ctx)
                 }
                 Err(err) => {
+                    if is_not_leader(&err) {
+                        // Reconnect at once to find the new leader instead of retrying.
+                        ctx.request_sent = MAX_REQUEST_COUNT;
+                    }
                     ctx.resp = Some(Err(err));
                     Err(ctx)
                 }
@@ -484,3 +594,56 @@ pub fn check_resp_header(header: &ResponseHeader) -> Result<()> {
         _ => Err(box_err!(err.get_message())),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use futures::sync::mpsc;
+    use grpc::{RpcStatus, RpcStatusCode};
+
+    use super::*;
+
+    #[test]
+    fn test_is_not_leader() {
+        let status = RpcStatus::new(RpcStatusCode::Unknown, Some("\"not leader\"".to_owned()));
+        assert!(is_not_leader(&Error::Grpc(GrpcError::RpcFailure(status))));
+        let status = RpcStatus::new(RpcStatusCode::Unavailable, None);
+        assert!(!is_not_leader(&Error::Grpc(GrpcError::RpcFailure(status))));
+        let err: Error = box_err!("not leader");
+        assert!(is_not_leader(&err));
+        assert!(!is_not_leader(&Error::Incompatible));
+    }
+
+    #[test]
+    fn test_batch_hint_stream() {
+        let s = BatchHintStream::new(stream::iter_ok::<_, ()>(vec![1, 2, 3]));
+        let items: Vec<_> = s
+            .map(|(i, flags)| (i, flags.get_buffer_hint()))
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(items, vec![(1, true), (2, true), (3, false)]);
+
+        let (tx, rx) = mpsc::unbounded();
+        tx.unbounded_send(1).unwrap();
+        let mut s = BatchHintStream::new(rx).wait();
+        let (i, flags) = s.next().unwrap().unwrap();
+        assert_eq!((i, flags.get_buffer_hint()), (1, false));
+        tx.unbounded_send(2).unwrap();
+        tx.unbounded_send(3).unwrap();
+        drop(tx);
+        let items: Vec<_> = s
+            .map(|r| r.map(|(i, flags)| (i, flags.get_buffer_hint())).unwrap())
+            .collect();
+        assert_eq!(items, vec![(2, true), (3, false)]);
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(0), Duration::from_millis(100));
+        assert_eq!(retry_backoff(1), Duration::from_millis(200));
+        assert_eq!(retry_backoff(3), Duration::from_millis(800));
+        assert_eq!(retry_backoff(4), Duration::from_millis(1000));
+        assert_eq!(retry_backoff(100), Duration::from_millis(1000));
+    }
+}
@@ -11,6 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use hex;
+
 use super::Result;
 use util::config::ReadableSize;
 
@@ -37,6 +39,12 @@ pub struct Config {
     /// And the number of keys in [a,b), [b,c), [c,d) will be region_split_keys.
     pub region_max_keys: u64,
     pub region_split_keys: u64,
+
+    /// Hex encoded key prefixes whose keys are written in an increasing order,
+    /// like the keys of logs or time series. A region holding such keys is split
+    /// at its last key as well, so that the new writes go to a small region which
+    /// is scattered by PD cheaply.
+    pub monotonic_key_prefixes: Vec<String>,
 }
 
 /// Default region split size.
@@ -56,6 +64,7 @@ impl Default for Config {
             region_max_size: split_size / 2 * 3,
             region_split_keys: SPLIT_KEYS,
             region_max_keys: SPLIT_KEYS / 2 * 3,
+            monotonic_key_prefixes: vec![],
         }
     }
 }
@@ -76,8 +85,21 @@ impl Config {
                 self.region_split_keys
             ));
         }
+        self.decode_monotonic_key_prefixes()?;
         Ok(())
     }
+
+    pub fn decode_monotonic_key_prefixes(&self) -> Result<Vec<Vec<u8>>> {
+        let mut prefixes = Vec::with_capacity(self.monotonic_key_prefixes.len());
+        for prefix in &self.monotonic_key_prefixes {
+            let p = box_try!(hex::decode(prefix));
+            if p.is_empty() {
+                return Err(box_err!("monotonic key prefix must not be empty"));
+            }
+            prefixes.push(p);
+        }
+        Ok(prefixes)
+    }
 }
 
 #[cfg(test)]
@@ -98,5 +120,16 @@ mod tests {
         cfg.region_max_keys = 10;
         cfg.region_split_keys = 20;
         assert!(cfg.validate().is_err());
+
+        cfg = Config::default();
+        cfg.monotonic_key_prefixes = vec!["74800000".to_owned()];
+        assert_eq!(
+            cfg.decode_monotonic_key_prefixes().unwrap(),
+            vec![b"t\x80\x00\x00".to_vec()]
+        );
+        cfg.monotonic_key_prefixes.push("7g".to_owned());
+        assert!(cfg.validate().is_err());
+        cfg.monotonic_key_prefixes = vec!["".to_owned()];
+        assert!(cfg.validate().is_err());
     }
 }
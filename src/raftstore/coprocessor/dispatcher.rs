@@ -122,6 +122,7 @@ macro_rules! loop_ob {
 #[derive(Default)]
 pub struct CoprocessorHost {
     pub registry: Registry,
+    monotonic_keys: MonotonicKeyCheckObserver,
 }
 
 impl CoprocessorHost {
@@ -154,7 +155,20 @@ impl CoprocessorHost {
         if cfg.split_region_on_table {
             registry.register_split_check_observer(400, Box::new(TableCheckObserver::default()));
         }
-        CoprocessorHost { registry }
+        // The prefixes have been checked in `Config::validate`.
+        let monotonic_keys =
+            MonotonicKeyCheckObserver::new(cfg.decode_monotonic_key_prefixes().unwrap());
+        registry.register_split_check_observer(500, Box::new(monotonic_keys.clone()));
+        CoprocessorHost {
+            registry,
+            monotonic_keys,
+        }
+    }
+
+    /// Checks whether the region is likely to receive the sequential writes of
+    /// monotonically increasing keys, so it's worth scattering.
+    pub fn is_monotonic_tail(&self, region: &Region) -> bool {
+        self.monotonic_keys.is_monotonic(region.get_start_key())
     }
 
     /// Call all prepose hooks until bypass is set to true.
@@ -29,8 +29,8 @@ pub use self::config::Config;
 pub use self::dispatcher::{CoprocessorHost, Registry};
 pub use self::error::{Error, Result};
 pub use self::split_check::{
-    HalfCheckObserver, Host as SplitCheckerHost, KeysCheckObserver, SizeCheckObserver,
-    TableCheckObserver,
+    HalfCheckObserver, Host as SplitCheckerHost, KeysCheckObserver, MonotonicKeyCheckObserver,
+    SizeCheckObserver, TableCheckObserver,
 };
 
 pub use raftstore::store::KeyEntry;
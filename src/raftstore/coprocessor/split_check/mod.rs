@@ -13,18 +13,21 @@
 
 mod half;
 mod keys;
+mod monotonic;
 mod size;
 mod table;
 
 use rocksdb::DB;
 
 use super::error::Result;
+use raftstore::store::keys as data_keys;
 use super::{KeyEntry, ObserverContext, SplitChecker};
 use kvproto::metapb::Region;
 use kvproto::pdpb::CheckPolicy;
 
 pub use self::half::HalfCheckObserver;
 pub use self::keys::KeysCheckObserver;
+pub use self::monotonic::MonotonicKeyCheckObserver;
 pub use self::size::SizeCheckObserver;
 pub use self::table::TableCheckObserver;
 
@@ -32,6 +35,8 @@ pub use self::table::TableCheckObserver;
 pub struct Host {
     checkers: Vec<Box<SplitChecker>>,
     auto_split: bool,
+    // An extra split key appended after the split keys found by the checkers.
+    tail_split_key: Option<Vec<u8>>,
 }
 
 impl Host {
@@ -39,6 +44,7 @@ impl Host {
         Host {
             auto_split,
             checkers: vec![],
+            tail_split_key: None,
         }
     }
 
@@ -75,20 +81,23 @@ impl Host {
     }
 
     pub fn split_keys(self) -> Vec<Vec<u8>> {
+        let tail_split_key = self.tail_split_key;
         for mut checker in self.checkers {
             let keys = checker.split_keys();
             if !keys.is_empty() {
-                return keys;
+                return append_tail_split_key(keys, tail_split_key);
             }
         }
         vec![]
     }
 
+    /// Approximate split keys are data keys, unlike the ones returned by `split_keys`.
     pub fn approximate_split_keys(mut self, region: &Region, engine: &DB) -> Result<Vec<Vec<u8>>> {
+        let tail_split_key = self.tail_split_key.take().map(|k| data_keys::data_key(&k));
         for checker in &mut self.checkers {
             let keys = box_try!(checker.approximate_split_keys(region, engine));
             if !keys.is_empty() {
-                return Ok(keys);
+                return Ok(append_tail_split_key(keys, tail_split_key));
             }
         }
         Ok(vec![])
@@ -98,4 +107,20 @@ impl Host {
     pub fn add_checker(&mut self, checker: Box<SplitChecker>) {
         self.checkers.push(checker);
     }
+
+    /// Sets a key to split the region at, in addition to the split keys found by the
+    /// checkers. It takes effect only when the checkers decide to split the region.
+    #[inline]
+    pub fn set_tail_split_key(&mut self, key: Vec<u8>) {
+        self.tail_split_key = Some(key);
+    }
+}
+
+fn append_tail_split_key(mut keys: Vec<Vec<u8>>, tail: Option<Vec<u8>>) -> Vec<Vec<u8>> {
+    if let Some(tail) = tail {
+        if keys.last().map_or(false, |k| *k < tail) {
+            keys.push(tail);
+        }
+    }
+    keys
 }
@@ -0,0 +1,172 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use kvproto::pdpb::CheckPolicy;
+use rocksdb::DB;
+
+use raftstore::store::keys;
+use storage::types::Key;
+
+use super::super::{Coprocessor, ObserverContext, SplitCheckObserver};
+use super::table::last_key_of_region;
+use super::Host;
+
+/// `MonotonicKeyCheckObserver` biases the split of regions holding keys that are
+/// written in an increasing order, like logs or time series.
+///
+/// All the writes of such keys go to the last region of the range. Splitting it in
+/// the middle leaves the hot part on the same store, and the part on the left is never
+/// written again. So the region is also split at its last key, which moves the write
+/// frontier to a nearly empty region that PD can scatter cheaply.
+#[derive(Clone, Default)]
+pub struct MonotonicKeyCheckObserver {
+    prefixes: Arc<Vec<Vec<u8>>>,
+}
+
+impl MonotonicKeyCheckObserver {
+    pub fn new(prefixes: Vec<Vec<u8>>) -> MonotonicKeyCheckObserver {
+        MonotonicKeyCheckObserver {
+            prefixes: Arc::new(prefixes),
+        }
+    }
+
+    /// Checks whether the encoded key has a monotonic key prefix.
+    pub fn is_monotonic(&self, key: &[u8]) -> bool {
+        self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+}
+
+impl Coprocessor for MonotonicKeyCheckObserver {}
+
+impl SplitCheckObserver for MonotonicKeyCheckObserver {
+    fn add_checker(
+        &self,
+        ctx: &mut ObserverContext,
+        host: &mut Host,
+        engine: &DB,
+        _: CheckPolicy,
+    ) {
+        // The split key of a manual split is respected.
+        if self.prefixes.is_empty() || !host.auto_split() {
+            return;
+        }
+        let region = ctx.region();
+        let last_key = match last_key_of_region(engine, region) {
+            Ok(Some(last_key)) => last_key,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    "[region {}] failed to get region last key: {}",
+                    region.get_id(),
+                    e
+                );
+                return;
+            }
+        };
+        // Keys in the write cf carry a timestamp, split before all versions of the key.
+        let last_key = match Key::truncate_ts_for(keys::origin_key(&last_key)) {
+            Ok(k) => k.to_vec(),
+            Err(_) => return,
+        };
+        if self.is_monotonic(&last_key) && last_key.as_slice() > region.get_start_key() {
+            host.set_tail_split_key(last_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kvproto::metapb::{Peer, Region};
+    use kvproto::pdpb::CheckPolicy;
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use raftstore::coprocessor::SplitChecker;
+    use raftstore::store::keys;
+    use storage::types::Key;
+    use storage::{ALL_CFS, CF_WRITE};
+    use util::rocksdb::new_engine;
+
+    use super::*;
+
+    struct FixedChecker(Vec<Vec<u8>>);
+
+    impl SplitChecker for FixedChecker {
+        fn split_keys(&mut self) -> Vec<Vec<u8>> {
+            self.0.clone()
+        }
+
+        fn policy(&self) -> CheckPolicy {
+            CheckPolicy::SCAN
+        }
+    }
+
+    fn check(
+        observer: &MonotonicKeyCheckObserver,
+        engine: &DB,
+        region: &Region,
+        auto_split: bool,
+        keys: Vec<Vec<u8>>,
+    ) -> Vec<Vec<u8>> {
+        let mut host = Host::new(auto_split);
+        let mut ctx = ObserverContext::new(region);
+        observer.add_checker(&mut ctx, &mut host, engine, CheckPolicy::SCAN);
+        host.add_checker(Box::new(FixedChecker(keys)));
+        host.split_keys()
+    }
+
+    #[test]
+    fn test_monotonic_key_check_observer() {
+        let path = TempDir::new("test_monotonic_key_check_observer").unwrap();
+        let engine = Arc::new(new_engine(path.path().to_str().unwrap(), ALL_CFS, None).unwrap());
+        let write_cf = engine.cf_handle(CF_WRITE).unwrap();
+
+        let mut region = Region::new();
+        region.set_id(1);
+        region.mut_peers().push(Peer::new());
+
+        let user_key = |i: u64| format!("log{:08}", i).into_bytes();
+        for i in 0..100 {
+            let k = keys::data_key(Key::from_encoded(user_key(i)).append_ts(1).as_encoded());
+            engine.put_cf(write_cf, &k, b"v").unwrap();
+        }
+
+        let observer = MonotonicKeyCheckObserver::new(vec![b"log".to_vec()]);
+        assert!(observer.is_monotonic(&user_key(1)));
+        assert!(!observer.is_monotonic(b"metric"));
+
+        // The region is split at the last key as well.
+        let keys = check(&observer, &engine, &region, true, vec![user_key(50)]);
+        assert_eq!(keys, vec![user_key(50), user_key(99)]);
+
+        // No split is triggered by the observer itself.
+        let keys = check(&observer, &engine, &region, true, vec![]);
+        assert!(keys.is_empty());
+
+        // The last key is a split key already.
+        let keys = check(&observer, &engine, &region, true, vec![user_key(99)]);
+        assert_eq!(keys, vec![user_key(99)]);
+
+        // Manual split is respected.
+        let keys = check(&observer, &engine, &region, false, vec![user_key(50)]);
+        assert_eq!(keys, vec![user_key(50)]);
+
+        let observer = MonotonicKeyCheckObserver::new(vec![b"metric".to_vec()]);
+        let keys = check(&observer, &engine, &region, true, vec![user_key(50)]);
+        assert_eq!(keys, vec![user_key(50)]);
+    }
+}
@@ -111,6 +111,9 @@ pub struct Config {
 
     pub allow_remove_leader: bool,
 
+    /// Whether followers serve the reads sent to them by the read index got from the leader.
+    pub allow_follower_read: bool,
+
     /// Max log gap allowed to propose merge.
     pub merge_max_log_gap: u64,
     /// Interval to repropose merge.
@@ -123,6 +126,12 @@ pub struct Config {
     /// Maximum size of every local read task batch.
     pub local_read_batch_size: u64,
 
+    /// Whether the apply worker writes large batches by multi-batch write. It follows
+    /// `rocksdb.enable-multi-batch-write`.
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub apply_multi_batch_write: bool,
+
     // Deprecated! These two configuration has been moved to Coprocessor.
     // They are preserved for compatibility check.
     #[doc(hidden)]
@@ -183,11 +192,13 @@ impl Default for Config {
             raft_store_max_leader_lease: ReadableDuration::secs(9),
             right_derive_when_split: true,
             allow_remove_leader: false,
+            allow_follower_read: false,
             merge_max_log_gap: 10,
             merge_check_tick_interval: ReadableDuration::secs(10),
             use_delete_range: false,
             cleanup_import_sst_interval: ReadableDuration::minutes(10),
             local_read_batch_size: 1024,
+            apply_multi_batch_write: false,
 
             // They are preserved for compatibility check.
             region_max_size: ReadableSize(0),
@@ -174,6 +174,7 @@ enum SeekMode {
     Prefix,
 }
 
+#[derive(Clone)]
 pub struct IterOption {
     lower_bound: Option<Vec<u8>>,
     upper_bound: Option<Vec<u8>>,
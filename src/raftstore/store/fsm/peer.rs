@@ -37,6 +37,7 @@ use pd::{PdClient, PdTask};
 use raftstore::{Error, Result};
 use storage::CF_RAFT;
 use util::escape;
+use util::memory;
 use util::time::{duration_to_sec, SlowTimer};
 use util::worker::{FutureWorker, Stopped};
 
@@ -975,6 +976,20 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             if let Err(e) = report_split_pd(&regions, &self.pd_worker) {
                 error!("{} failed to notify pd: {}", self.tag, e);
             }
+            // The last region takes the sequential writes of monotonically increasing
+            // keys, scatter it to spread the writes.
+            let last_region = regions.last().unwrap();
+            if self.coprocessor_host.is_monotonic_tail(last_region) {
+                // The peer on this store campaigns first, it's likely to be the leader.
+                let leader = util::find_peer(last_region, self.store_id()).unwrap().clone();
+                let task = PdTask::ScatterRegion {
+                    region: last_region.clone(),
+                    leader,
+                };
+                if let Err(e) = self.pd_worker.schedule(task) {
+                    error!("{} failed to scatter region: {}", self.tag, e);
+                }
+            }
         }
 
         let last_key = enc_end_key(regions.last().unwrap());
@@ -1486,7 +1501,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             }
         };
 
-        if !peer.is_leader() {
+        if !peer.is_leader() && !peer.is_follower_read(msg) {
             self.raft_metrics.invalid_proposal.not_leader += 1;
             return Err(Error::NotLeader(
                 region_id,
@@ -1597,6 +1612,18 @@ impl<T: Transport, C: PdClient> Store<T, C> {
 
         let mut total_gc_logs = 0;
 
+        // Evict all the entries that have been applied from the caches when the memory usage
+        // reaches the high water mark, the entries can still be fetched from the raft engine.
+        let evict_cache = memory::exceeds_high_water_mark();
+        if evict_cache {
+            warn!(
+                "{} memory usage {} exceeds the high water mark {}, evict raft entry caches",
+                self.tag,
+                memory::total(),
+                memory::high_water_mark()
+            );
+        }
+
         for (&region_id, peer) in &mut self.region_peers {
             let applied_idx = peer.get_store().applied_index();
             if !peer.is_leader() {
@@ -1644,8 +1671,12 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 );
                 REGION_MAX_LOG_LAG.observe((last_idx - replicated_idx) as f64);
             }
-            peer.mut_store()
-                .maybe_gc_cache(alive_cache_idx, applied_idx);
+            if evict_cache {
+                peer.mut_store().compact_to(applied_idx + 1);
+            } else {
+                peer.mut_store()
+                    .maybe_gc_cache(alive_cache_idx, applied_idx);
+            }
             let first_idx = peer.get_store().first_index();
             let mut compact_idx;
             if applied_idx > first_idx
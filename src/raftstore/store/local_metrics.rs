@@ -230,6 +230,7 @@ pub struct RaftProposeMetrics {
     pub local_read: u64,
     pub read_index: u64,
     pub unsafe_read_index: u64,
+    pub follower_read: u64,
     pub normal: u64,
     pub transfer_leader: u64,
     pub conf_change: u64,
@@ -243,6 +244,7 @@ impl Default for RaftProposeMetrics {
             local_read: 0,
             read_index: 0,
             unsafe_read_index: 0,
+            follower_read: 0,
             normal: 0,
             transfer_leader: 0,
             conf_change: 0,
@@ -279,6 +281,12 @@ impl RaftProposeMetrics {
                 .inc_by(self.unsafe_read_index as i64);
             self.unsafe_read_index = 0;
         }
+        if self.follower_read > 0 {
+            PEER_PROPOSAL_COUNTER_VEC
+                .with_label_values(&["follower_read"])
+                .inc_by(self.follower_read as i64);
+            self.follower_read = 0;
+        }
         if self.normal > 0 {
             PEER_PROPOSAL_COUNTER_VEC
                 .with_label_values(&["normal"])
@@ -18,6 +18,7 @@ pub mod engine;
 pub mod fsm;
 pub mod keys;
 pub mod msg;
+pub mod profiler;
 pub mod transport;
 pub mod util;
 
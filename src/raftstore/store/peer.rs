@@ -28,21 +28,22 @@ use kvproto::raft_serverpb::{MergeState, PeerState, RaftApplyState, RaftMessage}
 use protobuf::{self, Message};
 use raft::eraftpb::{self, ConfChangeType, EntryType, MessageType};
 use rocksdb::rocksdb_options::WriteOptions;
-use rocksdb::{WriteBatch, DB};
+use rocksdb::WriteBatch;
 use time::Timespec;
 
+use engine::{KvEngine, Peekable, RocksEngine};
 use pd::{PdTask, INVALID_ID};
 use raft::{
     self, Progress, ProgressState, RawNode, Ready, SnapshotStatus, StateRole, INVALID_INDEX,
     NO_LIMIT,
 };
 use raftstore::coprocessor::CoprocessorHost;
-use raftstore::store::engine::{Peekable, Snapshot, SyncSnapshot};
 use raftstore::store::worker::{
     apply, apply::ApplyMetrics, Apply, ApplyTask, Proposal, ReadProgress, ReadTask, RegionProposal,
 };
 use raftstore::store::{keys, Callback, Config, Engines, ReadResponse, RegionSnapshot};
 use raftstore::{Error, Result};
+use storage::CF_DEFAULT;
 use util::collections::{HashMap, HashSet};
 use util::time::{duration_to_sec, monotonic_raw_now};
 use util::worker::{FutureWorker, Scheduler};
@@ -52,6 +53,7 @@ use super::cmd_resp;
 use super::local_metrics::{RaftMessageMetrics, RaftMetrics, RaftProposeMetrics, RaftReadyMetrics};
 use super::metrics::*;
 use super::peer_storage::{write_peer_state, ApplySnapResult, InvokeContext, PeerStorage};
+use super::profiler::REGION_PROFILER;
 use super::transport::Transport;
 use super::util::{self, check_region_epoch, is_initial_msg, Lease, LeaseState};
 use super::{DestroyPeerJob, Store};
@@ -64,6 +66,9 @@ struct ReadIndexRequest {
     id: u64,
     cmds: MustConsumeVec<(RaftCmdRequest, Callback)>,
     renew_lease_time: Timespec,
+    // The read index got from the leader by a follower, the reads are served once it's
+    // applied. The reads of the leader are served once the leader applies to its term.
+    read_index: Option<u64>,
 }
 
 impl ReadIndexRequest {
@@ -1087,7 +1092,9 @@ impl Peer {
 
     fn apply_reads(&mut self, ready: &Ready) {
         let mut propose_time = None;
-        if self.ready_to_handle_read() {
+        if !self.is_leader() {
+            self.apply_follower_reads(ready);
+        } else if self.ready_to_handle_read() {
             for state in &ready.read_states {
                 let mut read = self.pending_reads.reads.pop_front().unwrap();
                 assert_eq!(state.request_ctx.as_slice(), read.binary_id());
@@ -1112,6 +1119,9 @@ impl Peer {
             // all uncommitted reads will be dropped silently in raft.
             self.pending_reads.clear_uncommitted(term);
         }
+        if !self.is_leader() {
+            self.handle_ready_reads();
+        }
 
         if let Some(propose_time) = propose_time {
             // `propose_time` is a placeholder, here cares about `Suspect` only,
@@ -1154,15 +1164,7 @@ impl Peer {
             self.mark_to_be_checked(groups);
         }
 
-        if self.pending_reads.ready_cnt > 0 && self.ready_to_handle_read() {
-            for _ in 0..self.pending_reads.ready_cnt {
-                let mut read = self.pending_reads.reads.pop_front().unwrap();
-                for (req, cb) in read.cmds.drain(..) {
-                    cb.invoke_read(self.handle_read(req, true));
-                }
-            }
-            self.pending_reads.ready_cnt = 0;
-        }
+        self.handle_ready_reads();
         self.pending_reads.gc();
 
         // Only leaders need to update applied_index_term.
@@ -1246,6 +1248,68 @@ impl Peer {
         None
     }
 
+    // Records the read indexes the leader responds to the follower reads with. The responses
+    // come in the order of the requests, so the requests before a responded one are dropped by
+    // the leader or the network, and their clients are told to retry.
+    fn apply_follower_reads(&mut self, ready: &Ready) {
+        let term = self.term();
+        let reads = &mut self.pending_reads;
+        for state in &ready.read_states {
+            let ctx = state.request_ctx.as_slice();
+            let ready_cnt = reads.ready_cnt;
+            let pos = match reads.reads.iter().skip(ready_cnt).position(|r| r.binary_id() == ctx) {
+                Some(pos) => pos,
+                // The request has been cleared on a leader change.
+                None => continue,
+            };
+            for mut read in reads.reads.drain(ready_cnt..ready_cnt + pos) {
+                for (_, cb) in read.cmds.drain(..) {
+                    apply::notify_stale_req(term, cb);
+                }
+            }
+            // It may be a read of this peer before it stepped down, whose read index is also
+            // confirmed by a quorum.
+            reads.reads[ready_cnt].read_index = Some(state.index);
+            reads.ready_cnt += 1;
+        }
+    }
+
+    // Serves the reads which have got their read indexes in order, until one is not safe to
+    // serve yet.
+    fn handle_ready_reads(&mut self) {
+        while self.pending_reads.ready_cnt > 0 {
+            let ready = match self.pending_reads.reads[0].read_index {
+                Some(index) => {
+                    !self.is_applying_snapshot() && self.get_store().applied_index() >= index
+                }
+                None => self.ready_to_handle_read(),
+            };
+            if !ready {
+                break;
+            }
+            let mut read = self.pending_reads.reads.pop_front().unwrap();
+            self.pending_reads.ready_cnt -= 1;
+            for (req, cb) in read.cmds.drain(..) {
+                cb.invoke_read(self.handle_read(req, true));
+            }
+        }
+    }
+
+    /// Whether the request is a read to be served by this peer as a follower. With
+    /// `allow-follower-read`, a follower serves the reads sent to it after it gets the read
+    /// index from the leader and applies to it, so the reads are as linearizable as the ones
+    /// served by the leader.
+    pub fn is_follower_read(&self, req: &RaftCmdRequest) -> bool {
+        self.cfg.allow_follower_read
+            && !self.is_leader()
+            && !req.has_admin_request()
+            && !req.get_requests().is_empty()
+            && req.get_requests().iter().all(|r| match r.get_cmd_type() {
+                CmdType::Get | CmdType::Snap => true,
+                _ => false,
+            })
+    }
+
     /// Propose a request.
     ///
     /// Return true means the request has been proposed successfully.
@@ -1262,17 +1326,36 @@ impl Peer {
 
         metrics.all += 1;
 
+        if self.is_follower_read(&req) {
+            let timer = REGION_PROFILER.start_timer(self.region_id);
+            let res = self.follower_read_index(req, err_resp, cb, metrics);
+            REGION_PROFILER.observe(self.region_id, &["read", "follower_read"], timer);
+            return res;
+        }
+
         let mut is_conf_change = false;
         let is_urgent = is_request_urgent(&req);
 
         let policy = self.inspect(&req);
         let res = match policy {
             Ok(RequestPolicy::ReadLocal) => {
+                let timer = REGION_PROFILER.start_timer(self.region_id);
                 self.read_local(req, cb, metrics);
+                REGION_PROFILER.observe(self.region_id, &["read", "local"], timer);
                 return false;
             }
-            Ok(RequestPolicy::ReadIndex) => return self.read_index(req, err_resp, cb, metrics),
-            Ok(RequestPolicy::ProposeNormal) => self.propose_normal(req, metrics),
+            Ok(RequestPolicy::ReadIndex) => {
+                let timer = REGION_PROFILER.start_timer(self.region_id);
+                let res = self.read_index(req, err_resp, cb, metrics);
+                REGION_PROFILER.observe(self.region_id, &["read", "read_index"], timer);
+                return res;
+            }
+            Ok(RequestPolicy::ProposeNormal) => {
+                let timer = REGION_PROFILER.start_timer(self.region_id);
+                let res = self.propose_normal(req, metrics);
+                REGION_PROFILER.observe(self.region_id, &["propose", "normal"], timer);
+                res
+            }
             Ok(RequestPolicy::ProposeTransferLeader) => {
                 return self.propose_transfer_leader(req, cb, metrics)
             }
@@ -1564,6 +1647,7 @@ impl Peer {
             id,
             cmds,
             renew_lease_time,
+            read_index: None,
         });
 
         // TimeoutNow has been sent out, so we need to propose explicitly to
@@ -1583,6 +1667,45 @@ impl Peer {
         true
     }
 
+    // Asks the leader for the read index of a read served by this follower.
+    fn follower_read_index(
+        &mut self,
+        req: RaftCmdRequest,
+        mut err_resp: RaftCmdResponse,
+        cb: Callback,
+        metrics: &mut RaftProposeMetrics,
+    ) -> bool {
+        // Raft drops the request silently if there is no leader.
+        let res = if self.leader_id() == INVALID_ID {
+            Err(Error::NotLeader(self.region_id, None))
+        } else {
+            self.pre_read_index()
+        };
+        if let Err(e) = res {
+            debug!("{} can't read on follower, err: {:?}", self.tag, e);
+            cmd_resp::bind_error(&mut err_resp, e);
+            cb.invoke_with_response(err_resp);
+            return false;
+        }
+
+        metrics.follower_read += 1;
+
+        // Unlike the ones of the leader, the reads are never batched into a pending request,
+        // whose read index may be older than the reads.
+        let id = self.pending_reads.next_id();
+        let ctx: [u8; 8] = unsafe { mem::transmute(id) };
+        self.raft_group.read_index(ctx.to_vec());
+        let mut cmds = MustConsumeVec::with_capacity("callback of index read", 1);
+        cmds.push((req, cb));
+        self.pending_reads.reads.push_back(ReadIndexRequest {
+            id,
+            cmds,
+            renew_lease_time: monotonic_raw_now(),
+            read_index: None,
+        });
+        true
+    }
+
     pub fn get_min_progress(&self) -> u64 {
         self.raft_group
             .status()
@@ -1792,7 +1915,7 @@ impl Peer {
 
     fn handle_read(&mut self, req: RaftCmdRequest, check_epoch: bool) -> ReadResponse {
         let mut resp = ReadExecutor::new(
-            self.engines.kv.clone(),
+            self.engines.kv_engine(),
             check_epoch,
             false, /* we don't need snapshot time */
         ).execute(&req, self.region());
@@ -2022,17 +2145,19 @@ impl RequestInspector for Peer {
     }
 }
 
+/// Executes the read requests on a snapshot of the kv engine, which is taken at the first
+/// read and shared by the following reads.
 #[derive(Debug)]
-pub struct ReadExecutor {
+pub struct ReadExecutor<E: KvEngine = RocksEngine> {
     check_epoch: bool,
-    engine: Arc<DB>,
-    snapshot: Option<SyncSnapshot>,
+    engine: E,
+    snapshot: Option<E::Snapshot>,
     snapshot_time: Option<Timespec>,
     need_snapshot_time: bool,
 }
 
-impl ReadExecutor {
-    pub fn new(engine: Arc<DB>, check_epoch: bool, need_snapshot_time: bool) -> Self {
+impl<E: KvEngine> ReadExecutor<E> {
+    pub fn new(engine: E, check_epoch: bool, need_snapshot_time: bool) -> Self {
         ReadExecutor {
             check_epoch,
             engine,
@@ -2053,8 +2178,7 @@ impl ReadExecutor {
         if self.snapshot.is_some() {
             return;
         }
-        let engine = self.engine.clone();
-        self.snapshot = Some(Snapshot::new(engine).into_sync());
+        self.snapshot = Some(self.engine.snapshot());
         // Reading current timespec after snapshot, in case we do not
         // expire lease in time.
         atomic::fence(atomic::Ordering::Release);
@@ -2071,32 +2195,23 @@ impl ReadExecutor {
 
         let mut resp = Response::new();
         let snapshot = self.snapshot.as_ref().unwrap();
-        let res = if !req.get_get().get_cf().is_empty() {
-            let cf = req.get_get().get_cf();
-            // TODO: check whether cf exists or not.
-            snapshot
-                .get_value_cf(cf, &keys::data_key(key))
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "[region {}] failed to get {} with cf {}: {:?}",
-                        region.get_id(),
-                        escape(key),
-                        cf,
-                        e
-                    )
-                })
+        let cf = if req.get_get().get_cf().is_empty() {
+            CF_DEFAULT
         } else {
-            snapshot
-                .get_value(&keys::data_key(key))
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "[region {}] failed to get {}: {:?}",
-                        region.get_id(),
-                        escape(key),
-                        e
-                    )
-                })
+            req.get_get().get_cf()
         };
+        // TODO: check whether cf exists or not.
+        let res = snapshot
+            .get_value_cf(cf, &keys::data_key(key))
+            .unwrap_or_else(|e| {
+                panic!(
+                    "[region {}] failed to get {} with cf {}: {:?}",
+                    region.get_id(),
+                    escape(key),
+                    cf,
+                    e
+                )
+            });
         if let Some(res) = res {
             resp.mut_get().set_value(res.to_vec());
         }
@@ -2104,18 +2219,20 @@ impl ReadExecutor {
         Ok(resp)
     }
 
-    pub fn execute(&mut self, msg: &RaftCmdRequest, region: &metapb::Region) -> ReadResponse {
+    /// Executes the requests of `msg` on the snapshot. The `Snap` requests get empty
+    /// responses, and the snapshot they ask for is left to the caller.
+    pub fn read(
+        &mut self,
+        msg: &RaftCmdRequest,
+        region: &metapb::Region,
+    ) -> Result<RaftCmdResponse> {
         if self.check_epoch {
             if let Err(e) = check_region_epoch(msg, region, true) {
                 debug!("[region {}] stale epoch err: {:?}", region.get_id(), e);
-                return ReadResponse {
-                    response: cmd_resp::new_error(e),
-                    snapshot: None,
-                };
+                return Err(e);
             }
         }
         self.maybe_update_snapshot();
-        let mut need_snapshot = false;
         let requests = msg.get_requests();
         let mut responses = Vec::with_capacity(requests.len());
         for req in requests {
@@ -2129,16 +2246,10 @@ impl ReadExecutor {
                             region.get_id(),
                             e
                         );
-                        return ReadResponse {
-                            response: cmd_resp::new_error(e),
-                            snapshot: None,
-                        };
+                        return Err(e);
                     }
                 },
-                CmdType::Snap => {
-                    need_snapshot = true;
-                    raft_cmdpb::Response::new()
-                }
+                CmdType::Snap => raft_cmdpb::Response::new(),
                 CmdType::Prewrite
                 | CmdType::Put
                 | CmdType::Delete
@@ -2152,9 +2263,28 @@ impl ReadExecutor {
 
         let mut response = RaftCmdResponse::new();
         response.set_responses(protobuf::RepeatedField::from_vec(responses));
+        Ok(response)
+    }
+}
+
+impl ReadExecutor<RocksEngine> {
+    pub fn execute(&mut self, msg: &RaftCmdRequest, region: &metapb::Region) -> ReadResponse {
+        let response = match self.read(msg, region) {
+            Ok(response) => response,
+            Err(e) => {
+                return ReadResponse {
+                    response: cmd_resp::new_error(e),
+                    snapshot: None,
+                };
+            }
+        };
+        let need_snapshot = msg
+            .get_requests()
+            .iter()
+            .any(|req| req.get_cmd_type() == CmdType::Snap);
         let snapshot = if need_snapshot {
             Some(RegionSnapshot::from_snapshot(
-                self.snapshot.clone().unwrap(),
+                self.snapshot.clone().unwrap().into_sync(),
                 region.to_owned(),
             ))
         } else {
@@ -2226,8 +2356,13 @@ fn make_transfer_leader_response() -> RaftCmdResponse {
 
 #[cfg(test)]
 mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
     use protobuf::ProtobufEnum;
 
+    use engine::{BTreeEngine, EngineOp, Fault, FaultEngine, WriteBatch as KvWriteBatch};
+    use storage::{ALL_CFS, CF_WRITE};
+
     use super::*;
 
     #[test]
@@ -2405,4 +2540,76 @@ mod tests {
             assert!(inspector.inspect(&req).is_err());
         }
     }
+
+    fn new_get(key: &[u8], cf: &str) -> Request {
+        let mut req = Request::new();
+        req.set_cmd_type(CmdType::Get);
+        req.mut_get().set_key(key.to_vec());
+        req.mut_get().set_cf(cf.to_owned());
+        req
+    }
+
+    #[test]
+    fn test_read_executor() {
+        let engine = BTreeEngine::new(ALL_CFS);
+        let mut wb = engine.write_batch();
+        wb.put_cf(CF_DEFAULT, &keys::data_key(b"k1"), b"v1").unwrap();
+        wb.put_cf(CF_WRITE, &keys::data_key(b"k1"), b"w1").unwrap();
+        engine.write(wb, false).unwrap();
+
+        let mut region = metapb::Region::new();
+        region.set_end_key(b"k2".to_vec());
+        let mut executor = ReadExecutor::new(engine.clone(), false, true);
+        let mut req = RaftCmdRequest::new();
+        let gets = vec![new_get(b"k1", ""), new_get(b"k1", CF_WRITE), new_get(b"k0", "")];
+        req.set_requests(gets.into());
+        let resp = executor.read(&req, &region).unwrap();
+        let values: Vec<_> = resp.get_responses().iter().map(|r| r.get_get().get_value()).collect();
+        assert_eq!(values, vec![&b"v1"[..], &b"w1"[..], &b""[..]]);
+        assert!(executor.snapshot_time().is_some());
+
+        // The following reads share the snapshot, which doesn't see the later writes.
+        let mut wb = engine.write_batch();
+        wb.delete_cf(CF_DEFAULT, &keys::data_key(b"k1")).unwrap();
+        engine.write(wb, false).unwrap();
+        req.set_requests(vec![new_get(b"k1", "")].into());
+        let resp = executor.read(&req, &region).unwrap();
+        assert_eq!(resp.get_responses()[0].get_get().get_value(), b"v1");
+
+        req.set_requests(vec![new_get(b"k2", "")].into());
+        assert!(executor.read(&req, &region).is_err());
+    }
+
+    #[test]
+    fn test_read_executor_on_faulty_engine() {
+        let engine = FaultEngine::new(BTreeEngine::new(ALL_CFS));
+        let mut wb = engine.write_batch();
+        wb.put_cf(CF_DEFAULT, &keys::data_key(b"k1"), b"v1").unwrap();
+        engine.write(wb, false).unwrap();
+
+        let region = metapb::Region::new();
+        let mut executor = ReadExecutor::new(engine.clone(), false, true);
+        let mut req = RaftCmdRequest::new();
+        req.set_requests(vec![new_get(b"k1", "")].into());
+
+        // A slow disk slows the reads down.
+        let fault = Fault {
+            latency: Duration::from_millis(50),
+            error_rate: 0.0,
+        };
+        engine.set_fault(EngineOp::Get, fault);
+        let now = Instant::now();
+        let resp = executor.read(&req, &region).unwrap();
+        assert!(now.elapsed() >= Duration::from_millis(50));
+        assert_eq!(resp.get_responses()[0].get_get().get_value(), b"v1");
+
+        // A broken disk can't be read from, which is fatal.
+        let fault = Fault {
+            latency: Duration::from_millis(0),
+            error_rate: 1.0,
+        };
+        engine.set_fault(EngineOp::Get, fault);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| executor.read(&req, &region)));
+        assert!(res.is_err());
+    }
 }
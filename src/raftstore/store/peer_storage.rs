@@ -29,10 +29,12 @@ use raft::eraftpb::{ConfState, Entry, HardState, Snapshot};
 use raft::{self, Error as RaftError, RaftState, Ready, Storage, StorageError};
 use rocksdb::{Writable, WriteBatch, DB};
 
+use engine::{KvEngine, Peekable as KvPeekable, RocksEngine};
 use raftstore::store::util::{conf_state_from_region, Engines};
 use raftstore::store::ProposalContext;
 use raftstore::{Error, Result};
 use storage::CF_RAFT;
+use util::memory::{self, MemoryConsumer};
 use util::worker::Scheduler;
 use util::{self, rocksdb};
 
@@ -124,6 +126,13 @@ pub fn last_index(state: &RaftLocalState) -> u64 {
 #[derive(Default)]
 struct EntryCache {
     cache: VecDeque<Entry>,
+    // The approximate memory used by the entries, which is reported to `util::memory`.
+    mem_size: usize,
+}
+
+#[inline]
+fn entry_mem_size(e: &Entry) -> usize {
+    e.get_data().len() + e.get_context().len()
 }
 
 impl EntryCache {
@@ -183,10 +192,12 @@ impl EntryCache {
             let first_index = entries[0].get_index();
             if cache_last_index >= first_index {
                 if self.cache.front().unwrap().get_index() >= first_index {
-                    self.cache.clear();
+                    self.clear();
                 } else {
                     let left = self.cache.len() - (cache_last_index - first_index + 1) as usize;
+                    let truncated: usize = self.cache.iter().skip(left).map(entry_mem_size).sum();
                     self.cache.truncate(left);
+                    self.release_mem(truncated);
                 }
                 if self.cache.len() + entries.len() < SHRINK_CACHE_CAPACITY
                     && self.cache.capacity() > SHRINK_CACHE_CAPACITY
@@ -203,15 +214,19 @@ impl EntryCache {
         let mut start_idx = 0;
         if let Some(len) = (self.cache.len() + entries.len()).checked_sub(MAX_CACHE_CAPACITY) {
             if len < self.cache.len() {
-                self.cache.drain(..len);
+                self.drain_to(len);
             } else {
                 start_idx = len - self.cache.len();
-                self.cache.clear();
+                self.clear();
             }
         }
+        let mut appended = 0;
         for e in &entries[start_idx..] {
+            appended += entry_mem_size(e);
             self.cache.push_back(e.to_owned());
         }
+        self.mem_size += appended;
+        memory::add(MemoryConsumer::RaftEntryCache, appended);
     }
 
     pub fn compact_to(&mut self, idx: u64) {
@@ -222,8 +237,7 @@ impl EntryCache {
         let cache_last_idx = self.cache.back().unwrap().get_index();
         // Use `cache_last_idx + 1` to make sure cache can be cleared completely
         // if neccessary.
-        self.cache
-            .drain(..(cmp::min(cache_last_idx + 1, idx) - cache_first_idx) as usize);
+        self.drain_to((cmp::min(cache_last_idx + 1, idx) - cache_first_idx) as usize);
         if self.cache.len() < SHRINK_CACHE_CAPACITY && self.cache.capacity() > SHRINK_CACHE_CAPACITY
         {
             // So the peer storage doesn't have much writes since the proposal of compaction,
@@ -231,6 +245,29 @@ impl EntryCache {
             self.cache.shrink_to_fit();
         }
     }
+
+    // Removes the first `len` entries.
+    fn drain_to(&mut self, len: usize) {
+        let drained: usize = self.cache.drain(..len).map(|e| entry_mem_size(&e)).sum();
+        self.release_mem(drained);
+    }
+
+    fn clear(&mut self) {
+        self.cache.clear();
+        let mem_size = self.mem_size;
+        self.release_mem(mem_size);
+    }
+
+    fn release_mem(&mut self, size: usize) {
+        self.mem_size -= size;
+        memory::sub(MemoryConsumer::RaftEntryCache, size);
+    }
+}
+
+impl Drop for EntryCache {
+    fn drop(&mut self) {
+        memory::sub(MemoryConsumer::RaftEntryCache, self.mem_size);
+    }
 }
 
 #[derive(Default)]
@@ -252,8 +289,11 @@ impl CacheQueryStats {
     }
 }
 
-pub struct PeerStorage {
+pub struct PeerStorage<E: KvEngine = RocksEngine> {
     pub engines: Engines,
+    // The kv data and the apply state of the region are read from it, the writes still go
+    // through the kv engine of `engines`.
+    kv_engine: E,
 
     region: metapb::Region,
     raft_state: RaftLocalState,
@@ -299,7 +339,7 @@ pub struct InvokeContext {
 }
 
 impl InvokeContext {
-    pub fn new(store: &PeerStorage) -> InvokeContext {
+    pub fn new<E: KvEngine>(store: &PeerStorage<E>) -> InvokeContext {
         InvokeContext {
             region_id: store.get_region_id(),
             raft_state: store.raft_state.clone(),
@@ -408,7 +448,7 @@ pub fn init_raft_state(raft_engine: &DB, region: &Region) -> Result<RaftLocalSta
     })
 }
 
-pub fn init_apply_state(kv_engine: &DB, region: &Region) -> Result<RaftApplyState> {
+pub fn init_apply_state<E: KvPeekable>(kv_engine: &E, region: &Region) -> Result<RaftApplyState> {
     Ok(
         match kv_engine.get_msg_cf(CF_RAFT, &keys::apply_state_key(region.get_id()))? {
             Some(s) => s,
@@ -463,9 +503,24 @@ impl PeerStorage {
         tag: String,
         stats: Rc<RefCell<CacheQueryStats>>,
     ) -> Result<PeerStorage> {
+        let kv_engine = engines.kv_engine();
+        PeerStorage::with_kv_engine(engines, kv_engine, region, region_sched, tag, stats)
+    }
+}
+
+impl<E: KvEngine> PeerStorage<E> {
+    /// Creates the storage which reads the kv data of the region from `kv_engine`.
+    pub fn with_kv_engine(
+        engines: Engines,
+        kv_engine: E,
+        region: &metapb::Region,
+        region_sched: Scheduler<RegionTask>,
+        tag: String,
+        stats: Rc<RefCell<CacheQueryStats>>,
+    ) -> Result<PeerStorage<E>> {
         debug!("creating storage on {} for {:?}", engines.kv.path(), region);
         let raft_state = init_raft_state(&engines.raft, region)?;
-        let apply_state = init_apply_state(&engines.kv, region)?;
+        let apply_state = init_apply_state(&kv_engine, region)?;
         if raft_state.get_last_index() < apply_state.get_applied_index() {
             panic!(
                 "{} unexpected raft log index: last_index {} < applied_index {}",
@@ -478,6 +533,7 @@ impl PeerStorage {
 
         Ok(PeerStorage {
             engines,
+            kv_engine,
             region: region.clone(),
             raft_state,
             apply_state,
@@ -653,8 +709,8 @@ impl PeerStorage {
         self.region = region;
     }
 
-    pub fn raw_snapshot(&self) -> DbSnapshot {
-        DbSnapshot::new(Arc::clone(&self.engines.kv))
+    pub fn raw_snapshot(&self) -> E::Snapshot {
+        self.kv_engine.snapshot()
     }
 
     fn validate_snap(&self, snap: &Snapshot) -> bool {
@@ -1403,7 +1459,7 @@ pub fn write_peer_state<T: Mutable>(
     Ok(())
 }
 
-impl Storage for PeerStorage {
+impl<E: KvEngine> Storage for PeerStorage<E> {
     fn initial_state(&self) -> raft::Result<RaftState> {
         self.initial_state()
     }
@@ -1453,6 +1509,8 @@ mod test {
     use util::rocksdb::new_engine;
     use util::worker::{Scheduler, Worker};
 
+    use engine::{BTreeEngine, WriteBatch as KvWriteBatch};
+
     use super::*;
 
     fn new_storage(sched: Scheduler<RegionTask>, path: &TempDir) -> PeerStorage {
@@ -1511,6 +1569,8 @@ mod test {
 
     fn validate_cache(store: &PeerStorage, exp_ents: &[Entry]) {
         assert_eq!(store.cache.cache, exp_ents);
+        let mem_size: usize = exp_ents.iter().map(entry_mem_size).sum();
+        assert_eq!(store.cache.mem_size, mem_size);
         for e in exp_ents {
             let key = keys::raft_log_key(store.get_region_id(), e.get_index());
             let bytes = store.engines.raft.get(&key).unwrap().unwrap();
@@ -1553,6 +1613,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_storage_on_btree_engine() {
+        let td = TempDir::new("tikv-store-test").unwrap();
+        let worker = Worker::new("snap-manager");
+        let sched = worker.scheduler();
+        let s = new_storage(sched.clone(), &td);
+        let region = s.region().clone();
+        let apply_state_key = keys::apply_state_key(region.get_id());
+        let apply_state = s.engines.kv.get_value_cf(CF_RAFT, &apply_state_key);
+        let apply_state = apply_state.unwrap().unwrap();
+
+        let kv_engine = BTreeEngine::new(ALL_CFS);
+        let mut wb = kv_engine.write_batch();
+        wb.put_cf(CF_RAFT, &apply_state_key, &apply_state).unwrap();
+        wb.put_cf(CF_DEFAULT, &keys::data_key(b"k"), b"v").unwrap();
+        kv_engine.write(wb, false).unwrap();
+
+        let metrics = Rc::new(RefCell::new(CacheQueryStats::default()));
+        let (engines, tag) = (s.engines.clone(), "".to_owned());
+        let store = PeerStorage::with_kv_engine(engines, kv_engine, &region, sched, tag, metrics);
+        let store = store.unwrap();
+        assert_eq!(store.apply_state(), s.apply_state());
+        assert_eq!(store.term(RAFT_INIT_LOG_INDEX).unwrap(), RAFT_INIT_LOG_TERM);
+        let key = keys::data_key(b"k");
+        let snap = store.raw_snapshot();
+        assert_eq!(&*snap.get_value_cf(CF_DEFAULT, &key).unwrap().unwrap(), b"v");
+        assert!(s.raw_snapshot().get_value_cf(CF_DEFAULT, &key).unwrap().is_none());
+    }
+
     fn get_meta_key_count(store: &PeerStorage) -> usize {
         let region_id = store.get_region_id();
         let mut count = 0;
@@ -1883,7 +1972,7 @@ mod test {
         let worker = Worker::new("snap-manager");
         let sched = worker.scheduler();
         let mut store = new_storage_from_ents(sched, &td, &ents);
-        store.cache.cache.clear();
+        store.cache.clear();
         // empty cache should fetch data from rocksdb directly.
         let mut res = store.entries(4, 6, u64::max_value()).unwrap();
         assert_eq!(*res, ents[1..]);
@@ -1926,10 +2015,11 @@ mod test {
         let worker = Worker::new("snap-manager");
         let sched = worker.scheduler();
         let mut store = new_storage_from_ents(sched, &td, &ents);
-        store.cache.cache.clear();
+        store.cache.clear();
 
         // initial cache
         let mut entries = vec![new_entry(6, 5), new_entry(7, 5)];
+        entries[1].set_data(b"data".to_vec());
         append_ents(&mut store, &entries);
         validate_cache(&store, &entries);
 
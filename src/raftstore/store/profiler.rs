@@ -0,0 +1,145 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use util::collections::HashMap;
+use util::time::duration_to_sec;
+
+use super::super::Result;
+
+const NO_REGION: usize = 0;
+
+lazy_static! {
+    pub static ref REGION_PROFILER: RegionProfiler = RegionProfiler::new();
+}
+
+/// `RegionProfiler` samples the time spent in the propose, apply and read stages of a
+/// single region, so that the pipeline of a problematic region can be profiled in
+/// production without profiling the whole process.
+///
+/// Only one region can be profiled at a time. When no region is being profiled, the
+/// cost of a sample point is a single atomic load.
+pub struct RegionProfiler {
+    region_id: AtomicUsize,
+    // folded stack -> total microseconds.
+    samples: Mutex<HashMap<String, u64>>,
+}
+
+impl RegionProfiler {
+    fn new() -> RegionProfiler {
+        RegionProfiler {
+            region_id: AtomicUsize::new(NO_REGION),
+            samples: Mutex::new(HashMap::default()),
+        }
+    }
+
+    #[inline]
+    pub fn is_profiling(&self, region_id: u64) -> bool {
+        self.region_id.load(Ordering::Relaxed) == region_id as usize
+    }
+
+    /// Returns a timer if the region is being profiled, it should be passed to `observe`
+    /// when the stage finishes.
+    #[inline]
+    pub fn start_timer(&self, region_id: u64) -> Option<Instant> {
+        if self.is_profiling(region_id) {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Records the time elapsed since `timer` under `stages`, like `["apply", "write"]`.
+    pub fn observe(&self, region_id: u64, stages: &[&str], timer: Option<Instant>) {
+        let timer = match timer {
+            Some(t) => t,
+            None => return,
+        };
+        // The profiling may be finished or switched to another region in the meantime.
+        if !self.is_profiling(region_id) {
+            return;
+        }
+        let micros = (duration_to_sec(timer.elapsed()) * 1_000_000.0) as u64;
+        let stack = stages.join(";");
+        let mut samples = self.samples.lock().unwrap();
+        *samples.entry(stack).or_insert(0) += micros;
+    }
+
+    /// Profiles the region for `duration` and returns the samples in the folded stack
+    /// format, one `region_<id>;<stage>;<sub stage> <microseconds>` per line, which can be
+    /// fed to flamegraph tools directly.
+    ///
+    /// This function blocks the current thread for `duration`.
+    pub fn profile(&self, region_id: u64, duration: Duration) -> Result<String> {
+        if region_id as usize == NO_REGION {
+            return Err(box_err!("invalid region id {}", region_id));
+        }
+        let prev = self
+            .region_id
+            .compare_and_swap(NO_REGION, region_id as usize, Ordering::SeqCst);
+        if prev != NO_REGION {
+            return Err(box_err!("region {} is being profiled", prev));
+        }
+        info!("[region {}] start profiling for {:?}", region_id, duration);
+
+        thread::sleep(duration);
+
+        self.region_id.store(NO_REGION, Ordering::SeqCst);
+        let samples: Vec<_> = self.samples.lock().unwrap().drain().collect();
+        let mut folded = String::new();
+        for (stack, micros) in samples {
+            writeln!(folded, "region_{};{} {}", region_id, stack, micros).unwrap();
+        }
+        info!("[region {}] finish profiling", region_id);
+        Ok(folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_profiler() {
+        let profiler = RegionProfiler::new();
+        assert!(profiler.start_timer(1).is_none());
+
+        let profiler = ::std::sync::Arc::new(profiler);
+        let p = ::std::sync::Arc::clone(&profiler);
+        let h = thread::spawn(move || p.profile(1, Duration::from_millis(200)).unwrap());
+        while !profiler.is_profiling(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Only one region can be profiled at a time.
+        assert!(profiler.profile(2, Duration::from_millis(10)).is_err());
+
+        for _ in 0..2 {
+            let timer = profiler.start_timer(1);
+            assert!(timer.is_some());
+            profiler.observe(1, &["apply", "write"], timer);
+        }
+        assert!(profiler.start_timer(2).is_none());
+        profiler.observe(2, &["read", "local"], Some(Instant::now()));
+
+        let folded = h.join().unwrap();
+        let lines: Vec<_> = folded.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("region_1;apply;write "));
+        assert!(!profiler.is_profiling(1));
+    }
+}
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use kvproto::metapb::Region;
+use kvproto::raft_serverpb::RaftApplyState;
 use rocksdb::{DBIterator, DBVector, SeekKey, TablePropertiesCollection, DB};
 use std::cmp;
 use std::sync::Arc;
@@ -19,6 +20,7 @@ use std::sync::Arc;
 use raftstore::store::engine::{IterOption, Peekable, Snapshot, SyncSnapshot};
 use raftstore::store::{keys, util, PeerStorage};
 use raftstore::Result;
+use storage::CF_RAFT;
 
 /// Snapshot of a region.
 ///
@@ -119,6 +121,19 @@ impl RegionSnapshot {
     pub fn get_end_key(&self) -> &[u8] {
         self.region.get_end_key()
     }
+
+    /// Gets the applied index of the region in the snapshot. The apply state is written
+    /// together with the data, so the index changes whenever the data of the region does.
+    pub fn get_apply_index(&self) -> Result<u64> {
+        let key = keys::apply_state_key(self.region.get_id());
+        match self.snap.get_msg_cf::<RaftApplyState>(CF_RAFT, &key)? {
+            Some(state) => Ok(state.get_applied_index()),
+            None => Err(box_err!(
+                "[region {}] apply state not found",
+                self.region.get_id()
+            )),
+        }
+    }
 }
 
 impl Clone for RegionSnapshot {
@@ -14,12 +14,13 @@
 use std::cmp::Reverse;
 use std::fmt::{self, Display, Formatter};
 use std::fs::{self, Metadata};
-use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::{error, result, str, thread, time, u64};
 
+use byteorder::{ByteOrder, LittleEndian};
 use kvproto::metapb::Region;
 use kvproto::raft_serverpb::RaftSnapshotData;
 use protobuf::Message;
@@ -33,7 +34,8 @@ use raftstore::Result as RaftStoreResult;
 use storage::{CfName, CF_DEFAULT, CF_LOCK, CF_WRITE};
 use util::codec::bytes::{BytesEncoder, CompactBytesFromFileDecoder};
 use util::collections::{HashMap, HashMapEntry as Entry};
-use util::io_limiter::{IOLimiter, LimitWriter};
+use util::encryption::{AesCtrCrypter, DataKeyManager, DecrypterReader, EncrypterWriter};
+use util::io_limiter::{self, IOLimiter, IOType, LimitWriter};
 use util::rocksdb::{prepare_sst_for_ingestion, validate_sst_for_ingestion};
 use util::transport::SendCh;
 use util::HandyRwLock;
@@ -59,6 +61,9 @@ const TMP_FILE_SUFFIX: &str = ".tmp";
 const SST_FILE_SUFFIX: &str = ".sst";
 const CLONE_FILE_SUFFIX: &str = ".clone";
 
+// The magic number at the end of an SST file in the block based table format.
+const SST_MAGIC_NUMBER: u64 = 0x88e2_41b7_85f4_cff7;
+
 const DELETE_RETRY_MAX_TIMES: u32 = 6;
 const DELETE_RETRY_TIME_MILLIS: u64 = 500;
 
@@ -84,9 +89,11 @@ quick_error! {
 pub type Result<T> = result::Result<T, Error>;
 
 // CF_LOCK is relatively small, so we use plain file for performance issue.
+// With encryption enabled, all cfs use plain files, which are encrypted by the data keys
+// while being written, and can be applied by any store.
 #[inline]
-fn plain_file_used(cf: &str) -> bool {
-    cf == CF_LOCK
+fn plain_file_used(cf: &str, encrypted: bool) -> bool {
+    cf == CF_LOCK || encrypted
 }
 
 #[inline]
@@ -226,7 +233,9 @@ use rocksdb::{DBCompressionType, EnvOptions, IngestExternalFileOptions, SstFileW
 use std::fs::{File, OpenOptions};
 use std::path::PathBuf;
 use std::time::Instant;
-use util::file::{calc_crc32, delete_file_if_exist, file_exists, get_file_size};
+use util::file::{
+    calc_crc32, calc_crc32_from_reader, delete_file_if_exist, file_exists, get_file_size,
+};
 use util::rocksdb;
 use util::rocksdb::get_fastest_supported_compression_type;
 use util::time::duration_to_sec;
@@ -268,8 +277,12 @@ fn check_file_size(path: &PathBuf, expected_size: u64) -> RaftStoreResult<()> {
     Ok(())
 }
 
-fn check_file_checksum(path: &PathBuf, expected_checksum: u32) -> RaftStoreResult<()> {
-    let checksum = calc_crc32(path)?;
+fn check_file_checksum(
+    path: &PathBuf,
+    expected_checksum: u32,
+    key_manager: Option<&Arc<DataKeyManager>>,
+) -> RaftStoreResult<()> {
+    let checksum = calc_plain_crc32(path, key_manager)?;
     if checksum != expected_checksum {
         return Err(box_err!(
             "invalid checksum {} for snapshot cf file {}, expected {}",
@@ -285,8 +298,116 @@ fn check_file_size_and_checksum(
     path: &PathBuf,
     expected_size: u64,
     expected_checksum: u32,
+    key_manager: Option<&Arc<DataKeyManager>>,
 ) -> RaftStoreResult<()> {
-    check_file_size(path, expected_size).and_then(|_| check_file_checksum(path, expected_checksum))
+    check_file_size(path, expected_size)
+        .and_then(|_| check_file_checksum(path, expected_checksum, key_manager))
+}
+
+fn encryption_error<E: fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(ErrorKind::Other, format!("{:?}", e))
+}
+
+// Returns the crypter to read the file from the beginning, or `None` if it's not encrypted.
+fn file_crypter(
+    key_manager: Option<&Arc<DataKeyManager>>,
+    path: &Path,
+) -> io::Result<Option<AesCtrCrypter>> {
+    match key_manager {
+        Some(m) => {
+            let info = m.get_file(path.to_str().unwrap()).map_err(encryption_error)?;
+            Ok(info.new_crypter())
+        }
+        None => Ok(None),
+    }
+}
+
+// Returns the crypter to write a new file, or `None` if encryption is disabled.
+fn new_file_crypter(
+    key_manager: Option<&Arc<DataKeyManager>>,
+    path: &Path,
+) -> io::Result<Option<AesCtrCrypter>> {
+    match key_manager {
+        Some(m) => {
+            let info = m.new_file(path.to_str().unwrap()).map_err(encryption_error)?;
+            Ok(info.new_crypter())
+        }
+        None => Ok(None),
+    }
+}
+
+fn rename_snap_file(
+    key_manager: Option<&Arc<DataKeyManager>>,
+    src: &Path,
+    dst: &Path,
+) -> io::Result<()> {
+    // The encryption info is linked before the rename and deleted after it, so that the
+    // file is never unknown to the key manager.
+    if let Some(m) = key_manager {
+        m.link_file(src.to_str().unwrap(), dst.to_str().unwrap())
+            .map_err(encryption_error)?;
+    }
+    fs::rename(src, dst)?;
+    if let Some(m) = key_manager {
+        m.delete_file(src.to_str().unwrap())
+            .map_err(encryption_error)?;
+    }
+    Ok(())
+}
+
+fn delete_snap_file(key_manager: Option<&Arc<DataKeyManager>>, path: &Path) -> io::Result<bool> {
+    let deleted = delete_file_if_exist(path)?;
+    if let Some(m) = key_manager {
+        m.delete_file(path.to_str().unwrap())
+            .map_err(encryption_error)?;
+    }
+    Ok(deleted)
+}
+
+// Calculates the checksum of the plaintext of the file, which is the same on all the stores.
+fn calc_plain_crc32(path: &Path, key_manager: Option<&Arc<DataKeyManager>>) -> io::Result<u32> {
+    match file_crypter(key_manager, path)? {
+        Some(crypter) => {
+            let mut reader = DecrypterReader::new(File::open(path)?, Some(crypter));
+            calc_crc32_from_reader(&mut reader)
+        }
+        None => calc_crc32(path),
+    }
+}
+
+// A received cf file may be a plain file or an SST file, depending on whether the sender
+// enables encryption, so the format is told by the magic number at the end. A plain file
+// always ends with the encoded empty key, which is 0.
+fn is_sst_file(path: &Path, key_manager: Option<&Arc<DataKeyManager>>) -> io::Result<bool> {
+    let mut f = File::open(path)?;
+    let size = f.metadata()?.len();
+    if size < 8 {
+        return Ok(false);
+    }
+    let mut footer = [0; 8];
+    f.seek(SeekFrom::Start(size - 8))?;
+    f.read_exact(&mut footer)?;
+    if let Some(mut crypter) = file_crypter(key_manager, path)? {
+        crypter.set_offset(size - 8);
+        crypter.crypt(&mut footer);
+    }
+    Ok(LittleEndian::read_u64(&footer) == SST_MAGIC_NUMBER)
+}
+
+// Encrypts the data with `crypter` if any, and writes it to `w`.
+fn write_encrypted<W: Write>(
+    w: &mut W,
+    crypter: Option<&mut AesCtrCrypter>,
+    data: &[u8],
+) -> io::Result<()> {
+    match crypter {
+        Some(c) => {
+            let mut buf = data.to_vec();
+            c.crypt(&mut buf);
+            w.write_all(&buf)
+        }
+        None => w.write_all(data),
+    }
 }
 
 #[derive(Default)]
@@ -297,6 +418,8 @@ struct CfFile {
     pub clone_path: PathBuf,
     pub sst_writer: Option<SstFileWriter>,
     pub file: Option<File>,
+    // Encrypts or decrypts `file` if encryption is enabled.
+    pub crypter: Option<AesCtrCrypter>,
     pub kv_count: u64,
     pub size: u64,
     pub written_size: u64,
@@ -322,6 +445,7 @@ pub struct Snap {
     meta_file: MetaFile,
     size_track: Arc<AtomicU64>,
     limiter: Option<Arc<IOLimiter>>,
+    key_manager: Option<Arc<DataKeyManager>>,
     hold_tmp_files: bool,
 }
 
@@ -334,6 +458,7 @@ impl Snap {
         to_build: bool,
         deleter: Box<SnapshotDeleter>,
         limiter: Option<Arc<IOLimiter>>,
+        key_manager: Option<Arc<DataKeyManager>>,
     ) -> RaftStoreResult<Snap> {
         let dir_path = dir.into();
         if !dir_path.exists() {
@@ -380,6 +505,7 @@ impl Snap {
             meta_file,
             size_track,
             limiter,
+            key_manager,
             hold_tmp_files: false,
         };
 
@@ -413,8 +539,18 @@ impl Snap {
         size_track: Arc<AtomicU64>,
         deleter: Box<SnapshotDeleter>,
         limiter: Option<Arc<IOLimiter>>,
+        key_manager: Option<Arc<DataKeyManager>>,
     ) -> RaftStoreResult<Snap> {
-        let mut s = Snap::new(dir, key, size_track, true, true, deleter, limiter)?;
+        let mut s = Snap::new(
+            dir,
+            key,
+            size_track,
+            true,
+            true,
+            deleter,
+            limiter,
+            key_manager,
+        )?;
         s.init_for_building(snap)?;
         Ok(s)
     }
@@ -424,8 +560,9 @@ impl Snap {
         key: &SnapKey,
         size_track: Arc<AtomicU64>,
         deleter: Box<SnapshotDeleter>,
+        key_manager: Option<Arc<DataKeyManager>>,
     ) -> RaftStoreResult<Snap> {
-        let mut s = Snap::new(dir, key, size_track, true, false, deleter, None)?;
+        let mut s = Snap::new(dir, key, size_track, true, false, deleter, None, key_manager)?;
 
         if !s.exists() {
             // Skip the initialization below if it doesn't exists.
@@ -436,6 +573,7 @@ impl Snap {
             if cf_file.size > 0 {
                 let file = File::open(&cf_file.path)?;
                 cf_file.file = Some(file);
+                cf_file.crypter = file_crypter(s.key_manager.as_ref(), &cf_file.path)?;
             }
         }
         Ok(s)
@@ -448,8 +586,18 @@ impl Snap {
         size_track: Arc<AtomicU64>,
         deleter: Box<SnapshotDeleter>,
         limiter: Option<Arc<IOLimiter>>,
+        key_manager: Option<Arc<DataKeyManager>>,
     ) -> RaftStoreResult<Snap> {
-        let mut s = Snap::new(dir, key, size_track, false, false, deleter, limiter)?;
+        let mut s = Snap::new(
+            dir,
+            key,
+            size_track,
+            false,
+            false,
+            deleter,
+            limiter,
+            key_manager,
+        )?;
         s.set_snapshot_meta(snapshot_meta)?;
         if s.exists() {
             return Ok(s);
@@ -471,6 +619,7 @@ impl Snap {
                 .create_new(true)
                 .open(&cf_file.tmp_path)?;
             cf_file.file = Some(f);
+            cf_file.crypter = new_file_crypter(s.key_manager.as_ref(), &cf_file.tmp_path)?;
             cf_file.write_digest = Some(Digest::new(crc32::IEEE));
         }
         Ok(s)
@@ -481,8 +630,9 @@ impl Snap {
         key: &SnapKey,
         size_track: Arc<AtomicU64>,
         deleter: Box<SnapshotDeleter>,
+        key_manager: Option<Arc<DataKeyManager>>,
     ) -> RaftStoreResult<Snap> {
-        let s = Snap::new(dir, key, size_track, false, false, deleter, None)?;
+        let s = Snap::new(dir, key, size_track, false, false, deleter, None, key_manager)?;
         Ok(s)
     }
 
@@ -497,13 +647,15 @@ impl Snap {
         self.meta_file.file = Some(file);
         self.hold_tmp_files = true;
 
+        let encrypted = self.key_manager.is_some();
         for cf_file in &mut self.cf_files {
-            if plain_file_used(cf_file.cf) {
+            if plain_file_used(cf_file.cf, encrypted) {
                 let f = OpenOptions::new()
                     .write(true)
                     .create_new(true)
                     .open(&cf_file.tmp_path)?;
                 cf_file.file = Some(f);
+                cf_file.crypter = new_file_crypter(self.key_manager.as_ref(), &cf_file.tmp_path)?;
             } else {
                 let handle = snap.cf_handle(cf_file.cf)?;
                 let mut io_options = snap.get_db().get_options_cf(handle).clone();
@@ -592,10 +744,26 @@ impl Snap {
                 // this is checked when loading the snapshot meta.
                 continue;
             }
-            if plain_file_used(cf_file.cf) {
-                check_file_size_and_checksum(&cf_file.path, cf_file.size, cf_file.checksum)?;
+            if self.is_plain_cf_file(cf_file)? {
+                check_file_size_and_checksum(
+                    &cf_file.path,
+                    cf_file.size,
+                    cf_file.checksum,
+                    self.key_manager.as_ref(),
+                )?;
             } else {
-                prepare_sst_for_ingestion(&cf_file.path, &cf_file.clone_path)?;
+                match file_crypter(self.key_manager.as_ref(), &cf_file.path)? {
+                    // RocksDB only ingests plaintext files, the decrypted file is moved into
+                    // the engine right away, whose files are not encrypted anyway.
+                    Some(crypter) => {
+                        let mut reader =
+                            DecrypterReader::new(File::open(&cf_file.path)?, Some(crypter));
+                        let mut f = File::create(&cf_file.clone_path)?;
+                        io::copy(&mut reader, &mut f)?;
+                        f.sync_all()?;
+                    }
+                    None => prepare_sst_for_ingestion(&cf_file.path, &cf_file.clone_path)?,
+                }
                 validate_sst_for_ingestion(
                     &db,
                     cf_file.cf,
@@ -608,6 +776,13 @@ impl Snap {
         Ok(())
     }
 
+    fn is_plain_cf_file(&self, cf_file: &CfFile) -> io::Result<bool> {
+        if plain_file_used(cf_file.cf, false) {
+            return Ok(true);
+        }
+        is_sst_file(&cf_file.path, self.key_manager.as_ref()).map(|sst| !sst)
+    }
+
     fn switch_to_cf_file(&mut self, cf: &str) -> io::Result<()> {
         match self.cf_files.iter().position(|x| x.cf == cf) {
             Some(index) => {
@@ -637,8 +812,9 @@ impl Snap {
     }
 
     fn save_cf_files(&mut self) -> io::Result<()> {
+        let encrypted = self.key_manager.is_some();
         for cf_file in &mut self.cf_files {
-            if plain_file_used(cf_file.cf) {
+            if plain_file_used(cf_file.cf, encrypted) {
                 let _ = cf_file.file.take();
             } else if cf_file.kv_count == 0 {
                 let _ = cf_file.sst_writer.take().unwrap();
@@ -649,15 +825,16 @@ impl Snap {
                 }
             }
             let size = get_file_size(&cf_file.tmp_path)?;
+            let key_manager = self.key_manager.as_ref();
             if size > 0 {
-                fs::rename(&cf_file.tmp_path, &cf_file.path)?;
+                rename_snap_file(key_manager, &cf_file.tmp_path, &cf_file.path)?;
                 cf_file.size = size;
                 // add size
                 self.size_track.fetch_add(size, Ordering::SeqCst);
-                cf_file.checksum = calc_crc32(&cf_file.path)?;
+                cf_file.checksum = calc_plain_crc32(&cf_file.path, key_manager)?;
             } else {
                 // Clean up the `tmp_path` if this cf file is empty.
-                delete_file_if_exist(&cf_file.tmp_path).unwrap();
+                delete_snap_file(key_manager, &cf_file.tmp_path).unwrap();
             }
         }
         Ok(())
@@ -711,17 +888,19 @@ impl Snap {
         let (begin_key, end_key) = (enc_start_key(region), enc_end_key(region));
         for cf in SNAPSHOT_CFS {
             self.switch_to_cf_file(cf)?;
-            let (cf_key_count, cf_size) = if plain_file_used(cf) {
+            let (cf_key_count, cf_size) = if plain_file_used(cf, self.key_manager.is_some()) {
+                let cf_file = &mut self.cf_files[self.cf_index];
                 // If the relative files are deleted after `Snap::new` and
                 // `init_for_building`, the file could be None.
-                let file = match self.cf_files[self.cf_index].file.as_mut() {
+                let file = match cf_file.file.as_mut() {
                     Some(f) => f,
                     None => {
                         let e = box_err!("cf_file is none for cf {}", cf);
                         return Err(RaftStoreError::Snapshot(e));
                     }
                 };
-                build_plain_cf_file(file, snap, cf, &begin_key, &end_key)?
+                let mut writer = EncrypterWriter::new(file, cf_file.crypter.take());
+                build_plain_cf_file(&mut writer, snap, cf, &begin_key, &end_key)?
             } else {
                 let mut key_count = 0;
                 let mut size = 0;
@@ -746,6 +925,9 @@ impl Snap {
                 })?;
                 (key_count, size)
             };
+            // The cf file is charged after it's built, so the following ones are delayed if
+            // the disk is busy.
+            io_limiter::request_io(IOType::Snapshot, cf_size);
             snap_key_count += cf_key_count;
             SNAPSHOT_CF_KV_COUNT
                 .with_label_values(&[cf])
@@ -879,12 +1061,13 @@ impl Snapshot for Snap {
 
     fn delete(&self) {
         debug!("deleting {}", self.path());
+        let key_manager = self.key_manager.as_ref();
         for cf_file in &self.cf_files {
             delete_file_if_exist(&cf_file.clone_path).unwrap();
             if self.hold_tmp_files {
-                delete_file_if_exist(&cf_file.tmp_path).unwrap();
+                delete_snap_file(key_manager, &cf_file.tmp_path).unwrap();
             }
-            if delete_file_if_exist(&cf_file.path).unwrap() {
+            if delete_snap_file(key_manager, &cf_file.path).unwrap() {
                 self.size_track.fetch_sub(cf_file.size, Ordering::SeqCst);
             }
         }
@@ -944,7 +1127,11 @@ impl Snapshot for Snap {
                 ));
             }
 
-            fs::rename(&cf_file.tmp_path, &cf_file.path)?;
+            rename_snap_file(
+                self.key_manager.as_ref(),
+                &cf_file.tmp_path,
+                &cf_file.path,
+            )?;
             self.size_track.fetch_add(cf_file.size, Ordering::SeqCst);
         }
         // write meta file
@@ -963,17 +1150,20 @@ impl Snapshot for Snap {
     fn apply(&mut self, options: ApplyOptions) -> Result<()> {
         box_try!(self.validate(Arc::clone(&options.db)));
 
-        for cf_file in &mut self.cf_files {
+        for cf_file in &self.cf_files {
             if cf_file.size == 0 {
                 // Skip empty cf file.
                 continue;
             }
 
             check_abort(&options.abort)?;
+            io_limiter::request_io(IOType::Snapshot, cf_file.size as usize);
             let cf_handle = box_try!(rocksdb::get_cf_handle(&options.db, cf_file.cf));
-            if plain_file_used(cf_file.cf) {
-                let mut file = box_try!(File::open(&cf_file.path));
-                apply_plain_cf_file(&mut BufReader::new(file), &options, cf_handle)?;
+            if box_try!(self.is_plain_cf_file(cf_file)) {
+                let file = box_try!(File::open(&cf_file.path));
+                let crypter = box_try!(file_crypter(self.key_manager.as_ref(), &cf_file.path));
+                let mut reader = BufReader::new(DecrypterReader::new(file, crypter));
+                apply_plain_cf_file(&mut reader, &options, cf_handle)?;
             } else {
                 let _timer = INGEST_SST_DURATION_SECONDS.start_coarse_timer();
                 let mut ingest_opt = IngestExternalFileOptions::new();
@@ -1007,6 +1197,10 @@ impl Read for Snap {
                     self.cf_index += 1;
                 }
                 Ok(n) => {
+                    // The snapshot is sent in plaintext, as the data keys are never shared.
+                    if let Some(ref mut crypter) = cf_file.crypter {
+                        crypter.crypt(&mut buf[..n]);
+                    }
                     return Ok(n);
                 }
                 e => return e,
@@ -1021,6 +1215,7 @@ impl Write for Snap {
         if buf.is_empty() {
             return Ok(0);
         }
+        io_limiter::request_io(IOType::Snapshot, buf.len());
 
         let mut next_buf = buf;
         while self.cf_index < self.cf_files.len() {
@@ -1040,13 +1235,13 @@ impl Write for Snap {
             let digest = cf_file.write_digest.as_mut().unwrap();
 
             if next_buf.len() > left {
-                file.write_all(&next_buf[0..left])?;
+                write_encrypted(&mut file, cf_file.crypter.as_mut(), &next_buf[0..left])?;
                 digest.write(&next_buf[0..left]);
                 cf_file.written_size += left as u64;
                 self.cf_index += 1;
                 next_buf = &next_buf[left..];
             } else {
-                file.write_all(next_buf)?;
+                write_encrypted(&mut file, cf_file.crypter.as_mut(), next_buf)?;
                 digest.write(next_buf);
                 cf_file.written_size += next_buf.len() as u64;
                 return Ok(buf.len());
@@ -1121,6 +1316,7 @@ pub struct SnapManager {
     ch: Option<SendCh<Msg>>,
     limiter: Option<Arc<IOLimiter>>,
     max_total_size: u64,
+    key_manager: Option<Arc<DataKeyManager>>,
 }
 
 impl SnapManager {
@@ -1147,7 +1343,7 @@ impl SnapManager {
             if p.file_type()?.is_file() {
                 if let Some(s) = p.file_name().to_str() {
                     if s.ends_with(TMP_FILE_SUFFIX) {
-                        fs::remove_file(p.path())?;
+                        delete_snap_file(self.key_manager.as_ref(), &p.path())?;
                     } else if s.ends_with(SST_FILE_SUFFIX) {
                         let len = p.metadata()?.len();
                         core.snap_size.fetch_add(len, Ordering::SeqCst);
@@ -1256,6 +1452,7 @@ impl SnapManager {
             snap_size,
             Box::new(self.clone()),
             self.limiter.clone(),
+            self.key_manager.clone(),
         )?;
         Ok(Box::new(f))
     }
@@ -1267,6 +1464,7 @@ impl SnapManager {
             key,
             Arc::clone(&core.snap_size),
             Box::new(self.clone()),
+            self.key_manager.clone(),
         )?;
         Ok(Box::new(s))
     }
@@ -1286,6 +1484,7 @@ impl SnapManager {
             Arc::clone(&core.snap_size),
             Box::new(self.clone()),
             self.limiter.clone(),
+            self.key_manager.clone(),
         )?;
         Ok(Box::new(f))
     }
@@ -1297,6 +1496,7 @@ impl SnapManager {
             key,
             Arc::clone(&core.snap_size),
             Box::new(self.clone()),
+            self.key_manager.clone(),
         )?;
         if !s.exists() {
             return Err(RaftStoreError::Other(From::from(
@@ -1413,6 +1613,7 @@ impl SnapshotDeleter for SnapManager {
 pub struct SnapManagerBuilder {
     max_write_bytes_per_sec: u64,
     max_total_size: u64,
+    key_manager: Option<Arc<DataKeyManager>>,
 }
 
 impl SnapManagerBuilder {
@@ -1424,6 +1625,14 @@ impl SnapManagerBuilder {
         self.max_total_size = bytes;
         self
     }
+    /// Encrypts the snapshot files at rest by the data keys of `m`.
+    pub fn encryption_key_manager(
+        &mut self,
+        m: Option<Arc<DataKeyManager>>,
+    ) -> &mut SnapManagerBuilder {
+        self.key_manager = m;
+        self
+    }
     pub fn build<T: Into<String>>(&self, path: T, ch: Option<SendCh<Msg>>) -> SnapManager {
         let limiter = if self.max_write_bytes_per_sec > 0 {
             Some(Arc::new(IOLimiter::new(self.max_write_bytes_per_sec)))
@@ -1444,6 +1653,7 @@ impl SnapManagerBuilder {
             ch,
             limiter,
             max_total_size,
+            key_manager: self.key_manager.clone(),
         }
     }
 }
@@ -1474,6 +1684,8 @@ mod test {
     use raftstore::store::peer_storage::JOB_STATUS_RUNNING;
     use raftstore::Result;
     use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+    use util::encryption::{generate_key, DataKeyManager, EncryptionMethod, FileMasterKey};
+    use util::file::calc_crc32;
     use util::rocksdb;
 
     const TEST_STORE_ID: u64 = 1;
@@ -1663,6 +1875,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         // Ensure that this snapshot file doesn't exist before being built.
         assert!(!s1.exists());
@@ -1694,6 +1907,7 @@ mod test {
             &key,
             Arc::clone(&size_track),
             deleter.clone(),
+            None,
         ).unwrap();
         assert!(s2.exists());
 
@@ -1709,6 +1923,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s3.exists());
 
@@ -1729,8 +1944,13 @@ mod test {
         assert_eq!(size_track.load(Ordering::SeqCst), size);
 
         // Ensure a snapshot could be applied to DB.
-        let mut s4 =
-            Snap::new_for_applying(dst_dir.path(), &key, Arc::clone(&size_track), deleter).unwrap();
+        let mut s4 = Snap::new_for_applying(
+            dst_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter,
+            None,
+        ).unwrap();
         assert!(s4.exists());
 
         let dst_db_dir = TempDir::new("test-snap-file-db-dst").unwrap();
@@ -1757,6 +1977,189 @@ mod test {
         assert_eq_db(db, dst_db.as_ref());
     }
 
+    fn new_key_manager(dir: &TempDir) -> Arc<DataKeyManager> {
+        let master_key = Box::new(FileMasterKey::from_key(generate_key().unwrap()));
+        let m = DataKeyManager::new(master_key, EncryptionMethod::Aes256Ctr, dir.path()).unwrap();
+        Arc::new(m)
+    }
+
+    fn apply_to_new_db(snap: &mut Snap, region: &Region) -> Result<(TempDir, Arc<DB>)> {
+        let db_dir = TempDir::new("test-snap-apply-db").unwrap();
+        let db = Arc::new(rocksdb::new_engine(db_dir.path().to_str().unwrap(), ALL_CFS, None)?);
+        let options = ApplyOptions {
+            db: Arc::clone(&db),
+            region: region.clone(),
+            abort: Arc::new(AtomicUsize::new(JOB_STATUS_RUNNING)),
+            write_batch_size: TEST_WRITE_BATCH_SIZE,
+        };
+        box_try!(snap.apply(options));
+        Ok((db_dir, db))
+    }
+
+    #[test]
+    fn test_encrypted_snap_file() {
+        let region_id = 1;
+        let region = get_test_region(region_id, 1, 1);
+        let db_dir = TempDir::new("test-encrypted-snap-db").unwrap();
+        let db = get_test_db(&db_dir).unwrap();
+        let snapshot = DbSnapshot::new(Arc::clone(&db));
+        let key = SnapKey::new(region_id, 1, 1);
+        let size_track = Arc::new(AtomicU64::new(0));
+        let deleter = Box::new(DummyDeleter {});
+
+        let src_dir = TempDir::new("test-encrypted-snap-src").unwrap();
+        let src_key_dir = TempDir::new("test-encrypted-snap-src-keys").unwrap();
+        let src_manager = new_key_manager(&src_key_dir);
+        let mut s1 = Snap::new_for_building(
+            src_dir.path(),
+            &key,
+            &snapshot,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+            Some(Arc::clone(&src_manager)),
+        ).unwrap();
+        let mut snap_data = RaftSnapshotData::new();
+        snap_data.set_region(region.clone());
+        let mut stat = SnapshotStatistics::new();
+        s1.build(
+            &snapshot,
+            &region,
+            &mut snap_data,
+            &mut stat,
+            deleter.clone(),
+        ).unwrap();
+
+        // All the cf files are plain files, and encrypted on the disk.
+        for cf_file in &s1.cf_files {
+            assert!(cf_file.size > 0);
+            assert!(!super::is_sst_file(&cf_file.path, Some(&src_manager)).unwrap());
+            let path = cf_file.path.to_str().unwrap();
+            assert!(src_manager.get_file(path).unwrap().is_encrypted());
+            assert_ne!(calc_crc32(&cf_file.path).unwrap(), cf_file.checksum);
+        }
+
+        // The receiver encrypts the snapshot by its own data keys.
+        let dst_dir = TempDir::new("test-encrypted-snap-dst").unwrap();
+        let dst_key_dir = TempDir::new("test-encrypted-snap-dst-keys").unwrap();
+        let dst_manager = new_key_manager(&dst_key_dir);
+        let mut s2 = Snap::new_for_sending(
+            src_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            Some(Arc::clone(&src_manager)),
+        ).unwrap();
+        let mut s3 = Snap::new_for_receiving(
+            dst_dir.path(),
+            &key,
+            snap_data.get_meta().clone(),
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+            Some(Arc::clone(&dst_manager)),
+        ).unwrap();
+        io::copy(&mut s2, &mut s3).unwrap();
+        s3.save().unwrap();
+        let path = s3.cf_files[0].path.to_str().unwrap().to_owned();
+        assert!(dst_manager.get_file(&path).unwrap().is_encrypted());
+
+        let mut s4 = Snap::new_for_applying(
+            dst_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            Some(Arc::clone(&dst_manager)),
+        ).unwrap();
+        let (_dir, dst_db) = apply_to_new_db(&mut s4, &region).unwrap();
+        assert_eq_db(Arc::clone(&db), dst_db.as_ref());
+        s4.delete();
+        assert!(!dst_manager.get_file(&path).unwrap().is_encrypted());
+
+        // A store without encryption can apply the snapshot as well.
+        let plain_dir = TempDir::new("test-encrypted-snap-plain").unwrap();
+        let mut s5 = Snap::new_for_sending(
+            src_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            Some(Arc::clone(&src_manager)),
+        ).unwrap();
+        let mut s6 = Snap::new_for_receiving(
+            plain_dir.path(),
+            &key,
+            snap_data.get_meta().clone(),
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+            None,
+        ).unwrap();
+        io::copy(&mut s5, &mut s6).unwrap();
+        s6.save().unwrap();
+        let mut s7 = Snap::new_for_applying(
+            plain_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+        ).unwrap();
+        let (_dir, dst_db) = apply_to_new_db(&mut s7, &region).unwrap();
+        assert_eq_db(Arc::clone(&db), dst_db.as_ref());
+
+        // A store with encryption receives the SST files from a store without it, which are
+        // encrypted on its disk and decrypted to be ingested.
+        let sst_dir = TempDir::new("test-encrypted-snap-sst").unwrap();
+        let sst_dst_dir = TempDir::new("test-encrypted-snap-sst-dst").unwrap();
+        let mut s8 = Snap::new_for_building(
+            sst_dir.path(),
+            &key,
+            &snapshot,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+            None,
+        ).unwrap();
+        let mut snap_data = RaftSnapshotData::new();
+        snap_data.set_region(region.clone());
+        s8.build(
+            &snapshot,
+            &region,
+            &mut snap_data,
+            &mut stat,
+            deleter.clone(),
+        ).unwrap();
+        let mut s9 = Snap::new_for_sending(
+            sst_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+        ).unwrap();
+        let mut s10 = Snap::new_for_receiving(
+            sst_dst_dir.path(),
+            &key,
+            snap_data.get_meta().clone(),
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+            Some(Arc::clone(&dst_manager)),
+        ).unwrap();
+        io::copy(&mut s9, &mut s10).unwrap();
+        s10.save().unwrap();
+        let sst_file = s10.cf_files.iter().find(|f| f.size > 0 && f.cf != CF_LOCK);
+        let path = sst_file.unwrap().path.to_str().unwrap().to_owned();
+        assert!(dst_manager.get_file(&path).unwrap().is_encrypted());
+        let mut s11 = Snap::new_for_applying(
+            sst_dst_dir.path(),
+            &key,
+            Arc::clone(&size_track),
+            deleter,
+            Some(dst_manager),
+        ).unwrap();
+        let (_dir, dst_db) = apply_to_new_db(&mut s11, &region).unwrap();
+        assert_eq_db(db, dst_db.as_ref());
+    }
+
     #[test]
     fn test_empty_snap_validation() {
         test_snap_validation(get_test_empty_db);
@@ -1785,6 +2188,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s1.exists());
 
@@ -1807,6 +2211,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(s2.exists());
 
@@ -1928,6 +2333,7 @@ mod test {
             key,
             Arc::clone(&size_track),
             deleter.clone(),
+            None,
         ).unwrap();
         assert!(from.exists());
 
@@ -1938,6 +2344,7 @@ mod test {
             Arc::clone(&size_track),
             deleter,
             None,
+            None,
         ).unwrap();
 
         assert!(!to.exists());
@@ -1965,6 +2372,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s1.exists());
 
@@ -1983,7 +2391,7 @@ mod test {
         corrupt_snapshot_size_in(dir.path());
 
         assert!(
-            Snap::new_for_sending(dir.path(), &key, Arc::clone(&size_track), deleter.clone())
+            Snap::new_for_sending(dir.path(), &key, Arc::clone(&size_track), deleter.clone(), None)
                 .is_err()
         );
 
@@ -1994,6 +2402,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s2.exists());
         s2.build(
@@ -2024,6 +2433,7 @@ mod test {
             &key,
             Arc::clone(&size_track),
             deleter.clone(),
+            None,
         ).unwrap();
         assert!(s5.exists());
 
@@ -2046,6 +2456,7 @@ mod test {
                 Arc::clone(&size_track),
                 deleter.clone(),
                 None,
+                None,
             ).is_err()
         );
         assert!(
@@ -2053,7 +2464,8 @@ mod test {
                 dst_dir.path(),
                 &key,
                 Arc::clone(&size_track),
-                deleter.clone()
+                deleter.clone(),
+                None
             ).is_err()
         );
     }
@@ -2077,6 +2489,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s1.exists());
 
@@ -2095,7 +2508,7 @@ mod test {
         assert_eq!(1, corrupt_snapshot_meta_file(dir.path()));
 
         assert!(
-            Snap::new_for_sending(dir.path(), &key, Arc::clone(&size_track), deleter.clone())
+            Snap::new_for_sending(dir.path(), &key, Arc::clone(&size_track), deleter.clone(), None)
                 .is_err()
         );
 
@@ -2106,6 +2519,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         assert!(!s2.exists());
         s2.build(
@@ -2134,7 +2548,8 @@ mod test {
                 dst_dir.path(),
                 &key,
                 Arc::clone(&size_track),
-                deleter.clone()
+                deleter.clone(),
+                None
             ).is_err()
         );
         assert!(
@@ -2145,6 +2560,7 @@ mod test {
                 Arc::clone(&size_track),
                 deleter.clone(),
                 None,
+                None,
             ).is_err()
         );
     }
@@ -2188,6 +2604,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         let mut region = get_test_region(1, 1, 1);
         let mut snap_data = RaftSnapshotData::new();
@@ -2200,8 +2617,13 @@ mod test {
             &mut stat,
             deleter.clone(),
         ).unwrap();
-        let mut s =
-            Snap::new_for_sending(&path, &key1, Arc::clone(&size_track), deleter.clone()).unwrap();
+        let mut s = Snap::new_for_sending(
+            &path,
+            &key1,
+            Arc::clone(&size_track),
+            deleter.clone(),
+            None,
+        ).unwrap();
         let expected_size = s.total_size().unwrap();
         let mut s2 = Snap::new_for_receiving(
             &path,
@@ -2210,6 +2632,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         let n = io::copy(&mut s, &mut s2).unwrap();
         assert_eq!(n, expected_size);
@@ -2225,6 +2648,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
         let s4 = Snap::new_for_receiving(
             &path,
@@ -2233,6 +2657,7 @@ mod test {
             Arc::clone(&size_track),
             deleter.clone(),
             None,
+            None,
         ).unwrap();
 
         assert!(s1.exists());
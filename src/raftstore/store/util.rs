@@ -17,6 +17,8 @@ use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::{fmt, u64};
 
+use engine::RocksEngine;
+use kvproto::import_sstpb::SSTMeta;
 use kvproto::metapb;
 use kvproto::raft_cmdpb::{AdminCmdType, RaftCmdRequest};
 use protobuf::{self, Message};
@@ -26,6 +28,7 @@ use raftstore::store::keys;
 use raftstore::{Error, Result};
 use rocksdb::{Range, TablePropertiesCollection, Writable, WriteBatch, DB};
 use time::{Duration, Timespec};
+use uuid::Uuid;
 
 use storage::{Key, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE, LARGE_CFS};
 use util::escape;
@@ -309,6 +312,40 @@ pub fn check_region_epoch(
     Ok(())
 }
 
+/// Checks whether the SST file can be ingested into the region, i.e. the file belongs to the
+/// region at its current epoch and its keys are in the region.
+pub fn check_sst_for_ingestion(sst: &SSTMeta, region: &metapb::Region) -> Result<()> {
+    let uuid = sst.get_uuid();
+    if let Err(e) = Uuid::from_bytes(uuid) {
+        return Err(box_err!("invalid uuid {:?}: {:?}", uuid, e));
+    }
+
+    let cf_name = sst.get_cf_name();
+    if cf_name != CF_DEFAULT && cf_name != CF_WRITE {
+        return Err(box_err!("invalid cf name {}", cf_name));
+    }
+
+    let region_id = sst.get_region_id();
+    if region_id != region.get_id() {
+        return Err(Error::RegionNotFound(region_id));
+    }
+
+    let epoch = sst.get_region_epoch();
+    let region_epoch = region.get_region_epoch();
+    if epoch.get_conf_ver() != region_epoch.get_conf_ver()
+        || epoch.get_version() != region_epoch.get_version()
+    {
+        let error = format!("{:?} != {:?}", epoch, region_epoch);
+        return Err(Error::StaleEpoch(error, vec![region.clone()]));
+    }
+
+    let range = sst.get_range();
+    check_key_in_region(range.get_start(), region)?;
+    check_key_in_region(range.get_end(), region)?;
+
+    Ok(())
+}
+
 #[inline]
 pub fn check_store_id(req: &RaftCmdRequest, store_id: u64) -> Result<()> {
     let peer = req.get_header().get_peer();
@@ -918,6 +955,11 @@ impl Engines {
             raft: raft_engine,
         }
     }
+
+    /// Returns the kv engine behind the `KvEngine` trait.
+    pub fn kv_engine(&self) -> RocksEngine {
+        RocksEngine::from_db(Arc::clone(&self.kv))
+    }
 }
 
 pub struct KeysInfoFormatter<'a>(pub &'a [Vec<u8>]);
@@ -1805,4 +1847,48 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_check_sst_for_ingestion() {
+        let mut sst = SSTMeta::new();
+        let mut region = metapb::Region::new();
+
+        // Check uuid and cf name
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.set_uuid(Uuid::new_v4().as_bytes().to_vec());
+        sst.set_cf_name(CF_DEFAULT.to_owned());
+        check_sst_for_ingestion(&sst, &region).unwrap();
+        sst.set_cf_name("test".to_owned());
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.set_cf_name(CF_WRITE.to_owned());
+        check_sst_for_ingestion(&sst, &region).unwrap();
+
+        // Check region id
+        region.set_id(1);
+        sst.set_region_id(2);
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.set_region_id(1);
+        check_sst_for_ingestion(&sst, &region).unwrap();
+
+        // Check region epoch
+        region.mut_region_epoch().set_conf_ver(1);
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.mut_region_epoch().set_conf_ver(1);
+        check_sst_for_ingestion(&sst, &region).unwrap();
+        region.mut_region_epoch().set_version(1);
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.mut_region_epoch().set_version(1);
+        check_sst_for_ingestion(&sst, &region).unwrap();
+
+        // Check region range
+        region.set_start_key(vec![2]);
+        region.set_end_key(vec![8]);
+        sst.mut_range().set_start(vec![1]);
+        sst.mut_range().set_end(vec![8]);
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.mut_range().set_start(vec![2]);
+        assert!(check_sst_for_ingestion(&sst, &region).is_err());
+        sst.mut_range().set_end(vec![7]);
+        check_sst_for_ingestion(&sst, &region).unwrap();
+    }
 }
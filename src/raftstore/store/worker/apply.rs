@@ -14,6 +14,7 @@
 use std::cmp;
 use std::collections::VecDeque;
 use std::fmt::{self, Debug, Display, Formatter};
+use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
@@ -22,7 +23,6 @@ use std::sync::Arc;
 use protobuf::RepeatedField;
 use rocksdb::rocksdb_options::WriteOptions;
 use rocksdb::{Writable, WriteBatch, DB};
-use uuid::Uuid;
 
 use kvproto::import_sstpb::SSTMeta;
 use kvproto::metapb::{Peer as PeerMeta, Region};
@@ -45,11 +45,14 @@ use raftstore::store::peer::Peer;
 use raftstore::store::peer_storage::{
     self, compact_raft_log, write_initial_apply_state, write_peer_state,
 };
+use raftstore::store::profiler::REGION_PROFILER;
 use raftstore::store::util::check_region_epoch;
 use raftstore::store::{cmd_resp, keys, util, Engines, Store};
 use raftstore::{Error, Result};
-use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_RAFT};
 use util::collections::HashMap;
+use util::io_limiter::{self, IOType};
+use util::memory::{self, MemoryConsumer};
 use util::time::{duration_to_sec, Instant, SlowTimer};
 use util::worker::Runnable;
 use util::{escape, rocksdb, MustConsumeVec};
@@ -57,6 +60,8 @@ use util::{escape, rocksdb, MustConsumeVec};
 use super::metrics::*;
 
 const WRITE_BATCH_MAX_KEYS: usize = 128;
+// With multi-batch write, at most `MAX_SUB_WRITE_BATCHES` batches are written together.
+const MAX_SUB_WRITE_BATCHES: usize = 16;
 const DEFAULT_APPLY_WB_SIZE: usize = 4 * 1024;
 const SHRINK_PENDING_CMD_QUEUE_CAP: usize = 64;
 
@@ -238,6 +243,11 @@ struct ApplyContextCore<'a> {
     host: &'a CoprocessorHost,
     importer: &'a SSTImporter,
     wb: Option<WriteBatch>,
+    // The full write batches which are not written yet, they are written together with `wb`
+    // by multi-batch write.
+    sealed_wbs: Vec<WriteBatch>,
+    sealed_bytes: u64,
+    multi_batch_write: bool,
     cbs: MustConsumeVec<ApplyCallback>,
     merged_regions: Vec<u64>,
     apply_res: Vec<ApplyRes>,
@@ -260,6 +270,9 @@ impl<'a> ApplyContextCore<'a> {
             host,
             importer,
             wb: None,
+            sealed_wbs: vec![],
+            sealed_bytes: 0,
+            multi_batch_write: false,
             cbs: MustConsumeVec::new("callback of apply context"),
             merged_regions: vec![],
             apply_res: vec![],
@@ -289,6 +302,11 @@ impl<'a> ApplyContextCore<'a> {
         self
     }
 
+    pub fn multi_batch_write(mut self, enabled: bool) -> ApplyContextCore<'a> {
+        self.multi_batch_write = enabled;
+        self
+    }
+
     /// Prepare for applying entries for `delegate`.
     ///
     /// A general apply progress for a delegate is:
@@ -325,18 +343,52 @@ impl<'a> ApplyContextCore<'a> {
         }
         self.wb_last_bytes = self.wb().data_size() as u64;
         self.wb_last_keys = self.wb().count() as u64;
+        memory::record(
+            MemoryConsumer::ApplyBatch,
+            (self.sealed_bytes + self.wb_last_bytes) as usize,
+        );
+    }
+
+    /// Whether the full write batch can be sealed instead of being written to rocksdb.
+    fn can_seal_wb(&self) -> bool {
+        self.multi_batch_write && self.sealed_wbs.len() + 1 < MAX_SUB_WRITE_BATCHES
+    }
+
+    /// Seal the full write batch, so that it's written together with the following ones, and
+    /// RocksDB can insert them into the memtable concurrently.
+    ///
+    /// This call is valid only when it's between a `prepare_for` and `finish_for`.
+    fn seal_wb(&mut self, delegate: &mut ApplyDelegate) {
+        delegate.update_metrics(self);
+        let wb = mem::replace(self.wb_mut(), WriteBatch::with_capacity(DEFAULT_APPLY_WB_SIZE));
+        self.sealed_bytes += wb.data_size() as u64;
+        self.sealed_wbs.push(wb);
+        self.wb_last_bytes = 0;
+        self.wb_last_keys = 0;
     }
 
     /// Write all the changes into rocksdb.
     pub fn write_to_db(&mut self, engine: &DB) {
+        let mut wbs = mem::replace(&mut self.sealed_wbs, vec![]);
         if self.wb.as_ref().map_or(false, |wb| !wb.is_empty()) {
+            wbs.push(self.wb.take().unwrap());
+        }
+        if !wbs.is_empty() {
+            let bytes = wbs.iter().map(|wb| wb.data_size()).sum();
+            io_limiter::record_io(IOType::ForegroundWrite, bytes);
             let mut write_opts = WriteOptions::new();
             write_opts.set_sync(self.enable_sync_log && self.sync_log_hint);
-            engine
-                .write_opt(self.wb.take().unwrap(), &write_opts)
-                .unwrap_or_else(|e| {
-                    panic!("failed to write to engine: {:?}", e);
-                });
+            let res = if wbs.len() == 1 {
+                engine.write_opt(wbs.pop().unwrap(), &write_opts)
+            } else {
+                APPLY_WRITE_SUB_BATCHES_HISTOGRAM.observe(wbs.len() as f64);
+                engine.multi_batch_write(&wbs, &write_opts)
+            };
+            res.unwrap_or_else(|e| {
+                panic!("failed to write to engine: {:?}", e);
+            });
+            self.sealed_bytes = 0;
+            memory::record(MemoryConsumer::ApplyBatch, 0);
         }
         for cbs in self.cbs.drain(..) {
             cbs.invoke_all(self.host);
@@ -460,6 +512,13 @@ pub fn notify_stale_req(term: u64, cb: Callback) {
 
 /// Check if a write is needed to be issued before handle the command.
 fn should_write_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
+    // When write batch contains more than `recommended` keys, write the batch to engine.
+    wb_keys >= WRITE_BATCH_MAX_KEYS || must_write_to_engine(cmd)
+}
+
+/// Checks if the command needs the changes before it to be written to the engine, because it
+/// reads the engine or modifies the keys covered by the current write batch.
+fn must_write_to_engine(cmd: &RaftCmdRequest) -> bool {
     if cmd.has_admin_request() {
         match cmd.get_admin_request().get_cmd_type() {
             // ComputeHash require an up to date snapshot.
@@ -471,11 +530,6 @@ fn should_write_to_engine(cmd: &RaftCmdRequest, wb_keys: usize) -> bool {
         }
     }
 
-    // When write batch contains more than `recommended` keys, write the batch to engine.
-    if wb_keys >= WRITE_BATCH_MAX_KEYS {
-        return true;
-    }
-
     // Some commands may modify keys covered by the current write batch, so we
     // must write the current write batch to the engine first.
     for req in cmd.get_requests() {
@@ -625,7 +679,12 @@ impl ApplyDelegate {
             let cmd = util::parse_data_at(data, index, &self.tag);
 
             if should_write_to_engine(&cmd, apply_ctx.wb().count()) {
-                apply_ctx.commit(self);
+                if !must_write_to_engine(&cmd) && apply_ctx.can_seal_wb() {
+                    // The batch is just full.
+                    apply_ctx.seal_wb(self);
+                } else {
+                    apply_ctx.commit(self);
+                }
             }
 
             return self.process_raft_cmd(apply_ctx, index, term, cmd);
@@ -886,11 +945,14 @@ impl ApplyDelegate {
         let include_region =
             req.get_header().get_region_epoch().get_version() >= self.last_merge_version;
         check_region_epoch(&req, &self.region, include_region)?;
-        if req.has_admin_request() {
-            self.exec_admin_cmd(ctx, req.get_admin_request())
+        let timer = REGION_PROFILER.start_timer(self.region_id());
+        let (res, stage) = if req.has_admin_request() {
+            (self.exec_admin_cmd(ctx, req.get_admin_request()), "admin")
         } else {
-            self.exec_write_cmd(ctx, req.get_requests())
-        }
+            (self.exec_write_cmd(ctx, req.get_requests()), "write")
+        };
+        REGION_PROFILER.observe(self.region_id(), &["apply", stage], timer);
+        res
     }
 
     fn exec_admin_cmd(
@@ -1580,6 +1642,8 @@ impl ApplyDelegate {
     ) -> Result<(RaftCmdResponse, Option<ExecResult>)> {
         let mut responses = Vec::with_capacity(requests.len());
 
+        self.check_ssts_for_ingestion(ctx, requests)?;
+
         let mut ranges = vec![];
         let mut ssts = vec![];
         for req in requests {
@@ -1786,21 +1850,35 @@ impl ApplyDelegate {
         Ok(resp)
     }
 
+    // All the SST files of a command are checked before any of them is ingested, so that
+    // they are ingested together or not at all.
+    fn check_ssts_for_ingestion(&self, ctx: &ApplyContext, requests: &[Request]) -> Result<()> {
+        let ssts: Vec<_> = requests
+            .iter()
+            .filter(|req| req.get_cmd_type() == CmdType::IngestSST)
+            .map(|req| req.get_ingest_sst().get_sst())
+            .collect();
+        for sst in &ssts {
+            if let Err(e) = util::check_sst_for_ingestion(sst, &self.region) {
+                error!("ingest {:?} to region {:?}: {:?}", sst, self.region, e);
+                // These files are not ingested, we can delete them here.
+                for sst in &ssts {
+                    let _ = ctx.importer.delete(sst);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     fn handle_ingest_sst(
         &mut self,
         ctx: &ApplyContext,
         req: &Request,
         ssts: &mut Vec<SSTMeta>,
     ) -> Result<Response> {
+        // The file has been checked by `check_ssts_for_ingestion`.
         let sst = req.get_ingest_sst().get_sst();
-
-        if let Err(e) = check_sst_for_ingestion(sst, &self.region) {
-            error!("ingest {:?} to region {:?}: {:?}", sst, self.region, e);
-            // This file is not valid, we can delete it here.
-            let _ = ctx.importer.delete(sst);
-            return Err(e);
-        }
-
         ctx.importer
             .ingest(sst, &self.engines.kv)
             .unwrap_or_else(|e| {
@@ -1826,38 +1904,6 @@ pub fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
     Some(req.get_change_peer())
 }
 
-fn check_sst_for_ingestion(sst: &SSTMeta, region: &Region) -> Result<()> {
-    let uuid = sst.get_uuid();
-    if let Err(e) = Uuid::from_bytes(uuid) {
-        return Err(box_err!("invalid uuid {:?}: {:?}", uuid, e));
-    }
-
-    let cf_name = sst.get_cf_name();
-    if cf_name != CF_DEFAULT && cf_name != CF_WRITE {
-        return Err(box_err!("invalid cf name {}", cf_name));
-    }
-
-    let region_id = sst.get_region_id();
-    if region_id != region.get_id() {
-        return Err(Error::RegionNotFound(region_id));
-    }
-
-    let epoch = sst.get_region_epoch();
-    let region_epoch = region.get_region_epoch();
-    if epoch.get_conf_ver() != region_epoch.get_conf_ver()
-        || epoch.get_version() != region_epoch.get_version()
-    {
-        let error = format!("{:?} != {:?}", epoch, region_epoch);
-        return Err(Error::StaleEpoch(error, vec![region.clone()]));
-    }
-
-    let range = sst.get_range();
-    util::check_key_in_region(range.get_start(), region)?;
-    util::check_key_in_region(range.get_end(), region)?;
-
-    Ok(())
-}
-
 // Consistency Check
 impl ApplyDelegate {
     fn exec_compute_hash(
@@ -2048,6 +2094,7 @@ pub struct Runner {
     notifier: Sender<TaskRes>,
     sync_log: bool,
     use_delete_range: bool,
+    multi_batch_write: bool,
     tag: String,
 }
 
@@ -2057,6 +2104,7 @@ impl Runner {
         notifier: Sender<TaskRes>,
         sync_log: bool,
         use_delete_range: bool,
+        multi_batch_write: bool,
     ) -> Runner {
         let mut delegates =
             HashMap::with_capacity_and_hasher(store.get_peers().len(), Default::default());
@@ -2071,6 +2119,7 @@ impl Runner {
             notifier,
             sync_log,
             use_delete_range,
+            multi_batch_write,
             tag: format!("[store {}]", store.store_id()),
         }
     }
@@ -2081,6 +2130,7 @@ impl Runner {
         let mut core = ApplyContextCore::new(self.host.as_ref(), self.importer.as_ref())
             .apply_res_capacity(applys.len())
             .use_delete_range(self.use_delete_range)
+            .multi_batch_write(self.multi_batch_write)
             .enable_sync_log(self.sync_log);
         for apply in applys {
             if apply.entries.is_empty() || core.merged_regions.contains(&apply.region_id) {
@@ -2238,12 +2288,14 @@ mod tests {
     use raftstore::store::msg::WriteResponse;
     use raftstore::store::peer_storage::RAFT_INIT_LOG_INDEX;
     use raftstore::store::util::{new_learner_peer, new_peer};
-    use rocksdb::{Writable, WriteBatch, DB};
+    use rocksdb::{ColumnFamilyOptions, DBOptions, Writable, WriteBatch, DB};
+    use storage::CF_WRITE;
     use tempdir::TempDir;
 
     use super::*;
     use import::test_helpers::*;
     use util::collections::HashMap;
+    use util::rocksdb::CFOptions;
 
     pub fn create_tmp_engine(path: &str) -> (TempDir, Engines) {
         let path = TempDir::new(path).unwrap();
@@ -2277,6 +2329,7 @@ mod tests {
             sync_log: false,
             tag: "".to_owned(),
             use_delete_range: true,
+            multi_batch_write: false,
         }
     }
 
@@ -2820,9 +2873,35 @@ mod tests {
         assert_eq!(delegate.applied_index_term, 3);
         assert_eq!(delegate.apply_state.get_applied_index(), 11);
 
+        // Files ingested by one command are checked together.
+        let sst_range = (100, 105);
+        let (mut meta3, data3) = gen_sst_file(&sst_path, sst_range);
+        meta3.set_region_epoch(delegate.region.get_region_epoch().clone());
+        let mut file3 = importer.create(&meta3).unwrap();
+        file3.append(&data3).unwrap();
+        file3.finish().unwrap();
+        let (mut meta4, data4) = gen_sst_file(&sst_path, sst_range);
+        meta4.mut_region_epoch().set_version(1234);
+        let mut file4 = importer.create(&meta4).unwrap();
+        file4.append(&data4).unwrap();
+        file4.finish().unwrap();
+        let ingest_paired = EntryBuilder::new(12, 3)
+            .capture_resp(&mut delegate, tx.clone())
+            .ingest_sst(&meta3)
+            .ingest_sst(&meta4)
+            .epoch(0, 3)
+            .build();
+        delegate.handle_raft_committed_entries(&mut apply_ctx, vec![ingest_paired]);
+        apply_ctx.write_to_db(&engines.kv);
+        let resp = rx.try_recv().unwrap();
+        assert!(resp.get_header().has_error());
+        assert!(engines.kv.get(&keys::data_key(&[102])).unwrap().is_none());
+        assert!(importer.list_ssts().unwrap().is_empty());
+        assert_eq!(delegate.apply_state.get_applied_index(), 12);
+
         let mut entries = vec![];
         for i in 0..WRITE_BATCH_MAX_KEYS {
-            let put_entry = EntryBuilder::new(i as u64 + 12, 3)
+            let put_entry = EntryBuilder::new(i as u64 + 13, 3)
                 .put(b"k", b"v")
                 .epoch(1, 3)
                 .capture_resp(&mut delegate, tx.clone())
@@ -2834,54 +2913,69 @@ mod tests {
         for _ in 0..WRITE_BATCH_MAX_KEYS {
             rx.try_recv().unwrap();
         }
-        let index = WRITE_BATCH_MAX_KEYS + 11;
+        let index = WRITE_BATCH_MAX_KEYS + 12;
         assert_eq!(delegate.apply_state.get_applied_index(), index as u64);
         assert_eq!(obs.pre_query_count.load(Ordering::SeqCst), index);
         assert_eq!(obs.post_query_count.load(Ordering::SeqCst), index);
     }
 
     #[test]
-    fn test_check_sst_for_ingestion() {
-        let mut sst = SSTMeta::new();
-        let mut region = Region::new();
-
-        // Check uuid and cf name
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.set_uuid(Uuid::new_v4().as_bytes().to_vec());
-        sst.set_cf_name(CF_DEFAULT.to_owned());
-        check_sst_for_ingestion(&sst, &region).unwrap();
-        sst.set_cf_name("test".to_owned());
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.set_cf_name(CF_WRITE.to_owned());
-        check_sst_for_ingestion(&sst, &region).unwrap();
-
-        // Check region id
-        region.set_id(1);
-        sst.set_region_id(2);
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.set_region_id(1);
-        check_sst_for_ingestion(&sst, &region).unwrap();
-
-        // Check region epoch
-        region.mut_region_epoch().set_conf_ver(1);
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.mut_region_epoch().set_conf_ver(1);
-        check_sst_for_ingestion(&sst, &region).unwrap();
-        region.mut_region_epoch().set_version(1);
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.mut_region_epoch().set_version(1);
-        check_sst_for_ingestion(&sst, &region).unwrap();
-
-        // Check region range
-        region.set_start_key(vec![2]);
-        region.set_end_key(vec![8]);
-        sst.mut_range().set_start(vec![1]);
-        sst.mut_range().set_end(vec![8]);
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.mut_range().set_start(vec![2]);
-        assert!(check_sst_for_ingestion(&sst, &region).is_err());
-        sst.mut_range().set_end(vec![7]);
-        check_sst_for_ingestion(&sst, &region).unwrap();
+    fn test_multi_batch_write() {
+        let path = TempDir::new("test-multi-batch-write").unwrap();
+        let mut db_opts = DBOptions::new();
+        db_opts.enable_pipelined_write(false);
+        db_opts.enable_multi_batch_write(true);
+        let cfs_opts = ALL_CFS
+            .iter()
+            .map(|cf| CFOptions::new(*cf, ColumnFamilyOptions::new()))
+            .collect();
+        let db =
+            rocksdb::new_engine_opt(path.path().to_str().unwrap(), db_opts, cfs_opts).unwrap();
+        let (_raft_path, raft_engines) = create_tmp_engine("test-multi-batch-write-raft");
+        let engines = Engines::new(Arc::new(db), raft_engines.raft);
+        let (_import_dir, importer) = create_tmp_importer("test-multi-batch-write");
+        let mut reg = Registration::default();
+        reg.region.set_end_key(b"k5".to_vec());
+        reg.region.mut_region_epoch().set_version(3);
+        let mut delegate = ApplyDelegate::from_registration(engines.clone(), reg);
+        let mut delegates = HashMap::default();
+        let (tx, rx) = mpsc::channel();
+
+        let host = CoprocessorHost::default();
+        let mut core = ApplyContextCore::new(&host, &importer).multi_batch_write(true);
+        let mut apply_ctx = ApplyContext::new(&mut core, &mut delegates);
+        let count = WRITE_BATCH_MAX_KEYS * 3;
+        let entries = (0..count)
+            .map(|i| {
+                EntryBuilder::new(i as u64 + 1, 1)
+                    .put(b"k1", i.to_string().as_bytes())
+                    .epoch(1, 3)
+                    .capture_resp(&mut delegate, tx.clone())
+                    .build()
+            })
+            .collect();
+        delegate.handle_raft_committed_entries(&mut apply_ctx, entries);
+        // The full batches are sealed instead of being written.
+        assert_eq!(apply_ctx.sealed_wbs.len(), 2);
+        assert!(engines.kv.get(&keys::data_key(b"k1")).unwrap().is_none());
+        assert!(rx.try_recv().is_err());
+
+        apply_ctx.write_to_db(&engines.kv);
+        assert!(apply_ctx.sealed_wbs.is_empty());
+        for _ in 0..count {
+            assert!(!rx.try_recv().unwrap().get_header().has_error());
+        }
+        let value = (count - 1).to_string();
+        assert_eq!(
+            engines.kv.get(&keys::data_key(b"k1")).unwrap().unwrap(),
+            value.as_bytes()
+        );
+        let state: RaftApplyState = engines
+            .kv
+            .get_msg_cf(CF_RAFT, &keys::apply_state_key(delegate.region_id()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.get_applied_index(), count as u64);
     }
 
     #[test]
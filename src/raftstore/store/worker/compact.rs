@@ -25,7 +25,7 @@ use util::rocksdb::compact_range;
 use util::rocksdb::stats::get_range_entries_and_versions;
 use util::worker::Runnable;
 
-use super::metrics::COMPACT_RANGE_CF;
+use super::metrics::{CHECK_COMPACT_RANGES_COUNTER_VEC, COMPACT_RANGE_CF};
 
 type Key = Vec<u8>;
 
@@ -214,12 +214,25 @@ fn collect_ranges_need_compact(
         if let Some((num_ent, num_ver)) =
             get_range_entries_and_versions(engine, cf, &range[0], &range[1])
         {
+            CHECK_COMPACT_RANGES_COUNTER_VEC
+                .with_label_values(&["checked"])
+                .inc();
             if need_compact(
                 num_ent,
                 num_ver,
                 tombstones_num_threshold,
                 tombstones_percent_threshold,
             ) {
+                CHECK_COMPACT_RANGES_COUNTER_VEC
+                    .with_label_values(&["need_compact"])
+                    .inc();
+                debug!(
+                    "range ({}, {}) needs compacting, entries: {}, versions: {}",
+                    escape(&range[0]),
+                    escape(&range[1]),
+                    num_ent,
+                    num_ver
+                );
                 if compact_start.is_none() {
                     // The previous range doesn't need compacting.
                     compact_start = Some(range[0].clone());
@@ -228,6 +241,11 @@ fn collect_ranges_need_compact(
                 // Move to next range.
                 continue;
             }
+        } else {
+            // Ranges without MVCC properties, e.g. with no SST files at all, are skipped.
+            CHECK_COMPACT_RANGES_COUNTER_VEC
+                .with_label_values(&["no_properties"])
+                .inc();
         }
 
         // Current range doesn't need compacting, save previous range that need compacting.
@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{exponential_buckets, Gauge, Histogram, HistogramVec, IntCounterVec};
+use prometheus::{
+    exponential_buckets, linear_buckets, Gauge, Histogram, HistogramVec, IntCounterVec,
+};
 
 lazy_static! {
     pub static ref SNAP_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
@@ -40,6 +42,11 @@ lazy_static! {
         "Bucketed histogram of compact range for cf execution",
         &["cf"]
     ).unwrap();
+    pub static ref CHECK_COMPACT_RANGES_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_raftstore_check_compact_ranges_total",
+        "Total number of ranges checked for the tombstones by the compact check.",
+        &["type"]
+    ).unwrap();
     pub static ref REGION_HASH_HISTOGRAM: Histogram = register_histogram!(
         "tikv_raftstore_hash_duration_seconds",
         "Bucketed histogram of raftstore hash compution duration"
@@ -49,6 +56,11 @@ lazy_static! {
         "Proposal count of all regions in a mio tick",
         exponential_buckets(1.0, 2.0, 20).unwrap()
     ).unwrap();
+    pub static ref APPLY_WRITE_SUB_BATCHES_HISTOGRAM: Histogram = register_histogram!(
+        "tikv_raftstore_apply_write_sub_batches",
+        "Number of the write batches written together by multi-batch write",
+        linear_buckets(2.0, 2.0, 8).unwrap()
+    ).unwrap();
     pub static ref STALE_PEER_PENDING_DELETE_RANGE_GAUGE: Gauge = register_gauge!(
         "tikv_pending_delete_ranges_of_stale_peer",
         "Total number of tikv pending delete range of stale peer"
@@ -13,7 +13,6 @@
 
 use std::cell::RefCell;
 use std::fmt::{self, Display, Formatter};
-use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use kvproto::errorpb;
@@ -21,11 +20,12 @@ use kvproto::metapb;
 use kvproto::raft_cmdpb::{CmdType, RaftCmdRequest, RaftCmdResponse};
 use mio;
 use prometheus::local::LocalHistogram;
-use rocksdb::DB;
 use time::Timespec;
 
+use engine::RocksEngine;
 use raftstore::errors::RAFTSTORE_IS_BUSY;
 use raftstore::store::msg::Callback;
+use raftstore::store::profiler::REGION_PROFILER;
 use raftstore::store::util::{self, LeaseState, RemoteLease};
 use raftstore::store::Store;
 use raftstore::store::{
@@ -103,7 +103,10 @@ impl ReadDelegate {
                 {
                     // Cache snapshot_time for remaining requests in the same batch.
                     *last_valid_ts = snapshot_time;
+                    let region_id = self.region.get_id();
+                    let timer = REGION_PROFILER.start_timer(region_id);
                     let mut resp = executor.execute(req, &self.region);
+                    REGION_PROFILER.observe(region_id, &["read", "local_reader"], timer);
                     // Leader can read local if and only if it is in lease.
                     cmd_resp::bind_term(&mut resp.response, term);
                     return Some(resp);
@@ -249,7 +252,7 @@ fn handle_busy(cmd: StoreMsg) {
 
 pub struct LocalReader<C: Sender<StoreMsg>> {
     store_id: u64,
-    kv_engine: Arc<DB>,
+    kv_engine: RocksEngine,
     metrics: RefCell<ReadMetrics>,
     // region id -> ReadDelegate
     delegates: HashMap<u64, ReadDelegate>,
@@ -274,7 +277,7 @@ impl LocalReader<mio::Sender<StoreMsg>> {
         LocalReader {
             delegates,
             store_id,
-            kv_engine: store.kv_engine(),
+            kv_engine: RocksEngine::from_db(store.kv_engine()),
             ch: store.get_sendch().into_inner(),
             metrics: Default::default(),
             tag: format!("[store {}]", store_id),
@@ -603,6 +606,7 @@ impl ReadMetrics {
 #[cfg(test)]
 mod tests {
     use std::sync::mpsc::*;
+    use std::sync::Arc;
     use std::thread;
 
     use kvproto::raft_cmdpb::*;
@@ -631,7 +635,7 @@ mod tests {
         let reader = LocalReader {
             store_id,
             ch,
-            kv_engine: Arc::new(db),
+            kv_engine: RocksEngine::from_db(Arc::new(db)),
             delegates: HashMap::default(),
             metrics: Default::default(),
             tag: "foo".to_owned(),
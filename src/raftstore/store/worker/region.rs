@@ -348,6 +348,9 @@ impl SnapContext {
             }
             Err(Error::Abort) => {
                 warn!("applying snapshot for region {} is aborted.", region_id);
+                // The peer is going to be destroyed, register the partially applied data for
+                // deletion now instead of leaving it to the destroy task.
+                self.cleanup_aborted_apply(region_id);
                 assert_eq!(
                     status.swap(JOB_STATUS_CANCELLED, Ordering::SeqCst),
                     JOB_STATUS_CANCELLING
@@ -366,6 +369,30 @@ impl SnapContext {
         timer.observe_duration();
     }
 
+    /// Deletes the data written by an aborted snapshot apply. Like the data of a destroyed
+    /// peer, it's deleted after `clean_stale_peer_delay`.
+    fn cleanup_aborted_apply(&mut self, region_id: u64) {
+        let region_key = keys::region_state_key(region_id);
+        let region_state: RegionLocalState =
+            match self.engines.kv.get_msg_cf(CF_RAFT, &region_key) {
+                Ok(Some(state)) => state,
+                Ok(None) => return,
+                Err(e) => {
+                    error!(
+                        "[region {}] failed to get region_state for cleanup: {:?}",
+                        region_id, e
+                    );
+                    return;
+                }
+            };
+        let region = region_state.get_region();
+        let start_key = keys::enc_start_key(region);
+        let end_key = keys::enc_end_key(region);
+        if !self.insert_pending_delete_range(region_id, &start_key, &end_key) {
+            self.cleanup_range(region_id, &start_key, &end_key, false);
+        }
+    }
+
     fn cleanup_range(
         &self,
         region_id: u64,
@@ -425,7 +452,17 @@ impl SnapContext {
             return false;
         }
 
-        self.cleanup_overlap_ranges(start_key, end_key);
+        let overlap_ranges = self
+            .pending_delete_ranges
+            .drain_overlap_ranges(start_key, end_key);
+        for (id, s_key, e_key) in overlap_ranges {
+            // The range registered by an aborted snapshot apply is registered again when the
+            // peer is destroyed, it will be deleted along with the new range.
+            if id == region_id && s_key.as_slice() >= start_key && e_key.as_slice() <= end_key {
+                continue;
+            }
+            self.cleanup_range(id, &s_key, &e_key, false);
+        }
 
         info!(
             "[region {}] register deleting data in [{}, {})",
@@ -552,11 +589,23 @@ impl RunnableWithTimer<Task, ()> for Runner {
 #[cfg(test)]
 mod test {
     use std::thread;
+    use std::path::Path;
+    use std::sync::Arc;
     use std::time::Duration;
 
+    use kvproto::metapb::Region;
+    use kvproto::raft_serverpb::RegionLocalState;
+    use rocksdb::Writable;
+    use tempdir::TempDir;
+
+    use raftstore::store::engine::{Mutable, Peekable};
+    use raftstore::store::util::Engines;
+    use raftstore::store::{keys, SnapManager};
+    use storage::{ALL_CFS, CF_DEFAULT, CF_RAFT};
+    use util::rocksdb;
     use util::time;
 
-    use super::PendingDeleteRanges;
+    use super::{PendingDeleteRanges, SnapContext};
 
     fn insert_range(
         pending_delete_ranges: &mut PendingDeleteRanges,
@@ -631,4 +680,68 @@ mod test {
         }
         assert_eq!(pending_delete_ranges.len(), 0);
     }
+
+    fn new_snap_context(path: &TempDir, clean_stale_peer_delay: Duration) -> SnapContext {
+        let kv_path = path.path().join(Path::new("kv"));
+        let raft_path = path.path().join(Path::new("raft"));
+        let engines = Engines::new(
+            Arc::new(rocksdb::new_engine(kv_path.to_str().unwrap(), ALL_CFS, None).unwrap()),
+            Arc::new(
+                rocksdb::new_engine(raft_path.to_str().unwrap(), &[CF_DEFAULT], None).unwrap(),
+            ),
+        );
+        let snap_path = path.path().join(Path::new("snap"));
+        SnapContext {
+            engines,
+            batch_size: 1024,
+            mgr: SnapManager::new(snap_path.to_str().unwrap(), None),
+            use_delete_range: false,
+            clean_stale_peer_delay,
+            pending_delete_ranges: PendingDeleteRanges::default(),
+        }
+    }
+
+    fn put_region_data(ctx: &SnapContext, region_id: u64) {
+        let mut region = Region::new();
+        region.set_id(region_id);
+        region.set_start_key(b"a".to_vec());
+        region.set_end_key(b"c".to_vec());
+        let mut region_state = RegionLocalState::new();
+        region_state.set_region(region);
+        let kv = &ctx.engines.kv;
+        let handle = rocksdb::get_cf_handle(kv, CF_RAFT).unwrap();
+        kv.put_msg_cf(handle, &keys::region_state_key(region_id), &region_state)
+            .unwrap();
+        kv.put(&keys::data_key(b"b"), b"v").unwrap();
+    }
+
+    #[test]
+    fn test_cleanup_aborted_apply() {
+        let path = TempDir::new("test-cleanup-aborted-apply").unwrap();
+        let mut ctx = new_snap_context(&path, Duration::from_secs(3600));
+        put_region_data(&ctx, 1);
+        let (start_key, end_key) = (keys::data_key(b"a"), keys::data_key(b"c"));
+
+        // The data is kept until the delay has passed.
+        ctx.cleanup_aborted_apply(1);
+        assert_eq!(ctx.pending_delete_ranges.len(), 1);
+        let data_key = keys::data_key(b"b");
+        assert!(ctx.engines.kv.get_value(&data_key).unwrap().is_some());
+        // Destroying the peer afterwards doesn't delete it either.
+        assert!(ctx.insert_pending_delete_range(1, &start_key, &end_key));
+        assert_eq!(ctx.pending_delete_ranges.len(), 1);
+        assert!(ctx.engines.kv.get_value(&data_key).unwrap().is_some());
+        // But the range of another region is deleted to make room for the new one.
+        assert!(ctx.insert_pending_delete_range(2, &start_key, &end_key));
+        assert_eq!(ctx.pending_delete_ranges.len(), 1);
+        assert!(ctx.engines.kv.get_value(&data_key).unwrap().is_none());
+
+        // The data is deleted right away without a delay.
+        let path = TempDir::new("test-cleanup-aborted-apply").unwrap();
+        let mut ctx = new_snap_context(&path, Duration::from_secs(0));
+        put_region_data(&ctx, 1);
+        ctx.cleanup_aborted_apply(1);
+        assert_eq!(ctx.pending_delete_ranges.len(), 0);
+        assert!(ctx.engines.kv.get_value(&data_key).unwrap().is_none());
+    }
 }
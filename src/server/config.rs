@@ -13,6 +13,7 @@
 
 use std::i32;
 
+use super::quota_limiter::{SourceQuota, ALL_SOURCES};
 use super::Result;
 use grpc::CompressionAlgorithms;
 
@@ -26,6 +27,7 @@ pub use storage::Config as StorageConfig;
 pub const DEFAULT_CLUSTER_ID: u64 = 0;
 pub const DEFAULT_LISTENING_ADDR: &str = "127.0.0.1:20160";
 const DEFAULT_ADVERTISE_LISTENING_ADDR: &str = "";
+const DEFAULT_STATUS_ADDR: &str = "127.0.0.1:20180";
 const DEFAULT_GRPC_CONCURRENCY: usize = 4;
 const DEFAULT_GRPC_CONCURRENT_STREAM: i32 = 1024;
 const DEFAULT_GRPC_RAFT_CONN_NUM: usize = 10;
@@ -49,6 +51,24 @@ pub enum GrpcCompressionType {
     Gzip,
 }
 
+impl GrpcCompressionType {
+    pub fn algorithm(&self) -> CompressionAlgorithms {
+        match *self {
+            GrpcCompressionType::None => CompressionAlgorithms::None,
+            GrpcCompressionType::Deflate => CompressionAlgorithms::Deflate,
+            GrpcCompressionType::Gzip => CompressionAlgorithms::Gzip,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            GrpcCompressionType::None => "none",
+            GrpcCompressionType::Deflate => "deflate",
+            GrpcCompressionType::Gzip => "gzip",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -63,8 +83,28 @@ pub struct Config {
     // If not set, we will use listening address instead.
     pub advertise_addr: String,
 
+    // HTTP status server listening address, the status server is disabled if it's empty.
+    pub status_addr: String,
+
+    // The token the requests to the status server should carry in the
+    // `Authorization: Bearer <token>` header, except for `/status`. Empty disables it.
+    // It's never serialized, so it's neither logged nor served by `/config`.
+    #[serde(skip_serializing)]
+    pub status_token: String,
+
+    // Requires the clients of the status server to present a certificate signed by the CA
+    // in `security`. It only applies if TLS is enabled there.
+    pub status_verify_client_cert: bool,
+
+    // Debug service listening address. If it's set, the debug service is served on it
+    // instead of `addr`, so that it can be firewalled separately.
+    pub debug_addr: String,
+
     // TODO: use CompressionAlgorithms instead once it supports traits like Clone etc.
+    /// The compression of the raft and snapshot messages sent to other stores.
     pub grpc_compression_type: GrpcCompressionType,
+    /// The compression of the responses of the kv service and other services of the server.
+    pub grpc_server_compression_type: GrpcCompressionType,
     pub grpc_concurrency: usize,
     pub grpc_concurrent_stream: i32,
     pub grpc_raft_conn_num: usize,
@@ -80,12 +120,38 @@ pub struct Config {
     pub end_point_batch_row_limit: usize,
     pub end_point_stream_batch_row_limit: usize,
     pub end_point_request_max_handle_duration: ReadableDuration,
+    /// How many streaming sessions are remembered to trim the overlapped ranges of retries.
+    /// 0 disables the trimming.
+    pub end_point_stream_dedup_capacity: usize,
+    /// How many results of unary requests are cached. 0 disables the cache.
+    pub end_point_result_cache_capacity: usize,
+    /// Results larger than it are not cached.
+    pub end_point_result_cache_max_size: ReadableSize,
+    /// The limits of the samples and count-min sketches of analyze requests.
+    pub end_point_analyze_max_sample_size: usize,
+    pub end_point_analyze_max_cmsketch_depth: usize,
+    pub end_point_analyze_max_cmsketch_width: usize,
+    /// The IO limits of checksum requests of the store and of each request, and how many of
+    /// them can run concurrently. 0 means unlimited.
+    pub end_point_checksum_max_bytes_per_sec: ReadableSize,
+    pub end_point_checksum_request_max_bytes_per_sec: ReadableSize,
+    pub end_point_checksum_max_concurrency: usize,
+    /// The memory quotas of the executor buffers of each request and of all requests.
+    /// 0 means unlimited.
+    pub end_point_request_memory_quota: ReadableSize,
+    pub end_point_memory_quota: ReadableSize,
     pub snap_max_write_bytes_per_sec: ReadableSize,
     pub snap_max_total_size: ReadableSize,
+    /// Whether the debugger can read the latest committed values ignoring locks. It's UNSAFE
+    /// and only for diagnosing.
+    pub enable_debug_dirty_read: bool,
 
     // Server labels to specify some attributes about this server.
     pub labels: HashMap<String, String>,
 
+    /// The CPU time and bandwidth quotas of the request sources, see `server::quota_limiter`.
+    pub source_quotas: HashMap<String, SourceQuota>,
+
     // deprecated. use readpool.coprocessor.xx_concurrency.
     #[doc(hidden)]
     #[serde(skip_serializing)]
@@ -108,8 +174,14 @@ impl Default for Config {
             cluster_id: DEFAULT_CLUSTER_ID,
             addr: DEFAULT_LISTENING_ADDR.to_owned(),
             labels: HashMap::default(),
+            source_quotas: HashMap::default(),
             advertise_addr: DEFAULT_ADVERTISE_LISTENING_ADDR.to_owned(),
+            status_addr: DEFAULT_STATUS_ADDR.to_owned(),
+            status_token: String::new(),
+            status_verify_client_cert: false,
+            debug_addr: String::new(),
             grpc_compression_type: GrpcCompressionType::None,
+            grpc_server_compression_type: GrpcCompressionType::None,
             grpc_concurrency: DEFAULT_GRPC_CONCURRENCY,
             grpc_concurrent_stream: DEFAULT_GRPC_CONCURRENT_STREAM,
             grpc_raft_conn_num: DEFAULT_GRPC_RAFT_CONN_NUM,
@@ -130,8 +202,20 @@ impl Default for Config {
             end_point_request_max_handle_duration: ReadableDuration::secs(
                 DEFAULT_ENDPOINT_REQUEST_MAX_HANDLE_SECS,
             ),
+            end_point_stream_dedup_capacity: 0,
+            end_point_result_cache_capacity: 0,
+            end_point_result_cache_max_size: ReadableSize::kb(64),
+            end_point_analyze_max_sample_size: 100_000,
+            end_point_analyze_max_cmsketch_depth: 16,
+            end_point_analyze_max_cmsketch_width: 32_768,
+            end_point_checksum_max_bytes_per_sec: ReadableSize(0),
+            end_point_checksum_request_max_bytes_per_sec: ReadableSize(0),
+            end_point_checksum_max_concurrency: 0,
+            end_point_request_memory_quota: ReadableSize(0),
+            end_point_memory_quota: ReadableSize(0),
             snap_max_write_bytes_per_sec: ReadableSize(DEFAULT_SNAP_MAX_BYTES_PER_SEC),
             snap_max_total_size: ReadableSize(0),
+            enable_debug_dirty_read: false,
         }
     }
 }
@@ -151,6 +235,24 @@ impl Config {
                 self.advertise_addr
             ));
         }
+        if !self.status_addr.is_empty() {
+            box_try!(config::check_addr(&self.status_addr));
+            if self.status_addr == self.addr {
+                return Err(box_err!(
+                    "status-addr can't be the same as addr: {:?}",
+                    self.status_addr
+                ));
+            }
+        }
+        if !self.debug_addr.is_empty() {
+            box_try!(config::check_addr(&self.debug_addr));
+            if self.debug_addr == self.addr || self.debug_addr == self.status_addr {
+                return Err(box_err!(
+                    "debug-addr can't be the same as addr or status-addr: {:?}",
+                    self.debug_addr
+                ));
+            }
+        }
 
         let non_zero_entries = vec![
             (
@@ -191,15 +293,25 @@ impl Config {
             validate_label(v, "value")?;
         }
 
+        for source in self.source_quotas.keys() {
+            if !ALL_SOURCES.contains(&source.as_str()) {
+                return Err(box_err!(
+                    "unknown source {:?} of server.source-quotas, expect one of {:?}",
+                    source,
+                    ALL_SOURCES
+                ));
+            }
+        }
+
         Ok(())
     }
 
     pub fn grpc_compression_algorithm(&self) -> CompressionAlgorithms {
-        match self.grpc_compression_type {
-            GrpcCompressionType::None => CompressionAlgorithms::None,
-            GrpcCompressionType::Deflate => CompressionAlgorithms::Deflate,
-            GrpcCompressionType::Gzip => CompressionAlgorithms::Gzip,
-        }
+        self.grpc_compression_type.algorithm()
+    }
+
+    pub fn grpc_server_compression_algorithm(&self) -> CompressionAlgorithms {
+        self.grpc_server_compression_type.algorithm()
     }
 }
 
@@ -268,6 +380,20 @@ mod tests {
         invalid_cfg.advertise_addr = "127.0.0.1:1000".to_owned();
         invalid_cfg.validate().unwrap();
 
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.status_addr = cfg.addr.clone();
+        assert!(invalid_cfg.validate().is_err());
+        invalid_cfg.status_addr = "".to_owned();
+        invalid_cfg.validate().unwrap();
+
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.debug_addr = cfg.addr.clone();
+        assert!(invalid_cfg.validate().is_err());
+        invalid_cfg.debug_addr = cfg.status_addr.clone();
+        assert!(invalid_cfg.validate().is_err());
+        invalid_cfg.debug_addr = "127.0.0.1:20170".to_owned();
+        invalid_cfg.validate().unwrap();
+
         let mut invalid_cfg = cfg.clone();
         invalid_cfg.grpc_stream_initial_window_size = ReadableSize(i32::MAX as u64 + 1);
         assert!(invalid_cfg.validate().is_err());
@@ -17,12 +17,12 @@ use std::iter::FromIterator;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::{error, result};
+use std::{error, result, u64};
 
 use protobuf::{self, Message, RepeatedField};
 
 use kvproto::debugpb::{self, DB as DBType, *};
-use kvproto::kvrpcpb::{MvccInfo, MvccLock, MvccValue, MvccWrite, Op};
+use kvproto::kvrpcpb::{IsolationLevel, MvccInfo, MvccLock, MvccValue, MvccWrite, Op};
 use kvproto::metapb::Region;
 use kvproto::raft_serverpb::*;
 use raft::eraftpb::Entry;
@@ -38,16 +38,22 @@ use raftstore::store::{
     init_apply_state, init_raft_state, write_initial_apply_state, write_initial_raft_state,
     write_peer_state,
 };
-use raftstore::store::{keys, CacheQueryStats, Engines, Iterable, Peekable, PeerStorage};
+use raftstore::store::{
+    keys, CacheQueryStats, Engines, Iterable, Peekable, PeerStorage, RegionSnapshot,
+};
 use storage::mvcc::{Lock, LockType, Write, WriteType};
+use storage::txn::SnapshotStore;
 use storage::types::Key;
-use storage::{CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
+use storage::{ScanMode, CF_DEFAULT, CF_LOCK, CF_RAFT, CF_WRITE};
 use util::codec::bytes;
 use util::collections::HashSet;
 use util::config::ReadableSize;
 use util::escape;
+use util::io_limiter::IOLimiter;
 use util::properties::MvccProperties;
-use util::rocksdb::get_cf_handle;
+use util::rocksdb::{
+    compact_bottommost_files_in_range_cf, get_cf_handle, get_cf_sst_sizes, SstSizes,
+};
 use util::worker::Worker;
 
 pub type Result<T> = result::Result<T, Error>;
@@ -136,11 +142,21 @@ impl Into<debugpb::BottommostLevelCompaction> for BottommostLevelCompaction {
 #[derive(Clone)]
 pub struct Debugger {
     engines: Engines,
+    /// Whether `dirty_read` is allowed.
+    enable_dirty_read: bool,
 }
 
 impl Debugger {
     pub fn new(engines: Engines) -> Debugger {
-        Debugger { engines }
+        Debugger {
+            engines,
+            enable_dirty_read: false,
+        }
+    }
+
+    /// Set whether `dirty_read` is allowed. It should follow `server.enable-debug-dirty-read`.
+    pub fn set_enable_dirty_read(&mut self, enable: bool) {
+        self.enable_dirty_read = enable;
     }
 
     pub fn get_engine(&self) -> &Engines {
@@ -186,6 +202,105 @@ impl Debugger {
         }
     }
 
+    /// Read the latest committed value of `key` in the region, where `key` is a data key
+    /// without ts, e.g. "z" + an encoded key.
+    ///
+    /// It's UNSAFE and only used for diagnosing: locks are ignored and no read ts is used, so
+    /// the result may be inconsistent with any snapshot of the transactional layer. It's only
+    /// served if the region is normal, contains the key and has a voter on this store, so that
+    /// data of a region being applied, destroyed or only learned isn't returned.
+    pub fn dirty_read(&self, region_id: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.enable_dirty_read {
+            return Err(Error::InvalidArgument(
+                "dirty read is disabled, set server.enable-debug-dirty-read to enable it"
+                    .to_owned(),
+            ));
+        }
+        if !keys::validate_data_key(key) {
+            return Err(Error::InvalidArgument(format!(
+                "key {} is not a data key",
+                escape(key)
+            )));
+        }
+        self.check_dirty_read_region(region_id, key)?;
+
+        let db = &self.engines.kv;
+        // Timestamps are encoded in descending order, so the latest version comes first.
+        let start = Key::from_encoded_slice(key).append_ts(u64::MAX);
+        let end = Key::from_encoded_slice(key).append_ts(0);
+        let mut latest = None;
+        box_try!(db.scan_cf(
+            CF_WRITE,
+            start.as_encoded(),
+            end.as_encoded(),
+            false,
+            |_, value| {
+                let write = Write::parse(value).map_err(|e| box_err!(e))?;
+                match write.write_type {
+                    WriteType::Put | WriteType::Delete => {
+                        latest = Some(write);
+                        Ok(false)
+                    }
+                    WriteType::Lock | WriteType::Rollback => Ok(true),
+                }
+            }
+        ));
+
+        match latest {
+            Some(Write {
+                write_type: WriteType::Put,
+                start_ts,
+                short_value,
+            }) => {
+                if short_value.is_some() {
+                    return Ok(short_value);
+                }
+                let default_key = Key::from_encoded_slice(key).append_ts(start_ts);
+                match box_try!(db.get_value_cf(CF_DEFAULT, default_key.as_encoded())) {
+                    Some(v) => Ok(Some(v.to_vec())),
+                    None => Err(box_err!(
+                        "default value of key {} at {} is missing",
+                        escape(key),
+                        start_ts
+                    )),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn check_dirty_read_region(&self, region_id: u64, key: &[u8]) -> Result<()> {
+        let region_state = self.get_region_state(region_id)?;
+        if region_state.get_state() != PeerState::Normal {
+            return Err(Error::InvalidArgument(format!(
+                "region {} is in state {:?}",
+                region_id,
+                region_state.get_state()
+            )));
+        }
+        let region = region_state.get_region();
+        let store_id = self.get_store_id()?;
+        match region.get_peers().iter().find(|p| p.get_store_id() == store_id) {
+            Some(peer) if !peer.get_is_learner() => {}
+            Some(_) => {
+                return Err(Error::InvalidArgument(format!(
+                    "peer of region {} on store {} is a learner",
+                    region_id, store_id
+                )))
+            }
+            None => {
+                return Err(Error::NotFound(format!(
+                    "peer of region {} on store {}",
+                    region_id, store_id
+                )))
+            }
+        }
+        if let Err(e) = raftstore_util::check_key_in_region(keys::origin_key(key), region) {
+            return Err(Error::InvalidArgument(format!("{:?}", e)));
+        }
+        Ok(())
+    }
+
     pub fn raft_log(&self, region_id: u64, log_index: u64) -> Result<Entry> {
         let key = keys::raft_log_key(region_id, log_index);
         match self.engines.raft.get_msg(&key) {
@@ -299,6 +414,31 @@ impl Debugger {
         Ok(())
     }
 
+    /// Compact only the bottommost level of the cf[start..end) in the db, to reclaim the
+    /// space of the versions removed by GC without a full manual compaction.
+    pub fn compact_bottommost(&self, db: DBType, cf: &str, start: &[u8], end: &[u8]) -> Result<()> {
+        validate_db_and_cf(db, cf)?;
+        let db = self.get_db_from_type(db)?;
+        let start = if start.is_empty() { None } else { Some(start) };
+        let end = if end.is_empty() { None } else { Some(end) };
+        info!("Debugger starts bottommost compact on {:?}.{}", db, cf);
+        box_try!(compact_bottommost_files_in_range_cf(db, cf, start, end));
+        info!("Debugger finishs bottommost compact on {:?}.{}", db, cf);
+        Ok(())
+    }
+
+    /// Get the sizes of the live SST files of every cf in the db, which tell the space
+    /// amplification.
+    pub fn get_sst_sizes(&self, db: DBType) -> Result<Vec<(String, SstSizes)>> {
+        let db = self.get_db_from_type(db)?;
+        let mut res = Vec::new();
+        for cf in db.cf_names() {
+            let handle = box_try!(get_cf_handle(db, cf));
+            res.push((cf.to_owned(), get_cf_sst_sizes(db, handle)));
+        }
+        Ok(res)
+    }
+
     /// Set regions to tombstone by manual, and apply other status(such as
     /// peers, version, and key range) from `region` which comes from PD normally.
     pub fn set_region_tombstone(&self, regions: Vec<Region>) -> Result<Vec<(u64, Error)>> {
@@ -389,7 +529,7 @@ impl Debugger {
                 })?;
 
             let raft_state = box_try!(init_raft_state(&self.engines.raft, region));
-            let apply_state = box_try!(init_apply_state(&self.engines.kv, region));
+            let apply_state = box_try!(init_apply_state(&self.engines.kv_engine(), region));
             if raft_state.get_last_index() < apply_state.get_applied_index() {
                 return Err(Error::Other("last index < applied index".into()));
             }
@@ -506,19 +646,13 @@ impl Debugger {
         Ok(())
     }
 
-    pub fn recreate_region(&self, region: Region) -> Result<()> {
-        let region_id = region.get_id();
-        let kv = self.engines.kv.as_ref();
-        let raft = self.engines.raft.as_ref();
-
-        let kv_wb = WriteBatch::new();
-        let raft_wb = WriteBatch::new();
-        let kv_handle = box_try!(get_cf_handle(kv, CF_RAFT));
-
-        if region.get_start_key() >= region.get_end_key() && !region.get_end_key().is_empty() {
-            return Err(box_err!("Bad region: {:?}", region));
-        }
-
+    /// Get the regions known by the store which would lose the quorum if the stores were
+    /// down, so that it can be checked before taking several stores down for maintenance.
+    /// Only the regions having a peer on this store are known, the caller should check all
+    /// the stores.
+    pub fn regions_losing_quorum(&self, store_ids: &[u64]) -> Result<Vec<Region>> {
+        let store_ids = HashSet::<u64>::from_iter(store_ids.iter().cloned());
+        let mut res = Vec::new();
         box_try!(self.engines.kv.scan_cf(
             CF_RAFT,
             keys::REGION_META_MIN_KEY,
@@ -529,27 +663,39 @@ impl Debugger {
                 if suffix_type != keys::REGION_STATE_SUFFIX {
                     return Ok(true);
                 }
-
                 let mut region_state = RegionLocalState::new();
                 box_try!(region_state.merge_from_bytes(value));
                 if region_state.get_state() == PeerState::Tombstone {
                     return Ok(true);
                 }
-                let exists_region = region_state.get_region();
-
-                if !region_overlap(exists_region, &region) {
-                    return Ok(true);
+                let peers = region_state.get_region().get_peers();
+                let voters = peers.iter().filter(|p| !p.get_is_learner());
+                let (mut total, mut alive) = (0, 0);
+                for peer in voters {
+                    total += 1;
+                    if !store_ids.contains(&peer.get_store_id()) {
+                        alive += 1;
+                    }
                 }
-
-                if exists_region.get_start_key() == region.get_start_key()
-                    && exists_region.get_end_key() == region.get_end_key()
-                {
-                    Err(box_err!("region still exists {:?}", region))
-                } else {
-                    Err(box_err!("region overlap with {:?}", exists_region))
+                if alive * 2 <= total {
+                    res.push(region_state.take_region());
                 }
-            },
+                Ok(true)
+            }
         ));
+        Ok(res)
+    }
+
+    pub fn recreate_region(&self, region: Region) -> Result<()> {
+        let region_id = region.get_id();
+        let kv = self.engines.kv.as_ref();
+        let raft = self.engines.raft.as_ref();
+
+        let kv_wb = WriteBatch::new();
+        let raft_wb = WriteBatch::new();
+        let kv_handle = box_try!(get_cf_handle(kv, CF_RAFT));
+
+        self.check_region_overlap(&region)?;
 
         // RegionLocalState.
         let mut region_state = RegionLocalState::new();
@@ -584,6 +730,110 @@ impl Debugger {
         Ok(())
     }
 
+    /// Rewrites the `RegionLocalState` of an existing region. The region must not overlap
+    /// with the other regions of the store.
+    pub fn set_region_state(&self, region_id: u64, region_state: RegionLocalState) -> Result<()> {
+        let kv = self.engines.kv.as_ref();
+        let region = region_state.get_region();
+        if region.get_id() != region_id {
+            return Err(Error::InvalidArgument(format!(
+                "region id mismatch: {} != {}",
+                region.get_id(),
+                region_id
+            )));
+        }
+        let key = keys::region_state_key(region_id);
+        if box_try!(kv.get_msg_cf::<RegionLocalState>(CF_RAFT, &key)).is_none() {
+            return Err(Error::NotFound(format!("region state of {}", region_id)));
+        }
+        if region_state.get_state() != PeerState::Tombstone {
+            self.check_region_overlap(region)?;
+        }
+
+        let handle = box_try!(get_cf_handle(kv, CF_RAFT));
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(true);
+        let wb = WriteBatch::new();
+        box_try!(wb.put_msg_cf(handle, &key, &region_state));
+        box_try!(kv.write_opt(wb, &write_opts));
+        Ok(())
+    }
+
+    /// Rewrites the `RaftApplyState` of an existing region. The applied index must be between
+    /// the truncated index and the last index of the raft log.
+    pub fn set_apply_state(&self, region_id: u64, apply_state: RaftApplyState) -> Result<()> {
+        let kv = self.engines.kv.as_ref();
+        let key = keys::apply_state_key(region_id);
+        if box_try!(kv.get_msg_cf::<RaftApplyState>(CF_RAFT, &key)).is_none() {
+            return Err(Error::NotFound(format!("apply state of {}", region_id)));
+        }
+        let raft_state = match box_try!(
+            self.engines
+                .raft
+                .get_msg::<RaftLocalState>(&keys::raft_state_key(region_id))
+        ) {
+            Some(state) => state,
+            None => return Err(Error::NotFound(format!("raft state of {}", region_id))),
+        };
+        let applied_index = apply_state.get_applied_index();
+        let truncated_index = apply_state.get_truncated_state().get_index();
+        if applied_index < truncated_index || applied_index > raft_state.get_last_index() {
+            return Err(Error::InvalidArgument(format!(
+                "applied index {} should be in [{}, {}]",
+                applied_index,
+                truncated_index,
+                raft_state.get_last_index()
+            )));
+        }
+
+        let handle = box_try!(get_cf_handle(kv, CF_RAFT));
+        let mut write_opts = WriteOptions::new();
+        write_opts.set_sync(true);
+        let wb = WriteBatch::new();
+        box_try!(wb.put_msg_cf(handle, &key, &apply_state));
+        box_try!(kv.write_opt(wb, &write_opts));
+        Ok(())
+    }
+
+    fn check_region_overlap(&self, region: &Region) -> Result<()> {
+        if region.get_start_key() >= region.get_end_key() && !region.get_end_key().is_empty() {
+            return Err(box_err!("Bad region: {:?}", region));
+        }
+
+        box_try!(self.engines.kv.scan_cf(
+            CF_RAFT,
+            keys::REGION_META_MIN_KEY,
+            keys::REGION_META_MAX_KEY,
+            false,
+            |key, value| {
+                let (region_id, suffix_type) = box_try!(keys::decode_region_meta_key(key));
+                if suffix_type != keys::REGION_STATE_SUFFIX || region_id == region.get_id() {
+                    return Ok(true);
+                }
+
+                let mut region_state = RegionLocalState::new();
+                box_try!(region_state.merge_from_bytes(value));
+                if region_state.get_state() == PeerState::Tombstone {
+                    return Ok(true);
+                }
+                let exists_region = region_state.get_region();
+
+                if !region_overlap(exists_region, region) {
+                    return Ok(true);
+                }
+
+                if exists_region.get_start_key() == region.get_start_key()
+                    && exists_region.get_end_key() == region.get_end_key()
+                {
+                    Err(box_err!("region still exists {:?}", region))
+                } else {
+                    Err(box_err!("region overlap with {:?}", exists_region))
+                }
+            },
+        ));
+        Ok(())
+    }
+
     pub fn get_store_id(&self) -> Result<u64> {
         let db = &self.engines.kv;
         db.get_msg::<StoreIdent>(keys::STORE_IDENT_KEY)
@@ -653,6 +903,78 @@ impl Debugger {
         }
     }
 
+    /// Exports at most `limit` committed key-value pairs of the region at `ts`, starting from
+    /// the encoded key `cursor`, or the start of the region if `cursor` is empty.
+    ///
+    /// Returns the raw key-value pairs and the cursor to resume from, which is `None` if all data
+    /// of the region has been exported. The read flow is limited by `limiter` if given.
+    pub fn export_region(
+        &self,
+        region_id: u64,
+        ts: u64,
+        cursor: &[u8],
+        limit: usize,
+        limiter: Option<&IOLimiter>,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>)> {
+        if limit == 0 {
+            return Err(Error::InvalidArgument("limit should be greater than 0".to_owned()));
+        }
+        let region_state = self.get_region_state(region_id)?;
+        if region_state.get_state() == PeerState::Tombstone {
+            return Err(Error::NotFound(format!("region {} is tombstone", region_id)));
+        }
+        let snap = RegionSnapshot::from_raw(
+            Arc::clone(&self.engines.kv),
+            region_state.get_region().clone(),
+        );
+        let store = SnapshotStore::new(snap, ts, IsolationLevel::SI, false);
+        let lower_bound = if cursor.is_empty() {
+            None
+        } else {
+            Some(Key::from_encoded(cursor.to_vec()))
+        };
+        let mut scanner = box_try!(store.scanner(ScanMode::Forward, false, lower_bound, None));
+
+        let mut pairs = Vec::with_capacity(limit);
+        let mut last_key = None;
+        let mut finished = false;
+        // The bytes read but not requested from the limiter yet. They are requested in chunks
+        // no larger than what the limiter can grant at a time.
+        let mut unlimited_bytes = 0;
+        while pairs.len() < limit {
+            let (key, value) = match box_try!(scanner.next()) {
+                Some(pair) => pair,
+                None => {
+                    finished = true;
+                    break;
+                }
+            };
+            let raw_key = box_try!(key.to_raw());
+            if let Some(limiter) = limiter {
+                unlimited_bytes += (raw_key.len() + value.len()) as i64;
+                let chunk = limiter.get_max_bytes_per_time();
+                while unlimited_bytes >= chunk {
+                    limiter.request(chunk);
+                    unlimited_bytes -= chunk;
+                }
+            }
+            pairs.push((raw_key, value));
+            last_key = Some(key);
+        }
+        if let Some(limiter) = limiter {
+            if unlimited_bytes > 0 {
+                limiter.request(unlimited_bytes);
+            }
+        }
+        if finished {
+            return Ok((pairs, None));
+        }
+        // The smallest encoded key greater than the last exported one.
+        let mut next = last_key.unwrap().into_encoded();
+        next.push(0);
+        Ok((pairs, Some(next)))
+    }
+
     pub fn get_region_properties(&self, region_id: u64) -> Result<Vec<(String, String)>> {
         let region_state = self.get_region_state(region_id)?;
         let region = region_state.get_region();
@@ -686,6 +1008,7 @@ impl Debugger {
             ("mvcc.max_ts", mvcc_properties.max_ts),
             ("mvcc.num_rows", mvcc_properties.num_rows),
             ("mvcc.num_puts", mvcc_properties.num_puts),
+            ("mvcc.num_deletes", mvcc_properties.num_deletes),
             ("mvcc.num_versions", mvcc_properties.num_versions),
             ("mvcc.max_row_versions", mvcc_properties.max_row_versions),
         ].iter()
@@ -1487,6 +1810,129 @@ mod tests {
         assert!(debugger.scan_mvcc(b"z", b"x", 3).is_err());
     }
 
+    #[test]
+    fn test_dirty_read() {
+        let mut debugger = new_debugger();
+        debugger.set_store_id(11);
+        let key = |k: &[u8]| keys::data_key(Key::from_raw(k).as_encoded());
+        assert!(debugger.dirty_read(1, &key(b"k1")).is_err());
+        debugger.set_enable_dirty_read(true);
+        assert!(debugger.dirty_read(1, b"k1").is_err());
+        // The region doesn't exist.
+        assert!(debugger.dirty_read(1, &key(b"k1")).is_err());
+
+        let engine = &debugger.engines.kv;
+        init_region_state(engine, 1, &[11]);
+        // The region has no peer on this store.
+        init_region_state(engine, 2, &[12]);
+        assert!(debugger.dirty_read(2, &key(b"k1")).is_err());
+        // The region is tombstone, the peer is a learner or the key is out of the region.
+        let cf_raft = engine.cf_handle(CF_RAFT).unwrap();
+        for region_id in 3..6 {
+            init_region_state(engine, region_id, &[11]);
+            let mut region_state = get_region_state(engine, region_id);
+            match region_id {
+                3 => region_state.set_state(PeerState::Tombstone),
+                4 => region_state.mut_region().mut_peers()[0].set_is_learner(true),
+                _ => region_state
+                    .mut_region()
+                    .set_end_key(Key::from_raw(b"k1").into_encoded()),
+            }
+            let state_key = keys::region_state_key(region_id);
+            engine
+                .put_msg_cf(cf_raft, &state_key, &region_state)
+                .unwrap();
+            assert!(debugger.dirty_read(region_id, &key(b"k1")).is_err());
+        }
+
+        let write_cf = engine.cf_handle(CF_WRITE).unwrap();
+        let cf_write_data = vec![
+            (b"k1", WriteType::Put, 5, 10, Some(b"v1".to_vec())),
+            (b"k1", WriteType::Rollback, 15, 15, None),
+            (b"k2", WriteType::Put, 5, 10, None),
+            (b"k2", WriteType::Lock, 15, 20, None),
+            (b"k3", WriteType::Put, 5, 10, Some(b"v3".to_vec())),
+            (b"k3", WriteType::Delete, 15, 20, None),
+        ];
+        for (k, tp, start_ts, commit_ts, short_value) in cf_write_data {
+            let write = Write::new(tp, start_ts, short_value);
+            let encoded_key = Key::from_raw(k).append_ts(commit_ts);
+            let write_key = keys::data_key(encoded_key.as_encoded());
+            engine
+                .put_cf(write_cf, &write_key, &write.to_bytes())
+                .unwrap();
+        }
+        let default_key = keys::data_key(Key::from_raw(b"k2").append_ts(5).as_encoded());
+        engine.put(&default_key, b"v2").unwrap();
+        // Locks are ignored.
+        let lock_cf = engine.cf_handle(CF_LOCK).unwrap();
+        let lock = Lock::new(LockType::Put, b"k1".to_vec(), 25, 0, None);
+        engine
+            .put_cf(lock_cf, &key(b"k1"), &lock.to_bytes())
+            .unwrap();
+
+        assert_eq!(debugger.dirty_read(1, &key(b"k1")).unwrap().unwrap(), b"v1");
+        assert_eq!(debugger.dirty_read(1, &key(b"k2")).unwrap().unwrap(), b"v2");
+        assert_eq!(debugger.dirty_read(1, &key(b"k3")).unwrap(), None);
+        assert_eq!(debugger.dirty_read(1, &key(b"k4")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_region() {
+        let debugger = new_debugger();
+        let engine = debugger.engines.kv.as_ref();
+        assert!(debugger.export_region(1, 100, b"", 10, None).is_err());
+        init_region_state(engine, 1, &[11]);
+
+        let write_cf = engine.cf_handle(CF_WRITE).unwrap();
+        for (i, k) in [b"k1", b"k2", b"k3", b"k4"].iter().enumerate() {
+            let commit_ts = if i == 3 { 200 } else { 10 };
+            let write = Write::new(WriteType::Put, 5, Some(k.to_vec()));
+            let write_key = keys::data_key(Key::from_raw(*k).append_ts(commit_ts).as_encoded());
+            engine
+                .put_cf(write_cf, &write_key, &write.to_bytes())
+                .unwrap();
+        }
+
+        let limiter = IOLimiter::new(1024 * 1024);
+        let (pairs, cursor) = debugger
+            .export_region(1, 100, b"", 2, Some(&limiter))
+            .unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"k1".to_vec(), b"k1".to_vec()),
+                (b"k2".to_vec(), b"k2".to_vec()),
+            ]
+        );
+        // Small pairs are requested from the limiter together.
+        assert_eq!(limiter.get_total_bytes_through(), 8);
+        assert_eq!(limiter.get_total_requests(), 1);
+        // k4 is committed after the export ts.
+        let (pairs, cursor) = debugger
+            .export_region(1, 100, &cursor.unwrap(), 2, None)
+            .unwrap();
+        assert_eq!(pairs, vec![(b"k3".to_vec(), b"k3".to_vec())]);
+        assert!(cursor.is_none());
+
+        // A large pair is requested in chunks.
+        let write = Write::new(WriteType::Put, 5, None);
+        let write_key = keys::data_key(Key::from_raw(b"k0").append_ts(10).as_encoded());
+        engine
+            .put_cf(write_cf, &write_key, &write.to_bytes())
+            .unwrap();
+        let value = vec![b'v'; limiter.get_max_bytes_per_time() as usize * 2];
+        let default_key = keys::data_key(Key::from_raw(b"k0").append_ts(5).as_encoded());
+        engine.put(&default_key, &value).unwrap();
+        let limiter = IOLimiter::new(1024 * 1024);
+        let (pairs, _) = debugger
+            .export_region(1, 100, b"", 1, Some(&limiter))
+            .unwrap();
+        assert_eq!(pairs, vec![(b"k0".to_vec(), value.clone())]);
+        assert_eq!(limiter.get_total_bytes_through(), (value.len() + 2) as i64);
+        assert_eq!(limiter.get_total_requests(), 3);
+    }
+
     #[test]
     fn test_tombstone_regions() {
         let debugger = new_debugger();
@@ -1576,6 +2022,30 @@ mod tests {
         debugger.remove_failed_stores(vec![100], None).unwrap_err();
     }
 
+    #[test]
+    fn test_regions_losing_quorum() {
+        let debugger = new_debugger();
+        let engine = debugger.engines.kv.as_ref();
+
+        init_region_state(engine, 1, &[11, 12, 13]);
+        init_region_state(engine, 2, &[11, 21, 22, 23]);
+        init_region_state(engine, 3, &[31]);
+
+        let region_ids = |stores: &[u64]| {
+            debugger
+                .regions_losing_quorum(stores)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.get_id())
+                .collect::<Vec<_>>()
+        };
+        assert!(region_ids(&[]).is_empty());
+        assert!(region_ids(&[11]).is_empty());
+        assert_eq!(region_ids(&[11, 12]), vec![1]);
+        assert_eq!(region_ids(&[11, 21]), vec![2]);
+        assert_eq!(region_ids(&[12, 31]), vec![3]);
+    }
+
     #[test]
     fn test_bad_regions() {
         let debugger = new_debugger();
@@ -1719,6 +2189,65 @@ mod tests {
         assert!(debugger.recreate_region(region).is_err());
     }
 
+    #[test]
+    fn test_set_region_and_apply_state() {
+        let debugger = new_debugger();
+        let engine = debugger.engines.kv.as_ref();
+        let cf_raft = engine.cf_handle(CF_RAFT).unwrap();
+
+        for (region_id, (start, end)) in vec![(1, ("", "m")), (2, ("m", ""))] {
+            let mut region = Region::new();
+            region.set_id(region_id);
+            region.set_start_key(start.as_bytes().to_vec());
+            region.set_end_key(end.as_bytes().to_vec());
+            let mut region_state = RegionLocalState::new();
+            region_state.set_state(PeerState::Normal);
+            region_state.set_region(region);
+            let key = keys::region_state_key(region_id);
+            engine.put_msg_cf(cf_raft, &key, &region_state).unwrap();
+        }
+
+        let mut region_state = get_region_state(engine, 1);
+        region_state.mut_region().set_end_key(b"z".to_vec());
+        // Overlaps with region 2.
+        assert!(debugger.set_region_state(1, region_state.clone()).is_err());
+        // Mismatched region id.
+        region_state.mut_region().set_end_key(b"k".to_vec());
+        assert!(debugger.set_region_state(2, region_state.clone()).is_err());
+        // No such region.
+        region_state.mut_region().set_id(3);
+        assert!(debugger.set_region_state(3, region_state.clone()).is_err());
+        region_state.mut_region().set_id(1);
+        debugger.set_region_state(1, region_state.clone()).unwrap();
+        assert_eq!(get_region_state(engine, 1), region_state);
+
+        let mut apply_state = RaftApplyState::new();
+        apply_state.set_applied_index(5);
+        apply_state.mut_truncated_state().set_index(5);
+        // No apply state yet.
+        assert!(debugger.set_apply_state(1, apply_state.clone()).is_err());
+        let key = keys::apply_state_key(1);
+        engine
+            .put_msg_cf(cf_raft, &key, &RaftApplyState::new())
+            .unwrap();
+        let mut raft_state = RaftLocalState::new();
+        raft_state.set_last_index(10);
+        debugger
+            .engines
+            .raft
+            .put_msg(&keys::raft_state_key(1), &raft_state)
+            .unwrap();
+        debugger.set_apply_state(1, apply_state.clone()).unwrap();
+        let state: RaftApplyState = engine.get_msg_cf(CF_RAFT, &key).unwrap().unwrap();
+        assert_eq!(state, apply_state);
+        // Applied index beyond the last index.
+        apply_state.set_applied_index(11);
+        assert!(debugger.set_apply_state(1, apply_state.clone()).is_err());
+        // Applied index behind the truncated index.
+        apply_state.set_applied_index(4);
+        assert!(debugger.set_apply_state(1, apply_state).is_err());
+    }
+
     #[test]
     fn test_mvcc_checker() {
         let (mut default, mut lock, mut write) = (vec![], vec![], vec![]);
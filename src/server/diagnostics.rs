@@ -0,0 +1,237 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use fs2;
+use regex::Regex;
+use sys_info;
+use url::form_urlencoded;
+
+use util::logger;
+
+use super::Result;
+
+// The format of the timestamps of the log lines, see `util::logger`.
+const LOG_TIME_FORMAT: &str = "%Y/%m/%d %H:%M:%S%.3f";
+const LOG_TIME_LEN: usize = 23;
+// The format of the time range of the searches.
+const SEARCH_TIME_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+const DEFAULT_SEARCH_LIMIT: usize = 1000;
+
+/// `LogSearch` filters the log lines by time range, level and pattern.
+#[derive(Debug, Default)]
+pub struct LogSearch {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+    /// The short names of the levels, like "WARN", empty means all levels.
+    pub levels: Vec<&'static str>,
+    pub pattern: Option<Regex>,
+    pub limit: usize,
+}
+
+impl LogSearch {
+    /// Parses the search from a URL query like
+    /// `start=2018/10/01 00:00:00&end=...&level=warn,error&pattern=region 2&limit=100`.
+    pub fn from_query(query: &str) -> Result<LogSearch> {
+        let mut search = LogSearch {
+            limit: DEFAULT_SEARCH_LIMIT,
+            ..Default::default()
+        };
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*key {
+                "start" => {
+                    search.start =
+                        Some(box_try!(NaiveDateTime::parse_from_str(&value, SEARCH_TIME_FORMAT)))
+                }
+                "end" => {
+                    search.end =
+                        Some(box_try!(NaiveDateTime::parse_from_str(&value, SEARCH_TIME_FORMAT)))
+                }
+                "level" => {
+                    for level in value.split(',') {
+                        match logger::get_level_by_string(level) {
+                            Some(l) => search.levels.push(l.as_short_str()),
+                            None => return Err(box_err!("invalid level {:?}", level)),
+                        }
+                    }
+                }
+                "pattern" => search.pattern = Some(box_try!(Regex::new(&value))),
+                "limit" => search.limit = box_try!(value.parse()),
+                _ => return Err(box_err!("unknown parameter {:?}", key)),
+            }
+        }
+        Ok(search)
+    }
+
+    // Returns `None` if the line has no timestamp, which happens when a message has several
+    // lines.
+    fn matches_header(&self, line: &str) -> Option<bool> {
+        if line.len() < LOG_TIME_LEN || !line.is_char_boundary(LOG_TIME_LEN) {
+            return None;
+        }
+        let time = NaiveDateTime::parse_from_str(&line[..LOG_TIME_LEN], LOG_TIME_FORMAT).ok()?;
+        if self.start.map_or(false, |start| time < start)
+            || self.end.map_or(false, |end| time >= end)
+        {
+            return Some(false);
+        }
+        if !self.levels.is_empty() {
+            let level = line[LOG_TIME_LEN..].split_whitespace().next().unwrap_or("");
+            if !self.levels.contains(&level) {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    fn matches(&self, line: &str, header_matched: bool) -> bool {
+        header_matched && self.pattern.as_ref().map_or(true, |p| p.is_match(line))
+    }
+}
+
+/// Searches `log_file` and its rotated files for the lines matching `search`, in the order
+/// they were written.
+pub fn search_log(log_file: &str, search: &LogSearch) -> Result<Vec<String>> {
+    let path = Path::new(log_file);
+    let (dir, name) = match (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        (Some(dir), Some(name)) => (dir, name.to_owned()),
+        _ => return Err(box_err!("invalid log file {:?}", log_file)),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    // The rotated files are named like "tikv.log.2018-10-01-00:00:00", so they are sorted
+    // by time, and the current file is read last.
+    let rotated_prefix = format!("{}.", name);
+    let mut rotated = vec![];
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with(&rotated_prefix) {
+            rotated.push(file_name);
+        }
+    }
+    rotated.sort();
+    rotated.push(name);
+
+    let mut lines = vec![];
+    for file_name in rotated {
+        let reader = BufReader::new(File::open(dir.join(file_name))?);
+        let mut header_matched = false;
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(matched) = search.matches_header(&line) {
+                header_matched = matched;
+            }
+            if search.matches(&line, header_matched) {
+                lines.push(line);
+                if lines.len() >= search.limit {
+                    return Ok(lines);
+                }
+            }
+        }
+    }
+    Ok(lines)
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SystemInfo {
+    pub hostname: String,
+    pub cpu_num: u32,
+    pub load_avg: Vec<f64>,
+    pub mem_total: u64,
+    pub mem_available: u64,
+    pub data_dir_capacity: u64,
+    pub data_dir_available: u64,
+}
+
+/// Collects the hardware, load and disk information of the host. The memory sizes are in
+/// bytes.
+pub fn system_info(data_dir: &str) -> Result<SystemInfo> {
+    let load = box_try!(sys_info::loadavg());
+    let mem = box_try!(sys_info::mem_info());
+    let disk = fs2::statvfs(data_dir)?;
+    Ok(SystemInfo {
+        hostname: sys_info::hostname().unwrap_or_default(),
+        cpu_num: box_try!(sys_info::cpu_num()),
+        load_avg: vec![load.one, load.five, load.fifteen],
+        mem_total: mem.total * 1024,
+        mem_available: mem.avail * 1024,
+        data_dir_capacity: disk.total_space(),
+        data_dir_available: disk.available_space(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_search_log() {
+        let dir = TempDir::new("test_search_log").unwrap();
+        let log_file = dir.path().join("tikv.log");
+        let rotated_file = dir.path().join("tikv.log.2018-10-01-00:00:00");
+        let mut f = File::create(&rotated_file).unwrap();
+        writeln!(f, "2018/10/01 00:00:00.000 INFO a.rs:1: [region 1] start").unwrap();
+        writeln!(f, "2018/10/01 00:00:01.000 WARN a.rs:2: [region 2] slow").unwrap();
+        let mut f = File::create(&log_file).unwrap();
+        writeln!(f, "2018/10/02 00:00:00.000 ERRO b.rs:1: [region 2] panic").unwrap();
+        writeln!(f, "backtrace of region 2").unwrap();
+        writeln!(f, "2018/10/02 00:00:01.000 INFO b.rs:2: [region 1] stop").unwrap();
+        let log_file = log_file.to_str().unwrap();
+
+        let search = LogSearch::from_query("").unwrap();
+        assert_eq!(search_log(log_file, &search).unwrap().len(), 5);
+
+        let search = LogSearch::from_query("level=warn,error").unwrap();
+        let lines = search_log(log_file, &search).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("slow"));
+        assert_eq!(lines[2], "backtrace of region 2");
+
+        let search = LogSearch::from_query("pattern=region+1&start=2018%2F10%2F02+00:00:00")
+            .unwrap();
+        let lines = search_log(log_file, &search).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("stop"));
+
+        let search = LogSearch::from_query("end=2018/10/02 00:00:00&limit=1").unwrap();
+        let lines = search_log(log_file, &search).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("start"));
+
+        assert!(LogSearch::from_query("level=unknown").is_err());
+        assert!(LogSearch::from_query("start=yesterday").is_err());
+        assert!(LogSearch::from_query("foo=bar").is_err());
+    }
+
+    #[test]
+    fn test_system_info() {
+        let dir = TempDir::new("test_system_info").unwrap();
+        let info = system_info(dir.path().to_str().unwrap()).unwrap();
+        assert!(info.cpu_num > 0);
+        assert_eq!(info.load_avg.len(), 3);
+        assert!(info.mem_total >= info.mem_available);
+        assert!(info.data_dir_capacity >= info.data_dir_available);
+    }
+}
@@ -75,6 +75,16 @@ lazy_static! {
         "Total number of handle grpc message failure",
         &["type"]
     ).unwrap();
+    pub static ref GRPC_CLIENT_MSG_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_grpc_client_msg_total",
+        "Total number of grpc messages of each client",
+        &["client", "type"]
+    ).unwrap();
+    pub static ref GRPC_CLIENT_MSG_DURATION: CounterVec = register_counter_vec!(
+        "tikv_grpc_client_msg_duration_seconds_total",
+        "Total seconds spent on the grpc messages of each client",
+        &["client"]
+    ).unwrap();
     pub static ref RAFT_MESSAGE_RECV_COUNTER: IntCounter = register_int_counter!(
         "tikv_server_raft_message_recv_total",
         "Total number of raft messages received"
@@ -93,4 +103,9 @@ lazy_static! {
         "tikv_server_raft_message_flush_total",
         "Total number of raft messages flushed"
     ).unwrap();
+    pub static ref RAFT_MESSAGE_RAW_BYTES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_server_raft_message_raw_bytes",
+        "Total bytes of raft messages sent before compression",
+        &["compression"]
+    ).unwrap();
 }
@@ -14,15 +14,19 @@
 mod metrics;
 mod raft_client;
 mod service;
+mod tls;
 
 pub mod config;
 pub mod debug;
+pub mod diagnostics;
 pub mod errors;
 pub mod node;
+pub mod quota_limiter;
 pub mod readpool;
 pub mod resolve;
 pub mod server;
 pub mod snap;
+pub mod status_server;
 pub mod transport;
 
 pub use self::config::{Config, DEFAULT_CLUSTER_ID, DEFAULT_LISTENING_ADDR};
@@ -31,4 +35,5 @@ pub use self::node::{create_raft_storage, Node};
 pub use self::raft_client::RaftClient;
 pub use self::resolve::{PdStoreAddrResolver, StoreAddrResolver};
 pub use self::server::Server;
+pub use self::status_server::StatusServer;
 pub use self::transport::{ServerRaftStoreRouter, ServerTransport};
@@ -159,11 +159,19 @@ where
         }
 
         self.store.set_id(store_id);
-        self.check_prepare_bootstrap_cluster(&engines)?;
-        if !bootstrapped {
-            // cluster is not bootstrapped, and we choose first store to bootstrap
-            // prepare bootstrap.
-            let region = self.prepare_bootstrap_cluster(&engines, store_id)?;
+        if bootstrapped {
+            self.check_prepare_bootstrap_cluster(&engines)?;
+        } else {
+            // cluster is not bootstrapped, and we choose first store to bootstrap.
+            // The first region prepared by a previous attempt acts as a fencing token,
+            // so that all attempts of this store propose the same first region.
+            let region = match self.load_prepared_region(&engines)? {
+                Some(region) => {
+                    info!("resume bootstrap with prepared first region {:?}", region);
+                    region
+                }
+                None => self.prepare_bootstrap_cluster(&engines, store_id)?,
+            };
             self.bootstrap_cluster(&engines, region)?;
         }
 
@@ -253,15 +261,33 @@ where
         Ok(region)
     }
 
-    fn check_prepare_bootstrap_cluster(&self, engines: &Engines) -> Result<()> {
-        let res = engines
+    // Load the first region prepared by a previous bootstrap attempt, if any.
+    fn load_prepared_region(&self, engines: &Engines) -> Result<Option<metapb::Region>> {
+        let region = match engines
             .kv
-            .get_msg::<metapb::Region>(keys::PREPARE_BOOTSTRAP_KEY)?;
-        if res.is_none() {
-            return Ok(());
+            .get_msg::<metapb::Region>(keys::PREPARE_BOOTSTRAP_KEY)?
+        {
+            Some(region) => region,
+            None => return Ok(None),
+        };
+        let store_id = self.store.get_id();
+        if region.get_peers().len() != 1 || region.get_peers()[0].get_store_id() != store_id {
+            return Err(box_err!(
+                "prepared first region {:?} doesn't belong to store {}",
+                region,
+                store_id
+            ));
         }
+        Ok(Some(region))
+    }
 
-        let first_region = res.unwrap();
+    // Recover from a partial bootstrap: keep the prepared first region if PD has accepted it,
+    // otherwise the cluster is bootstrapped by another store and the prepared data is removed.
+    fn check_prepare_bootstrap_cluster(&self, engines: &Engines) -> Result<()> {
+        let first_region = match self.load_prepared_region(engines)? {
+            Some(region) => region,
+            None => return Ok(()),
+        };
         for _ in 0..MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT {
             match self.pd_client.get_region(b"") {
                 Ok(region) => {
@@ -286,21 +312,30 @@ where
     }
 
     fn bootstrap_cluster(&mut self, engines: &Engines, region: metapb::Region) -> Result<()> {
-        let region_id = region.get_id();
-        match self.pd_client.bootstrap_cluster(self.store.clone(), region) {
-            Err(PdError::ClusterBootstrapped(_)) => {
-                error!("cluster {} is already bootstrapped", self.cluster_id);
-                store::clear_prepare_bootstrap(engines, region_id)?;
-                Ok(())
-            }
-            // TODO: should we clean region for other errors too?
-            Err(e) => panic!("bootstrap cluster {} err: {:?}", self.cluster_id, e),
-            Ok(_) => {
-                store::clear_prepare_bootstrap_state(engines)?;
-                info!("bootstrap cluster {} ok", self.cluster_id);
-                Ok(())
+        for _ in 0..MAX_CHECK_CLUSTER_BOOTSTRAPPED_RETRY_COUNT {
+            match self
+                .pd_client
+                .bootstrap_cluster(self.store.clone(), region.clone())
+            {
+                Ok(_) => {
+                    store::clear_prepare_bootstrap_state(engines)?;
+                    info!("bootstrap cluster {} ok", self.cluster_id);
+                    return Ok(());
+                }
+                Err(PdError::ClusterBootstrapped(_)) => {
+                    // The cluster may be bootstrapped by another store, or by this store
+                    // in a previous request whose response is lost.
+                    warn!("cluster {} is already bootstrapped", self.cluster_id);
+                    return self.check_prepare_bootstrap_cluster(engines);
+                }
+                // The request may have been accepted by PD, keep the prepared data and retry.
+                Err(e) => warn!("bootstrap cluster {} err: {:?}", self.cluster_id, e),
             }
+            thread::sleep(Duration::from_secs(
+                CHECK_CLUSTER_BOOTSTRAPPED_RETRY_SECONDS,
+            ));
         }
+        Err(box_err!("bootstrap cluster {} failed", self.cluster_id))
     }
 
     fn check_cluster_bootstrapped(&self) -> Result<bool> {
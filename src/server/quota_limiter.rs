@@ -0,0 +1,244 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use kvproto::kvrpcpb::CommandPri;
+use prometheus::IntCounterVec;
+
+use util::collections::HashMap;
+use util::config::{ReadableDuration, ReadableSize};
+use util::time::duration_to_nanos;
+
+/// Requests with normal or high priority.
+pub const SOURCE_FOREGROUND: &str = "foreground";
+/// Requests with low priority.
+pub const SOURCE_BACKGROUND: &str = "background";
+/// Coprocessor analyze requests.
+pub const SOURCE_ANALYZE: &str = "analyze";
+/// Coprocessor checksum requests, which are sent by the import and backup tools.
+pub const SOURCE_CHECKSUM: &str = "checksum";
+
+pub const ALL_SOURCES: &[&str] = &[
+    SOURCE_FOREGROUND,
+    SOURCE_BACKGROUND,
+    SOURCE_ANALYZE,
+    SOURCE_CHECKSUM,
+];
+
+/// Gets the source of a request from its priority, since the clients don't tag the requests.
+pub fn source_of_priority(priority: CommandPri) -> &'static str {
+    match priority {
+        CommandPri::Low => SOURCE_BACKGROUND,
+        CommandPri::Normal | CommandPri::High => SOURCE_FOREGROUND,
+    }
+}
+
+/// The quota of a request source, 0 means unlimited.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceQuota {
+    /// The CPU time the requests can use every second.
+    pub cpu_time_per_sec: ReadableDuration,
+    /// The bytes the requests can read or write every second.
+    pub bytes_per_sec: ReadableSize,
+}
+
+impl Default for SourceQuota {
+    fn default() -> SourceQuota {
+        SourceQuota {
+            cpu_time_per_sec: ReadableDuration::secs(0),
+            bytes_per_sec: ReadableSize(0),
+        }
+    }
+}
+
+/// A token bucket that can go into debt, which holds at most the tokens of one second.
+pub struct TokenBucket {
+    rate_per_sec: f64,
+    // (available tokens, last refill time)
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate_per_sec: f64) -> TokenBucket {
+        TokenBucket {
+            rate_per_sec,
+            state: Mutex::new((rate_per_sec, Instant::now())),
+        }
+    }
+
+    fn refill(&self, state: &mut (f64, Instant), now: Instant) {
+        let elapsed = now.duration_since(state.1);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        state.0 = (state.0 + secs * self.rate_per_sec).min(self.rate_per_sec);
+        state.1 = now;
+    }
+
+    pub fn consume(&self, tokens: f64) {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state, Instant::now());
+        state.0 -= tokens;
+    }
+
+    /// The time to wait until the debt is paid.
+    pub fn delay(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state, Instant::now());
+        if state.0 >= 0.0 {
+            return Duration::from_secs(0);
+        }
+        let micros = (-state.0 / self.rate_per_sec * 1e6) as u64;
+        Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000)
+    }
+}
+
+struct Quota {
+    // In microseconds.
+    cpu_time: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+/// `QuotaLimiter` limits the CPU time and the bandwidth used by the requests of every source.
+/// Requests are never rejected, instead the requests of an over-quota source are delayed until
+/// its debt is paid.
+pub struct QuotaLimiter {
+    quotas: HashMap<String, Quota>,
+}
+
+impl QuotaLimiter {
+    pub fn new(quotas: &HashMap<String, SourceQuota>) -> QuotaLimiter {
+        let quotas = quotas
+            .iter()
+            .map(|(source, quota)| {
+                let cpu_micros = duration_to_nanos(quota.cpu_time_per_sec.0) / 1000;
+                let quota = Quota {
+                    cpu_time: if cpu_micros > 0 {
+                        Some(TokenBucket::new(cpu_micros as f64))
+                    } else {
+                        None
+                    },
+                    bytes: if quota.bytes_per_sec.0 > 0 {
+                        Some(TokenBucket::new(quota.bytes_per_sec.0 as f64))
+                    } else {
+                        None
+                    },
+                };
+                (source.clone(), quota)
+            })
+            .collect();
+        QuotaLimiter { quotas }
+    }
+
+    /// Records the CPU time and the bytes used by a request of `source`.
+    pub fn consume(&self, source: &str, cpu_time: Duration, bytes: usize) {
+        let quota = match self.quotas.get(source) {
+            Some(q) => q,
+            None => return,
+        };
+        if let Some(ref bucket) = quota.cpu_time {
+            bucket.consume((duration_to_nanos(cpu_time) / 1000) as f64);
+        }
+        if let Some(ref bucket) = quota.bytes {
+            bucket.consume(bytes as f64);
+        }
+    }
+
+    /// Gets how long the next request of `source` should be delayed.
+    pub fn delay(&self, source: &str) -> Duration {
+        let quota = match self.quotas.get(source) {
+            Some(q) => q,
+            None => return Duration::from_secs(0),
+        };
+        let cpu_delay = quota
+            .cpu_time
+            .as_ref()
+            .map_or(Duration::from_secs(0), |b| b.delay());
+        let bytes_delay = quota
+            .bytes
+            .as_ref()
+            .map_or(Duration::from_secs(0), |b| b.delay());
+        let delay = cmp::max(cpu_delay, bytes_delay);
+        if delay > Duration::from_secs(0) {
+            QUOTA_THROTTLED_COUNTER_VEC
+                .with_label_values(&[source])
+                .inc();
+        }
+        delay
+    }
+}
+
+lazy_static! {
+    static ref QUOTA_LIMITER: RwLock<Option<Arc<QuotaLimiter>>> = RwLock::new(None);
+    static ref QUOTA_THROTTLED_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_quota_throttled_requests",
+        "Total number of requests delayed for exceeding the quota of their sources",
+        &["source"]
+    ).unwrap();
+}
+
+/// Sets the quota limiter shared by the whole instance.
+pub fn set_quota_limiter(limiter: Option<Arc<QuotaLimiter>>) {
+    *QUOTA_LIMITER.write().unwrap() = limiter;
+}
+
+pub fn get_quota_limiter() -> Option<Arc<QuotaLimiter>> {
+    QUOTA_LIMITER.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_limiter() {
+        let mut quotas = HashMap::default();
+        quotas.insert(
+            SOURCE_ANALYZE.to_owned(),
+            SourceQuota {
+                cpu_time_per_sec: ReadableDuration::millis(100),
+                bytes_per_sec: ReadableSize(0),
+            },
+        );
+        quotas.insert(
+            SOURCE_CHECKSUM.to_owned(),
+            SourceQuota {
+                cpu_time_per_sec: ReadableDuration::secs(0),
+                bytes_per_sec: ReadableSize::kb(1),
+            },
+        );
+        let limiter = QuotaLimiter::new(&quotas);
+        let zero = Duration::from_secs(0);
+
+        // Sources without quotas are never delayed.
+        limiter.consume(SOURCE_FOREGROUND, Duration::from_secs(10), 1 << 30);
+        assert_eq!(limiter.delay(SOURCE_FOREGROUND), zero);
+
+        // The quota of one second can be used at once.
+        limiter.consume(SOURCE_ANALYZE, Duration::from_millis(100), 1 << 30);
+        assert_eq!(limiter.delay(SOURCE_ANALYZE), zero);
+        // 200ms in debt takes about 2 seconds to pay.
+        limiter.consume(SOURCE_ANALYZE, Duration::from_millis(200), 0);
+        let delay = limiter.delay(SOURCE_ANALYZE);
+        assert!(delay > Duration::from_millis(1900) && delay <= Duration::from_secs(2));
+
+        limiter.consume(SOURCE_CHECKSUM, Duration::from_secs(10), 1024);
+        assert_eq!(limiter.delay(SOURCE_CHECKSUM), zero);
+        limiter.consume(SOURCE_CHECKSUM, zero, 512);
+        let delay = limiter.delay(SOURCE_CHECKSUM);
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+    }
+}
@@ -21,6 +21,8 @@ use futures::{stream, Future, Sink, Stream};
 use grpc::{ChannelBuilder, Environment, WriteFlags};
 use kvproto::raft_serverpb::RaftMessage;
 use kvproto::tikvpb_grpc::TikvClient;
+use prometheus::IntCounter;
+use protobuf::Message;
 
 use super::metrics::*;
 use super::{Config, Error, Result};
@@ -114,6 +116,10 @@ pub struct RaftClient {
     pub addrs: HashMap<u64, String>,
     cfg: Arc<Config>,
     security_mgr: Arc<SecurityManager>,
+    // The bytes of the messages sent since the last flush, which can be compared with the
+    // network traffic to see how well the messages are compressed.
+    raw_bytes: u64,
+    raw_bytes_counter: IntCounter,
 }
 
 impl RaftClient {
@@ -122,12 +128,16 @@ impl RaftClient {
         cfg: Arc<Config>,
         security_mgr: Arc<SecurityManager>,
     ) -> RaftClient {
+        let raw_bytes_counter = RAFT_MESSAGE_RAW_BYTES_COUNTER
+            .with_label_values(&[cfg.grpc_compression_type.as_str()]);
         RaftClient {
             env,
             conns: HashMap::default(),
             addrs: HashMap::default(),
             cfg,
             security_mgr,
+            raw_bytes: 0,
+            raw_bytes_counter,
         }
     }
 
@@ -143,6 +153,7 @@ impl RaftClient {
     }
 
     pub fn send(&mut self, store_id: u64, addr: &str, msg: RaftMessage) -> Result<()> {
+        self.raw_bytes += u64::from(msg.compute_size());
         let conn = self.get_conn(addr, msg.region_id, store_id);
         conn.buffer
             .as_mut()
@@ -193,6 +204,10 @@ impl RaftClient {
         if counter > 0 {
             RAFT_MESSAGE_FLUSH_COUNTER.inc_by(counter as i64);
         }
+        if self.raw_bytes > 0 {
+            self.raw_bytes_counter.inc_by(self.raw_bytes as i64);
+            self.raw_bytes = 0;
+        }
     }
 }
 
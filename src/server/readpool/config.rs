@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use util::config::ReadableSize;
+use util::config::{ReadableDuration, ReadableSize};
 
 // Assume a request can be finished in 1ms, a request at position x will wait about
 // 0.001 * x secs to be actual started. A server-is-busy error will trigger 2 seconds
@@ -21,6 +21,8 @@ pub const DEFAULT_MAX_TASKS_PER_WORKER: usize = 2 as usize * 1000;
 
 pub const DEFAULT_STACK_SIZE_MB: u64 = 10;
 
+pub const DEFAULT_MAX_QUEUE_LATENCY_MS: u64 = 10;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub high_concurrency: usize,
@@ -30,6 +32,13 @@ pub struct Config {
     pub max_tasks_per_worker_normal: usize,
     pub max_tasks_per_worker_low: usize,
     pub stack_size: ReadableSize,
+    /// The min number of threads of each pool that are running tasks. If it's less than the
+    /// concurrency of a pool, the pool is resized between the two according to `max_queue_latency`.
+    /// 0 means the pools are never resized.
+    pub min_concurrency: usize,
+    /// Active threads of a pool are added when tasks wait longer than it on average before
+    /// being started, and removed when tasks wait less than half of it.
+    pub max_queue_latency: ReadableDuration,
 }
 
 impl Config {
@@ -43,6 +52,8 @@ impl Config {
             max_tasks_per_worker_normal: DEFAULT_MAX_TASKS_PER_WORKER,
             max_tasks_per_worker_low: DEFAULT_MAX_TASKS_PER_WORKER,
             stack_size: ReadableSize::mb(DEFAULT_STACK_SIZE_MB),
+            min_concurrency: 0,
+            max_queue_latency: ReadableDuration::millis(DEFAULT_MAX_QUEUE_LATENCY_MS),
         }
     }
 
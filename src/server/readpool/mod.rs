@@ -18,11 +18,12 @@ use std::error::Error;
 use std::fmt;
 use std::time::Duration;
 
+use futures::sync::oneshot;
 use futures::Future;
 use futures_cpupool::CpuFuture;
 
 use util;
-use util::futurepool::{self, FuturePool};
+use util::futurepool::{self, FuturePool, Turn};
 
 pub use self::config::Config;
 pub use self::priority::Priority;
@@ -61,29 +62,24 @@ impl<T: futurepool::Context + 'static> ReadPool<T> {
         CF: futurepool::Factory<T>,
     {
         let tick_interval = Duration::from_secs(TICK_INTERVAL_SEC);
-
-        ReadPool {
-            pool_high: FuturePool::new(
-                config.high_concurrency,
-                config.stack_size.0 as usize,
-                &format!("{}-high", name_prefix),
-                tick_interval,
-                context_factory_builder.build(),
-            ),
-            pool_normal: FuturePool::new(
-                config.normal_concurrency,
-                config.stack_size.0 as usize,
-                &format!("{}-normal", name_prefix),
-                tick_interval,
-                context_factory_builder.build(),
-            ),
-            pool_low: FuturePool::new(
-                config.low_concurrency,
+        let build_pool = |concurrency: usize, name: String| {
+            let mut pool = FuturePool::new(
+                concurrency,
                 config.stack_size.0 as usize,
-                &format!("{}-low", name_prefix),
+                &name,
                 tick_interval,
                 context_factory_builder.build(),
-            ),
+            );
+            if config.min_concurrency > 0 {
+                pool.enable_auto_resize(config.min_concurrency, config.max_queue_latency.0);
+            }
+            pool
+        };
+
+        ReadPool {
+            pool_high: build_pool(config.high_concurrency, format!("{}-high", name_prefix)),
+            pool_normal: build_pool(config.normal_concurrency, format!("{}-normal", name_prefix)),
+            pool_low: build_pool(config.low_concurrency, format!("{}-low", name_prefix)),
             max_tasks_high: config.max_tasks_per_worker_high * config.high_concurrency,
             max_tasks_normal: config.max_tasks_per_worker_normal * config.normal_concurrency,
             max_tasks_low: config.max_tasks_per_worker_low * config.low_concurrency,
@@ -134,6 +130,21 @@ impl<T: futurepool::Context + 'static> ReadPool<T> {
             Ok(pool.spawn(future_factory))
         }
     }
+
+    /// Runs `step` on the specified future pool in turns, see `FuturePool::run_in_turns`. It
+    /// should be called by a task running on the same future pool.
+    pub fn run_in_turns<S, I, E>(
+        &self,
+        priority: Priority,
+        step: S,
+    ) -> oneshot::Receiver<Result<I, E>>
+    where
+        S: FnMut() -> Result<Turn<I>, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        self.get_pool_by_priority(priority).run_in_turns(step)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
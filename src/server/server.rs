@@ -41,6 +41,9 @@ pub struct Server<T: RaftStoreRouter + 'static, S: StoreAddrResolver + 'static,
     // Grpc server.
     grpc_server: GrpcServer,
     local_addr: SocketAddr,
+    // Serves the debug service if `debug-addr` is set.
+    debug_server: Option<GrpcServer>,
+    debug_addr: Option<SocketAddr>,
     // Transport.
     trans: ServerTransport<T, S>,
     raft_router: T,
@@ -85,19 +88,34 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
         let addr = SocketAddr::from_str(&cfg.addr)?;
         info!("listening on {}", addr);
         let ip = format!("{}", addr.ip());
-        let channel_args = ChannelBuilder::new(Arc::clone(&env))
-            .stream_initial_window_size(cfg.grpc_stream_initial_window_size.0 as i32)
-            .max_concurrent_stream(cfg.grpc_concurrent_stream)
-            .max_receive_message_len(MAX_GRPC_RECV_MSG_LEN)
-            .max_send_message_len(-1)
-            .build_args();
+        let new_channel_args = || {
+            ChannelBuilder::new(Arc::clone(&env))
+                .stream_initial_window_size(cfg.grpc_stream_initial_window_size.0 as i32)
+                .max_concurrent_stream(cfg.grpc_concurrent_stream)
+                .max_receive_message_len(MAX_GRPC_RECV_MSG_LEN)
+                .max_send_message_len(-1)
+                .default_compression_algorithm(cfg.grpc_server_compression_algorithm())
+                .build_args()
+        };
+        let debug_service = debug_engines.map(|e| DebugService::new(e, raft_router.clone()));
+        let (debug_service, debug_server) = match debug_service {
+            Some(service) if !cfg.debug_addr.is_empty() => {
+                let debug_addr = SocketAddr::from_str(&cfg.debug_addr)?;
+                info!("debug service is listening on {}", debug_addr);
+                let mut sb = ServerBuilder::new(Arc::clone(&env))
+                    .channel_args(new_channel_args())
+                    .register_service(create_debug(service));
+                sb = security_mgr.bind(sb, &format!("{}", debug_addr.ip()), debug_addr.port());
+                (None, Some(sb.build()?))
+            }
+            service => (service, None),
+        };
         let grpc_server = {
             let mut sb = ServerBuilder::new(Arc::clone(&env))
-                .channel_args(channel_args)
+                .channel_args(new_channel_args())
                 .register_service(create_tikv(kv_service));
             sb = security_mgr.bind(sb, &ip, addr.port());
-            if let Some(engines) = debug_engines {
-                let debug_service = DebugService::new(engines, raft_router.clone());
+            if let Some(debug_service) = debug_service {
                 sb = sb.register_service(create_debug(debug_service));
             }
             if let Some(service) = import_service {
@@ -106,9 +124,10 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
             sb.build()?
         };
 
-        let addr = {
-            let (ref host, port) = grpc_server.bind_addrs()[0];
-            SocketAddr::new(IpAddr::from_str(host)?, port as u16)
+        let addr = bind_addr(&grpc_server)?;
+        let debug_addr = match debug_server {
+            Some(ref s) => Some(bind_addr(s)?),
+            None => None,
         };
 
         let trans = ServerTransport::new(
@@ -122,6 +141,8 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
             env: Arc::clone(&env),
             grpc_server,
             local_addr: addr,
+            debug_server,
+            debug_addr,
             trans,
             raft_router,
             storage,
@@ -146,6 +167,9 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
         );
         box_try!(self.snap_worker.start(snap_runner));
         self.grpc_server.start();
+        if let Some(ref mut s) = self.debug_server {
+            s.start();
+        }
         info!("TiKV is ready to serve");
         Ok(())
     }
@@ -156,6 +180,9 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
             error!("failed to stop store: {:?}", e);
         }
         self.grpc_server.shutdown();
+        if let Some(ref mut s) = self.debug_server {
+            s.shutdown();
+        }
         Ok(())
     }
 
@@ -165,6 +192,16 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver + 'static, E: Engine> Server<T, S,
     pub fn listening_addr(&self) -> SocketAddr {
         self.local_addr
     }
+
+    /// Returns the listening address of the debug service if it's served separately.
+    pub fn debug_listening_addr(&self) -> Option<SocketAddr> {
+        self.debug_addr
+    }
+}
+
+fn bind_addr(server: &GrpcServer) -> Result<SocketAddr> {
+    let (ref host, port) = server.bind_addrs()[0];
+    Ok(SocketAddr::new(IpAddr::from_str(host)?, port as u16))
 }
 
 #[cfg(test)]
@@ -22,6 +22,7 @@ use kvproto::kvrpcpb;
 use kvproto::kvrpcpb::*;
 use kvproto::raft_serverpb::*;
 use kvproto::tikvpb_grpc;
+use prometheus::HistogramTimer;
 use protobuf::RepeatedField;
 use std::iter::{self, FromIterator};
 
@@ -37,10 +38,14 @@ use storage::txn::Error as TxnError;
 use storage::{self, Engine, Key, Mutation, Options, Storage, Value};
 use util::collections::HashMap;
 use util::future::{paired_future_callback, AndThenWith};
+use util::logger::{self, SLOW_LOG_TARGET};
+use util::time::{duration_to_sec, Instant};
 use util::worker::Scheduler;
 
 const SCHEDULER_IS_BUSY: &str = "scheduler is busy";
 const GC_WORKER_IS_BUSY: &str = "gc worker is busy";
+const REGION_IN_FLASHBACK: &str = "region is in flashback";
+const DEADLINE_EXCEEDED: &str = "request is queued for too long";
 
 #[derive(Clone)]
 pub struct Service<T: RaftStoreRouter + 'static, E: Engine> {
@@ -81,9 +86,68 @@ impl<T: RaftStoreRouter + 'static, E: Engine> Service<T, E> {
     }
 }
 
+/// RequestTimer observes the duration of a gRPC message like the `HistogramTimer` it wraps,
+/// and also attributes it to the client sending it in the per-client metrics and the slow log,
+/// so that the load of the store can be traced back to the clients.
+struct RequestTimer {
+    timer: HistogramTimer,
+    start: Instant,
+    client: String,
+    kind: &'static str,
+    region_id: u64,
+}
+
+impl RequestTimer {
+    fn new(
+        ctx: &RpcContext,
+        req_ctx: &kvrpcpb::Context,
+        kind: &'static str,
+        timer: HistogramTimer,
+    ) -> RequestTimer {
+        RequestTimer {
+            timer,
+            start: Instant::now_coarse(),
+            client: client_of_peer(&ctx.peer()).to_owned(),
+            kind,
+            region_id: req_ctx.get_region_id(),
+        }
+    }
+
+    fn observe_duration(self) {
+        self.timer.observe_duration();
+        let elapsed = self.start.elapsed();
+        GRPC_CLIENT_MSG_COUNTER
+            .with_label_values(&[&self.client, self.kind])
+            .inc();
+        GRPC_CLIENT_MSG_DURATION
+            .with_label_values(&[&self.client])
+            .inc_by(duration_to_sec(elapsed));
+        if logger::is_slow(elapsed) {
+            info!(
+                target: SLOW_LOG_TARGET,
+                "[region {}] [slow-query] {} from {} takes {:?}",
+                self.region_id,
+                self.kind,
+                self.client,
+                elapsed
+            );
+        }
+    }
+}
+
+// The peer address of a gRPC call is like "ipv4:127.0.0.1:20160". The port is dropped, as
+// the connections of a client come from different ones.
+fn client_of_peer(peer: &str) -> &str {
+    match peer.rfind(':') {
+        Some(i) if peer[..i].contains(':') => &peer[..i],
+        _ => peer,
+    }
+}
+
 impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E> {
     fn kv_get(&self, ctx: RpcContext, mut req: GetRequest, sink: UnarySink<GetResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_get.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_get", timer);
 
         let future = self
             .storage
@@ -117,6 +181,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn kv_scan(&self, ctx: RpcContext, mut req: ScanRequest, sink: UnarySink<ScanResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_scan.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_scan", timer);
 
         let mut options = Options::default();
         options.key_only = req.get_key_only();
@@ -157,6 +222,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<PrewriteResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_prewrite.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_prewrite", timer);
 
         let mutations = req
             .take_mutations()
@@ -172,18 +238,16 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         options.lock_ttl = req.get_lock_ttl();
         options.skip_constraint_check = req.get_skip_constraint_check();
 
-        let (cb, f) = paired_future_callback();
-        let res = self.storage.async_prewrite(
-            req.take_context(),
-            mutations,
-            req.take_primary_lock(),
-            req.get_start_version(),
-            options,
-            cb,
-        );
-
-        let future = AndThenWith::new(res, f.map_err(Error::from))
-            .and_then(|v| {
+        let future = self
+            .storage
+            .prewrite(
+                req.take_context(),
+                mutations,
+                req.take_primary_lock(),
+                req.get_start_version(),
+                options,
+            )
+            .then(|v| {
                 let mut resp = PrewriteResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -203,20 +267,19 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn kv_commit(&self, ctx: RpcContext, mut req: CommitRequest, sink: UnarySink<CommitResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_commit.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_commit", timer);
 
         let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
 
-        let (cb, f) = paired_future_callback();
-        let res = self.storage.async_commit(
-            req.take_context(),
-            keys,
-            req.get_start_version(),
-            req.get_commit_version(),
-            cb,
-        );
-
-        let future = AndThenWith::new(res, f.map_err(Error::from))
-            .and_then(|v| {
+        let future = self
+            .storage
+            .commit(
+                req.take_context(),
+                keys,
+                req.get_start_version(),
+                req.get_commit_version(),
+            )
+            .then(|v| {
                 let mut resp = CommitResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -245,17 +308,16 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<CleanupResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_cleanup.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_cleanup", timer);
 
-        let (cb, f) = paired_future_callback();
-        let res = self.storage.async_cleanup(
-            req.take_context(),
-            Key::from_raw(req.get_key()),
-            req.get_start_version(),
-            cb,
-        );
-
-        let future = AndThenWith::new(res, f.map_err(Error::from))
-            .and_then(|v| {
+        let future = self
+            .storage
+            .cleanup(
+                req.take_context(),
+                Key::from_raw(req.get_key()),
+                req.get_start_version(),
+            )
+            .then(|v| {
                 let mut resp = CleanupResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -284,6 +346,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<BatchGetResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_batch_get.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_batch_get", timer);
 
         let keys = req
             .get_keys()
@@ -322,6 +385,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .kv_batch_rollback
             .start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_batch_rollback", timer);
 
         let keys = req
             .get_keys()
@@ -329,13 +393,10 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
             .map(|x| Key::from_raw(x))
             .collect();
 
-        let (cb, f) = paired_future_callback();
-        let res =
-            self.storage
-                .async_rollback(req.take_context(), keys, req.get_start_version(), cb);
-
-        let future = AndThenWith::new(res, f.map_err(Error::from))
-            .and_then(|v| {
+        let future = self
+            .storage
+            .rollback(req.take_context(), keys, req.get_start_version())
+            .then(|v| {
                 let mut resp = BatchRollbackResponse::new();
                 if let Some(err) = extract_region_error(&v) {
                     resp.set_region_error(err);
@@ -360,15 +421,28 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<ScanLockResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_scan_lock.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_scan_lock", timer);
 
+        // The kvproto in use has no PhysicalScanLock RPC, so a request without a region scans
+        // the locks of the whole store physically instead.
         let (cb, f) = paired_future_callback();
-        let res = self.storage.async_scan_locks(
-            req.take_context(),
-            req.get_max_version(),
-            req.take_start_key(),
-            req.get_limit() as usize,
-            cb,
-        );
+        let res = if req.get_context().get_region_id() == 0 {
+            self.storage.async_physical_scan_lock(
+                req.take_context(),
+                req.get_max_version(),
+                req.take_start_key(),
+                req.get_limit() as usize,
+                cb,
+            )
+        } else {
+            self.storage.async_scan_locks(
+                req.take_context(),
+                req.get_max_version(),
+                req.take_start_key(),
+                req.get_limit() as usize,
+                cb,
+            )
+        };
 
         let future = AndThenWith::new(res, f.map_err(Error::from))
             .and_then(|v| {
@@ -399,6 +473,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<ResolveLockResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_resolve_lock.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_resolve_lock", timer);
 
         let txn_status = if req.get_start_version() > 0 {
             HashMap::from_iter(iter::once((
@@ -439,6 +514,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn kv_gc(&self, ctx: RpcContext, mut req: GCRequest, sink: UnarySink<GCResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_gc.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_gc", timer);
 
         let (cb, f) = paired_future_callback();
         let res = self
@@ -472,6 +548,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<DeleteRangeResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.kv_delete_range.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "kv_delete_range", timer);
 
         let (cb, f) = paired_future_callback();
         let res = self.storage.async_delete_range(
@@ -502,6 +579,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn raw_get(&self, ctx: RpcContext, mut req: RawGetRequest, sink: UnarySink<RawGetResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_get.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_get", timer);
 
         let future = self
             .storage
@@ -535,6 +613,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawBatchGetResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_batch_get.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_batch_get", timer);
 
         let keys = req.take_keys().into_vec();
         let future = self
@@ -560,6 +639,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn raw_scan(&self, ctx: RpcContext, mut req: RawScanRequest, sink: UnarySink<RawScanResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_scan.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_scan", timer);
 
         let future = self
             .storage
@@ -595,6 +675,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawBatchScanResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_batch_scan.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_batch_scan", timer);
 
         let future = self
             .storage
@@ -625,6 +706,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn raw_put(&self, ctx: RpcContext, mut req: RawPutRequest, sink: UnarySink<RawPutResponse>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_put.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_put", timer);
 
         let (cb, f) = paired_future_callback();
         let res = self.storage.async_raw_put(
@@ -661,6 +743,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawBatchPutResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_batch_put.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_batch_put", timer);
 
         let pairs = req
             .take_pairs()
@@ -698,6 +781,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawDeleteResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_delete.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_delete", timer);
 
         let (cb, f) = paired_future_callback();
         let res =
@@ -730,6 +814,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawBatchDeleteResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_batch_delete.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_batch_delete", timer);
 
         let keys = req.take_keys().into_vec();
         let (cb, f) = paired_future_callback();
@@ -763,6 +848,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<RawDeleteRangeResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.raw_delete_range.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "raw_delete_range", timer);
 
         let (cb, f) = paired_future_callback();
         let res = self.storage.async_raw_delete_range(
@@ -801,6 +887,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .unsafe_destroy_range
             .start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "unsafe_destroy_range", timer);
 
         // DestroyRange is a very dangerous operation. We don't allow passing MIN_KEY as start, or
         // MAX_KEY as end here.
@@ -835,6 +922,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
 
     fn coprocessor(&self, ctx: RpcContext, req: Request, sink: UnarySink<Response>) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.coprocessor.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "coprocessor", timer);
 
         let future = self
             .cop
@@ -859,6 +947,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .coprocessor_stream
             .start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "coprocessor_stream", timer);
 
         let stream = self
             .cop
@@ -924,6 +1013,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<MvccGetByKeyResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.mvcc_get_by_key.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "mvcc_get_by_key", timer);
 
         let storage = self.storage.clone();
 
@@ -964,6 +1054,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         let timer = GRPC_MSG_HISTOGRAM_VEC
             .mvcc_get_by_start_ts
             .start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "mvcc_get_by_start_ts", timer);
 
         let storage = self.storage.clone();
 
@@ -1005,6 +1096,7 @@ impl<T: RaftStoreRouter + 'static, E: Engine> tikvpb_grpc::Tikv for Service<T, E
         sink: UnarySink<SplitRegionResponse>,
     ) {
         let timer = GRPC_MSG_HISTOGRAM_VEC.split_region.start_coarse_timer();
+        let timer = RequestTimer::new(&ctx, req.get_context(), "split_region", timer);
 
         let region_id = req.get_context().get_region_id();
         let (cb, future) = paired_future_callback();
@@ -1073,6 +1165,14 @@ fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
             err.set_server_is_busy(server_is_busy_err);
             Some(err)
         }
+        Err(Error::SchedTooBusyWithBackoff(backoff_ms)) => {
+            let mut err = RegionError::new();
+            let mut server_is_busy_err = ServerIsBusy::new();
+            server_is_busy_err.set_reason(SCHEDULER_IS_BUSY.to_owned());
+            server_is_busy_err.set_backoff_ms(backoff_ms);
+            err.set_server_is_busy(server_is_busy_err);
+            Some(err)
+        }
         Err(Error::GCWorkerTooBusy) => {
             let mut err = RegionError::new();
             let mut server_is_busy_err = ServerIsBusy::new();
@@ -1080,6 +1180,21 @@ fn extract_region_error<T>(res: &storage::Result<T>) -> Option<RegionError> {
             err.set_server_is_busy(server_is_busy_err);
             Some(err)
         }
+        // Let the client back off and retry after the flashback.
+        Err(Error::RegionInFlashback(_)) => {
+            let mut err = RegionError::new();
+            let mut server_is_busy_err = ServerIsBusy::new();
+            server_is_busy_err.set_reason(REGION_IN_FLASHBACK.to_owned());
+            err.set_server_is_busy(server_is_busy_err);
+            Some(err)
+        }
+        Err(Error::Txn(TxnError::DeadlineExceeded(_))) => {
+            let mut err = RegionError::new();
+            let mut server_is_busy_err = ServerIsBusy::new();
+            server_is_busy_err.set_reason(DEADLINE_EXCEEDED.to_owned());
+            err.set_server_is_busy(server_is_busy_err);
+            Some(err)
+        }
         _ => None,
     }
 }
@@ -1230,10 +1345,19 @@ fn extract_key_errors(res: storage::Result<Vec<storage::Result<()>>>) -> Vec<Key
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
     use storage;
     use storage::mvcc::Error as MvccError;
     use storage::txn::Error as TxnError;
 
+    #[test]
+    fn test_client_of_peer() {
+        assert_eq!(client_of_peer("ipv4:127.0.0.1:53422"), "ipv4:127.0.0.1");
+        assert_eq!(client_of_peer("ipv6:[::1]:53422"), "ipv6:[::1]");
+        assert_eq!(client_of_peer("unix:/tmp/tikv.sock"), "unix:/tmp/tikv.sock");
+        assert_eq!(client_of_peer("unknown"), "unknown");
+    }
+
     #[test]
     fn test_extract_key_error_write_conflict() {
         let start_ts = 110;
@@ -1259,4 +1383,23 @@ mod tests {
         assert_eq!(got, expect);
     }
 
+    #[test]
+    fn test_extract_region_error_sched_too_busy() {
+        let res: storage::Result<()> = Err(storage::Error::SchedTooBusyWithBackoff(200));
+        let err = extract_region_error(&res).unwrap();
+        assert_eq!(err.get_server_is_busy().get_reason(), SCHEDULER_IS_BUSY);
+        assert_eq!(err.get_server_is_busy().get_backoff_ms(), 200);
+
+        let res: storage::Result<()> = Err(storage::Error::SchedTooBusy);
+        let err = extract_region_error(&res).unwrap();
+        assert_eq!(err.get_server_is_busy().get_backoff_ms(), 0);
+    }
+
+    #[test]
+    fn test_extract_region_error_deadline_exceeded() {
+        let err = TxnError::DeadlineExceeded(Duration::from_secs(61));
+        let res: storage::Result<()> = Err(storage::Error::from(err));
+        let err = extract_region_error(&res).unwrap();
+        assert_eq!(err.get_server_is_busy().get_reason(), DEADLINE_EXCEEDED);
+    }
 }
@@ -0,0 +1,944 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crypto::util::fixed_time_eq;
+use serde_json;
+use tempdir::TempDir;
+
+use config::{ConfigController, TiKvConfig};
+use raftstore::store::profiler::REGION_PROFILER;
+use util::encryption::DataKeyManager;
+use util::security::SecurityConfig;
+use util::{cpu_profiler, jemalloc, metrics};
+
+use super::diagnostics::{self, LogSearch};
+use super::tls::TlsAcceptor;
+use super::Result;
+
+// The request, including the TLS handshake, should be read within it.
+const READ_REQUEST_TIMEOUT_SECS: u64 = 10;
+const WRITE_TIMEOUT_SECS: u64 = 30;
+// The max bytes of the request line and the headers.
+const MAX_REQUEST_HEAD_SIZE: u64 = 16 * 1024;
+// The connections beyond it are closed right away.
+const MAX_CONNECTIONS: usize = 32;
+const DEFAULT_PROFILE_SECONDS: u64 = 10;
+const MAX_PROFILE_SECONDS: u64 = 300;
+const CPU_PROFILE_FREQUENCY: u32 = 99;
+
+// Only one profiling can run at a time.
+static PROFILING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// `StatusServer` is a lightweight HTTP server for operators. It serves:
+///
+/// - `/metrics`: the Prometheus metrics, for Prometheus to pull.
+/// - `/config`: the effective configuration in JSON.
+/// - `POST /config?name=value`: changes the configurations online, e.g.
+///   `rocksdb.defaultcf.write-buffer-size=256MB`, see `ConfigController` for the supported ones.
+///   Nothing is changed if any of the pairs is invalid.
+/// - `/status`: 200 once the server is ready to serve requests, 503 before that.
+/// - `/debug/pprof/profile?seconds=N`: samples the CPU for N seconds and returns how often each
+///   function of each thread is running in the folded stack format, see `cpu_profiler`.
+/// - `/debug/pprof/heap?seconds=N`: samples the allocations for N seconds and returns the
+///   heap profile, it requires the `mem-profiling` feature.
+/// - `/debug/region/profile?id=N&seconds=N`: profiles the propose, apply and read stages of the
+///   region for N seconds, and returns the result in the folded stack format.
+/// - `/diagnostics/log?start=..&end=..&level=..&pattern=..&limit=N`: searches the log files.
+/// - `/diagnostics/sysinfo`: the hardware, load and disk information of the host.
+/// - `/encryption`: the current data key and the number of files encrypted by each data key.
+/// - `POST /encryption/rotate-data-key`: generates a new data key for the new snapshot and
+///   import files.
+///
+/// If `server.status-token` is set, the requests except `/status` should carry it in the
+/// `Authorization: Bearer <token>` header, or they are rejected with 401.
+///
+/// If the certificate and the private key are set in `security`, the server only serves
+/// HTTPS. With `server.status-verify-client-cert`, the clients should present a certificate
+/// signed by the CA there too, with a common name in `security.cert-allowed-cn` if it's set.
+/// The `POST` endpoints, which change the state of TiKV, are rejected with 403 unless the
+/// requests are authenticated by the token or the certificates.
+///
+/// At most `MAX_CONNECTIONS` connections are served at a time. A request should be read within
+/// `READ_REQUEST_TIMEOUT_SECS`, and its head can't exceed `MAX_REQUEST_HEAD_SIZE`.
+pub struct StatusServer {
+    config: Arc<StatusConfig>,
+    security: SecurityConfig,
+    controller: Option<Arc<ConfigController>>,
+    key_manager: Option<Arc<DataKeyManager>>,
+    ready: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    addr: Option<SocketAddr>,
+    handle: Option<JoinHandle<()>>,
+}
+
+// The parts of the configuration the handlers need.
+struct StatusConfig {
+    json: String,
+    log_file: String,
+    data_dir: String,
+    token: String,
+    // Whether the requests changing the state are served.
+    mutable: bool,
+}
+
+impl StatusServer {
+    pub fn new(cfg: &TiKvConfig) -> StatusServer {
+        let config = StatusConfig {
+            json: serde_json::to_string_pretty(cfg).unwrap(),
+            log_file: cfg.log_file.clone(),
+            data_dir: cfg.storage.data_dir.clone(),
+            token: cfg.server.status_token.clone(),
+            mutable: !cfg.server.status_token.is_empty()
+                || (cfg.server.status_verify_client_cert && !cfg.security.cert_path.is_empty()),
+        };
+        if !config.mutable {
+            warn!(
+                "the status server can't change the configurations or rotate the data keys \
+                 without server.status-token or server.status-verify-client-cert"
+            );
+        }
+        let mut security = cfg.security.clone();
+        if !cfg.server.status_verify_client_cert {
+            // Only the CA is needed to verify the clients.
+            security.ca_path.clear();
+        }
+        StatusServer {
+            config: Arc::new(config),
+            security,
+            controller: None,
+            key_manager: None,
+            ready: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            addr: None,
+            handle: None,
+        }
+    }
+
+    /// Enables changing the configurations online. It should be called before `start`.
+    pub fn set_config_controller(&mut self, controller: Arc<ConfigController>) {
+        self.controller = Some(controller);
+    }
+
+    /// Enables managing the data keys of the encryption. It should be called before `start`.
+    pub fn set_data_key_manager(&mut self, key_manager: Arc<DataKeyManager>) {
+        self.key_manager = Some(key_manager);
+    }
+
+    pub fn start(&mut self, addr: &str) -> Result<()> {
+        let tls = if self.security.cert_path.is_empty() {
+            None
+        } else {
+            let verify_client = !self.security.ca_path.is_empty();
+            match TlsAcceptor::new(&self.security, verify_client) {
+                Ok(acceptor) => Some(Arc::new(acceptor)),
+                Err(e) => return Err(box_err!("failed to set up TLS: {}", e)),
+            }
+        };
+        let listener = TcpListener::bind(addr)?;
+        self.addr = Some(listener.local_addr()?);
+        info!("status server is listening on {}", self.addr.unwrap());
+
+        let config = Arc::clone(&self.config);
+        let controller = self.controller.clone();
+        let key_manager = self.key_manager.clone();
+        let ready = Arc::clone(&self.ready);
+        let stopped = Arc::clone(&self.stopped);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let h = thread::Builder::new()
+            .name(thd_name!("status-server"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::Acquire) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("status server failed to accept connection: {:?}", e);
+                            continue;
+                        }
+                    };
+                    // Only this thread adds connections, so the count can't exceed the limit.
+                    if connections.load(Ordering::Acquire) >= MAX_CONNECTIONS {
+                        warn!(
+                            "status server has too many connections, close the one from {:?}",
+                            stream.peer_addr()
+                        );
+                        continue;
+                    }
+                    let guard = ConnectionGuard::new(Arc::clone(&connections));
+                    // Profiling may take a while, so don't block the other requests.
+                    let (config, ready) = (Arc::clone(&config), Arc::clone(&ready));
+                    let (controller, key_manager) = (controller.clone(), key_manager.clone());
+                    let tls = tls.clone();
+                    let res = thread::Builder::new()
+                        .name(thd_name!("status-handler"))
+                        .spawn(move || {
+                            let _guard = guard;
+                            let controller = controller.as_ref().map(|c| c.as_ref());
+                            let key_manager = key_manager.as_ref().map(|m| m.as_ref());
+                            let tls = tls.as_ref().map(|t| t.as_ref());
+                            let res = handle_connection(
+                                stream,
+                                tls,
+                                &config,
+                                controller,
+                                key_manager,
+                                &ready,
+                            );
+                            if let Err(e) = res {
+                                warn!("status server failed to handle request: {:?}", e);
+                            }
+                        });
+                    if let Err(e) = res {
+                        warn!("status server failed to spawn handler: {:?}", e);
+                    }
+                }
+            })?;
+        self.handle = Some(h);
+        Ok(())
+    }
+
+    /// Makes `/status` report the server is ready.
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn listening_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    pub fn stop(&mut self) {
+        let h = match self.handle.take() {
+            Some(h) => h,
+            None => return,
+        };
+        self.stopped.store(true, Ordering::Release);
+        // Wakes up the blocking `accept`.
+        let mut addr = self.addr.unwrap();
+        if addr.ip().is_unspecified() {
+            addr.set_ip([127, 0, 0, 1].into());
+        }
+        if let Err(e) = TcpStream::connect(addr) {
+            warn!("failed to wake up status server: {:?}", e);
+            return;
+        }
+        if let Err(e) = h.join() {
+            error!("failed to join status server: {:?}", e);
+        }
+    }
+}
+
+// Counts a connection until it's dropped.
+struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+}
+
+impl ConnectionGuard {
+    fn new(connections: Arc<AtomicUsize>) -> ConnectionGuard {
+        connections.fetch_add(1, Ordering::AcqRel);
+        ConnectionGuard { connections }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Fails the reads once the deadline is exceeded, so that a client sending the request slowly
+// can't hold a handler for long.
+struct DeadlineStream<S> {
+    inner: S,
+    // The socket under `inner`, whose read timeout is renewed before every read.
+    socket: TcpStream,
+    deadline: Instant,
+}
+
+impl<S: Read> Read for DeadlineStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "reading request timed out"));
+        }
+        self.socket.set_read_timeout(Some(self.deadline - now))?;
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for DeadlineStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text<S: Into<String>>(status: &'static str, body: S) -> Response {
+        Response::new(status, "text/plain", body.into().into_bytes())
+    }
+}
+
+// Handles the request of the connection, over TLS if `tls` is given.
+fn handle_connection(
+    stream: TcpStream,
+    tls: Option<&TlsAcceptor>,
+    config: &StatusConfig,
+    controller: Option<&ConfigController>,
+    key_manager: Option<&DataKeyManager>,
+    ready: &AtomicBool,
+) -> Result<()> {
+    let timeout = Duration::from_secs(READ_REQUEST_TIMEOUT_SECS);
+    let deadline = Instant::now() + timeout;
+    // It bounds the reads of the TLS handshake, `DeadlineStream` takes over after that.
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)))?;
+    let socket = stream.try_clone()?;
+    match tls {
+        Some(tls) => {
+            let stream = DeadlineStream {
+                inner: tls.accept(stream)?,
+                socket,
+                deadline,
+            };
+            handle_request(stream, config, controller, key_manager, ready)
+        }
+        None => {
+            let stream = DeadlineStream {
+                inner: stream,
+                socket,
+                deadline,
+            };
+            handle_request(stream, config, controller, key_manager, ready)
+        }
+    }
+}
+
+// Reads the request line and the authorization header, the other headers are ignored as the
+// requests have no body. Returns `None` if the head is cut short by the EOF or exceeds
+// `MAX_REQUEST_HEAD_SIZE`.
+fn read_request_head<R: Read>(stream: R) -> io::Result<Option<(String, String)>> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD_SIZE));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    if !request_line.ends_with('\n') {
+        return Ok(None);
+    }
+    let mut authorization = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap();
+        if name.trim().eq_ignore_ascii_case("authorization") {
+            authorization = header.next().unwrap_or("").trim().to_owned();
+        }
+    }
+    Ok(Some((request_line, authorization)))
+}
+
+fn handle_request<S: Read + Write>(
+    mut stream: S,
+    config: &StatusConfig,
+    controller: Option<&ConfigController>,
+    key_manager: Option<&DataKeyManager>,
+    ready: &AtomicBool,
+) -> Result<()> {
+    let (request_line, authorization) = match read_request_head(&mut stream)? {
+        Some(head) => head,
+        None => {
+            let resp = Response::text("400 Bad Request", "incomplete or too large request");
+            return write_response(&mut stream, &resp);
+        }
+    };
+    let mut parts = request_line.split_whitespace();
+    let (method, uri) = (parts.next(), parts.next().unwrap_or(""));
+    let mut uri_parts = uri.splitn(2, '?');
+    let (path, query) = (uri_parts.next().unwrap(), uri_parts.next().unwrap_or(""));
+    let resp = match (method, path) {
+        // `/status` is left open for health checks.
+        (_, p) if p != "/status" && !authorized(&config.token, &authorization) => {
+            Response::text("401 Unauthorized", "unauthorized")
+        }
+        (Some("POST"), _) if !config.mutable => Response::text(
+            "403 Forbidden",
+            "set server.status-token or server.status-verify-client-cert to enable it",
+        ),
+        (Some("GET"), "/metrics") => Response::new(
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics::dump().into_bytes(),
+        ),
+        (Some("GET"), "/config") => {
+            let json = match controller {
+                Some(c) => serde_json::to_string_pretty(&c.get_current()).unwrap(),
+                None => config.json.clone(),
+            };
+            Response::new("200 OK", "application/json", json.into_bytes())
+        }
+        (Some("POST"), "/config") => update_config(controller, query),
+        (Some("GET"), "/status") => {
+            if ready.load(Ordering::Acquire) {
+                Response::text("200 OK", "ok")
+            } else {
+                Response::text("503 Service Unavailable", "not ready")
+            }
+        }
+        (Some("GET"), "/debug/pprof/heap") => heap_profile(query),
+        (Some("GET"), "/debug/region/profile") => region_profile(query),
+        (Some("GET"), "/debug/pprof/profile") => cpu_profile(query),
+        (Some("GET"), "/diagnostics/log") => search_log(&config.log_file, query),
+        (Some("GET"), "/diagnostics/sysinfo") => match diagnostics::system_info(&config.data_dir) {
+            Ok(info) => Response::new(
+                "200 OK",
+                "application/json",
+                serde_json::to_vec_pretty(&info).unwrap(),
+            ),
+            Err(e) => Response::text(
+                "500 Internal Server Error",
+                format!("failed to get system info: {:?}", e),
+            ),
+        },
+        (Some("GET"), "/encryption") => encryption_status(key_manager),
+        (Some("POST"), "/encryption/rotate-data-key") => rotate_data_key(key_manager),
+        (Some("GET"), _) => Response::text("404 Not Found", "not found"),
+        _ => Response::text("405 Method Not Allowed", "method not allowed"),
+    };
+    write_response(&mut stream, &resp)
+}
+
+fn write_response<W: Write>(stream: &mut W, resp: &Response) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        resp.status,
+        resp.content_type,
+        resp.body.len()
+    )?;
+    stream.write_all(&resp.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn authorized(token: &str, authorization: &str) -> bool {
+    if token.is_empty() {
+        return true;
+    }
+    let mut parts = authorization.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(t)) if scheme.eq_ignore_ascii_case("bearer") => {
+            fixed_time_eq(t.trim().as_bytes(), token.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+fn update_config(controller: Option<&ConfigController>, query: &str) -> Response {
+    let controller = match controller {
+        Some(c) => c,
+        None => return Response::text("404 Not Found", "online config is not supported"),
+    };
+    let changes: Vec<_> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            (kv.next().unwrap(), kv.next().unwrap_or(""))
+        })
+        .collect();
+    match controller.update_all(&changes) {
+        Ok(()) => Response::text("200 OK", "ok"),
+        Err(e) => Response::text("400 Bad Request", e.to_string()),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct EncryptionStatus {
+    current_key_id: u64,
+    // The number of files encrypted by each data key.
+    files: BTreeMap<u64, usize>,
+}
+
+fn encryption_status(key_manager: Option<&DataKeyManager>) -> Response {
+    let key_manager = match key_manager {
+        Some(m) => m,
+        None => return Response::text("404 Not Found", "encryption is not enabled"),
+    };
+    let status = EncryptionStatus {
+        current_key_id: key_manager.current_key_id(),
+        files: key_manager.file_count_by_key().into_iter().collect(),
+    };
+    Response::new(
+        "200 OK",
+        "application/json",
+        serde_json::to_vec_pretty(&status).unwrap(),
+    )
+}
+
+fn rotate_data_key(key_manager: Option<&DataKeyManager>) -> Response {
+    let key_manager = match key_manager {
+        Some(m) => m,
+        None => return Response::text("404 Not Found", "encryption is not enabled"),
+    };
+    match key_manager.rotate_data_key() {
+        Ok(key_id) => Response::text("200 OK", key_id.to_string()),
+        Err(e) => Response::text(
+            "500 Internal Server Error",
+            format!("failed to rotate data key: {:?}", e),
+        ),
+    }
+}
+
+fn search_log(log_file: &str, query: &str) -> Response {
+    if log_file.is_empty() {
+        return Response::text("404 Not Found", "logs are not written to files");
+    }
+    let search = match LogSearch::from_query(query) {
+        Ok(s) => s,
+        Err(e) => return Response::text("400 Bad Request", format!("{:?}", e)),
+    };
+    match diagnostics::search_log(log_file, &search) {
+        Ok(mut lines) => {
+            lines.push(String::new());
+            Response::text("200 OK", lines.join("\n"))
+        }
+        Err(e) => Response::text(
+            "500 Internal Server Error",
+            format!("failed to search log: {:?}", e),
+        ),
+    }
+}
+
+fn parse_seconds(query: &str) -> ::std::result::Result<u64, String> {
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() != Some("seconds") {
+            continue;
+        }
+        let seconds = kv
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| format!("invalid seconds: {:?}", pair))?;
+        if seconds == 0 || seconds > MAX_PROFILE_SECONDS {
+            return Err(format!(
+                "seconds should be in [1, {}], got {}",
+                MAX_PROFILE_SECONDS, seconds
+            ));
+        }
+        return Ok(seconds);
+    }
+    Ok(DEFAULT_PROFILE_SECONDS)
+}
+
+fn parse_region_id(query: &str) -> ::std::result::Result<u64, String> {
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        if kv.next() != Some("id") {
+            continue;
+        }
+        return match kv.next().and_then(|v| v.parse().ok()) {
+            Some(id) if id != 0 => Ok(id),
+            _ => Err(format!("invalid region id: {:?}", pair)),
+        };
+    }
+    Err("region id is missing".to_owned())
+}
+
+/// Profiles the region for the given seconds. See `RegionProfiler` for the output.
+fn region_profile(query: &str) -> Response {
+    let (region_id, seconds) = match (parse_region_id(query), parse_seconds(query)) {
+        (Ok(id), Ok(s)) => (id, s),
+        (Err(e), _) | (_, Err(e)) => return Response::text("400 Bad Request", e),
+    };
+    // Only one region can be profiled at a time.
+    match REGION_PROFILER.profile(region_id, Duration::from_secs(seconds)) {
+        Ok(folded) => Response::text("200 OK", folded),
+        Err(e) => Response::text("409 Conflict", format!("{:?}", e)),
+    }
+}
+
+/// Samples the CPU for the given seconds. The output can be turned into a flame graph by
+/// `flamegraph.pl`.
+fn cpu_profile(query: &str) -> Response {
+    let seconds = match parse_seconds(query) {
+        Ok(s) => s,
+        Err(e) => return Response::text("400 Bad Request", e),
+    };
+    if PROFILING.compare_and_swap(false, true, Ordering::AcqRel) {
+        return Response::text("409 Conflict", "another profiling is running");
+    }
+    let res = cpu_profiler::profile(Duration::from_secs(seconds), CPU_PROFILE_FREQUENCY);
+    PROFILING.store(false, Ordering::Release);
+    match res {
+        Ok(folded) => Response::text("200 OK", folded),
+        Err(e) => Response::text(
+            "500 Internal Server Error",
+            format!("failed to profile CPU: {:?}", e),
+        ),
+    }
+}
+
+/// Samples the allocations for the given seconds and returns the heap profile in the format
+/// of `jeprof`, which can generate pprof or flamegraph outputs from it.
+fn heap_profile(query: &str) -> Response {
+    let seconds = match parse_seconds(query) {
+        Ok(s) => s,
+        Err(e) => return Response::text("400 Bad Request", e),
+    };
+    if PROFILING.compare_and_swap(false, true, Ordering::AcqRel) {
+        return Response::text("409 Conflict", "another profiling is running");
+    }
+    let res = dump_heap_profile(Duration::from_secs(seconds));
+    PROFILING.store(false, Ordering::Release);
+    match res {
+        Ok(body) => Response::new("200 OK", "application/octet-stream", body),
+        Err(e) => Response::text(
+            "500 Internal Server Error",
+            format!("failed to profile heap: {:?}", e),
+        ),
+    }
+}
+
+fn dump_heap_profile(duration: Duration) -> io::Result<Vec<u8>> {
+    jemalloc::activate_prof()?;
+    thread::sleep(duration);
+    let dir = TempDir::new("heap_profile")?;
+    let path = dir.path().join("tikv.heap");
+    let res = jemalloc::dump_prof(path.to_str().unwrap());
+    jemalloc::deactivate_prof()?;
+    res?;
+    let mut body = vec![];
+    File::open(&path)?.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::Shutdown;
+    use std::path::Path;
+
+    use super::*;
+    use server::tls;
+    use util::encryption::{EncryptionMethod, PlaintextMasterKey};
+
+    fn request(addr: SocketAddr, method: &str, path: &str) -> String {
+        request_with_token(addr, method, path, "")
+    }
+
+    fn request_with_token(addr: SocketAddr, method: &str, path: &str, token: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path).unwrap();
+        if !token.is_empty() {
+            write!(stream, "Authorization: Bearer {}\r\n", token).unwrap();
+        }
+        write!(stream, "\r\n").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    fn tls_request(
+        addr: SocketAddr,
+        cfg: &SecurityConfig,
+        method: &str,
+        path: &str,
+    ) -> io::Result<String> {
+        let mut stream = tls::connect(cfg, TcpStream::connect(addr)?)?;
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path)?;
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp)?;
+        Ok(resp)
+    }
+
+    fn new_security_cfg() -> SecurityConfig {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("components/test_util/data");
+        SecurityConfig {
+            ca_path: dir.join("ca.crt").to_str().unwrap().to_owned(),
+            cert_path: dir.join("server.crt").to_str().unwrap().to_owned(),
+            key_path: dir.join("server.pem").to_str().unwrap().to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_status_server() {
+        let cfg = TiKvConfig::default();
+        let mut server = StatusServer::new(&cfg);
+        server.start("127.0.0.1:0").unwrap();
+        let addr = server.listening_addr().unwrap();
+
+        let resp = request(addr, "GET", "/status");
+        assert!(resp.starts_with("HTTP/1.1 503"), "{}", resp);
+        server.set_ready();
+        let resp = request(addr, "GET", "/status");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nok"), "{}", resp);
+
+        let resp = request(addr, "GET", "/config");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.contains("\"log-level\": \"info\""), "{}", resp);
+
+        let resp = request(addr, "GET", "/metrics");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+
+        let resp = request(addr, "GET", "/unknown");
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+        let resp = request(addr, "POST", "/status");
+        assert!(resp.starts_with("HTTP/1.1 405"), "{}", resp);
+        // Changing the state needs authentication.
+        let resp = request(addr, "POST", "/config?rocksdb.max-background-jobs=8");
+        assert!(resp.starts_with("HTTP/1.1 403"), "{}", resp);
+        let resp = request(addr, "GET", "/debug/pprof/heap?seconds=0");
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        let resp = request(addr, "GET", "/debug/pprof/profile?seconds=0");
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        let resp = request(addr, "GET", "/debug/region/profile?seconds=1");
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        let resp = request(addr, "GET", "/debug/region/profile?id=1&seconds=1");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        // Logs are written to stderr by default.
+        let resp = request(addr, "GET", "/diagnostics/log");
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+        let resp = request(addr, "POST", "/encryption/rotate-data-key");
+        assert!(resp.starts_with("HTTP/1.1 403"), "{}", resp);
+
+        server.stop();
+    }
+
+    #[test]
+    fn test_rotate_data_key() {
+        let dir = TempDir::new("test-status-server-encryption").unwrap();
+        let master_key = Box::new(PlaintextMasterKey);
+        let key_manager =
+            DataKeyManager::new(master_key, EncryptionMethod::Aes256Ctr, dir.path()).unwrap();
+        key_manager.new_file("f1").unwrap();
+        let mut cfg = TiKvConfig::default();
+        cfg.server.status_token = "secret".to_owned();
+        let mut server = StatusServer::new(&cfg);
+        server.set_data_key_manager(Arc::new(key_manager));
+        server.start("127.0.0.1:0").unwrap();
+        let addr = server.listening_addr().unwrap();
+
+        let resp = request_with_token(addr, "POST", "/encryption/rotate-data-key", "secret");
+        assert!(resp.ends_with("\r\n\r\n2"), "{}", resp);
+        let resp = request_with_token(addr, "GET", "/encryption", "secret");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        assert!(resp.contains("\"current-key-id\": 2"), "{}", resp);
+        assert!(resp.contains("\"1\": 1"), "{}", resp);
+        assert!(resp.contains("\"2\": 0"), "{}", resp);
+
+        server.stop();
+    }
+
+    #[test]
+    fn test_status_token() {
+        let mut cfg = TiKvConfig::default();
+        cfg.server.status_token = "secret".to_owned();
+        cfg.security.encryption.kms.secret_access_key = "aws-secret".to_owned();
+        let mut server = StatusServer::new(&cfg);
+        server.start("127.0.0.1:0").unwrap();
+        let addr = server.listening_addr().unwrap();
+
+        let resp = request(addr, "GET", "/config");
+        assert!(resp.starts_with("HTTP/1.1 401"), "{}", resp);
+        let resp = request(addr, "GET", "/status");
+        assert!(resp.starts_with("HTTP/1.1 503"), "{}", resp);
+
+        let resp = request_with_token(addr, "GET", "/config", "secret");
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        // The secrets are not served.
+        assert!(!resp.contains("status-token"), "{}", resp);
+        assert!(!resp.contains("aws-secret"), "{}", resp);
+        // The token enables changing the state, the config can't be changed without a
+        // controller though.
+        let path = "/config?rocksdb.max-background-jobs=8";
+        let resp = request_with_token(addr, "POST", path, "secret");
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+
+        server.stop();
+
+        assert!(authorized("", ""));
+        assert!(authorized("secret", "Bearer secret"));
+        assert!(authorized("secret", "bearer  secret"));
+        assert!(!authorized("secret", "Bearer secret2"));
+        assert!(!authorized("secret", "Basic secret"));
+        assert!(!authorized("secret", "secret"));
+    }
+
+    #[test]
+    fn test_status_server_limits() {
+        let mut server = StatusServer::new(&TiKvConfig::default());
+        server.start("127.0.0.1:0").unwrap();
+        let addr = server.listening_addr().unwrap();
+
+        // The head is too large. Exactly the limit is sent, so that the server reads all of it
+        // and doesn't reset the connection.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut line = "GET /status?".to_owned();
+        let padding = MAX_REQUEST_HEAD_SIZE as usize - line.len();
+        line.push_str(&"a".repeat(padding));
+        stream.write_all(line.as_bytes()).unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        // The head is incomplete.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /status HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+
+        // The connections beyond the limit are closed.
+        let idle: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let _ = write!(stream, "GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut resp = vec![];
+        let _ = stream.read_to_end(&mut resp);
+        assert!(resp.is_empty(), "{:?}", resp);
+        drop(idle);
+
+        server.stop();
+    }
+
+    #[test]
+    fn test_deadline_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        let mut stream = DeadlineStream {
+            inner: socket.try_clone().unwrap(),
+            socket,
+            deadline: Instant::now() + Duration::from_millis(100),
+        };
+        let mut buf = [0; 8];
+        // The client sends nothing, so the read waits until the deadline.
+        stream.read(&mut buf).unwrap_err();
+        let e = stream.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_status_server_tls() {
+        let mut cfg = TiKvConfig::default();
+        cfg.security = new_security_cfg();
+        cfg.server.status_verify_client_cert = true;
+        let mut server = StatusServer::new(&cfg);
+        server.set_ready();
+        server.start("127.0.0.1:0").unwrap();
+        let addr = server.listening_addr().unwrap();
+
+        // The server certificate is signed by the CA, so it's a valid client certificate too.
+        let resp = tls_request(addr, &cfg.security, "GET", "/status").unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200"), "{}", resp);
+        // The client certificate enables changing the state, the data key can't be rotated
+        // without encryption though.
+        let path = "/encryption/rotate-data-key";
+        let resp = tls_request(addr, &cfg.security, "POST", path).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+
+        // A client without a certificate is rejected.
+        let mut no_cert = cfg.security.clone();
+        no_cert.cert_path.clear();
+        no_cert.key_path.clear();
+        if let Ok(resp) = tls_request(addr, &no_cert, "GET", "/status") {
+            assert!(!resp.starts_with("HTTP/1.1"), "{}", resp);
+        }
+        // Plain HTTP isn't served.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut resp = vec![];
+        let _ = stream.read_to_end(&mut resp);
+        assert!(!resp.starts_with(b"HTTP/1.1"), "{:?}", resp);
+
+        server.stop();
+    }
+
+    #[test]
+    fn test_status_server_cert_allowed_cn() {
+        let mut cfg = TiKvConfig::default();
+        cfg.security = new_security_cfg();
+        cfg.server.status_verify_client_cert = true;
+        for (allowed_cn, accepted) in vec![(vec!["example.com"], true), (vec!["tidb"], false)] {
+            cfg.security.cert_allowed_cn = allowed_cn.into_iter().map(From::from).collect();
+            let mut server = StatusServer::new(&cfg);
+            server.set_ready();
+            server.start("127.0.0.1:0").unwrap();
+            let addr = server.listening_addr().unwrap();
+
+            // The common name of the certificate is "example.com".
+            match tls_request(addr, &cfg.security, "GET", "/status") {
+                Ok(resp) => assert_eq!(resp.starts_with("HTTP/1.1 200"), accepted, "{}", resp),
+                Err(e) => assert!(!accepted, "{:?}", e),
+            }
+            server.stop();
+        }
+    }
+
+    #[test]
+    fn test_parse_seconds() {
+        assert_eq!(parse_seconds("").unwrap(), DEFAULT_PROFILE_SECONDS);
+        assert_eq!(parse_seconds("seconds=5").unwrap(), 5);
+        assert_eq!(parse_seconds("debug=1&seconds=20").unwrap(), 20);
+        assert!(parse_seconds("seconds=0").is_err());
+        assert!(parse_seconds("seconds=abc").is_err());
+        assert!(parse_seconds("seconds=1000").is_err());
+    }
+
+    #[test]
+    fn test_parse_region_id() {
+        assert_eq!(parse_region_id("id=2").unwrap(), 2);
+        assert_eq!(parse_region_id("seconds=5&id=3").unwrap(), 3);
+        assert!(parse_region_id("").is_err());
+        assert!(parse_region_id("id=0").is_err());
+        assert!(parse_region_id("id=abc").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS for the servers which don't speak gRPC, e.g. the status server.
+//!
+//! It uses the BoringSSL linked into TiKV by grpcio, so that TiKV doesn't link another TLS
+//! library, whose symbols would clash with BoringSSL's.
+
+use std::cmp;
+use std::ffi::{CStr, CString};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+use libc::{c_char, c_int, c_void, size_t};
+
+use util::security::SecurityConfig;
+
+#[allow(non_camel_case_types)]
+enum SSL_METHOD {}
+#[allow(non_camel_case_types)]
+enum SSL_CTX {}
+#[allow(non_camel_case_types)]
+enum SSL {}
+#[allow(non_camel_case_types)]
+enum X509 {}
+#[allow(non_camel_case_types)]
+enum X509_NAME {}
+
+const SSL_FILETYPE_PEM: c_int = 1;
+const SSL_VERIFY_PEER: c_int = 0x01;
+const SSL_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 0x02;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const TLS1_2_VERSION: u16 = 0x0303;
+const NID_COMMON_NAME: c_int = 13;
+
+extern "C" {
+    fn TLS_server_method() -> *const SSL_METHOD;
+    #[cfg(test)]
+    fn TLS_client_method() -> *const SSL_METHOD;
+    fn SSL_CTX_new(method: *const SSL_METHOD) -> *mut SSL_CTX;
+    fn SSL_CTX_free(ctx: *mut SSL_CTX);
+    fn SSL_CTX_set_min_proto_version(ctx: *mut SSL_CTX, version: u16) -> c_int;
+    fn SSL_CTX_use_certificate_chain_file(ctx: *mut SSL_CTX, file: *const c_char) -> c_int;
+    fn SSL_CTX_use_PrivateKey_file(ctx: *mut SSL_CTX, file: *const c_char, tp: c_int) -> c_int;
+    fn SSL_CTX_check_private_key(ctx: *const SSL_CTX) -> c_int;
+    fn SSL_CTX_load_verify_locations(
+        ctx: *mut SSL_CTX,
+        ca_file: *const c_char,
+        ca_dir: *const c_char,
+    ) -> c_int;
+    // The callback is always null, so it's declared as a plain pointer.
+    fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, callback: *const c_void);
+    fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
+    fn SSL_free(ssl: *mut SSL);
+    fn SSL_set_fd(ssl: *mut SSL, fd: c_int) -> c_int;
+    fn SSL_accept(ssl: *mut SSL) -> c_int;
+    #[cfg(test)]
+    fn SSL_connect(ssl: *mut SSL) -> c_int;
+    fn SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_shutdown(ssl: *mut SSL) -> c_int;
+    fn SSL_get_error(ssl: *const SSL, ret: c_int) -> c_int;
+    fn SSL_get_peer_certificate(ssl: *const SSL) -> *mut X509;
+    fn X509_get_subject_name(x509: *const X509) -> *mut X509_NAME;
+    fn X509_NAME_get_text_by_NID(
+        name: *const X509_NAME,
+        nid: c_int,
+        buf: *mut c_char,
+        len: c_int,
+    ) -> c_int;
+    fn X509_free(x509: *mut X509);
+    fn ERR_get_error() -> u32;
+    fn ERR_error_string_n(err: u32, buf: *mut c_char, len: size_t);
+    fn ERR_clear_error();
+}
+
+// Pops the earliest error of the thread's error queue of BoringSSL.
+fn last_error() -> String {
+    let err = unsafe { ERR_get_error() };
+    if err == 0 {
+        return "unknown error".to_owned();
+    }
+    let mut buf = [0 as c_char; 256];
+    unsafe {
+        ERR_error_string_n(err, buf.as_mut_ptr(), buf.len());
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+struct Context {
+    ctx: *mut SSL_CTX,
+}
+
+// `SSL_CTX` is safe to be shared once it's configured.
+unsafe impl Send for Context {}
+unsafe impl Sync for Context {}
+
+impl Context {
+    // Creates a context with the certificate, the private key and the CA of `cfg`, which
+    // verifies the peer's certificate with the CA if `set_verify_peer` is called.
+    fn new(method: *const SSL_METHOD, cfg: &SecurityConfig) -> Result<Context, String> {
+        let path = |tag: &str, p: &str| {
+            CString::new(p).map_err(|e| format!("invalid {} path {:?}: {:?}", tag, p, e))
+        };
+        let (ca, cert, key) = (
+            path("CA", &cfg.ca_path)?,
+            path("certificate", &cfg.cert_path)?,
+            path("private key", &cfg.key_path)?,
+        );
+        unsafe {
+            ERR_clear_error();
+            let ctx = SSL_CTX_new(method);
+            if ctx.is_null() {
+                return Err(format!("failed to create TLS context: {}", last_error()));
+            }
+            // Freed on errors.
+            let ctx = Context { ctx };
+            if SSL_CTX_set_min_proto_version(ctx.ctx, TLS1_2_VERSION) != 1 {
+                return Err(format!("failed to set TLS version: {}", last_error()));
+            }
+            // A client may have no certificate.
+            if !cfg.cert_path.is_empty() {
+                if SSL_CTX_use_certificate_chain_file(ctx.ctx, cert.as_ptr()) != 1 {
+                    return Err(format!(
+                        "failed to load certificate {}: {}",
+                        cfg.cert_path,
+                        last_error()
+                    ));
+                }
+                if SSL_CTX_use_PrivateKey_file(ctx.ctx, key.as_ptr(), SSL_FILETYPE_PEM) != 1
+                    || SSL_CTX_check_private_key(ctx.ctx) != 1
+                {
+                    return Err(format!(
+                        "failed to load private key {}: {}",
+                        cfg.key_path,
+                        last_error()
+                    ));
+                }
+            }
+            if SSL_CTX_load_verify_locations(ctx.ctx, ca.as_ptr(), ptr::null()) != 1 {
+                return Err(format!("failed to load CA {}: {}", cfg.ca_path, last_error()));
+            }
+            Ok(ctx)
+        }
+    }
+
+    fn set_verify_peer(&self) {
+        let mode = SSL_VERIFY_PEER | SSL_VERIFY_FAIL_IF_NO_PEER_CERT;
+        unsafe { SSL_CTX_set_verify(self.ctx, mode, ptr::null()) }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe { SSL_CTX_free(self.ctx) }
+    }
+}
+
+/// Accepts the TLS connections with the certificate and the private key of the `security`
+/// config. If `verify_client` is true, the clients must present a certificate signed by the
+/// CA of the config, or the handshakes fail, and its common name should be in
+/// `cert_allowed_cn` of the config if that's set.
+pub struct TlsAcceptor {
+    ctx: Context,
+    allowed_cn: Vec<String>,
+}
+
+impl TlsAcceptor {
+    pub fn new(cfg: &SecurityConfig, verify_client: bool) -> Result<TlsAcceptor, String> {
+        let ctx = Context::new(unsafe { TLS_server_method() }, cfg)?;
+        let mut allowed_cn = vec![];
+        if verify_client {
+            ctx.set_verify_peer();
+            allowed_cn = cfg.cert_allowed_cn.clone();
+        }
+        Ok(TlsAcceptor { ctx, allowed_cn })
+    }
+
+    /// Does the handshake on `stream`. The timeouts of `stream` apply to the handshake too.
+    /// A client whose certificate isn't allowed is rejected with `PermissionDenied`.
+    pub fn accept(&self, stream: TcpStream) -> io::Result<TlsStream> {
+        let s = TlsStream::new(&self.ctx, stream)?;
+        unsafe { ERR_clear_error() };
+        let ret = unsafe { SSL_accept(s.ssl) };
+        if ret != 1 {
+            return Err(s.error(ret));
+        }
+        if !self.allowed_cn.is_empty() {
+            let cn = s.peer_common_name();
+            if !cn.as_ref().map_or(false, |cn| self.allowed_cn.contains(cn)) {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("the common name {:?} of the certificate is not allowed", cn),
+                ));
+            }
+        }
+        Ok(s)
+    }
+}
+
+/// A TLS connection over a `TcpStream`.
+pub struct TlsStream {
+    ssl: *mut SSL,
+    // The socket `ssl` reads and writes, which is closed after `ssl` is freed.
+    stream: TcpStream,
+}
+
+unsafe impl Send for TlsStream {}
+
+impl TlsStream {
+    fn new(ctx: &Context, stream: TcpStream) -> io::Result<TlsStream> {
+        let ssl = unsafe { SSL_new(ctx.ctx) };
+        if ssl.is_null() {
+            return Err(io::Error::new(ErrorKind::Other, last_error()));
+        }
+        let s = TlsStream {
+            ssl,
+            stream,
+        };
+        if unsafe { SSL_set_fd(s.ssl, s.stream.as_raw_fd()) } != 1 {
+            return Err(io::Error::new(ErrorKind::Other, last_error()));
+        }
+        Ok(s)
+    }
+
+    /// The common name of the peer's certificate, `None` if it presents no certificate.
+    pub fn peer_common_name(&self) -> Option<String> {
+        // The common names are at most 64 characters.
+        let mut buf = [0u8; 256];
+        let len = unsafe {
+            let cert = SSL_get_peer_certificate(self.ssl);
+            if cert.is_null() {
+                return None;
+            }
+            let name = X509_get_subject_name(cert);
+            let len = if name.is_null() {
+                -1
+            } else {
+                let (ptr, cap) = (buf.as_mut_ptr() as *mut c_char, buf.len() as c_int);
+                X509_NAME_get_text_by_NID(name, NID_COMMON_NAME, ptr, cap)
+            };
+            X509_free(cert);
+            len
+        };
+        if len < 0 {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned())
+    }
+
+    fn error(&self, ret: c_int) -> io::Error {
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_SYSCALL if ret == 0 => {
+                io::Error::new(ErrorKind::UnexpectedEof, "connection closed")
+            }
+            // Including the timeouts of the socket.
+            SSL_ERROR_SYSCALL => io::Error::last_os_error(),
+            _ => io::Error::new(ErrorKind::Other, last_error()),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), c_int::max_value() as usize) as c_int;
+        unsafe { ERR_clear_error() };
+        let ret = unsafe { SSL_read(self.ssl, buf.as_mut_ptr() as *mut c_void, len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        if unsafe { SSL_get_error(self.ssl, ret) } == SSL_ERROR_ZERO_RETURN {
+            return Ok(0);
+        }
+        Err(self.error(ret))
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len(), c_int::max_value() as usize) as c_int;
+        unsafe { ERR_clear_error() };
+        let ret = unsafe { SSL_write(self.ssl, buf.as_ptr() as *const c_void, len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        Err(self.error(ret))
+    }
+
+    // The records are written to the socket right away.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        unsafe {
+            SSL_shutdown(self.ssl);
+            SSL_free(self.ssl);
+        }
+    }
+}
+
+/// Connects to a TLS server with the CA of `cfg`, presenting its certificate if it's set.
+#[cfg(test)]
+pub fn connect(cfg: &SecurityConfig, stream: TcpStream) -> io::Result<TlsStream> {
+    let ctx = Context::new(unsafe { TLS_client_method() }, cfg)
+        .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+    ctx.set_verify_peer();
+    let s = TlsStream::new(&ctx, stream)?;
+    unsafe { ERR_clear_error() };
+    let ret = unsafe { SSL_connect(s.ssl) };
+    if ret != 1 {
+        return Err(s.error(ret));
+    }
+    Ok(s)
+}
@@ -0,0 +1,111 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use tokio_timer::Delay;
+
+use util::future::paired_future_callback;
+use util::timer::GLOBAL_TIMER_HANDLE;
+
+use super::{Callback, EngineError, Error, Result};
+
+/// The future of an asynchronous storage command.
+///
+/// It resolves to the result of the command. Dropping it cancels the wait, but the command
+/// which has been scheduled still runs to the end.
+pub struct CommandFuture<T> {
+    // The error returned when scheduling the command.
+    err: Option<Error>,
+    rx: oneshot::Receiver<Result<T>>,
+    timeout: Option<(Duration, Delay)>,
+}
+
+impl<T: Send + 'static> CommandFuture<T> {
+    /// Schedules a command by `f` with a callback, and returns the future of its result.
+    pub fn new<F>(f: F) -> CommandFuture<T>
+    where
+        F: FnOnce(Callback<T>) -> Result<()>,
+    {
+        let (cb, rx) = paired_future_callback();
+        CommandFuture {
+            err: f(cb).err(),
+            rx,
+            timeout: None,
+        }
+    }
+
+    /// Fails the future with `EngineError::Timeout` if the command doesn't finish in `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> CommandFuture<T> {
+        let delay = GLOBAL_TIMER_HANDLE.delay(Instant::now() + timeout);
+        self.timeout = Some((timeout, delay));
+        self
+    }
+}
+
+impl<T> Future for CommandFuture<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<T, Error> {
+        if let Some(e) = self.err.take() {
+            return Err(e);
+        }
+        match self.rx.poll() {
+            Ok(Async::Ready(res)) => return res.map(Async::Ready),
+            Ok(Async::NotReady) => {}
+            Err(_) => return Err(box_err!("the callback of the command is dropped")),
+        }
+        if let Some((timeout, ref mut delay)) = self.timeout {
+            match delay.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(())) => return Err(Error::Engine(EngineError::Timeout(timeout))),
+                Err(e) => return Err(box_err!("timer error: {:?}", e)),
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_future() {
+        let f = CommandFuture::new(|cb: Callback<u64>| {
+            cb(Ok(1));
+            Ok(())
+        });
+        assert_eq!(f.wait().unwrap(), 1);
+
+        let f = CommandFuture::new(|_: Callback<u64>| Err(Error::SchedTooBusy));
+        match f.wait() {
+            Err(Error::SchedTooBusy) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let f = CommandFuture::new(|cb: Callback<u64>| {
+            tx.send(cb).unwrap();
+            Ok(())
+        }).with_timeout(Duration::from_millis(50));
+        match f.wait() {
+            Err(Error::Engine(EngineError::Timeout(_))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        drop(rx);
+    }
+}
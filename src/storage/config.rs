@@ -15,20 +15,45 @@ use std::error::Error;
 
 use sys_info;
 
-use util::config::{self, ReadableSize};
+use util::config::{self, ReadableDuration, ReadableSize};
 
 pub const DEFAULT_DATA_DIR: &str = "";
 pub const DEFAULT_ROCKSDB_SUB_DIR: &str = "db";
 const DEFAULT_GC_RATIO_THRESHOLD: f64 = 1.1;
+const DEFAULT_GC_POLL_SAFE_POINT_INTERVAL_SECS: u64 = 10;
 const DEFAULT_MAX_KEY_SIZE: usize = 4 * 1024;
 const DEFAULT_SCHED_CAPACITY: usize = 10240;
 const DEFAULT_SCHED_CONCURRENCY: usize = 2048000;
+const DEFAULT_TXN_STATUS_CACHE_CAPACITY: usize = 10240;
+const DEFAULT_REQUEST_MAX_HANDLE_SECS: u64 = 60;
+const DEFAULT_BLOCK_CACHE_NUM_SHARD_BITS: i32 = 6;
+// RocksDB refuses to create an LRU cache with 2^20 or more shards.
+const MAX_BLOCK_CACHE_NUM_SHARD_BITS: i32 = 19;
 
 // According to "Little's law", assuming you can write 100MB per
 // second, and it takes about 100ms to process the write requests
 // on average, in that situation the writing bytes estimated 10MB,
 // here we use 100MB as default value for tolerate 1s latency.
 const DEFAULT_SCHED_PENDING_WRITE_MB: u64 = 100;
+// Limits the number of pending write commands, so that the scheduler won't keep
+// accepting small writes when the engine stalls.
+const DEFAULT_SCHED_PENDING_WRITE_TASKS: usize = 10240;
+
+/// How a failed assertion of a prewrite is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssertionMode {
+    /// Aborts the prewrite.
+    Enforce,
+    /// Only logs the failure and counts it, the prewrite goes on.
+    LogOnly,
+}
+
+impl Default for AssertionMode {
+    fn default() -> AssertionMode {
+        AssertionMode::Enforce
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
@@ -36,11 +61,20 @@ const DEFAULT_SCHED_PENDING_WRITE_MB: u64 = 100;
 pub struct Config {
     pub data_dir: String,
     pub gc_ratio_threshold: f64,
+    pub gc_poll_safe_point_interval: ReadableDuration,
+    pub gc_max_write_bytes_per_sec: ReadableSize,
+    pub max_io_bytes_per_sec: ReadableSize,
     pub max_key_size: usize,
     pub scheduler_notify_capacity: usize,
     pub scheduler_concurrency: usize,
     pub scheduler_worker_pool_size: usize,
     pub scheduler_pending_write_threshold: ReadableSize,
+    pub scheduler_pending_write_tasks_threshold: usize,
+    pub txn_status_cache_capacity: usize,
+    pub assertion_mode: AssertionMode,
+    /// The reads and the commands still queued after this long are dropped. 0 means never.
+    pub request_max_handle_duration: ReadableDuration,
+    pub block_cache: BlockCacheConfig,
 }
 
 impl Default for Config {
@@ -49,11 +83,21 @@ impl Default for Config {
         Config {
             data_dir: DEFAULT_DATA_DIR.to_owned(),
             gc_ratio_threshold: DEFAULT_GC_RATIO_THRESHOLD,
+            gc_poll_safe_point_interval: ReadableDuration::secs(
+                DEFAULT_GC_POLL_SAFE_POINT_INTERVAL_SECS,
+            ),
+            gc_max_write_bytes_per_sec: ReadableSize(0),
+            max_io_bytes_per_sec: ReadableSize(0),
             max_key_size: DEFAULT_MAX_KEY_SIZE,
             scheduler_notify_capacity: DEFAULT_SCHED_CAPACITY,
             scheduler_concurrency: DEFAULT_SCHED_CONCURRENCY,
             scheduler_worker_pool_size: if total_cpu >= 16 { 8 } else { 4 },
             scheduler_pending_write_threshold: ReadableSize::mb(DEFAULT_SCHED_PENDING_WRITE_MB),
+            scheduler_pending_write_tasks_threshold: DEFAULT_SCHED_PENDING_WRITE_TASKS,
+            txn_status_cache_capacity: DEFAULT_TXN_STATUS_CACHE_CAPACITY,
+            assertion_mode: AssertionMode::Enforce,
+            request_max_handle_duration: ReadableDuration::secs(DEFAULT_REQUEST_MAX_HANDLE_SECS),
+            block_cache: BlockCacheConfig::default(),
         }
     }
 }
@@ -63,6 +107,70 @@ impl Config {
         if self.data_dir != DEFAULT_DATA_DIR {
             self.data_dir = config::canonicalize_path(&self.data_dir)?
         }
+        self.block_cache.validate()?;
         Ok(())
     }
 }
+
+/// The block cache shared by all the column families of the kv and raft engines.
+///
+/// When `shared` is enabled, the `block-cache-size` of every column family is no longer the
+/// size of its own cache, but its budget in the shared one.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BlockCacheConfig {
+    pub shared: bool,
+    /// The total capacity of the shared cache. The sum of the budgets of all the column
+    /// families is used if it's not set.
+    pub capacity: Option<ReadableSize>,
+    pub num_shard_bits: i32,
+    pub strict_capacity_limit: bool,
+}
+
+impl Default for BlockCacheConfig {
+    fn default() -> BlockCacheConfig {
+        BlockCacheConfig {
+            shared: false,
+            capacity: None,
+            num_shard_bits: DEFAULT_BLOCK_CACHE_NUM_SHARD_BITS,
+            strict_capacity_limit: false,
+        }
+    }
+}
+
+impl BlockCacheConfig {
+    pub fn validate(&self) -> Result<(), Box<Error>> {
+        if self.capacity.map_or(false, |c| c.0 == 0) {
+            return Err("storage.block-cache.capacity should be > 0".into());
+        }
+        if self.num_shard_bits < 0 || self.num_shard_bits > MAX_BLOCK_CACHE_NUM_SHARD_BITS {
+            return Err(format!(
+                "storage.block-cache.num-shard-bits should be between 0 and {}",
+                MAX_BLOCK_CACHE_NUM_SHARD_BITS
+            ).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_block_cache_config() {
+        let cfg = BlockCacheConfig::default();
+        assert!(cfg.validate().is_ok());
+
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.capacity = Some(ReadableSize(0));
+        assert!(invalid_cfg.validate().is_err());
+
+        let mut invalid_cfg = cfg.clone();
+        invalid_cfg.num_shard_bits = -1;
+        assert!(invalid_cfg.validate().is_err());
+        invalid_cfg.num_shard_bits = MAX_BLOCK_CACHE_NUM_SHARD_BITS + 1;
+        assert!(invalid_cfg.validate().is_err());
+    }
+}
@@ -130,6 +130,11 @@ pub trait Snapshot: Send + Debug + Clone + Sized {
     fn get_properties_cf(&self, _: CfName) -> Result<TablePropertiesCollection> {
         Err(Error::RocksDb("no user properties".to_owned()))
     }
+    /// Returns a version which changes whenever the data in the snapshot changes, or `None`
+    /// if the engine can't tell.
+    fn get_data_version(&self) -> Option<u64> {
+        None
+    }
 }
 
 pub trait Iterator: Send + Sized {
@@ -203,7 +208,7 @@ pub struct FlowStatistics {
 
 impl FlowStatistics {
     pub fn add(&mut self, other: &Self) {
-        self.read_bytes = self.read_keys.saturating_add(other.read_bytes);
+        self.read_bytes = self.read_bytes.saturating_add(other.read_bytes);
         self.read_keys = self.read_keys.saturating_add(other.read_keys);
     }
 }
@@ -262,6 +267,12 @@ impl Statistics {
         self.lock.processed + self.write.processed + self.data.processed
     }
 
+    pub fn total_read_bytes(&self) -> usize {
+        self.lock.flow_stats.read_bytes
+            + self.write.flow_stats.read_bytes
+            + self.data.flow_stats.read_bytes
+    }
+
     pub fn details(&self) -> Vec<(&str, Vec<(&str, usize)>)> {
         vec![
             (CF_DEFAULT, self.data.details()),
@@ -632,10 +643,10 @@ pub fn new_local_engine(path: &str, cfs: &[CfName]) -> Result<RocksEngine> {
     let cfg_rocksdb = config::DbConfig::default();
     for cf in cfs {
         let cf_opt = match *cf {
-            CF_DEFAULT => CFOptions::new(CF_DEFAULT, cfg_rocksdb.defaultcf.build_opt()),
-            CF_LOCK => CFOptions::new(CF_LOCK, cfg_rocksdb.lockcf.build_opt()),
-            CF_WRITE => CFOptions::new(CF_WRITE, cfg_rocksdb.writecf.build_opt()),
-            CF_RAFT => CFOptions::new(CF_RAFT, cfg_rocksdb.raftcf.build_opt()),
+            CF_DEFAULT => CFOptions::new(CF_DEFAULT, cfg_rocksdb.defaultcf.build_opt(&None)),
+            CF_LOCK => CFOptions::new(CF_LOCK, cfg_rocksdb.lockcf.build_opt(&None)),
+            CF_WRITE => CFOptions::new(CF_WRITE, cfg_rocksdb.writecf.build_opt(&None)),
+            CF_RAFT => CFOptions::new(CF_RAFT, cfg_rocksdb.raftcf.build_opt(&None)),
             _ => CFOptions::new(*cf, ColumnFamilyOptions::new()),
         };
         cfs_opts.push(cf_opt);
@@ -693,6 +704,7 @@ pub mod tests {
     use super::SEEK_BOUND;
     use super::*;
     use kvproto::kvrpcpb::Context;
+    use std::usize;
     use storage::{CfName, Key, CF_DEFAULT};
     use tempdir::TempDir;
     use util::codec::bytes;
@@ -1124,6 +1136,28 @@ pub mod tests {
         engine.write(&Context::new(), vec![]).unwrap_err();
     }
 
+    #[test]
+    fn test_flow_statistics() {
+        let mut stats = FlowStatistics::default();
+        stats.add(&FlowStatistics {
+            read_keys: 1,
+            read_bytes: 10,
+        });
+        stats.add(&FlowStatistics {
+            read_keys: 2,
+            read_bytes: 20,
+        });
+        assert_eq!(stats.read_keys, 3);
+        assert_eq!(stats.read_bytes, 30);
+
+        stats.add(&FlowStatistics {
+            read_keys: usize::MAX,
+            read_bytes: usize::MAX,
+        });
+        assert_eq!(stats.read_keys, usize::MAX);
+        assert_eq!(stats.read_bytes, usize::MAX);
+    }
+
     #[test]
     fn test_statistics() {
         let dir = TempDir::new("rocksdb_statistics_test").unwrap();
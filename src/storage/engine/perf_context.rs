@@ -22,6 +22,25 @@ pub struct PerfStatisticsFields {
     pub block_cache_hit_count: usize,
     pub block_read_count: usize,
     pub block_read_byte: usize,
+    pub get_from_memtable_count: usize,
+    pub seek_on_memtable_count: usize,
+    pub seek_child_seek_count: usize,
+}
+
+impl PerfStatisticsFields {
+    /// Returns the fields with their metric names, which are used as the metric labels.
+    pub fn labeled_values(&self) -> [(&'static str, usize); 8] {
+        [
+            ("internal_key_skipped_count", self.internal_key_skipped_count),
+            ("internal_delete_skipped_count", self.internal_delete_skipped_count),
+            ("block_cache_hit_count", self.block_cache_hit_count),
+            ("block_read_count", self.block_read_count),
+            ("block_read_byte", self.block_read_byte),
+            ("get_from_memtable_count", self.get_from_memtable_count),
+            ("seek_on_memtable_count", self.seek_on_memtable_count),
+            ("seek_child_seek_count", self.seek_child_seek_count),
+        ]
+    }
 }
 
 /// Store statistics we need. Data comes from RocksDB's `PerfContext`.
@@ -39,6 +58,9 @@ impl PerfStatisticsInstant {
             block_cache_hit_count: perf_context.block_cache_hit_count() as usize,
             block_read_count: perf_context.block_read_count() as usize,
             block_read_byte: perf_context.block_read_byte() as usize,
+            get_from_memtable_count: perf_context.get_from_memtable_count() as usize,
+            seek_on_memtable_count: perf_context.seek_on_memtable_count() as usize,
+            seek_child_seek_count: perf_context.seek_child_seek_count() as usize,
         })
     }
 
@@ -94,6 +116,9 @@ mod tests {
             block_cache_hit_count: 3,
             block_read_count: 4,
             block_read_byte: 5,
+            get_from_memtable_count: 6,
+            seek_on_memtable_count: 7,
+            seek_child_seek_count: 8,
         };
         let f2 = PerfStatisticsFields {
             internal_key_skipped_count: 2,
@@ -101,11 +126,15 @@ mod tests {
             block_cache_hit_count: 5,
             block_read_count: 7,
             block_read_byte: 11,
+            get_from_memtable_count: 13,
+            seek_on_memtable_count: 17,
+            seek_child_seek_count: 19,
         };
         let f3 = f1 + f2;
         assert_eq!(f3.internal_key_skipped_count, 3);
         assert_eq!(f3.block_cache_hit_count, 8);
         assert_eq!(f3.block_read_byte, 16);
+        assert_eq!(f3.seek_child_seek_count, 27);
 
         let mut f3 = f1;
         f3 += f2;
@@ -133,9 +162,25 @@ mod tests {
             block_cache_hit_count: 3,
             block_read_count: 4,
             block_read_byte: 5,
+            get_from_memtable_count: 6,
+            seek_on_memtable_count: 7,
+            seek_child_seek_count: 8,
         });
         assert_eq!(stats.block_cache_hit_count, 3);
         stats.block_cache_hit_count = 6;
         assert_eq!(stats.block_cache_hit_count, 6);
     }
+
+    #[test]
+    fn test_labeled_values() {
+        let stats = PerfStatisticsFields {
+            block_read_count: 4,
+            get_from_memtable_count: 6,
+            ..Default::default()
+        };
+        let values = stats.labeled_values();
+        assert!(values.contains(&("block_read_count", 4)));
+        assert!(values.contains(&("get_from_memtable_count", 6)));
+        assert_eq!(values.iter().map(|&(_, v)| v).sum::<usize>(), 10);
+    }
 }
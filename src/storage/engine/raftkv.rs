@@ -437,6 +437,10 @@ impl Snapshot for RegionSnapshot {
     fn get_properties_cf(&self, cf: CfName) -> engine::Result<TablePropertiesCollection> {
         RegionSnapshot::get_properties_cf(self, cf).map_err(|e| e.into())
     }
+
+    fn get_data_version(&self) -> Option<u64> {
+        self.get_apply_index().ok()
+    }
 }
 
 impl EngineIterator for RegionIterator {
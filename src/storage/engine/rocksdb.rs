@@ -15,13 +15,15 @@ use super::{
     Callback, CbContext, Cursor, Engine, Error, Iterator as EngineIterator, Modify, Result,
     ScanMode, Snapshot, TEMP_DIR,
 };
+use engine::{KvEngine, RocksEngine as KvRocksEngine, WriteBatch as KvWriteBatch};
 use kvproto::kvrpcpb::Context;
 use raftstore::store::engine::{IterOption, Peekable};
-use rocksdb::{DBIterator, SeekKey, Writable, WriteBatch, DB};
+use raftstore::Error as RaftStoreError;
+use rocksdb::{DBIterator, SeekKey, DB};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
-use storage::{CfName, Key, Value, CF_DEFAULT};
+use storage::{CfName, Key, Value};
 use tempdir::TempDir;
 use util::escape;
 use util::rocksdb;
@@ -44,16 +46,13 @@ impl Display for Task {
     }
 }
 
-struct Runner(Arc<DB>);
+struct Runner(KvRocksEngine);
 
 impl Runnable<Task> for Runner {
     fn run(&mut self, t: Task) {
         match t {
             Task::Write(modifies, cb) => cb((CbContext::new(), write_modifies(&self.0, modifies))),
-            Task::Snapshot(cb) => cb((
-                CbContext::new(),
-                Ok(RocksSnapshot::new(Arc::clone(&self.0))),
-            )),
+            Task::Snapshot(cb) => cb((CbContext::new(), Ok(self.0.snapshot().into_sync()))),
         }
     }
 }
@@ -95,7 +94,7 @@ impl RocksEngine {
         };
         let mut worker = Worker::new("engine-rocksdb");
         let db = Arc::new(rocksdb::new_engine(&path, cfs, cfs_opts)?);
-        box_try!(worker.start(Runner(Arc::clone(&db))));
+        box_try!(worker.start(Runner(KvRocksEngine::from_db(Arc::clone(&db)))));
         Ok(RocksEngine {
             sched: worker.scheduler(),
             core: Arc::new(Mutex::new(RocksEngineCore { temp_dir, worker })),
@@ -125,26 +124,18 @@ impl Debug for RocksEngine {
     }
 }
 
-fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
-    let wb = WriteBatch::new();
+fn write_modifies<E: KvEngine>(engine: &E, modifies: Vec<Modify>) -> Result<()> {
+    let mut wb = engine.write_batch();
     for rev in modifies {
-        let res = match rev {
-            Modify::Delete(cf, k) => if cf == CF_DEFAULT {
-                trace!("RocksEngine: delete {}", k);
-                wb.delete(k.as_encoded())
-            } else {
+        match rev {
+            Modify::Delete(cf, k) => {
                 trace!("RocksEngine: delete_cf {} {}", cf, k);
-                let handle = rocksdb::get_cf_handle(db, cf)?;
-                wb.delete_cf(handle, k.as_encoded())
-            },
-            Modify::Put(cf, k, v) => if cf == CF_DEFAULT {
-                trace!("RocksEngine: put {},{}", k, escape(&v));
-                wb.put(k.as_encoded(), &v)
-            } else {
+                wb.delete_cf(cf, k.as_encoded()).map_err(engine_error)?;
+            }
+            Modify::Put(cf, k, v) => {
                 trace!("RocksEngine: put_cf {}, {}, {}", cf, k, escape(&v));
-                let handle = rocksdb::get_cf_handle(db, cf)?;
-                wb.put_cf(handle, k.as_encoded(), &v)
-            },
+                wb.put_cf(cf, k.as_encoded(), &v).map_err(engine_error)?;
+            }
             Modify::DeleteRange(cf, start_key, end_key) => {
                 trace!(
                     "RocksEngine: delete_range_cf {}, {}, {}",
@@ -152,18 +143,19 @@ fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
                     escape(start_key.as_encoded()),
                     escape(end_key.as_encoded())
                 );
-                let handle = rocksdb::get_cf_handle(db, cf)?;
-                wb.delete_range_cf(handle, start_key.as_encoded(), end_key.as_encoded())
+                wb.delete_range_cf(cf, start_key.as_encoded(), end_key.as_encoded())
+                    .map_err(engine_error)?;
             }
-        };
-        if let Err(msg) = res {
-            return Err(Error::RocksDb(msg));
         }
     }
-    if let Err(msg) = db.write(wb) {
-        return Err(Error::RocksDb(msg));
+    engine.write(wb, false).map_err(engine_error)
+}
+
+fn engine_error(e: RaftStoreError) -> Error {
+    match e {
+        RaftStoreError::RocksDb(msg) => Error::RocksDb(msg),
+        e => box_err!(e),
     }
-    Ok(())
 }
 
 impl Engine for RocksEngine {
@@ -0,0 +1,342 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::Future;
+use kvproto::kvrpcpb::Context;
+use kvproto::metapb;
+use pd::{ClusterWatcher, PdClient};
+use raftstore::store::SeekRegionResult;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::engine::{Engine, RegionInfoProvider};
+use super::gc_worker::GCWorker;
+use super::metrics::*;
+use super::{Error, Result};
+
+/// The max number of regions `seek_region` may skip in a single call.
+const SEEK_REGION_LIMIT: u32 = 128;
+/// How long to wait before retrying when the `GCWorker` is too busy to accept a task.
+const GC_WORKER_BUSY_RETRY_INTERVAL_MS: u64 = 500;
+
+/// Provides the GC safe point, which is usually maintained by PD.
+pub trait GCSafePointProvider: Send + 'static {
+    fn get_safe_point(&self) -> Result<u64>;
+}
+
+impl<T: PdClient + 'static> GCSafePointProvider for Arc<T> {
+    fn get_safe_point(&self) -> Result<u64> {
+        self.get_gc_safe_point()
+            .wait()
+            .map_err(|e| box_err!("failed to get gc safe point from pd: {:?}", e))
+    }
+}
+
+/// Uses the safe point watched from PD, which is 0 before it's learned.
+impl GCSafePointProvider for ClusterWatcher {
+    fn get_safe_point(&self) -> Result<u64> {
+        Ok(ClusterWatcher::get_safe_point(self))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GCManagerConfig {
+    /// How often to poll the safe point.
+    pub poll_safe_point_interval: Duration,
+}
+
+/// Marks that the `GCManager` is asked to stop.
+struct Stopped;
+
+/// `GCManager` watches the GC safe point and, once it advances, sends GC tasks to the
+/// `GCWorker` for every region whose leader is on this TiKV.
+///
+/// Regions are handled one by one, and the write flow of GC is limited by the `GCWorker`
+/// itself, so the GC won't disturb the foreground requests too much.
+pub struct GCManager<S: GCSafePointProvider, R: RegionInfoProvider, E: Engine> {
+    cfg: GCManagerConfig,
+    safe_point_provider: S,
+    region_info_provider: R,
+    worker: GCWorker<E>,
+    /// The safe point that all leader regions have been collected with.
+    safe_point: u64,
+    stop_rx: Option<Receiver<()>>,
+}
+
+impl<S: GCSafePointProvider, R: RegionInfoProvider, E: Engine> GCManager<S, R, E> {
+    pub fn new(
+        cfg: GCManagerConfig,
+        safe_point_provider: S,
+        region_info_provider: R,
+        worker: GCWorker<E>,
+    ) -> GCManager<S, R, E> {
+        GCManager {
+            cfg,
+            safe_point_provider,
+            region_info_provider,
+            worker,
+            safe_point: 0,
+            stop_rx: None,
+        }
+    }
+
+    /// Starts the manager in a new thread. The returned handle is used to stop it.
+    pub fn start(mut self) -> Result<GCManagerHandle> {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        self.stop_rx = Some(stop_rx);
+        let join_handle = thread::Builder::new()
+            .name(thd_name!("gc-manager"))
+            .spawn(move || self.run())
+            .map_err(|e| box_err!("failed to start gc manager: {:?}", e))?;
+        Ok(GCManagerHandle {
+            join_handle,
+            stop_tx,
+        })
+    }
+
+    fn run(&mut self) {
+        info!("gc manager started");
+        while self.wait(self.cfg.poll_safe_point_interval).is_ok() {
+            let safe_point = match self.safe_point_provider.get_safe_point() {
+                Ok(sp) => sp,
+                Err(e) => {
+                    error!("gc manager failed to get safe point: {:?}", e);
+                    continue;
+                }
+            };
+            if safe_point < self.safe_point {
+                warn!(
+                    "gc manager: safe point {} is less than the last one {}, ignore it",
+                    safe_point, self.safe_point
+                );
+                continue;
+            }
+            if safe_point == self.safe_point {
+                continue;
+            }
+
+            info!("gc manager: start gc with safe point {}", safe_point);
+            GC_SAFE_POINT_GAUGE.set(safe_point as i64);
+            match self.gc_all_regions(safe_point) {
+                Ok(true) => {
+                    info!("gc manager: finished gc with safe point {}", safe_point);
+                    self.safe_point = safe_point;
+                }
+                Ok(false) => {}
+                Err(Stopped) => break,
+            }
+        }
+        info!("gc manager stopped");
+    }
+
+    /// Runs GC on all regions whose leader is on this TiKV. Returns `Ok(false)` if it failed to
+    /// walk through all regions, so that the same safe point will be tried again next time.
+    fn gc_all_regions(&mut self, safe_point: u64) -> ::std::result::Result<bool, Stopped> {
+        let mut key = vec![];
+        loop {
+            self.check_stopped()?;
+            let res = self.region_info_provider.seek_region(
+                &key,
+                box |peer| peer.is_leader(),
+                SEEK_REGION_LIMIT,
+            );
+            match res {
+                Ok(SeekRegionResult::Found { local_peer, region }) => {
+                    self.gc_region(&region, local_peer, safe_point)?;
+                    if region.get_end_key().is_empty() {
+                        return Ok(true);
+                    }
+                    key = region.get_end_key().to_vec();
+                }
+                Ok(SeekRegionResult::LimitExceeded { next_key }) => key = next_key,
+                Ok(SeekRegionResult::Ended) => return Ok(true),
+                Err(e) => {
+                    error!("gc manager failed to seek region from {:?}: {:?}", key, e);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    fn gc_region(
+        &self,
+        region: &metapb::Region,
+        peer: metapb::Peer,
+        safe_point: u64,
+    ) -> ::std::result::Result<(), Stopped> {
+        let mut ctx = Context::new();
+        ctx.set_region_id(region.get_id());
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(peer);
+
+        loop {
+            let (tx, rx) = mpsc::channel();
+            let res = self.worker.async_gc(
+                ctx.clone(),
+                safe_point,
+                box move |res| {
+                    let _ = tx.send(res);
+                },
+            );
+            let res = match res {
+                Ok(()) => rx
+                    .recv()
+                    .unwrap_or_else(|e| Err(box_err!("gc callback dropped: {:?}", e))),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(()) => {
+                    GC_MANAGER_REGION_COUNTER_VEC
+                        .with_label_values(&["success"])
+                        .inc();
+                    return Ok(());
+                }
+                Err(Error::GCWorkerTooBusy) => {
+                    self.wait(Duration::from_millis(GC_WORKER_BUSY_RETRY_INTERVAL_MS))?
+                }
+                Err(e) => {
+                    warn!(
+                        "gc manager failed to gc region {}: {:?}",
+                        region.get_id(),
+                        e
+                    );
+                    GC_MANAGER_REGION_COUNTER_VEC
+                        .with_label_values(&["failed"])
+                        .inc();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn check_stopped(&self) -> ::std::result::Result<(), Stopped> {
+        match self.stop_rx.as_ref().unwrap().try_recv() {
+            Err(TryRecvError::Empty) => Ok(()),
+            _ => Err(Stopped),
+        }
+    }
+
+    /// Sleeps for `timeout`, and wakes up early if the manager is asked to stop.
+    fn wait(&self, timeout: Duration) -> ::std::result::Result<(), Stopped> {
+        match self.stop_rx.as_ref().unwrap().recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            _ => Err(Stopped),
+        }
+    }
+}
+
+/// Used to stop a started `GCManager`.
+pub struct GCManagerHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl GCManagerHandle {
+    pub fn stop(self) -> Result<()> {
+        let _ = self.stop_tx.send(());
+        self.join_handle
+            .join()
+            .map_err(|e| box_err!("failed to join gc manager thread: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raftstore::store::SeekRegionFilter;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use storage::engine::{self, new_local_engine, TEMP_DIR};
+    use storage::ALL_CFS;
+
+    #[derive(Clone)]
+    struct MockSafePointProvider(Arc<Mutex<u64>>);
+
+    impl GCSafePointProvider for MockSafePointProvider {
+        fn get_safe_point(&self) -> Result<u64> {
+            Ok(*self.0.lock().unwrap())
+        }
+    }
+
+    /// Provides regions `["", "b")`, `["b", "c")` and `["c", "")`, and records the keys it is
+    /// asked to seek from.
+    #[derive(Clone)]
+    struct MockRegionInfoProvider(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl RegionInfoProvider for MockRegionInfoProvider {
+        fn seek_region(
+            &self,
+            from: &[u8],
+            _: SeekRegionFilter,
+            _: u32,
+        ) -> engine::Result<SeekRegionResult> {
+            self.0.lock().unwrap().push(from.to_vec());
+            let bounds: &[(&[u8], &[u8])] = &[(b"", b"b"), (b"b", b"c"), (b"c", b"")];
+            for (id, &(start, end)) in bounds.iter().enumerate() {
+                if end.is_empty() || from < end {
+                    let mut region = metapb::Region::new();
+                    region.set_id(id as u64 + 1);
+                    region.set_start_key(start.to_vec());
+                    region.set_end_key(end.to_vec());
+                    return Ok(SeekRegionResult::Found {
+                        local_peer: metapb::Peer::new(),
+                        region,
+                    });
+                }
+            }
+            Ok(SeekRegionResult::Ended)
+        }
+    }
+
+    #[test]
+    fn test_gc_manager() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let mut worker = GCWorker::new(engine, 1.1, 0);
+        worker.start().unwrap();
+
+        let safe_point = Arc::new(Mutex::new(0));
+        let seeks = Arc::new(Mutex::new(vec![]));
+        let cfg = GCManagerConfig {
+            poll_safe_point_interval: Duration::from_millis(10),
+        };
+        let handle = GCManager::new(
+            cfg,
+            MockSafePointProvider(Arc::clone(&safe_point)),
+            MockRegionInfoProvider(Arc::clone(&seeks)),
+            worker.clone(),
+        ).start()
+            .unwrap();
+
+        // Nothing to do until the safe point advances.
+        thread::sleep(Duration::from_millis(100));
+        assert!(seeks.lock().unwrap().is_empty());
+
+        *safe_point.lock().unwrap() = 10;
+        let start = Instant::now();
+        while seeks.lock().unwrap().len() < 3 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // The same safe point won't be processed twice.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            *seeks.lock().unwrap(),
+            vec![b"".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+
+        handle.stop().unwrap();
+        worker.stop().unwrap();
+    }
+}
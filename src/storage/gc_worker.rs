@@ -11,11 +11,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::engine::{Engine, Error as EngineError, ScanMode, StatisticsSummary};
+use super::engine::{Engine, Error as EngineError, Modify, ScanMode, StatisticsSummary};
 use super::metrics::*;
-use super::mvcc::{MvccReader, MvccTxn};
+use super::mvcc::{Lock, MvccReader, MvccTxn};
 use super::{Callback, Error, Key, Result, CF_DEFAULT, CF_LOCK, CF_WRITE};
-use kvproto::kvrpcpb::Context;
+use kvproto::kvrpcpb::{Context, LockInfo};
+use raftstore::store::engine::Iterable;
 use raftstore::store::keys;
 use raftstore::store::msg::Msg as RaftStoreMsg;
 use raftstore::store::util::delete_all_in_range_cf;
@@ -23,9 +24,12 @@ use rocksdb::rocksdb::DB;
 use server::transport::{RaftStoreRouter, ServerRaftStoreRouter};
 use std::fmt::{self, Display, Formatter};
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use util::io_limiter::{self, IOLimiter, IOType};
 use util::rocksdb::get_cf_handle;
+use util::escape;
 use util::time::{duration_to_sec, SlowTimer};
 use util::worker::{self, Builder, Runnable, ScheduleError, Worker};
 
@@ -56,13 +60,22 @@ enum GCTask {
         end_key: Key,
         callback: Callback<()>,
     },
+    PhysicalScanLock {
+        ctx: Context,
+        max_ts: u64,
+        start_key: Key,
+        limit: usize,
+        callback: Callback<Vec<LockInfo>>,
+    },
 }
 
 impl GCTask {
-    pub fn take_callback(self) -> Callback<()> {
+    /// Finishes the task with `err` without running it.
+    pub fn fail(self, err: Error) {
         match self {
-            GCTask::GC { callback, .. } => callback,
-            GCTask::UnsafeDestroyRange { callback, .. } => callback,
+            GCTask::GC { callback, .. } => callback(Err(err)),
+            GCTask::UnsafeDestroyRange { callback, .. } => callback(Err(err)),
+            GCTask::PhysicalScanLock { callback, .. } => callback(Err(err)),
         }
     }
 
@@ -70,6 +83,7 @@ impl GCTask {
         match self {
             GCTask::GC { .. } => "gc",
             GCTask::UnsafeDestroyRange { .. } => "unsafe_destroy_range",
+            GCTask::PhysicalScanLock { .. } => "physical_scan_lock",
         }
     }
 }
@@ -94,6 +108,17 @@ impl Display for GCTask {
                 .field("start_key", &format!("{}", start_key))
                 .field("end_key", &format!("{}", end_key))
                 .finish(),
+            GCTask::PhysicalScanLock {
+                max_ts,
+                start_key,
+                limit,
+                ..
+            } => f
+                .debug_struct("PhysicalScanLock")
+                .field("max_ts", max_ts)
+                .field("start_key", &format!("{}", start_key))
+                .field("limit", limit)
+                .finish(),
         }
     }
 }
@@ -105,6 +130,9 @@ struct GCRunner<E: Engine> {
     raft_store_router: Option<ServerRaftStoreRouter>,
 
     ratio_threshold: f64,
+    use_delete_range: bool,
+    /// Limits the write flow of GC, `None` means unlimited.
+    limiter: Option<IOLimiter>,
 
     stats: StatisticsSummary,
 }
@@ -115,12 +143,21 @@ impl<E: Engine> GCRunner<E> {
         local_storage: Option<Arc<DB>>,
         raft_store_router: Option<ServerRaftStoreRouter>,
         ratio_threshold: f64,
+        use_delete_range: bool,
+        max_write_bytes_per_sec: u64,
     ) -> Self {
+        let limiter = if max_write_bytes_per_sec > 0 {
+            Some(IOLimiter::new(max_write_bytes_per_sec))
+        } else {
+            None
+        };
         Self {
             engine,
             local_storage,
             raft_store_router,
             ratio_threshold,
+            use_delete_range,
+            limiter,
             stats: StatisticsSummary::default(),
         }
     }
@@ -224,6 +261,17 @@ impl<E: Engine> GCRunner<E> {
 
         let modifies = txn.into_modifies();
         if !modifies.is_empty() {
+            let size = modifies_size(&modifies);
+            io_limiter::request_io(IOType::Gc, size);
+            if let Some(ref limiter) = self.limiter {
+                let mut bytes = size as i64;
+                let single = limiter.get_max_bytes_per_time();
+                while bytes > 0 {
+                    let request = if bytes > single { single } else { bytes };
+                    limiter.request(request);
+                    bytes -= request;
+                }
+            }
             self.engine.write(ctx, modifies)?;
         }
         Ok(next_scan_key)
@@ -271,6 +319,22 @@ impl<E: Engine> GCRunner<E> {
             start_key, end_key
         );
 
+        if !end_key.as_encoded().is_empty() {
+            if start_key == end_key {
+                info!("unsafe destroy range: the range is empty, nothing to do");
+                return Ok(());
+            }
+            if start_key > end_key {
+                let e: Error = box_err!(
+                    "unsafe destroy range failed: invalid range [{}, {})",
+                    start_key,
+                    end_key
+                );
+                warn!("{:?}", e);
+                return Err(e);
+            }
+        }
+
         // TODO: Refine usage of errors
 
         let local_storage = self.local_storage.as_ref().ok_or_else(|| {
@@ -285,7 +349,11 @@ impl<E: Engine> GCRunner<E> {
         let start_data_key = keys::data_key(start_key.as_encoded());
         let end_data_key = keys::data_end_key(end_key.as_encoded());
 
-        let cfs = &[CF_LOCK, CF_DEFAULT, CF_WRITE];
+        // Locks and writes refer to values in the default CF, so the default CF must be
+        // cleaned up last. Otherwise a reader or a snapshot generator working on the range
+        // concurrently may find a write whose value is missing, which is treated as data
+        // corruption.
+        let cfs = &[CF_LOCK, CF_WRITE, CF_DEFAULT];
 
         // First, call delete_files_in_range to free as much disk space as possible
         let delete_files_start_time = Instant::now();
@@ -311,8 +379,13 @@ impl<E: Engine> GCRunner<E> {
         // Then, delete all remaining keys in the range.
         let cleanup_all_start_time = Instant::now();
         for cf in cfs {
-            // TODO: set use_delete_range with config here.
-            delete_all_in_range_cf(local_storage, cf, &start_data_key, &end_data_key, false)
+            delete_all_in_range_cf(
+                local_storage,
+                cf,
+                &start_data_key,
+                &end_data_key,
+                self.use_delete_range,
+            )
                 .map_err(|e| {
                     let e: Error = box_err!(e);
                     warn!(
@@ -349,12 +422,70 @@ impl<E: Engine> GCRunner<E> {
         Ok(())
     }
 
+    /// Scans at most `limit` locks whose ts <= `max_ts` from `start_key` directly on the local
+    /// RocksDB, bypassing the Raft layer. So locks of all regions on this TiKV are returned,
+    /// no matter whether they are leaders, and some of them may be stale. 0 means no limit.
+    fn physical_scan_lock(
+        &self,
+        _: &Context,
+        max_ts: u64,
+        start_key: &Key,
+        limit: usize,
+    ) -> Result<Vec<LockInfo>> {
+        let local_storage = self.local_storage.as_ref().ok_or_else(|| {
+            let e: Error = box_err!("physical scan lock not supported: local_storage not set");
+            warn!("physical scan lock failed: {:?}", &e);
+            e
+        })?;
+
+        let start_data_key = keys::data_key(start_key.as_encoded());
+        let mut locks = vec![];
+        let mut err = None;
+        box_try!(local_storage.scan_cf(
+            CF_LOCK,
+            &start_data_key,
+            keys::DATA_MAX_KEY,
+            false,
+            |key, value| {
+                let lock = match Lock::parse(value) {
+                    Ok(lock) => lock,
+                    Err(e) => {
+                        err = Some(Error::from(e));
+                        return Ok(false);
+                    }
+                };
+                if lock.ts > max_ts {
+                    return Ok(true);
+                }
+                let key = match Key::from_encoded_slice(keys::origin_key(key)).into_raw() {
+                    Ok(key) => key,
+                    Err(e) => {
+                        err = Some(box_err!("invalid lock key {}: {:?}", escape(key), e));
+                        return Ok(false);
+                    }
+                };
+                let mut lock_info = LockInfo::new();
+                lock_info.set_primary_lock(lock.primary);
+                lock_info.set_lock_version(lock.ts);
+                lock_info.set_key(key);
+                lock_info.set_lock_ttl(lock.ttl);
+                locks.push(lock_info);
+                Ok(limit == 0 || locks.len() < limit)
+            }
+        ));
+        match err {
+            Some(e) => Err(e),
+            None => Ok(locks),
+        }
+    }
+
     fn handle_gc_worker_task(&mut self, mut task: GCTask) {
         let label = task.get_label();
         GC_GCTASK_COUNTER_VEC.with_label_values(&[label]).inc();
 
         let timer = SlowTimer::from_secs(GC_TASK_SLOW_SECONDS);
 
+        let mut locks = vec![];
         let result = match &mut task {
             GCTask::GC {
                 ctx, safe_point, ..
@@ -365,6 +496,15 @@ impl<E: Engine> GCRunner<E> {
                 end_key,
                 ..
             } => self.unsafe_destroy_range(ctx, start_key, end_key),
+            GCTask::PhysicalScanLock {
+                ctx,
+                max_ts,
+                start_key,
+                limit,
+                ..
+            } => self
+                .physical_scan_lock(ctx, *max_ts, start_key, *limit)
+                .map(|res| locks = res),
         };
 
         GC_TASK_DURATION_HISTOGRAM_VEC
@@ -375,7 +515,12 @@ impl<E: Engine> GCRunner<E> {
         if result.is_err() {
             GC_GCTASK_FAIL_COUNTER_VEC.with_label_values(&[label]).inc();
         }
-        (task.take_callback())(result);
+        match task {
+            GCTask::GC { callback, .. } | GCTask::UnsafeDestroyRange { callback, .. } => {
+                callback(result)
+            }
+            GCTask::PhysicalScanLock { callback, .. } => callback(result.map(|()| locks)),
+        }
     }
 }
 
@@ -406,6 +551,17 @@ impl<E: Engine> Runnable<GCTask> for GCRunner<E> {
     }
 }
 
+fn modifies_size(modifies: &[Modify]) -> usize {
+    modifies
+        .iter()
+        .map(|m| match *m {
+            Modify::Delete(_, ref k) => k.as_encoded().len(),
+            Modify::Put(_, ref k, ref v) => k.as_encoded().len() + v.len(),
+            Modify::DeleteRange(_, ref s, ref e) => s.as_encoded().len() + e.as_encoded().len(),
+        })
+        .sum()
+}
+
 /// `GCWorker` is used to schedule GC operations
 #[derive(Clone)]
 pub struct GCWorker<E: Engine> {
@@ -416,13 +572,19 @@ pub struct GCWorker<E: Engine> {
     raft_store_router: Option<ServerRaftStoreRouter>,
 
     ratio_threshold: f64,
+    /// Whether to use RocksDB's `DeleteRange` when destroying ranges.
+    use_delete_range: bool,
+    max_write_bytes_per_sec: u64,
 
     worker: Arc<Mutex<Worker<GCTask>>>,
     worker_scheduler: worker::Scheduler<GCTask>,
+
+    /// The max safe point GC is requested with, the versions before it may be deleted.
+    safe_point: Arc<AtomicU64>,
 }
 
 impl<E: Engine> GCWorker<E> {
-    pub fn new(engine: E, ratio_threshold: f64) -> GCWorker<E> {
+    pub fn new(engine: E, ratio_threshold: f64, max_write_bytes_per_sec: u64) -> GCWorker<E> {
         let worker = Arc::new(Mutex::new(
             Builder::new("gc-worker")
                 .pending_capacity(GC_MAX_PENDING_TASKS)
@@ -434,8 +596,11 @@ impl<E: Engine> GCWorker<E> {
             local_storage: None,
             raft_store_router: None,
             ratio_threshold,
+            use_delete_range: false,
+            max_write_bytes_per_sec,
             worker,
             worker_scheduler,
+            safe_point: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -452,12 +617,21 @@ impl<E: Engine> GCWorker<E> {
         self.raft_store_router = Some(router);
     }
 
+    /// This method should be called before `start`.
+    /// Set whether `destroy_range` cleans up the remaining keys with RocksDB's `DeleteRange`,
+    /// which should follow the `use_delete_range` option of raftstore.
+    pub fn set_use_delete_range(&mut self, use_delete_range: bool) {
+        self.use_delete_range = use_delete_range;
+    }
+
     pub fn start(&mut self) -> Result<()> {
         let runner = GCRunner::new(
             self.engine.clone(),
             self.local_storage.take(),
             self.raft_store_router.take(),
             self.ratio_threshold,
+            self.use_delete_range,
+            self.max_write_bytes_per_sec,
         );
         self.worker
             .lock()
@@ -479,7 +653,7 @@ impl<E: Engine> GCWorker<E> {
         match e {
             ScheduleError::Full(task) => {
                 GC_TOO_BUSY_COUNTER.inc();
-                (task.take_callback())(Err(Error::GCWorkerTooBusy));
+                task.fail(Error::GCWorkerTooBusy);
                 Ok(())
             }
             _ => Err(box_err!("failed to schedule gc task: {:?}", e)),
@@ -487,6 +661,16 @@ impl<E: Engine> GCWorker<E> {
     }
 
     pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+        let mut current = self.safe_point.load(Ordering::SeqCst);
+        while current < safe_point {
+            let prev = self
+                .safe_point
+                .compare_and_swap(current, safe_point, Ordering::SeqCst);
+            if prev == current {
+                break;
+            }
+            current = prev;
+        }
         self.worker_scheduler
             .schedule(GCTask::GC {
                 ctx,
@@ -496,6 +680,12 @@ impl<E: Engine> GCWorker<E> {
             .or_else(Self::handle_schedule_error)
     }
 
+    /// Returns the max safe point GC has been requested with since started. The versions before
+    /// it may have been deleted, so they can't be read any more.
+    pub fn safe_point(&self) -> u64 {
+        self.safe_point.load(Ordering::SeqCst)
+    }
+
     /// Clean up all keys in a range and quickly free the disk space. The range might span over
     /// multiple regions, and the `ctx` doesn't indicate region. The request will be done directly
     /// on RocksDB, bypassing the Raft layer. User must promise that, after calling `destroy_range`,
@@ -517,15 +707,39 @@ impl<E: Engine> GCWorker<E> {
             })
             .or_else(Self::handle_schedule_error)
     }
+
+    /// Scan locks whose ts <= `max_ts` from `start_key` on the underlying RocksDB directly. It
+    /// needs `local_storage`. Since it bypasses the Raft layer, the result is not consistent
+    /// with any region, and may contain locks that are already resolved on the leaders.
+    pub fn async_physical_scan_lock(
+        &self,
+        ctx: Context,
+        max_ts: u64,
+        start_key: Key,
+        limit: usize,
+        callback: Callback<Vec<LockInfo>>,
+    ) -> Result<()> {
+        self.worker_scheduler
+            .schedule(GCTask::PhysicalScanLock {
+                ctx,
+                max_ts,
+                start_key,
+                limit,
+                callback,
+            })
+            .or_else(Self::handle_schedule_error)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use futures::Future;
+    use rocksdb::Writable;
     use server::readpool::{self, ReadPool};
     use std::collections::BTreeMap;
     use storage::engine::{new_local_engine, TEMP_DIR};
+    use storage::mvcc::LockType;
     use storage::{Config, Mutation, Options, ReadPoolContext, Storage, ALL_CFS};
     use util::worker::FutureWorker;
 
@@ -557,6 +771,27 @@ mod tests {
         commit_ts: u64,
         start_key: &[u8],
         end_key: &[u8],
+    ) -> Result<()> {
+        for &use_delete_range in &[false, true] {
+            test_destroy_range_with(
+                use_delete_range,
+                init_keys,
+                start_ts,
+                commit_ts,
+                start_key,
+                end_key,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn test_destroy_range_with(
+        use_delete_range: bool,
+        init_keys: &[Vec<u8>],
+        start_ts: u64,
+        commit_ts: u64,
+        start_key: &[u8],
+        end_key: &[u8],
     ) -> Result<()> {
         // Return Result from this function so we can use the `wait_op` macro here.
 
@@ -571,6 +806,7 @@ mod tests {
         );
         let mut storage = Storage::from_engine(engine, &Config::default(), read_pool).unwrap();
         storage.mut_gc_worker().set_local_storage(db);
+        storage.mut_gc_worker().set_use_delete_range(use_delete_range);
         storage.start(&Config::default()).unwrap();
 
         // Convert keys to key value pairs, where the value is "value-{key}".
@@ -717,4 +953,96 @@ mod tests {
             b"key1\x00",
         ).unwrap();
     }
+
+    fn unsafe_destroy_range<E: Engine>(
+        worker: &GCWorker<E>,
+        start_key: Key,
+        end_key: Key,
+    ) -> Result<()> {
+        wait_op!(|cb| worker.async_unsafe_destroy_range(
+            Context::default(),
+            start_key,
+            end_key,
+            cb
+        )).unwrap()
+    }
+
+    #[test]
+    fn test_destroy_invalid_range() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let db = engine.get_rocksdb();
+        {
+            let lock_cf = db.cf_handle(CF_LOCK).unwrap();
+            let lock = Lock::new(LockType::Put, b"k1".to_vec(), 10, 0, None);
+            let key = keys::data_key(Key::from_raw(b"k1").as_encoded());
+            db.put_cf(lock_cf, &key, &lock.to_bytes()).unwrap();
+        }
+        let mut worker = GCWorker::new(engine, 1.1, 0);
+        worker.set_local_storage(Arc::clone(&db));
+        worker.start().unwrap();
+
+        // The start key should be less than the end key.
+        let res = unsafe_destroy_range(&worker, Key::from_raw(b"k2"), Key::from_raw(b"k1"));
+        assert!(res.is_err());
+        // An empty range is a no-op.
+        unsafe_destroy_range(&worker, Key::from_raw(b"k1"), Key::from_raw(b"k1")).unwrap();
+        let lock_cf = db.cf_handle(CF_LOCK).unwrap();
+        let key = keys::data_key(Key::from_raw(b"k1").as_encoded());
+        assert!(db.get_cf(lock_cf, &key).unwrap().is_some());
+        // An empty end key means the end of all keys.
+        unsafe_destroy_range(&worker, Key::from_raw(b"k0"), Key::from_encoded(vec![])).unwrap();
+        assert!(db.get_cf(lock_cf, &key).unwrap().is_none());
+
+        worker.stop().unwrap();
+    }
+
+    fn physical_scan_lock<E: Engine>(
+        worker: &GCWorker<E>,
+        max_ts: u64,
+        start_key: &[u8],
+        limit: usize,
+    ) -> Result<Vec<LockInfo>> {
+        wait_op!(|cb| worker.async_physical_scan_lock(
+            Context::default(),
+            max_ts,
+            Key::from_raw(start_key),
+            limit,
+            cb
+        )).unwrap()
+    }
+
+    #[test]
+    fn test_physical_scan_lock() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let db = engine.get_rocksdb();
+        {
+            let lock_cf = db.cf_handle(CF_LOCK).unwrap();
+            for (i, k) in [b"k1", b"k2", b"k3", b"k4"].iter().enumerate() {
+                let lock = Lock::new(LockType::Put, b"k1".to_vec(), i as u64 * 10, 0, None);
+                let key = keys::data_key(Key::from_raw(*k).as_encoded());
+                db.put_cf(lock_cf, &key, &lock.to_bytes()).unwrap();
+            }
+        }
+
+        let mut worker = GCWorker::new(engine, 1.1, 0);
+        worker.set_local_storage(db);
+        worker.start().unwrap();
+
+        let lock_keys = |locks: Vec<LockInfo>| -> Vec<Vec<u8>> {
+            locks.into_iter().map(|mut l| l.take_key()).collect()
+        };
+        let locks = physical_scan_lock(&worker, 20, b"", 0).unwrap();
+        assert_eq!(lock_keys(locks), vec![b"k1".to_vec(), b"k2".to_vec(), b"k3".to_vec()]);
+
+        // Scan by pages.
+        let locks = physical_scan_lock(&worker, 30, b"", 2).unwrap();
+        assert_eq!(locks[1].get_lock_version(), 10);
+        assert_eq!(lock_keys(locks), vec![b"k1".to_vec(), b"k2".to_vec()]);
+        let locks = physical_scan_lock(&worker, 30, b"k2\x00", 2).unwrap();
+        assert_eq!(lock_keys(locks), vec![b"k3".to_vec(), b"k4".to_vec()]);
+        let locks = physical_scan_lock(&worker, 30, b"k4\x00", 2).unwrap();
+        assert!(locks.is_empty());
+
+        worker.stop().unwrap();
+    }
 }
@@ -28,6 +28,10 @@ lazy_static! {
         "tikv_scheduler_writing_bytes",
         "Total number of writing kv."
     ).unwrap();
+    pub static ref SCHED_WRITING_TASKS_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_scheduler_writing_tasks",
+        "Total number of pending write commands."
+    ).unwrap();
     pub static ref SCHED_CONTEX_GAUGE: IntGauge = register_int_gauge!(
         "tikv_scheduler_contex_total",
         "Total number of pending commands."
@@ -44,6 +48,15 @@ lazy_static! {
         &["type"],
         exponential_buckets(0.0005, 2.0, 20).unwrap()
     ).unwrap();
+    pub static ref SCHED_LATCH_CONTENDED_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_scheduler_latch_contended_total",
+        "Total number of commands which waited for latches held by other commands",
+        &["type"]
+    ).unwrap();
+    pub static ref SCHED_LATCH_WAITING_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_scheduler_latch_waiting_commands",
+        "Number of commands waiting for latches."
+    ).unwrap();
     pub static ref SCHED_PROCESSING_READ_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
         "tikv_scheduler_processing_read_duration_seconds",
         "Bucketed histogram of processing read duration",
@@ -77,6 +90,11 @@ lazy_static! {
         "Bucketed counter of kv keys scan details for each cf",
         &["req", "cf", "tag"]
     ).unwrap();
+    pub static ref KV_COMMAND_ROCKSDB_PERF_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "tikv_storage_rocksdb_perf",
+        "Total number of RocksDB internal operations from PerfContext of kv reads",
+        &["req", "metric"]
+    ).unwrap();
     pub static ref KV_COMMAND_KEYWRITE_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
         "tikv_scheduler_kv_command_key_write",
         "Bucketed histogram of keys write of a kv command",
@@ -116,4 +134,13 @@ lazy_static! {
         "Counter of keys affected during gc",
         &["cf", "tag"]
     ).unwrap();
+    pub static ref GC_SAFE_POINT_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_gcworker_autogc_safe_point",
+        "Safe point used by the GC manager"
+    ).unwrap();
+    pub static ref GC_MANAGER_REGION_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_gcworker_autogc_regions",
+        "Counter of regions processed by the GC manager",
+        &["result"]
+    ).unwrap();
 }
@@ -13,8 +13,8 @@
 
 use self::gc_worker::GCWorker;
 use self::metrics::*;
-use self::mvcc::Lock;
-use self::txn::CMD_BATCH_SIZE;
+use self::mvcc::{Lock, ScanChecksum, TxnStatus, TxnStatusCache};
+use self::txn::{Deadline, CMD_BATCH_SIZE, QUOTA_DELAY_CHECK_INTERVAL_MS};
 use futures::{future, Future};
 use kvproto::errorpb;
 use kvproto::kvrpcpb::{CommandPri, Context, KeyRange, LockInfo};
@@ -25,14 +25,22 @@ use std::cmp;
 use std::error;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::io::Error as IoError;
-use std::sync::{Arc, Mutex};
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
 use std::u64;
 use util;
-use util::collections::HashMap;
+use util::collections::{HashMap, HashSet};
+use util::futurepool::Turn;
+use util::logger::{self, SLOW_LOG_TARGET};
+use util::time::{Duration, Instant};
+use util::timer::Timer;
+use util::trace::Trace;
 use util::worker::{self, Builder, ScheduleError, Worker};
 
+mod command_future;
 pub mod config;
 pub mod engine;
+pub mod gc_manager;
 pub mod gc_worker;
 mod metrics;
 pub mod mvcc;
@@ -40,12 +48,15 @@ mod readpool_context;
 pub mod txn;
 pub mod types;
 
-pub use self::config::{Config, DEFAULT_DATA_DIR, DEFAULT_ROCKSDB_SUB_DIR};
+pub use self::command_future::CommandFuture;
+pub use self::config::{
+    AssertionMode, BlockCacheConfig, Config, DEFAULT_DATA_DIR, DEFAULT_ROCKSDB_SUB_DIR,
+};
 pub use self::engine::raftkv::RaftKv;
 pub use self::engine::{
     new_local_engine, CFStatistics, Cursor, CursorBuilder, Engine, Error as EngineError,
-    FlowStatistics, Iterator, Modify, RocksEngine, ScanMode, Snapshot, Statistics,
-    StatisticsSummary, TEMP_DIR,
+    FlowStatistics, Iterator, Modify, PerfStatisticsDelta, PerfStatisticsInstant, RocksEngine,
+    ScanMode, Snapshot, Statistics, StatisticsSummary, TEMP_DIR,
 };
 pub use self::readpool_context::Context as ReadPoolContext;
 pub use self::txn::{Msg, Scheduler, SnapshotStore, StoreScanner};
@@ -70,6 +81,30 @@ pub fn is_short_value(value: &[u8]) -> bool {
     value.len() <= SHORT_VALUE_MAX_LEN
 }
 
+/// Prints a kv read to the slow log if it takes long, with the RocksDB perf statistics, which
+/// tell whether the read is slowed by block reads, skipped tombstones or memtable lookups.
+fn log_slow_read(
+    cmd: &str,
+    ctx: &Context,
+    process_time: Duration,
+    statistics: &Statistics,
+    perf_statistics: &PerfStatisticsDelta,
+) {
+    if !logger::is_slow(process_time) {
+        return;
+    }
+    info!(
+        target: SLOW_LOG_TARGET,
+        "[region {}] [slow-query] {} takes {:?} [keys: {}, processed: {}, perf: {:?}]",
+        ctx.get_region_id(),
+        cmd,
+        process_time,
+        statistics.total_op_count(),
+        statistics.total_processed(),
+        perf_statistics,
+    );
+}
+
 #[derive(Debug, Clone)]
 pub enum Mutation {
     Put((Key, Value)),
@@ -88,6 +123,38 @@ impl Mutation {
     }
 }
 
+/// The expectation on the current value of a key, which is verified atomically when the key
+/// is prewritten. A failed assertion aborts the prewrite with `mvcc::Error::AssertionFailed`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Assertion {
+    None,
+    /// The key must not exist.
+    NotExist,
+    /// The key must exist.
+    Exist,
+    /// The key must exist and its value must be equal to the given one.
+    Equal(Value),
+}
+
+impl Assertion {
+    /// Checks the assertion against `value`, the latest committed value of the key.
+    pub fn check(&self, value: Option<&Value>) -> bool {
+        match (self, value) {
+            (&Assertion::None, _) => true,
+            (&Assertion::NotExist, v) => v.is_none(),
+            (&Assertion::Exist, v) => v.is_some(),
+            (&Assertion::Equal(ref expected), Some(v)) => expected == v,
+            (&Assertion::Equal(_), None) => false,
+        }
+    }
+}
+
+impl Default for Assertion {
+    fn default() -> Assertion {
+        Assertion::None
+    }
+}
+
 pub enum StorageCb {
     Boolean(Callback<()>),
     Booleans(Callback<Vec<Result<()>>>),
@@ -102,6 +169,8 @@ pub enum Command {
     Prewrite {
         ctx: Context,
         mutations: Vec<Mutation>,
+        // The assertion of each mutation, empty if there is none.
+        assertions: Vec<Assertion>,
         primary: Vec<u8>,
         start_ts: u64,
         options: Options,
@@ -134,6 +203,18 @@ pub enum Command {
         scan_key: Option<Key>,
         key_locks: Vec<(Key, Lock)>,
     },
+    /// Rewinds the keys in [`scan_key`, `end_key`) to their values at `version`, by committing
+    /// the old values at `commit_ts` again. It's processed in batches, `key_values` is empty when
+    /// reading the keys to rewind, and has them when writing.
+    Flashback {
+        ctx: Context,
+        version: u64,
+        start_ts: u64,
+        commit_ts: u64,
+        scan_key: Option<Key>,
+        end_key: Option<Key>,
+        key_values: Vec<(Key, Option<Value>)>,
+    },
     DeleteRange {
         ctx: Context,
         start_key: Key,
@@ -212,6 +293,17 @@ impl Display for Command {
                 start_key, limit, max_ts, ctx
             ),
             Command::ResolveLock { .. } => write!(f, "kv::resolve_lock"),
+            Command::Flashback {
+                ref ctx,
+                version,
+                ref scan_key,
+                ref end_key,
+                ..
+            } => write!(
+                f,
+                "kv::command::flashback [{:?}, {:?}) to {} | {:?}",
+                scan_key, end_key, version, ctx
+            ),
             Command::DeleteRange {
                 ref ctx,
                 ref start_key,
@@ -243,6 +335,12 @@ impl Debug for Command {
 
 pub const CMD_TAG_GC: &str = "gc";
 pub const CMD_TAG_UNSAFE_DESTROY_RANGE: &str = "unsafe_destroy_range";
+pub const CMD_TAG_PHYSICAL_SCAN_LOCK: &str = "physical_scan_lock";
+
+// A scan yields the read pool thread after running for this long, and the time is checked
+// every `SCAN_BATCH_SIZE` keys.
+const SCAN_TIME_SLICE_MILLIS: u64 = 10;
+const SCAN_BATCH_SIZE: usize = 256;
 
 impl Command {
     pub fn readonly(&self) -> bool {
@@ -256,6 +354,7 @@ impl Command {
             Command::MvccByKey { .. } |
             Command::MvccByStartTs { .. } => true,
             Command::ResolveLock { ref key_locks, .. } => key_locks.is_empty(),
+            Command::Flashback { ref key_values, .. } => key_values.is_empty(),
             _ => false,
         }
     }
@@ -284,6 +383,7 @@ impl Command {
             Command::Rollback { .. } => "rollback",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
+            Command::Flashback { .. } => "flashback",
             Command::DeleteRange { .. } => "delete_range",
             Command::Pause { .. } => "pause",
             Command::MvccByKey { .. } => "key_mvcc",
@@ -296,6 +396,7 @@ impl Command {
             Command::Prewrite { start_ts, .. }
             | Command::Cleanup { start_ts, .. }
             | Command::Rollback { start_ts, .. }
+            | Command::Flashback { start_ts, .. }
             | Command::MvccByStartTs { start_ts, .. } => start_ts,
             Command::Commit { lock_ts, .. } => lock_ts,
             Command::ScanLock { max_ts, .. } => max_ts,
@@ -314,6 +415,7 @@ impl Command {
             | Command::Rollback { ref ctx, .. }
             | Command::ScanLock { ref ctx, .. }
             | Command::ResolveLock { ref ctx, .. }
+            | Command::Flashback { ref ctx, .. }
             | Command::DeleteRange { ref ctx, .. }
             | Command::Pause { ref ctx, .. }
             | Command::MvccByKey { ref ctx, .. }
@@ -329,6 +431,7 @@ impl Command {
             | Command::Rollback { ref mut ctx, .. }
             | Command::ScanLock { ref mut ctx, .. }
             | Command::ResolveLock { ref mut ctx, .. }
+            | Command::Flashback { ref mut ctx, .. }
             | Command::DeleteRange { ref mut ctx, .. }
             | Command::Pause { ref mut ctx, .. }
             | Command::MvccByKey { ref mut ctx, .. }
@@ -358,6 +461,10 @@ impl Command {
             Command::ResolveLock { ref key_locks, .. } => for lock in key_locks {
                 bytes += lock.0.as_encoded().len();
             },
+            Command::Flashback { ref key_values, .. } => for &(ref key, ref value) in key_values {
+                bytes += key.as_encoded().len();
+                bytes += value.as_ref().map_or(0, |v| v.len());
+            },
             Command::Cleanup { ref key, .. } => {
                 bytes += key.as_encoded().len();
             }
@@ -373,6 +480,7 @@ pub struct Options {
     pub skip_constraint_check: bool,
     pub key_only: bool,
     pub reverse_scan: bool,
+    pub assertion_mode: AssertionMode,
 }
 
 impl Options {
@@ -382,6 +490,7 @@ impl Options {
             skip_constraint_check,
             key_only,
             reverse_scan: false,
+            assertion_mode: AssertionMode::default(),
         }
     }
 
@@ -391,6 +500,21 @@ impl Options {
     }
 }
 
+/// A point get request used by `Storage::async_batch_get_command`. Every request
+/// carries its own context and timestamp.
+#[derive(Clone, Debug)]
+pub struct PointGetCommand {
+    pub ctx: Context,
+    pub key: Key,
+    pub ts: u64,
+}
+
+impl PointGetCommand {
+    pub fn new(ctx: Context, key: Key, ts: u64) -> PointGetCommand {
+        PointGetCommand { ctx, key, ts }
+    }
+}
+
 #[derive(Clone)]
 pub struct Storage<E: Engine> {
     engine: E,
@@ -402,8 +526,16 @@ pub struct Storage<E: Engine> {
     read_pool: ReadPool<ReadPoolContext>,
     gc_worker: GCWorker<E>,
 
+    // Statuses of recently resolved transactions, shared by all readers.
+    txn_status_cache: Arc<TxnStatusCache>,
+
+    // The regions in flashback, which reject other writes until the flashback finishes.
+    flashback_regions: Arc<RwLock<HashSet<u64>>>,
+
     // Storage configurations.
     max_key_size: usize,
+    assertion_mode: AssertionMode,
+    max_handle_duration: Duration,
 }
 
 impl Storage<RocksEngine> {
@@ -428,14 +560,22 @@ impl<E: Engine> Storage<E> {
                 .create(),
         ));
         let worker_scheduler = worker.lock().unwrap().scheduler();
-        let gc_worker = GCWorker::new(engine.clone(), config.gc_ratio_threshold);
+        let gc_worker = GCWorker::new(
+            engine.clone(),
+            config.gc_ratio_threshold,
+            config.gc_max_write_bytes_per_sec.0,
+        );
         Ok(Storage {
             engine,
             worker,
             worker_scheduler,
             read_pool,
             gc_worker,
+            txn_status_cache: Arc::new(TxnStatusCache::new(config.txn_status_cache_capacity)),
+            flashback_regions: Arc::new(RwLock::new(HashSet::default())),
             max_key_size: config.max_key_size,
+            assertion_mode: config.assertion_mode,
+            max_handle_duration: config.request_max_handle_duration.0,
         })
     }
 
@@ -447,6 +587,7 @@ impl<E: Engine> Storage<E> {
         let sched_concurrency = config.scheduler_concurrency;
         let sched_worker_pool_size = config.scheduler_worker_pool_size;
         let sched_pending_write_threshold = config.scheduler_pending_write_threshold.0 as usize;
+        let sched_pending_write_tasks_threshold = config.scheduler_pending_write_tasks_threshold;
         let mut worker = self.worker.lock().unwrap();
         let scheduler = Scheduler::new(
             self.engine.clone(),
@@ -454,8 +595,12 @@ impl<E: Engine> Storage<E> {
             sched_concurrency,
             sched_worker_pool_size,
             sched_pending_write_threshold,
+            sched_pending_write_tasks_threshold,
+            config.request_max_handle_duration.0,
         );
-        worker.start(scheduler)?;
+        let mut timer = Timer::new(1);
+        timer.add_task(Duration::from_millis(QUOTA_DELAY_CHECK_INTERVAL_MS), ());
+        worker.start_with_timer(scheduler, timer)?;
         self.gc_worker.start()?;
         Ok(())
     }
@@ -485,6 +630,20 @@ impl<E: Engine> Storage<E> {
     #[inline]
     fn schedule(&self, cmd: Command, cb: StorageCb) -> Result<()> {
         fail_point!("storage_drop_message", |_| Ok(()));
+        // The read lock is held until the command is sent, so that the commands accepted
+        // before a flashback always reach the scheduler before it, which waits for them.
+        let flashback_regions = self.flashback_regions.read().unwrap();
+        let may_write = match cmd {
+            // Resolving locks starts with reading the locks.
+            Command::ResolveLock { .. } => true,
+            _ => !cmd.readonly(),
+        };
+        if may_write {
+            let region_id = cmd.get_context().get_region_id();
+            if flashback_regions.contains(&region_id) {
+                return Err(Error::RegionInFlashback(region_id));
+            }
+        }
         match self.worker_scheduler.schedule(Msg::RawCmd { cmd, cb }) {
             Ok(()) => Ok(()),
             Err(ScheduleError::Full(_)) => Err(Error::SchedTooBusy),
@@ -492,16 +651,31 @@ impl<E: Engine> Storage<E> {
         }
     }
 
-    fn async_snapshot(engine: E, ctx: &Context) -> impl Future<Item = E::Snap, Error = Error> {
+    fn async_snapshot(
+        engine: E,
+        ctx: &Context,
+        deadline: Deadline,
+        trace: &Trace,
+    ) -> impl Future<Item = E::Snap, Error = Error> {
+        trace.tag("region", ctx.get_region_id());
+        let trace = trace.clone();
         let (callback, future) = util::future::paired_future_callback();
-        let val = engine.async_snapshot(ctx, callback);
+        // A read queued in the read pool for too long is dropped before taking the snapshot.
+        let val = deadline
+            .check()
+            .and_then(|_| engine.async_snapshot(ctx, callback).map_err(txn::Error::from));
 
         future::result(val)
-            .and_then(|_| future.map_err(|cancel| EngineError::Other(box_err!(cancel))))
-            .and_then(|(_ctx, result)| result)
-            // map storage::engine::Error -> storage::txn::Error -> storage::Error
-            .map_err(txn::Error::from)
+            .and_then(|_| {
+                future.map_err(|cancel| txn::Error::from(EngineError::Other(box_err!(cancel))))
+            })
+            .and_then(|(_ctx, result)| result.map_err(txn::Error::from))
+            // map storage::txn::Error -> storage::Error
             .map_err(Error::from)
+            .map(move |snapshot| {
+                trace.stage("snapshot");
+                snapshot
+            })
     }
 
     /// Get from the snapshot.
@@ -513,27 +687,34 @@ impl<E: Engine> Storage<E> {
     ) -> impl Future<Item = Option<Value>, Error = Error> {
         const CMD: &str = "get";
         let engine = self.get_engine();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
 
+                    let process_begin_at = Instant::now_coarse();
+                    let perf_statistics = PerfStatisticsInstant::new();
                     let mut statistics = Statistics::default();
-                    let snap_store = SnapshotStore::new(
+                    let mut snap_store = SnapshotStore::new(
                         snapshot,
                         start_ts,
                         ctx.get_isolation_level(),
                         !ctx.get_not_fill_cache(),
                     );
+                    snap_store.set_txn_status_cache(txn_status_cache);
                     let result = snap_store
                         .get(&key, &mut statistics)
                         // map storage::txn::Error -> storage::Error
@@ -543,13 +724,24 @@ impl<E: Engine> Storage<E> {
                             r
                         });
 
+                    let perf_statistics = perf_statistics.delta();
                     thread_ctx.collect_scan_count(CMD, &statistics);
+                    thread_ctx.collect_perf_stats(CMD, &perf_statistics);
                     thread_ctx.collect_read_flow(ctx.get_region_id(), &statistics);
+                    log_slow_read(
+                        CMD,
+                        &ctx,
+                        process_begin_at.elapsed(),
+                        &statistics,
+                        &perf_statistics,
+                    );
 
                     result
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -568,27 +760,34 @@ impl<E: Engine> Storage<E> {
     ) -> impl Future<Item = Vec<Result<KvPair>>, Error = Error> {
         const CMD: &str = "batch_get";
         let engine = self.get_engine();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
 
+                    let process_begin_at = Instant::now_coarse();
+                    let perf_statistics = PerfStatisticsInstant::new();
                     let mut statistics = Statistics::default();
-                    let snap_store = SnapshotStore::new(
+                    let mut snap_store = SnapshotStore::new(
                         snapshot,
                         start_ts,
                         ctx.get_isolation_level(),
                         !ctx.get_not_fill_cache(),
                     );
+                    snap_store.set_txn_status_cache(txn_status_cache);
                     let result = snap_store
                         .batch_get(&keys, &mut statistics)
                         // map storage::txn::Error -> storage::Error
@@ -611,13 +810,135 @@ impl<E: Engine> Storage<E> {
                             r
                         });
 
+                    let perf_statistics = perf_statistics.delta();
                     thread_ctx.collect_scan_count(CMD, &statistics);
+                    thread_ctx.collect_perf_stats(CMD, &perf_statistics);
                     thread_ctx.collect_read_flow(ctx.get_region_id(), &statistics);
+                    log_slow_read(
+                        CMD,
+                        &ctx,
+                        process_begin_at.elapsed(),
+                        &statistics,
+                        &perf_statistics,
+                    );
 
                     result
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
+                    r
+                })
+        });
+
+        future::result(res)
+            .map_err(|_| Error::SchedTooBusy)
+            .flatten()
+    }
+
+    /// Get values of a batch of point get requests.
+    ///
+    /// Requests of the same region are served by one snapshot. Unlike `async_batch_get`,
+    /// every request gets its own result, which is `None` if the key is not found, or
+    /// an error if the key is locked or the snapshot of its region can't be taken.
+    pub fn async_batch_get_command(
+        &self,
+        gets: Vec<PointGetCommand>,
+    ) -> impl Future<Item = Vec<Result<Option<Value>>>, Error = Error> {
+        const CMD: &str = "batch_get_command";
+        let engine = self.get_engine();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
+        let priority = gets
+            .first()
+            .map_or(readpool::Priority::Normal, |get| {
+                readpool::Priority::from(get.ctx.get_priority())
+            });
+
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
+        let res = self.read_pool.future_execute(priority, move |ctxd| {
+            let mut _timer = {
+                let ctxd = ctxd.clone();
+                let mut thread_ctx = ctxd.current_thread_context_mut();
+                thread_ctx.start_command_duration_timer(CMD, priority)
+            };
+            trace.stage("read_pool_wait");
+
+            let len = gets.len();
+            let mut groups: HashMap<u64, Vec<(usize, PointGetCommand)>> = HashMap::default();
+            for (i, get) in gets.into_iter().enumerate() {
+                groups
+                    .entry(get.ctx.get_region_id())
+                    .or_insert_with(Vec::new)
+                    .push((i, get));
+            }
+
+            let futures: Vec<_> = groups
+                .into_iter()
+                .map(|(region_id, group)| {
+                    let ctxd = ctxd.clone();
+                    let txn_status_cache = Arc::clone(&txn_status_cache);
+                    let snap =
+                        Self::async_snapshot(engine.clone(), &group[0].1.ctx, deadline, &trace);
+                    snap.then(move |res| {
+                        let snapshot = match res {
+                            Ok(snapshot) => snapshot,
+                            Err(e) => {
+                                // Every request of the region gets the same error.
+                                let results = group
+                                    .into_iter()
+                                    .map(|(i, _)| {
+                                        let err = e
+                                            .maybe_clone()
+                                            .unwrap_or_else(|| box_err!("{:?}", e));
+                                        (i, Err(err))
+                                    })
+                                    .collect::<Vec<_>>();
+                                return Ok(results);
+                            }
+                        };
+
+                        let mut thread_ctx = ctxd.current_thread_context_mut();
+                        let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
+                        let mut statistics = Statistics::default();
+                        let results = group
+                            .into_iter()
+                            .map(|(i, get)| {
+                                let mut snap_store = SnapshotStore::new(
+                                    snapshot.clone(),
+                                    get.ts,
+                                    get.ctx.get_isolation_level(),
+                                    !get.ctx.get_not_fill_cache(),
+                                );
+                                snap_store.set_txn_status_cache(Arc::clone(&txn_status_cache));
+                                // map storage::txn::Error -> storage::Error
+                                let res = snap_store.get(&get.key, &mut statistics);
+                                (i, res.map_err(Error::from))
+                            })
+                            .collect::<Vec<_>>();
+
+                        thread_ctx.collect_key_reads(CMD, results.len() as u64);
+                        thread_ctx.collect_scan_count(CMD, &statistics);
+                        thread_ctx.collect_read_flow(region_id, &statistics);
+                        Ok::<_, Error>(results)
+                    })
+                })
+                .collect();
+
+            future::join_all(futures)
+                .map(move |groups| {
+                    let mut results: Vec<Option<Result<Option<Value>>>> =
+                        (0..len).map(|_| None).collect();
+                    for (i, res) in groups.into_iter().flat_map(|g| g) {
+                        results[i] = Some(res);
+                    }
+                    results.into_iter().map(Option::unwrap).collect()
+                })
+                .then(move |r| {
+                    _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -636,22 +957,50 @@ impl<E: Engine> Storage<E> {
         start_ts: u64,
         options: Options,
     ) -> impl Future<Item = Vec<Result<KvPair>>, Error = Error> {
+        self.scan_impl(ctx, start_key, limit, start_ts, options, false)
+            .map(|(results, _)| results)
+    }
+
+    /// Like `async_scan`, but also returns the checksum of the pairs scanned, so that
+    /// clients migrating or verifying data can check them without a second pass.
+    pub fn async_scan_with_checksum(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        limit: usize,
+        start_ts: u64,
+        options: Options,
+    ) -> impl Future<Item = (Vec<Result<KvPair>>, ScanChecksum), Error = Error> {
+        self.scan_impl(ctx, start_key, limit, start_ts, options, true)
+            .map(|(results, checksum)| (results, checksum.unwrap()))
+    }
+
+    fn scan_impl(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        limit: usize,
+        start_ts: u64,
+        options: Options,
+        checksum: bool,
+    ) -> impl Future<Item = (Vec<Result<KvPair>>, Option<ScanChecksum>), Error = Error> {
         const CMD: &str = "scan";
         let engine = self.get_engine();
+        let read_pool = self.read_pool.clone();
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
-                    let mut thread_ctx = ctxd.current_thread_context_mut();
-                    let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
-
                     let snap_store = SnapshotStore::new(
                         snapshot,
                         start_ts,
@@ -659,38 +1008,85 @@ impl<E: Engine> Storage<E> {
                         !ctx.get_not_fill_cache(),
                     );
 
-                    let mut scanner;
-                    if !options.reverse_scan {
-                        scanner = snap_store.scanner(
-                            ScanMode::Forward,
-                            options.key_only,
-                            Some(start_key),
-                            None,
-                        )?;
+                    let (mode, lower_bound, upper_bound) = if !options.reverse_scan {
+                        (ScanMode::Forward, Some(start_key), None)
                     } else {
-                        scanner = snap_store.scanner(
-                            ScanMode::Backward,
+                        (ScanMode::Backward, None, Some(start_key))
+                    };
+                    let mut scanner = if checksum {
+                        snap_store.scanner_with_checksum(
+                            mode,
                             options.key_only,
-                            None,
-                            Some(start_key),
-                        )?;
+                            lower_bound,
+                            upper_bound,
+                        )?
+                    } else {
+                        snap_store.scanner(mode, options.key_only, lower_bound, upper_bound)?
                     };
-                    let res = scanner.scan(limit);
 
-                    let statistics = scanner.take_statistics();
-                    thread_ctx.collect_scan_count(CMD, &statistics);
-                    thread_ctx.collect_read_flow(ctx.get_region_id(), &statistics);
+                    // Scan in batches, and continue in a new turn of the read pool once the
+                    // time slice is used up, so that a huge scan won't block small requests
+                    // behind it.
+                    let time_slice = Duration::from_millis(SCAN_TIME_SLICE_MILLIS);
+                    let mut results = vec![];
+                    type ScanResult = (Vec<Result<KvPair>>, Option<ScanChecksum>);
+                    let step = move || -> Result<Turn<ScanResult>> {
+                        let mut thread_ctx = ctxd.current_thread_context_mut();
+                        let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
+
+                        let yield_at = Instant::now_coarse() + time_slice;
+                        let perf_statistics = PerfStatisticsInstant::new();
+                        let mut res = Ok(());
+                        while results.len() < limit {
+                            let batch_size = cmp::min(SCAN_BATCH_SIZE, limit - results.len());
+                            match scanner.scan(batch_size) {
+                                Ok(batch) => {
+                                    let finished = batch.len() < batch_size;
+                                    results.extend(batch);
+                                    if finished {
+                                        break;
+                                    }
+                                }
+                                Err(e) => {
+                                    res = Err(e);
+                                    break;
+                                }
+                            }
+                            if results.len() < limit && Instant::now_coarse() >= yield_at {
+                                let statistics = scanner.take_statistics();
+                                thread_ctx.collect_scan_count(CMD, &statistics);
+                                thread_ctx.collect_perf_stats(CMD, &perf_statistics.delta());
+                                thread_ctx.collect_read_flow(ctx.get_region_id(), &statistics);
+                                return Ok(Turn::Continue(Duration::from_secs(0)));
+                            }
+                        }
+
+                        let statistics = scanner.take_statistics();
+                        thread_ctx.collect_scan_count(CMD, &statistics);
+                        thread_ctx.collect_perf_stats(CMD, &perf_statistics.delta());
+                        thread_ctx.collect_read_flow(ctx.get_region_id(), &statistics);
 
-                    res.map_err(Error::from).map(|results| {
+                        res.map_err(Error::from)?;
+                        let results = mem::replace(&mut results, vec![]);
                         thread_ctx.collect_key_reads(CMD, results.len() as u64);
-                        results
-                            .into_iter()
-                            .map(|x| x.map_err(Error::from))
-                            .collect()
-                    })
+                        Ok(Turn::Finished((
+                            results
+                                .into_iter()
+                                .map(|x| x.map_err(Error::from))
+                                .collect(),
+                            scanner.checksum(),
+                        )))
+                    };
+                    Ok(read_pool
+                        .run_in_turns(priority, step)
+                        .map_err(|cancel| Error::Other(box_err!(cancel)))
+                        .and_then(|r| r))
                 })
+                .flatten()
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -715,6 +1111,39 @@ impl<E: Engine> Storage<E> {
         options: Options,
         callback: Callback<Vec<Result<()>>>,
     ) -> Result<()> {
+        self.async_conditional_prewrite(
+            ctx,
+            mutations,
+            vec![],
+            primary,
+            start_ts,
+            options,
+            callback,
+        )
+    }
+
+    /// Prewrites `mutations` like `async_prewrite`, but every mutation is applied only if the
+    /// corresponding assertion holds, so the caller doesn't need to read the keys in advance
+    /// to check constraints. `assertions` must be empty or have the same length as
+    /// `mutations`.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn async_conditional_prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        assertions: Vec<Assertion>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        mut options: Options,
+        callback: Callback<Vec<Result<()>>>,
+    ) -> Result<()> {
+        if !assertions.is_empty() && assertions.len() != mutations.len() {
+            return Err(box_err!(
+                "the number of assertions {} doesn't match the number of mutations {}",
+                assertions.len(),
+                mutations.len()
+            ));
+        }
         for m in &mutations {
             let size = m.key().as_encoded().len();
             if size > self.max_key_size {
@@ -722,9 +1151,11 @@ impl<E: Engine> Storage<E> {
                 return Ok(());
             }
         }
+        options.assertion_mode = self.assertion_mode;
         let cmd = Command::Prewrite {
             ctx,
             mutations,
+            assertions,
             primary,
             start_ts,
             options,
@@ -750,6 +1181,13 @@ impl<E: Engine> Storage<E> {
             commit_ts,
         };
         let tag = cmd.tag();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
+        let callback: Callback<()> = Box::new(move |res: Result<()>| {
+            if res.is_ok() {
+                txn_status_cache.insert(lock_ts, TxnStatus::Committed(commit_ts));
+            }
+            callback(res)
+        });
         self.schedule(cmd, StorageCb::Boolean(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
@@ -796,6 +1234,13 @@ impl<E: Engine> Storage<E> {
     ) -> Result<()> {
         let cmd = Command::Cleanup { ctx, key, start_ts };
         let tag = cmd.tag();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
+        let callback: Callback<()> = Box::new(move |res: Result<()>| {
+            if res.is_ok() {
+                txn_status_cache.insert(start_ts, TxnStatus::RolledBack);
+            }
+            callback(res)
+        });
         self.schedule(cmd, StorageCb::Boolean(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
@@ -814,11 +1259,70 @@ impl<E: Engine> Storage<E> {
             start_ts,
         };
         let tag = cmd.tag();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
+        let callback: Callback<()> = Box::new(move |res: Result<()>| {
+            if res.is_ok() {
+                txn_status_cache.insert(start_ts, TxnStatus::RolledBack);
+            }
+            callback(res)
+        });
         self.schedule(cmd, StorageCb::Boolean(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
+    /// The future version of `async_prewrite`.
+    pub fn prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    ) -> CommandFuture<Vec<Result<()>>> {
+        CommandFuture::new(|cb| {
+            self.async_prewrite(ctx, mutations, primary, start_ts, options, cb)
+        })
+    }
+
+    /// The future version of `async_conditional_prewrite`.
+    pub fn conditional_prewrite(
+        &self,
+        ctx: Context,
+        mutations: Vec<Mutation>,
+        assertions: Vec<Assertion>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        options: Options,
+    ) -> CommandFuture<Vec<Result<()>>> {
+        CommandFuture::new(|cb| {
+            self.async_conditional_prewrite(
+                ctx, mutations, assertions, primary, start_ts, options, cb,
+            )
+        })
+    }
+
+    /// The future version of `async_commit`.
+    pub fn commit(
+        &self,
+        ctx: Context,
+        keys: Vec<Key>,
+        lock_ts: u64,
+        commit_ts: u64,
+    ) -> CommandFuture<()> {
+        CommandFuture::new(|cb| self.async_commit(ctx, keys, lock_ts, commit_ts, cb))
+    }
+
+    /// The future version of `async_cleanup`.
+    pub fn cleanup(&self, ctx: Context, key: Key, start_ts: u64) -> CommandFuture<()> {
+        CommandFuture::new(|cb| self.async_cleanup(ctx, key, start_ts, cb))
+    }
+
+    /// The future version of `async_rollback`.
+    pub fn rollback(&self, ctx: Context, keys: Vec<Key>, start_ts: u64) -> CommandFuture<()> {
+        CommandFuture::new(|cb| self.async_rollback(ctx, keys, start_ts, cb))
+    }
+
     pub fn async_scan_locks(
         &self,
         ctx: Context,
@@ -849,6 +1353,10 @@ impl<E: Engine> Storage<E> {
         txn_status: HashMap<u64, u64>,
         callback: Callback<()>,
     ) -> Result<()> {
+        let statuses: Vec<_> = txn_status
+            .iter()
+            .map(|(start_ts, commit_ts)| (*start_ts, TxnStatus::from_commit_ts(*commit_ts)))
+            .collect();
         let cmd = Command::ResolveLock {
             ctx,
             txn_status,
@@ -856,11 +1364,84 @@ impl<E: Engine> Storage<E> {
             key_locks: vec![],
         };
         let tag = cmd.tag();
+        let txn_status_cache = Arc::clone(&self.txn_status_cache);
+        let callback: Callback<()> = Box::new(move |res: Result<()>| {
+            if res.is_ok() {
+                for (start_ts, status) in statuses {
+                    txn_status_cache.insert(start_ts, status);
+                }
+            }
+            callback(res)
+        });
         self.schedule(cmd, StorageCb::Boolean(callback))?;
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
+    /// Rewinds the keys in [`start_key`, `end_key`) of the region to their values at `version`,
+    /// by committing the old values again at `commit_ts`. `None` `end_key` means unbounded.
+    ///
+    /// Other writes to the region are rejected with `RegionInFlashback` until it finishes, and
+    /// it starts after the writes accepted before it finish. The flashback fails if any key in
+    /// the range is locked, the locks should be resolved first. `version` can't be before the
+    /// GC safe point.
+    /// The keys rewound before the failure are kept, and retrying with the same timestamps
+    /// continues with the rest.
+    pub fn async_flashback(
+        &self,
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        version: u64,
+        start_ts: u64,
+        commit_ts: u64,
+        callback: Callback<()>,
+    ) -> Result<()> {
+        if version >= start_ts || start_ts >= commit_ts {
+            return Err(box_err!(
+                "invalid flashback version {}, start_ts {}, commit_ts {}",
+                version,
+                start_ts,
+                commit_ts
+            ));
+        }
+        // The versions before the safe point may have been deleted by GC.
+        let safe_point = self.gc_worker.safe_point();
+        if version < safe_point {
+            return Err(box_err!(
+                "flashback version {} is before the gc safe point {}",
+                version,
+                safe_point
+            ));
+        }
+        let region_id = ctx.get_region_id();
+        if !self.flashback_regions.write().unwrap().insert(region_id) {
+            return Err(Error::RegionInFlashback(region_id));
+        }
+        let cmd = Command::Flashback {
+            ctx,
+            version,
+            start_ts,
+            commit_ts,
+            scan_key: Some(start_key),
+            end_key,
+            key_values: vec![],
+        };
+        let tag = cmd.tag();
+        let flashback_regions = Arc::clone(&self.flashback_regions);
+        let callback: Callback<()> = Box::new(move |res: Result<()>| {
+            flashback_regions.write().unwrap().remove(&region_id);
+            callback(res)
+        });
+        if let Err(e) = self.schedule(cmd, StorageCb::Boolean(callback)) {
+            // The callback is dropped without being called.
+            self.flashback_regions.write().unwrap().remove(&region_id);
+            return Err(e);
+        }
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
     pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
         self.gc_worker.async_gc(ctx, safe_point, callback)?;
         KV_COMMAND_COUNTER_VEC
@@ -884,6 +1465,33 @@ impl<E: Engine> Storage<E> {
         Ok(())
     }
 
+    /// Scan locks whose ts <= `max_ts` on the local RocksDB directly, without reading through
+    /// region leaders. Pass the successor of the last returned key as `start_key` to get the
+    /// next page.
+    ///
+    /// The kvproto in use has no `PhysicalScanLock` RPC, so it serves the `ScanLock` requests
+    /// without a region id instead.
+    pub fn async_physical_scan_lock(
+        &self,
+        ctx: Context,
+        max_ts: u64,
+        start_key: Vec<u8>,
+        limit: usize,
+        callback: Callback<Vec<LockInfo>>,
+    ) -> Result<()> {
+        self.gc_worker.async_physical_scan_lock(
+            ctx,
+            max_ts,
+            Key::from_raw(&start_key),
+            limit,
+            callback,
+        )?;
+        KV_COMMAND_COUNTER_VEC
+            .with_label_values(&[CMD_TAG_PHYSICAL_SCAN_LOCK])
+            .inc();
+        Ok(())
+    }
+
     pub fn async_raw_get(
         &self,
         ctx: Context,
@@ -894,14 +1502,17 @@ impl<E: Engine> Storage<E> {
         let engine = self.get_engine();
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
@@ -925,6 +1536,8 @@ impl<E: Engine> Storage<E> {
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -946,14 +1559,17 @@ impl<E: Engine> Storage<E> {
 
         let keys: Vec<Key> = keys.into_iter().map(Key::from_encoded).collect();
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
@@ -983,6 +1599,8 @@ impl<E: Engine> Storage<E> {
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -1171,14 +1789,17 @@ impl<E: Engine> Storage<E> {
         let engine = self.get_engine();
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
@@ -1202,6 +1823,8 @@ impl<E: Engine> Storage<E> {
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -1250,14 +1873,17 @@ impl<E: Engine> Storage<E> {
         let engine = self.get_engine();
         let priority = readpool::Priority::from(ctx.get_priority());
 
+        let deadline = Deadline::from_now(self.max_handle_duration);
+        let trace = Trace::new(CMD);
         let res = self.read_pool.future_execute(priority, move |ctxd| {
             let mut _timer = {
                 let ctxd = ctxd.clone();
                 let mut thread_ctx = ctxd.current_thread_context_mut();
                 thread_ctx.start_command_duration_timer(CMD, priority)
             };
+            trace.stage("read_pool_wait");
 
-            Self::async_snapshot(engine, &ctx)
+            Self::async_snapshot(engine, &ctx, deadline, &trace)
                 .and_then(move |snapshot: E::Snap| {
                     let mut thread_ctx = ctxd.current_thread_context_mut();
                     let _t_process = thread_ctx.start_processing_read_duration_timer(CMD);
@@ -1300,6 +1926,8 @@ impl<E: Engine> Storage<E> {
                 })
                 .then(move |r| {
                     _timer.observe_duration();
+                    trace.stage("process");
+                    trace.finish();
                     r
                 })
         });
@@ -1370,6 +1998,10 @@ quick_error! {
         SchedTooBusy {
             description("scheduler is too busy")
         }
+        SchedTooBusyWithBackoff(backoff_ms: u64) {
+            description("scheduler is too busy")
+            display("scheduler is too busy, retry after {}ms", backoff_ms)
+        }
         GCWorkerTooBusy {
             description("gc worker is too busy")
         }
@@ -1381,6 +2013,28 @@ quick_error! {
             description("invalid cf name")
             display("invalid cf name: {}", cf_name)
         }
+        RegionInFlashback(region_id: u64) {
+            description("region is in flashback")
+            display("region {} is in flashback", region_id)
+        }
+    }
+}
+
+impl Error {
+    pub fn maybe_clone(&self) -> Option<Error> {
+        match *self {
+            Error::Engine(ref e) => e.maybe_clone().map(Error::Engine),
+            Error::Txn(ref e) => e.maybe_clone().map(Error::Txn),
+            Error::Mvcc(ref e) => e.maybe_clone().map(Error::Mvcc),
+            Error::Closed => Some(Error::Closed),
+            Error::SchedTooBusy => Some(Error::SchedTooBusy),
+            Error::SchedTooBusyWithBackoff(ms) => Some(Error::SchedTooBusyWithBackoff(ms)),
+            Error::GCWorkerTooBusy => Some(Error::GCWorkerTooBusy),
+            Error::KeyTooLarge(size, limit) => Some(Error::KeyTooLarge(size, limit)),
+            Error::InvalidCf(ref cf_name) => Some(Error::InvalidCf(cf_name.clone())),
+            Error::RegionInFlashback(region_id) => Some(Error::RegionInFlashback(region_id)),
+            Error::Other(_) | Error::Io(_) => None,
+        }
     }
 }
 
@@ -1500,7 +2154,7 @@ mod tests {
         Box::new(move |x: Result<T>| {
             expect_error(
                 |err| match err {
-                    Error::SchedTooBusy => {}
+                    Error::SchedTooBusyWithBackoff(backoff_ms) => assert!(backoff_ms > 0),
                     e => panic!("unexpected error chain: {:?}, expect too busy", e),
                 },
                 x,
@@ -1716,6 +2370,135 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_scan_in_batches() {
+        let read_pool = new_read_pool();
+        let config = Config::default();
+        let mut storage = Storage::new(&config, read_pool).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let keys: Vec<_> = (0..SCAN_BATCH_SIZE * 2 + 10)
+            .map(|i| format!("k{:04}", i).into_bytes())
+            .collect();
+        let mutations = keys
+            .iter()
+            .map(|k| Mutation::Put((Key::from_raw(k), k.clone())))
+            .collect();
+        storage
+            .async_prewrite(
+                Context::new(),
+                mutations,
+                keys[0].clone(),
+                1,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                keys.iter().map(|k| Key::from_raw(k)).collect(),
+                1,
+                2,
+                expect_ok_callback(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        for &limit in &[SCAN_BATCH_SIZE, SCAN_BATCH_SIZE + 1, keys.len(), keys.len() + 1] {
+            let expected = keys
+                .iter()
+                .take(limit)
+                .map(|k| Some((k.clone(), k.clone())))
+                .collect();
+            expect_multi_values(
+                expected,
+                storage
+                    .async_scan(
+                        Context::new(),
+                        Key::from_raw(b"\x00"),
+                        limit,
+                        5,
+                        Options::default(),
+                    )
+                    .wait(),
+            );
+        }
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_scan_with_checksum() {
+        let read_pool = new_read_pool();
+        let config = Config::default();
+        let mut storage = Storage::new(&config, read_pool).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let keys: Vec<_> = (0..5).map(|i| format!("k{}", i).into_bytes()).collect();
+        let mutations = keys
+            .iter()
+            .map(|k| Mutation::Put((Key::from_raw(k), k.clone())))
+            .collect();
+        storage
+            .async_prewrite(
+                Context::new(),
+                mutations,
+                keys[0].clone(),
+                1,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                keys.iter().map(|k| Key::from_raw(k)).collect(),
+                1,
+                2,
+                expect_ok_callback(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        let mut expected = ScanChecksum::default();
+        for k in &keys {
+            expected.update(k, k, 1);
+        }
+        {
+            let scan = |start_key: &[u8], limit: usize, options: Options| {
+                storage
+                    .async_scan_with_checksum(
+                        Context::new(),
+                        Key::from_raw(start_key),
+                        limit,
+                        5,
+                        options,
+                    )
+                    .wait()
+                    .unwrap()
+            };
+
+            let (results, checksum) = scan(b"\x00", 10, Options::default());
+            assert_eq!(results.len(), keys.len());
+            assert_eq!(checksum, expected);
+            assert_eq!(checksum.total_kvs, keys.len() as u64);
+
+            // The checksum doesn't depend on the order of the pairs.
+            let (_, checksum) = scan(b"\xff", 10, Options::default().reverse_scan());
+            assert_eq!(checksum, expected);
+
+            // The checksums of consecutive scans can be merged.
+            let (_, mut checksum) = scan(b"\x00", 2, Options::default());
+            assert_ne!(checksum, expected);
+            let (_, rest) = scan(b"k2", 10, Options::default());
+            checksum.merge(&rest);
+            assert_eq!(checksum, expected);
+        }
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_batch_get() {
         let read_pool = new_read_pool();
@@ -1784,6 +2567,78 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_batch_get_command() {
+        let read_pool = new_read_pool();
+        let config = Config::default();
+        let mut storage = Storage::new(&config, read_pool).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![
+                    Mutation::Put((Key::from_raw(b"a"), b"aa".to_vec())),
+                    Mutation::Put((Key::from_raw(b"b"), b"bb".to_vec())),
+                ],
+                b"a".to_vec(),
+                1,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 0),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![Key::from_raw(b"a")],
+                1,
+                2,
+                expect_ok_callback(tx.clone(), 1),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((Key::from_raw(b"c"), b"cc".to_vec()))],
+                b"c".to_vec(),
+                3,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        let gets = vec![
+            PointGetCommand::new(Context::new(), Key::from_raw(b"a"), 5),
+            PointGetCommand::new(Context::new(), Key::from_raw(b"a"), 1),
+            PointGetCommand::new(Context::new(), Key::from_raw(b"b"), 5),
+            PointGetCommand::new(Context::new(), Key::from_raw(b"c"), 5),
+            PointGetCommand::new(Context::new(), Key::from_raw(b"x"), 5),
+        ];
+        let mut results = storage
+            .async_batch_get_command(gets)
+            .wait()
+            .unwrap()
+            .into_iter();
+        expect_value(b"aa".to_vec(), results.next().unwrap());
+        expect_none(results.next().unwrap());
+        // The lock of `b` is resolved by the txn status cache, since its primary
+        // is committed.
+        expect_value(b"bb".to_vec(), results.next().unwrap());
+        expect_error(
+            |e| match e {
+                Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked { .. })) => (),
+                e => panic!("unexpected error chain: {:?}", e),
+            },
+            results.next().unwrap(),
+        );
+        expect_none(results.next().unwrap());
+        assert!(results.next().is_none());
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_txn() {
         let read_pool = new_read_pool();
@@ -2043,6 +2898,37 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_low_priority_no_block_normal() {
+        let read_pool = new_read_pool();
+        let mut config = Config::default();
+        config.scheduler_worker_pool_size = 1;
+        let mut storage = Storage::new(&config, read_pool).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+
+        let mut ctx = Context::new();
+        ctx.set_priority(CommandPri::Low);
+        storage
+            .async_pause(ctx, 1000, expect_ok_callback(tx.clone(), 1))
+            .unwrap();
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((Key::from_raw(b"x"), b"100".to_vec()))],
+                b"x".to_vec(),
+                100,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 2),
+            )
+            .unwrap();
+        // Command Prewrite with normal priority not block by the low priority Pause.
+        assert_eq!(rx.recv().unwrap(), 2);
+        assert_eq!(rx.recv().unwrap(), 1);
+
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_delete_range() {
         let read_pool = new_read_pool();
@@ -3047,4 +3933,157 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_flashback() {
+        use storage::txn::FLASHBACK_BATCH_SIZE;
+
+        let read_pool = new_read_pool();
+        let config = Config::default();
+        let mut storage = Storage::new(&config, read_pool).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+
+        let keys: Vec<_> = (0..FLASHBACK_BATCH_SIZE + 1)
+            .map(|i| Key::from_raw(format!("x{:08}", i).as_bytes()))
+            .collect();
+        // Puts "v1" at 11 and "v2" at 21 to all keys, and creates "y" at 31.
+        for &(value, start_ts) in &[(b"v1", 10), (b"v2", 20)] {
+            let mutations = keys
+                .iter()
+                .map(|k| Mutation::Put((k.clone(), value.to_vec())))
+                .collect();
+            storage
+                .async_prewrite(
+                    Context::new(),
+                    mutations,
+                    b"x".to_vec(),
+                    start_ts,
+                    Options::default(),
+                    expect_ok_callback(tx.clone(), 0),
+                )
+                .unwrap();
+            rx.recv().unwrap();
+            storage
+                .async_commit(
+                    Context::new(),
+                    keys.clone(),
+                    start_ts,
+                    start_ts + 1,
+                    expect_ok_callback(tx.clone(), 1),
+                )
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((Key::from_raw(b"y"), b"v3".to_vec()))],
+                b"y".to_vec(),
+                30,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 2),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_commit(
+                Context::new(),
+                vec![Key::from_raw(b"y")],
+                30,
+                31,
+                expect_ok_callback(tx.clone(), 3),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Writes are rejected while the region is in flashback.
+        storage.flashback_regions.write().unwrap().insert(0);
+        match storage.async_rollback(Context::new(), vec![Key::from_raw(b"y")], 32, box |_| ()) {
+            Err(Error::RegionInFlashback(0)) => {}
+            res => panic!("expect region in flashback, got {:?}", res),
+        }
+        storage.flashback_regions.write().unwrap().clear();
+
+        // The flashback waits for the commands received before it.
+        storage
+            .async_pause(Context::new(), 500, expect_ok_callback(tx.clone(), 7))
+            .unwrap();
+        storage
+            .async_flashback(
+                Context::new(),
+                Key::from_raw(b""),
+                None,
+                15,
+                40,
+                41,
+                expect_ok_callback(tx.clone(), 4),
+            )
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), 7);
+        assert_eq!(rx.recv().unwrap(), 4);
+        for k in &keys {
+            expect_value(
+                b"v1".to_vec(),
+                storage.async_get(Context::new(), k.clone(), 41).wait(),
+            );
+        }
+        expect_none(
+            storage
+                .async_get(Context::new(), Key::from_raw(b"y"), 41)
+                .wait(),
+        );
+        expect_value(
+            b"v2".to_vec(),
+            storage.async_get(Context::new(), keys[0].clone(), 40).wait(),
+        );
+
+        // Locks fail the flashback, even if the locked key has no writes yet.
+        storage
+            .async_prewrite(
+                Context::new(),
+                vec![Mutation::Put((Key::from_raw(b"z"), b"v4".to_vec()))],
+                b"z".to_vec(),
+                50,
+                Options::default(),
+                expect_ok_callback(tx.clone(), 5),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_flashback(
+                Context::new(),
+                Key::from_raw(b""),
+                None,
+                31,
+                55,
+                56,
+                expect_fail_callback(tx.clone(), 6, |e| match e {
+                    Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked { .. })) => (),
+                    e => panic!("unexpected error chain: {:?}", e),
+                }),
+            )
+            .unwrap();
+        rx.recv().unwrap();
+        assert!(storage.flashback_regions.read().unwrap().is_empty());
+
+        // The versions before the GC safe point can't be flashed back to.
+        storage
+            .async_gc(Context::new(), 20, expect_ok_callback(tx.clone(), 8))
+            .unwrap();
+        rx.recv().unwrap();
+        storage
+            .async_flashback(
+                Context::new(),
+                Key::from_raw(b""),
+                None,
+                15,
+                60,
+                61,
+                box |_| (),
+            )
+            .unwrap_err();
+        assert!(storage.flashback_regions.read().unwrap().is_empty());
+        storage.stop().unwrap();
+    }
 }
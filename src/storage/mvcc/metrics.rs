@@ -27,6 +27,16 @@ make_static_metric! {
         rollback,
     }
 
+    pub label_enum TxnStatusCacheResult {
+        hit,
+        miss,
+    }
+
+    pub label_enum MvccAssertionResult {
+        pass,
+        fail,
+    }
+
     pub struct MvccConflictCounterVec: IntCounter {
         "type" => MvccConflictKind,
     }
@@ -34,6 +44,14 @@ make_static_metric! {
     pub struct MvccDuplicateCmdCounterVec: IntCounter {
         "type" => MvccDuplicateCommandKind,
     }
+
+    pub struct TxnStatusCacheCounterVec: IntCounter {
+        "result" => TxnStatusCacheResult,
+    }
+
+    pub struct MvccAssertionCounterVec: IntCounter {
+        "result" => MvccAssertionResult,
+    }
 }
 
 lazy_static! {
@@ -63,4 +81,20 @@ lazy_static! {
             &["type"]
         ).unwrap()
     };
+    pub static ref TXN_STATUS_CACHE_COUNTER: TxnStatusCacheCounterVec = {
+        register_static_int_counter_vec!(
+            TxnStatusCacheCounterVec,
+            "tikv_storage_mvcc_txn_status_cache",
+            "Total number of txn status cache lookups",
+            &["result"]
+        ).unwrap()
+    };
+    pub static ref MVCC_ASSERTION_COUNTER: MvccAssertionCounterVec = {
+        register_static_int_counter_vec!(
+            MvccAssertionCounterVec,
+            "tikv_storage_mvcc_assertion_counter",
+            "Total number of assertions checked in prewrite",
+            &["result"]
+        ).unwrap()
+    };
 }
@@ -15,16 +15,19 @@ mod lock;
 mod metrics;
 mod reader;
 mod txn;
+mod txn_status_cache;
 mod write;
 
 pub use self::lock::{Lock, LockType};
-pub use self::reader::MvccReader;
+pub use self::reader::{MvccReader, ScanChecksum};
 pub use self::reader::{BackwardScanner, BackwardScannerBuilder};
 pub use self::reader::{ForwardScanner, ForwardScannerBuilder};
 pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
+pub use self::txn_status_cache::{TxnStatus, TxnStatusCache};
 pub use self::write::{Write, WriteType};
 use std::error;
 use std::io;
+use storage::Assertion;
 use util::escape;
 
 quick_error! {
@@ -68,6 +71,11 @@ quick_error! {
             display("write conflict {} with {}, key:{:?}, primary:{:?}",
              start_ts, conflict_ts, escape(key), escape(primary))
         }
+        AssertionFailed { start_ts: u64, key: Vec<u8>, assertion: Assertion, existing: Option<Vec<u8>> } {
+            description("assertion failed")
+            display("assertion {:?} failed on key:{:?} @{}, existing value:{:?}",
+             assertion, escape(key), start_ts, existing.as_ref().map(|v| escape(v)))
+        }
         KeyVersion {description("bad format key(version)")}
         Other(err: Box<error::Error + Sync + Send>) {
             from()
@@ -116,6 +124,17 @@ impl Error {
                 key: key.to_owned(),
                 primary: primary.to_owned(),
             }),
+            Error::AssertionFailed {
+                start_ts,
+                ref key,
+                ref assertion,
+                ref existing,
+            } => Some(Error::AssertionFailed {
+                start_ts,
+                key: key.to_owned(),
+                assertion: assertion.clone(),
+                existing: existing.clone(),
+            }),
             Error::KeyVersion => Some(Error::KeyVersion),
             Error::Committed { commit_ts } => Some(Error::Committed { commit_ts }),
             Error::Io(_) | Error::Other(_) => None,
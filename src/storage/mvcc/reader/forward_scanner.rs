@@ -21,7 +21,7 @@ use storage::mvcc::Result;
 use storage::{Cursor, CursorBuilder, Key, Lock, Snapshot, Statistics, Value};
 use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE};
 
-use super::util::CheckLockResult;
+use super::util::{CheckLockResult, ScanChecksum};
 
 /// `ForwardScanner` factory.
 pub struct ForwardScannerBuilder<S: Snapshot> {
@@ -32,6 +32,7 @@ pub struct ForwardScannerBuilder<S: Snapshot> {
     lower_bound: Option<Key>,
     upper_bound: Option<Key>,
     ts: u64,
+    checksum: bool,
 }
 
 impl<S: Snapshot> ForwardScannerBuilder<S> {
@@ -45,6 +46,7 @@ impl<S: Snapshot> ForwardScannerBuilder<S> {
             lower_bound: None,
             upper_bound: None,
             ts,
+            checksum: false,
         }
     }
 
@@ -78,6 +80,15 @@ impl<S: Snapshot> ForwardScannerBuilder<S> {
         self
     }
 
+    /// Set whether to compute the checksum of the versions read, see `ScanChecksum`.
+    ///
+    /// Defaults to `false`.
+    #[inline]
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
     /// Limit the range to `[lower_bound, upper_bound)` in which the `ForwardScanner` should scan.
     /// `None` means unbounded.
     ///
@@ -114,6 +125,11 @@ impl<S: Snapshot> ForwardScannerBuilder<S> {
             default_cursor: None,
             is_started: false,
             statistics: Statistics::default(),
+            checksum: if self.checksum {
+                Some(ScanChecksum::default())
+            } else {
+                None
+            },
         })
     }
 }
@@ -148,6 +164,8 @@ pub struct ForwardScanner<S: Snapshot> {
     is_started: bool,
 
     statistics: Statistics,
+
+    checksum: Option<ScanChecksum>,
 }
 
 impl<S: Snapshot> ForwardScanner<S> {
@@ -156,6 +174,11 @@ impl<S: Snapshot> ForwardScanner<S> {
         ::std::mem::replace(&mut self.statistics, Statistics::default())
     }
 
+    /// Get the checksum of the versions read so far, if it's enabled.
+    pub fn checksum(&self) -> Option<ScanChecksum> {
+        self.checksum
+    }
+
     /// Get the next key-value pair, in forward order.
     pub fn read_next(&mut self) -> Result<Option<(Key, Value)>> {
         if !self.is_started {
@@ -398,26 +421,31 @@ impl<S: Snapshot> ForwardScanner<S> {
     /// The implementation is the same as `PointGetter::load_data_by_write`.
     #[inline]
     fn load_data_by_write(&mut self, write: Write, user_key: &Key) -> Result<Value> {
-        if self.omit_value {
-            return Ok(vec![]);
-        }
-        match write.short_value {
-            Some(value) => {
-                // Value is carried in `write`.
-                Ok(value)
-            }
-            None => {
-                // Value is in the default CF.
-                self.ensure_default_cursor()?;
-                let value = super::util::near_load_data_by_write(
-                    &mut self.default_cursor.as_mut().unwrap(),
-                    user_key,
-                    write,
-                    &mut self.statistics,
-                )?;
-                Ok(value)
+        let start_ts = write.start_ts;
+        let value = if self.omit_value {
+            vec![]
+        } else {
+            match write.short_value {
+                Some(value) => {
+                    // Value is carried in `write`.
+                    value
+                }
+                None => {
+                    // Value is in the default CF.
+                    self.ensure_default_cursor()?;
+                    super::util::near_load_data_by_write(
+                        &mut self.default_cursor.as_mut().unwrap(),
+                        user_key,
+                        write,
+                        &mut self.statistics,
+                    )?
+                }
             }
+        };
+        if let Some(ref mut checksum) = self.checksum {
+            checksum.update(&user_key.to_raw()?, &value, start_ts);
         }
+        Ok(value)
     }
 
     /// After `self.get()`, our write cursor may be pointing to current user key (if we
@@ -16,10 +16,12 @@ mod forward_scanner;
 mod util;
 
 use super::lock::{Lock, LockType};
+use super::txn_status_cache::{TxnStatus, TxnStatusCache};
 use super::write::{Write, WriteType};
 use super::{Error, Result};
 use kvproto::kvrpcpb::IsolationLevel;
 use raftstore::store::engine::IterOption;
+use std::sync::Arc;
 use std::u64;
 use storage::engine::{Cursor, ScanMode, Snapshot, Statistics};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
@@ -27,6 +29,7 @@ use util::properties::MvccProperties;
 
 pub use self::backward_scanner::{BackwardScanner, BackwardScannerBuilder};
 pub use self::forward_scanner::{ForwardScanner, ForwardScannerBuilder};
+pub use self::util::ScanChecksum;
 
 const GC_MAX_ROW_VERSIONS_THRESHOLD: u64 = 100;
 
@@ -45,6 +48,7 @@ pub struct MvccReader<S: Snapshot> {
     lower_bound: Option<Vec<u8>>,
     upper_bound: Option<Vec<u8>>,
     isolation_level: IsolationLevel,
+    txn_status_cache: Option<Arc<TxnStatusCache>>,
 }
 
 impl<S: Snapshot> MvccReader<S> {
@@ -68,9 +72,16 @@ impl<S: Snapshot> MvccReader<S> {
             fill_cache,
             lower_bound,
             upper_bound,
+            txn_status_cache: None,
         }
     }
 
+    /// Sets the cache used to resolve locks of already committed or rolled back
+    /// transactions when getting a key.
+    pub fn set_txn_status_cache(&mut self, cache: Arc<TxnStatusCache>) {
+        self.txn_status_cache = Some(cache);
+    }
+
     pub fn get_statistics(&self) -> &Statistics {
         &self.statistics
     }
@@ -199,11 +210,32 @@ impl<S: Snapshot> MvccReader<S> {
         Ok(Some((commit_ts, write)))
     }
 
-    fn check_lock(&mut self, key: &Key, ts: u64) -> Result<u64> {
-        if let Some(lock) = self.load_lock(key)? {
-            return self.check_lock_impl(key, ts, lock);
+    /// Looks up the status of the lock's transaction in the txn status cache, only
+    /// if the lock may block reading at `ts`.
+    fn cached_txn_status(&self, ts: u64, lock: &Lock) -> Option<TxnStatus> {
+        match self.txn_status_cache {
+            Some(ref cache) if lock.ts <= ts && lock.lock_type != LockType::Lock => {
+                cache.get(lock.ts)
+            }
+            _ => None,
+        }
+    }
+
+    /// Loads the value carried by a lock whose transaction is known to be committed.
+    fn load_locked_value(&mut self, key: &Key, mut lock: Lock) -> Result<Option<Value>> {
+        match lock.lock_type {
+            LockType::Put => {
+                if self.key_only {
+                    return Ok(Some(vec![]));
+                }
+                if lock.short_value.is_some() {
+                    return Ok(lock.short_value.take());
+                }
+                self.load_data(key, lock.ts).map(Some)
+            }
+            LockType::Delete => Ok(None),
+            LockType::Lock => unreachable!(),
         }
-        Ok(ts)
     }
 
     fn check_lock_impl(&self, key: &Key, ts: u64, lock: Lock) -> Result<u64> {
@@ -231,7 +263,19 @@ impl<S: Snapshot> MvccReader<S> {
     pub fn get(&mut self, key: &Key, mut ts: u64) -> Result<Option<Value>> {
         // Check for locks that signal concurrent writes.
         match self.isolation_level {
-            IsolationLevel::SI => ts = self.check_lock(key, ts)?,
+            IsolationLevel::SI => if let Some(lock) = self.load_lock(key)? {
+                match self.cached_txn_status(ts, &lock) {
+                    // The lock's transaction is committed before `ts`, so the locked
+                    // value is what we should read.
+                    Some(TxnStatus::Committed(commit_ts)) if commit_ts <= ts => {
+                        return self.load_locked_value(key, lock);
+                    }
+                    // The lock's transaction is rolled back or committed after `ts`,
+                    // so the lock can be ignored.
+                    Some(_) => {}
+                    None => ts = self.check_lock_impl(key, ts, lock)?,
+                }
+            },
             IsolationLevel::RC => {}
         }
         loop {
@@ -457,6 +501,12 @@ impl<S: Snapshot> MvccReader<S> {
             return true;
         }
 
+        // A lot of delete marks to GC, which can be removed together with all the versions
+        // of their rows.
+        if props.num_deletes as f64 > props.num_rows as f64 * (ratio_threshold - 1.0) {
+            return true;
+        }
+
         // A lot of MVCC versions of a single row to GC.
         props.max_row_versions > GC_MAX_ROW_VERSIONS_THRESHOLD
     }
@@ -481,6 +531,11 @@ impl<S: Snapshot> MvccReader<S> {
                 continue;
             }
             props.add(&mvcc);
+            // The delete marks after safe_point can't be removed by GC, only count the ones
+            // in the files older than safe_point.
+            if mvcc.max_ts > safe_point {
+                props.num_deletes -= mvcc.num_deletes;
+            }
         }
         Some(props)
     }
@@ -711,8 +766,12 @@ mod tests {
         assert_eq!(props.max_ts, 8);
         assert_eq!(props.num_rows, 6);
         assert_eq!(props.num_puts, 6);
+        assert_eq!(props.num_deletes, 2);
         assert_eq!(props.num_versions, 8);
         assert_eq!(props.max_row_versions, 2);
+        // The delete marks after the `safe_point` can't be GC-ed, so they are not counted.
+        let props = check_need_gc(Arc::clone(&db), region.clone(), 6, true).unwrap();
+        assert_eq!(props.num_deletes, 0);
         // But if the `safe_point` is older than all versions, we don't need gc too.
         let props = check_need_gc(Arc::clone(&db), region.clone(), 0, false).unwrap();
         assert_eq!(props.min_ts, u64::MAX);
@@ -11,6 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use byteorder::{BigEndian, ByteOrder};
+use crc::crc64::{self, Digest, Hasher64};
+
 use storage::mvcc::{Error, Result};
 use storage::mvcc::{Lock, LockType, Write};
 use storage::{Cursor, Iterator, Key, Statistics, Value};
@@ -56,6 +59,39 @@ pub fn check_lock(key: &Key, ts: u64, lock: &Lock) -> Result<CheckLockResult> {
     }))
 }
 
+/// `ScanChecksum` is a checksum of the `(key, value, start_ts)` of the versions read by
+/// a scanner, so the results can be verified without reading the data a second time.
+///
+/// The checksum of each version is combined by xor, so it doesn't depend on the order of
+/// the versions: scanning forward or backward, or in several ranges, gives the same result.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScanChecksum {
+    pub crc64_xor: u64,
+    pub total_kvs: u64,
+    pub total_bytes: u64,
+}
+
+impl ScanChecksum {
+    pub fn update(&mut self, raw_key: &[u8], value: &[u8], start_ts: u64) {
+        let mut ts = [0; 8];
+        BigEndian::write_u64(&mut ts, start_ts);
+        let mut digest = Digest::new(crc64::ECMA);
+        digest.write(raw_key);
+        digest.write(value);
+        digest.write(&ts);
+        self.crc64_xor ^= digest.sum64();
+        self.total_kvs += 1;
+        self.total_bytes += (raw_key.len() + value.len()) as u64;
+    }
+
+    /// Combines the checksum of another set of versions.
+    pub fn merge(&mut self, other: &ScanChecksum) {
+        self.crc64_xor ^= other.crc64_xor;
+        self.total_kvs += other.total_kvs;
+        self.total_bytes += other.total_bytes;
+    }
+}
+
 /// Reads user key's value in default CF according to the given write CF value
 /// (`write`).
 ///
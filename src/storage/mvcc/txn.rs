@@ -20,7 +20,8 @@ use kvproto::kvrpcpb::IsolationLevel;
 use std::fmt;
 use storage::engine::{Modify, ScanMode, Snapshot};
 use storage::{
-    is_short_value, Key, Mutation, Options, Statistics, Value, CF_DEFAULT, CF_LOCK, CF_WRITE,
+    is_short_value, Assertion, AssertionMode, Key, Mutation, Options, Statistics, Value,
+    CF_DEFAULT, CF_LOCK, CF_WRITE,
 };
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
@@ -144,6 +145,19 @@ impl<S: Snapshot> MvccTxn<S> {
         mutation: Mutation,
         primary: &[u8],
         options: &Options,
+    ) -> Result<()> {
+        self.conditional_prewrite(mutation, &Assertion::None, primary, options)
+    }
+
+    /// Prewrites the mutation only if `assertion` holds on the latest committed value of
+    /// the key, otherwise `Error::AssertionFailed` is returned. If `options.assertion_mode`
+    /// is `LogOnly`, a failed assertion is only logged and the mutation is still prewritten.
+    pub fn conditional_prewrite(
+        &mut self,
+        mutation: Mutation,
+        assertion: &Assertion,
+        primary: &[u8],
+        options: &Options,
     ) -> Result<()> {
         {
             let key = mutation.key();
@@ -179,6 +193,25 @@ impl<S: Snapshot> MvccTxn<S> {
                 MVCC_DUPLICATE_CMD_COUNTER_VEC.prewrite.inc();
                 return Ok(());
             }
+            // There is no lock on the key, so the value read is the latest committed one.
+            if *assertion != Assertion::None {
+                let existing = self.reader.get(key, u64::max_value())?;
+                if assertion.check(existing.as_ref()) {
+                    MVCC_ASSERTION_COUNTER.pass.inc();
+                } else {
+                    MVCC_ASSERTION_COUNTER.fail.inc();
+                    let err = Error::AssertionFailed {
+                        start_ts: self.start_ts,
+                        key: key.to_raw()?,
+                        assertion: assertion.clone(),
+                        existing,
+                    };
+                    match options.assertion_mode {
+                        AssertionMode::Enforce => return Err(err),
+                        AssertionMode::LogOnly => warn!("{}", err),
+                    }
+                }
+            }
         }
 
         let lock_type = LockType::from_mutation(&mutation);
@@ -300,6 +333,43 @@ impl<S: Snapshot> MvccTxn<S> {
         Ok(())
     }
 
+    /// Commits `value` as the newest version of `key` at `commit_ts` directly, without a
+    /// prewrite, which rewinds the key to the value while keeping its history. `None` means
+    /// the key is deleted. It's only used by flashback, whose region rejects other writes.
+    pub fn flashback(&mut self, key: Key, value: Option<Value>, commit_ts: u64) -> Result<()> {
+        if let Some(lock) = self.reader.load_lock(&key)? {
+            return Err(Error::KeyIsLocked {
+                key: key.to_raw()?,
+                primary: lock.primary,
+                ts: lock.ts,
+                ttl: lock.ttl,
+            });
+        }
+        if let Some((commit, _)) = self.reader.seek_write(&key, u64::max_value())? {
+            if commit >= self.start_ts {
+                MVCC_CONFLICT_COUNTER.prewrite_write_conflict.inc();
+                return Err(Error::WriteConflict {
+                    start_ts: self.start_ts,
+                    conflict_ts: commit,
+                    key: key.to_raw()?,
+                    primary: vec![],
+                });
+            }
+        }
+        let write = match value {
+            Some(value) => if is_short_value(&value) {
+                Write::new(WriteType::Put, self.start_ts, Some(value))
+            } else {
+                let ts = self.start_ts;
+                self.put_value(key.clone(), ts, value);
+                Write::new(WriteType::Put, self.start_ts, None)
+            },
+            None => Write::new(WriteType::Delete, self.start_ts, None),
+        };
+        self.put_write(key, commit_ts, write.to_bytes());
+        Ok(())
+    }
+
     fn collapse_prev_rollback(&mut self, key: Key) -> Result<()> {
         if let Some((commit_ts, write)) = self.reader.seek_write(&key, self.start_ts)? {
             if write.write_type == WriteType::Rollback {
@@ -385,8 +455,10 @@ mod tests {
     use storage::engine::{self, Engine, TEMP_DIR};
     use storage::mvcc::tests::*;
     use storage::mvcc::WriteType;
-    use storage::mvcc::{MvccReader, MvccTxn};
-    use storage::{Key, Mutation, Options, ScanMode, ALL_CFS, SHORT_VALUE_MAX_LEN};
+    use storage::mvcc::{Error, MvccReader, MvccTxn, Result};
+    use storage::{
+        Assertion, AssertionMode, Key, Mutation, Options, ScanMode, ALL_CFS, SHORT_VALUE_MAX_LEN,
+    };
 
     fn test_mvcc_txn_read_imp(k: &[u8], v: &[u8]) {
         let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
@@ -464,6 +536,54 @@ mod tests {
         must_rollback(&engine, k, 15);
     }
 
+    fn must_flashback<E: Engine>(
+        engine: &E,
+        key: &[u8],
+        value: Option<&[u8]>,
+        start_ts: u64,
+        commit_ts: u64,
+    ) -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, start_ts, true).unwrap();
+        txn.flashback(Key::from_raw(key), value.map(|v| v.to_vec()), commit_ts)?;
+        engine.write(&ctx, txn.into_modifies()).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_flashback() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        let (k, v1) = (b"k1", b"v1");
+        let v2 = "v".repeat(SHORT_VALUE_MAX_LEN + 1);
+        must_prewrite_put(&engine, k, v1, k, 5);
+        must_commit(&engine, k, 5, 10);
+        must_prewrite_put(&engine, k, v2.as_bytes(), k, 15);
+        must_commit(&engine, k, 15, 20);
+
+        // Rewind to the long value, then to the short one, and delete it at last.
+        must_flashback(&engine, k, Some(v2.as_bytes()), 25, 30).unwrap();
+        must_get(&engine, k, 30, v2.as_bytes());
+        must_flashback(&engine, k, Some(v1), 35, 40).unwrap();
+        must_get(&engine, k, 40, v1);
+        must_get(&engine, k, 35, v2.as_bytes());
+        must_flashback(&engine, k, None, 45, 50).unwrap();
+        must_get_none(&engine, k, 50);
+
+        // Conflicts with newer writes.
+        match must_flashback(&engine, k, Some(v1), 45, 55) {
+            Err(Error::WriteConflict { conflict_ts, .. }) => assert_eq!(conflict_ts, 50),
+            res => panic!("expect write conflict, got {:?}", res),
+        }
+        // Fails on locks.
+        must_prewrite_put(&engine, k, v1, k, 60);
+        match must_flashback(&engine, k, None, 65, 70) {
+            Err(Error::KeyIsLocked { ts, .. }) => assert_eq!(ts, 60),
+            res => panic!("expect key is locked, got {:?}", res),
+        }
+    }
+
     #[test]
     fn test_rollback_del() {
         let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
@@ -488,6 +608,83 @@ mod tests {
         test_mvcc_txn_prewrite_imp(b"k2", &long_value);
     }
 
+    fn conditional_prewrite_with_mode<E: Engine>(
+        engine: &E,
+        mutation: Mutation,
+        assertion: Assertion,
+        mode: AssertionMode,
+        ts: u64,
+    ) -> Result<()> {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot, ts, true).unwrap();
+        let pk = mutation.key().to_raw().unwrap();
+        let mut options = Options::default();
+        options.assertion_mode = mode;
+        txn.conditional_prewrite(mutation, &assertion, &pk, &options)?;
+        let modifies = txn.into_modifies();
+        if !modifies.is_empty() {
+            engine.write(&ctx, modifies).unwrap();
+        }
+        Ok(())
+    }
+
+    fn conditional_prewrite<E: Engine>(
+        engine: &E,
+        mutation: Mutation,
+        assertion: Assertion,
+        ts: u64,
+    ) -> Result<()> {
+        conditional_prewrite_with_mode(engine, mutation, assertion, AssertionMode::Enforce, ts)
+    }
+
+    #[test]
+    fn test_mvcc_txn_conditional_prewrite() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let (k, v) = (b"k", b"v");
+        let put = || Mutation::Put((Key::from_raw(k), v.to_vec()));
+
+        match conditional_prewrite(&engine, put(), Assertion::Exist, 5) {
+            Err(Error::AssertionFailed { existing: None, .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        conditional_prewrite(&engine, put(), Assertion::NotExist, 5).unwrap();
+        must_locked(&engine, k, 5);
+        // The assertion is skipped if the key is already locked by the transaction.
+        conditional_prewrite(&engine, put(), Assertion::Exist, 5).unwrap();
+        must_commit(&engine, k, 5, 10);
+
+        match conditional_prewrite(&engine, put(), Assertion::NotExist, 15) {
+            Err(Error::AssertionFailed { existing, .. }) => assert_eq!(existing.unwrap(), v),
+            res => panic!("unexpected result {:?}", res),
+        }
+        must_unlocked(&engine, k);
+        match conditional_prewrite(&engine, put(), Assertion::Equal(b"v1".to_vec()), 15) {
+            Err(Error::AssertionFailed { .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        conditional_prewrite(&engine, put(), Assertion::Equal(v.to_vec()), 15).unwrap();
+        must_commit(&engine, k, 15, 20);
+
+        let delete = Mutation::Delete(Key::from_raw(k));
+        conditional_prewrite(&engine, delete, Assertion::Exist, 25).unwrap();
+        must_commit(&engine, k, 25, 30);
+        match conditional_prewrite(&engine, put(), Assertion::Exist, 35) {
+            Err(Error::AssertionFailed { existing: None, .. }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // In the log-only mode, the key is prewritten even if the assertion fails.
+        conditional_prewrite_with_mode(
+            &engine,
+            put(),
+            Assertion::Exist,
+            AssertionMode::LogOnly,
+            35,
+        ).unwrap();
+        must_locked(&engine, k, 35);
+    }
+
     fn test_mvcc_txn_commit_ok_imp(k1: &[u8], v1: &[u8], k2: &[u8], k3: &[u8]) {
         let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
         must_prewrite_put(&engine, k1, v1, k1, 10);
@@ -0,0 +1,86 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Mutex;
+
+use util::lru::LruCache;
+
+use super::metrics::*;
+
+/// The final status of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxnStatus {
+    /// The transaction is committed with the commit ts.
+    Committed(u64),
+    RolledBack,
+}
+
+impl TxnStatus {
+    /// Converts the commit ts used by `ResolveLock`, in which 0 means rolled back.
+    pub fn from_commit_ts(commit_ts: u64) -> TxnStatus {
+        if commit_ts == 0 {
+            TxnStatus::RolledBack
+        } else {
+            TxnStatus::Committed(commit_ts)
+        }
+    }
+}
+
+/// `TxnStatusCache` remembers the statuses of recently resolved transactions,
+/// keyed by their start ts.
+///
+/// Readers that meet a lock can look up the status of the lock's transaction here
+/// and resolve the lock locally, instead of letting every client send resolve
+/// requests for the same lock.
+pub struct TxnStatusCache {
+    cache: Mutex<LruCache<u64, TxnStatus>>,
+}
+
+impl TxnStatusCache {
+    pub fn new(capacity: usize) -> TxnStatusCache {
+        TxnStatusCache {
+            cache: Mutex::new(LruCache::with_capacity(capacity)),
+        }
+    }
+
+    pub fn insert(&self, start_ts: u64, status: TxnStatus) {
+        self.cache.lock().unwrap().insert(start_ts, status);
+    }
+
+    pub fn get(&self, start_ts: u64) -> Option<TxnStatus> {
+        let res = self.cache.lock().unwrap().get(&start_ts).cloned();
+        if res.is_some() {
+            TXN_STATUS_CACHE_COUNTER.hit.inc();
+        } else {
+            TXN_STATUS_CACHE_COUNTER.miss.inc();
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txn_status_cache() {
+        let cache = TxnStatusCache::new(2);
+        cache.insert(1, TxnStatus::from_commit_ts(2));
+        cache.insert(3, TxnStatus::from_commit_ts(0));
+        assert_eq!(cache.get(1), Some(TxnStatus::Committed(2)));
+        assert_eq!(cache.get(3), Some(TxnStatus::RolledBack));
+        cache.insert(5, TxnStatus::Committed(6));
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(5), Some(TxnStatus::Committed(6)));
+    }
+}
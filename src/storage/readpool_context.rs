@@ -18,6 +18,7 @@ use std::mem;
 use pd;
 use server::readpool;
 use storage;
+use storage::engine::PerfStatisticsDelta;
 use util::collections::HashMap;
 use util::futurepool;
 use util::worker;
@@ -35,6 +36,7 @@ pub struct Context {
     command_counter: LocalIntCounterVec,
     command_pri_counter: LocalIntCounterVec,
     scan_details: LocalIntCounterVec,
+    rocksdb_perf_stats: LocalIntCounterVec,
 
     read_flow_stats: HashMap<u64, storage::FlowStatistics>,
 }
@@ -55,6 +57,7 @@ impl Context {
             command_counter: KV_COMMAND_COUNTER_VEC.local(),
             command_pri_counter: SCHED_COMMANDS_PRI_COUNTER_VEC.local(),
             scan_details: KV_COMMAND_SCAN_DETAILS.local(),
+            rocksdb_perf_stats: KV_COMMAND_ROCKSDB_PERF_COUNTER.local(),
             read_flow_stats: HashMap::default(),
         }
     }
@@ -99,6 +102,15 @@ impl Context {
         }
     }
 
+    #[inline]
+    pub fn collect_perf_stats(&mut self, cmd: &str, perf_statistics: &PerfStatisticsDelta) {
+        for &(metric, value) in &perf_statistics.labeled_values() {
+            self.rocksdb_perf_stats
+                .with_label_values(&[cmd, metric])
+                .inc_by(value as i64);
+        }
+    }
+
     #[inline]
     pub fn collect_read_flow(&mut self, region_id: u64, statistics: &storage::Statistics) {
         let flow_stats = self
@@ -119,6 +131,7 @@ impl futurepool::Context for Context {
         self.command_counter.flush();
         self.command_pri_counter.flush();
         self.scan_details.flush();
+        self.rocksdb_perf_stats.flush();
 
         // Report PD metrics
         if !self.read_flow_stats.is_empty() {
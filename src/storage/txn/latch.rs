@@ -20,15 +20,18 @@ use std::usize;
 
 /// Latch which is used to serialize accesses to resources hashed to the same slot.
 ///
-/// Latches are indexed by slot IDs. The keys of a command are hashed to slot IDs, then the command
-/// is added to the waiting queues of the latches.
+/// Latches are indexed by slot IDs. The keys of a command are hashed, and the hashes are mapped
+/// to slot IDs, then the command is added to the waiting queues of the latches along with the key
+/// hashes. Commands in the same queue conflict only if they have the same key hash, so unrelated
+/// keys sharing a slot don't block each other.
 ///
-/// If command A is ahead of command B in one latch, it must be ahead of command B in all the
-/// overlapping latches. This is an invariant ensured by the `gen_lock`, `acquire` and `release`.
+/// If command A is ahead of command B on one key hash, it must be ahead of command B on all the
+/// overlapping key hashes. This is an invariant ensured by the `gen_lock`, `acquire` and
+/// `release`.
 #[derive(Clone)]
 struct Latch {
-    // store waiting commands
-    pub waiting: VecDeque<u64>,
+    // store waiting commands as (key hash, command ID)
+    pub waiting: VecDeque<(u64, u64)>,
 }
 
 impl Latch {
@@ -38,34 +41,53 @@ impl Latch {
             waiting: VecDeque::new(),
         }
     }
+
+    /// Returns the ID of the first command waiting for the key hash.
+    fn first_req_by_hash(&self, hash: u64) -> Option<u64> {
+        self.waiting
+            .iter()
+            .find(|&&(h, _)| h == hash)
+            .map(|&(_, cid)| cid)
+    }
+
+    /// Removes the first command waiting for the key hash, which must be `who`.
+    fn pop_front_by_hash(&mut self, hash: u64, who: u64) {
+        let pos = self
+            .waiting
+            .iter()
+            .position(|&(h, _)| h == hash)
+            .unwrap();
+        let (_, front) = self.waiting.remove(pos).unwrap();
+        assert_eq!(front, who);
+    }
 }
 
 /// Lock required for a command.
 #[derive(Clone)]
 pub struct Lock {
-    /// The slot IDs of the latches that a command must acquire before being able to be processed.
-    pub required_slots: Vec<usize>,
+    /// The key hashes that a command must acquire before being able to be processed.
+    pub required_hashes: Vec<u64>,
 
-    /// The number of latches that the command has acquired.
+    /// The number of key hashes that the command has acquired.
     pub owned_count: usize,
 }
 
 impl Lock {
     /// Creates a lock.
-    pub fn new(required_slots: Vec<usize>) -> Lock {
+    pub fn new(required_hashes: Vec<u64>) -> Lock {
         Lock {
-            required_slots,
+            required_hashes,
             owned_count: 0,
         }
     }
 
     /// Returns true if all the required latches have be acquired, false otherwise.
     pub fn acquired(&self) -> bool {
-        self.required_slots.len() == self.owned_count
+        self.required_hashes.len() == self.owned_count
     }
 
     pub fn is_write_lock(&self) -> bool {
-        !self.required_slots.is_empty()
+        !self.required_hashes.is_empty()
     }
 }
 
@@ -95,33 +117,32 @@ impl Latches {
     where
         H: Hash,
     {
-        // prevent from deadlock, so we sort and deduplicate the index
-        let mut slots: Vec<usize> = keys.iter().map(|x| self.calc_slot(x)).collect();
-        slots.sort();
-        slots.dedup();
-        Lock::new(slots)
+        // prevent from deadlock, so we sort and deduplicate the hashes, then all commands
+        // acquire them in the same order.
+        let mut hashes: Vec<u64> = keys.iter().map(|x| self.calc_hash(x)).collect();
+        hashes.sort();
+        hashes.dedup();
+        Lock::new(hashes)
     }
 
     /// Tries to acquire the latches specified by the `lock` for command with ID `who`.
     ///
-    /// This method will enqueue the command ID into the waiting queues of the latches. A latch is
-    /// considered acquired if the command ID is at the front of the queue. Returns true if all the
-    /// Latches are acquired, false otherwise.
+    /// This method will enqueue the command ID into the waiting queues of the latches. A key hash
+    /// is considered acquired if the command ID is the first one waiting for it in the queue.
+    /// Returns true if all the key hashes are acquired, false otherwise.
     pub fn acquire(&mut self, lock: &mut Lock, who: u64) -> bool {
         let mut acquired_count: usize = 0;
-        for i in &lock.required_slots[lock.owned_count..] {
-            let latch = &mut self.slots[*i];
-
-            let front = latch.waiting.front().cloned();
-            match front {
+        for &hash in &lock.required_hashes[lock.owned_count..] {
+            let latch = &mut self.slots[self.slot_of(hash)];
+            match latch.first_req_by_hash(hash) {
                 Some(cid) => if cid == who {
                     acquired_count += 1;
                 } else {
-                    latch.waiting.push_back(who);
+                    latch.waiting.push_back((hash, who));
                     break;
                 },
                 None => {
-                    latch.waiting.push_back(who);
+                    latch.waiting.push_back((hash, who));
                     acquired_count += 1;
                 }
             }
@@ -133,29 +154,35 @@ impl Latches {
 
     /// Releases all latches owned by the `lock` of command with ID `who`, returns the wakeup list.
     ///
-    /// Preconditions: the caller must ensure the command is at the front of the latches.
+    /// Preconditions: the caller must ensure the command is the first one waiting for the key
+    /// hashes.
     pub fn release(&mut self, lock: &Lock, who: u64) -> Vec<u64> {
         let mut wakeup_list: Vec<u64> = vec![];
-        for i in &lock.required_slots[..lock.owned_count] {
-            let latch = &mut self.slots[*i];
-            let front = latch.waiting.pop_front().unwrap();
-            assert_eq!(front, who);
+        for &hash in &lock.required_hashes[..lock.owned_count] {
+            let latch = &mut self.slots[self.slot_of(hash)];
+            latch.pop_front_by_hash(hash, who);
 
-            if let Some(wakeup) = latch.waiting.front() {
-                wakeup_list.push(*wakeup);
+            if let Some(wakeup) = latch.first_req_by_hash(hash) {
+                wakeup_list.push(wakeup);
             }
         }
         wakeup_list
     }
 
-    /// Calculates the slot ID by hashing the `key`.
-    fn calc_slot<H>(&self, key: &H) -> usize
+    /// Calculates the hash of the `key`.
+    fn calc_hash<H>(&self, key: &H) -> u64
     where
         H: Hash,
     {
         let mut s = DefaultHasher::new();
         key.hash(&mut s);
-        (s.finish() as usize) & (self.size - 1)
+        s.finish()
+    }
+
+    /// Calculates the slot ID of the key hash.
+    #[inline]
+    fn slot_of(&self, hash: u64) -> usize {
+        (hash as usize) & (self.size - 1)
     }
 }
 
@@ -167,9 +194,9 @@ mod tests {
     fn test_wakeup() {
         let mut latches = Latches::new(256);
 
-        let slots_a: Vec<usize> = vec![1, 3, 5];
+        let slots_a: Vec<u64> = vec![1, 3, 5];
         let mut lock_a = Lock::new(slots_a);
-        let slots_b: Vec<usize> = vec![4, 5, 6];
+        let slots_b: Vec<u64> = vec![4, 5, 6];
         let mut lock_b = Lock::new(slots_b);
         let cid_a: u64 = 1;
         let cid_b: u64 = 2;
@@ -195,9 +222,9 @@ mod tests {
     fn test_wakeup_by_multi_cmds() {
         let mut latches = Latches::new(256);
 
-        let slots_a: Vec<usize> = vec![1, 2, 3];
-        let slots_b: Vec<usize> = vec![4, 5, 6];
-        let slots_c: Vec<usize> = vec![3, 4];
+        let slots_a: Vec<u64> = vec![1, 2, 3];
+        let slots_b: Vec<u64> = vec![4, 5, 6];
+        let slots_c: Vec<u64> = vec![3, 4];
         let mut lock_a = Lock::new(slots_a);
         let mut lock_b = Lock::new(slots_b);
         let mut lock_c = Lock::new(slots_c);
@@ -233,4 +260,25 @@ mod tests {
         acquired_c = latches.acquire(&mut lock_c, cid_c);
         assert_eq!(acquired_c, true);
     }
+
+    #[test]
+    fn test_no_false_conflict() {
+        let mut latches = Latches::new(256);
+
+        // 1 and 257 are mapped to the same slot, but they are different keys.
+        let mut lock_a = Lock::new(vec![1]);
+        let mut lock_b = Lock::new(vec![257]);
+        let mut lock_c = Lock::new(vec![1, 257]);
+        let (cid_a, cid_b, cid_c) = (1, 2, 3);
+
+        assert!(latches.acquire(&mut lock_a, cid_a));
+        assert!(latches.acquire(&mut lock_b, cid_b));
+        assert!(!latches.acquire(&mut lock_c, cid_c));
+
+        // b is released first, c still waits for a.
+        assert!(latches.release(&lock_b, cid_b).is_empty());
+        let wakeup = latches.release(&lock_a, cid_a);
+        assert_eq!(wakeup, vec![cid_c]);
+        assert!(latches.acquire(&mut lock_c, cid_c));
+    }
 }
@@ -18,9 +18,12 @@ mod store;
 
 use std::error;
 use std::io::Error as IoError;
+use std::time::Duration;
 
-pub use self::process::RESOLVE_LOCK_BATCH_SIZE;
-pub use self::scheduler::{Msg, Scheduler, CMD_BATCH_SIZE};
+use util::time::Instant;
+
+pub use self::process::{FLASHBACK_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
+pub use self::scheduler::{Msg, Scheduler, CMD_BATCH_SIZE, QUOTA_DELAY_CHECK_INTERVAL_MS};
 pub use self::store::{SnapshotStore, StoreScanner};
 
 quick_error! {
@@ -63,6 +66,10 @@ quick_error! {
                         start_ts,
                         commit_ts)
         }
+        DeadlineExceeded(elapsed: Duration) {
+            description("deadline exceeded")
+            display("deadline exceeded after waiting for {:?}", elapsed)
+        }
     }
 }
 
@@ -79,9 +86,57 @@ impl Error {
                 start_ts,
                 commit_ts,
             }),
+            Error::DeadlineExceeded(elapsed) => Some(Error::DeadlineExceeded(elapsed)),
             Error::Other(_) | Error::ProtoBuf(_) | Error::Io(_) => None,
         }
     }
 }
 
+/// The time by which a request has to be handled. A request still queued after it is dropped,
+/// since its client has most likely given up on it.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    start: Instant,
+    max_handle_duration: Duration,
+}
+
+impl Deadline {
+    /// A `max_handle_duration` of 0 means the request never expires.
+    pub fn from_now(max_handle_duration: Duration) -> Deadline {
+        Deadline {
+            start: Instant::now_coarse(),
+            max_handle_duration,
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        let elapsed = self.start.elapsed();
+        if self.max_handle_duration != Duration::from_secs(0)
+            && elapsed >= self.max_handle_duration
+        {
+            return Err(Error::DeadlineExceeded(elapsed));
+        }
+        Ok(())
+    }
+}
+
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Deadline::from_now(Duration::from_secs(0));
+        let expiring = Deadline::from_now(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(50));
+        deadline.check().unwrap();
+        match expiring.check() {
+            Err(Error::DeadlineExceeded(elapsed)) => assert!(elapsed >= Duration::from_millis(10)),
+            res => panic!("expect deadline exceeded, got {:?}", res),
+        }
+    }
+}
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::mem;
 use std::thread;
 use std::time::Duration;
@@ -19,6 +20,7 @@ use std::u64;
 use kvproto::kvrpcpb::{CommandPri, Context, LockInfo};
 use prometheus::local::LocalHistogramVec;
 
+use server::quota_limiter;
 use storage::engine::{CbContext, Modify, Result as EngineResult};
 use storage::mvcc::{
     Error as MvccError, Lock as MvccLock, MvccReader, MvccTxn, Write, MAX_TXN_WRITE_SIZE,
@@ -30,16 +32,20 @@ use storage::{
 use storage::{Key, KvPair, MvccInfo, Value};
 use util::collections::HashMap;
 use util::threadpool::{self, Context as ThreadContext, ContextFactory as ThreadContextFactory};
-use util::time::SlowTimer;
+use util::logger::{self, SLOW_LOG_TARGET};
+use util::time::{thread_cpu_time, Instant};
+use util::trace::Trace;
 use util::worker::{self, ScheduleError};
 
 use super::super::metrics::*;
 use super::scheduler::Msg;
-use super::{Error, Result};
+use super::{Deadline, Error, Result};
 
 // To resolve a key, the write size is about 100~150 bytes, depending on key and value length.
 // The write batch will be around 32KB if we scan 256 keys each time.
 pub const RESOLVE_LOCK_BATCH_SIZE: usize = 256;
+// The number of keys to scan for flashback each time.
+pub const FLASHBACK_BATCH_SIZE: usize = 256;
 
 /// Process result of a command.
 pub enum ProcessResult {
@@ -103,17 +109,27 @@ pub struct Task {
     cmd: Command,
     ts: u64,
     region_id: u64,
+    created_at: Instant,
+    deadline: Deadline,
+    trace: Trace,
 }
 
 impl Task {
     /// Creates a task for a running command.
-    pub fn new(cid: u64, cmd: Command) -> Task {
+    pub fn new(cid: u64, cmd: Command, max_handle_duration: Duration) -> Task {
+        let region_id = cmd.get_context().get_region_id();
+        let trace = Trace::new(cmd.tag());
+        trace.tag("region", region_id);
+        trace.tag("ts", cmd.ts());
         Task {
             cid,
             tag: cmd.tag(),
-            region_id: cmd.get_context().get_region_id(),
+            region_id,
             ts: cmd.ts(),
             cmd,
+            created_at: Instant::now_coarse(),
+            deadline: Deadline::from_now(max_handle_duration),
+            trace,
         }
     }
 
@@ -128,6 +144,10 @@ impl Task {
     pub fn context(&self) -> &Context {
         self.cmd.get_context()
     }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
 }
 
 pub struct Executor<E: Engine> {
@@ -158,6 +178,7 @@ impl<E: Engine> Executor<E> {
 
     /// Start the execution of the task.
     pub fn execute(mut self, cb_ctx: CbContext, snapshot: EngineResult<E::Snap>, task: Task) {
+        task.trace.stage("snapshot");
         debug!(
             "receive snapshot finish msg for cid={}, cb_ctx={:?}",
             task.cid, cb_ctx
@@ -206,6 +227,18 @@ impl<E: Engine> Executor<E> {
         let readonly = task.cmd.readonly();
         pool.schedule(move |ctx: &mut SchedContext<E>| {
             fail_point!("scheduler_async_snapshot_finish");
+            task.trace.stage("worker_wait");
+
+            // The latches are released without doing the dead work.
+            if let Err(err) = task.deadline.check() {
+                SCHED_STAGE_COUNTER_VEC
+                    .with_label_values(&[tag, "deadline_exceeded"])
+                    .inc();
+                task.trace.finish();
+                let cid = task.cid;
+                notify_scheduler(self.take_scheduler(), Msg::FinishedWithErr { cid, err, tag });
+                return;
+            }
 
             let _processing_read_timer = ctx
                 .processing_read_duration
@@ -214,7 +247,11 @@ impl<E: Engine> Executor<E> {
 
             let region_id = task.region_id;
             let ts = task.ts;
-            let timer = SlowTimer::new();
+            let source = quota_limiter::source_of_priority(task.priority());
+            let write_bytes = task.cmd.write_bytes();
+            let wait_time = task.created_at.elapsed();
+            let process_begin_at = Instant::now_coarse();
+            let cpu_begin = thread_cpu_time();
 
             let statistics = if readonly {
                 self.process_read(ctx, snapshot, task)
@@ -222,13 +259,26 @@ impl<E: Engine> Executor<E> {
                 self.process_write(ctx, snapshot, task)
             };
             ctx.add_statistics(tag, &statistics);
-            slow_log!(
-                timer,
-                "[region {}] scheduler handle command: {}, ts: {}",
-                region_id,
-                tag,
-                ts
-            );
+            let process_time = process_begin_at.elapsed();
+            if let Some(limiter) = quota_limiter::get_quota_limiter() {
+                let bytes = write_bytes + statistics.total_read_bytes();
+                limiter.consume(source, thread_cpu_time() - cpu_begin, bytes);
+            }
+            if logger::is_slow(wait_time + process_time) {
+                info!(
+                    target: SLOW_LOG_TARGET,
+                    "[region {}] [slow-query] scheduler handle command: {}, ts: {}, \
+                     takes {:?} (wait: {:?}, process: {:?}) [keys: {}, processed: {}]",
+                    region_id,
+                    tag,
+                    ts,
+                    wait_time + process_time,
+                    wait_time,
+                    process_time,
+                    statistics.total_op_count(),
+                    statistics.total_processed()
+                );
+            }
         });
     }
 
@@ -244,11 +294,14 @@ impl<E: Engine> Executor<E> {
         debug!("process read cmd(cid={}) in worker pool", task.cid);
         let tag = task.tag;
         let cid = task.cid;
+        let trace = task.trace;
         let mut statistics = Statistics::default();
         let pr = match process_read_impl(sched_ctx, task.cmd, snapshot, &mut statistics) {
             Err(e) => ProcessResult::Failed { err: e.into() },
             Ok(pr) => pr,
         };
+        trace.stage("process");
+        trace.finish();
         notify_scheduler(self.take_scheduler(), Msg::ReadFinished { cid, pr, tag });
         statistics
     }
@@ -264,9 +317,12 @@ impl<E: Engine> Executor<E> {
         fail_point!("txn_before_process_write");
         let tag = task.tag;
         let cid = task.cid;
+        let trace = task.trace;
         let mut statistics = Statistics::default();
         let scheduler = self.take_scheduler();
-        let msg = match process_write_impl(task.cmd, snapshot, &mut statistics) {
+        let res = process_write_impl(task.cmd, snapshot, &mut statistics);
+        trace.stage("process");
+        let msg = match res {
             // Initiates an async write operation on the storage engine, there'll be a `WriteFinished`
             // message when it finishes.
             Ok((ctx, pr, to_be_write, rows)) => {
@@ -274,6 +330,7 @@ impl<E: Engine> Executor<E> {
                     .with_label_values(&[tag, "write"])
                     .inc();
                 if to_be_write.is_empty() {
+                    trace.finish();
                     Msg::WriteFinished {
                         cid,
                         pr,
@@ -284,6 +341,9 @@ impl<E: Engine> Executor<E> {
                     let sched = scheduler.clone();
                     // The callback to receive async results of write prepare from the storage engine.
                     let engine_cb = Box::new(move |(_, result)| {
+                        // From proposing to applying the write.
+                        trace.stage("raft_write");
+                        trace.finish();
                         if notify_scheduler(
                             sched,
                             Msg::WriteFinished {
@@ -320,6 +380,7 @@ impl<E: Engine> Executor<E> {
                     .inc();
 
                 debug!("write command(cid={}) failed at prewrite.", cid);
+                trace.finish();
                 Msg::FinishedWithErr { cid, err, tag }
             }
         };
@@ -462,6 +523,53 @@ fn process_read_impl<E: Engine>(
                 })
             }
         }
+        // Scans the keys to rewind.
+        Command::Flashback {
+            ref ctx,
+            version,
+            start_ts,
+            commit_ts,
+            ref mut scan_key,
+            ref end_key,
+            ..
+        } => {
+            let mut reader = MvccReader::new(
+                snapshot,
+                Some(ScanMode::Forward),
+                !ctx.get_not_fill_cache(),
+                None,
+                None,
+                ctx.get_isolation_level(),
+            );
+            let result = scan_flashback_keys(
+                &mut reader,
+                scan_key.take(),
+                end_key.as_ref(),
+                version,
+                FLASHBACK_BATCH_SIZE,
+            );
+            statistics.add(reader.get_statistics());
+            let (key_values, next_scan_key) = result?;
+            sched_ctx
+                .command_keyread_duration
+                .with_label_values(&[tag])
+                .observe(key_values.len() as f64);
+            if key_values.is_empty() && next_scan_key.is_none() {
+                Ok(ProcessResult::Res)
+            } else {
+                Ok(ProcessResult::NextCommand {
+                    cmd: Command::Flashback {
+                        ctx: ctx.clone(),
+                        version,
+                        start_ts,
+                        commit_ts,
+                        scan_key: next_scan_key,
+                        end_key: end_key.clone(),
+                        key_values,
+                    },
+                })
+            }
+        }
         Command::Pause { duration, .. } => {
             thread::sleep(Duration::from_millis(duration));
             Ok(ProcessResult::Res)
@@ -479,6 +587,7 @@ fn process_write_impl<S: Snapshot>(
         Command::Prewrite {
             ctx,
             mutations,
+            assertions,
             primary,
             start_ts,
             options,
@@ -487,8 +596,10 @@ fn process_write_impl<S: Snapshot>(
             let mut txn = MvccTxn::new(snapshot, start_ts, !ctx.get_not_fill_cache())?;
             let mut locks = vec![];
             let rows = mutations.len();
+            let mut assertions = assertions.into_iter();
             for m in mutations {
-                match txn.prewrite(m, &primary, &options) {
+                let assertion = assertions.next().unwrap_or_default();
+                match txn.conditional_prewrite(m, &assertion, &primary, &options) {
                     Ok(_) => {}
                     e @ Err(MvccError::KeyIsLocked { .. }) => {
                         locks.push(e.map_err(Error::from).map_err(StorageError::from));
@@ -607,12 +718,98 @@ fn process_write_impl<S: Snapshot>(
             };
             (pr, modifies, rows, ctx)
         }
+        Command::Flashback {
+            ctx,
+            version,
+            start_ts,
+            commit_ts,
+            mut scan_key,
+            end_key,
+            key_values,
+        } => {
+            let mut txn = MvccTxn::new(snapshot, start_ts, !ctx.get_not_fill_cache())?;
+            let mut rows = 0;
+            let mut key_values = key_values.into_iter();
+            while let Some((key, value)) = key_values.next() {
+                txn.flashback(key, value, commit_ts)?;
+                rows += 1;
+                if txn.write_size() >= MAX_TXN_WRITE_SIZE {
+                    // The rest keys will be scanned again.
+                    if let Some((key, _)) = key_values.next() {
+                        scan_key = Some(key);
+                    }
+                    break;
+                }
+            }
+
+            statistics.add(&txn.take_statistics());
+            let pr = if scan_key.is_none() {
+                ProcessResult::Res
+            } else {
+                ProcessResult::NextCommand {
+                    cmd: Command::Flashback {
+                        ctx: ctx.clone(),
+                        version,
+                        start_ts,
+                        commit_ts,
+                        scan_key,
+                        end_key,
+                        key_values: vec![],
+                    },
+                }
+            };
+            (pr, txn.into_modifies(), rows, ctx)
+        }
         _ => panic!("unsupported write command"),
     };
 
     Ok((ctx, pr, modifies, rows))
 }
 
+/// Scans at most `limit` keys from `start` which are changed after `version`, and returns them
+/// with their values at `version`. The key to continue scanning from is also returned, which is
+/// `None` if all the keys before `end` are scanned.
+fn scan_flashback_keys<S: Snapshot>(
+    reader: &mut MvccReader<S>,
+    start: Option<Key>,
+    end: Option<&Key>,
+    version: u64,
+    limit: usize,
+) -> Result<(Vec<(Key, Option<Value>)>, Option<Key>)> {
+    let (keys, next_key) = reader.scan_keys(start.clone(), limit)?;
+    // The locks are scanned separately, since the keys locked by their first prewrites have
+    // no writes yet.
+    let (locks, _) = reader.scan_locks(start.as_ref(), |_| true, 1)?;
+    if let Some((key, lock)) = locks.into_iter().next() {
+        // The keys of the batch are before `upper`, `None` means unbounded.
+        let in_batch = match (next_key.as_ref(), end) {
+            (Some(next_key), Some(end)) => &key < cmp::min(next_key, end),
+            (next_key, end) => next_key.or(end).map_or(true, |upper| &key < upper),
+        };
+        if in_batch {
+            return Err(Error::from(MvccError::KeyIsLocked {
+                key: key.to_raw()?,
+                primary: lock.primary,
+                ts: lock.ts,
+                ttl: lock.ttl,
+            }));
+        }
+    }
+    let mut key_values = Vec::with_capacity(keys.len());
+    for key in keys {
+        if end.map_or(false, |end| &key >= end) {
+            return Ok((key_values, None));
+        }
+        // Read the newer version first, since the reader scans forward.
+        let latest = reader.get(&key, u64::MAX)?;
+        let value = reader.get(&key, version)?;
+        if latest != value {
+            key_values.push((key, value));
+        }
+    }
+    Ok((key_values, next_key))
+}
+
 fn notify_scheduler(scheduler: worker::Scheduler<Msg>, msg: Msg) -> bool {
     match scheduler.schedule(msg) {
         Ok(_) => true,
@@ -31,18 +31,25 @@
 //! is ensured by the transaction protocol implemented in the client library, which is transparent
 //! to the scheduler.
 
+use std::cmp;
 use std::fmt::{self, Debug, Display, Formatter};
+use std::mem;
+use std::time::Duration;
 use std::u64;
 
 use kvproto::kvrpcpb::CommandPri;
 use prometheus::HistogramTimer;
 
+use server::quota_limiter;
 use storage::engine::Result as EngineResult;
 use storage::Key;
 use storage::{Command, Engine, Error as StorageError, StorageCb};
 use util::collections::HashMap;
+use util::memory::{self, MemoryConsumer};
 use util::threadpool::{ThreadPool, ThreadPoolBuilder};
-use util::worker::{self, Runnable};
+use util::time::Instant;
+use util::timer::Timer;
+use util::worker::{self, Runnable, RunnableWithTimer};
 
 use super::super::metrics::*;
 use super::latch::{Latches, Lock};
@@ -52,6 +59,13 @@ use super::process::{
 use super::Error;
 
 pub const CMD_BATCH_SIZE: usize = 256;
+// low priority writes are throttled at a fraction of the pending write threshold
+const LOW_PRIORITY_THRESHOLD_DIVISOR: usize = 2;
+// the backoff suggested to the throttled writes when the pending writes reach the threshold
+const BUSY_BACKOFF_BASE_MS: u64 = 100;
+const BUSY_BACKOFF_MAX_MS: u64 = 3000;
+// the interval to check whether the commands delayed by the quota limiter can be scheduled
+pub const QUOTA_DELAY_CHECK_INTERVAL_MS: u64 = 10;
 
 /// Message types for the scheduler event loop.
 pub enum Msg {
@@ -102,6 +116,8 @@ impl Display for Msg {
 struct TaskContext {
     lock: Lock,
     cb: StorageCb,
+    // The region of the command, whose flashback waits for it. `None` for flashbacks.
+    region_id: Option<u64>,
     write_bytes: usize,
     tag: &'static str,
     // How long it waits on latches.
@@ -121,6 +137,7 @@ impl TaskContext {
         TaskContext {
             lock,
             cb,
+            region_id: flashback_barrier(cmd),
             write_bytes,
             tag: cmd.tag(),
             latch_timer: Some(
@@ -162,14 +179,32 @@ pub struct Scheduler<E: Engine> {
     // speed of recent write requests.
     sched_pending_write_threshold: usize,
 
+    sched_pending_write_tasks_threshold: usize,
+
+    // the commands still queued after this long are dropped
+    max_handle_duration: Duration,
+
     // worker pool
     worker_pool: ThreadPool<SchedContext<E>>,
 
     // high priority commands will be delivered to this pool
     high_priority_pool: ThreadPool<SchedContext<E>>,
 
+    // low priority commands will be delivered to this pool, so that bulk loads
+    // can't occupy the worker pool
+    low_priority_pool: ThreadPool<SchedContext<E>>,
+
     // used to control write flow
     running_write_bytes: usize,
+    running_write_tasks: usize,
+
+    // commands delayed by the quota limiter, they haven't acquired any latches yet
+    delayed_cmds: Timer<(Command, StorageCb)>,
+
+    // region id -> the number of commands of the region, excluding flashbacks
+    region_cmds: HashMap<u64, usize>,
+    // flashbacks waiting for the commands of their regions to finish
+    pending_flashbacks: Vec<(Command, StorageCb)>,
 }
 
 impl<E: Engine> Scheduler<E> {
@@ -180,6 +215,8 @@ impl<E: Engine> Scheduler<E> {
         concurrency: usize,
         worker_pool_size: usize,
         sched_pending_write_threshold: usize,
+        sched_pending_write_tasks_threshold: usize,
+        max_handle_duration: Duration,
     ) -> Self {
         let factory = SchedContextFactory::new(engine.clone());
         Scheduler {
@@ -191,12 +228,22 @@ impl<E: Engine> Scheduler<E> {
             id_alloc: 0,
             latches: Latches::new(concurrency),
             sched_pending_write_threshold,
+            sched_pending_write_tasks_threshold,
+            max_handle_duration,
             worker_pool: ThreadPoolBuilder::new(thd_name!("sched-worker-pool"), factory.clone())
                 .thread_count(worker_pool_size)
                 .build(),
-            high_priority_pool: ThreadPoolBuilder::new(thd_name!("sched-high-pri-pool"), factory)
+            high_priority_pool: ThreadPoolBuilder::new(
+                thd_name!("sched-high-pri-pool"),
+                factory.clone(),
+            ).build(),
+            low_priority_pool: ThreadPoolBuilder::new(thd_name!("sched-low-pri-pool"), factory)
                 .build(),
             running_write_bytes: 0,
+            running_write_tasks: 0,
+            delayed_cmds: Timer::new(0),
+            region_cmds: Default::default(),
+            pending_flashbacks: vec![],
         }
     }
 
@@ -223,6 +270,15 @@ impl<E: Engine> Scheduler<E> {
 
         self.running_write_bytes += tctx.write_bytes;
         SCHED_WRITING_BYTES_GAUGE.set(self.running_write_bytes as i64);
+        memory::record(MemoryConsumer::Scheduler, self.running_write_bytes);
+        if tctx.lock.is_write_lock() {
+            self.running_write_tasks += 1;
+            SCHED_WRITING_TASKS_GAUGE.set(self.running_write_tasks as i64);
+        }
+
+        if let Some(region_id) = tctx.region_id {
+            self.inc_region_cmds(region_id);
+        }
 
         if self.pending_tasks.insert(cid, task).is_some() {
             panic!("command cid={} shouldn't exist", cid);
@@ -235,9 +291,17 @@ impl<E: Engine> Scheduler<E> {
 
     fn dequeue_task_context(&mut self, cid: u64) -> TaskContext {
         let tctx = self.task_contexts.remove(&cid).unwrap();
+        if let Some(region_id) = tctx.region_id {
+            self.dec_region_cmds(region_id);
+        }
 
         self.running_write_bytes -= tctx.write_bytes;
         SCHED_WRITING_BYTES_GAUGE.set(self.running_write_bytes as i64);
+        memory::record(MemoryConsumer::Scheduler, self.running_write_bytes);
+        if tctx.lock.is_write_lock() {
+            self.running_write_tasks -= 1;
+            SCHED_WRITING_TASKS_GAUGE.set(self.running_write_tasks as i64);
+        }
         SCHED_CONTEX_GAUGE.set(self.pending_tasks.len() as i64);
 
         tctx
@@ -245,7 +309,8 @@ impl<E: Engine> Scheduler<E> {
 
     pub fn fetch_executor(&self, priority: CommandPri) -> Executor<E> {
         let pool = match priority {
-            CommandPri::Low | CommandPri::Normal => &self.worker_pool,
+            CommandPri::Low => &self.low_priority_pool,
+            CommandPri::Normal => &self.worker_pool,
             CommandPri::High => &self.high_priority_pool,
         };
         let pool_scheduler = pool.scheduler();
@@ -269,10 +334,13 @@ impl<E: Engine> Scheduler<E> {
 
         let tag = cmd.tag();
         let priority_tag = cmd.priority_tag();
-        let task = Task::new(cid, cmd);
+        let task = Task::new(cid, cmd, self.max_handle_duration);
         // TODO: enqueue_task should return an reference of the tctx.
         self.enqueue_task(task, callback);
-        self.try_to_wake_up(cid);
+        if !self.try_to_wake_up(cid) {
+            SCHED_LATCH_CONTENDED_COUNTER_VEC.with_label_values(&[tag]).inc();
+            SCHED_LATCH_WAITING_GAUGE.inc();
+        }
 
         SCHED_STAGE_COUNTER_VEC
             .with_label_values(&[tag, "new"])
@@ -283,8 +351,8 @@ impl<E: Engine> Scheduler<E> {
     }
 
     /// Tries to acquire all the necessary latches. If all the necessary latches are acquired,
-    /// the method initiates a get snapshot operation for furthur processing.
-    fn try_to_wake_up(&mut self, cid: u64) {
+    /// the method initiates a get snapshot operation for furthur processing and returns true.
+    fn try_to_wake_up(&mut self, cid: u64) -> bool {
         let wake = if let Some(tctx) = self.acquire_lock(cid) {
             tctx.on_schedule();
             true
@@ -294,34 +362,118 @@ impl<E: Engine> Scheduler<E> {
         if wake {
             self.get_snapshot(cid);
         }
+        wake
     }
 
-    fn too_busy(&self) -> bool {
-        fail_point!("txn_scheduler_busy", |_| true);
-        self.running_write_bytes >= self.sched_pending_write_threshold
+    /// Writes are throttled once the pending write bytes or the number of pending write
+    /// commands reach the thresholds. Low priority commands are throttled at
+    /// `1 / LOW_PRIORITY_THRESHOLD_DIVISOR` of the thresholds, so they leave room for the
+    /// normal ones. All writes are throttled once the memory usage of the instance reaches its
+    /// high water mark.
+    ///
+    /// Returns the backoff in milliseconds suggested to the client if the write is throttled.
+    fn too_busy(&self, priority: CommandPri) -> Option<u64> {
+        fail_point!("txn_scheduler_busy", |_| Some(BUSY_BACKOFF_BASE_MS));
+        if memory::exceeds_high_water_mark() {
+            return Some(BUSY_BACKOFF_MAX_MS);
+        }
+        let bytes_threshold = priority_threshold(self.sched_pending_write_threshold, priority);
+        let tasks_threshold =
+            priority_threshold(self.sched_pending_write_tasks_threshold, priority);
+        if self.running_write_bytes < bytes_threshold && self.running_write_tasks < tasks_threshold
+        {
+            return None;
+        }
+        Some(cmp::max(
+            busy_backoff_ms(self.running_write_bytes, bytes_threshold),
+            busy_backoff_ms(self.running_write_tasks, tasks_threshold),
+        ))
     }
 
     fn on_receive_new_cmd(&mut self, cmd: Command, callback: StorageCb) {
         // write flow control
-        if cmd.need_flow_control() && self.too_busy() {
-            SCHED_TOO_BUSY_COUNTER_VEC
-                .with_label_values(&[cmd.tag()])
-                .inc();
-            execute_callback(
-                callback,
-                ProcessResult::Failed {
-                    err: StorageError::SchedTooBusy,
-                },
-            );
-            return;
+        if cmd.need_flow_control() {
+            if let Some(backoff_ms) = self.too_busy(cmd.priority()) {
+                SCHED_TOO_BUSY_COUNTER_VEC
+                    .with_label_values(&[cmd.tag()])
+                    .inc();
+                execute_callback(
+                    callback,
+                    ProcessResult::Failed {
+                        err: StorageError::SchedTooBusyWithBackoff(backoff_ms),
+                    },
+                );
+                return;
+            }
+        }
+        // Commands of an over-quota source are delayed before acquiring the latches, so that
+        // they don't block the conflicting commands or occupy the workers while waiting.
+        if let Some(limiter) = quota_limiter::get_quota_limiter() {
+            let delay = limiter.delay(quota_limiter::source_of_priority(cmd.priority()));
+            if delay > Duration::from_secs(0) {
+                if let Some(region_id) = flashback_barrier(&cmd) {
+                    self.inc_region_cmds(region_id);
+                }
+                self.delayed_cmds.add_task(delay, (cmd, callback));
+                return;
+            }
+        }
+        // A flashback waits for the commands of its region received before it, which may still
+        // write to the region, to finish before scanning. The newer ones are rejected by
+        // `Storage`.
+        let flashback_region = match cmd {
+            Command::Flashback { ref ctx, .. } => Some(ctx.get_region_id()),
+            _ => None,
+        };
+        if let Some(region_id) = flashback_region {
+            if self.region_cmds.contains_key(&region_id) {
+                self.pending_flashbacks.push((cmd, callback));
+                return;
+            }
         }
         self.schedule_command(cmd, callback);
     }
 
+    fn inc_region_cmds(&mut self, region_id: u64) {
+        *self.region_cmds.entry(region_id).or_insert(0) += 1;
+    }
+
+    fn dec_region_cmds(&mut self, region_id: u64) {
+        let remove = {
+            let count = self.region_cmds.get_mut(&region_id).unwrap();
+            *count -= 1;
+            *count == 0
+        };
+        if remove {
+            self.region_cmds.remove(&region_id);
+        }
+    }
+
+    /// Schedules the pending flashbacks whose regions have no commands left. It's called after
+    /// handling the messages, since a multi-stage command leaves no task of its region
+    /// between finishing a stage and scheduling the next one.
+    fn schedule_drained_flashbacks(&mut self) {
+        if self.pending_flashbacks.is_empty() {
+            return;
+        }
+        let pending = mem::replace(&mut self.pending_flashbacks, vec![]);
+        for (cmd, cb) in pending {
+            if self
+                .region_cmds
+                .contains_key(&cmd.get_context().get_region_id())
+            {
+                self.pending_flashbacks.push((cmd, cb));
+            } else {
+                self.schedule_command(cmd, cb);
+            }
+        }
+    }
+
     /// Initiates an async operation to get a snapshot from the storage engine, then posts a
     /// `SnapshotFinished` message back to the event loop when it finishes.
     fn get_snapshot(&mut self, cid: u64) {
         let task = self.dequeue_task(cid);
+        task.trace().stage("latch_wait");
         let tag = task.tag;
         let ctx = task.context().clone();
         let executor = self.fetch_executor(task.priority());
@@ -439,7 +591,10 @@ impl<E: Engine> Scheduler<E> {
     fn release_lock(&mut self, lock: &Lock, cid: u64) {
         let wakeup_list = self.latches.release(lock, cid);
         for wcid in wakeup_list {
-            self.try_to_wake_up(wcid);
+            // Only commands waiting for latches are woken up.
+            if self.try_to_wake_up(wcid) {
+                SCHED_LATCH_WAITING_GAUGE.dec();
+            }
         }
     }
 }
@@ -463,19 +618,58 @@ impl<E: Engine> Runnable<Msg> for Scheduler<E> {
                 Msg::FinishedWithErr { cid, err, .. } => self.finish_with_err(cid, err),
             }
         }
+        self.schedule_drained_flashbacks();
     }
 
     fn shutdown(&mut self) {
+        // The commands delayed by the quota and the flashbacks waiting for their regions will
+        // never be scheduled, so their clients are told instead of waiting for a timeout.
+        let mut cbs: Vec<StorageCb> = self.delayed_cmds.drain().into_iter().map(|t| t.1).collect();
+        cbs.extend(self.pending_flashbacks.drain(..).map(|t| t.1));
+        for cb in cbs {
+            execute_callback(
+                cb,
+                ProcessResult::Failed {
+                    err: StorageError::Closed,
+                },
+            );
+        }
+        self.region_cmds.clear();
         if let Err(e) = self.worker_pool.stop() {
             error!("scheduler run err when worker pool stop:{:?}", e);
         }
         if let Err(e) = self.high_priority_pool.stop() {
             error!("scheduler run err when high priority pool stop:{:?}", e);
         }
+        if let Err(e) = self.low_priority_pool.stop() {
+            error!("scheduler run err when low priority pool stop:{:?}", e);
+        }
         info!("scheduler stopped");
     }
 }
 
+impl<E: Engine> RunnableWithTimer<Msg, ()> for Scheduler<E> {
+    fn on_timeout(&mut self, timer: &mut Timer<()>, _: ()) {
+        let now = Instant::now();
+        while let Some((cmd, cb)) = self.delayed_cmds.pop_task_before(now) {
+            if let Some(region_id) = flashback_barrier(&cmd) {
+                self.dec_region_cmds(region_id);
+            }
+            self.schedule_command(cmd, cb);
+        }
+        self.schedule_drained_flashbacks();
+        timer.add_task(Duration::from_millis(QUOTA_DELAY_CHECK_INTERVAL_MS), ());
+    }
+}
+
+/// Returns the region of the command if the flashbacks of the region should wait for it.
+fn flashback_barrier(cmd: &Command) -> Option<u64> {
+    match *cmd {
+        Command::Flashback { .. } => None,
+        _ => Some(cmd.get_context().get_region_id()),
+    }
+}
+
 fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
     match *cmd {
         Command::Prewrite { ref mutations, .. } => {
@@ -486,6 +680,10 @@ fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
             let keys: Vec<&Key> = key_locks.iter().map(|x| &x.0).collect();
             latches.gen_lock(&keys)
         }
+        Command::Flashback { ref key_values, .. } => {
+            let keys: Vec<&Key> = key_values.iter().map(|x| &x.0).collect();
+            latches.gen_lock(&keys)
+        }
         Command::Commit { ref keys, .. } | Command::Rollback { ref keys, .. } => {
             latches.gen_lock(keys)
         }
@@ -494,13 +692,34 @@ fn gen_command_lock(latches: &Latches, cmd: &Command) -> Lock {
     }
 }
 
+/// Scales the write threshold for the priority. It's at least 1, otherwise every write of
+/// the priority would be rejected.
+fn priority_threshold(threshold: usize, priority: CommandPri) -> usize {
+    if priority == CommandPri::Low {
+        cmp::max(threshold / LOW_PRIORITY_THRESHOLD_DIVISOR, 1)
+    } else {
+        threshold
+    }
+}
+
+/// Suggests the backoff of a throttled write in proportion to how far the pending writes are
+/// over the threshold, e.g. the base backoff at the threshold and twice of it at twice of the
+/// threshold, so the clients back off longer as the queue grows.
+fn busy_backoff_ms(pending: usize, threshold: usize) -> u64 {
+    let threshold = cmp::max(threshold, 1) as u64;
+    let backoff = BUSY_BACKOFF_BASE_MS.saturating_mul(pending as u64) / threshold;
+    cmp::min(cmp::max(backoff, BUSY_BACKOFF_BASE_MS), BUSY_BACKOFF_MAX_MS)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use kvproto::kvrpcpb::Context;
     use storage::mvcc;
+    use std::sync::mpsc::channel;
+    use storage::engine::{self, TEMP_DIR};
     use storage::txn::latch::*;
-    use storage::{Command, Key, Mutation, Options};
+    use storage::{Command, Key, Mutation, Options, ALL_CFS};
     use util::collections::HashMap;
 
     #[test]
@@ -533,6 +752,7 @@ mod tests {
             Command::Prewrite {
                 ctx: Context::new(),
                 mutations: vec![Mutation::Put((Key::from_raw(b"k"), b"v".to_vec()))],
+                assertions: vec![],
                 primary: b"k".to_vec(),
                 start_ts: 10,
                 options: Options::default(),
@@ -596,4 +816,55 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_priority_threshold() {
+        assert_eq!(priority_threshold(10, CommandPri::Normal), 10);
+        assert_eq!(priority_threshold(10, CommandPri::High), 10);
+        assert_eq!(priority_threshold(10, CommandPri::Low), 5);
+        // The threshold of low priority writes doesn't round down to 0.
+        assert_eq!(priority_threshold(1, CommandPri::Low), 1);
+        assert_eq!(priority_threshold(1, CommandPri::Normal), 1);
+    }
+
+    #[test]
+    fn test_shutdown_with_delayed_cmds() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let scheduler = worker::dummy_scheduler();
+        let max_handle_duration = Duration::from_secs(0);
+        let mut sched = Scheduler::new(engine, scheduler, 16, 1, 1024, 1024, max_handle_duration);
+        let (tx, rx) = channel();
+        for start_ts in 1..3 {
+            let cmd = Command::Rollback {
+                ctx: Context::new(),
+                keys: vec![Key::from_raw(b"k")],
+                start_ts,
+            };
+            let tx = tx.clone();
+            let cb = StorageCb::Boolean(Box::new(move |res| tx.send(res).unwrap()));
+            sched.inc_region_cmds(0);
+            sched.delayed_cmds.add_task(Duration::from_secs(100), (cmd, cb));
+        }
+
+        sched.shutdown();
+        for _ in 1..3 {
+            match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+                Err(StorageError::Closed) => {}
+                res => panic!("expect closed, got {:?}", res),
+            }
+        }
+        assert!(sched.region_cmds.is_empty());
+    }
+
+    #[test]
+    fn test_busy_backoff_ms() {
+        // Not over the threshold yet, e.g. only the other threshold is reached.
+        assert_eq!(busy_backoff_ms(0, 10), BUSY_BACKOFF_BASE_MS);
+        assert_eq!(busy_backoff_ms(10, 10), BUSY_BACKOFF_BASE_MS);
+        assert_eq!(busy_backoff_ms(15, 10), BUSY_BACKOFF_BASE_MS * 3 / 2);
+        assert_eq!(busy_backoff_ms(20, 10), BUSY_BACKOFF_BASE_MS * 2);
+        assert_eq!(busy_backoff_ms(1000, 10), BUSY_BACKOFF_MAX_MS);
+        assert_eq!(busy_backoff_ms(usize::max_value(), 1), BUSY_BACKOFF_MAX_MS);
+        assert_eq!(busy_backoff_ms(3, 0), BUSY_BACKOFF_BASE_MS * 3);
+    }
 }
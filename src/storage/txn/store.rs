@@ -16,7 +16,8 @@ use kvproto::kvrpcpb::IsolationLevel;
 use storage::mvcc::{
     BackwardScanner, BackwardScannerBuilder, ForwardScanner, ForwardScannerBuilder,
 };
-use storage::mvcc::{Error as MvccError, MvccReader};
+use std::sync::Arc;
+use storage::mvcc::{Error as MvccError, MvccReader, ScanChecksum, TxnStatusCache};
 use storage::{Key, KvPair, ScanMode, Snapshot, Statistics, Value};
 
 pub struct SnapshotStore<S: Snapshot> {
@@ -24,6 +25,7 @@ pub struct SnapshotStore<S: Snapshot> {
     start_ts: u64,
     isolation_level: IsolationLevel,
     fill_cache: bool,
+    txn_status_cache: Option<Arc<TxnStatusCache>>,
 }
 
 impl<S: Snapshot> SnapshotStore<S> {
@@ -38,10 +40,16 @@ impl<S: Snapshot> SnapshotStore<S> {
             start_ts,
             isolation_level,
             fill_cache,
+            txn_status_cache: None,
         }
     }
 
-    pub fn get(&self, key: &Key, statistics: &mut Statistics) -> Result<Option<Value>> {
+    /// Lets point gets resolve locks by the statuses of recently resolved transactions.
+    pub fn set_txn_status_cache(&mut self, cache: Arc<TxnStatusCache>) {
+        self.txn_status_cache = Some(cache);
+    }
+
+    fn new_reader(&self) -> MvccReader<S> {
         let mut reader = MvccReader::new(
             self.snapshot.clone(),
             None,
@@ -50,6 +58,14 @@ impl<S: Snapshot> SnapshotStore<S> {
             None,
             self.isolation_level,
         );
+        if let Some(ref cache) = self.txn_status_cache {
+            reader.set_txn_status_cache(Arc::clone(cache));
+        }
+        reader
+    }
+
+    pub fn get(&self, key: &Key, statistics: &mut Statistics) -> Result<Option<Value>> {
+        let mut reader = self.new_reader();
         let v = reader.get(key, self.start_ts)?;
         statistics.add(reader.get_statistics());
         Ok(v)
@@ -61,14 +77,7 @@ impl<S: Snapshot> SnapshotStore<S> {
         statistics: &mut Statistics,
     ) -> Result<Vec<Result<Option<Value>>>> {
         // TODO: sort the keys and use ScanMode::Forward
-        let mut reader = MvccReader::new(
-            self.snapshot.clone(),
-            None,
-            self.fill_cache,
-            None,
-            None,
-            self.isolation_level,
-        );
+        let mut reader = self.new_reader();
         let mut results = Vec::with_capacity(keys.len());
         for k in keys {
             results.push(reader.get(k, self.start_ts).map_err(Error::from));
@@ -85,6 +94,29 @@ impl<S: Snapshot> SnapshotStore<S> {
         key_only: bool,
         lower_bound: Option<Key>,
         upper_bound: Option<Key>,
+    ) -> Result<StoreScanner<S>> {
+        self.build_scanner(mode, key_only, lower_bound, upper_bound, false)
+    }
+
+    /// Create a scanner which computes the checksum of the pairs scanned, see
+    /// `StoreScanner::checksum`.
+    pub fn scanner_with_checksum(
+        &self,
+        mode: ScanMode,
+        key_only: bool,
+        lower_bound: Option<Key>,
+        upper_bound: Option<Key>,
+    ) -> Result<StoreScanner<S>> {
+        self.build_scanner(mode, key_only, lower_bound, upper_bound, true)
+    }
+
+    fn build_scanner(
+        &self,
+        mode: ScanMode,
+        key_only: bool,
+        lower_bound: Option<Key>,
+        upper_bound: Option<Key>,
+        checksum: bool,
     ) -> Result<StoreScanner<S>> {
         let (forward_scanner, backward_scanner) = match mode {
             ScanMode::Forward => {
@@ -94,6 +126,7 @@ impl<S: Snapshot> SnapshotStore<S> {
                         .omit_value(key_only)
                         .fill_cache(self.fill_cache)
                         .isolation_level(self.isolation_level)
+                        .checksum(checksum)
                         .build()?;
                 (Some(forward_scanner), None)
             }
@@ -104,6 +137,7 @@ impl<S: Snapshot> SnapshotStore<S> {
                         .omit_value(key_only)
                         .fill_cache(self.fill_cache)
                         .isolation_level(self.isolation_level)
+                        .checksum(checksum)
                         .build()?;
                 (None, Some(backward_scanner))
             }
@@ -148,6 +182,15 @@ impl<S: Snapshot> StoreScanner<S> {
         Ok(results)
     }
 
+    /// Returns the checksum of the pairs scanned so far if the scanner is created by
+    /// `SnapshotStore::scanner_with_checksum`.
+    pub fn checksum(&self) -> Option<ScanChecksum> {
+        match self.forward_scanner {
+            Some(ref scanner) => scanner.checksum(),
+            None => self.backward_scanner.as_ref().unwrap().checksum(),
+        }
+    }
+
     pub fn take_statistics(&mut self) -> Statistics {
         if self.forward_scanner.is_some() {
             return self.forward_scanner.as_mut().unwrap().take_statistics();
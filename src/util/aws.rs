@@ -0,0 +1,305 @@
+// Copyright 2017 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The pieces shared by the clients of AWS services, which sign the requests by the AWS
+//! Signature Version 4 and send them over plain HTTP to a local proxy.
+
+use std::env;
+use std::io;
+use std::str;
+
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use hex;
+use url::{Host, Url};
+
+/// Parses the endpoint of an AWS service, which must be a plain HTTP address on the loopback
+/// interface. The requests and the responses are not encrypted, so they must not cross the
+/// network without TLS, which is left to a local proxy.
+pub fn parse_endpoint(endpoint: &str) -> Result<Url, String> {
+    let url = Url::parse(endpoint).map_err(|e| format!("invalid endpoint {}: {:?}", endpoint, e))?;
+    if url.scheme() != "http" {
+        return Err(format!(
+            "endpoint {} should be plain HTTP, use a local proxy to access it over TLS",
+            endpoint
+        ));
+    }
+    let loopback = match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+    if !loopback {
+        return Err(format!(
+            "endpoint {} should be a loopback address, use a local proxy to access it over TLS",
+            endpoint
+        ));
+    }
+    Ok(url)
+}
+
+/// Returns the value of the `host` header of the requests sent to `endpoint`.
+pub fn host_header(endpoint: &Url) -> Option<String> {
+    match (endpoint.host_str(), endpoint.port()) {
+        (Some(host), Some(port)) => Some(format!("{}:{}", host, port)),
+        (Some(host), None) => Some(host.to_owned()),
+        (None, _) => None,
+    }
+}
+
+fn invalid_response(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid response: {}", msg))
+}
+
+/// Parses the status line and the headers of an HTTP response, and returns the status code
+/// and the headers, whose names are lowercase.
+pub fn parse_response_header(header: &[u8]) -> io::Result<(u16, Vec<(String, String)>)> {
+    let header = str::from_utf8(header).map_err(|_| invalid_response("non-utf8 header"))?;
+    let mut lines = header.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split(' ').nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_response("bad status line"))?;
+    let headers = lines
+        .filter_map(|l| {
+            let mut parts = l.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => {
+                    Some((name.trim().to_lowercase(), value.trim().to_owned()))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    Ok((status, headers))
+}
+
+/// Parses a whole HTTP response, and returns the status code and the body.
+pub fn parse_response(response: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid_response("incomplete header"))?;
+    let (status, headers) = parse_response_header(&response[..header_end])?;
+    let chunked = headers
+        .iter()
+        .any(|&(ref name, ref value)| name == "transfer-encoding" && value.contains("chunked"));
+    let body = &response[header_end + 4..];
+    if !chunked {
+        return Ok((status, body.to_vec()));
+    }
+
+    let mut data = vec![];
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| invalid_response("incomplete chunk"))?;
+        let size = str::from_utf8(&rest[..line_end])
+            .ok()
+            .and_then(|l| usize::from_str_radix(l.split(';').next().unwrap().trim(), 16).ok())
+            .ok_or_else(|| invalid_response("bad chunk size"))?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok((status, data));
+        }
+        if rest.len() < size + 2 {
+            return Err(invalid_response("incomplete chunk"));
+        }
+        data.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Uses `access_key` and `secret_key` if they are set, or the ones in the environment
+    /// variables.
+    pub fn new(access_key: &str, secret_key: &str) -> Result<Credentials, String> {
+        if !access_key.is_empty() {
+            return Ok(Credentials {
+                access_key: access_key.to_owned(),
+                secret_key: secret_key.to_owned(),
+                session_token: None,
+            });
+        }
+        match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key), Ok(secret_key)) => Ok(Credentials {
+                access_key,
+                secret_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            _ => Err("no AWS credentials found".to_owned()),
+        }
+    }
+
+    /// Returns the `authorization` header of a request signed by the AWS Signature Version 4.
+    /// `headers` should be lowercase and contain all the headers to send. `path` should be URI
+    /// encoded, and the request should have no query string.
+    pub fn sign(
+        &self,
+        region: &str,
+        service: &str,
+        amz_date: &str,
+        method: &str,
+        path: &str,
+        headers: &[(String, String)],
+        payload: &[u8],
+    ) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort();
+        let mut canonical_headers = String::new();
+        for &(ref name, ref value) in &headers {
+            canonical_headers.push_str(&format!("{}:{}\n", name, value.trim()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|&(ref name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method,
+            path,
+            canonical_headers,
+            signed_headers,
+            sha256_hex(payload)
+        );
+
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+        let key = signing_key(&self.secret_key, date, region, service);
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_key() {
+        // The example in the AWS documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        // The get-vanilla case of the AWS Signature Version 4 test suite.
+        let credentials = Credentials {
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        };
+        let headers = vec![
+            ("x-amz-date".to_owned(), "20150830T123600Z".to_owned()),
+            ("host".to_owned(), "example.amazonaws.com".to_owned()),
+        ];
+        let authorization = credentials.sign(
+            "us-east-1",
+            "service",
+            "20150830T123600Z",
+            "GET",
+            "/",
+            &headers,
+            b"",
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let resp = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(parse_response(resp).unwrap(), (200, b"{}".to_vec()));
+        let resp = b"HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n\
+                     3\r\nabc\r\n2;ext\r\nde\r\n0\r\n\r\n";
+        assert_eq!(parse_response(resp).unwrap(), (400, b"abcde".to_vec()));
+        parse_response(b"HTTP/1.1 200 OK\r\n").unwrap_err();
+        parse_response(b"HTTP/1.1 OK\r\n\r\n").unwrap_err();
+        let resp = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab";
+        parse_response(resp).unwrap_err();
+
+        let (status, headers) = parse_response_header(b"HTTP/1.1 404 Not Found\r\nA-B: c").unwrap();
+        assert_eq!(status, 404);
+        assert_eq!(headers, vec![("a-b".to_owned(), "c".to_owned())]);
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        parse_endpoint("https://kms.us-west-2.amazonaws.com").unwrap_err();
+        parse_endpoint("not a url").unwrap_err();
+        // Only the loopback addresses are allowed.
+        parse_endpoint("http://kms-proxy").unwrap_err();
+        parse_endpoint("http://10.0.0.1:8080").unwrap_err();
+        let host = |e: &str| host_header(&parse_endpoint(e).unwrap()).unwrap();
+        assert_eq!(host("http://localhost"), "localhost");
+        assert_eq!(host("http://127.0.0.1:8080"), "127.0.0.1:8080");
+        assert_eq!(host("http://[::1]:8080"), "[::1]:8080");
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A sampling CPU profiler driven by SIGPROF.
+//!
+//! Every sample records the thread and the instruction it's running. Only the innermost function
+//! is known, because the stacks can't be walked safely in a signal handler without frame
+//! pointers, so a profile tells which functions of which threads burn the CPU, like `perf top`.
+
+use std::io;
+use std::time::Duration;
+
+/// Samples the running threads `frequency` times per second of CPU time for `duration`, and
+/// returns the number of samples of each function of each thread in the folded stack format,
+/// i.e. "thread;function count" per line, which `flamegraph.pl` takes.
+///
+/// The profiles are taken one at a time.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn profile(duration: Duration, frequency: u32) -> io::Result<String> {
+    imp::profile(duration, frequency)
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+pub fn profile(_: Duration, _: u32) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "CPU profiling is only supported on x86_64 Linux",
+    ))
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod imp {
+    use std::fmt::Write;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::{cmp, mem, ptr, thread};
+
+    use backtrace;
+    use libc::{self, c_int, c_void};
+
+    use util::collections::HashMap;
+
+    // The samples beyond it are dropped.
+    const MAX_SAMPLES: usize = 1 << 18;
+    const ITIMER_PROF: c_int = 2;
+    // The index of RIP in the general registers of `mcontext_t`.
+    const REG_RIP: usize = 16;
+
+    #[allow(non_camel_case_types)]
+    #[repr(C)]
+    struct itimerval {
+        it_interval: libc::timeval,
+        it_value: libc::timeval,
+    }
+
+    extern "C" {
+        fn setitimer(which: c_int, new_value: *const itimerval, old_value: *mut itimerval) -> c_int;
+    }
+
+    lazy_static! {
+        // The thread ID and the instruction pointer of every sample. It's allocated before the
+        // signal handler is installed, since the handler can't allocate.
+        static ref SAMPLES: Vec<AtomicUsize> =
+            (0..MAX_SAMPLES * 2).map(|_| AtomicUsize::new(0)).collect();
+        // Whether the signal handler is installed, which also serializes the profiles.
+        static ref HANDLER_INSTALLED: Mutex<bool> = Mutex::new(false);
+    }
+
+    static SAMPLING: AtomicBool = ATOMIC_BOOL_INIT;
+    // The number of samples taken, which may exceed `MAX_SAMPLES`.
+    static SAMPLE_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    extern "C" fn on_sigprof(_: c_int, _: *mut libc::siginfo_t, ctx: *mut c_void) {
+        if !SAMPLING.load(Ordering::Acquire) {
+            return;
+        }
+        let i = SAMPLE_COUNT.fetch_add(1, Ordering::Relaxed);
+        if i >= MAX_SAMPLES {
+            return;
+        }
+        let (tid, ip) = unsafe {
+            let ctx = &*(ctx as *const libc::ucontext_t);
+            (libc::syscall(libc::SYS_gettid), ctx.uc_mcontext.gregs[REG_RIP])
+        };
+        SAMPLES[2 * i].store(tid as usize, Ordering::Relaxed);
+        SAMPLES[2 * i + 1].store(ip as usize, Ordering::Relaxed);
+    }
+
+    // The handler is never uninstalled, because a SIGPROF still pending after the profiling
+    // would kill the process with the default action.
+    fn install_handler() -> io::Result<()> {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_sigprof as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGPROF, &action, ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // Fires SIGPROF `frequency` times per second of the CPU time of the process, 0 stops it.
+    fn set_timer(frequency: u32) -> io::Result<()> {
+        let usec = if frequency == 0 {
+            0
+        } else {
+            cmp::max(1_000_000 / frequency, 1)
+        };
+        let tv = libc::timeval {
+            tv_sec: 0,
+            tv_usec: usec as libc::suseconds_t,
+        };
+        let timer = itimerval {
+            it_interval: tv,
+            it_value: tv,
+        };
+        if unsafe { setitimer(ITIMER_PROF, &timer, ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn profile(duration: Duration, frequency: u32) -> io::Result<String> {
+        let mut installed = HANDLER_INSTALLED.lock().unwrap();
+        for s in SAMPLES.iter() {
+            s.store(0, Ordering::Relaxed);
+        }
+        SAMPLE_COUNT.store(0, Ordering::Relaxed);
+        if !*installed {
+            install_handler()?;
+            *installed = true;
+        }
+
+        SAMPLING.store(true, Ordering::Release);
+        let res = set_timer(frequency).map(|_| thread::sleep(duration));
+        let stopped = set_timer(0);
+        SAMPLING.store(false, Ordering::Release);
+        res?;
+        stopped?;
+
+        let count = cmp::min(SAMPLE_COUNT.load(Ordering::Relaxed), MAX_SAMPLES);
+        let mut samples = HashMap::default();
+        for i in 0..count {
+            let tid = SAMPLES[2 * i].load(Ordering::Relaxed);
+            let ip = SAMPLES[2 * i + 1].load(Ordering::Relaxed);
+            // The handler of a thread may be interrupted before it records the sample.
+            if tid != 0 && ip != 0 {
+                *samples.entry((tid, ip)).or_insert(0) += 1;
+            }
+        }
+        Ok(fold(samples))
+    }
+
+    // Names the threads and the functions of the samples, and sums the samples of the same
+    // function of the same thread name up.
+    fn fold(samples: HashMap<(usize, usize), u64>) -> String {
+        let mut threads = HashMap::default();
+        let mut functions = HashMap::default();
+        let mut stacks = HashMap::default();
+        for ((tid, ip), n) in samples {
+            let thread = threads
+                .entry(tid)
+                .or_insert_with(|| thread_name(tid))
+                .clone();
+            let function = functions.entry(ip).or_insert_with(|| function_name(ip));
+            *stacks.entry(format!("{};{}", thread, function)).or_insert(0) += n;
+        }
+        let mut stacks: Vec<(String, u64)> = stacks.into_iter().collect();
+        stacks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let mut folded = String::new();
+        for (stack, n) in stacks {
+            writeln!(folded, "{} {}", stack, n).unwrap();
+        }
+        folded
+    }
+
+    fn thread_name(tid: usize) -> String {
+        let mut name = String::new();
+        let path = format!("/proc/self/task/{}/comm", tid);
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut name)) {
+            Ok(_) if !name.trim().is_empty() => name.trim().replace(';', ":"),
+            // The thread has exited.
+            _ => format!("thread-{}", tid),
+        }
+    }
+
+    fn function_name(ip: usize) -> String {
+        let mut name = None;
+        backtrace::resolve(ip as *mut c_void, |symbol| {
+            if name.is_none() {
+                name = symbol.name().map(|n| n.to_string());
+            }
+        });
+        match name {
+            Some(name) => name.replace(';', ":"),
+            None => format!("{:#x}", ip),
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop2 = Arc::clone(&stop);
+        let h = thread::Builder::new()
+            .name("cpu-burner".to_owned())
+            .spawn(move || {
+                let mut n = 0u64;
+                while !stop2.load(Ordering::Relaxed) {
+                    n = n.wrapping_mul(31).wrapping_add(1);
+                }
+                n
+            })
+            .unwrap();
+        let folded = profile(Duration::from_millis(500), 99).unwrap();
+        stop.store(true, Ordering::Relaxed);
+        h.join().unwrap();
+
+        assert!(
+            folded.lines().any(|l| l.starts_with("cpu-burner;")),
+            "{}",
+            folded
+        );
+        for line in folded.lines() {
+            let mut parts = line.rsplitn(2, ' ');
+            parts.next().unwrap().parse::<u64>().unwrap();
+            assert_eq!(parts.next().unwrap().split(';').count(), 2, "{}", line);
+        }
+    }
+}
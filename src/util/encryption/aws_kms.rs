@@ -0,0 +1,380 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use chrono::Utc;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use url::Url;
+
+use util::aws::{self, parse_response, Credentials};
+
+use super::kms::{DataKeyPair, KmsProvider};
+use super::{Error, KmsConfig, Result};
+
+const SERVICE: &str = "kms";
+const TARGET_PREFIX: &str = "TrentService.";
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Sends an HTTP POST request, and returns the status code and the body of the response.
+pub trait HttpClient: Send + Sync {
+    fn post(
+        &self,
+        url: &Url,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> io::Result<(u16, Vec<u8>)>;
+}
+
+/// A minimal HTTP/1.1 client, which sends a request per connection.
+struct PlainHttpClient {
+    timeout: Duration,
+}
+
+impl HttpClient for PlainHttpClient {
+    fn post(
+        &self,
+        url: &Url,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> io::Result<(u16, Vec<u8>)> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no host in url"))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let mut stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut request = format!("POST {} HTTP/1.1\r\n", url.path());
+        for &(ref name, ref value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str(&format!(
+            "content-length: {}\r\nconnection: close\r\n\r\n",
+            body.len()
+        ));
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct GenerateDataKeyRequest<'a> {
+    key_id: &'a str,
+    key_spec: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GenerateDataKeyResponse {
+    ciphertext_blob: String,
+    plaintext: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptRequest {
+    ciphertext_blob: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DecryptResponse {
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(rename = "__type")]
+    error_type: String,
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>> {
+    s.from_base64()
+        .map_err(|e| box_err!("invalid base64 from AWS KMS: {:?}", e))
+}
+
+/// Parses the endpoint of the KMS, which must be a plain HTTP address on the loopback
+/// interface. The data keys are returned in plaintext in the responses, so they must not
+/// cross the network without TLS, which is left to a local proxy.
+pub fn parse_endpoint(endpoint: &str) -> Result<Url> {
+    aws::parse_endpoint(endpoint).map_err(|e| box_err!("invalid KMS endpoint: {}", e))
+}
+
+/// The AWS KMS, which is accessed by its JSON API.
+pub struct AwsKms {
+    key_id: String,
+    region: String,
+    endpoint: Url,
+    host: String,
+    credentials: Credentials,
+    client: Box<HttpClient>,
+}
+
+impl AwsKms {
+    pub fn new(cfg: &KmsConfig) -> Result<AwsKms> {
+        let client = PlainHttpClient {
+            timeout: Duration::from_secs(REQUEST_TIMEOUT_SECS),
+        };
+        AwsKms::with_client(cfg, Box::new(client))
+    }
+
+    fn with_client(cfg: &KmsConfig, client: Box<HttpClient>) -> Result<AwsKms> {
+        let endpoint = parse_endpoint(&cfg.endpoint)?;
+        let host = match aws::host_header(&endpoint) {
+            Some(host) => host,
+            None => return Err(box_err!("no host in KMS endpoint {}", cfg.endpoint)),
+        };
+        let credentials = match Credentials::new(&cfg.access_key, &cfg.secret_access_key) {
+            Ok(credentials) => credentials,
+            Err(e) => return Err(box_err!("{}", e)),
+        };
+        Ok(AwsKms {
+            key_id: cfg.key_id.clone(),
+            region: cfg.region.clone(),
+            endpoint,
+            host,
+            credentials,
+            client,
+        })
+    }
+
+    fn request<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        action: &str,
+        req: &Req,
+    ) -> Result<Resp> {
+        let payload = serde_json::to_vec(req)?;
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut headers = vec![
+            ("content-type".to_owned(), CONTENT_TYPE.to_owned()),
+            ("host".to_owned(), self.host.clone()),
+            ("x-amz-date".to_owned(), amz_date.clone()),
+            ("x-amz-target".to_owned(), format!("{}{}", TARGET_PREFIX, action)),
+        ];
+        if let Some(ref token) = self.credentials.session_token {
+            headers.push(("x-amz-security-token".to_owned(), token.clone()));
+        }
+        let authorization = self.credentials.sign(
+            &self.region,
+            SERVICE,
+            &amz_date,
+            "POST",
+            self.endpoint.path(),
+            &headers,
+            &payload,
+        );
+        headers.push(("authorization".to_owned(), authorization));
+
+        let (status, body) = self
+            .client
+            .post(&self.endpoint, &headers, &payload)
+            .map_err(|e| Error::KmsUnavailable(format!("{} failed: {:?}", action, e)))?;
+        if status == 200 {
+            return Ok(serde_json::from_slice(&body)?);
+        }
+        let error_type = serde_json::from_slice::<ErrorResponse>(&body)
+            .map(|e| e.error_type)
+            .unwrap_or_default();
+        let msg = format!(
+            "{} failed with status {}: {}",
+            action,
+            status,
+            String::from_utf8_lossy(&body)
+        );
+        // Server errors and throttling are temporary.
+        if status >= 500 || error_type.ends_with("ThrottlingException") {
+            return Err(Error::KmsUnavailable(msg));
+        }
+        Err(box_err!("{}", msg))
+    }
+}
+
+impl KmsProvider for AwsKms {
+    fn name(&self) -> &str {
+        "aws"
+    }
+
+    fn generate_data_key(&self) -> Result<DataKeyPair> {
+        let req = GenerateDataKeyRequest {
+            key_id: &self.key_id,
+            key_spec: "AES_256",
+        };
+        let resp: GenerateDataKeyResponse = self.request("GenerateDataKey", &req)?;
+        Ok(DataKeyPair {
+            plaintext: decode_base64(&resp.plaintext)?,
+            ciphertext: decode_base64(&resp.ciphertext_blob)?,
+        })
+    }
+
+    fn decrypt_data_key(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let req = DecryptRequest {
+            ciphertext_blob: ciphertext.to_base64(STANDARD),
+        };
+        let resp: DecryptResponse = self.request("Decrypt", &req)?;
+        decode_base64(&resp.plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // Responds by the action, and records the requests.
+    #[derive(Clone, Default)]
+    struct MockHttpClient {
+        responses: Arc<Mutex<HashMap<String, (u16, String)>>>,
+        requests: Arc<Mutex<Vec<(Vec<(String, String)>, Vec<u8>)>>>,
+    }
+
+    impl HttpClient for MockHttpClient {
+        fn post(
+            &self,
+            _: &Url,
+            headers: &[(String, String)],
+            body: &[u8],
+        ) -> io::Result<(u16, Vec<u8>)> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((headers.to_vec(), body.to_vec()));
+            let target = &headers.iter().find(|h| h.0 == "x-amz-target").unwrap().1;
+            match self.responses.lock().unwrap().get(target) {
+                Some(&(status, ref body)) => Ok((status, body.clone().into_bytes())),
+                None => Err(io::Error::new(io::ErrorKind::ConnectionRefused, "mock")),
+            }
+        }
+    }
+
+    fn new_kms(client: MockHttpClient) -> AwsKms {
+        let cfg = KmsConfig {
+            vendor: "aws".to_owned(),
+            key_id: "alias/tikv".to_owned(),
+            region: "us-west-2".to_owned(),
+            endpoint: "http://127.0.0.1:8080".to_owned(),
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "secret".to_owned(),
+        };
+        AwsKms::with_client(&cfg, Box::new(client)).unwrap()
+    }
+
+    #[test]
+    fn test_aws_kms() {
+        let client = MockHttpClient::default();
+        let kms = new_kms(client.clone());
+        let plaintext = vec![1; 32];
+        let ciphertext = vec![2; 64];
+        client.responses.lock().unwrap().insert(
+            "TrentService.GenerateDataKey".to_owned(),
+            (
+                200,
+                format!(
+                    r#"{{"CiphertextBlob":"{}","Plaintext":"{}","KeyId":"k"}}"#,
+                    ciphertext.to_base64(STANDARD),
+                    plaintext.to_base64(STANDARD)
+                ),
+            ),
+        );
+        let pair = kms.generate_data_key().unwrap();
+        assert_eq!(pair.plaintext, plaintext);
+        assert_eq!(pair.ciphertext, ciphertext);
+        {
+            let requests = client.requests.lock().unwrap();
+            let (ref headers, ref body) = requests[0];
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(body["KeyId"], "alias/tikv");
+            assert_eq!(body["KeySpec"], "AES_256");
+            let get = |name: &str| &headers.iter().find(|h| h.0 == name).unwrap().1;
+            assert_eq!(get("host"), "127.0.0.1:8080");
+            assert!(get("authorization").contains("/us-west-2/kms/aws4_request"));
+        }
+
+        // Temporary failures.
+        match kms.decrypt_data_key(&ciphertext) {
+            Err(Error::KmsUnavailable(_)) => {}
+            res => panic!("expect KMS unavailable, got {:?}", res),
+        }
+        client.responses.lock().unwrap().insert(
+            "TrentService.Decrypt".to_owned(),
+            (
+                400,
+                r#"{"__type":"ThrottlingException","message":"rate exceeded"}"#.to_owned(),
+            ),
+        );
+        match kms.decrypt_data_key(&ciphertext) {
+            Err(Error::KmsUnavailable(_)) => {}
+            res => panic!("expect KMS unavailable, got {:?}", res),
+        }
+        // Permanent failures.
+        client.responses.lock().unwrap().insert(
+            "TrentService.Decrypt".to_owned(),
+            (400, r#"{"__type":"InvalidCiphertextException"}"#.to_owned()),
+        );
+        match kms.decrypt_data_key(&ciphertext) {
+            Err(Error::Other(_)) => {}
+            res => panic!("expect other error, got {:?}", res),
+        }
+
+        client.responses.lock().unwrap().insert(
+            "TrentService.Decrypt".to_owned(),
+            (
+                200,
+                format!(r#"{{"Plaintext":"{}"}}"#, plaintext.to_base64(STANDARD)),
+            ),
+        );
+        assert_eq!(kms.decrypt_data_key(&ciphertext).unwrap(), plaintext);
+        let requests = client.requests.lock().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().1).unwrap();
+        assert_eq!(body["CiphertextBlob"], ciphertext.to_base64(STANDARD));
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let mut cfg = KmsConfig {
+            vendor: "aws".to_owned(),
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "secret".to_owned(),
+            endpoint: "https://kms.us-west-2.amazonaws.com".to_owned(),
+            ..Default::default()
+        };
+        AwsKms::new(&cfg).unwrap_err();
+        cfg.endpoint = "not a url".to_owned();
+        AwsKms::new(&cfg).unwrap_err();
+        // Only the loopback addresses are allowed.
+        cfg.endpoint = "http://kms-proxy".to_owned();
+        AwsKms::new(&cfg).unwrap_err();
+        cfg.endpoint = "http://10.0.0.1:8080".to_owned();
+        AwsKms::new(&cfg).unwrap_err();
+        cfg.endpoint = "http://localhost".to_owned();
+        assert_eq!(AwsKms::new(&cfg).unwrap().host, "localhost");
+        cfg.endpoint = "http://127.0.0.1:8080".to_owned();
+        assert_eq!(AwsKms::new(&cfg).unwrap().host, "127.0.0.1:8080");
+        cfg.endpoint = "http://[::1]:8080".to_owned();
+        assert_eq!(AwsKms::new(&cfg).unwrap().host, "[::1]:8080");
+    }
+}
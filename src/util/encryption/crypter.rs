@@ -0,0 +1,230 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Read, Write};
+
+use crypto::aessafe::AesSafe256Encryptor;
+use crypto::symmetriccipher::BlockEncryptor;
+use rand::{OsRng, Rng};
+
+use super::Result;
+
+pub const KEY_LEN: usize = 32;
+pub const IV_LEN: usize = 16;
+const BLOCK_SIZE: usize = 16;
+
+pub(super) fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut rng = OsRng::new()?;
+    let mut buf = vec![0; len];
+    rng.fill_bytes(&mut buf);
+    Ok(buf)
+}
+
+/// Generates a random AES-256 key.
+pub fn generate_key() -> Result<Vec<u8>> {
+    random_bytes(KEY_LEN)
+}
+
+/// Generates a random initial counter block. A key must never be used with the same IV twice.
+pub fn generate_iv() -> Result<Vec<u8>> {
+    random_bytes(IV_LEN)
+}
+
+/// `AesCtrCrypter` encrypts and decrypts a stream by AES-256 in the CTR mode, which is the same
+/// operation. The counter of a block is the IV plus the index of the block, so any position of
+/// the stream can be crypted independently.
+pub struct AesCtrCrypter {
+    cipher: AesSafe256Encryptor,
+    iv: u128,
+    offset: u64,
+    // The key stream of the block at `block_index`.
+    block: [u8; BLOCK_SIZE],
+    block_index: Option<u64>,
+}
+
+impl AesCtrCrypter {
+    pub fn new(key: &[u8], iv: &[u8]) -> AesCtrCrypter {
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(iv.len(), IV_LEN);
+        AesCtrCrypter {
+            cipher: AesSafe256Encryptor::new(key),
+            iv: iv.iter().fold(0, |acc, &b| (acc << 8) | u128::from(b)),
+            offset: 0,
+            block: [0; BLOCK_SIZE],
+            block_index: None,
+        }
+    }
+
+    /// Sets the position in the stream of the next byte to crypt.
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Crypts `data` in place, and advances the offset by its length.
+    pub fn crypt(&mut self, data: &mut [u8]) {
+        for b in data.iter_mut() {
+            let index = self.offset / BLOCK_SIZE as u64;
+            if self.block_index != Some(index) {
+                self.fill_block(index);
+            }
+            *b ^= self.block[(self.offset % BLOCK_SIZE as u64) as usize];
+            self.offset += 1;
+        }
+    }
+
+    fn fill_block(&mut self, index: u64) {
+        let counter = self.iv.wrapping_add(u128::from(index));
+        let mut input = [0; BLOCK_SIZE];
+        for (i, b) in input.iter_mut().enumerate() {
+            *b = (counter >> (8 * (BLOCK_SIZE - 1 - i))) as u8;
+        }
+        self.cipher.encrypt_block(&input, &mut self.block);
+        self.block_index = Some(index);
+    }
+}
+
+/// Encrypts the data written to the inner writer. Without a crypter, the data is written as is.
+pub struct EncrypterWriter<W: Write> {
+    writer: W,
+    crypter: Option<AesCtrCrypter>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> EncrypterWriter<W> {
+    pub fn new(writer: W, crypter: Option<AesCtrCrypter>) -> EncrypterWriter<W> {
+        EncrypterWriter {
+            writer,
+            crypter,
+            buf: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for EncrypterWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let crypter = match self.crypter {
+            Some(ref mut c) => c,
+            None => return self.writer.write(data),
+        };
+        // The key stream is consumed, so all of the data must be written.
+        self.buf.clear();
+        self.buf.extend_from_slice(data);
+        crypter.crypt(&mut self.buf);
+        self.writer.write_all(&self.buf)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts the data read from the inner reader. Without a crypter, the data is read as is.
+pub struct DecrypterReader<R: Read> {
+    reader: R,
+    crypter: Option<AesCtrCrypter>,
+}
+
+impl<R: Read> DecrypterReader<R> {
+    pub fn new(reader: R, crypter: Option<AesCtrCrypter>) -> DecrypterReader<R> {
+        DecrypterReader { reader, crypter }
+    }
+}
+
+impl<R: Read> Read for DecrypterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if let Some(ref mut c) = self.crypter {
+            c.crypt(&mut buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex;
+
+    use super::*;
+
+    // The test vector of F.5.5 in NIST SP 800-38A.
+    const KEY: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+    const IV: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51";
+    const CIPHERTEXT: &str = "601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c5";
+
+    fn new_crypter() -> AesCtrCrypter {
+        AesCtrCrypter::new(&hex::decode(KEY).unwrap(), &hex::decode(IV).unwrap())
+    }
+
+    #[test]
+    fn test_aes_ctr_crypter() {
+        let plaintext = hex::decode(PLAINTEXT).unwrap();
+        let ciphertext = hex::decode(CIPHERTEXT).unwrap();
+
+        let mut data = plaintext.clone();
+        new_crypter().crypt(&mut data);
+        assert_eq!(data, ciphertext);
+
+        // Crypting byte by byte is the same as crypting at once.
+        let mut crypter = new_crypter();
+        for b in data.chunks_mut(1) {
+            crypter.crypt(b);
+        }
+        assert_eq!(data, plaintext);
+        assert_eq!(crypter.offset(), plaintext.len() as u64);
+
+        // Crypts from the middle of the stream.
+        let mut crypter = new_crypter();
+        crypter.set_offset(20);
+        let mut data = ciphertext[20..].to_vec();
+        crypter.crypt(&mut data);
+        assert_eq!(data, &plaintext[20..]);
+    }
+
+    #[test]
+    fn test_encrypter_and_decrypter() {
+        let plaintext = hex::decode(PLAINTEXT).unwrap();
+        let mut writer = EncrypterWriter::new(vec![], Some(new_crypter()));
+        writer.write_all(&plaintext[..7]).unwrap();
+        writer.write_all(&plaintext[7..]).unwrap();
+        let ciphertext = writer.into_inner();
+        assert_eq!(ciphertext, hex::decode(CIPHERTEXT).unwrap());
+
+        let mut reader = DecrypterReader::new(ciphertext.as_slice(), Some(new_crypter()));
+        let mut data = vec![];
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, plaintext);
+
+        // Without crypters, the data is passed through.
+        let mut writer = EncrypterWriter::new(vec![], None);
+        writer.write_all(&plaintext).unwrap();
+        let mut reader = DecrypterReader::new(writer.into_inner().as_slice(), None);
+        let mut data = vec![];
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, plaintext);
+
+        let (k1, k2) = (generate_key().unwrap(), generate_key().unwrap());
+        assert_eq!(k1.len(), KEY_LEN);
+        assert_ne!(k1, k2);
+        assert_eq!(generate_iv().unwrap().len(), IV_LEN);
+    }
+}
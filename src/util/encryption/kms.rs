@@ -0,0 +1,259 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use util::collections::HashMap;
+
+use super::aws_kms::AwsKms;
+use super::crypter::KEY_LEN;
+use super::master_key::{EncryptedContent, FileMasterKey, MasterKey};
+use super::{Error, KmsConfig, Result};
+
+const MAX_RETRY_TIMES: usize = 5;
+const INITIAL_BACKOFF_MILLIS: u64 = 200;
+
+/// A data key generated by a KMS, in plaintext and in the ciphertext which only the KMS can
+/// decrypt.
+#[derive(Clone)]
+pub struct DataKeyPair {
+    pub plaintext: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// A KMS keeps the customer master key, which never leaves it. Returns
+/// `Error::KmsUnavailable` if a request fails temporarily and can be retried.
+pub trait KmsProvider: Send + Sync {
+    fn name(&self) -> &str;
+    /// Generates an AES-256 data key.
+    fn generate_data_key(&self) -> Result<DataKeyPair>;
+    fn decrypt_data_key(&self, ciphertext: &[u8]) -> Result<Vec<u8>>;
+}
+
+pub fn new_kms_provider(cfg: &KmsConfig) -> Result<Box<KmsProvider>> {
+    match cfg.vendor.as_str() {
+        "aws" => Ok(Box::new(AwsKms::new(cfg)?)),
+        vendor => Err(box_err!("unsupported KMS vendor {:?}", vendor)),
+    }
+}
+
+/// Uses the data keys generated by a KMS as the master keys.
+///
+/// The content is encrypted by a data key of the KMS, and the ciphertext of the key is saved
+/// along with it. The decrypted data keys are cached, so TiKV keeps working during a KMS
+/// outage once it has started, and the KMS is only asked for a new data key at most once.
+pub struct KmsMasterKey {
+    provider: Box<KmsProvider>,
+    // The data key to encrypt new contents with, and its ciphertext.
+    current: Mutex<Option<(Vec<u8>, Arc<FileMasterKey>)>>,
+    // The decrypted data keys, keyed by their ciphertexts.
+    keys: Mutex<HashMap<Vec<u8>, Arc<FileMasterKey>>>,
+}
+
+impl KmsMasterKey {
+    pub fn new(provider: Box<KmsProvider>) -> KmsMasterKey {
+        KmsMasterKey {
+            provider,
+            current: Mutex::new(None),
+            keys: Mutex::new(HashMap::default()),
+        }
+    }
+
+    fn current_key(&self) -> Result<(Vec<u8>, Arc<FileMasterKey>)> {
+        let mut current = self.current.lock().unwrap();
+        if let Some(ref c) = *current {
+            return Ok(c.clone());
+        }
+        let pair = retry("generate data key", || self.provider.generate_data_key())?;
+        let key = Arc::new(new_master_key(pair.plaintext)?);
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(pair.ciphertext.clone(), Arc::clone(&key));
+        *current = Some((pair.ciphertext.clone(), Arc::clone(&key)));
+        Ok((pair.ciphertext, key))
+    }
+
+    fn decrypt_key(&self, ciphertext: &[u8]) -> Result<Arc<FileMasterKey>> {
+        if let Some(key) = self.keys.lock().unwrap().get(ciphertext) {
+            return Ok(Arc::clone(key));
+        }
+        let plaintext = retry("decrypt data key", || self.provider.decrypt_data_key(ciphertext))?;
+        let key = Arc::new(new_master_key(plaintext)?);
+        self.keys
+            .lock()
+            .unwrap()
+            .insert(ciphertext.to_vec(), Arc::clone(&key));
+        // Reuses the existing key, so encrypting doesn't depend on the KMS after a restart.
+        let mut current = self.current.lock().unwrap();
+        if current.is_none() {
+            *current = Some((ciphertext.to_vec(), Arc::clone(&key)));
+        }
+        Ok(key)
+    }
+}
+
+impl MasterKey for KmsMasterKey {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedContent> {
+        let (ciphertext_key, key) = self.current_key()?;
+        let mut content = key.encrypt(plaintext)?;
+        content.kms_vendor = self.provider.name().to_owned();
+        content.kms_ciphertext_key = ciphertext_key;
+        Ok(content)
+    }
+
+    fn decrypt(&self, content: &EncryptedContent) -> Result<Vec<u8>> {
+        if content.kms_vendor != self.provider.name() {
+            return Err(Error::WrongMasterKey(format!(
+                "content is encrypted by KMS {:?}",
+                content.kms_vendor
+            )));
+        }
+        self.decrypt_key(&content.kms_ciphertext_key)?.decrypt(content)
+    }
+}
+
+fn new_master_key(key: Vec<u8>) -> Result<FileMasterKey> {
+    if key.len() != KEY_LEN {
+        return Err(box_err!(
+            "data key from KMS should be {} bytes, got {}",
+            KEY_LEN,
+            key.len()
+        ));
+    }
+    Ok(FileMasterKey::from_key(key))
+}
+
+/// Retries `f` with exponential backoff while the KMS is unavailable.
+fn retry<T, F: FnMut() -> Result<T>>(op: &str, mut f: F) -> Result<T> {
+    let mut backoff = Duration::from_millis(INITIAL_BACKOFF_MILLIS);
+    let mut retry_times = 0;
+    loop {
+        match f() {
+            Err(Error::KmsUnavailable(msg)) => {
+                retry_times += 1;
+                if retry_times >= MAX_RETRY_TIMES {
+                    return Err(Error::KmsUnavailable(msg));
+                }
+                warn!("failed to {} by KMS: {}, retry after {:?}", op, msg, backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::super::crypter::generate_key;
+    use super::*;
+
+    // Wraps the data keys by XOR, and fails the first `failures` requests.
+    #[derive(Clone, Default)]
+    struct MockKms {
+        failures: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl MockKms {
+        fn request(&self) -> Result<()> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(Error::KmsUnavailable("mock".to_owned()));
+            }
+            Ok(())
+        }
+    }
+
+    fn wrap(key: &[u8]) -> Vec<u8> {
+        key.iter().map(|b| b ^ 0xff).collect()
+    }
+
+    impl KmsProvider for MockKms {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        fn generate_data_key(&self) -> Result<DataKeyPair> {
+            self.request()?;
+            let plaintext = generate_key()?;
+            let ciphertext = wrap(&plaintext);
+            Ok(DataKeyPair {
+                plaintext,
+                ciphertext,
+            })
+        }
+
+        fn decrypt_data_key(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+            self.request()?;
+            Ok(wrap(ciphertext))
+        }
+    }
+
+    #[test]
+    fn test_kms_master_key() {
+        let kms = MockKms::default();
+        let master_key = KmsMasterKey::new(Box::new(kms.clone()));
+        let content = master_key.encrypt(b"data key").unwrap();
+        assert_eq!(content.kms_vendor, "mock");
+        assert_ne!(content.ciphertext, b"data key");
+        assert_eq!(master_key.decrypt(&content).unwrap(), b"data key");
+        // The data key is generated once and cached.
+        master_key.encrypt(b"another key").unwrap();
+        assert_eq!(kms.requests.load(Ordering::SeqCst), 1);
+
+        // A restarted TiKV decrypts the data key by the KMS, and reuses it.
+        let master_key = KmsMasterKey::new(Box::new(kms.clone()));
+        assert_eq!(master_key.decrypt(&content).unwrap(), b"data key");
+        assert_eq!(kms.requests.load(Ordering::SeqCst), 2);
+        let content2 = master_key.encrypt(b"another key").unwrap();
+        assert_eq!(content2.kms_ciphertext_key, content.kms_ciphertext_key);
+        assert_eq!(kms.requests.load(Ordering::SeqCst), 2);
+
+        // The cached keys still work when the KMS is down.
+        kms.failures.store(usize::max_value(), Ordering::SeqCst);
+        assert_eq!(master_key.decrypt(&content2).unwrap(), b"another key");
+
+        // Contents not wrapped by the KMS are rejected.
+        let mut content3 = content.clone();
+        content3.kms_vendor.clear();
+        match master_key.decrypt(&content3) {
+            Err(Error::WrongMasterKey(_)) => {}
+            res => panic!("expect wrong master key, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_kms_unavailable() {
+        let kms = MockKms::default();
+        kms.failures.store(2, Ordering::SeqCst);
+        let master_key = KmsMasterKey::new(Box::new(kms.clone()));
+        let content = master_key.encrypt(b"data key").unwrap();
+        assert_eq!(kms.requests.load(Ordering::SeqCst), 3);
+
+        kms.failures.store(MAX_RETRY_TIMES, Ordering::SeqCst);
+        let master_key = KmsMasterKey::new(Box::new(kms.clone()));
+        match master_key.decrypt(&content) {
+            Err(Error::KmsUnavailable(_)) => {}
+            res => panic!("expect KMS unavailable, got {:?}", res),
+        }
+        // It works once the KMS is back.
+        assert_eq!(master_key.decrypt(&content).unwrap(), b"data key");
+    }
+}
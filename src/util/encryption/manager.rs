@@ -0,0 +1,600 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use super::crypter::{generate_iv, generate_key, AesCtrCrypter};
+use super::kms::{new_kms_provider, KmsMasterKey};
+use super::master_key::{EncryptedContent, FileMasterKey, MasterKey};
+use super::metrics::*;
+use super::{EncryptionConfig, EncryptionMethod, Error, Result};
+use util::collections::HashMap;
+
+const KEY_DICT_NAME: &str = "key.dict";
+const FILE_DICT_NAME: &str = "file.dict";
+/// How often the age of the current data key is checked.
+pub const DATA_KEY_ROTATION_CHECK_INTERVAL_SECS: u64 = 600;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DataKey {
+    pub key: Vec<u8>,
+    pub method: EncryptionMethod,
+    /// Seconds since the unix epoch.
+    pub creation_time: u64,
+}
+
+// Persisted encrypted by the master key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct KeyDictionary {
+    current_key_id: u64,
+    keys: HashMap<u64, DataKey>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct FileInfo {
+    key_id: u64,
+    iv: Vec<u8>,
+    method: EncryptionMethod,
+}
+
+// Persisted in plaintext, as it contains no keys.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FileDictionary {
+    files: HashMap<String, FileInfo>,
+}
+
+/// How a file is encrypted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileEncryptionInfo {
+    pub method: EncryptionMethod,
+    pub key: Vec<u8>,
+    pub iv: Vec<u8>,
+}
+
+impl FileEncryptionInfo {
+    pub fn plaintext() -> FileEncryptionInfo {
+        FileEncryptionInfo {
+            method: EncryptionMethod::Plaintext,
+            key: vec![],
+            iv: vec![],
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.method != EncryptionMethod::Plaintext
+    }
+
+    /// Returns a crypter starting at the beginning of the file, or `None` if the file is not
+    /// encrypted.
+    pub fn new_crypter(&self) -> Option<AesCtrCrypter> {
+        match self.method {
+            EncryptionMethod::Plaintext => None,
+            EncryptionMethod::Aes256Ctr => Some(AesCtrCrypter::new(&self.key, &self.iv)),
+        }
+    }
+}
+
+struct Dicts {
+    key_dict: KeyDictionary,
+    file_dict: FileDictionary,
+}
+
+/// `DataKeyManager` generates the data keys, and tracks which data key each file is encrypted
+/// with. Both dictionaries are persisted in `dict_path` and replaced atomically on updates.
+///
+/// The files are identified by their paths, so the manager must be told when a file is renamed
+/// or deleted as well.
+///
+/// The data keys are rotated by `rotate_data_key`. Each file records the ID of the key it's
+/// encrypted with, so the files encrypted by the old keys are still readable.
+///
+/// Only the files tracked by the manager, i.e. the snapshot and import files, are encrypted.
+/// The files of the RocksDB engines are not, see the module doc.
+pub struct DataKeyManager {
+    master_key: Box<MasterKey>,
+    method: EncryptionMethod,
+    dict_path: PathBuf,
+    dicts: Mutex<Dicts>,
+}
+
+impl DataKeyManager {
+    /// Creates a manager if the encryption is enabled by `cfg`.
+    pub fn from_config<P: AsRef<Path>>(
+        cfg: &EncryptionConfig,
+        dict_path: P,
+    ) -> Result<Option<DataKeyManager>> {
+        if !cfg.enabled() {
+            return Ok(None);
+        }
+        let master_key: Box<MasterKey> = if cfg.kms.enabled() {
+            Box::new(KmsMasterKey::new(new_kms_provider(&cfg.kms)?))
+        } else {
+            Box::new(FileMasterKey::new(&cfg.master_key_path)?)
+        };
+        let manager = DataKeyManager::new(master_key, cfg.method, dict_path)?;
+        if cfg.data_key_rotation_period.as_secs() > 0 {
+            manager.rotate_data_key_if_expired(cfg.data_key_rotation_period.0)?;
+        }
+        Ok(Some(manager))
+    }
+
+    pub fn new<P: AsRef<Path>>(
+        master_key: Box<MasterKey>,
+        method: EncryptionMethod,
+        dict_path: P,
+    ) -> Result<DataKeyManager> {
+        let dict_path = dict_path.as_ref().to_path_buf();
+        fs::create_dir_all(&dict_path)?;
+        let key_dict = load_key_dict(master_key.as_ref(), &dict_path)?;
+        let file_dict = load_file_dict(&dict_path)?;
+        let manager = DataKeyManager {
+            master_key,
+            method,
+            dict_path,
+            dicts: Mutex::new(Dicts {
+                key_dict,
+                file_dict,
+            }),
+        };
+        manager.init_keys()?;
+        Ok(manager)
+    }
+
+    // Generates the current data key if it doesn't exist yet, or a new one if the method is
+    // changed.
+    fn init_keys(&self) -> Result<()> {
+        let mut dicts = self.dicts.lock().unwrap();
+        let current_method = dicts
+            .key_dict
+            .keys
+            .get(&dicts.key_dict.current_key_id)
+            .map(|k| k.method);
+        if current_method != Some(self.method) {
+            self.generate_data_key(&mut dicts.key_dict)?;
+            self.save_key_dict(&dicts.key_dict)?;
+        }
+        update_key_metrics(&dicts);
+        Ok(())
+    }
+
+    // Adds a new data key and makes it the current one. The caller should persist the key
+    // dictionary.
+    fn generate_data_key(&self, key_dict: &mut KeyDictionary) -> Result<u64> {
+        let key_id = key_dict.keys.keys().max().map_or(1, |id| id + 1);
+        let key = DataKey {
+            key: generate_key()?,
+            method: self.method,
+            creation_time: now_secs(),
+        };
+        info!("generate data key {} for {:?}", key_id, self.method);
+        key_dict.keys.insert(key_id, key);
+        key_dict.current_key_id = key_id;
+        Ok(key_id)
+    }
+
+    /// Generates a new data key to encrypt the new files with, and returns its ID. The
+    /// existing files are still encrypted by the old keys. Only the files tracked by the
+    /// manager are affected, the RocksDB files are not encrypted at all.
+    pub fn rotate_data_key(&self) -> Result<u64> {
+        let mut dicts = self.dicts.lock().unwrap();
+        // Keeps the dictionary untouched if it fails to be persisted.
+        let mut key_dict = dicts.key_dict.clone();
+        let key_id = self.generate_data_key(&mut key_dict)?;
+        self.save_key_dict(&key_dict)?;
+        dicts.key_dict = key_dict;
+        ENCRYPTION_DATA_KEY_ROTATION_COUNTER.inc();
+        update_key_metrics(&dicts);
+        Ok(key_id)
+    }
+
+    /// Rotates the data key if the current one is older than `period`. Returns whether it's
+    /// rotated.
+    pub fn rotate_data_key_if_expired(&self, period: Duration) -> Result<bool> {
+        let creation_time = {
+            let dicts = self.dicts.lock().unwrap();
+            let key_dict = &dicts.key_dict;
+            key_dict.keys[&key_dict.current_key_id].creation_time
+        };
+        if now_secs().saturating_sub(creation_time) < period.as_secs() {
+            return Ok(false);
+        }
+        self.rotate_data_key()?;
+        Ok(true)
+    }
+
+    pub fn current_key_id(&self) -> u64 {
+        self.dicts.lock().unwrap().key_dict.current_key_id
+    }
+
+    /// Returns the number of files encrypted by each data key.
+    pub fn file_count_by_key(&self) -> HashMap<u64, usize> {
+        file_count_by_key(&self.dicts.lock().unwrap())
+    }
+
+    /// Allocates the encryption info of a new file, which is encrypted by the current data key.
+    pub fn new_file(&self, fname: &str) -> Result<FileEncryptionInfo> {
+        let mut dicts = self.dicts.lock().unwrap();
+        let key_id = dicts.key_dict.current_key_id;
+        let info = FileInfo {
+            key_id,
+            iv: generate_iv()?,
+            method: self.method,
+        };
+        dicts.file_dict.files.insert(fname.to_owned(), info.clone());
+        self.save_file_dict(&dicts.file_dict)?;
+        update_key_metrics(&dicts);
+        to_encryption_info(&dicts.key_dict, &info)
+    }
+
+    /// Returns how a file is encrypted. The files unknown to the manager are regarded as
+    /// plaintext, e.g. the ones created before the encryption is enabled.
+    pub fn get_file(&self, fname: &str) -> Result<FileEncryptionInfo> {
+        let dicts = self.dicts.lock().unwrap();
+        match dicts.file_dict.files.get(fname) {
+            Some(info) => to_encryption_info(&dicts.key_dict, info),
+            None => Ok(FileEncryptionInfo::plaintext()),
+        }
+    }
+
+    pub fn delete_file(&self, fname: &str) -> Result<()> {
+        let mut dicts = self.dicts.lock().unwrap();
+        if dicts.file_dict.files.remove(fname).is_some() {
+            self.save_file_dict(&dicts.file_dict)?;
+            update_key_metrics(&dicts);
+        }
+        Ok(())
+    }
+
+    /// Copies the encryption info of `src` to `dst`. To rename a file, link its info to the
+    /// new name first, then rename the file and delete the info of the old name, so that the
+    /// file is always known to the manager even if it crashes in the middle.
+    pub fn link_file(&self, src: &str, dst: &str) -> Result<()> {
+        let mut dicts = self.dicts.lock().unwrap();
+        let info = match dicts.file_dict.files.get(src) {
+            Some(info) => info.clone(),
+            None => return Ok(()),
+        };
+        dicts.file_dict.files.insert(dst.to_owned(), info);
+        self.save_file_dict(&dicts.file_dict)?;
+        update_key_metrics(&dicts);
+        Ok(())
+    }
+
+    fn save_key_dict(&self, dict: &KeyDictionary) -> Result<()> {
+        let content = self.master_key.encrypt(&serde_json::to_vec(dict)?)?;
+        write_file_atomically(
+            &self.dict_path.join(KEY_DICT_NAME),
+            &serde_json::to_vec(&content)?,
+        )
+    }
+
+    fn save_file_dict(&self, dict: &FileDictionary) -> Result<()> {
+        write_file_atomically(
+            &self.dict_path.join(FILE_DICT_NAME),
+            &serde_json::to_vec(dict)?,
+        )
+    }
+}
+
+impl fmt::Debug for DataKeyManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DataKeyManager")
+            .field("method", &self.method)
+            .field("dict_path", &self.dict_path)
+            .finish()
+    }
+}
+
+/// Rotates the data key of a manager once it's older than the period, which is checked every
+/// interval in a background thread until it's dropped.
+pub struct RotatorHandle {
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RotatorHandle {
+    pub fn start(
+        manager: Arc<DataKeyManager>,
+        period: Duration,
+        interval: Duration,
+    ) -> RotatorHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name(thd_name!("data-key-rotator"))
+            .spawn(move || loop {
+                match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Err(e) = manager.rotate_data_key_if_expired(period) {
+                            error!("failed to rotate the data key: {:?}", e);
+                        }
+                    }
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+            })
+            .unwrap();
+        RotatorHandle {
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for RotatorHandle {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(h) = self.handle.take() {
+            if let Err(e) = h.join() {
+                error!("failed to join data key rotator: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Checks that the encryption isn't disabled once some files may have been encrypted, which
+/// can't be read without the data keys. It can be enabled at any time, as the files unknown to
+/// the manager are regarded as plaintext.
+pub fn check_encryption_mode<P: AsRef<Path>>(cfg: &EncryptionConfig, dict_path: P) -> Result<()> {
+    let dict_path = dict_path.as_ref();
+    let encrypted = dict_path.join(KEY_DICT_NAME).exists();
+    if !cfg.enabled() && encrypted {
+        return Err(Error::ModeMismatch(format!(
+            "the data is encrypted by the keys in {}, the encryption can't be disabled",
+            dict_path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn file_count_by_key(dicts: &Dicts) -> HashMap<u64, usize> {
+    let mut counts: HashMap<u64, usize> = dicts.key_dict.keys.keys().map(|&id| (id, 0)).collect();
+    for info in dicts.file_dict.files.values() {
+        if info.method != EncryptionMethod::Plaintext {
+            *counts.entry(info.key_id).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+fn update_key_metrics(dicts: &Dicts) {
+    for (key_id, count) in file_count_by_key(dicts) {
+        ENCRYPTION_DATA_KEY_FILES_GAUGE_VEC
+            .with_label_values(&[&key_id.to_string()])
+            .set(count as i64);
+    }
+    ENCRYPTION_CURRENT_DATA_KEY_GAUGE.set(dicts.key_dict.current_key_id as i64);
+}
+
+fn to_encryption_info(key_dict: &KeyDictionary, info: &FileInfo) -> Result<FileEncryptionInfo> {
+    if info.method == EncryptionMethod::Plaintext {
+        return Ok(FileEncryptionInfo::plaintext());
+    }
+    match key_dict.keys.get(&info.key_id) {
+        Some(key) => Ok(FileEncryptionInfo {
+            method: info.method,
+            key: key.key.clone(),
+            iv: info.iv.clone(),
+        }),
+        None => Err(box_err!("data key {} is not found", info.key_id)),
+    }
+}
+
+fn load_key_dict(master_key: &MasterKey, dict_path: &Path) -> Result<KeyDictionary> {
+    let content: EncryptedContent = match read_file(&dict_path.join(KEY_DICT_NAME))? {
+        Some(data) => serde_json::from_slice(&data)?,
+        None => return Ok(KeyDictionary::default()),
+    };
+    let data = master_key.decrypt(&content)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+fn load_file_dict(dict_path: &Path) -> Result<FileDictionary> {
+    match read_file(&dict_path.join(FILE_DICT_NAME))? {
+        Some(data) => Ok(serde_json::from_slice(&data)?),
+        None => Ok(FileDictionary::default()),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut data = vec![];
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+// Writes to a temporary file and renames it, so that a crash never leaves a partial file.
+fn write_file_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    // Syncs the directory to persist the rename.
+    File::open(path.parent().unwrap())?.sync_all()?;
+    Ok(())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::super::crypter::{IV_LEN, KEY_LEN};
+    use super::super::master_key::PlaintextMasterKey;
+    use super::super::Error;
+    use super::*;
+
+    fn new_manager(path: &Path, master_key: Vec<u8>) -> DataKeyManager {
+        let master_key = Box::new(FileMasterKey::from_key(master_key));
+        DataKeyManager::new(master_key, EncryptionMethod::Aes256Ctr, path).unwrap()
+    }
+
+    #[test]
+    fn test_data_key_manager() {
+        let dir = TempDir::new("test-data-key-manager").unwrap();
+        let master_key = generate_key().unwrap();
+        let manager = new_manager(dir.path(), master_key.clone());
+
+        let f1 = manager.new_file("f1").unwrap();
+        assert!(f1.is_encrypted());
+        assert_eq!(f1.key.len(), KEY_LEN);
+        assert_eq!(f1.iv.len(), IV_LEN);
+        let f2 = manager.new_file("f2").unwrap();
+        assert_eq!(f1.key, f2.key);
+        assert_ne!(f1.iv, f2.iv);
+        assert_eq!(manager.get_file("f1").unwrap(), f1);
+        assert_eq!(
+            manager.get_file("unknown").unwrap(),
+            FileEncryptionInfo::plaintext()
+        );
+        assert!(manager.get_file("unknown").unwrap().new_crypter().is_none());
+
+        manager.link_file("f1", "f3").unwrap();
+        assert_eq!(manager.get_file("f1").unwrap(), f1);
+        assert_eq!(manager.get_file("f3").unwrap(), f1);
+        manager.delete_file("f1").unwrap();
+        assert!(!manager.get_file("f1").unwrap().is_encrypted());
+        manager.delete_file("f2").unwrap();
+        assert!(!manager.get_file("f2").unwrap().is_encrypted());
+        drop(manager);
+
+        // The dictionaries are persisted.
+        let manager = new_manager(dir.path(), master_key.clone());
+        assert_eq!(manager.get_file("f3").unwrap(), f1);
+        assert!(!manager.get_file("f2").unwrap().is_encrypted());
+        assert_eq!(manager.current_key_id(), 1);
+        drop(manager);
+
+        // The keys can't be decrypted by a wrong master key.
+        let master_key = Box::new(FileMasterKey::from_key(generate_key().unwrap()));
+        match DataKeyManager::new(master_key, EncryptionMethod::Aes256Ctr, dir.path()) {
+            Err(Error::WrongMasterKey(_)) => {}
+            Err(e) => panic!("expect wrong master key, got {:?}", e),
+            Ok(_) => panic!("expect wrong master key"),
+        }
+    }
+
+    #[test]
+    fn test_change_method() {
+        let dir = TempDir::new("test-data-key-manager-method").unwrap();
+        let master_key = Box::new(PlaintextMasterKey);
+        let manager =
+            DataKeyManager::new(master_key, EncryptionMethod::Plaintext, dir.path()).unwrap();
+        let f1 = manager.new_file("f1").unwrap();
+        assert!(!f1.is_encrypted());
+        drop(manager);
+
+        // A new data key is generated for the new method, and the old files are still known.
+        let master_key = Box::new(PlaintextMasterKey);
+        let manager =
+            DataKeyManager::new(master_key, EncryptionMethod::Aes256Ctr, dir.path()).unwrap();
+        assert_eq!(manager.get_file("f1").unwrap(), f1);
+        assert!(manager.new_file("f2").unwrap().is_encrypted());
+        assert_eq!(manager.current_key_id(), 2);
+    }
+
+    #[test]
+    fn test_rotate_data_key() {
+        let dir = TempDir::new("test-data-key-manager-rotate").unwrap();
+        let master_key = generate_key().unwrap();
+        let manager = new_manager(dir.path(), master_key.clone());
+        let f1 = manager.new_file("f1").unwrap();
+        assert_eq!(manager.current_key_id(), 1);
+        assert!(!manager
+            .rotate_data_key_if_expired(Duration::from_secs(3600))
+            .unwrap());
+
+        // The new files are encrypted by the new key, and the old files are still readable.
+        assert_eq!(manager.rotate_data_key().unwrap(), 2);
+        let f2 = manager.new_file("f2").unwrap();
+        assert_ne!(f1.key, f2.key);
+        assert_eq!(manager.get_file("f1").unwrap(), f1);
+        let counts = manager.file_count_by_key();
+        assert_eq!(counts[&1], 1);
+        assert_eq!(counts[&2], 1);
+
+        assert!(manager
+            .rotate_data_key_if_expired(Duration::from_secs(0))
+            .unwrap());
+        assert_eq!(manager.current_key_id(), 3);
+        manager.delete_file("f1").unwrap();
+        let counts = manager.file_count_by_key();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&1], 0);
+        assert_eq!(counts[&3], 0);
+        drop(manager);
+
+        // The rotated keys are persisted.
+        let manager = new_manager(dir.path(), master_key);
+        assert_eq!(manager.current_key_id(), 3);
+        assert_eq!(manager.get_file("f2").unwrap(), f2);
+    }
+
+    #[test]
+    fn test_rotator() {
+        let dir = TempDir::new("test-data-key-manager-rotator").unwrap();
+        let manager = Arc::new(new_manager(dir.path(), generate_key().unwrap()));
+        let period = Duration::from_secs(3600);
+        let interval = Duration::from_millis(10);
+        {
+            let _h = RotatorHandle::start(Arc::clone(&manager), period, interval);
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(manager.current_key_id(), 1);
+
+        {
+            let _h = RotatorHandle::start(Arc::clone(&manager), Duration::from_secs(0), interval);
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(manager.current_key_id() > 1);
+    }
+
+    #[test]
+    fn test_check_encryption_mode() {
+        let dir = TempDir::new("test-check-encryption-mode").unwrap();
+        let dict_path = dir.path().join("encryption");
+        let mut cfg = EncryptionConfig::default();
+        cfg.method = EncryptionMethod::Aes256Ctr;
+        let plaintext = EncryptionConfig::default();
+
+        // The encryption can be enabled or not before any key is generated.
+        check_encryption_mode(&cfg, &dict_path).unwrap();
+        check_encryption_mode(&plaintext, &dict_path).unwrap();
+
+        // But it can't be disabled afterwards.
+        let _manager = new_manager(&dict_path, generate_key().unwrap());
+        check_encryption_mode(&cfg, &dict_path).unwrap();
+        match check_encryption_mode(&plaintext, &dict_path) {
+            Err(Error::ModeMismatch(_)) => {}
+            res => panic!("expect mode mismatch, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::io::Read;
+use std::ptr;
+
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use hex;
+
+use super::crypter::{generate_iv, AesCtrCrypter, KEY_LEN};
+use super::{EncryptionMethod, Error, Result};
+
+// The MAC key is derived from the master key, instead of reusing it for both purposes.
+const MAC_KEY_LABEL: &[u8] = b"tikv-encryption-mac";
+
+/// The content encrypted by a master key, with everything needed to decrypt it except the key.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncryptedContent {
+    pub method: EncryptionMethod,
+    pub iv: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    /// Authenticates the IV and the ciphertext, so that a wrong master key is detected instead
+    /// of producing garbage.
+    pub mac: Vec<u8>,
+    /// The vendor of the KMS which wraps the key of the content, empty if no KMS is used.
+    pub kms_vendor: String,
+    /// The key of the content encrypted by the KMS.
+    pub kms_ciphertext_key: Vec<u8>,
+}
+
+/// A master key encrypts the data keys, and is never stored by TiKV.
+pub trait MasterKey: Send + Sync {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedContent>;
+    fn decrypt(&self, content: &EncryptedContent) -> Result<Vec<u8>>;
+}
+
+/// Keeps the data keys in plaintext. Test purpose only.
+pub struct PlaintextMasterKey;
+
+impl MasterKey for PlaintextMasterKey {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedContent> {
+        Ok(EncryptedContent {
+            method: EncryptionMethod::Plaintext,
+            ciphertext: plaintext.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn decrypt(&self, content: &EncryptedContent) -> Result<Vec<u8>> {
+        if content.method != EncryptionMethod::Plaintext {
+            return Err(Error::WrongMasterKey(format!(
+                "content is encrypted by {:?}",
+                content.method
+            )));
+        }
+        Ok(content.ciphertext.clone())
+    }
+}
+
+/// An AES-256 master key, which is read from a file containing it in hex.
+pub struct FileMasterKey {
+    key: Vec<u8>,
+}
+
+impl FileMasterKey {
+    pub fn new(path: &str) -> Result<FileMasterKey> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+        let key = hex::decode(content.trim())
+            .map_err(|e| box_err!("failed to decode master key from {}: {:?}", path, e))?;
+        if key.len() != KEY_LEN {
+            return Err(box_err!(
+                "master key in {} should be {} bytes, got {}",
+                path,
+                KEY_LEN,
+                key.len()
+            ));
+        }
+        Ok(FileMasterKey::from_key(key))
+    }
+
+    pub fn from_key(key: Vec<u8>) -> FileMasterKey {
+        assert_eq!(key.len(), KEY_LEN);
+        FileMasterKey { key }
+    }
+
+    fn mac(&self, iv: &[u8], ciphertext: &[u8]) -> MacResult {
+        let mut derive = Hmac::new(Sha256::new(), &self.key);
+        derive.input(MAC_KEY_LABEL);
+        let mut hmac = Hmac::new(Sha256::new(), derive.result().code());
+        hmac.input(iv);
+        hmac.input(ciphertext);
+        hmac.result()
+    }
+}
+
+impl Drop for FileMasterKey {
+    fn drop(&mut self) {
+        unsafe {
+            for b in &mut self.key {
+                ptr::write_volatile(b, 0);
+            }
+        }
+    }
+}
+
+impl MasterKey for FileMasterKey {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedContent> {
+        let iv = generate_iv()?;
+        let mut ciphertext = plaintext.to_vec();
+        AesCtrCrypter::new(&self.key, &iv).crypt(&mut ciphertext);
+        let mac = self.mac(&iv, &ciphertext).code().to_vec();
+        Ok(EncryptedContent {
+            method: EncryptionMethod::Aes256Ctr,
+            iv,
+            ciphertext,
+            mac,
+            ..Default::default()
+        })
+    }
+
+    fn decrypt(&self, content: &EncryptedContent) -> Result<Vec<u8>> {
+        if content.method != EncryptionMethod::Aes256Ctr {
+            return Err(Error::WrongMasterKey(format!(
+                "content is encrypted by {:?}",
+                content.method
+            )));
+        }
+        if self.mac(&content.iv, &content.ciphertext) != MacResult::new(&content.mac) {
+            return Err(Error::WrongMasterKey("MAC mismatch".to_owned()));
+        }
+        let mut plaintext = content.ciphertext.clone();
+        AesCtrCrypter::new(&self.key, &content.iv).crypt(&mut plaintext);
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::super::crypter::generate_key;
+    use super::*;
+
+    #[test]
+    fn test_file_master_key() {
+        let dir = TempDir::new("test-file-master-key").unwrap();
+        let path = dir.path().join("master.key");
+        let key = generate_key().unwrap();
+        writeln!(File::create(&path).unwrap(), "{}", hex::encode(&key)).unwrap();
+        let master_key = FileMasterKey::new(path.to_str().unwrap()).unwrap();
+
+        let content = master_key.encrypt(b"data key").unwrap();
+        assert_ne!(content.ciphertext, b"data key");
+        assert_eq!(master_key.decrypt(&content).unwrap(), b"data key");
+        // Every encryption uses a new IV.
+        assert_ne!(master_key.encrypt(b"data key").unwrap().iv, content.iv);
+
+        // Wrong master keys are detected.
+        let wrong_key = FileMasterKey::from_key(generate_key().unwrap());
+        match wrong_key.decrypt(&content) {
+            Err(Error::WrongMasterKey(_)) => {}
+            res => panic!("expect wrong master key, got {:?}", res),
+        }
+        match PlaintextMasterKey.decrypt(&content) {
+            Err(Error::WrongMasterKey(_)) => {}
+            res => panic!("expect wrong master key, got {:?}", res),
+        }
+        let mut tampered = content.clone();
+        tampered.ciphertext[0] ^= 1;
+        master_key.decrypt(&tampered).unwrap_err();
+
+        // Invalid key files.
+        writeln!(File::create(&path).unwrap(), "not hex").unwrap();
+        FileMasterKey::new(path.to_str().unwrap()).unwrap_err();
+        writeln!(File::create(&path).unwrap(), "{}", hex::encode(&key[1..])).unwrap();
+        FileMasterKey::new(path.to_str().unwrap()).unwrap_err();
+        FileMasterKey::new(dir.path().join("missing").to_str().unwrap()).unwrap_err();
+    }
+
+    #[test]
+    fn test_plaintext_master_key() {
+        let content = PlaintextMasterKey.encrypt(b"data key").unwrap();
+        assert_eq!(PlaintextMasterKey.decrypt(&content).unwrap(), b"data key");
+    }
+}
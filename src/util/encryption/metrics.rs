@@ -0,0 +1,30 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use prometheus::*;
+
+lazy_static! {
+    pub static ref ENCRYPTION_DATA_KEY_FILES_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_encryption_data_key_files",
+        "Number of files encrypted by each data key, excluding the RocksDB files",
+        &["key_id"]
+    ).unwrap();
+    pub static ref ENCRYPTION_CURRENT_DATA_KEY_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_encryption_current_data_key",
+        "ID of the data key which encrypts the new files"
+    ).unwrap();
+    pub static ref ENCRYPTION_DATA_KEY_ROTATION_COUNTER: IntCounter = register_int_counter!(
+        "tikv_encryption_data_key_rotation_total",
+        "Total number of data key rotations"
+    ).unwrap();
+}
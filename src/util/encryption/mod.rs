@@ -0,0 +1,239 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encryption at rest.
+//!
+//! The files are encrypted by data keys, which are generated by TiKV and never leave it in
+//! plaintext: they are kept in a key dictionary encrypted by the master key, which is provided
+//! by the user. Another dictionary tracks which data key and IV each file is encrypted with.
+//! `DataKeyManager` maintains both of them.
+//!
+//! Only the files written by TiKV itself are encrypted, i.e. the snapshot files and the SST
+//! files waiting to be ingested. The files of the RocksDB engines, including the engines of
+//! tikv-importer, are NOT encrypted: the only encrypted env of the RocksDB binding is its CTR
+//! env for testing, whose block cipher is a XOR with the key rather than AES. They should be
+//! kept on an encrypted file system if they need to be protected, and the rotation of the data
+//! keys doesn't apply to them.
+//!
+//! The master key is either read from a file, or kept by a KMS (Key Management Service), which
+//! wraps the keys that actually encrypt the key dictionary.
+
+mod aws_kms;
+mod crypter;
+mod kms;
+mod manager;
+mod master_key;
+mod metrics;
+
+pub use self::aws_kms::AwsKms;
+
+pub use self::crypter::{
+    generate_iv, generate_key, AesCtrCrypter, DecrypterReader, EncrypterWriter, IV_LEN, KEY_LEN,
+};
+pub use self::kms::{new_kms_provider, DataKeyPair, KmsMasterKey, KmsProvider};
+pub use self::manager::{
+    check_encryption_mode, DataKey, DataKeyManager, FileEncryptionInfo, RotatorHandle,
+    DATA_KEY_ROTATION_CHECK_INTERVAL_SECS,
+};
+pub use self::master_key::{EncryptedContent, FileMasterKey, MasterKey, PlaintextMasterKey};
+
+use std::error;
+use std::io;
+use std::result;
+
+use serde_json;
+
+use util::config::ReadableDuration;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: io::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        Json(err: serde_json::Error) {
+            from()
+            cause(err)
+            description(err.description())
+        }
+        WrongMasterKey(msg: String) {
+            description("wrong master key")
+            display("wrong master key: {}", msg)
+        }
+        /// The encryption method doesn't match the existing data.
+        ModeMismatch(msg: String) {
+            description("encryption mode mismatch")
+            display("encryption mode mismatch: {}", msg)
+        }
+        /// The KMS is temporarily unavailable, and the request can be retried.
+        KmsUnavailable(msg: String) {
+            description("KMS is unavailable")
+            display("KMS is unavailable: {}", msg)
+        }
+        Other(err: Box<error::Error + Sync + Send>) {
+            from()
+            cause(err.as_ref())
+            description(err.description())
+            display("{:?}", err)
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionMethod {
+    Plaintext,
+    Aes256Ctr,
+}
+
+impl Default for EncryptionMethod {
+    fn default() -> EncryptionMethod {
+        EncryptionMethod::Plaintext
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncryptionConfig {
+    /// The method to encrypt the data files with. `plaintext` disables the encryption.
+    pub method: EncryptionMethod,
+    /// The file which contains the master key in hex.
+    pub master_key_path: String,
+    /// Uses a KMS as the master key instead of `master_key_path`.
+    pub kms: KmsConfig,
+    /// A new data key is generated for the new snapshot and import files once the current one
+    /// is older than it, which is checked on start and every 10 minutes. 0 disables the rotation
+    /// by age. The files of RocksDB are not encrypted, so they are not affected.
+    pub data_key_rotation_period: ReadableDuration,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> EncryptionConfig {
+        EncryptionConfig {
+            method: EncryptionMethod::Plaintext,
+            master_key_path: String::new(),
+            kms: KmsConfig::default(),
+            data_key_rotation_period: ReadableDuration::hours(7 * 24),
+        }
+    }
+}
+
+impl EncryptionConfig {
+    pub fn validate(&self) -> result::Result<(), Box<error::Error>> {
+        if !self.master_key_path.is_empty() && self.kms.enabled() {
+            return Err("encryption.master-key-path and encryption.kms can't be both set".into());
+        }
+        if self.enabled() && self.master_key_path.is_empty() && !self.kms.enabled() {
+            return Err(
+                "encryption.master-key-path or encryption.kms should be set to enable encryption"
+                    .into(),
+            );
+        }
+        self.kms.validate()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.method != EncryptionMethod::Plaintext
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct KmsConfig {
+    /// The vendor of the KMS, only `aws` is supported now. Empty disables the KMS.
+    pub vendor: String,
+    /// The ID or the ARN of the customer master key in the KMS.
+    pub key_id: String,
+    pub region: String,
+    /// The address of the KMS service, like `http://127.0.0.1:8080`. TiKV talks to it in plain
+    /// HTTP, so it must be a loopback address, usually a local proxy which forwards the
+    /// requests over TLS.
+    pub endpoint: String,
+    /// The credentials are read from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN` if they are not set.
+    pub access_key: String,
+    /// Never serialized, so it's neither logged nor served by the status server.
+    #[serde(skip_serializing)]
+    pub secret_access_key: String,
+}
+
+impl KmsConfig {
+    pub fn enabled(&self) -> bool {
+        !self.vendor.is_empty()
+    }
+
+    pub fn validate(&self) -> result::Result<(), Box<error::Error>> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        if self.vendor != "aws" {
+            return Err(format!("unsupported KMS vendor {:?}", self.vendor).into());
+        }
+        if self.key_id.is_empty() || self.region.is_empty() {
+            return Err("encryption.kms.key-id and encryption.kms.region should be set".into());
+        }
+        aws_kms::parse_endpoint(&self.endpoint)?;
+        if self.access_key.is_empty() != self.secret_access_key.is_empty() {
+            return Err(
+                "encryption.kms.access-key and encryption.kms.secret-access-key should be set \
+                 together"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_encryption_config() {
+        let mut cfg = EncryptionConfig::default();
+        cfg.validate().unwrap();
+        cfg.method = EncryptionMethod::Aes256Ctr;
+        cfg.validate().unwrap_err();
+        cfg.master_key_path = "/master/key".to_owned();
+        cfg.validate().unwrap();
+
+        cfg.kms.vendor = "aws".to_owned();
+        cfg.kms.key_id = "alias/tikv".to_owned();
+        cfg.kms.region = "us-west-2".to_owned();
+        cfg.kms.endpoint = "http://127.0.0.1:8080".to_owned();
+        cfg.validate().unwrap_err();
+        cfg.master_key_path.clear();
+        cfg.validate().unwrap();
+
+        cfg.kms.endpoint = "https://kms.us-west-2.amazonaws.com".to_owned();
+        cfg.validate().unwrap_err();
+        cfg.kms.endpoint = "http://kms.us-west-2.amazonaws.com".to_owned();
+        cfg.validate().unwrap_err();
+        cfg.kms.endpoint = "http://127.0.0.1:8080".to_owned();
+        cfg.kms.access_key = "AKIDEXAMPLE".to_owned();
+        cfg.validate().unwrap_err();
+        cfg.kms.secret_access_key = "secret".to_owned();
+        cfg.validate().unwrap();
+        cfg.kms.region.clear();
+        cfg.validate().unwrap_err();
+        cfg.kms.region = "us-west-2".to_owned();
+        cfg.kms.vendor = "gcp".to_owned();
+        cfg.validate().unwrap_err();
+    }
+}
@@ -75,11 +75,16 @@ pub fn copy_and_sync<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Resu
 const DIGEST_BUFFER_SIZE: usize = 1024 * 1024;
 
 pub fn calc_crc32<P: AsRef<Path>>(path: P) -> io::Result<u32> {
-    let mut digest = Digest::new(crc32::IEEE);
     let mut f = OpenOptions::new().read(true).open(path)?;
+    calc_crc32_from_reader(&mut f)
+}
+
+/// Calculates the crc32 of all the data read from `reader`.
+pub fn calc_crc32_from_reader<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut digest = Digest::new(crc32::IEEE);
     let mut buf = vec![0; DIGEST_BUFFER_SIZE];
     loop {
-        match f.read(&mut buf[..]) {
+        match reader.read(&mut buf[..]) {
             Ok(0) => {
                 return Ok(digest.sum32());
             }
@@ -92,6 +97,35 @@ pub fn calc_crc32<P: AsRef<Path>>(path: P) -> io::Result<u32> {
     }
 }
 
+/// Checks whether `path` is on a memory-backed file system, i.e. tmpfs or ramfs, whose files
+/// are never written to disk.
+#[cfg(target_os = "linux")]
+pub fn is_memory_backed<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    use libc;
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    const TMPFS_MAGIC: u32 = 0x0102_1994;
+    const RAMFS_MAGIC: u32 = 0x8584_58f6;
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    unsafe {
+        let mut stat: libc::statfs = mem::zeroed();
+        if libc::statfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let tp = stat.f_type as u32;
+        Ok(tp == TMPFS_MAGIC || tp == RAMFS_MAGIC)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_memory_backed<P: AsRef<Path>>(_: P) -> io::Result<bool> {
+    Ok(false)
+}
+
 #[cfg(test)]
 mod test {
     use rand::{thread_rng, Rng};
@@ -209,4 +243,12 @@ mod test {
         assert!(!create_dir_if_not_exist(&subdir).unwrap());
         assert!(delete_dir_if_exist(&subdir).unwrap());
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_memory_backed() {
+        assert!(!is_memory_backed("/proc").unwrap());
+        let tmp_dir = TempDir::new("").unwrap();
+        assert!(is_memory_backed(tmp_dir.path().join("missing")).is_err());
+    }
 }
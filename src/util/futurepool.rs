@@ -11,21 +11,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use futures::future::{self, Either};
+use futures::sync::oneshot;
 use futures::Future;
 use futures_cpupool::{self as cpupool, CpuFuture, CpuPool};
 use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
 use std::cell::{Cell, RefCell, RefMut};
+use std::cmp;
+use std::collections::VecDeque;
 /// This mod implemented a wrapped future pool that supports `on_tick()` which is driven by
 /// tasks and is invoked no less than the specific interval.
 use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant as StdInstant};
 
 use util;
 use util::collections::HashMap;
 use util::time::Instant;
+use util::timer::GLOBAL_TIMER_HANDLE;
 
 lazy_static! {
     pub static ref FUTUREPOOL_PENDING_TASK_VEC: IntGaugeVec = register_int_gauge_vec!(
@@ -38,6 +43,11 @@ lazy_static! {
         "Total number of future_pool handled tasks.",
         &["name"]
     ).unwrap();
+    pub static ref FUTUREPOOL_ACTIVE_THREAD_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_futurepool_active_thread_count",
+        "Current number of threads allowed to run tasks in future_pool.",
+        &["name"]
+    ).unwrap();
 }
 
 pub trait Context: fmt::Debug + Send {
@@ -124,11 +134,164 @@ impl<T: Context> ContextDelegators<T> {
     }
 }
 
+#[derive(Debug)]
+struct ActiveThreadsState {
+    running: usize,
+    limit: usize,
+    // The tasks waiting for a slot, with the time they were spawned.
+    waiters: VecDeque<(Instant, oneshot::Sender<ActiveThreadGuard>)>,
+    total_wait: Duration,
+    waited_tasks: u32,
+    last_adjust: Instant,
+}
+
+/// Limits how many tasks of a pool can run at the same time, and adjusts the limit between
+/// `min` and `max` according to how long tasks wait before they are started.
+///
+/// Threads of `CpuPool` can't be added or removed after it is created, so the pool is created
+/// with `max` threads. A slot is acquired when a task is spawned: the task either gets one
+/// right away, or is queued and only polled after a running task hands its slot over. Waiting
+/// tasks are never polled, so no thread of the pool is blocked by the limit.
+#[derive(Debug)]
+struct ActiveThreads {
+    state: Mutex<ActiveThreadsState>,
+    min: usize,
+    max: usize,
+    max_queue_latency: Duration,
+    adjust_interval: Duration,
+    metrics_active_thread_count: IntGauge,
+}
+
+/// Holds a slot of `ActiveThreads`, which is released when the guard is dropped, whether the
+/// task is finished, panics or is dropped.
+#[derive(Debug)]
+struct ActiveThreadGuard {
+    threads: Arc<ActiveThreads>,
+}
+
+impl Drop for ActiveThreadGuard {
+    fn drop(&mut self) {
+        ActiveThreads::release(&self.threads);
+    }
+}
+
+impl ActiveThreads {
+    fn new(
+        min: usize,
+        max: usize,
+        max_queue_latency: Duration,
+        adjust_interval: Duration,
+        metrics_active_thread_count: IntGauge,
+    ) -> ActiveThreads {
+        metrics_active_thread_count.set(max as i64);
+        ActiveThreads {
+            state: Mutex::new(ActiveThreadsState {
+                running: 0,
+                limit: max,
+                waiters: VecDeque::new(),
+                total_wait: Duration::from_secs(0),
+                waited_tasks: 0,
+                last_adjust: Instant::now_coarse(),
+            }),
+            min,
+            max,
+            max_queue_latency,
+            adjust_interval,
+            metrics_active_thread_count,
+        }
+    }
+
+    /// Acquires a slot for a task spawned at `spawn_time`. The receiver is resolved with the
+    /// guard of the slot once the task is allowed to run. It never blocks.
+    fn acquire(
+        this: &Arc<ActiveThreads>,
+        spawn_time: Instant,
+    ) -> oneshot::Receiver<ActiveThreadGuard> {
+        let (tx, rx) = oneshot::channel();
+        this.state
+            .lock()
+            .unwrap()
+            .waiters
+            .push_back((spawn_time, tx));
+        ActiveThreads::dispatch(this);
+        rx
+    }
+
+    /// Releases a slot, and hands it over to the next waiting task if any.
+    fn release(this: &Arc<ActiveThreads>) {
+        this.state.lock().unwrap().running -= 1;
+        ActiveThreads::dispatch(this);
+    }
+
+    /// Starts the waiting tasks while there are free slots.
+    fn dispatch(this: &Arc<ActiveThreads>) {
+        let mut ready = Vec::new();
+        {
+            let mut state = this.state.lock().unwrap();
+            while state.running < state.limit {
+                let (spawn_time, tx) = match state.waiters.pop_front() {
+                    Some(w) => w,
+                    None => break,
+                };
+                state.running += 1;
+                let now = Instant::now_coarse();
+                state.total_wait += now.duration_since(spawn_time);
+                state.waited_tasks += 1;
+                if now.duration_since(state.last_adjust) >= this.adjust_interval {
+                    this.adjust(&mut state);
+                    state.last_adjust = now;
+                }
+                ready.push(tx);
+            }
+        }
+        // The guards are sent without holding the lock. If a task is gone, its guard is
+        // dropped and the slot is released again.
+        for tx in ready {
+            let guard = ActiveThreadGuard {
+                threads: Arc::clone(this),
+            };
+            let _ = tx.send(guard);
+        }
+    }
+
+    /// Grows the limit if tasks waited too long on average in the last interval, or shrinks
+    /// it if they were started quickly enough.
+    fn adjust(&self, state: &mut ActiveThreadsState) {
+        let avg_wait = state.total_wait / state.waited_tasks;
+        state.total_wait = Duration::from_secs(0);
+        state.waited_tasks = 0;
+        if avg_wait > self.max_queue_latency && state.limit < self.max {
+            state.limit += 1;
+        } else if avg_wait < self.max_queue_latency / 2 && state.limit > self.min {
+            state.limit -= 1;
+        } else {
+            return;
+        }
+        self.metrics_active_thread_count.set(state.limit as i64);
+    }
+
+    fn get_limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+}
+
+/// The outcome of a turn of `FuturePool::run_in_turns`.
+#[derive(Debug, PartialEq)]
+pub enum Turn<T> {
+    Finished(T),
+    /// The rest of the work should run in a new turn after the delay, which may be zero.
+    Continue(Duration),
+}
+
 /// A future thread pool that supports `on_tick` for each thread.
 pub struct FuturePool<T: Context + 'static> {
     pool: CpuPool,
+    pool_size: usize,
+    tick_interval: Duration,
     context_delegators: ContextDelegators<T>,
     running_task_count: Arc<AtomicUsize>,
+    active_threads: Option<Arc<ActiveThreads>>,
+    metrics_active_thread_count: IntGauge,
     metrics_pending_task_count: IntGauge,
     metrics_handled_task_count: IntCounter,
 }
@@ -146,8 +309,12 @@ impl<T: Context + 'static> Clone for FuturePool<T> {
     fn clone(&self) -> FuturePool<T> {
         FuturePool {
             pool: self.pool.clone(),
+            pool_size: self.pool_size,
+            tick_interval: self.tick_interval,
             context_delegators: self.context_delegators.clone(),
             running_task_count: Arc::clone(&self.running_task_count),
+            active_threads: self.active_threads.clone(),
+            metrics_active_thread_count: self.metrics_active_thread_count.clone(),
             metrics_pending_task_count: self.metrics_pending_task_count.clone(),
             metrics_handled_task_count: self.metrics_handled_task_count.clone(),
         }
@@ -201,10 +368,17 @@ impl<T: Context + 'static> FuturePool<T> {
                 (thread_id, context_delegator)
             })
             .collect();
+        let metrics_active_thread_count =
+            FUTUREPOOL_ACTIVE_THREAD_VEC.with_label_values(&[name_prefix]);
+        metrics_active_thread_count.set(pool_size as i64);
         FuturePool {
             pool,
+            pool_size,
+            tick_interval,
             context_delegators: ContextDelegators::new(contexts),
             running_task_count: Arc::new(AtomicUsize::new(0)),
+            active_threads: None,
+            metrics_active_thread_count,
             metrics_pending_task_count: FUTUREPOOL_PENDING_TASK_VEC
                 .with_label_values(&[name_prefix]),
             metrics_handled_task_count: FUTUREPOOL_HANDLED_TASK_VEC
@@ -212,12 +386,35 @@ impl<T: Context + 'static> FuturePool<T> {
         }
     }
 
+    /// Lets the number of threads running tasks grow and shrink between `min_pool_size` and
+    /// the pool size according to the queue latency of tasks. The pool starts with all
+    /// threads active. It should be called before the pool is cloned or used.
+    pub fn enable_auto_resize(&mut self, min_pool_size: usize, max_queue_latency: Duration) {
+        if min_pool_size >= self.pool_size {
+            return;
+        }
+        self.active_threads = Some(Arc::new(ActiveThreads::new(
+            cmp::max(min_pool_size, 1),
+            self.pool_size,
+            max_queue_latency,
+            self.tick_interval,
+            self.metrics_active_thread_count.clone(),
+        )));
+    }
+
     /// Get current running task count
     #[inline]
     pub fn get_running_task_count(&self) -> usize {
         self.running_task_count.load(Ordering::Acquire)
     }
 
+    /// Get the number of threads that are allowed to run tasks now.
+    pub fn get_active_thread_count(&self) -> usize {
+        self.active_threads
+            .as_ref()
+            .map_or(self.pool_size, |a| a.get_limit())
+    }
+
     pub fn spawn<F, R>(&self, future_factory: R) -> CpuFuture<F::Item, F::Error>
     where
         R: FnOnce(ContextDelegators<T>) -> F + Send + 'static,
@@ -229,14 +426,26 @@ impl<T: Context + 'static> FuturePool<T> {
         let metrics_pending_task_count = self.metrics_pending_task_count.clone();
         let metrics_handled_task_count = self.metrics_handled_task_count.clone();
         let delegators = self.context_delegators.clone();
+        // The slot is acquired on spawning, so that a task over the limit waits without
+        // occupying a thread.
+        let slot = self.active_threads
+            .as_ref()
+            .map(|a| ActiveThreads::acquire(a, Instant::now_coarse()));
         let func = move || {
-            future_factory(delegators.clone()).then(move |r| {
-                let delegator = delegators.get_current_thread_delegator();
-                delegator.on_task_finish();
-                running_task_count.fetch_sub(1, Ordering::Release);
-                metrics_pending_task_count.dec();
-                metrics_handled_task_count.inc();
-                r
+            let slot = match slot {
+                Some(rx) => Either::A(rx.then(|r| Ok::<_, F::Error>(r.ok()))),
+                None => Either::B(future::ok::<_, F::Error>(None)),
+            };
+            slot.and_then(move |guard| {
+                future_factory(delegators.clone()).then(move |r| {
+                    drop(guard);
+                    let delegator = delegators.get_current_thread_delegator();
+                    delegator.on_task_finish();
+                    running_task_count.fetch_sub(1, Ordering::Release);
+                    metrics_pending_task_count.dec();
+                    metrics_handled_task_count.inc();
+                    r
+                })
             })
         };
 
@@ -244,6 +453,52 @@ impl<T: Context + 'static> FuturePool<T> {
         self.metrics_pending_task_count.inc();
         self.pool.spawn_fn(func)
     }
+
+    /// Runs `step` until it's finished. Every time it returns `Turn::Continue`, the rest of the
+    /// work is spawned to the pool as a new task after the delay, behind the tasks spawned
+    /// meanwhile, so that a long task gives the thread up to others. A delayed turn doesn't
+    /// occupy a thread while waiting.
+    ///
+    /// It should be called by a task of the pool, which keeps its slot while waiting for the
+    /// result, so the rest of the work isn't counted as a new task and doesn't wait for a
+    /// slot. The result is canceled if `step` panics or the pool is dropped.
+    pub fn run_in_turns<S, I, E>(&self, step: S) -> oneshot::Receiver<Result<I, E>>
+    where
+        S: FnMut() -> Result<Turn<I>, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        run_turn(self.pool.clone(), step, tx);
+        rx
+    }
+}
+
+fn run_turn<S, I, E>(pool: CpuPool, mut step: S, tx: oneshot::Sender<Result<I, E>>)
+where
+    S: FnMut() -> Result<Turn<I>, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    let res = match step() {
+        Ok(Turn::Continue(delay)) => {
+            let wait = if delay == Duration::from_secs(0) {
+                Either::A(future::ok(()))
+            } else {
+                Either::B(GLOBAL_TIMER_HANDLE.delay(StdInstant::now() + delay))
+            };
+            let next_pool = pool.clone();
+            // If the timer fails, the turn just runs earlier.
+            pool.spawn(wait.then(move |_| {
+                run_turn(next_pool, step, tx);
+                Ok::<_, ()>(())
+            })).forget();
+            return;
+        }
+        Ok(Turn::Finished(item)) => Ok(item),
+        Err(e) => Err(e),
+    };
+    let _ = tx.send(res);
 }
 
 #[cfg(test)]
@@ -400,4 +655,154 @@ mod tests {
         f4.join(f5).wait().unwrap();
         assert_eq!(pool.get_running_task_count(), 0);
     }
+
+    fn new_active_threads(
+        min: usize,
+        max: usize,
+        adjust_interval: Duration,
+    ) -> Arc<ActiveThreads> {
+        Arc::new(ActiveThreads::new(
+            min,
+            max,
+            Duration::from_millis(100),
+            adjust_interval,
+            FUTUREPOOL_ACTIVE_THREAD_VEC.with_label_values(&["test-active-threads"]),
+        ))
+    }
+
+    fn running_and_waiting(a: &ActiveThreads) -> (usize, usize) {
+        let state = a.state.lock().unwrap();
+        (state.running, state.waiters.len())
+    }
+
+    #[test]
+    fn test_active_threads() {
+        let a = new_active_threads(1, 2, Duration::from_secs(3600));
+        let now = Instant::now_coarse();
+        let g1 = ActiveThreads::acquire(&a, now).try_recv().unwrap().unwrap();
+        let g2 = ActiveThreads::acquire(&a, now).try_recv().unwrap().unwrap();
+        // No slot is left, so the task waits.
+        let mut r3 = ActiveThreads::acquire(&a, now);
+        assert!(r3.try_recv().unwrap().is_none());
+        assert_eq!(running_and_waiting(&a), (2, 1));
+
+        // The slot is handed over to the waiting task.
+        drop(g1);
+        let g3 = r3.try_recv().unwrap().unwrap();
+        assert_eq!(running_and_waiting(&a), (2, 0));
+
+        // A task dropped before it's started doesn't take the slot.
+        let r4 = ActiveThreads::acquire(&a, now);
+        drop(r4);
+        drop(g2);
+        assert_eq!(running_and_waiting(&a), (1, 0));
+
+        // The slot is released if the task panics.
+        let res = thread::spawn(move || {
+            let _g = g3;
+            panic!("task panics");
+        }).join();
+        assert!(res.is_err());
+        assert_eq!(running_and_waiting(&a), (0, 0));
+    }
+
+    #[test]
+    fn test_active_threads_adjust() {
+        // The limit is adjusted every time a task is started.
+        let a = new_active_threads(1, 3, Duration::from_secs(0));
+        assert_eq!(a.get_limit(), 3);
+
+        // Tasks are started immediately, so the limit shrinks to the min.
+        let now = Instant::now_coarse();
+        for limit in &[2, 1, 1] {
+            drop(ActiveThreads::acquire(&a, now).try_recv().unwrap().unwrap());
+            assert_eq!(a.get_limit(), *limit);
+        }
+
+        // Tasks waited too long, so the limit grows to the max.
+        let spawn_time = Instant::now_coarse() - Duration::from_secs(1);
+        for limit in &[2, 3, 3] {
+            drop(ActiveThreads::acquire(&a, spawn_time).try_recv().unwrap().unwrap());
+            assert_eq!(a.get_limit(), *limit);
+        }
+        assert_eq!(running_and_waiting(&a), (0, 0));
+    }
+
+    #[test]
+    fn test_auto_resize_not_block_threads() {
+        #[derive(Debug)]
+        struct MyContext;
+        impl Context for MyContext {}
+
+        let mut pool = FuturePool::new(
+            2,
+            1024000,
+            "test-pool",
+            Duration::from_secs(3600),
+            move || MyContext {},
+        );
+        pool.enable_auto_resize(1, Duration::from_millis(10));
+        let active_threads = Arc::clone(pool.active_threads.as_ref().unwrap());
+        active_threads.state.lock().unwrap().limit = 1;
+
+        // The first task holds the only slot until it's notified.
+        let (tx, rx) = oneshot::channel::<()>();
+        let f1 = pool.spawn(move |_| rx.map_err(|_| ()));
+        let futures: Vec<_> = (0..4)
+            .map(|_| pool.spawn(|_| future::ok::<(), ()>(())))
+            .collect();
+        assert_eq!(running_and_waiting(&active_threads), (1, 4));
+
+        // The waiting tasks don't occupy the threads of the pool.
+        pool.pool.spawn_fn(|| Ok::<(), ()>(())).wait().unwrap();
+
+        tx.send(()).unwrap();
+        f1.wait().unwrap();
+        for f in futures {
+            f.wait().unwrap();
+        }
+        assert_eq!(running_and_waiting(&active_threads), (0, 0));
+    }
+
+    #[test]
+    fn test_run_in_turns() {
+        #[derive(Debug)]
+        struct MyContext;
+        impl Context for MyContext {}
+
+        let pool = FuturePool::new(
+            1,
+            1024000,
+            "test-pool",
+            Duration::from_secs(3600),
+            move || MyContext {},
+        );
+
+        // A task spawned during the first turn runs before the second one.
+        let (tx, rx) = channel();
+        let p = pool.clone();
+        let f = pool.spawn(move |_| {
+            let mut turn = 0;
+            p.clone().run_in_turns(move || -> Result<Turn<i32>, ()> {
+                turn += 1;
+                tx.send(turn).unwrap();
+                match turn {
+                    1 => {
+                        let tx = tx.clone();
+                        p.spawn(move |_| {
+                            tx.send(0).unwrap();
+                            future::ok::<(), ()>(())
+                        }).forget();
+                        Ok(Turn::Continue(Duration::from_secs(0)))
+                    }
+                    2 => Ok(Turn::Continue(Duration::from_millis(100))),
+                    _ => Ok(Turn::Finished(turn)),
+                }
+            })
+        });
+        let begin = Instant::now();
+        assert_eq!(f.wait().unwrap(), Ok(3));
+        assert!(begin.elapsed() >= Duration::from_millis(100));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1, 0, 2, 3]);
+    }
 }
@@ -11,10 +11,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::io::{Result, Write};
 use std::option::Option;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use prometheus::IntCounterVec;
 use rocksdb::RateLimiter;
 
 const PRIORITY_HIGH: u8 = 1;
@@ -63,6 +68,216 @@ impl IOLimiter {
     }
 }
 
+/// The type of an IO, which decides its priority in `IORateLimiter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IOType {
+    Wal,
+    ForegroundRead,
+    ForegroundWrite,
+    Flush,
+    Compaction,
+    Snapshot,
+    Gc,
+    Import,
+}
+
+impl IOType {
+    pub fn tag(self) -> &'static str {
+        match self {
+            IOType::Wal => "wal",
+            IOType::ForegroundRead => "foreground_read",
+            IOType::ForegroundWrite => "foreground_write",
+            IOType::Flush => "flush",
+            IOType::Compaction => "compaction",
+            IOType::Snapshot => "snapshot",
+            IOType::Gc => "gc",
+            IOType::Import => "import",
+        }
+    }
+
+    /// Returns `None` if the IO bypasses the limiter, which is the case of WAL writes, since
+    /// delaying them delays all the foreground writes.
+    pub fn priority(self) -> Option<IOPriority> {
+        match self {
+            IOType::Wal => None,
+            IOType::ForegroundRead | IOType::ForegroundWrite => Some(IOPriority::Foreground),
+            IOType::Flush => Some(IOPriority::Flush),
+            IOType::Compaction | IOType::Snapshot => Some(IOPriority::Compaction),
+            IOType::Gc | IOType::Import => Some(IOPriority::Background),
+        }
+    }
+}
+
+/// The priorities of the IOs, from the highest to the lowest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IOPriority {
+    Foreground = 0,
+    Flush = 1,
+    Compaction = 2,
+    Background = 3,
+}
+
+const PRIORITY_COUNT: usize = 4;
+const EPOCH_MILLIS: u64 = 100;
+// Every priority can use at least `1 / MIN_SHARE_DIVISOR` of the budget of an epoch, so that
+// the low priority IOs are never starved.
+const MIN_SHARE_DIVISOR: usize = 10;
+
+#[derive(Debug)]
+struct Epoch {
+    start: Instant,
+    consumed: [usize; PRIORITY_COUNT],
+    last_consumed: [usize; PRIORITY_COUNT],
+}
+
+impl Epoch {
+    fn new(start: Instant) -> Epoch {
+        Epoch {
+            start,
+            consumed: [0; PRIORITY_COUNT],
+            last_consumed: [0; PRIORITY_COUNT],
+        }
+    }
+
+    fn maybe_advance(&mut self, now: Instant) {
+        let epoch = Duration::from_millis(EPOCH_MILLIS);
+        let elapsed = now.duration_since(self.start);
+        if elapsed < epoch {
+            return;
+        }
+        self.last_consumed = if elapsed < epoch * 2 {
+            self.consumed
+        } else {
+            [0; PRIORITY_COUNT]
+        };
+        self.consumed = [0; PRIORITY_COUNT];
+        self.start = now;
+    }
+
+    // The IOs of a priority share the budget left by the higher priorities with the lower
+    // priorities. The higher priorities are expected to use as much as they used in the last
+    // epoch.
+    fn has_quota(&self, priority: usize, budget: usize, bytes: usize) -> bool {
+        let reserved: usize = (0..priority)
+            .map(|p| cmp::max(self.consumed[p], self.last_consumed[p]))
+            .sum();
+        let quota = cmp::max(budget.saturating_sub(reserved), budget / MIN_SHARE_DIVISOR);
+        let used: usize = self.consumed[priority..].iter().sum();
+        used + bytes <= quota
+    }
+}
+
+/// `IORateLimiter` limits the disk bandwidth shared by all kinds of IOs of the instance.
+///
+/// The budget is refilled every epoch. Foreground IOs are never delayed, but the budget they
+/// use is kept from the background IOs in the next epoch, and so forth for the other
+/// priorities.
+#[derive(Debug)]
+pub struct IORateLimiter {
+    // 0 means unlimited.
+    bytes_per_epoch: AtomicUsize,
+    epoch: Mutex<Epoch>,
+}
+
+impl IORateLimiter {
+    pub fn new(bytes_per_sec: u64) -> IORateLimiter {
+        let limiter = IORateLimiter {
+            bytes_per_epoch: AtomicUsize::new(0),
+            epoch: Mutex::new(Epoch::new(Instant::now())),
+        };
+        limiter.set_bytes_per_second(bytes_per_sec);
+        limiter
+    }
+
+    pub fn set_bytes_per_second(&self, bytes_per_sec: u64) {
+        let bytes_per_epoch = bytes_per_sec * EPOCH_MILLIS / 1000;
+        self.bytes_per_epoch
+            .store(bytes_per_epoch as usize, Ordering::Relaxed);
+    }
+
+    pub fn get_bytes_per_second(&self) -> u64 {
+        self.bytes_per_epoch.load(Ordering::Relaxed) as u64 * 1000 / EPOCH_MILLIS
+    }
+
+    /// Records `bytes` of IO of `io_type` which has been done without asking the limiter, like
+    /// the compactions of RocksDB. It takes the budget of the lower priorities as well.
+    pub fn record(&self, io_type: IOType, bytes: usize) {
+        IO_BYTES_COUNTER_VEC
+            .with_label_values(&[io_type.tag()])
+            .inc_by(bytes as i64);
+        if let Some(priority) = io_type.priority() {
+            let mut epoch = self.epoch.lock().unwrap();
+            epoch.maybe_advance(Instant::now());
+            epoch.consumed[priority as usize] += bytes;
+        }
+    }
+
+    /// Requests `bytes` of IO of `io_type`, blocks until the priority of the IO has enough
+    /// budget.
+    pub fn request(&self, io_type: IOType, mut bytes: usize) {
+        IO_BYTES_COUNTER_VEC
+            .with_label_values(&[io_type.tag()])
+            .inc_by(bytes as i64);
+        let priority = match io_type.priority() {
+            Some(p) => p as usize,
+            None => return,
+        };
+        while bytes > 0 {
+            let budget = self.bytes_per_epoch.load(Ordering::Relaxed);
+            // Large requests are split, so that they always fit in the quota of an epoch.
+            let single = cmp::min(bytes, cmp::max(budget / MIN_SHARE_DIVISOR, 1));
+            let wait = {
+                let mut epoch = self.epoch.lock().unwrap();
+                let now = Instant::now();
+                epoch.maybe_advance(now);
+                if budget == 0
+                    || priority == IOPriority::Foreground as usize
+                    || epoch.has_quota(priority, budget, single)
+                {
+                    epoch.consumed[priority] += single;
+                    bytes -= single;
+                    continue;
+                }
+                epoch.start + Duration::from_millis(EPOCH_MILLIS) - now
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+lazy_static! {
+    static ref IO_RATE_LIMITER: RwLock<Option<Arc<IORateLimiter>>> = RwLock::new(None);
+    static ref IO_BYTES_COUNTER_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_io_bytes",
+        "Bytes of the IOs requested from the IO rate limiter",
+        &["type"]
+    ).unwrap();
+}
+
+/// Sets the IO rate limiter shared by the whole instance.
+pub fn set_io_rate_limiter(limiter: Option<Arc<IORateLimiter>>) {
+    *IO_RATE_LIMITER.write().unwrap() = limiter;
+}
+
+pub fn get_io_rate_limiter() -> Option<Arc<IORateLimiter>> {
+    IO_RATE_LIMITER.read().unwrap().clone()
+}
+
+/// Requests `bytes` of IO from the shared IO rate limiter if there is one. Callers should
+/// batch small IOs, since every request takes a lock.
+pub fn request_io(io_type: IOType, bytes: usize) {
+    if let Some(limiter) = get_io_rate_limiter() {
+        limiter.request(io_type, bytes);
+    }
+}
+
+/// Records `bytes` of IO in the shared IO rate limiter if there is one.
+pub fn record_io(io_type: IOType, bytes: usize) {
+    if let Some(limiter) = get_io_rate_limiter() {
+        limiter.record(io_type, bytes);
+    }
+}
+
 pub struct LimitWriter<'a, T: Write + 'a> {
     limiter: Option<Arc<IOLimiter>>,
     writer: &'a mut T,
@@ -107,10 +322,11 @@ impl<'a, T: Write + 'a> Write for LimitWriter<'a, T> {
 mod test {
     use std::fs::File;
     use std::io::{Read, Write};
-    use std::sync::Arc;
     use tempdir::TempDir;
 
-    use super::{IOLimiter, LimitWriter, SNAP_MAX_BYTES_PER_TIME};
+    use std::time::{Duration, Instant};
+
+    use super::*;
 
     #[test]
     fn test_io_limiter() {
@@ -147,4 +363,63 @@ mod test {
         file.read_to_string(&mut contents).unwrap();
         assert_eq!(contents, s);
     }
+
+    #[test]
+    fn test_epoch_quota() {
+        let budget = 1000;
+        let mut epoch = Epoch::new(Instant::now());
+        let (compaction, background) = (
+            IOPriority::Compaction as usize,
+            IOPriority::Background as usize,
+        );
+        assert!(epoch.has_quota(background, budget, 1000));
+        assert!(!epoch.has_quota(background, budget, 1001));
+
+        // The budget used by the higher priorities in the last epoch is kept from the lower
+        // priorities.
+        epoch.last_consumed[IOPriority::Foreground as usize] = 600;
+        epoch.consumed[IOPriority::Flush as usize] = 200;
+        assert!(epoch.has_quota(compaction, budget, 200));
+        assert!(!epoch.has_quota(compaction, budget, 201));
+        epoch.consumed[background] = 150;
+        assert!(epoch.has_quota(compaction, budget, 50));
+        assert!(!epoch.has_quota(compaction, budget, 51));
+
+        // Lower priorities are never starved.
+        epoch.last_consumed[IOPriority::Foreground as usize] = 2000;
+        epoch.consumed[background] = 0;
+        assert!(epoch.has_quota(background, budget, 100));
+        assert!(!epoch.has_quota(background, budget, 101));
+
+        // The consumption is kept for one epoch only.
+        let start = epoch.start;
+        epoch.maybe_advance(start + Duration::from_millis(EPOCH_MILLIS));
+        assert_eq!(epoch.last_consumed[IOPriority::Flush as usize], 200);
+        assert_eq!(epoch.consumed, [0; PRIORITY_COUNT]);
+        epoch.maybe_advance(start + Duration::from_millis(EPOCH_MILLIS * 3));
+        assert_eq!(epoch.last_consumed, [0; PRIORITY_COUNT]);
+    }
+
+    #[test]
+    fn test_io_rate_limiter() {
+        // 1000 bytes per epoch.
+        let limiter = IORateLimiter::new(10_000);
+        assert_eq!(limiter.get_bytes_per_second(), 10_000);
+
+        // Foreground IOs and WAL writes are never delayed.
+        let timer = Instant::now();
+        limiter.request(IOType::ForegroundWrite, 100_000);
+        limiter.request(IOType::Wal, 100_000);
+        assert!(timer.elapsed() < Duration::from_millis(EPOCH_MILLIS));
+
+        let limiter = IORateLimiter::new(10_000);
+        let timer = Instant::now();
+        limiter.request(IOType::Gc, 2500);
+        assert!(timer.elapsed() >= Duration::from_millis(EPOCH_MILLIS * 2));
+
+        limiter.set_bytes_per_second(0);
+        let timer = Instant::now();
+        limiter.request(IOType::Import, 100_000);
+        assert!(timer.elapsed() < Duration::from_millis(EPOCH_MILLIS));
+    }
 }
@@ -65,4 +65,83 @@ mod jemalloc {
     }
 }
 
+#[cfg(all(unix, feature = "mem-profiling"))]
+mod profiling {
+    use std::ffi::CString;
+    use std::io::{Error, ErrorKind, Result};
+    use std::{mem, ptr};
+
+    use libc::{c_char, c_int, c_void, size_t};
+
+    extern "C" {
+        #[cfg_attr(target_os = "macos", link_name = "je_mallctl")]
+        fn mallctl(
+            name: *const c_char,
+            oldp: *mut c_void,
+            oldlenp: *mut size_t,
+            newp: *mut c_void,
+            newlen: size_t,
+        ) -> c_int;
+    }
+
+    // `name` must end with '\0'.
+    unsafe fn write<T>(name: &[u8], mut value: T) -> Result<()> {
+        let res = mallctl(
+            name.as_ptr() as *const c_char,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut value as *mut T as *mut c_void,
+            mem::size_of::<T>(),
+        );
+        if res != 0 {
+            return Err(Error::from_raw_os_error(res));
+        }
+        Ok(())
+    }
+
+    fn set_prof_active(active: bool) -> Result<()> {
+        unsafe { write(b"prof.active\0", active) }
+    }
+
+    /// Starts sampling the allocations. jemalloc must be started with `prof:true`.
+    pub fn activate_prof() -> Result<()> {
+        set_prof_active(true)
+    }
+
+    pub fn deactivate_prof() -> Result<()> {
+        set_prof_active(false)
+    }
+
+    /// Dumps the heap profile to `path`, which can be read by `jeprof`.
+    pub fn dump_prof(path: &str) -> Result<()> {
+        let path = CString::new(path).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        unsafe { write(b"prof.dump\0", path.as_ptr()) }
+    }
+}
+
+#[cfg(not(all(unix, feature = "mem-profiling")))]
+mod profiling {
+    use std::io::{Error, ErrorKind, Result};
+
+    fn unsupported() -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Other,
+            "heap profiling is not supported, build with the mem-profiling feature",
+        ))
+    }
+
+    pub fn activate_prof() -> Result<()> {
+        unsupported()
+    }
+
+    pub fn deactivate_prof() -> Result<()> {
+        unsupported()
+    }
+
+    pub fn dump_prof(_: &str) -> Result<()> {
+        unsupported()
+    }
+}
+
 pub use self::jemalloc::dump_stats;
+pub use self::profiling::{activate_prof, deactivate_prof, dump_prof};
@@ -15,6 +15,8 @@ use std::fmt;
 use std::io::{self, Write};
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use chrono;
 use grpc;
@@ -37,6 +39,26 @@ const ENABLED_TARGETS: &[&str] = &[
     "raft::",
 ];
 
+/// The target of the slow logs, which are written to the slow log file if it's set.
+pub const SLOW_LOG_TARGET: &str = "slow_log";
+const DEFAULT_SLOW_LOG_THRESHOLD_MS: usize = 1000;
+
+static SLOW_LOG_THRESHOLD_MS: AtomicUsize = AtomicUsize::new(DEFAULT_SLOW_LOG_THRESHOLD_MS);
+
+pub fn set_slow_log_threshold(threshold: Duration) {
+    let ms = threshold.as_secs() as usize * 1000 + threshold.subsec_nanos() as usize / 1_000_000;
+    SLOW_LOG_THRESHOLD_MS.store(ms, Ordering::Relaxed);
+}
+
+pub fn slow_log_threshold() -> Duration {
+    Duration::from_millis(SLOW_LOG_THRESHOLD_MS.load(Ordering::Relaxed) as u64)
+}
+
+/// Returns whether a request taking `elapsed` should be written to the slow log.
+pub fn is_slow(elapsed: Duration) -> bool {
+    elapsed >= slow_log_threshold()
+}
+
 pub fn init_log<D>(drain: D, level: Level) -> Result<GlobalLoggerGuard, SetLoggerError>
 where
     D: Drain + Send + Sync + 'static + RefUnwindSafe + UnwindSafe,
@@ -105,6 +127,15 @@ pub fn convert_slog_level_to_log_level(lv: Level) -> log::LogLevel {
     }
 }
 
+#[test]
+fn test_slow_log_threshold() {
+    set_slow_log_threshold(Duration::from_millis(1500));
+    assert_eq!(slow_log_threshold(), Duration::from_millis(1500));
+    assert!(!is_slow(Duration::from_millis(1499)));
+    assert!(is_slow(Duration::from_millis(1500)));
+    set_slow_log_threshold(Duration::from_millis(DEFAULT_SLOW_LOG_THRESHOLD_MS as u64));
+}
+
 #[test]
 fn test_get_level_by_string() {
     // Ensure UPPER, Capitalized, and lower case all map over.
@@ -119,6 +150,35 @@ fn test_get_level_by_string() {
     assert_eq!(None, get_level_by_string("definitely not an option"));
 }
 
+/// `LogDispatcher` writes the slow logs to `slow`, and the other logs to `normal`.
+pub struct LogDispatcher<N, S> {
+    normal: N,
+    slow: S,
+}
+
+impl<N, S> LogDispatcher<N, S> {
+    pub fn new(normal: N, slow: S) -> Self {
+        LogDispatcher { normal, slow }
+    }
+}
+
+impl<N, S> Drain for LogDispatcher<N, S>
+where
+    N: Drain,
+    S: Drain<Ok = N::Ok, Err = N::Err>,
+{
+    type Ok = N::Ok;
+    type Err = N::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if record.tag() == SLOW_LOG_TARGET {
+            self.slow.log(record, values)
+        } else {
+            self.normal.log(record, values)
+        }
+    }
+}
+
 pub struct TikvFormat<D>
 where
     D: Decorator,
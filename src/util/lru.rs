@@ -0,0 +1,128 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use util::collections::HashMap;
+
+/// A simple least-recently-used cache.
+///
+/// Every access bumps the entry to a new sequence number, and the entry with the
+/// smallest sequence number is evicted when the capacity is exceeded.
+pub struct LruCache<K, V> {
+    map: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+    capacity: usize,
+    seq: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn with_capacity(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            map: HashMap::default(),
+            order: BTreeMap::new(),
+            capacity,
+            seq: 0,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.seq += 1;
+        if let Some((_, seq)) = self.map.insert(key.clone(), (value, self.seq)) {
+            self.order.remove(&seq);
+        }
+        self.order.insert(self.seq, key);
+        while self.map.len() > self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let key = self.order.remove(&oldest).unwrap();
+            self.map.remove(&key);
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.seq += 1;
+        let seq = self.seq;
+        match self.map.get_mut(key) {
+            Some(entry) => {
+                let old = entry.1;
+                entry.1 = seq;
+                let k = self.order.remove(&old).unwrap();
+                self.order.insert(seq, k);
+                Some(&entry.0)
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key).map(|(v, seq)| {
+            self.order.remove(&seq);
+            v
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::with_capacity(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some(&"a"));
+        // 2 is the least recently used one.
+        cache.insert(3, "c");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some(&"c"));
+
+        // Updating an existing key doesn't evict anything.
+        cache.insert(1, "aa");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(&"aa"));
+        cache.insert(4, "d");
+        assert_eq!(cache.get(&3), None);
+
+        assert_eq!(cache.remove(&1), Some("aa"));
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+
+        let mut cache = LruCache::with_capacity(0);
+        cache.insert(1, 1);
+        assert!(cache.is_empty());
+    }
+}
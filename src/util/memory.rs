@@ -0,0 +1,137 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The global memory usage of the major consumers of a TiKV instance.
+//!
+//! The consumers report their usage here, and check `exceeds_high_water_mark` to slow down
+//! or to release memory before the process is killed for running out of memory.
+
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use prometheus::IntGaugeVec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryConsumer {
+    BlockCache,
+    RaftEntryCache,
+    ApplyBatch,
+    Coprocessor,
+    Scheduler,
+}
+
+const ALL_CONSUMERS: &[MemoryConsumer] = &[
+    MemoryConsumer::BlockCache,
+    MemoryConsumer::RaftEntryCache,
+    MemoryConsumer::ApplyBatch,
+    MemoryConsumer::Coprocessor,
+    MemoryConsumer::Scheduler,
+];
+
+impl MemoryConsumer {
+    pub fn tag(self) -> &'static str {
+        match self {
+            MemoryConsumer::BlockCache => "block_cache",
+            MemoryConsumer::RaftEntryCache => "raft_entry_cache",
+            MemoryConsumer::ApplyBatch => "apply_batch",
+            MemoryConsumer::Coprocessor => "coprocessor",
+            MemoryConsumer::Scheduler => "scheduler",
+        }
+    }
+
+    fn usage(self) -> &'static AtomicUsize {
+        &USAGES[self as usize]
+    }
+}
+
+static USAGES: [AtomicUsize; 5] = [
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+    ATOMIC_USIZE_INIT,
+];
+
+// 0 means there is no high water mark.
+static HIGH_WATER_MARK: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref MEMORY_USAGE_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_memory_usage_bytes",
+        "Memory used by the major consumers",
+        &["type"]
+    ).unwrap();
+}
+
+/// Sets the memory used by `consumer`, for the consumers which know their total usage.
+pub fn record(consumer: MemoryConsumer, bytes: usize) {
+    consumer.usage().store(bytes, Ordering::Relaxed);
+}
+
+pub fn add(consumer: MemoryConsumer, bytes: usize) {
+    consumer.usage().fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn sub(consumer: MemoryConsumer, bytes: usize) {
+    consumer.usage().fetch_sub(bytes, Ordering::Relaxed);
+}
+
+pub fn usage(consumer: MemoryConsumer) -> usize {
+    consumer.usage().load(Ordering::Relaxed)
+}
+
+/// Gets the memory used by all the consumers.
+pub fn total() -> usize {
+    ALL_CONSUMERS.iter().map(|c| usage(*c)).sum()
+}
+
+/// Sets the high water mark of the total memory usage, 0 disables it.
+pub fn set_high_water_mark(bytes: usize) {
+    HIGH_WATER_MARK.store(bytes, Ordering::Relaxed);
+}
+
+pub fn high_water_mark() -> usize {
+    HIGH_WATER_MARK.load(Ordering::Relaxed)
+}
+
+/// Checks whether the total memory usage reaches the high water mark. New requests should be
+/// rejected and caches should be evicted when it returns true.
+pub fn exceeds_high_water_mark() -> bool {
+    exceeds(total(), high_water_mark())
+}
+
+fn exceeds(total: usize, high_water_mark: usize) -> bool {
+    high_water_mark > 0 && total >= high_water_mark
+}
+
+pub fn flush_metrics() {
+    for consumer in ALL_CONSUMERS {
+        MEMORY_USAGE_GAUGE_VEC
+            .with_label_values(&[consumer.tag()])
+            .set(usage(*consumer) as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeds_high_water_mark() {
+        // The usages and the high water mark are global and shared with the other tests, so
+        // only the check itself is tested here.
+        assert!(!exceeds(1024, 0));
+        assert!(!exceeds(1023, 1024));
+        assert!(exceeds(1024, 1024));
+        assert!(exceeds(2048, 1024));
+    }
+}
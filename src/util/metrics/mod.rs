@@ -19,12 +19,12 @@ use prometheus::{self, Encoder, TextEncoder};
 #[cfg(target_os = "linux")]
 mod threads_linux;
 #[cfg(target_os = "linux")]
-pub use self::threads_linux::monitor_threads;
+pub use self::threads_linux::{monitor_threads, process_stat, ProcessStat};
 
 #[cfg(not(target_os = "linux"))]
 mod threads_dummy;
 #[cfg(not(target_os = "linux"))]
-pub use self::threads_dummy::monitor_threads;
+pub use self::threads_dummy::{monitor_threads, process_stat, ProcessStat};
 
 /// `run_prometheus` runs a background prometheus client.
 pub fn run_prometheus(
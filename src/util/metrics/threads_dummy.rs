@@ -23,3 +23,19 @@ use std::io;
 pub fn monitor_threads<S: Into<String>>(_: S) -> io::Result<()> {
     Ok(())
 }
+
+/// The CPU time and the IO bytes of the current process so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessStat {
+    /// The user and system CPU time in seconds.
+    pub cpu_time: f64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+pub fn process_stat() -> io::Result<ProcessStat> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "process stats are only supported on Linux",
+    ))
+}
@@ -26,6 +26,33 @@ pub fn monitor_threads<S: Into<String>>(namespace: S) -> Result<()> {
     prometheus::register(Box::new(tc)).map_err(|e| to_io_err(format!("{:?}", e)))
 }
 
+/// The CPU time and the IO bytes of the current process so far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessStat {
+    /// The user and system CPU time in seconds.
+    pub cpu_time: f64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+/// Collects the CPU time and the IO bytes of the current process.
+pub fn process_stat() -> Result<ProcessStat> {
+    let mut stat = String::new();
+    fs::File::open("/proc/self/stat").and_then(|mut f| f.read_to_string(&mut stat))?;
+    let Stat { utime, stime, .. } = get_thread_stat_internal(0, &stat)?;
+    let mut io = String::new();
+    fs::File::open("/proc/self/io").and_then(|mut f| f.read_to_string(&mut io))?;
+    let Io {
+        read_bytes,
+        write_bytes,
+    } = get_thread_io_internal(&io)?;
+    Ok(ProcessStat {
+        cpu_time: (utime + stime) / *CLK_TCK,
+        read_bytes,
+        write_bytes,
+    })
+}
+
 struct Metrics {
     cpu_totals: CounterVec,
     io_totals: CounterVec,
@@ -440,6 +467,15 @@ cancelled_write_bytes: 0";
         assert_eq!(write_bytes as i64, 323932170);
     }
 
+    #[test]
+    fn test_process_stat() {
+        let start = process_stat().unwrap();
+        let end = process_stat().unwrap();
+        assert!(end.cpu_time >= start.cpu_time);
+        assert!(end.read_bytes >= start.read_bytes);
+        assert!(end.write_bytes >= start.write_bytes);
+    }
+
     #[test]
     fn test_smoke() {
         let pid = unsafe { libc::getpid() };
@@ -25,9 +25,12 @@ use rand::{self, ThreadRng};
 
 #[macro_use]
 pub mod macros;
+pub mod aws;
 pub mod codec;
 pub mod collections;
 pub mod config;
+pub mod cpu_profiler;
+pub mod encryption;
 pub mod file;
 pub mod file_log;
 pub mod future;
@@ -35,6 +38,8 @@ pub mod futurepool;
 pub mod io_limiter;
 pub mod jemalloc;
 pub mod logger;
+pub mod lru;
+pub mod memory;
 pub mod metrics;
 pub mod mpsc;
 pub mod panic_hook;
@@ -44,6 +49,7 @@ pub mod sys;
 pub mod threadpool;
 pub mod time;
 pub mod timer;
+pub mod trace;
 pub mod transport;
 pub mod worker;
 
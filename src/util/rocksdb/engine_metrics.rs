@@ -13,7 +13,9 @@
 
 use std::i64;
 
-use prometheus::{exponential_buckets, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec};
+use prometheus::{
+    exponential_buckets, GaugeVec, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+};
 use rocksdb::{
     DBStatisticsHistogramType as HistType, DBStatisticsTickerType as TickerType, HistogramData, DB,
 };
@@ -30,6 +32,12 @@ pub const ROCKSDB_COMPRESSION_RATIO_AT_LEVEL: &str = "rocksdb.compression-ratio-
 pub const ROCKSDB_NUM_SNAPSHOTS: &str = "rocksdb.num-snapshots";
 pub const ROCKSDB_OLDEST_SNAPSHOT_TIME: &str = "rocksdb.oldest-snapshot-time";
 pub const ROCKSDB_NUM_FILES_AT_LEVEL: &str = "rocksdb.num-files-at-level";
+pub const ROCKSDB_NUM_IMMUTABLE_MEM_TABLE: &str = "rocksdb.num-immutable-mem-table";
+pub const ROCKSDB_CF_STATS: &str = "rocksdb.cfstats";
+pub const ROCKSDB_IS_WRITE_STOPPED: &str = "rocksdb.is-write-stopped";
+pub const ROCKSDB_ACTUAL_DELAYED_WRITE_RATE: &str = "rocksdb.actual-delayed-write-rate";
+
+const STALLS_COUNT_PREFIX: &str = "Stalls(count): ";
 
 pub const ENGINE_TICKER_TYPES: &[TickerType] = &[
     TickerType::BlockCacheMiss,
@@ -907,12 +915,6 @@ pub fn flush_engine_properties(engine: &DB, name: &str) {
             .with_label_values(&[name, cf])
             .set(cf_used_size as i64);
 
-        // For block cache usage
-        let block_cache_usage = engine.get_block_cache_usage_cf(handle);
-        STORE_ENGINE_BLOCK_CACHE_USAGE_GAUGE_VEC
-            .with_label_values(&[name, cf])
-            .set(block_cache_usage as i64);
-
         // TODO: find a better place to record these metrics.
         // Refer: https://github.com/facebook/rocksdb/wiki/Memory-usage-in-RocksDB
         // For index and filter blocks memory
@@ -969,6 +971,50 @@ pub fn flush_engine_properties(engine: &DB, name: &str) {
                     .set(v as i64);
             }
         }
+
+        // Sizes of the live SST files at levels
+        let cf_meta = engine.get_column_family_meta_data(handle);
+        for (level, level_meta) in cf_meta.get_levels().iter().enumerate() {
+            let level_size: u64 = level_meta.get_files().iter().map(|f| f.get_size() as u64).sum();
+            let level_str = level.to_string();
+            STORE_ENGINE_SIZE_AT_LEVEL_VEC
+                .with_label_values(&[name, cf, &level_str])
+                .set(level_size as i64);
+        }
+
+        // Number of immutable memtables waiting to be flushed
+        if let Some(v) = engine.get_property_int_cf(handle, ROCKSDB_NUM_IMMUTABLE_MEM_TABLE) {
+            STORE_ENGINE_NUM_IMMUTABLE_MEM_TABLE_VEC
+                .with_label_values(&[name, cf])
+                .set(v as i64);
+        }
+
+        // Write stall conditions by cause
+        if let Some(stats) = engine.get_property_value_cf(handle, ROCKSDB_CF_STATS) {
+            for (cause, count) in parse_stall_counts(&stats) {
+                STORE_ENGINE_STALL_CONDITIONS_COUNT_VEC
+                    .with_label_values(&[name, cf, &cause])
+                    .set(count as i64);
+            }
+        }
+
+        // Space amplification estimated from the live SST files
+        let sizes = rocksdb::get_cf_sst_sizes(engine, handle);
+        STORE_ENGINE_SPACE_AMPLIFICATION_VEC
+            .with_label_values(&[name, cf])
+            .set(sizes.space_amplification());
+    }
+
+    // For write stall
+    if let Some(v) = engine.get_property_int(ROCKSDB_IS_WRITE_STOPPED) {
+        STORE_ENGINE_WRITE_STALL_STATE_VEC
+            .with_label_values(&[name, "write_stopped"])
+            .set(v as i64);
+    }
+    if let Some(v) = engine.get_property_int(ROCKSDB_ACTUAL_DELAYED_WRITE_RATE) {
+        STORE_ENGINE_WRITE_STALL_STATE_VEC
+            .with_label_values(&[name, "delayed_write_rate"])
+            .set(v as i64);
     }
 
     // For snapshot
@@ -987,6 +1033,28 @@ pub fn flush_engine_properties(engine: &DB, name: &str) {
     }
 }
 
+/// Parses the cumulative stall counts by cause from the `rocksdb.cfstats` property, which
+/// contains a line like "Stalls(count): 0 level0_slowdown, 1 memtable_compaction, ...".
+fn parse_stall_counts(stats: &str) -> Vec<(String, u64)> {
+    let line = match stats.lines().find(|l| l.starts_with(STALLS_COUNT_PREFIX)) {
+        Some(l) => &l[STALLS_COUNT_PREFIX.len()..],
+        None => return vec![],
+    };
+    let mut counts = vec![];
+    for item in line.split(", ") {
+        let mut parts = item.trim().splitn(2, ' ');
+        // Skips the "interval N total count" item.
+        let count = match parts.next().and_then(|c| c.parse().ok()) {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some(cause) = parts.next() {
+            counts.push((cause.replace(' ', "_"), count));
+        }
+    }
+    counts
+}
+
 // Skip with rustfmt since several names are too long.
 #[cfg_attr(rustfmt, rustfmt_skip)]
 lazy_static! {
@@ -1000,6 +1068,10 @@ lazy_static! {
         "Usage of each column families' block cache",
         &["db", "cf"]
     ).unwrap();
+    pub static ref STORE_ENGINE_RATE_LIMITER_BYTES_PER_SEC_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_engine_rate_limiter_bytes_per_sec",
+        "Bytes per second of the auto tuned rate limiter of the kv engine"
+    ).unwrap();
     pub static ref STORE_ENGINE_MEMORY_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
         "tikv_engine_memory_bytes",
         "Sizes of each column families",
@@ -1158,6 +1230,11 @@ lazy_static! {
         "Histogram of decompression time nanos",
         &["db", "type"]
     ).unwrap();
+    pub static ref STORE_ENGINE_SPACE_AMPLIFICATION_VEC: GaugeVec = register_gauge_vec!(
+        "tikv_engine_space_amplification",
+        "Space amplification estimated from the sizes of the live SST files",
+        &["db", "cf"]
+    ).unwrap();
     pub static ref STORE_ENGINE_PENDING_COMACTION_BYTES_VEC: IntGaugeVec = register_int_gauge_vec!(
         "tikv_engine_pending_compaction_bytes",
         "Pending compaction bytes",
@@ -1235,6 +1312,26 @@ lazy_static! {
         "Number of files at each level",
         &["db", "cf", "level"]
     ).unwrap();
+    pub static ref STORE_ENGINE_SIZE_AT_LEVEL_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_engine_size_at_level_bytes",
+        "Size of the live SST files at each level",
+        &["db", "cf", "level"]
+    ).unwrap();
+    pub static ref STORE_ENGINE_NUM_IMMUTABLE_MEM_TABLE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_engine_num_immutable_mem_table",
+        "Number of immutable mem-table",
+        &["db", "cf"]
+    ).unwrap();
+    pub static ref STORE_ENGINE_STALL_CONDITIONS_COUNT_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_engine_stall_conditions_count",
+        "Cumulative number of write stall conditions by cause",
+        &["db", "cf", "type"]
+    ).unwrap();
+    pub static ref STORE_ENGINE_WRITE_STALL_STATE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_engine_write_stall_state",
+        "Whether writes are stopped and the delayed write rate",
+        &["db", "type"]
+    ).unwrap();
 }
 
 #[cfg(test)]
@@ -1260,4 +1357,21 @@ mod tests {
 
         flush_engine_properties(&db, "test-name");
     }
+
+    #[test]
+    fn test_parse_stall_counts() {
+        let stats = "\n** Compaction Stats [default] **\n\
+                     Cumulative stall: 00:00:0.000 H:M:S, 0.0 percent\n\
+                     Stalls(count): 2 level0_slowdown, 0 level0_numfiles, \
+                     1 stop for pending_compaction_bytes, interval 3 total count\n";
+        assert_eq!(
+            parse_stall_counts(stats),
+            vec![
+                ("level0_slowdown".to_owned(), 2),
+                ("level0_numfiles".to_owned(), 0),
+                ("stop_for_pending_compaction_bytes".to_owned(), 1),
+            ]
+        );
+        assert!(parse_stall_counts("").is_empty());
+    }
 }
@@ -13,8 +13,10 @@
 
 use std::cmp;
 use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
 
 use rocksdb::{self, CompactionJobInfo, FlushJobInfo, IngestionInfo};
+use util::io_limiter::{self, IOType};
 use util::rocksdb::engine_metrics::*;
 
 use super::properties::RangeProperties;
@@ -48,6 +50,8 @@ impl rocksdb::EventListener for EventListener {
         STORE_ENGINE_COMPACTION_NUM_CORRUPT_KEYS_VEC
             .with_label_values(&[&self.db_name, info.cf_name()])
             .inc_by(info.num_corrupt_keys() as i64);
+        let bytes = info.total_input_bytes() + info.total_output_bytes();
+        io_limiter::record_io(IOType::Compaction, bytes as usize);
     }
 
     fn on_external_file_ingested(&self, info: &IngestionInfo) {
@@ -57,6 +61,45 @@ impl rocksdb::EventListener for EventListener {
     }
 }
 
+/// `EventListenerRegistry` forwards the events of a RocksDB instance to all listeners
+/// registered to it. Unlike `DBOptions::add_event_listener`, listeners can be registered
+/// after the instance is opened, so components created later can still watch the flushes,
+/// compactions and ingestions of the instance.
+#[derive(Clone, Default)]
+pub struct EventListenerRegistry {
+    listeners: Arc<RwLock<Vec<Box<rocksdb::EventListener>>>>,
+}
+
+impl EventListenerRegistry {
+    pub fn new() -> EventListenerRegistry {
+        EventListenerRegistry::default()
+    }
+
+    pub fn register<L: rocksdb::EventListener + 'static>(&self, listener: L) {
+        self.listeners.write().unwrap().push(box listener);
+    }
+}
+
+impl rocksdb::EventListener for EventListenerRegistry {
+    fn on_flush_completed(&self, info: &FlushJobInfo) {
+        for l in self.listeners.read().unwrap().iter() {
+            l.on_flush_completed(info);
+        }
+    }
+
+    fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+        for l in self.listeners.read().unwrap().iter() {
+            l.on_compaction_completed(info);
+        }
+    }
+
+    fn on_external_file_ingested(&self, info: &IngestionInfo) {
+        for l in self.listeners.read().unwrap().iter() {
+            l.on_external_file_ingested(info);
+        }
+    }
+}
+
 pub struct CompactedEvent {
     pub cf: String,
     pub output_level: i32,
@@ -177,3 +220,54 @@ impl rocksdb::EventListener for CompactionListener {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocksdb::{DBOptions, Writable};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use storage::ALL_CFS;
+    use tempdir::TempDir;
+    use util::rocksdb::new_engine_opt;
+    use util::rocksdb::CFOptions;
+
+    #[derive(Clone, Default)]
+    struct FlushCounter(Arc<AtomicUsize>);
+
+    impl rocksdb::EventListener for FlushCounter {
+        fn on_flush_completed(&self, _: &FlushJobInfo) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_event_listener_registry() {
+        let path = TempDir::new("_util_rocksdb_test_event_listener_registry").unwrap();
+        let registry = EventListenerRegistry::new();
+        let mut opts = DBOptions::new();
+        opts.add_event_listener(registry.clone());
+        let cfs_opts = ALL_CFS
+            .iter()
+            .map(|cf| CFOptions::new(cf, rocksdb::ColumnFamilyOptions::new()))
+            .collect();
+        let db = new_engine_opt(path.path().to_str().unwrap(), opts, cfs_opts).unwrap();
+
+        // Listeners can be registered after the db is opened.
+        let (c1, c2) = (FlushCounter::default(), FlushCounter::default());
+        registry.register(c1.clone());
+        registry.register(c2.clone());
+        db.put(b"k", b"v").unwrap();
+        db.flush(true).unwrap();
+        // The listeners may be notified after the flush returns.
+        for _ in 0..100 {
+            if c2.0.load(Ordering::SeqCst) == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(c1.0.load(Ordering::SeqCst), 1);
+        assert_eq!(c2.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -18,6 +18,8 @@ use std::sync::mpsc::{self, Sender};
 use std::sync::Arc;
 use std::thread::{Builder, JoinHandle};
 use std::time::{Duration, Instant};
+use util::memory::{self, MemoryConsumer};
+use util::rocksdb;
 use util::rocksdb::engine_metrics::*;
 
 pub const DEFAULT_FLUSHER_INTERVAL: u64 = 10000;
@@ -28,6 +30,7 @@ pub struct MetricsFlusher {
     handle: Option<JoinHandle<()>>,
     sender: Option<Sender<bool>>,
     interval: Duration,
+    shared_block_cache: bool,
 }
 
 impl MetricsFlusher {
@@ -37,14 +40,21 @@ impl MetricsFlusher {
             handle: None,
             sender: None,
             interval,
+            shared_block_cache: false,
         }
     }
 
+    /// Tells the flusher that the kv and raft engines share one block cache.
+    pub fn set_shared_block_cache(&mut self, shared: bool) {
+        self.shared_block_cache = shared;
+    }
+
     pub fn start(&mut self) -> Result<(), io::Error> {
         let db = Arc::clone(&self.engines.kv);
         let raft_db = Arc::clone(&self.engines.raft);
         let (tx, rx) = mpsc::channel();
         let interval = self.interval;
+        let shared_block_cache = self.shared_block_cache;
         self.sender = Some(tx);
         let h = Builder::new()
             .name(thd_name!("rocksdb-metrics"))
@@ -54,6 +64,10 @@ impl MetricsFlusher {
                 while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
                     flush_metrics(&db, "kv");
                     flush_metrics(&raft_db, "raft");
+                    let block_cache_usage =
+                        flush_block_cache_usage(&db, &raft_db, shared_block_cache);
+                    memory::record(MemoryConsumer::BlockCache, block_cache_usage as usize);
+                    memory::flush_metrics();
                     if last_reset.elapsed() >= reset_interval {
                         db.reset_statistics();
                         raft_db.reset_statistics();
@@ -92,6 +106,34 @@ fn flush_metrics(db: &DB, name: &str) {
     flush_engine_properties(db, name);
 }
 
+/// Records the usage of every block cache once, and returns the total usage.
+fn flush_block_cache_usage(db: &DB, raft_db: &DB, shared_block_cache: bool) -> u64 {
+    if shared_block_cache {
+        // All the column families of both engines report the usage of the same cache.
+        let usage = cf_block_cache_usage(db, db.cf_names()[0]);
+        STORE_ENGINE_BLOCK_CACHE_USAGE_GAUGE_VEC
+            .with_label_values(&["shared", "all"])
+            .set(usage as i64);
+        return usage;
+    }
+    // Otherwise every column family has its own cache.
+    let mut total = 0;
+    for &(name, db) in &[("kv", db), ("raft", raft_db)] {
+        for cf in db.cf_names() {
+            let usage = cf_block_cache_usage(db, cf);
+            STORE_ENGINE_BLOCK_CACHE_USAGE_GAUGE_VEC
+                .with_label_values(&[name, cf])
+                .set(usage as i64);
+            total += usage;
+        }
+    }
+    total
+}
+
+fn cf_block_cache_usage(db: &DB, cf: &str) -> u64 {
+    db.get_block_cache_usage_cf(rocksdb::get_cf_handle(db, cf).unwrap())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
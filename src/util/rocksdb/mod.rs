@@ -15,9 +15,12 @@ pub mod engine_metrics;
 pub mod event_listener;
 pub mod metrics_flusher;
 pub mod properties;
+pub mod rate_limiter_tuner;
 pub mod stats;
 
-pub use self::event_listener::{CompactedEvent, CompactionListener, EventListener};
+pub use self::event_listener::{
+    CompactedEvent, CompactionListener, EventListener, EventListenerRegistry,
+};
 pub use self::metrics_flusher::MetricsFlusher;
 
 use std::cmp;
@@ -281,6 +284,39 @@ pub fn get_engine_compression_ratio_at_level(
     None
 }
 
+/// The sizes of the live SST files of a column family.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SstSizes {
+    pub total: u64,
+    /// The size of the bottommost non-empty level.
+    pub bottommost: u64,
+}
+
+impl SstSizes {
+    /// Estimates the space amplification. With leveled compaction the bottommost level
+    /// holds nearly all the live data, the other levels are mostly overwritten or deleted
+    /// versions of it.
+    pub fn space_amplification(&self) -> f64 {
+        if self.bottommost == 0 {
+            return 1.0;
+        }
+        self.total as f64 / self.bottommost as f64
+    }
+}
+
+pub fn get_cf_sst_sizes(engine: &DB, handle: &CFHandle) -> SstSizes {
+    let cf_meta = engine.get_column_family_meta_data(handle);
+    let mut sizes = SstSizes::default();
+    for level in cf_meta.get_levels() {
+        let level_size = level.get_files().iter().map(|f| f.get_size() as u64).sum();
+        if level_size > 0 {
+            sizes.total += level_size;
+            sizes.bottommost = level_size;
+        }
+    }
+    sizes
+}
+
 pub fn auto_compactions_is_disabled(engine: &DB) -> bool {
     for cf_name in engine.cf_names() {
         let cf = engine.cf_handle(cf_name).unwrap();
@@ -420,12 +456,6 @@ pub fn compact_files_in_range_cf(
     let cf = db.cf_handle(cf_name).unwrap();
     let cf_opts = db.get_options_cf(cf);
     let output_level = output_level.unwrap_or(cf_opts.get_num_levels() as i32 - 1);
-    let output_compression = cf_opts
-        .get_compression_per_level()
-        .get(output_level as usize)
-        .cloned()
-        .unwrap_or(DBCompressionType::No);
-    let output_file_size_limit = cf_opts.get_target_file_size_base() as usize;
 
     let mut input_files = Vec::new();
     let cf_meta = db.get_column_family_meta_data(cf);
@@ -443,9 +473,57 @@ pub fn compact_files_in_range_cf(
             input_files.push(f.get_name());
         }
     }
+    compact_files_to_level(db, cf, &input_files, output_level)
+}
+
+/// Compact only the files of the bottommost non-empty level in the range, and output them
+/// to the same level. It drops the versions removed by GC from the bottommost level, where
+/// most of the data lives, without rewriting the other levels.
+pub fn compact_bottommost_files_in_range_cf(
+    db: &DB,
+    cf_name: &str,
+    start: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Result<(), String> {
+    let cf = db.cf_handle(cf_name).unwrap();
+    let cf_meta = db.get_column_family_meta_data(cf);
+    let levels = cf_meta.get_levels();
+    let bottommost = match levels.iter().rposition(|l| !l.get_files().is_empty()) {
+        // Files in level 0 overlap with each other, compacting some of them may break the
+        // order of the versions.
+        Some(0) | None => return Ok(()),
+        Some(i) => i,
+    };
+
+    let mut input_files = Vec::new();
+    for f in levels[bottommost].get_files() {
+        if end.is_some() && end.unwrap() <= f.get_smallestkey() {
+            continue;
+        }
+        if start.is_some() && start.unwrap() > f.get_largestkey() {
+            continue;
+        }
+        input_files.push(f.get_name());
+    }
+    compact_files_to_level(db, cf, &input_files, bottommost as i32)
+}
+
+fn compact_files_to_level(
+    db: &DB,
+    cf: &CFHandle,
+    input_files: &[String],
+    output_level: i32,
+) -> Result<(), String> {
     if input_files.is_empty() {
         return Ok(());
     }
+    let cf_opts = db.get_options_cf(cf);
+    let output_compression = cf_opts
+        .get_compression_per_level()
+        .get(output_level as usize)
+        .cloned()
+        .unwrap_or(DBCompressionType::No);
+    let output_file_size_limit = cf_opts.get_target_file_size_base() as usize;
 
     let mut opts = CompactionOptions::new();
     opts.set_compression(output_compression);
@@ -453,7 +531,7 @@ pub fn compact_files_in_range_cf(
     let max_subcompactions = cmp::min(max_subcompactions, 32);
     opts.set_max_subcompactions(max_subcompactions as i32);
     opts.set_output_file_size_limit(output_file_size_limit);
-    db.compact_files_cf(cf, &opts, &input_files, output_level)?;
+    db.compact_files_cf(cf, &opts, input_files, output_level)?;
 
     Ok(())
 }
@@ -793,4 +871,48 @@ mod tests {
             assert_eq!(level_n[0].get_largestkey(), &[4]);
         }
     }
+
+    #[test]
+    fn test_compact_bottommost_files_in_range() {
+        let temp_dir = TempDir::new("test_compact_bottommost_files_in_range").unwrap();
+
+        let mut cf_opts = ColumnFamilyOptions::new();
+        cf_opts.set_disable_auto_compactions(true);
+        let cfs_opts = vec![CFOptions::new("default", cf_opts)];
+        let db = new_engine(temp_dir.path().to_str().unwrap(), &["default"], Some(cfs_opts))
+            .unwrap();
+        let cf = db.cf_handle("default").unwrap();
+        let num_levels = db.get_options_cf(cf).get_num_levels();
+        let files_at = |level: usize| {
+            let cf_meta = db.get_column_family_meta_data(cf);
+            cf_meta.get_levels()[level].get_files().len()
+        };
+
+        for i in 0..5 {
+            db.put_cf(cf, &[i], &[i]).unwrap();
+        }
+        db.flush_cf(cf, true).unwrap();
+        // Only level 0 has files, nothing is compacted.
+        compact_bottommost_files_in_range_cf(&db, "default", None, None).unwrap();
+        assert_eq!(files_at(0), 1);
+
+        compact_files_in_range(&db, None, None, None).unwrap();
+        let sizes = get_cf_sst_sizes(&db, cf);
+        assert!(sizes.total > 0);
+        assert_eq!(sizes.total, sizes.bottommost);
+        assert_eq!(sizes.space_amplification(), 1.0);
+
+        for i in 0..5 {
+            db.put_cf(cf, &[i], &[i + 1]).unwrap();
+        }
+        db.flush_cf(cf, true).unwrap();
+        let sizes = get_cf_sst_sizes(&db, cf);
+        assert!(sizes.space_amplification() > 1.0);
+
+        // Only the bottommost level is compacted.
+        compact_bottommost_files_in_range_cf(&db, "default", Some(&[1]), Some(&[3])).unwrap();
+        assert_eq!(files_at(0), 1);
+        assert_eq!(files_at(num_levels - 1), 1);
+        assert_eq!(db.get_cf(cf, &[1]).unwrap().unwrap().to_vec(), vec![2]);
+    }
 }
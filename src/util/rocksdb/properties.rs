@@ -32,6 +32,7 @@ const PROP_MIN_TS: &str = "tikv.min_ts";
 const PROP_MAX_TS: &str = "tikv.max_ts";
 const PROP_NUM_ROWS: &str = "tikv.num_rows";
 const PROP_NUM_PUTS: &str = "tikv.num_puts";
+const PROP_NUM_DELETES: &str = "tikv.num_deletes";
 const PROP_NUM_VERSIONS: &str = "tikv.num_versions";
 const PROP_MAX_ROW_VERSIONS: &str = "tikv.max_row_versions";
 const PROP_ROWS_INDEX: &str = "tikv.rows_index";
@@ -48,6 +49,7 @@ pub struct MvccProperties {
     pub max_ts: u64,           // The maximal timestamp.
     pub num_rows: u64,         // The number of rows.
     pub num_puts: u64,         // The number of MVCC puts of all rows.
+    pub num_deletes: u64,      // The number of MVCC delete marks of all rows.
     pub num_versions: u64,     // The number of MVCC versions of all rows.
     pub max_row_versions: u64, // The maximal number of MVCC versions of a single row.
 }
@@ -59,6 +61,7 @@ impl MvccProperties {
             max_ts: u64::MIN,
             num_rows: 0,
             num_puts: 0,
+            num_deletes: 0,
             num_versions: 0,
             max_row_versions: 0,
         }
@@ -69,6 +72,7 @@ impl MvccProperties {
         self.max_ts = cmp::max(self.max_ts, other.max_ts);
         self.num_rows += other.num_rows;
         self.num_puts += other.num_puts;
+        self.num_deletes += other.num_deletes;
         self.num_versions += other.num_versions;
         self.max_row_versions = cmp::max(self.max_row_versions, other.max_row_versions);
     }
@@ -79,6 +83,7 @@ impl MvccProperties {
         props.encode_u64(PROP_MAX_TS, self.max_ts);
         props.encode_u64(PROP_NUM_ROWS, self.num_rows);
         props.encode_u64(PROP_NUM_PUTS, self.num_puts);
+        props.encode_u64(PROP_NUM_DELETES, self.num_deletes);
         props.encode_u64(PROP_NUM_VERSIONS, self.num_versions);
         props.encode_u64(PROP_MAX_ROW_VERSIONS, self.max_row_versions);
         props
@@ -90,6 +95,8 @@ impl MvccProperties {
         res.max_ts = props.decode_u64(PROP_MAX_TS)?;
         res.num_rows = props.decode_u64(PROP_NUM_ROWS)?;
         res.num_puts = props.decode_u64(PROP_NUM_PUTS)?;
+        // SST files written by older versions have no delete marks recorded.
+        res.num_deletes = props.decode_u64(PROP_NUM_DELETES).unwrap_or(0);
         res.num_versions = props.decode_u64(PROP_NUM_VERSIONS)?;
         res.max_row_versions = props.decode_u64(PROP_MAX_ROW_VERSIONS)?;
         Ok(res)
@@ -161,8 +168,10 @@ impl TablePropertiesCollector for MvccPropertiesCollector {
             }
         };
 
-        if write_type == WriteType::Put {
-            self.props.num_puts += 1;
+        match write_type {
+            WriteType::Put => self.props.num_puts += 1,
+            WriteType::Delete => self.props.num_deletes += 1,
+            _ => {}
         }
 
         // Add new row.
@@ -689,6 +698,7 @@ mod tests {
         assert_eq!(props.max_ts, 7);
         assert_eq!(props.num_rows, 4);
         assert_eq!(props.num_puts, 4);
+        assert_eq!(props.num_deletes, 3);
         assert_eq!(props.num_versions, 7);
         assert_eq!(props.max_row_versions, 3);
     }
@@ -0,0 +1,219 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tunes the rate limiter of RocksDB by the compaction backlog.
+//!
+//! The limit is doubled once the pending compaction bytes grow large or the foreground writes
+//! are stalled, so that compactions can catch up, and lowered step by step while compactions
+//! keep up, to leave the disk bandwidth to the foreground.
+
+use std::cmp;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rocksdb::DB;
+
+use super::engine_metrics::{
+    ROCKSDB_ACTUAL_DELAYED_WRITE_RATE, ROCKSDB_IS_WRITE_STOPPED, ROCKSDB_PENDING_COMPACTION_BYTES,
+    STORE_ENGINE_RATE_LIMITER_BYTES_PER_SEC_GAUGE,
+};
+use super::get_cf_handle;
+
+pub const DEFAULT_TUNE_INTERVAL_SECS: u64 = 10;
+// The limit is lowered by `1 / RATE_STEP_DIVISOR` of the max limit every time.
+const RATE_STEP_DIVISOR: u64 = 10;
+
+/// The compaction state of an engine in an interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompactionLoad {
+    /// The estimated bytes that compactions need to rewrite, of all the column families.
+    pub pending_compaction_bytes: u64,
+    /// Whether the foreground writes are delayed or stopped.
+    pub write_stalled: bool,
+}
+
+pub trait CompactionLoadSampler: Send {
+    fn sample(&mut self) -> CompactionLoad;
+}
+
+/// Samples the compaction load from the properties of a DB.
+pub struct DbSampler {
+    db: Arc<DB>,
+}
+
+impl DbSampler {
+    pub fn new(db: Arc<DB>) -> DbSampler {
+        DbSampler { db }
+    }
+}
+
+impl CompactionLoadSampler for DbSampler {
+    fn sample(&mut self) -> CompactionLoad {
+        let pending_compaction_bytes = self
+            .db
+            .cf_names()
+            .into_iter()
+            .filter_map(|cf| {
+                let handle = get_cf_handle(&self.db, cf).unwrap();
+                self.db
+                    .get_property_int_cf(handle, ROCKSDB_PENDING_COMPACTION_BYTES)
+            })
+            .sum();
+        let write_stalled = self
+            .db
+            .get_property_int(ROCKSDB_IS_WRITE_STOPPED)
+            .map_or(false, |v| v > 0)
+            || self
+                .db
+                .get_property_int(ROCKSDB_ACTUAL_DELAYED_WRITE_RATE)
+                .map_or(false, |v| v > 0);
+        CompactionLoad {
+            pending_compaction_bytes,
+            write_stalled,
+        }
+    }
+}
+
+/// `RateLimiterTuner` decides the bytes per second of the rate limiter from the compaction
+/// load, within `[min_rate, max_rate]`.
+pub struct RateLimiterTuner {
+    min_rate: u64,
+    max_rate: u64,
+    // The limit is raised once the pending compaction bytes exceed the high water, and
+    // lowered once they drop below the low water.
+    high_water: u64,
+    low_water: u64,
+    rate: u64,
+}
+
+impl RateLimiterTuner {
+    /// Starts at the max rate.
+    pub fn new(min_rate: u64, max_rate: u64, high_water: u64, low_water: u64) -> RateLimiterTuner {
+        RateLimiterTuner {
+            min_rate,
+            max_rate,
+            high_water,
+            low_water,
+            rate: max_rate,
+        }
+    }
+
+    /// Returns the new bytes per second.
+    pub fn tune(&mut self, load: &CompactionLoad) -> u64 {
+        if load.write_stalled || load.pending_compaction_bytes > self.high_water {
+            self.rate = cmp::min(self.rate.saturating_mul(2), self.max_rate);
+        } else if load.pending_compaction_bytes < self.low_water {
+            let step = cmp::max(self.max_rate / RATE_STEP_DIVISOR, 1);
+            self.rate = cmp::max(self.rate.saturating_sub(step), self.min_rate);
+        }
+        self.rate
+    }
+}
+
+/// Tunes the rate limiter of a DB every interval in a background thread until it's dropped.
+pub struct TunerHandle {
+    stop_tx: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TunerHandle {
+    pub fn start<S: CompactionLoadSampler + 'static>(
+        db: Arc<DB>,
+        mut sampler: S,
+        mut tuner: RateLimiterTuner,
+        interval: Duration,
+    ) -> TunerHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name(thd_name!("rate-limiter-tuner"))
+            .spawn(move || {
+                let mut rate = tuner.rate;
+                STORE_ENGINE_RATE_LIMITER_BYTES_PER_SEC_GAUGE.set(rate as i64);
+                loop {
+                    match stop_rx.recv_timeout(interval) {
+                        Err(RecvTimeoutError::Timeout) => {
+                            let load = sampler.sample();
+                            let new_rate = tuner.tune(&load);
+                            if new_rate == rate {
+                                continue;
+                            }
+                            let mut opts = db.get_db_options();
+                            if let Err(e) = opts.set_rate_bytes_per_sec(new_rate as i64) {
+                                warn!("failed to tune the rate limiter: {:?}", e);
+                                continue;
+                            }
+                            debug!("rate limiter is tuned to {} with {:?}", new_rate, load);
+                            STORE_ENGINE_RATE_LIMITER_BYTES_PER_SEC_GAUGE.set(new_rate as i64);
+                            rate = new_rate;
+                        }
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            })
+            .unwrap();
+        TunerHandle {
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for TunerHandle {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(h) = self.handle.take() {
+            if let Err(e) = h.join() {
+                error!("failed to join rate limiter tuner: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_tuner() {
+        let mut tuner = RateLimiterTuner::new(20, 100, 1000, 100);
+        let idle = CompactionLoad {
+            pending_compaction_bytes: 10,
+            write_stalled: false,
+        };
+        let steady = CompactionLoad {
+            pending_compaction_bytes: 500,
+            ..idle
+        };
+        let backlog = CompactionLoad {
+            pending_compaction_bytes: 2000,
+            ..idle
+        };
+        let stalled = CompactionLoad {
+            write_stalled: true,
+            ..idle
+        };
+
+        assert_eq!(tuner.tune(&idle), 90);
+        assert_eq!(tuner.tune(&steady), 90);
+        for _ in 0..10 {
+            tuner.tune(&idle);
+        }
+        assert_eq!(tuner.tune(&idle), 20);
+        assert_eq!(tuner.tune(&backlog), 40);
+        assert_eq!(tuner.tune(&stalled), 80);
+        assert_eq!(tuner.tune(&backlog), 100);
+        assert_eq!(tuner.tune(&steady), 100);
+    }
+}
@@ -12,14 +12,18 @@
 // limitations under the License.
 
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::ptr;
+use std::sync::RwLock;
+use std::time::SystemTime;
 
 use grpc::{
     Channel, ChannelBuilder, ChannelCredentialsBuilder, ServerBuilder, ServerCredentialsBuilder,
 };
 
+use super::encryption::EncryptionConfig;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 #[serde(rename_all = "kebab-case")]
@@ -27,6 +31,9 @@ pub struct SecurityConfig {
     pub ca_path: String,
     pub cert_path: String,
     pub key_path: String,
+    // The common names of the client certificates allowed, any if it's empty.
+    pub cert_allowed_cn: Vec<String>,
+    pub encryption: EncryptionConfig,
     // Test purpose only.
     #[serde(skip)]
     pub override_ssl_target: String,
@@ -38,6 +45,8 @@ impl Default for SecurityConfig {
             ca_path: String::new(),
             cert_path: String::new(),
             key_path: String::new(),
+            cert_allowed_cn: vec![],
+            encryption: EncryptionConfig::default(),
             override_ssl_target: String::new(),
         }
     }
@@ -76,20 +85,25 @@ impl SecurityConfig {
         {
             return Err("ca, cert and private key should be all configured.".into());
         }
+        if !self.cert_allowed_cn.is_empty() && self.ca_path.is_empty() {
+            return Err("cert-allowed-cn needs the CA to verify the certificates.".into());
+        }
+        self.encryption.validate()?;
 
         Ok(())
     }
 }
 
 #[derive(Default)]
-pub struct SecurityManager {
+struct Certs {
     ca: Vec<u8>,
     cert: Vec<u8>,
     key: Vec<u8>,
-    override_ssl_target: String,
+    // The latest modification time of the files when they are loaded.
+    modified: Option<SystemTime>,
 }
 
-impl Drop for SecurityManager {
+impl Drop for Certs {
     fn drop(&mut self) {
         unsafe {
             for b in &mut self.key {
@@ -99,38 +113,91 @@ impl Drop for SecurityManager {
     }
 }
 
-impl SecurityManager {
-    pub fn new(cfg: &SecurityConfig) -> Result<SecurityManager, Box<Error>> {
-        Ok(SecurityManager {
+impl Certs {
+    fn load(cfg: &SecurityConfig) -> Result<Certs, Box<Error>> {
+        Ok(Certs {
             ca: load_key("CA", &cfg.ca_path)?,
             cert: load_key("certificate", &cfg.cert_path)?,
             key: load_key("private key", &cfg.key_path)?,
-            override_ssl_target: cfg.override_ssl_target.clone(),
+            modified: last_modified(cfg),
+        })
+    }
+}
+
+/// Returns the latest modification time of the certificate files.
+fn last_modified(cfg: &SecurityConfig) -> Option<SystemTime> {
+    [&cfg.ca_path, &cfg.cert_path, &cfg.key_path]
+        .iter()
+        .filter(|path| !path.is_empty())
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// `SecurityManager` builds secure channels and servers with the certificates.
+///
+/// The certificates are reloaded when the files are modified, so that new client
+/// connections pick up rotated certificates without restarting. Servers keep the
+/// certificates they are bound with.
+#[derive(Default)]
+pub struct SecurityManager {
+    cfg: SecurityConfig,
+    certs: RwLock<Certs>,
+}
+
+impl SecurityManager {
+    pub fn new(cfg: &SecurityConfig) -> Result<SecurityManager, Box<Error>> {
+        Ok(SecurityManager {
+            cfg: cfg.clone(),
+            certs: RwLock::new(Certs::load(cfg)?),
         })
     }
 
+    /// Reloads the certificates if the files are modified since they are loaded. Returns
+    /// whether they are reloaded.
+    pub fn reload_if_modified(&self) -> Result<bool, Box<Error>> {
+        if self.cfg.ca_path.is_empty() {
+            return Ok(false);
+        }
+        let modified = last_modified(&self.cfg);
+        if modified <= self.certs.read().unwrap().modified {
+            return Ok(false);
+        }
+        let certs = Certs::load(&self.cfg)?;
+        *self.certs.write().unwrap() = certs;
+        info!("certificates are reloaded");
+        Ok(true)
+    }
+
     pub fn connect(&self, mut cb: ChannelBuilder, addr: &str) -> Channel {
-        if self.ca.is_empty() {
+        if let Err(e) = self.reload_if_modified() {
+            warn!("failed to reload certificates: {:?}", e);
+        }
+        let certs = self.certs.read().unwrap();
+        if certs.ca.is_empty() {
             cb.connect(addr)
         } else {
-            if !self.override_ssl_target.is_empty() {
-                cb = cb.override_ssl_target(self.override_ssl_target.clone());
+            if !self.cfg.override_ssl_target.is_empty() {
+                cb = cb.override_ssl_target(self.cfg.override_ssl_target.clone());
             }
             let cred = ChannelCredentialsBuilder::new()
-                .root_cert(self.ca.clone())
-                .cert(self.cert.clone(), self.key.clone())
+                .root_cert(certs.ca.clone())
+                .cert(certs.cert.clone(), certs.key.clone())
                 .build();
             cb.secure_connect(addr, cred)
         }
     }
 
     pub fn bind(&self, sb: ServerBuilder, addr: &str, port: u16) -> ServerBuilder {
-        if self.ca.is_empty() {
+        if let Err(e) = self.reload_if_modified() {
+            warn!("failed to reload certificates: {:?}", e);
+        }
+        let certs = self.certs.read().unwrap();
+        if certs.ca.is_empty() {
             sb.bind(addr, port)
         } else {
             let cred = ServerCredentialsBuilder::new()
-                .root_cert(self.ca.clone(), true)
-                .add_cert(self.cert.clone(), self.key.clone())
+                .root_cert(certs.ca.clone(), true)
+                .add_cert(certs.cert.clone(), certs.key.clone())
                 .build();
             sb.bind_secure(addr, port, cred)
         }
@@ -143,6 +210,7 @@ mod tests {
 
     use std::fs::File;
     use std::io::Write;
+    use std::time::UNIX_EPOCH;
 
     use tempdir::TempDir;
 
@@ -152,9 +220,13 @@ mod tests {
         // default is disable secure connection.
         cfg.validate().unwrap();
         let mut mgr = SecurityManager::new(&cfg).unwrap();
-        assert!(mgr.ca.is_empty());
-        assert!(mgr.cert.is_empty());
-        assert!(mgr.key.is_empty());
+        {
+            let certs = mgr.certs.read().unwrap();
+            assert!(certs.ca.is_empty());
+            assert!(certs.cert.is_empty());
+            assert!(certs.key.is_empty());
+        }
+        assert!(!mgr.reload_if_modified().unwrap());
 
         let assert_cfg = |c: fn(&mut SecurityConfig), valid: bool| {
             let mut invalid_cfg = cfg.clone();
@@ -192,8 +264,20 @@ mod tests {
         c.ca_path = format!("{}", example_ca.display());
         c.validate().unwrap();
         mgr = SecurityManager::new(&c).unwrap();
-        assert_eq!(mgr.ca, vec![0]);
-        assert_eq!(mgr.cert, vec![1]);
-        assert_eq!(mgr.key, vec![2]);
+        {
+            let certs = mgr.certs.read().unwrap();
+            assert_eq!(certs.ca, vec![0]);
+            assert_eq!(certs.cert, vec![1]);
+            assert_eq!(certs.key, vec![2]);
+        }
+        assert!(!mgr.reload_if_modified().unwrap());
+
+        // Rotated certificates are reloaded. The loaded time is moved back as the resolution
+        // of the modification time may be coarse.
+        File::create(&example_cert).unwrap().write_all(&[3]).unwrap();
+        mgr.certs.write().unwrap().modified = Some(UNIX_EPOCH);
+        assert!(mgr.reload_if_modified().unwrap());
+        assert_eq!(mgr.certs.read().unwrap().cert, vec![3]);
+        assert!(!mgr.reload_if_modified().unwrap());
     }
 }
@@ -166,6 +166,13 @@ use self::inner::monotonic_coarse_now;
 pub use self::inner::monotonic_now;
 /// `monotonic_raw_now` returns the monotonic raw time since some unspecified starting point.
 pub use self::inner::monotonic_raw_now;
+use self::inner::thread_cpu_now;
+
+/// Returns the CPU time consumed by the current thread so far.
+pub fn thread_cpu_time() -> Duration {
+    let t = thread_cpu_now();
+    Duration::new(t.sec as u64, t.nsec as u32)
+}
 
 const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
 const MILLISECOND_PER_SECOND: i64 = 1_000;
@@ -194,6 +201,12 @@ mod inner {
         // TODO Add monotonic coarse clock time impl for macos and windows
         monotonic_raw_now()
     }
+
+    pub fn thread_cpu_now() -> Timespec {
+        // TODO Add thread CPU time impl for macos and windows
+        // Currently use the wall time instead.
+        monotonic_raw_now()
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -217,6 +230,11 @@ mod inner {
         get_time(libc::CLOCK_MONOTONIC_COARSE)
     }
 
+    #[inline]
+    pub fn thread_cpu_now() -> Timespec {
+        get_time(libc::CLOCK_THREAD_CPUTIME_ID)
+    }
+
     #[inline]
     fn get_time(clock: libc::clockid_t) -> Timespec {
         let mut t = libc::timespec {
@@ -448,6 +466,20 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_thread_cpu_time() {
+        let begin = thread_cpu_time();
+        thread::sleep(Duration::from_millis(200));
+        // Sleeping consumes little CPU time.
+        assert!(thread_cpu_time() - begin < Duration::from_millis(100));
+
+        let begin = thread_cpu_time();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(50) {}
+        assert!(thread_cpu_time() - begin >= Duration::from_millis(10));
+    }
+
     #[test]
     fn test_now() {
         let pairs = vec![
@@ -59,6 +59,11 @@ impl<T> Timer<T> {
         }
         None
     }
+
+    /// Removes all the tasks from the `Timer`, whether they should be ticked or not.
+    pub fn drain(&mut self) -> Vec<T> {
+        self.pending.drain().map(|t| t.0.task).collect()
+    }
 }
 
 #[derive(Debug)]
@@ -175,6 +180,10 @@ mod tests {
         let tick_time = timer.next_timeout().unwrap();
         assert_eq!(timer.pop_task_before(tick_time).unwrap(), Task::C);
         assert_eq!(timer.pop_task_before(tick_time), None);
+
+        timer.add_task(Duration::from_secs(100), Task::A);
+        assert_eq!(timer.drain(), vec![Task::A]);
+        assert_eq!(timer.next_timeout(), None);
     }
 
     #[test]
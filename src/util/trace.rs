@@ -0,0 +1,394 @@
+// Copyright 2018 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Traces of the requests, exported to a collector taking the Zipkin v2 API, like Jaeger.
+//!
+//! A trace records the stages a request goes through one after another, like waiting for the
+//! latches, taking the snapshot and processing, each as a span. Only the traces of the requests
+//! taking longer than `min-duration` are exported, so that the path of a slow request can be
+//! looked into without the cost of exporting all of them.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{cmp, mem, thread};
+
+use rand;
+use serde_json;
+use url::Url;
+
+use util::config::ReadableDuration;
+use util::time::{duration_to_nanos, Instant};
+
+const SERVICE_NAME: &str = "tikv";
+// The traces beyond it are dropped while the collector is slow.
+const MAX_PENDING_TRACES: usize = 1024;
+const MAX_BATCH_SIZE: usize = 128;
+const EXPORT_INTERVAL_MS: u64 = 1000;
+const EXPORT_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The Zipkin v2 API of the collector, like "http://127.0.0.1:9411/api/v2/spans". No trace
+    /// is recorded if it's empty.
+    pub endpoint: String,
+    /// Only the traces of the requests taking longer are exported.
+    pub min_duration: ReadableDuration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            endpoint: "".to_owned(),
+            min_duration: ReadableDuration::secs(1),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), Box<Error>> {
+        if !self.endpoint.is_empty() {
+            parse_endpoint(&self.endpoint)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_endpoint(endpoint: &str) -> Result<Url, String> {
+    let url = Url::parse(endpoint).map_err(|e| format!("invalid endpoint {}: {:?}", endpoint, e))?;
+    if url.scheme() != "http" || url.host_str().is_none() {
+        return Err(format!("endpoint {} should be a plain HTTP address", endpoint));
+    }
+    Ok(url)
+}
+
+static ENABLED: AtomicBool = ATOMIC_BOOL_INIT;
+static MIN_DURATION_US: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref EXPORTER: Mutex<Option<SyncSender<TraceInner>>> = Mutex::new(None);
+}
+
+/// Starts recording the traces and exporting the slow ones in the background.
+pub fn init(cfg: &Config) -> Result<(), String> {
+    if cfg.endpoint.is_empty() {
+        return Ok(());
+    }
+    let endpoint = parse_endpoint(&cfg.endpoint)?;
+    let (tx, rx) = mpsc::sync_channel(MAX_PENDING_TRACES);
+    thread::Builder::new()
+        .name(thd_name!("trace-exporter"))
+        .spawn(move || export_traces(&endpoint, &rx))
+        .map_err(|e| format!("failed to spawn the trace exporter: {:?}", e))?;
+    *EXPORTER.lock().unwrap() = Some(tx);
+    MIN_DURATION_US.store(to_us(cfg.min_duration.0) as usize, Ordering::Release);
+    ENABLED.store(true, Ordering::Release);
+    Ok(())
+}
+
+struct Span {
+    name: &'static str,
+    // Since the start of the trace.
+    start: Duration,
+    duration: Duration,
+}
+
+struct TraceInner {
+    name: &'static str,
+    start_time: SystemTime,
+    start: Instant,
+    last: Instant,
+    spans: Vec<Span>,
+    tags: Vec<(&'static str, String)>,
+}
+
+/// Trace records the stages of a request. It's a no-op unless the tracing is enabled.
+///
+/// The clones share the record, so the stages of a request can be recorded by the callbacks
+/// it goes through.
+#[derive(Clone, Default)]
+pub struct Trace(Option<Arc<Mutex<TraceInner>>>);
+
+impl Trace {
+    pub fn new(name: &'static str) -> Trace {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Trace(None);
+        }
+        Trace::start(name)
+    }
+
+    fn start(name: &'static str) -> Trace {
+        let now = Instant::now();
+        Trace(Some(Arc::new(Mutex::new(TraceInner {
+            name,
+            start_time: SystemTime::now(),
+            start: now,
+            last: now,
+            spans: vec![],
+            tags: vec![],
+        }))))
+    }
+
+    pub fn tag<V: Display>(&self, key: &'static str, value: V) {
+        if let Some(ref t) = self.0 {
+            t.lock().unwrap().tags.push((key, value.to_string()));
+        }
+    }
+
+    /// Ends the current stage, which began when the last stage ended or the trace started.
+    pub fn stage(&self, name: &'static str) {
+        if let Some(ref t) = self.0 {
+            let mut t = t.lock().unwrap();
+            let now = Instant::now();
+            let span = Span {
+                name,
+                start: t.last.duration_since(t.start),
+                duration: now.duration_since(t.last),
+            };
+            t.spans.push(span);
+            t.last = now;
+        }
+    }
+
+    /// Exports the trace if the request takes long enough.
+    pub fn finish(self) {
+        let t = match self.0 {
+            Some(t) => t,
+            None => return,
+        };
+        let t = {
+            let mut t = t.lock().unwrap();
+            if to_us(t.start.elapsed()) < MIN_DURATION_US.load(Ordering::Relaxed) as u64 {
+                return;
+            }
+            TraceInner {
+                spans: mem::replace(&mut t.spans, vec![]),
+                tags: mem::replace(&mut t.tags, vec![]),
+                ..*t
+            }
+        };
+        if let Some(ref tx) = *EXPORTER.lock().unwrap() {
+            // It's dropped if the exporter falls behind.
+            let _ = tx.try_send(t);
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ZipkinSpan<'a> {
+    trace_id: String,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_id: Option<String>,
+    name: &'a str,
+    timestamp: u64,
+    duration: u64,
+    local_endpoint: LocalEndpoint,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LocalEndpoint {
+    service_name: &'static str,
+}
+
+fn to_us(d: Duration) -> u64 {
+    duration_to_nanos(d) / 1000
+}
+
+// Encodes the traces as the Zipkin v2 spans. The request is the root span, and its stages are
+// the children.
+fn encode_traces(traces: &[TraceInner]) -> Vec<u8> {
+    let mut spans = vec![];
+    for t in traces {
+        let trace_id = format!("{:016x}", rand::random::<u64>());
+        let root_id = format!("{:016x}", rand::random::<u64>());
+        let timestamp = to_us(t.start_time.duration_since(UNIX_EPOCH).unwrap_or_default());
+        let duration = t.last.duration_since(t.start);
+        spans.push(ZipkinSpan {
+            trace_id: trace_id.clone(),
+            id: root_id.clone(),
+            parent_id: None,
+            name: t.name,
+            timestamp,
+            // Zipkin takes no span shorter than a microsecond.
+            duration: cmp::max(to_us(duration), 1),
+            local_endpoint: LocalEndpoint {
+                service_name: SERVICE_NAME,
+            },
+            tags: t.tags.iter().map(|&(k, ref v)| (k, v.as_str())).collect(),
+        });
+        for span in &t.spans {
+            spans.push(ZipkinSpan {
+                trace_id: trace_id.clone(),
+                id: format!("{:016x}", rand::random::<u64>()),
+                parent_id: Some(root_id.clone()),
+                name: span.name,
+                timestamp: timestamp + to_us(span.start),
+                duration: cmp::max(to_us(span.duration), 1),
+                local_endpoint: LocalEndpoint {
+                    service_name: SERVICE_NAME,
+                },
+                tags: BTreeMap::new(),
+            });
+        }
+    }
+    serde_json::to_vec(&spans).unwrap()
+}
+
+// Posts the traces in batches, every `EXPORT_INTERVAL_MS` or once `MAX_BATCH_SIZE` of them
+// are pending.
+fn export_traces(endpoint: &Url, rx: &Receiver<TraceInner>) {
+    let interval = Duration::from_millis(EXPORT_INTERVAL_MS);
+    let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+    let mut last_export = Instant::now_coarse();
+    loop {
+        let timeout = interval
+            .checked_sub(last_export.elapsed())
+            .unwrap_or_default();
+        match rx.recv_timeout(timeout) {
+            Ok(t) => {
+                batch.push(t);
+                if batch.len() < MAX_BATCH_SIZE && last_export.elapsed() < interval {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        last_export = Instant::now_coarse();
+        if batch.is_empty() {
+            continue;
+        }
+        if let Err(e) = post(endpoint, &encode_traces(&batch)) {
+            warn!("failed to export {} traces to {}: {:?}", batch.len(), endpoint, e);
+        }
+        batch.clear();
+    }
+}
+
+fn post(endpoint: &Url, body: &[u8]) -> io::Result<()> {
+    let host = endpoint.host_str().unwrap();
+    let port = endpoint.port_or_known_default().unwrap_or(80);
+    let mut stream = TcpStream::connect((host, port))?;
+    let timeout = Some(Duration::from_secs(EXPORT_TIMEOUT_SECS));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let path = match endpoint.query() {
+        Some(query) => format!("{}?{}", endpoint.path(), query),
+        None => endpoint.path().to_owned(),
+    };
+    let header = format!(
+        "POST {} HTTP/1.1\r\nhost: {}:{}\r\ncontent-type: application/json\r\n\
+         content-length: {}\r\nconnection: close\r\n\r\n",
+        path,
+        host,
+        port,
+        body.len()
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body)?;
+
+    // Like "HTTP/1.1 202 Accepted".
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unexpected response {:?}", status_line.trim()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_trace_disabled() {
+        let trace = Trace::new("get");
+        trace.tag("region", 1);
+        trace.stage("snapshot");
+        assert!(trace.0.is_none());
+        trace.finish();
+    }
+
+    #[test]
+    fn test_encode_traces() {
+        let trace = Trace::start("prewrite");
+        trace.tag("region", 2);
+        trace.stage("latch_wait");
+        thread::sleep(Duration::from_millis(10));
+        trace.clone().stage("process");
+        let t = Arc::try_unwrap(trace.0.unwrap()).ok().unwrap();
+        let t = t.into_inner().unwrap();
+        let process = t.spans[1].duration;
+
+        let spans: Value = serde_json::from_slice(&encode_traces(&[t])).unwrap();
+        let spans = spans.as_array().unwrap();
+        assert_eq!(spans.len(), 3);
+        let root = &spans[0];
+        assert_eq!(root["name"], "prewrite");
+        assert_eq!(root["tags"]["region"], "2");
+        assert_eq!(root["localEndpoint"]["serviceName"], SERVICE_NAME);
+        assert!(root.get("parentId").is_none());
+        for (span, name) in spans[1..].iter().zip(&["latch_wait", "process"]) {
+            assert_eq!(span["name"], *name);
+            assert_eq!(span["traceId"], root["traceId"]);
+            assert_eq!(span["parentId"], root["id"]);
+            assert!(span.get("tags").is_none());
+        }
+        assert_eq!(spans[2]["duration"].as_u64().unwrap(), to_us(process));
+        assert!(root["duration"].as_u64().unwrap() >= 10_000);
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v2/spans", listener.local_addr().unwrap());
+        let endpoint = parse_endpoint(&url).unwrap();
+        let h = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"HTTP/1.1 202 Accepted\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            request
+        });
+        post(&endpoint, b"[]").unwrap();
+        let request = h.join().unwrap();
+        assert!(request.starts_with("POST /api/v2/spans HTTP/1.1\r\n"));
+        assert!(request.ends_with("\r\n\r\n[]"));
+
+        assert!(parse_endpoint("https://127.0.0.1:9411/api/v2/spans").is_err());
+        assert!(parse_endpoint("127.0.0.1:9411").is_err());
+    }
+}
@@ -19,16 +19,21 @@ use rocksdb::{CompactionPriority, DBCompactionStyle, DBCompressionType, DBRecove
 use slog::Level;
 use toml;
 
+use tikv::backup::Config as BackupConfig;
 use tikv::config::*;
 use tikv::import::Config as ImportConfig;
+use tikv::log_backup::Config as LogBackupConfig;
 use tikv::pd::Config as PdConfig;
 use tikv::raftstore::coprocessor::Config as CopConfig;
 use tikv::raftstore::store::Config as RaftstoreConfig;
 use tikv::server::config::GrpcCompressionType;
+use tikv::server::quota_limiter::SourceQuota;
 use tikv::server::Config as ServerConfig;
-use tikv::storage::Config as StorageConfig;
+use tikv::storage::{AssertionMode, BlockCacheConfig, Config as StorageConfig};
 use tikv::util::config::{ReadableDuration, ReadableSize};
+use tikv::util::encryption::{EncryptionConfig, EncryptionMethod, KmsConfig};
 use tikv::util::security::SecurityConfig;
+use tikv::util::trace::Config as TracingConfig;
 
 #[test]
 fn test_toml_serde() {
@@ -55,14 +60,28 @@ fn test_serde_custom_tikv_config() {
     let mut value = TiKvConfig::default();
     value.log_level = Level::Debug;
     value.log_file = "foo".to_owned();
+    value.slow_log_file = "slow_foo".to_owned();
+    value.slow_log_threshold = ReadableDuration::millis(500);
+    value.memory_usage_high_water = ReadableSize::gb(8);
     value.server = ServerConfig {
         cluster_id: 0, // KEEP IT ZERO, it is skipped by serde.
         addr: "example.com:443".to_owned(),
         labels: map!{ "a".to_owned() => "b".to_owned() },
+        source_quotas: map!{
+            "analyze".to_owned() => SourceQuota {
+                cpu_time_per_sec: ReadableDuration::millis(500),
+                bytes_per_sec: ReadableSize::mb(100),
+            }
+        },
         advertise_addr: "example.com:443".to_owned(),
+        status_addr: "example.com:8080".to_owned(),
+        status_token: "secret".to_owned(),
+        status_verify_client_cert: true,
+        debug_addr: "example.com:8081".to_owned(),
         concurrent_send_snap_limit: 4,
         concurrent_recv_snap_limit: 4,
         grpc_compression_type: GrpcCompressionType::Gzip,
+        grpc_server_compression_type: GrpcCompressionType::Deflate,
         grpc_concurrency: 123,
         grpc_concurrent_stream: 1_234,
         grpc_raft_conn_num: 123,
@@ -77,8 +96,20 @@ fn test_serde_custom_tikv_config() {
         end_point_batch_row_limit: 64,
         end_point_stream_batch_row_limit: 4096,
         end_point_request_max_handle_duration: ReadableDuration::secs(12),
+        end_point_stream_dedup_capacity: 1024,
+        end_point_result_cache_capacity: 2048,
+        end_point_result_cache_max_size: ReadableSize::kb(16),
+        end_point_analyze_max_sample_size: 20_000,
+        end_point_analyze_max_cmsketch_depth: 8,
+        end_point_analyze_max_cmsketch_width: 4096,
+        end_point_checksum_max_bytes_per_sec: ReadableSize::mb(200),
+        end_point_checksum_request_max_bytes_per_sec: ReadableSize::mb(50),
+        end_point_checksum_max_concurrency: 4,
+        end_point_request_memory_quota: ReadableSize::mb(256),
+        end_point_memory_quota: ReadableSize::gb(2),
         snap_max_write_bytes_per_sec: ReadableSize::mb(10),
         snap_max_total_size: ReadableSize::gb(10),
+        enable_debug_dirty_read: true,
     };
     value.readpool = ReadPoolConfig {
         storage: StorageReadPoolConfig {
@@ -89,6 +120,8 @@ fn test_serde_custom_tikv_config() {
             max_tasks_per_worker_normal: 1500,
             max_tasks_per_worker_low: 2500,
             stack_size: ReadableSize::mb(20),
+            min_concurrency: 1,
+            max_queue_latency: ReadableDuration::millis(20),
         },
         coprocessor: CoprocessorReadPoolConfig {
             high_concurrency: 2,
@@ -98,6 +131,8 @@ fn test_serde_custom_tikv_config() {
             max_tasks_per_worker_normal: 1000,
             max_tasks_per_worker_low: 3000,
             stack_size: ReadableSize::mb(12),
+            min_concurrency: 2,
+            max_queue_latency: ReadableDuration::millis(30),
         },
     };
     value.metric = MetricConfig {
@@ -149,6 +184,7 @@ fn test_serde_custom_tikv_config() {
         raft_store_max_leader_lease: ReadableDuration::secs(12),
         right_derive_when_split: false,
         allow_remove_leader: true,
+        allow_follower_read: true,
         merge_max_log_gap: 3,
         merge_check_tick_interval: ReadableDuration::secs(11),
         use_delete_range: true,
@@ -156,6 +192,7 @@ fn test_serde_custom_tikv_config() {
         region_max_size: ReadableSize(0),
         region_split_size: ReadableSize(0),
         local_read_batch_size: 33,
+        apply_multi_batch_write: false,
     };
     value.pd = PdConfig {
         endpoints: vec!["example.com:443".to_owned()],
@@ -178,16 +215,20 @@ fn test_serde_custom_tikv_config() {
         info_log_keep_log_file_num: 1000,
         info_log_dir: "/var".to_owned(),
         rate_bytes_per_sec: ReadableSize::kb(1),
+        rate_limiter_auto_tuned: true,
+        rate_limiter_min_bytes_per_sec: ReadableSize(512),
         bytes_per_sync: ReadableSize::mb(1),
         wal_bytes_per_sync: ReadableSize::kb(32),
         max_sub_compactions: 12,
         writable_file_max_buffer_size: ReadableSize::mb(12),
         use_direct_io_for_flush_and_compaction: true,
         enable_pipelined_write: false,
+        enable_multi_batch_write: true,
         defaultcf: DefaultCfConfig {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.1,
             cache_index_and_filter_blocks: false,
             pin_l0_filter_and_index_blocks: false,
             use_bloom_filter: false,
@@ -226,6 +267,7 @@ fn test_serde_custom_tikv_config() {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.1,
             cache_index_and_filter_blocks: false,
             pin_l0_filter_and_index_blocks: false,
             use_bloom_filter: false,
@@ -264,6 +306,7 @@ fn test_serde_custom_tikv_config() {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.1,
             cache_index_and_filter_blocks: false,
             pin_l0_filter_and_index_blocks: false,
             use_bloom_filter: false,
@@ -302,6 +345,7 @@ fn test_serde_custom_tikv_config() {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.1,
             cache_index_and_filter_blocks: false,
             pin_l0_filter_and_index_blocks: false,
             use_bloom_filter: false,
@@ -364,6 +408,7 @@ fn test_serde_custom_tikv_config() {
             block_size: ReadableSize::kb(12),
             block_cache_size: ReadableSize::gb(12),
             disable_block_cache: false,
+            block_cache_high_pri_ratio: 0.1,
             cache_index_and_filter_blocks: false,
             pin_l0_filter_and_index_blocks: false,
             use_bloom_filter: false,
@@ -402,11 +447,24 @@ fn test_serde_custom_tikv_config() {
     value.storage = StorageConfig {
         data_dir: "/var".to_owned(),
         gc_ratio_threshold: 1.2,
+        gc_poll_safe_point_interval: ReadableDuration::secs(12),
+        gc_max_write_bytes_per_sec: ReadableSize::mb(10),
+        max_io_bytes_per_sec: ReadableSize::mb(200),
         max_key_size: 8192,
         scheduler_notify_capacity: 123,
         scheduler_concurrency: 123,
         scheduler_worker_pool_size: 1,
         scheduler_pending_write_threshold: ReadableSize::kb(123),
+        scheduler_pending_write_tasks_threshold: 123,
+        txn_status_cache_capacity: 123,
+        assertion_mode: AssertionMode::LogOnly,
+        request_max_handle_duration: ReadableDuration::secs(12),
+        block_cache: BlockCacheConfig {
+            shared: true,
+            capacity: Some(ReadableSize::gb(40)),
+            num_shard_bits: 10,
+            strict_capacity_limit: true,
+        },
     };
     value.coprocessor = CopConfig {
         split_region_on_table: true,
@@ -415,11 +473,26 @@ fn test_serde_custom_tikv_config() {
         region_split_size: ReadableSize::mb(12),
         region_max_keys: 100000,
         region_split_keys: 100000,
+        monotonic_key_prefixes: vec!["7480000000000000ff".to_owned()],
     };
     value.security = SecurityConfig {
         ca_path: "invalid path".to_owned(),
         cert_path: "invalid path".to_owned(),
         key_path: "invalid path".to_owned(),
+        cert_allowed_cn: vec!["tikv-server".to_owned(), "tidb-server".to_owned()],
+        encryption: EncryptionConfig {
+            method: EncryptionMethod::Aes256Ctr,
+            master_key_path: "".to_owned(),
+            kms: KmsConfig {
+                vendor: "aws".to_owned(),
+                key_id: "alias/tikv".to_owned(),
+                region: "us-west-2".to_owned(),
+                endpoint: "http://127.0.0.1:8080".to_owned(),
+                access_key: "AKIDEXAMPLE".to_owned(),
+                secret_access_key: "secret".to_owned(),
+            },
+            data_key_rotation_period: ReadableDuration::hours(24),
+        },
         override_ssl_target: "".to_owned(),
     };
     value.import = ImportConfig {
@@ -431,6 +504,38 @@ fn test_serde_custom_tikv_config() {
         region_split_size: ReadableSize::mb(123),
         stream_channel_window: 123,
         max_open_engines: 2,
+        engine_flush_chunk_size: ReadableSize::mb(123),
+        num_engine_shards: 6,
+        import_mode_timeout: ReadableDuration::minutes(3),
+        upload_speed_limit: ReadableSize::mb(123),
+        ingest_speed_limit: ReadableSize::mb(456),
+        verify_sst_checksum: true,
+        sst_file_size: ReadableSize::mb(32),
+        memory_dir: "/dev/shm/abc".to_owned(),
+        max_download_size: ReadableSize::gb(2),
+    };
+    value.backup = BackupConfig {
+        num_threads: 123,
+        upload_speed_limit: ReadableSize::mb(123),
+        auto_tune: false,
+        auto_tune_interval: ReadableDuration::secs(12),
+        min_num_threads: 12,
+        min_upload_speed_limit: ReadableSize::mb(12),
+        foreground_latency_threshold: ReadableDuration::millis(123),
+        cpu_usage_threshold: 0.5,
+    };
+    value.log_backup = LogBackupConfig {
+        enable: true,
+        storage_url: "local:///abc".to_owned(),
+        flush_interval: ReadableDuration::secs(12),
+        max_buffer_size: ReadableSize::mb(123),
+        watermark_lag_alert_threshold: ReadableDuration::minutes(12),
+        enable_old_value: true,
+        old_value_cache_capacity: 123,
+    };
+    value.tracing = TracingConfig {
+        endpoint: "http://127.0.0.1:9411/api/v2/spans".to_owned(),
+        min_duration: ReadableDuration::millis(12),
     };
 
     let custom = read_file_in_project_dir("tests/config/test-custom.toml");
@@ -11,7 +11,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::{mpsc::channel, Arc};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
@@ -84,6 +85,55 @@ fn test_storage_gcworker_busy() {
     }
 }
 
+#[test]
+fn test_scheduler_too_busy_by_pending_tasks() {
+    let _guard = ::setup();
+    let process_fp = "txn_before_process_write";
+    let pd_worker = FutureWorker::new("test-future–worker");
+    let read_pool = ReadPool::new("readpool", &readpool::Config::default_for_test(), || {
+        || storage::ReadPoolContext::new(pd_worker.scheduler())
+    });
+    let mut config = Config::default();
+    config.scheduler_pending_write_tasks_threshold = 1;
+    let mut storage = Storage::new(&config, read_pool).unwrap();
+    storage.start(&config).unwrap();
+
+    // The first write is counted once it's received, and is held until the failpoint is
+    // removed.
+    fail::cfg(process_fp, "pause").unwrap();
+    let (tx1, rx1) = channel();
+    prewrite(&storage, b"x", 100, tx1);
+    let (tx2, rx2) = channel();
+    prewrite(&storage, b"y", 101, tx2);
+    match rx2.recv_timeout(Duration::from_secs(5)).unwrap() {
+        // One pending write at the threshold of one.
+        Err(storage::Error::SchedTooBusyWithBackoff(backoff_ms)) => assert_eq!(backoff_ms, 100),
+        res => panic!("expect too busy, got {:?}", res),
+    }
+
+    fail::remove(process_fp);
+    rx1.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    let (tx3, rx3) = channel();
+    prewrite(&storage, b"z", 102, tx3);
+    rx3.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    storage.stop().unwrap();
+}
+
+fn prewrite<E: Engine>(storage: &Storage<E>, key: &[u8], ts: u64, tx: Sender<storage::Result<()>>) {
+    storage
+        .async_prewrite(
+            Context::new(),
+            vec![Mutation::Put((Key::from_raw(key), b"v".to_vec()))],
+            key.to_vec(),
+            ts,
+            Options::default(),
+            box move |res: storage::Result<Vec<storage::Result<()>>>| {
+                tx.send(res.map(|_| ())).unwrap();
+            },
+        )
+        .unwrap();
+}
+
 #[test]
 fn test_scheduler_leader_change_twice() {
     let _guard = ::setup();
@@ -30,7 +30,7 @@ fn new_kv_server() -> (ImportKVServer, ImportKvClient) {
     let mut cfg = TiKvConfig::default();
     cfg.server.addr = "127.0.0.1:0".to_owned();
     cfg.import.import_dir = temp_dir.path().to_str().unwrap().to_owned();
-    let server = ImportKVServer::new(&cfg);
+    let server = ImportKVServer::new(&cfg).unwrap();
 
     let ch = {
         let env = Arc::new(Environment::new(1));
@@ -135,6 +135,46 @@ fn test_ingest_sst() {
     send_upload_sst(&import, &meta, &data).unwrap();
 }
 
+#[test]
+fn test_read_after_ingest_sst() {
+    let (_cluster, ctx, tikv, import) = new_cluster_and_tikv_import_client();
+
+    let temp_dir = TempDir::new("test_read_after_ingest_sst").unwrap();
+    let sst_path = temp_dir.path().join("test.sst");
+    let sst_range = (0, 100);
+    let (mut meta, data) = gen_sst_file(sst_path, sst_range);
+    meta.set_region_id(ctx.get_region_id());
+    meta.set_region_epoch(ctx.get_region_epoch().clone());
+    send_upload_sst(&import, &meta, &data).unwrap();
+
+    let raw_get = |key: u8| {
+        let mut m = RawGetRequest::new();
+        m.set_context(ctx.clone());
+        m.set_key(vec![key]);
+        let resp = tikv.raw_get(&m).unwrap();
+        assert!(resp.get_error().is_empty());
+        assert!(!resp.has_region_error());
+        resp.get_value().to_vec()
+    };
+
+    // Read before ingesting, so that the following reads are served by the warmed up
+    // local reader.
+    for i in sst_range.0..sst_range.1 {
+        assert!(raw_get(i).is_empty());
+    }
+
+    let mut ingest = IngestRequest::new();
+    ingest.set_context(ctx.clone());
+    ingest.set_sst(meta.clone());
+    let resp = import.ingest(&ingest).unwrap();
+    assert!(!resp.has_error());
+
+    // The ingested kvs must be visible once the ingestion returns.
+    for i in sst_range.0..sst_range.1 {
+        assert_eq!(raw_get(i), &[i]);
+    }
+}
+
 #[test]
 fn test_cleanup_sst() {
     let (mut cluster, ctx, _, import) = new_cluster_and_tikv_import_client();
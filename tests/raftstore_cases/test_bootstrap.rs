@@ -21,6 +21,7 @@ use kvproto::raft_serverpb::RegionLocalState;
 
 use test_raftstore::*;
 use tikv::import::SSTImporter;
+use tikv::pd::PdClient;
 use tikv::raftstore::coprocessor::CoprocessorHost;
 use tikv::raftstore::store::{
     bootstrap_store, create_event_loop, keys, Engines, Peekable, SnapManager,
@@ -44,8 +45,7 @@ fn test_bootstrap_idempotent<T: Simulator>(cluster: &mut Cluster<T>) {
     cluster.check_regions_number(1);
 }
 
-#[test]
-fn test_node_bootstrap_with_prepared_data() {
+fn check_node_bootstrap_with_prepared_data(cluster_bootstrapped: bool) {
     // create a node
     let pd_client = Arc::new(TestPdClient::new(0, false));
     let cfg = new_tikv_config(0);
@@ -72,8 +72,10 @@ fn test_node_bootstrap_with_prepared_data() {
     let pd_worker = FutureWorker::new("test-pd-worker");
     let local_reader = Worker::new("test-local-reader");
 
-    // assume there is a node has bootstrapped the cluster and add region in pd successfully
-    bootstrap_with_first_region(Arc::clone(&pd_client)).unwrap();
+    if cluster_bootstrapped {
+        // assume there is a node has bootstrapped the cluster and add region in pd successfully
+        bootstrap_with_first_region(Arc::clone(&pd_client)).unwrap();
+    }
 
     // now anthoer node at same time begin bootstrap node, but panic after prepared bootstrap
     // now rocksDB must have some prepare data
@@ -101,7 +103,8 @@ fn test_node_bootstrap_with_prepared_data() {
         Arc::new(SSTImporter::new(dir).unwrap())
     };
 
-    // try to restart this node, will clear the prepare data
+    // try to restart this node, will clear the prepare data, or resume the bootstrap
+    // with the prepared region if the cluster is not bootstrapped yet.
     node.start(
         event_loop,
         engines,
@@ -119,16 +122,32 @@ fn test_node_bootstrap_with_prepared_data() {
             .unwrap()
             .is_none()
     );
-    assert!(
+    assert_eq!(
         engine
             .get_msg_cf::<RegionLocalState>(CF_RAFT, &region_state_key)
             .unwrap()
-            .is_none()
+            .is_some(),
+        !cluster_bootstrapped
     );
     assert_eq!(pd_client.get_regions_number() as u32, 1);
+    let first_region = pd_client.get_region(b"").unwrap();
+    assert_eq!(
+        first_region.get_id() == region.get_id(),
+        !cluster_bootstrapped
+    );
     node.stop().unwrap();
 }
 
+#[test]
+fn test_node_bootstrap_with_prepared_data() {
+    check_node_bootstrap_with_prepared_data(true);
+}
+
+#[test]
+fn test_node_resume_bootstrap_with_prepared_data() {
+    check_node_bootstrap_with_prepared_data(false);
+}
+
 #[test]
 fn test_node_bootstrap_idempotent() {
     let mut cluster = new_node_cluster(0, 3);
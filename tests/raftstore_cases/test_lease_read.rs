@@ -311,3 +311,39 @@ fn test_node_callback_when_destroyed() {
         resp
     );
 }
+
+#[test]
+fn test_node_follower_read() {
+    let mut cluster = new_node_cluster(0, 3);
+    cluster.pd_client.disable_default_operator();
+    cluster.run();
+    cluster.must_transfer_leader(1, new_peer(1, 1));
+    cluster.must_put(b"k1", b"v1");
+    let region = cluster.get_region(b"k1");
+    let follower = new_peer(2, 2);
+    let timeout = Duration::from_secs(1);
+
+    // Followers don't serve reads by default.
+    let resp = read_on_peer(&mut cluster, follower.clone(), region.clone(), b"k1", false, timeout)
+        .unwrap();
+    assert!(resp.get_header().get_error().has_not_leader(), "{:?}", resp);
+
+    cluster.stop_node(2);
+    cluster.cfg.raft_store.allow_follower_read = true;
+    cluster.run_node(2);
+    must_get_equal(&cluster.get_engine(2), b"k1", b"v1");
+    must_read_on_peer(&mut cluster, follower.clone(), region.clone(), b"k1", b"v1");
+
+    // The follower waits until it applies the read index got from the leader, so it never
+    // reads the stale value.
+    cluster.add_send_filter(CloneFilterFactory(
+        RegionPacketFilter::new(1, 2)
+            .msg_type(MessageType::MsgAppend)
+            .direction(Direction::Recv),
+    ));
+    cluster.must_put(b"k1", b"v2");
+    let res = read_on_peer(&mut cluster, follower.clone(), region.clone(), b"k1", false, timeout);
+    assert!(res.is_err(), "{:?}", res);
+    cluster.clear_send_filters();
+    must_read_on_peer(&mut cluster, follower, region, b"k1", b"v2");
+}
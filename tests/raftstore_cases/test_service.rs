@@ -331,6 +331,47 @@ fn test_mvcc_rollback_and_cleanup() {
     assert_eq!(scan_lock_resp.locks.len(), 0);
 }
 
+#[test]
+fn test_physical_scan_lock() {
+    let (mut cluster, client, ctx) = must_new_cluster_and_kv_client();
+
+    let mut mutation = Mutation::new();
+    mutation.op = Op::Put;
+    for (k, ts) in vec![(b"k1", 10), (b"k2", 10), (b"k3", 20)] {
+        mutation.key = k.to_vec();
+        mutation.value = b"v".to_vec();
+        must_kv_prewrite(&client, ctx.clone(), vec![mutation.clone()], k.to_vec(), ts);
+    }
+    // The locks are scanned across the regions.
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, &Key::from_raw(b"k2").into_encoded());
+
+    let scan = |max_version, start_key: &[u8], limit| {
+        // Without a region id.
+        let mut req = ScanLockRequest::new();
+        req.set_max_version(max_version);
+        req.set_start_key(start_key.to_vec());
+        req.set_limit(limit);
+        let resp = client.kv_scan_lock(&req).unwrap();
+        assert!(!resp.has_region_error(), "{:?}", resp);
+        assert!(!resp.has_error(), "{:?}", resp);
+        resp.get_locks()
+            .iter()
+            .map(|l| (l.get_key().to_vec(), l.get_lock_version()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        scan(15, b"", 0),
+        vec![(b"k1".to_vec(), 10), (b"k2".to_vec(), 10)]
+    );
+    assert_eq!(scan(20, b"", 1), vec![(b"k1".to_vec(), 10)]);
+    assert_eq!(
+        scan(20, b"k1\x00", 2),
+        vec![(b"k2".to_vec(), 10), (b"k3".to_vec(), 20)]
+    );
+    assert!(scan(5, b"", 0).is_empty());
+}
+
 #[test]
 fn test_mvcc_resolve_lock_gc_and_delete() {
     use kvproto::kvrpcpb::*;
@@ -24,10 +24,11 @@ use test_raftstore::*;
 use tikv::pd::PdClient;
 use tikv::raftstore::store::engine::Iterable;
 use tikv::raftstore::store::keys::data_key;
-use tikv::raftstore::store::{Callback, WriteResponse};
+use tikv::raftstore::store::{Callback, Msg, WriteResponse};
 use tikv::raftstore::Result;
 use tikv::storage::CF_WRITE;
 use tikv::util::config::*;
+use tikv::util::HandyRwLock;
 
 pub const REGION_MAX_SIZE: u64 = 50000;
 pub const REGION_SPLIT_SIZE: u64 = 30000;
@@ -253,6 +254,68 @@ fn test_incompatible_server_auto_split_region() {
     test_auto_split_region(&mut cluster);
 }
 
+fn test_incompatible_split_region_one_by_one<T: Simulator>(
+    cluster: &mut Cluster<T>,
+    right_derive: bool,
+) {
+    cluster.cfg.raft_store.right_derive_when_split = right_derive;
+    cluster.run();
+    let pd_client = Arc::clone(&cluster.pd_client);
+
+    let region = pd_client.get_region(b"").unwrap();
+    let leader = cluster.leader_of_region(region.get_id()).unwrap();
+    let ch = cluster
+        .sim
+        .rl()
+        .get_store_sendch(leader.get_store_id())
+        .unwrap();
+    let (tx, rx) = channel();
+    let cb = Box::new(move |write_resp: WriteResponse| {
+        tx.send(write_resp.response).unwrap();
+    });
+    // PD refuses to allocate the ids for batch split, so the region is split at the keys
+    // one by one.
+    ch.try_send(Msg::SplitRegion {
+        region_id: region.get_id(),
+        region_epoch: region.get_region_epoch().clone(),
+        split_keys: vec![b"k1".to_vec(), b"k2".to_vec(), b"k3".to_vec()],
+        callback: Callback::Write(cb),
+    }).unwrap();
+    let resp = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    let keys: Vec<&[u8]> = vec![b"", b"k1", b"k2", b"k3"];
+    for _ in 0..100 {
+        let regions: Vec<_> = keys
+            .iter()
+            .map(|k| pd_client.get_region(k).unwrap())
+            .collect();
+        if regions
+            .iter()
+            .zip(&keys)
+            .all(|(r, k)| r.get_start_key() == *k)
+        {
+            let derived = if right_derive { &regions[3] } else { &regions[0] };
+            assert_eq!(derived.get_id(), region.get_id());
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("region {:?} has not been split one by one", region);
+}
+
+#[test]
+fn test_incompatible_server_split_region_one_by_one() {
+    let mut cluster = new_incompatible_server_cluster(0, 3);
+    test_incompatible_split_region_one_by_one(&mut cluster, false);
+}
+
+#[test]
+fn test_incompatible_node_split_region_one_by_one_right_derive() {
+    let mut cluster = new_incompatible_node_cluster(0, 3);
+    test_incompatible_split_region_one_by_one(&mut cluster, true);
+}
+
 // A filter that disable commitment by heartbeat.
 #[derive(Clone)]
 struct EraseHeartbeatCommit;